@@ -0,0 +1,16 @@
+//! Any bytes are either a program or an error, and a program
+//! has as many commands as the source has command bytes.
+#![no_main]
+
+use bfrs_common::parser;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|source: &[u8]| {
+    if let Ok(instructions) = parser::parse_bytes(source) {
+        let commands = source
+            .iter()
+            .filter(|&&byte| bfrs_common::BFCommand::from_u8(byte).is_some())
+            .count();
+        assert_eq!(instructions.len(), commands);
+    }
+});
@@ -0,0 +1,9 @@
+//! Any bytes are either a pattern or an error.
+#![no_main]
+
+use bfrs_patterns::pattern;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|source: &[u8]| {
+    let _ = pattern::parse_pattern_bytes(source);
+});
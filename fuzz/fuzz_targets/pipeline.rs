@@ -0,0 +1,25 @@
+//! The whole pipeline: balanced programs are run, and then searched for
+//! patterns, both on their instructions and on their folded operations.
+#![no_main]
+
+use bfrs_common::fuzz::Balanced;
+use bfrs_patterns::fuzz::PatternSource;
+use bfrs_patterns::ir::Ir;
+use bfrs_patterns::pattern;
+use bfrs_patterns::r#match::MatchSM;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: (PatternSource, Balanced, &[u8])| {
+    let (pattern, program, input) = data;
+    let _ = bfrs::run_with_fuel(&program.to_source(), input, 64, 10_000);
+    let scope = match pattern::parse_pattern(&pattern.0) {
+        Ok(scope) => scope,
+        Err(_) => return,
+    };
+    for found in MatchSM::find_all(&program.0, &scope) {
+        assert_eq!(&program.0[found.start..][..found.commands.len()], found.commands);
+    }
+    for found in Ir::compile(&program.0).find_all(&program.0, &scope) {
+        assert_eq!(&program.0[found.start..][..found.commands.len()], found.commands);
+    }
+});
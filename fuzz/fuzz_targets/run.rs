@@ -0,0 +1,9 @@
+//! Any bytes can be run, and the interpreter stops once it runs out of fuel.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: (&[u8], &[u8], u8)| {
+    let (source, input, cells) = data;
+    let _ = bfrs::run_with_fuel(source, input, cells as usize, 10_000);
+});
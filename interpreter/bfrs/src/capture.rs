@@ -0,0 +1,233 @@
+//! Running a program on bytes in memory and keeping everything it did,
+//! without touching the stdio of the process, to test programs and the
+//! interpreter itself:
+//! ```
+//! use bfrs::capture::{run_captured, RunOptions, RunResult};
+//! use bfrs::Program;
+//!
+//! let instructions = bfrs_common::parser::parse_bytes(b",+.,+.").unwrap();
+//! let program = Program::from_instructions(instructions, 10);
+//! let result: RunResult = run_captured(&program, b"HI", &RunOptions::default());
+//! assert_eq!(result.output, b"IJ");
+//! assert_eq!(result.stats.bytes_read, 2);
+//! ```
+
+use crate::{Cell, ExecHooks, Machine, Program};
+use bfrs_common::BFCommand;
+use std::time::{Duration, Instant};
+
+/// How many instructions run between looking at the clock, for a timeout.
+const CLOCK_EVERY: u64 = 4096;
+
+/// How to run a program with [`run_captured`].
+#[derive(Debug, Clone, Default)]
+pub struct RunOptions {
+    /// the most instructions to run, or as many as it takes
+    pub fuel: Option<u64>,
+    /// the most bytes to print, stopping the program
+    /// right before it prints another one
+    pub max_output: Option<usize>,
+    /// the longest to run for
+    pub timeout: Option<Duration>,
+    /// the arguments written on the tape before running, like
+    /// [`Machine::load_args`] does, which the tape has to fit
+    pub args: Vec<Vec<u8>>,
+}
+
+impl RunOptions {
+    /// The limit a program started at `started`, that printed `printed`
+    /// bytes, runs into before running `instruction`, if any.
+    pub(crate) fn limit(
+        &self,
+        instruction: BFCommand,
+        stats: &RunStats,
+        printed: usize,
+        started: Instant,
+    ) -> Option<Limit> {
+        if self.fuel == Some(stats.instructions) {
+            Some(Limit::Fuel)
+        } else if instruction == BFCommand::Print
+            && self.max_output.is_some_and(|max| printed >= max)
+        {
+            Some(Limit::Output)
+        } else if stats.instructions.is_multiple_of(CLOCK_EVERY)
+            && self
+                .timeout
+                .is_some_and(|timeout| started.elapsed() >= timeout)
+        {
+            Some(Limit::Time)
+        } else {
+            None
+        }
+    }
+}
+
+/// Everything a program did.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunResult<C: Cell = u8> {
+    pub output: Vec<u8>,
+    /// the tape once the program stopped
+    pub tape: Vec<C>,
+    /// the cell the pointer was at once the program stopped
+    pub pointer: usize,
+    pub stats: RunStats,
+    /// whether the program got to its end, instead of running out of fuel
+    pub completed: bool,
+    /// the limit of the [`RunOptions`] that stopped the
+    /// program before its end, if one did
+    pub limit: Option<Limit>,
+}
+
+/// A limit a program can run into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    Fuel,
+    Output,
+    Time,
+}
+
+/// What a program did, in numbers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RunStats {
+    pub instructions: u64,
+    /// the bytes read, not counting reading at the end of the input
+    pub bytes_read: u64,
+    pub bytes_printed: u64,
+}
+
+impl<C: Cell> ExecHooks<C> for RunStats {
+    fn on_instruction(&mut self, _: usize, _: &Machine<C>) {
+        self.instructions += 1;
+    }
+
+    fn on_output(&mut self, _: u8) {
+        self.bytes_printed += 1;
+    }
+
+    fn on_input(&mut self, byte: Option<u8>) {
+        if byte.is_some() {
+            self.bytes_read += 1;
+        }
+    }
+}
+
+/// Runs `program` on a blank tape, reading `input` and then its end.
+pub fn run_captured<C: Cell>(
+    program: &Program,
+    input: &[u8],
+    options: &RunOptions,
+) -> RunResult<C> {
+    let mut machine = Machine::new(program.tape_size);
+    if !options.args.is_empty() {
+        machine.load_args(&options.args);
+    }
+    let mut input = input;
+    let mut output = Vec::new();
+    let mut stats = RunStats::default();
+    let started = Instant::now();
+    let mut next = 0;
+    let mut limit = None;
+    while next < program.code.instructions.len() {
+        limit = options.limit(
+            program.code.instructions[next],
+            &stats,
+            output.len(),
+            started,
+        );
+        if limit.is_some() {
+            break;
+        }
+        next = machine
+            .step_with_hooks(program, next, &mut input, &mut output, &mut stats)
+            .expect("reading and writing memory doesn't fail");
+    }
+    RunResult {
+        output,
+        tape: machine.tape.into_vec(),
+        pointer: machine.pivot,
+        stats,
+        completed: limit.is_none(),
+        limit,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(src: &str, input: &[u8], options: &RunOptions) -> RunResult {
+        let instructions = bfrs_common::parser::parse_str(src).unwrap();
+        run_captured(
+            &Program::from_instructions(instructions, 10),
+            input,
+            options,
+        )
+    }
+
+    #[test]
+    fn finished_runs() {
+        let result = run(",>,>,.<.", b"ab", &RunOptions::default());
+        assert!(result.completed);
+        assert_eq!(result.limit, None);
+        // reading past the end of the input doesn't count.
+        let stats = RunStats {
+            instructions: 8,
+            bytes_read: 2,
+            bytes_printed: 2,
+        };
+        assert_eq!(result.stats, stats);
+        assert_eq!(result.output, [255, b'b']);
+        assert_eq!(result.tape[..3], [b'a', b'b', 255]);
+        assert_eq!(result.pointer, 1);
+    }
+
+    #[test]
+    fn fuel() {
+        let options = RunOptions {
+            fuel: Some(10),
+            ..RunOptions::default()
+        };
+        let result = run("+[]", b"", &options);
+        assert!(!result.completed);
+        assert_eq!(result.limit, Some(Limit::Fuel));
+        assert_eq!(result.stats.instructions, 10);
+        // as much fuel as the program takes is enough.
+        let result = run(
+            "+++",
+            b"",
+            &RunOptions {
+                fuel: Some(3),
+                ..options
+            },
+        );
+        assert!(result.completed);
+    }
+
+    #[test]
+    fn max_output() {
+        let options = RunOptions {
+            max_output: Some(3),
+            ..RunOptions::default()
+        };
+        let result = run("+[.+]", b"", &options);
+        assert_eq!(result.limit, Some(Limit::Output));
+        assert_eq!(result.output, [1, 2, 3]);
+        assert_eq!(result.stats.bytes_printed, 3);
+        // printing the most bytes isn't going past it.
+        let result = run("+.+.+.", b"", &options);
+        assert!(result.completed);
+    }
+
+    #[test]
+    fn timeout() {
+        let options = RunOptions {
+            timeout: Some(Duration::from_millis(10)),
+            ..RunOptions::default()
+        };
+        let result = run("+[]", b"", &options);
+        assert_eq!(result.limit, Some(Limit::Time));
+        // the clock is only looked at every so often.
+        assert!(result.stats.instructions.is_multiple_of(CLOCK_EVERY));
+        assert!(run("+.", b"", &options).completed);
+    }
+}
@@ -0,0 +1,269 @@
+//! The cells of the tape, which don't have to be bytes.
+//!
+//! The interpreter is generic over [`Cell`], so every kind of cell runs
+//! through the same code, compiled once for each of them, without checking
+//! which one it is on every instruction. Cells wrap around on overflow,
+//! only print their lowest byte and read bytes as they are.
+//! Cells without a bound are behind the `bignum` feature.
+
+use std::fmt;
+
+/// What the interpreter needs from a cell.
+pub trait Cell: Clone + PartialEq + fmt::Debug + fmt::Display {
+    /// The value of the cells of a blank tape.
+    fn zero() -> Self;
+
+    fn is_zero(&self) -> bool;
+
+    /// Adds `amount`, wrapping around on overflow.
+    fn add(&mut self, amount: u32);
+
+    /// Subtracts `amount`, wrapping around on underflow.
+    fn sub(&mut self, amount: u32);
+
+    /// The cell a byte of the input is read as.
+    fn from_byte(byte: u8) -> Self;
+
+    /// The byte the cell is printed as, which is its lowest one,
+    /// in two's complement for negative cells.
+    fn to_byte(&self) -> u8;
+
+    /// What reading at the end of the input leaves in a cell, which is -1
+    /// like in most interpreters: 255 for bytes and -1 for signed cells.
+    fn eof() -> Self {
+        let mut cell = Self::zero();
+        cell.sub(1);
+        cell
+    }
+
+    /// The cells `bytes` hold, a byte each, for tapes kept somewhere
+    /// other than a `Vec`, like a mapped file. Only bytes can be.
+    fn from_bytes(_bytes: &[u8]) -> Option<&[Self]> {
+        None
+    }
+
+    fn from_bytes_mut(_bytes: &mut [u8]) -> Option<&mut [Self]> {
+        None
+    }
+}
+
+macro_rules! impl_cell {
+    ($($ty:ty $({ $($extra:item)* })?),*) => {
+        $(
+            impl Cell for $ty {
+                #[inline]
+                fn zero() -> Self {
+                    0
+                }
+
+                #[inline]
+                fn is_zero(&self) -> bool {
+                    *self == 0
+                }
+
+                #[inline]
+                fn add(&mut self, amount: u32) {
+                    // only the bits that fit in the cell change its value.
+                    *self = self.wrapping_add(amount as $ty);
+                }
+
+                #[inline]
+                fn sub(&mut self, amount: u32) {
+                    *self = self.wrapping_sub(amount as $ty);
+                }
+
+                #[inline]
+                fn from_byte(byte: u8) -> Self {
+                    byte as $ty
+                }
+
+                #[inline]
+                fn to_byte(&self) -> u8 {
+                    *self as u8
+                }
+
+                $($($extra)*)?
+            }
+        )*
+    };
+}
+
+impl_cell!(
+    u8 {
+        #[inline]
+        fn from_bytes(bytes: &[u8]) -> Option<&[Self]> {
+            Some(bytes)
+        }
+
+        #[inline]
+        fn from_bytes_mut(bytes: &mut [u8]) -> Option<&mut [Self]> {
+            Some(bytes)
+        }
+    },
+    u16, u32, u64, i8, i16, i32, i64);
+
+#[cfg(feature = "bignum")]
+impl Cell for num_bigint::BigInt {
+    fn zero() -> Self {
+        Self::default()
+    }
+
+    fn is_zero(&self) -> bool {
+        self.sign() == num_bigint::Sign::NoSign
+    }
+
+    fn add(&mut self, amount: u32) {
+        *self += amount;
+    }
+
+    fn sub(&mut self, amount: u32) {
+        *self -= amount;
+    }
+
+    fn from_byte(byte: u8) -> Self {
+        Self::from(byte)
+    }
+
+    fn to_byte(&self) -> u8 {
+        self.to_signed_bytes_le()[0]
+    }
+}
+
+/// The kinds of cells a program can be run with, to pick one at runtime.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    U8,
+    U16,
+    U32,
+    I8,
+    I16,
+    I32,
+    /// cells without a bound, which never wrap around
+    #[cfg(feature = "bignum")]
+    Bignum,
+}
+
+impl Kind {
+    #[cfg(feature = "bignum")]
+    pub const VARIANTS: &'static [&'static str] =
+        &["u8", "u16", "u32", "i8", "i16", "i32", "bignum"];
+    #[cfg(not(feature = "bignum"))]
+    pub const VARIANTS: &'static [&'static str] = &["u8", "u16", "u32", "i8", "i16", "i32"];
+
+    /// Calls `f` with the type of the cells of this kind.
+    pub fn with<F: WithCell>(self, f: F) -> F::Output {
+        match self {
+            Self::U8 => f.call::<u8>(),
+            Self::U16 => f.call::<u16>(),
+            Self::U32 => f.call::<u32>(),
+            Self::I8 => f.call::<i8>(),
+            Self::I16 => f.call::<i16>(),
+            Self::I32 => f.call::<i32>(),
+            #[cfg(feature = "bignum")]
+            Self::Bignum => f.call::<num_bigint::BigInt>(),
+        }
+    }
+}
+
+/// Something to do with the cells of a [`Kind`] picked at runtime,
+/// which closures can't do, since they can't be generic.
+pub trait WithCell {
+    type Output;
+
+    fn call<C: Cell>(self) -> Self::Output;
+}
+
+impl std::str::FromStr for Kind {
+    type Err = UnknownKind;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "u8" => Ok(Self::U8),
+            "u16" => Ok(Self::U16),
+            "u32" => Ok(Self::U32),
+            "i8" => Ok(Self::I8),
+            "i16" => Ok(Self::I16),
+            "i32" => Ok(Self::I32),
+            #[cfg(feature = "bignum")]
+            "bignum" => Ok(Self::Bignum),
+            _ => Err(UnknownKind(s.to_string())),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct UnknownKind(String);
+
+impl fmt::Display for UnknownKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown cell kind: {:?}", self.0)
+    }
+}
+
+impl std::error::Error for UnknownKind {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Machine, Program};
+
+    /// Runs a program on a tape of a single cell, returning it as
+    /// text and what the program printed.
+    struct Run<'a>(&'a str, &'a [u8]);
+
+    impl WithCell for Run<'_> {
+        type Output = (String, Vec<u8>);
+
+        fn call<C: Cell>(self) -> Self::Output {
+            let instructions = bfrs_common::parser::parse_str(self.0).unwrap();
+            let program = Program::from_instructions(instructions, 1);
+            let mut machine = Machine::<C>::new(1);
+            let mut output = Vec::new();
+            machine.run(&program, &mut { self.1 }, &mut output).unwrap();
+            (machine.tape[0].to_string(), output)
+        }
+    }
+
+    fn run(kind: &str, src: &str, input: &[u8]) -> (String, Vec<u8>) {
+        kind.parse::<Kind>().unwrap().with(Run(src, input))
+    }
+
+    #[test]
+    fn cells_wrap_around_at_their_size() {
+        assert_eq!(run("u8", "-.", b""), ("255".to_string(), vec![255]));
+        assert_eq!(run("u16", "-.", b""), ("65535".to_string(), vec![255]));
+        assert_eq!(run("i8", "-.", b""), ("-1".to_string(), vec![255]));
+        let mut cell = i8::MAX;
+        cell.add(1);
+        assert_eq!(cell, i8::MIN);
+        // only the bits that fit in the cell are added
+        let mut cell = 0u8;
+        cell.add(257);
+        assert_eq!(cell, 1);
+    }
+
+    #[test]
+    fn bytes_are_read_as_they_are() {
+        assert_eq!(run("i32", ",", b"\xff").0, "255");
+        assert_eq!(run("i32", ",,", b"a").0, "-1");
+        assert_eq!(u16::eof(), u16::MAX);
+        assert_eq!(300u32.to_byte(), 44);
+    }
+
+    #[test]
+    fn only_bytes_are_stored_as_bytes() {
+        assert!(u8::from_bytes(&[1, 2]).is_some());
+        assert!(u16::from_bytes(&[1, 2]).is_none());
+        assert_eq!(
+            "u128".parse::<Kind>().unwrap_err().to_string(),
+            r#"unknown cell kind: "u128""#
+        );
+    }
+
+    #[cfg(feature = "bignum")]
+    #[test]
+    fn bignums_never_wrap() {
+        assert_eq!(run("bignum", "-", b"").0, "-1");
+        assert_eq!(run("bignum", &"+".repeat(300), b"").0, "300");
+    }
+}
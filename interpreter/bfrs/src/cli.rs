@@ -0,0 +1,1338 @@
+//! Running a program, which is what `bfrs` and `bf run` do, and reading
+//! it from its files, which every subcommand of `bf` does.
+
+use crate::capture::{run_captured, RunOptions, RunResult};
+use crate::cell::{self, Cell, WithCell};
+use crate::cost::CostModel;
+use crate::hooks::{Heatmap, Profiler};
+use crate::output::{Flush, Newline, OutputFormat, ProgramOutput};
+use crate::tapes::Tapes;
+use crate::viz::{self, Recorder, VizFormat};
+use crate::{highlight_code, Machine, Program, Tape};
+use bfrs_common::dialect::Dialect;
+use bfrs_common::errors::{Diagnostic, Diagnostics, ErrorCode};
+use bfrs_common::ext::{ExtCommand, Extensions};
+use bfrs_common::{parser, BFCommand, Location, Position, PositionConfig};
+use bfrs_input::bytes::BufferedBytes;
+use bfrs_input::chain::ChainedBytes;
+use bfrs_input::mmap::{InputBytes, MappedBytes};
+use bfrs_input::tee::Tee;
+use bfrs_patterns::annotate;
+use bfrs_patterns::armor::Armored;
+use bfrs_patterns::canon;
+use bfrs_patterns::cli::Common;
+use bfrs_patterns::decompile;
+use bfrs_patterns::ir::Ir;
+use bfrs_patterns::rewrite;
+use bfrs_patterns::unroll;
+use std::error::Error;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use structopt::clap::AppSettings;
+use structopt::StructOpt;
+
+mod coverage;
+mod golden;
+mod heat;
+mod reduce;
+mod selftest;
+
+pub use coverage::{CoverageFormat, UnknownCoverageFormat};
+
+enum Input {
+    Stdin(io::Stdin),
+    File(File),
+}
+
+impl Input {
+    fn from_optional_arg(arg: Option<String>) -> io::Result<(Self, String)> {
+        match arg {
+            Some(filename) if filename != "-" => {
+                File::open(&filename).map(|x| (Self::File(x), filename))
+            }
+            _ => {
+                tracing::info!("reading the program from stdin");
+                Ok((Self::Stdin(io::stdin()), String::from("<stdin>")))
+            }
+        }
+    }
+}
+
+impl Input {
+    /// The bytes of the input, mapping regular files instead of reading them.
+    fn into_bytes(self) -> io::Result<InputBytes<Self>> {
+        if let Self::File(file) = &self {
+            if let Some(mapped) = MappedBytes::map_regular(file)? {
+                return Ok(InputBytes::Mapped(mapped));
+            }
+        }
+        Ok(InputBytes::Buffered(BufferedBytes::new(self)))
+    }
+}
+
+impl io::Read for Input {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Stdin(s) => s.lock().read(buf),
+            Self::File(f) => f.read(buf),
+        }
+    }
+}
+
+// Where a program is read from, and how it's spelled. Not a doc
+// comment, which would become the about of the commands flattening it.
+#[derive(Debug, StructOpt)]
+pub struct Source {
+    /// Input files, run as a single program made of all of them in order
+    #[structopt()]
+    pub inputs: Vec<String>,
+
+    /// The dialect the program is written in: `brainfuck` (or `bf`), `ook`, `blub`
+    /// or a file mapping every command to its token. Running takes `tapes` or
+    /// `grid` too, for several tapes or a grid of cells, on their own or after
+    /// another dialect, like `ook+grid`
+    #[structopt(short, long, default_value = "brainfuck")]
+    pub dialect: String,
+}
+
+/// Instructions along with their positions.
+pub type Located = Vec<(BFCommand, Position)>;
+
+/// A built-in dialect by name, or the one of a mapping file.
+pub fn load_dialect(name: &str) -> Result<Dialect, Box<dyn Error>> {
+    let (dialect, extensions) = split_dialect(name)?;
+    if let Some(extension) = extensions.first() {
+        return Err(format!("programs of the `{}` dialect can only be run", extension).into());
+    }
+    match Dialect::builtin(dialect) {
+        Some(dialect) => Ok(dialect),
+        None => Ok(Dialect::parse_mapping(&std::fs::read_to_string(dialect)?)?),
+    }
+}
+
+/// The extensions of brainfuck with more room than a single tape, which
+/// [`Tapes`] runs: `tapes` for several tapes, switched between with `~`,
+/// and `grid` for a grid of cells, moved up and down with `^` and `v`.
+pub const SPACES: [&str; 2] = ["tapes", "grid"];
+
+/// Splits a dialect into the one spelling the commands and the [`SPACES`]
+/// after it, like `ook+grid`. On their own, they extend brainfuck.
+pub fn split_dialect(name: &str) -> Result<(&str, Vec<&str>), Box<dyn Error>> {
+    // the name of a mapping file can have a `+` in it.
+    if std::path::Path::new(name).is_file() {
+        return Ok((name, Vec::new()));
+    }
+    let mut parts: Vec<&str> = name.split('+').collect();
+    let dialect = match parts[0] {
+        first if SPACES.contains(&first) => "brainfuck",
+        _ => parts.remove(0),
+    };
+    if let Some(unknown) = parts.iter().find(|part| !SPACES.contains(part)) {
+        return Err(format!(
+            "unknown extension `{}` of `{}`: expected {}",
+            unknown,
+            name,
+            SPACES.join(" or ")
+        )
+        .into());
+    }
+    Ok((dialect, parts))
+}
+
+impl Source {
+    /// The instructions of the program, along with the name of
+    /// its files, or `<stdin>`. Every bracket error is reported
+    /// at once, instead of only the first one.
+    pub fn load(
+        &self,
+        positions: PositionConfig,
+    ) -> Result<(Vec<BFCommand>, String), Box<dyn Error>> {
+        let (located, filename) = self.load_located(positions)?;
+        let instructions = located.into_iter().map(|(instruction, _)| instruction);
+        Ok((instructions.collect(), filename))
+    }
+
+    /// The instructions of the program like [`load`](Self::load), along
+    /// with their positions. The positions of a program made of several
+    /// files count the lines of all of them, as if they were a single one.
+    pub fn load_located(
+        &self,
+        positions: PositionConfig,
+    ) -> Result<(Located, String), Box<dyn Error>> {
+        let (program, filename) = self.load_program(positions)?;
+        let locations = program.locations.unwrap_or_default();
+        let instructions = program
+            .instructions
+            .into_iter()
+            .zip(locations.into_iter().map(|location| location.position))
+            .collect();
+        Ok((instructions, filename))
+    }
+
+    /// The program like [`load_located`](Self::load_located), with
+    /// the byte offsets of its instructions too, and its jumps.
+    pub fn load_program(
+        &self,
+        positions: PositionConfig,
+    ) -> Result<(bfrs_common::program::Program, String), Box<dyn Error>> {
+        let (program, filename, _) = self.load_program_to_end(positions)?;
+        Ok((program, filename))
+    }
+
+    /// The program like [`load_program`](Self::load_program), along
+    /// with where its source ends, right after its last byte.
+    pub fn load_program_to_end(
+        &self,
+        positions: PositionConfig,
+    ) -> Result<(bfrs_common::program::Program, String, Location), Box<dyn Error>> {
+        let _span = tracing::info_span!("parse", dialect = %self.dialect).entered();
+        let dialect = load_dialect(&self.dialect)?;
+        let (recovered, filename) = if self.inputs.len() > 1 {
+            let mut chain = ChainedBytes::open(&self.inputs, positions)?;
+            let recovered = parser::parse_recovering_with(&mut chain, dialect, positions)?;
+            if !recovered.errors.is_empty() {
+                return Err(
+                    file_errors(&recovered.errors, |position| chain.locate(position)).into(),
+                );
+            }
+            (recovered, self.inputs.join(", "))
+        } else {
+            let (input, filename) = Input::from_optional_arg(self.inputs.first().cloned())?;
+            let from_file = matches!(input, Input::File(_));
+            let recovered = parser::parse_recovering_with(input.into_bytes()?, dialect, positions)?;
+            if !recovered.errors.is_empty() {
+                let name = from_file.then_some(filename.as_str());
+                return Err(file_errors(&recovered.errors, |position| {
+                    name.map(|name| (name, position))
+                })
+                .into());
+            }
+            (recovered, filename)
+        };
+        let end = recovered.end;
+        let program = bfrs_common::program::Program::located(recovered.into_result()?);
+        tracing::debug!(instructions = program.len(), "parsed {}", filename);
+        Ok((program, filename, end))
+    }
+}
+
+#[derive(Debug, StructOpt)]
+// without external subcommands, clap takes programs named close to a subcommand,
+// like `hello.b` for `help`, for a misspelled one, instead of running them. The
+// program always goes in `inputs`, so no subcommand is ever taken as external.
+#[structopt(settings = &[AppSettings::ArgsNegateSubcommands, AppSettings::AllowExternalSubcommands])]
+pub struct Opt {
+    /// Amount of cells to use, 30000 unless an armored program says otherwise
+    #[structopt(short, long)]
+    cells: Option<usize>,
+
+    /// What the cells of the tape are: bytes (`u8`), wider (`u16`, `u32`),
+    /// signed (`i8`, `i16`, `i32`) or without a bound (`bignum`)
+    #[structopt(long, default_value = "u8", possible_values = cell::Kind::VARIANTS)]
+    cell: cell::Kind,
+
+    /// How many tapes the `tapes` dialect has, 2 unless given
+    #[structopt(long)]
+    tapes: Option<usize>,
+
+    /// The cells of every row of the `grid` dialect, 100 unless given
+    #[structopt(long)]
+    width: Option<usize>,
+
+    /// Keep the tape in this file, mapped into memory, so what the program leaves
+    /// on it is there for the next run, and only the cells it touches take memory.
+    /// It's made with --cells cells if it isn't there, or grown to them. Only for
+    /// `u8` cells
+    #[structopt(long, parse(from_os_str), conflicts_with_all = &["highlight", "armor", "verify-opt"])]
+    tape_file: Option<PathBuf>,
+
+    #[structopt(flatten)]
+    source: Source,
+
+    /// Only highlight the code, don't run it
+    #[structopt(long = "highlight")]
+    highlight_only: bool,
+
+    /// Show the tape after
+    #[structopt(short, long)]
+    show_tape: bool,
+
+    /// Show how many instructions every loop ran after running the program,
+    /// the ones taking the longest first, or with `--profile=folded`, the
+    /// folded stacks of every chain of nested loops, for flamegraph tools, or
+    /// with `--profile=annotated`, the source colored by the times every
+    /// instruction ran
+    #[structopt(
+        long,
+        min_values = 0,
+        require_equals = true,
+        possible_values = ProfileFormat::VARIANTS
+    )]
+    profile: Option<Option<ProfileFormat>>,
+
+    /// Strings written on the tape before running, for the program to take
+    /// like the arguments of a command: from cell 1, where the pointer
+    /// starts, every one ending with a 0, and another 0 ending the list.
+    /// Everything after it is an argument, until the next option
+    #[structopt(long, min_values = 1)]
+    args: Vec<String>,
+
+    /// When to show what the program prints, other than before reading and
+    /// at the end: after every byte, every line, or only when it piles up
+    #[structopt(long, default_value = "line", possible_values = Flush::VARIANTS)]
+    flush: Flush,
+
+    /// How to write what the program prints: as it is (`raw`), as `hex` or
+    /// `decimal` numbers, 16 on every line, or `escaped`, as text with the
+    /// bytes that aren't printable ASCII escaped, to look at binary output
+    #[structopt(long, default_value = "raw", possible_values = OutputFormat::VARIANTS)]
+    output_format: OutputFormat,
+
+    /// What to turn the line endings the program prints into, before
+    /// --output-format: `keep` them, `lf` turns `\r\n` into `\n`, and
+    /// `crlf` turns `\n` into `\r\n`
+    #[structopt(long, default_value = "keep", possible_values = Newline::VARIANTS)]
+    newline: Newline,
+
+    /// Show the simulated cost of running the program, a cycle for every
+    /// instruction unless --cost-model says otherwise
+    #[structopt(long)]
+    score: bool,
+
+    /// A file with the cycles of every command (`+ 2`, one per line) and
+    /// the penalty of every byte printed or read (`io 10`), to --score with
+    #[structopt(long, parse(from_os_str))]
+    cost_model: Option<std::path::PathBuf>,
+
+    /// Write the profile to a file instead of stderr
+    #[structopt(long, parse(from_os_str), requires = "profile")]
+    profile_output: Option<std::path::PathBuf>,
+
+    /// Show which instructions of the source ran after running the program,
+    /// as the source with the times every line ran, or with
+    /// `--coverage=lcov`, as lcov data. The program is run as it's written
+    #[structopt(
+        long,
+        min_values = 0,
+        require_equals = true,
+        possible_values = CoverageFormat::VARIANTS,
+        conflicts_with_all = &["rules", "from-armor"]
+    )]
+    coverage: Option<Option<CoverageFormat>>,
+
+    /// Write an animation of the tape as the program runs to a file, as a GIF,
+    /// as an asciinema cast if the file ends in `.cast`, or as a web page to
+    /// step through the run with, showing the instruction and the output too,
+    /// if it ends in `.html`
+    #[structopt(long, parse(from_os_str))]
+    export_viz: Option<PathBuf>,
+
+    /// The instructions between the frames of --export-viz at first, 1 by
+    /// default, which doubles whenever they pile up, so the animation covers
+    /// the whole run
+    // Not a default value, which clap takes as given, requiring
+    // --export-viz every time.
+    #[structopt(long, requires = "export-viz")]
+    viz_every: Option<u64>,
+
+    /// Write the coverage to a file instead of stderr
+    #[structopt(long, parse(from_os_str), requires = "coverage")]
+    coverage_output: Option<std::path::PathBuf>,
+
+    /// Show how many instructions ran on every cell of the tape after running
+    /// the program
+    #[structopt(long)]
+    heatmap: bool,
+
+    /// A file of rewrite rules (`pattern => template`, one per line) applied
+    /// to the program before running it
+    #[structopt(short, long, parse(from_os_str))]
+    rules: Option<std::path::PathBuf>,
+
+    /// Unroll the loops that run a number of times known without running the
+    /// program, into at most this many operations each (256 unless given, like
+    /// `--unroll=64`), before running it
+    #[structopt(long, value_name = "ops", min_values = 0, require_equals = true)]
+    unroll: Option<Option<usize>>,
+
+    /// Read every key as soon as it's pressed, without waiting for
+    /// enter or echoing it, for interactive programs
+    #[structopt(long)]
+    raw_input: bool,
+
+    /// Read the program's input from a file instead of stdin
+    #[structopt(long, parse(from_os_str), conflicts_with = "raw-input")]
+    input_file: Option<std::path::PathBuf>,
+
+    /// Copy everything the program reads into a file, to replay it with --input-file
+    #[structopt(long, parse(from_os_str))]
+    record_input: Option<std::path::PathBuf>,
+
+    /// Print the compiled program as text to paste anywhere, instead of running it
+    #[structopt(long, conflicts_with = "from-armor")]
+    armor: bool,
+
+    /// Read a program printed by --armor, instead of a source
+    #[structopt(long, conflicts_with = "dialect")]
+    from_armor: bool,
+
+    /// Instead of running the program, check that it does the same rewritten
+    /// by --rules, unrolled by --unroll and compiled like --armor does, running
+    /// it both ways on the whole input read beforehand, and report where they differ
+    #[structopt(long, conflicts_with_all = &["highlight", "armor", "raw-input"])]
+    verify_opt: bool,
+
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum Command {
+    /// run every program with a `.out` file next to it in the given files
+    /// or directories, checking that it prints that output reading the
+    /// `.in` file next to it, if there's one
+    Test {
+        /// programs, or directories to run every test in, recursively
+        #[structopt(parse(from_os_str), required = true)]
+        paths: Vec<PathBuf>,
+    },
+    /// shrink a program while a command keeps failing on it, like one
+    /// crashing a tool, to find the instructions that make it fail
+    Reduce {
+        /// the program to shrink
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+
+        /// the command checking every smaller program, run by the shell with
+        /// `{}` standing for its file. Programs are kept while it fails
+        #[structopt(long)]
+        check: String,
+
+        /// the seconds a check can run, after which it's stopped, which
+        /// counts as not failing
+        #[structopt(long, default_value = "10")]
+        timeout: f64,
+
+        /// write the smallest program to a file instead of stdout
+        #[structopt(short, long, parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+    /// print programs in a canonical form, which is the same for variants
+    /// of a program that only spell it differently, to compare them
+    Canon {
+        /// programs, or directories to canonicalize every program in, recursively
+        #[structopt(parse(from_os_str), required = true)]
+        paths: Vec<PathBuf>,
+
+        /// print a hash of every canonical program along with its file,
+        /// like `sha256sum`, to find the duplicates of a corpus
+        #[structopt(long)]
+        hash: bool,
+    },
+    /// print a program as pseudocode, with the cells it uses and what its
+    /// loops do, to read a program nobody explained
+    Decompile {
+        /// the program to decompile
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+
+        /// number every cell, instead of naming the ones whose use can be
+        /// told, like `counter_a` for the cell a loop runs on
+        #[structopt(long)]
+        indices: bool,
+    },
+    /// run the programs bundled with the interpreter, as written and
+    /// optimized, every way it can run them, checking they print what
+    /// they should, to tell whether a build or a port works
+    Selftest,
+    /// print a program with what every loop does under it, like the idiom
+    /// it is, to read a program nobody explained
+    Explain {
+        /// the program to explain
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+    },
+}
+
+/// The names of the subcommands, in the order of [`Command`].
+const SUBCOMMANDS: [&str; 6] = [
+    "test",
+    "reduce",
+    "canon",
+    "decompile",
+    "selftest",
+    "explain",
+];
+
+/// Spells the argument a program named like a subcommand is given by as a
+/// path, like `./test`, when the file is there, so a program that's there
+/// wins over a subcommand named the same, which can still be run where there
+/// isn't one. Only the arguments before the first other file are looked at,
+/// since clap doesn't take a subcommand after the program.
+pub fn prefer_files(args: &mut [std::ffi::OsString]) {
+    for arg in args
+        .iter_mut()
+        .filter(|arg| !arg.to_string_lossy().starts_with('-'))
+    {
+        let is_file = Path::new(arg.as_os_str()).is_file();
+        if is_file && SUBCOMMANDS.iter().any(|name| arg == name) {
+            *arg = Path::new(".").join(&*arg).into_os_string();
+        }
+        if is_file {
+            return;
+        }
+    }
+}
+
+/// The cells of the tape, when neither the options nor the program say.
+const DEFAULT_CELLS: usize = 30000;
+
+/// The cells of every row of a grid, when the options don't say.
+const DEFAULT_WIDTH: usize = 100;
+
+/// What the program reads from, which is stdin one way or the other.
+#[cfg(unix)]
+pub fn program_input(raw: bool) -> io::Result<Box<dyn io::Read>> {
+    Ok(if raw {
+        Box::new(bfrs_input::raw::RawInput::new()?)
+    } else {
+        Box::new(io::stdin())
+    })
+}
+
+#[cfg(not(unix))]
+pub fn program_input(raw: bool) -> io::Result<Box<dyn io::Read>> {
+    if raw {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "raw input is only supported on unix",
+        ));
+    }
+    Ok(Box::new(io::stdin()))
+}
+
+/// What the program reads, the way the options say.
+fn open_input(opt: &Opt) -> io::Result<Box<dyn io::Read>> {
+    let mut input = match &opt.input_file {
+        Some(path) => Box::new(File::open(path)?),
+        None => program_input(opt.raw_input)?,
+    };
+    if let Some(path) = &opt.record_input {
+        input = Box::new(Tee::new(input, File::create(path)?));
+    }
+    Ok(input)
+}
+
+/// The parse errors of a program, at the file `locate` finds them in.
+fn file_errors<'a>(
+    errors: &[parser::ParseError],
+    locate: impl Fn(Position) -> Option<(&'a str, Position)>,
+) -> Diagnostics {
+    // the file a position is in, if it's known, and the position in it.
+    let place = |position| match locate(position) {
+        Some((name, position)) => (Some(name), position),
+        None => (None, position),
+    };
+    errors
+        .iter()
+        .map(|e| {
+            let (file, position) = place(e.position());
+            let mut diagnostic = Diagnostic::new(e.kind.to_string())
+                .with_code(e.code())
+                .at(position)
+                .with_end(place(e.end.position).1);
+            if let Some(file) = file {
+                diagnostic = diagnostic.in_file(file);
+            }
+            if let parser::ParseErrorKind::MissingRB(opened_at) = e.kind {
+                let (opened_in, opened_at) = place(opened_at);
+                diagnostic.message = match opened_in {
+                    Some(name) => format!(
+                        "Unclosed loop: last opening was found at {}:{}",
+                        name, opened_at
+                    ),
+                    None => format!("Unclosed loop: last opening was found at {}", opened_at),
+                };
+                // only the openings in the same file can be shown along with the error.
+                if opened_in == file {
+                    diagnostic = diagnostic.with_label(opened_at, "the loop was opened here");
+                }
+            }
+            diagnostic
+        })
+        .collect()
+}
+
+/// An armored program, named after its file if it doesn't have a name.
+fn load_armored(inputs: &[String]) -> Result<Armored, Box<dyn Error>> {
+    let (mut input, filename) = Input::from_optional_arg(inputs.first().cloned())?;
+    if inputs.len() > 1 {
+        return Err("only one armored program can be run at once".into());
+    }
+    let mut text = String::new();
+    io::Read::read_to_string(&mut input, &mut text)?;
+    let mut armored = Armored::decode(&text)?;
+    armored.name = armored.name.or(Some(filename));
+    Ok(armored)
+}
+
+/// Runs the program the options say.
+pub fn run(opt: Opt, common: &Common) -> Result<(), Box<dyn Error>> {
+    match &opt.command {
+        Some(Command::Test { paths }) => return golden::run(paths),
+        Some(Command::Reduce {
+            input,
+            check,
+            timeout,
+            output,
+        }) => {
+            let timeout = std::time::Duration::from_secs_f64(*timeout);
+            return reduce::run(input, check, timeout, output.as_deref());
+        }
+        Some(Command::Canon { paths, hash }) => return canon(paths, *hash),
+        Some(Command::Decompile { input, indices }) => {
+            let instructions = parser::parse_bytes(&std::fs::read(input)?)?;
+            print!("{}", decompile::decompile(&instructions, !indices));
+            return Ok(());
+        }
+        Some(Command::Selftest) => return selftest::run(),
+        Some(Command::Explain { input }) => {
+            let source = std::fs::read(input)?;
+            let program =
+                bfrs_common::program::Program::parse_located(source.iter().map(|&byte| Ok(byte)))?;
+            let library = bfrs_patterns::library::idioms();
+            let notes = annotate::annotate(&program.instructions, &library);
+            let locations = program.locations.unwrap_or_default();
+            annotate::print(&source, &locations, &notes, &mut io::stdout().lock())?;
+            return Ok(());
+        }
+        None => (),
+    }
+    let (dialect, extensions) = split_dialect(&opt.source.dialect)?;
+    if !extensions.is_empty() {
+        return run_extended(&opt, common, dialect, &extensions);
+    }
+    let viz_format = match &opt.export_viz {
+        Some(path) => match VizFormat::from_path(path) {
+            Some(format) => Some(format),
+            None => return Err("--export-viz writes `.gif`, `.cast` or `.html` files".into()),
+        },
+        None => None,
+    };
+    if opt.coverage.is_some() && opt.source.inputs.is_empty() {
+        return Err("--coverage shows the files of the program, which is read from stdin".into());
+    }
+    if opt.profile == Some(Some(ProfileFormat::Annotated)) {
+        if opt.source.inputs.is_empty() {
+            return Err(
+                "--profile=annotated shows the files of the program, which is read from stdin"
+                    .into(),
+            );
+        }
+        if opt.rules.is_some() || opt.unroll.is_some() || opt.from_armor {
+            return Err(
+                "--profile=annotated shows the source, which --rules, --unroll and --from-armor lose"
+                    .into(),
+            );
+        }
+    }
+    let (mut instructions, mut positions, filename, armored_cells) = if opt.from_armor {
+        let armored = load_armored(&opt.source.inputs)?;
+        let name = armored.name.unwrap_or_default();
+        (Ir::expand(&armored.ops), None, name, armored.cells)
+    } else {
+        let (located, filename) = opt.source.load_located(common.positions())?;
+        let (instructions, positions) = located.into_iter().unzip();
+        (instructions, Some(positions), filename, None)
+    };
+    let original = opt.verify_opt.then(|| instructions.clone());
+    if let Some(rules_path) = &opt.rules {
+        instructions = apply_rules_file(instructions, rules_path)?;
+        // the instructions don't line up with the source anymore.
+        positions = None;
+    }
+    if let Some(budget) = opt.unroll {
+        instructions = unroll::unroll(&instructions, budget.unwrap_or(unroll::BUDGET));
+        positions = None;
+    }
+    let tape_file = match &opt.tape_file {
+        Some(path) => Some(open_tape(path, opt.cells, opt.cell)?),
+        None => None,
+    };
+    let cells = match &tape_file {
+        Some(file) => file.metadata()?.len() as usize,
+        None => opt.cells.or(armored_cells).unwrap_or(DEFAULT_CELLS),
+    };
+    let args_len = Machine::<u8>::args_len(&opt.args);
+    if !opt.args.is_empty() && args_len > cells {
+        return Err(format!(
+            "the arguments take {} cells, but the tape only has {}",
+            args_len, cells
+        )
+        .into());
+    }
+    if opt.armor {
+        let armored = Armored {
+            ops: Ir::compile(&instructions).ops,
+            cells: Some(cells),
+            name: Some(filename),
+        };
+        print!("{}", armored.encode());
+        return Ok(());
+    }
+    // a cost model is only given to score with it.
+    let costs = match &opt.cost_model {
+        Some(path) => Some(CostModel::parse(&std::fs::read_to_string(path)?)?),
+        None => opt.score.then(CostModel::default),
+    };
+    let target = Target {
+        program: Program::from_instructions(instructions, cells),
+        filename,
+        positions,
+        viz_format,
+        config: common.positions(),
+        color: common.colored(io::stderr().is_terminal()),
+        costs,
+    };
+    if opt.highlight_only {
+        let theme = common.theme(io::stdout().is_terminal())?;
+        highlight_code(&target.program.code, theme.as_ref());
+    } else {
+        let mut input = open_input(&opt)?;
+        if let Some(original) = original {
+            let mut bytes = Vec::new();
+            input.read_to_end(&mut bytes)?;
+            return opt.cell.with(Verify {
+                original: &original,
+                target: &target,
+                input: &bytes,
+                opt: &opt,
+            });
+        }
+        opt.cell.with(Run {
+            target: &target,
+            input: &mut input,
+            opt: &opt,
+            tape_file: tape_file.as_ref(),
+        })?;
+    }
+    Ok(())
+}
+
+/// Rewrites the instructions with the rules of the file at `path`, in order.
+fn apply_rules_file(
+    instructions: Vec<BFCommand>,
+    path: &Path,
+) -> Result<Vec<BFCommand>, Box<dyn Error>> {
+    let rules = rewrite::parse_rules(&std::fs::read_to_string(path)?)?;
+    Ok(rewrite::apply_rules(instructions, &rules))
+}
+
+/// Prints the canonical form of every program in `paths`, or its hash.
+fn canon(paths: &[PathBuf], hash: bool) -> Result<(), Box<dyn Error>> {
+    // a broken program doesn't stop the others, which corpora are full of.
+    let mut diagnostics = Diagnostics::new();
+    for path in bfrs_patterns::corpus::files(paths, &[])? {
+        let instructions = match parser::parse_bytes(&std::fs::read(&path)?) {
+            Ok(instructions) => instructions,
+            Err(e) => {
+                diagnostics.push(e.diagnostic().in_file(path.display().to_string()));
+                continue;
+            }
+        };
+        let canonical = canon::canonicalize(&instructions);
+        if hash {
+            println!("{:016x}  {}", canon::hash(&canonical), path.display());
+        } else {
+            let source: String = canonical.iter().map(ToString::to_string).collect();
+            println!("{}", source);
+        }
+    }
+    Ok(diagnostics.into_result(())?)
+}
+
+/// Runs the program as it was written and the way it's run, rewritten by
+/// the rules and compiled, on the same input, failing at the first
+/// difference in what they print or in the tape they leave.
+struct Verify<'a> {
+    original: &'a [BFCommand],
+    target: &'a Target,
+    input: &'a [u8],
+    opt: &'a Opt,
+}
+
+impl WithCell for Verify<'_> {
+    type Output = Result<(), Box<dyn Error>>;
+
+    fn call<C: Cell>(self) -> Self::Output {
+        verify::<C>(self.original, self.target, self.input, self.opt)
+    }
+}
+
+fn verify<C: Cell>(
+    original: &[BFCommand],
+    target: &Target,
+    input: &[u8],
+    opt: &Opt,
+) -> Result<(), Box<dyn Error>> {
+    let cells = target.program.tape_size;
+    let optimized = Ir::expand(&Ir::compile(&target.program.code.instructions).ops);
+    let options = RunOptions {
+        fuel: None,
+        args: opt.args.iter().map(|arg| arg.as_bytes().to_vec()).collect(),
+        ..RunOptions::default()
+    };
+    let original = Program::from_instructions(original.to_vec(), cells);
+    let expected: RunResult<C> = run_captured(&original, input, &options);
+    let optimized = Program::from_instructions(optimized, cells);
+    let actual: RunResult<C> = run_captured(&optimized, input, &options);
+    let byte = |output: &[u8], i: usize| match output.get(i) {
+        Some(&byte) => format!("{:?}", char::from(byte)),
+        None => String::from("nothing"),
+    };
+    if let Some(i) = first_difference(&expected.output, &actual.output) {
+        return Err(format!(
+            "the output differs at byte {}: {} as written, {} optimized",
+            i,
+            byte(&expected.output, i),
+            byte(&actual.output, i)
+        )
+        .into());
+    }
+    if let Some(i) = first_difference(&expected.tape, &actual.tape) {
+        return Err(format!(
+            "cell {} differs at the end: {} as written, {} optimized",
+            i, expected.tape[i], actual.tape[i]
+        )
+        .into());
+    }
+    if expected.pointer != actual.pointer {
+        return Err(format!(
+            "the pointer ends at cell {} as written, but at {} optimized",
+            expected.pointer, actual.pointer
+        )
+        .into());
+    }
+    eprintln!(
+        "program {} does the same optimized, printing {} bytes",
+        target.filename,
+        expected.output.len()
+    );
+    Ok(())
+}
+
+/// Runs a program of `dialect` with the [`SPACES`] of `extensions`, whose
+/// instructions only [`Tapes`] runs, so the options about watching or
+/// rewriting the program don't apply.
+fn run_extended(
+    opt: &Opt,
+    common: &Common,
+    dialect: &str,
+    extensions: &[&str],
+) -> Result<(), Box<dyn Error>> {
+    let unsupported = [
+        (opt.highlight_only, "--highlight"),
+        (opt.profile.is_some(), "--profile"),
+        (opt.coverage.is_some(), "--coverage"),
+        (opt.heatmap, "--heatmap"),
+        (opt.export_viz.is_some(), "--export-viz"),
+        (opt.score || opt.cost_model.is_some(), "--score"),
+        (!opt.args.is_empty(), "--args"),
+        (opt.rules.is_some(), "--rules"),
+        (opt.unroll.is_some(), "--unroll"),
+        (opt.armor, "--armor"),
+        (opt.from_armor, "--from-armor"),
+        (opt.verify_opt, "--verify-opt"),
+        (opt.tape_file.is_some(), "--tape-file"),
+    ];
+    if let Some((_, option)) = unsupported.iter().find(|(given, _)| *given) {
+        return Err(format!(
+            "{} can't be used with the `{}` dialect",
+            option, opt.source.dialect
+        )
+        .into());
+    }
+    let has = |extension| extensions.contains(&extension);
+    if opt.tapes.is_some() && !has("tapes") {
+        return Err("--tapes is only for the `tapes` dialect".into());
+    }
+    if opt.width.is_some() && !has("grid") {
+        return Err("--width is only for the `grid` dialect".into());
+    }
+    let mut source = Vec::new();
+    let inputs = match opt.source.inputs.as_slice() {
+        [] => vec![None],
+        inputs => inputs.iter().cloned().map(Some).collect(),
+    };
+    for input in inputs {
+        Input::from_optional_arg(input)?
+            .0
+            .read_to_end(&mut source)?;
+    }
+    let spaces = extensions
+        .iter()
+        .filter_map(|extension| Extensions::builtin(extension))
+        .fold(Extensions::default(), |all, extension| all.with(&extension));
+    let bytes = source.iter().map(|&byte| Ok(byte));
+    let instructions =
+        parser::parse_extended_with(bytes, load_dialect(dialect)?, spaces, common.positions())
+            .map(|parsed| parsed.map(|(instruction, _)| instruction))
+            .collect::<Result<Vec<_>, _>>()?;
+    let cells = opt.cells.unwrap_or(DEFAULT_CELLS);
+    opt.cell.with(RunTapes {
+        instructions: &instructions,
+        tapes: if has("tapes") {
+            opt.tapes.unwrap_or(2)
+        } else {
+            1
+        },
+        cells,
+        width: if has("grid") {
+            opt.width.unwrap_or(DEFAULT_WIDTH)
+        } else {
+            cells
+        },
+        input: &mut open_input(opt)?,
+        opt,
+    })?;
+    Ok(())
+}
+
+/// Runs a program with [`Tapes`] of the cells the options say.
+struct RunTapes<'a> {
+    instructions: &'a [ExtCommand],
+    tapes: usize,
+    cells: usize,
+    width: usize,
+    input: &'a mut dyn io::Read,
+    opt: &'a Opt,
+}
+
+impl WithCell for RunTapes<'_> {
+    type Output = io::Result<()>;
+
+    fn call<C: Cell>(self) -> Self::Output {
+        let mut tapes = Tapes::<C>::new(self.tapes, self.cells, self.width);
+        let mut output = ProgramOutput::new(io::stdout().lock(), self.opt.flush)
+            .transcoded(self.opt.output_format, self.opt.newline);
+        tapes.run(self.instructions, self.input, &mut output)?;
+        drop(output);
+        if self.opt.show_tape {
+            for (i, tape) in tapes.tapes.iter().enumerate() {
+                eprintln!("result tape {}: {:?}", i, tape);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The index of the first element that differs, or where the shorter ends.
+fn first_difference<T: PartialEq>(a: &[T], b: &[T]) -> Option<usize> {
+    a.iter()
+        .zip(b)
+        .position(|(a, b)| a != b)
+        .or_else(|| (a.len() != b.len()).then(|| a.len().min(b.len())))
+}
+
+/// A program to run, along with where it comes from.
+struct Target {
+    program: Program,
+    filename: String,
+    /// the position of every instruction, unless they
+    /// come from an armored program or were rewritten
+    positions: Option<Vec<Position>>,
+    /// what to write the animation of the tape as, if it's exported
+    viz_format: Option<VizFormat>,
+    /// how the positions are counted
+    config: PositionConfig,
+    /// whether to color what's shown about the run on stderr
+    color: bool,
+    /// the model to score the program with, if it's scored
+    costs: Option<CostModel>,
+}
+
+impl Target {
+    /// Where the instruction at `index` is, for people to find it.
+    fn locate(&self, index: usize) -> String {
+        match &self.positions {
+            Some(positions) => positions[index].to_string(),
+            None => format!("instruction {}", index),
+        }
+    }
+}
+
+/// Opens the file of `--tape-file`, making it or growing it to `cells`
+/// bytes, or the default tape if it's new, so it can be mapped as the tape.
+fn open_tape(path: &Path, cells: Option<usize>, kind: cell::Kind) -> Result<File, Box<dyn Error>> {
+    if kind != cell::Kind::U8 {
+        return Err("--tape-file only keeps tapes of `u8` cells".into());
+    }
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)?;
+    let len = file.metadata()?.len() as usize;
+    let cells = match cells {
+        Some(cells) => cells,
+        None if len == 0 => DEFAULT_CELLS,
+        None => len,
+    };
+    // shrinking it would lose what the program left there.
+    if cells < len {
+        return Err(format!(
+            "{} has {} cells, more than the {} of --cells",
+            path.display(),
+            len,
+            cells
+        )
+        .into());
+    }
+    file.set_len(cells as u64)?;
+    Ok(file)
+}
+
+/// Runs the program on a tape of the cells the options say, timing it.
+struct Run<'a> {
+    target: &'a Target,
+    input: &'a mut dyn io::Read,
+    opt: &'a Opt,
+    /// the file to map as the tape, instead of a blank one
+    tape_file: Option<&'a File>,
+}
+
+impl WithCell for Run<'_> {
+    type Output = io::Result<()>;
+
+    fn call<C: Cell>(self) -> Self::Output {
+        run_with::<C>(self.target, self.input, self.opt, self.tape_file)
+    }
+}
+
+fn run_with<C: Cell>(
+    target: &Target,
+    input: &mut dyn io::Read,
+    opt: &Opt,
+    tape_file: Option<&File>,
+) -> io::Result<()> {
+    use std::time::Instant;
+    let program = &target.program;
+    let mut machine = match tape_file {
+        Some(file) => Machine::<C>::with_tape(Tape::map(file)?),
+        None => Machine::<C>::new(program.tape_size),
+    };
+    if !opt.args.is_empty() {
+        machine.load_args(&opt.args);
+    }
+    let mut hooks = (
+        (opt.profile.is_some() || opt.coverage.is_some()).then(|| Profiler::new(program)),
+        (
+            opt.heatmap.then(|| Heatmap::new(program.tape_size)),
+            (
+                target.costs.as_ref().map(|costs| costs.scorer(program)),
+                opt.export_viz
+                    .as_ref()
+                    .map(|_| Recorder::new(opt.viz_every.unwrap_or(1))),
+            ),
+        ),
+    );
+    let _span = tracing::info_span!("run", program = %target.filename).entered();
+    let start_time = Instant::now();
+    let mut output = ProgramOutput::new(io::stdout().lock(), opt.flush)
+        .transcoded(opt.output_format, opt.newline);
+    // the threaded core is faster, but nothing can watch it.
+    let watched = opt.profile.is_some()
+        || opt.coverage.is_some()
+        || opt.heatmap
+        || target.costs.is_some()
+        || opt.export_viz.is_some();
+    let ran = if watched {
+        machine.run_with_hooks(program, input, &mut output, &mut hooks)
+    } else {
+        machine.run(program, input, &mut output)
+    };
+    drop(output);
+    // what the program left on a mapped tape is kept even if it failed.
+    machine.tape.flush()?;
+    ran?;
+    let time = Instant::now().duration_since(start_time);
+    tracing::info!(
+        micros = time.as_micros() as u64,
+        "program {} executed",
+        target.filename
+    );
+    if opt.show_tape {
+        eprintln!("result tape: {:?}", machine.tape);
+    }
+    if let (Some(profiler), Some(format), Some(positions)) =
+        (&hooks.0, opt.coverage, &target.positions)
+    {
+        let mut out: Box<dyn io::Write> = match &opt.coverage_output {
+            Some(path) => Box::new(io::BufWriter::new(File::create(path)?)),
+            None => Box::new(io::stderr()),
+        };
+        let format = format.unwrap_or(CoverageFormat::Annotated);
+        let inputs = &opt.source.inputs;
+        coverage::write(format, profiler, positions, inputs, target.config, &mut out)?;
+        out.flush()?;
+    }
+    if let (Some(profiler), Some(format)) = (&hooks.0, opt.profile) {
+        let mut out: Box<dyn io::Write> = match &opt.profile_output {
+            Some(path) => Box::new(io::BufWriter::new(File::create(path)?)),
+            None => Box::new(io::stderr()),
+        };
+        match format.unwrap_or(ProfileFormat::Table) {
+            ProfileFormat::Table => write_profile(profiler, target, &mut out)?,
+            ProfileFormat::Annotated => {
+                let positions = target
+                    .positions
+                    .as_ref()
+                    .expect("the source of annotated profiles is checked before running");
+                let color = target.color && opt.profile_output.is_none();
+                let inputs = &opt.source.inputs;
+                heat::write(profiler, positions, inputs, target.config, color, &mut out)?;
+            }
+            ProfileFormat::Folded => {
+                let stacks = profiler.folded(program, &target.filename, |start| {
+                    format!("loop@{}", target.locate(start).replace(' ', "-"))
+                });
+                for (stack, count) in stacks {
+                    writeln!(out, "{} {}", stack, count)?;
+                }
+            }
+        }
+        out.flush()?;
+    }
+    let (heatmap, (scorer, recorder)) = &mut hooks.1;
+    if let (Some(recorder), Some(path), Some(format)) =
+        (recorder, &opt.export_viz, target.viz_format)
+    {
+        recorder.finish(&machine);
+        let mut out = io::BufWriter::new(File::create(path)?);
+        viz::write(
+            recorder,
+            &target.program.code.instructions,
+            format,
+            &mut out,
+        )?;
+        out.flush()?;
+    }
+    if let Some(scorer) = scorer {
+        eprintln!("score: {} cycles", scorer.score);
+    }
+    if let Some(heatmap) = heatmap {
+        print_heatmap(heatmap);
+    }
+    Ok(())
+}
+
+/// How `--profile` shows where the time went.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProfileFormat {
+    /// the loops that ran the most instructions
+    Table,
+    /// the folded stacks of flamegraph tools
+    Folded,
+    /// the source, colored by the times every instruction ran
+    Annotated,
+}
+
+impl ProfileFormat {
+    pub const VARIANTS: &'static [&'static str] = &["table", "folded", "annotated"];
+}
+
+impl std::str::FromStr for ProfileFormat {
+    type Err = UnknownProfileFormat;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "table" => Self::Table,
+            "folded" => Self::Folded,
+            "annotated" => Self::Annotated,
+            _ => return Err(UnknownProfileFormat(s.to_string())),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct UnknownProfileFormat(String);
+
+impl std::fmt::Display for UnknownProfileFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "unknown profile format: {:?}", self.0)
+    }
+}
+
+impl Error for UnknownProfileFormat {}
+
+/// The loops shown by `--profile`, since a program can have thousands.
+const PROFILED_LOOPS: usize = 20;
+/// The instructions of a loop shown by `--profile`.
+const PROFILED_CODE: usize = 40;
+
+fn write_profile(profiler: &Profiler, target: &Target, out: &mut dyn io::Write) -> io::Result<()> {
+    let program = &target.program;
+    writeln!(out, "profile: {} instructions run", profiler.total())?;
+    let loops = profiler.loops(program);
+    if loops.is_empty() {
+        return Ok(());
+    }
+    writeln!(out, "{:>14} {:>12}  loop", "instructions", "iterations")?;
+    for profile in loops.iter().take(PROFILED_LOOPS) {
+        let code = &program.code.instructions[profile.start..=profile.end];
+        let mut text: String = code
+            .iter()
+            .take(PROFILED_CODE)
+            .map(ToString::to_string)
+            .collect();
+        if code.len() > PROFILED_CODE {
+            text += "...";
+        }
+        writeln!(
+            out,
+            "{:>14} {:>12}  {} ({})",
+            profile.instructions,
+            profile.iterations,
+            text,
+            target.locate(profile.start)
+        )?;
+    }
+    if loops.len() > PROFILED_LOOPS {
+        writeln!(out, "and {} more loops", loops.len() - PROFILED_LOOPS)?;
+    }
+    Ok(())
+}
+
+/// The widest bar of `--heatmap`.
+const HEATMAP_WIDTH: u64 = 50;
+
+fn print_heatmap(heatmap: &Heatmap) {
+    let used = heatmap.used();
+    let hottest = used.iter().copied().max().unwrap_or_default().max(1);
+    eprintln!("heatmap: instructions run on every cell");
+    for (cell, &count) in used.iter().enumerate() {
+        // a cell that was used at all gets at least a bit of a bar.
+        let width = (count * HEATMAP_WIDTH).div_ceil(hottest) as usize;
+        eprintln!("{:>6} {:>14} {}", cell, count, "#".repeat(width));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn subcommands_are_listed() {
+        let app = Opt::clap();
+        let names: Vec<&str> = app.p.subcommands.iter().map(|app| app.get_name()).collect();
+        assert_eq!(names, SUBCOMMANDS);
+    }
+
+    #[test]
+    fn rules_keep_what_programs_print() {
+        let path = std::env::temp_dir().join(format!("bfrs-rules-{}", std::process::id()));
+        std::fs::write(
+            &path,
+            "# the same loops, written another way\n[+] => [-]\nx[-y+z+x] => x[-z+y+x]\n",
+        )
+        .unwrap();
+        let programs = [
+            "++++++++[->++++++++<]>+.[+]++++[->+>+<<]>.>.",
+            ",[->+>+<<]>.>.<<,[+]+++.",
+        ];
+        for src in programs.iter() {
+            let instructions = parser::parse_str(src).unwrap();
+            let rewritten = apply_rules_file(instructions.clone(), &path).unwrap();
+            assert_ne!(rewritten, instructions, "no rule applied to {}", src);
+            let run = |instructions: Vec<BFCommand>| {
+                let program = Program::from_instructions(instructions, 30);
+                run_captured::<u8>(&program, b"ab", &RunOptions::default()).output
+            };
+            assert_eq!(run(rewritten), run(instructions), "{}", src);
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    fn target(src: &str) -> Target {
+        Target {
+            program: Program::from_instructions(parser::parse_str(src).unwrap(), 8),
+            filename: String::from("a.bf"),
+            positions: None,
+            viz_format: None,
+            config: PositionConfig::default(),
+            color: false,
+            costs: None,
+        }
+    }
+
+    #[test]
+    fn optimized_programs_are_compared_with_their_source() {
+        let opt = Opt::from_iter_safe(["bfrs", "a.bf", "--verify-opt"].iter()).unwrap();
+        let src = ",[->+>+<<]>.>.[-]";
+        let original = parser::parse_str(src).unwrap();
+        assert!(verify::<u8>(&original, &target(src), b"a", &opt).is_ok());
+
+        // as if a rule had broken the program
+        let err = verify::<u8>(&original, &target(",[->+>+<<]>.>+."), b"a", &opt).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "the output differs at byte 1: 'a' as written, 'b' optimized"
+        );
+        let err = verify::<u8>(&original, &target(",[->+>+<<]>.>.+"), b"a", &opt).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "cell 2 differs at the end: 0 as written, 98 optimized"
+        );
+        let err = verify::<u8>(&original, &target(",[->+>+<<]>.>.[-]>"), b"a", &opt).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "the pointer ends at cell 2 as written, but at 3 optimized"
+        );
+    }
+
+    #[test]
+    fn differences_are_where_the_shorter_ends() {
+        assert_eq!(first_difference(b"abc", b"abd"), Some(2));
+        assert_eq!(first_difference(b"ab", b"abc"), Some(2));
+        assert_eq!(first_difference(b"abc", b"abc"), None);
+    }
+
+    #[test]
+    fn dialects_with_more_room() {
+        let split = |name| split_dialect(name).unwrap();
+        assert_eq!(split("ook"), ("ook", vec![]));
+        assert_eq!(split("grid"), ("brainfuck", vec!["grid"]));
+        assert_eq!(split("blub+tapes+grid"), ("blub", vec!["tapes", "grid"]));
+        let e = split_dialect("ook+stack").unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "unknown extension `stack` of `ook+stack`: expected tapes or grid"
+        );
+        let e = load_dialect("bf+tapes").unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "programs of the `tapes` dialect can only be run"
+        );
+    }
+
+    #[test]
+    fn tape_files_are_made_and_grown() {
+        let path = std::env::temp_dir().join(format!("bfrs-tape-file-{}", std::process::id()));
+        let len = |file: File| file.metadata().unwrap().len();
+        assert_eq!(len(open_tape(&path, None, cell::Kind::U8).unwrap()), 30000);
+        std::fs::write(&path, b"ab").unwrap();
+        assert_eq!(len(open_tape(&path, None, cell::Kind::U8).unwrap()), 2);
+        assert_eq!(len(open_tape(&path, Some(5), cell::Kind::U8).unwrap()), 5);
+        assert_eq!(std::fs::read(&path).unwrap(), b"ab\0\0\0");
+        let e = open_tape(&path, Some(3), cell::Kind::U8).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            format!("{} has 5 cells, more than the 3 of --cells", path.display())
+        );
+        let e = open_tape(&path, None, cell::Kind::U16).unwrap_err();
+        assert_eq!(e.to_string(), "--tape-file only keeps tapes of `u8` cells");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,257 @@
+//! What `--coverage` shows: which instructions of the source ran, from
+//! the counts of a [`Profiler`], to find the code a run never gets to.
+//!
+//! The annotated listing shows every line of the source like gcov does,
+//! with the times it ran, `#####` if it has instructions that never ran,
+//! or `-` if it has none, and marks the instructions that never ran in the
+//! lines that ran with a `^` below them. lcov data can be read by the
+//! tools of lcov, like `genhtml`.
+
+use crate::hooks::Profiler;
+use bfrs_common::{Position, PositionConfig};
+use bfrs_input::chain::ChainedBytes;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+/// How `--coverage` shows what ran.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoverageFormat {
+    /// the source, with the times every line ran
+    Annotated,
+    /// the tracefiles of lcov
+    Lcov,
+}
+
+impl CoverageFormat {
+    pub const VARIANTS: &'static [&'static str] = &["annotated", "lcov"];
+}
+
+impl FromStr for CoverageFormat {
+    type Err = UnknownCoverageFormat;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "annotated" => Self::Annotated,
+            "lcov" => Self::Lcov,
+            _ => return Err(UnknownCoverageFormat(s.to_string())),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct UnknownCoverageFormat(String);
+
+impl fmt::Display for UnknownCoverageFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown coverage format: {:?}", self.0)
+    }
+}
+
+impl Error for UnknownCoverageFormat {}
+
+/// What ran of a line of the source.
+#[derive(Debug, Default)]
+struct Line {
+    /// the most times an instruction of the line ran
+    hits: u64,
+    /// the columns of the instructions that never ran
+    dead: Vec<usize>,
+}
+
+/// The lines with instructions of a file, by line.
+type Lines = BTreeMap<usize, Line>;
+
+/// Writes the coverage of a run of the program made of the files `inputs`,
+/// where the instruction at every index is at `positions[index]`, counted
+/// the way `config` says.
+pub fn write(
+    format: CoverageFormat,
+    profiler: &Profiler,
+    positions: &[Position],
+    inputs: &[String],
+    config: PositionConfig,
+    out: &mut dyn io::Write,
+) -> io::Result<()> {
+    let mut files: Vec<_> = inputs.iter().map(|_| Lines::new()).collect();
+    let located = locate(positions, inputs, config)?;
+    for ((file, position), &count) in located.into_iter().zip(&profiler.counts) {
+        let line = files[file].entry(position.line).or_default();
+        line.hits = line.hits.max(count);
+        if count == 0 {
+            line.dead.push(position.column);
+        }
+    }
+
+    match format {
+        CoverageFormat::Annotated => {
+            let run = profiler.counts.iter().filter(|&&count| count > 0).count();
+            writeln!(
+                out,
+                "coverage: {} of {} instructions run ({:.1}%)",
+                run,
+                positions.len(),
+                percent(run, positions.len())
+            )?;
+            for (name, lines) in inputs.iter().zip(&files) {
+                let src = String::from_utf8_lossy(&std::fs::read(name)?).into_owned();
+                annotate(name, &src, lines, config, out)?;
+            }
+        }
+        CoverageFormat::Lcov => {
+            for (name, lines) in inputs.iter().zip(&files) {
+                writeln!(out, "TN:\nSF:{}", name)?;
+                for (line, coverage) in lines {
+                    writeln!(out, "DA:{},{}", lcov_line(*line, config), coverage.hits)?;
+                }
+                let hit = lines.values().filter(|line| line.hits > 0).count();
+                writeln!(out, "LF:{}\nLH:{}\nend_of_record", lines.len(), hit)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Where every instruction at `positions` of the program made of the files
+/// `inputs` is, as the index of its file and its position in it.
+pub(super) fn locate(
+    positions: &[Position],
+    inputs: &[String],
+    config: PositionConfig,
+) -> io::Result<Vec<(usize, Position)>> {
+    if inputs.len() <= 1 {
+        return Ok(positions.iter().map(|&position| (0, position)).collect());
+    }
+    // the positions of a program made of several files are in all of them.
+    let mut chain = ChainedBytes::open(inputs, config)?;
+    chain.by_ref().try_for_each(|byte| byte.map(drop))?;
+    let located = positions.iter().map(|&position| {
+        let (name, position) = chain
+            .locate(position)
+            .expect("every instruction is in a file");
+        let file = inputs.iter().position(|input| *input == name);
+        (file.expect("the files are the inputs"), position)
+    });
+    Ok(located.collect())
+}
+
+fn percent(part: usize, whole: usize) -> f64 {
+    if whole == 0 {
+        100.0
+    } else {
+        part as f64 * 100.0 / whole as f64
+    }
+}
+
+/// lcov counts lines from 1, whichever way they're shown.
+fn lcov_line(line: usize, config: PositionConfig) -> usize {
+    line - Position::start(&config).line + 1
+}
+
+fn annotate(
+    name: &str,
+    src: &str,
+    lines: &Lines,
+    config: PositionConfig,
+    out: &mut dyn io::Write,
+) -> io::Result<()> {
+    writeln!(out, "{:>9}:{:>5}:Source:{}", "-", 0, name)?;
+    let first = Position::start(&config);
+    for (i, text) in src.lines().enumerate() {
+        let line = first.line + i;
+        let hits = match lines.get(&line) {
+            None => "-".to_string(),
+            Some(coverage) if coverage.hits == 0 => "#####".to_string(),
+            Some(coverage) => coverage.hits.to_string(),
+        };
+        writeln!(out, "{:>9}:{:>5}:{}", hits, line, text)?;
+        let partial = lines
+            .get(&line)
+            .filter(|coverage| coverage.hits > 0 && !coverage.dead.is_empty());
+        if let Some(coverage) = partial {
+            writeln!(
+                out,
+                "{:>9} {:>5} {}",
+                "",
+                "",
+                marks(text, &coverage.dead, config)
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// A `^` below every column of `text` in `dead`, keeping its tabs
+/// so the marks line up with it.
+fn marks(text: &str, dead: &[usize], config: PositionConfig) -> String {
+    let mut position = Position::start(&config);
+    let mut marks = String::new();
+    for ch in text.chars() {
+        marks.push(match ch {
+            _ if dead.contains(&position.column) => '^',
+            '\t' => '\t',
+            _ => ' ',
+        });
+        position.advance_char_with(ch, &config);
+    }
+    marks.trim_end().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Machine, Program};
+    use bfrs_common::dialect::Dialect;
+    use bfrs_common::parser;
+
+    /// The lcov data of running `src` once, from a file named `f.b`.
+    fn lcov(src: &str, config: PositionConfig) -> String {
+        let (instructions, locations): (Vec<_>, Vec<_>) =
+            parser::parse_located_with(src.bytes().map(Ok), Dialect::default(), config)
+                .map(Result::unwrap)
+                .unzip();
+        let positions: Vec<_> = locations.iter().map(|location| location.position).collect();
+        let program = Program::from_instructions(instructions, 10);
+        let mut profiler = Profiler::new(&program);
+        let mut machine = Machine::<u8>::new(program.tape_size);
+        let mut next = 0;
+        while next < program.code.instructions.len() {
+            next = machine
+                .step_with_hooks(
+                    &program,
+                    next,
+                    &mut io::empty(),
+                    &mut io::sink(),
+                    &mut profiler,
+                )
+                .unwrap();
+        }
+        let mut out = Vec::new();
+        let inputs = ["f.b".to_string()];
+        write(
+            CoverageFormat::Lcov,
+            &profiler,
+            &positions,
+            &inputs,
+            config,
+            &mut out,
+        )
+        .unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn lcov_lines() {
+        // the loop is never taken, so its second line never runs, and
+        // the line without instructions isn't in the data at all.
+        let src = "+>[\n-\n\n]<.";
+        let expected = "TN:\nSF:f.b\nDA:1,1\nDA:2,0\nDA:4,1\nLF:3\nLH:2\nend_of_record\n";
+        assert_eq!(lcov(src, PositionConfig::default()), expected);
+        let zero_based = PositionConfig {
+            zero_based: true,
+            ..PositionConfig::default()
+        };
+        assert_eq!(lcov(src, zero_based), expected);
+    }
+}
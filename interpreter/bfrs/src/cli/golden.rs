@@ -0,0 +1,226 @@
+//! Golden tests: programs run on the input next to them, whose output has
+//! to be the one next to them too. `prog.b` is a test when there's a
+//! `prog.out` beside it, and it reads `prog.in` if there's one, or
+//! nothing otherwise.
+//!
+//! The lines a test starts with can change how it's run, as comments
+//! starting with `#`:
+//! ```text
+//! # cells: 100
+//! # cell: u16
+//! # args: hello world
+//! # fuel: 1000
+//! ```
+//! `fuel` is the most instructions the test can run before failing,
+//! which keeps a test that never ends from hanging the others.
+
+use super::{first_difference, DEFAULT_CELLS};
+use crate::capture::{run_captured, RunOptions, RunResult};
+use crate::cell::{self, Cell, WithCell};
+use crate::Program;
+use bfrs_common::errors::{Diagnostic, Diagnostics};
+use bfrs_common::parser;
+use bfrs_patterns::corpus;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+/// The instructions a test can run unless its header says otherwise.
+const DEFAULT_FUEL: u64 = 10_000_000;
+
+/// The lines of a diff shown at most from each side.
+const DIFF_LINES: usize = 5;
+
+/// How a test is run, from its header.
+struct Header {
+    cells: usize,
+    cell: cell::Kind,
+    options: RunOptions,
+}
+
+impl Header {
+    /// Reads the header off `src`, blanking its lines so they're neither
+    /// run nor moving the positions of the rest.
+    fn strip(src: &mut [u8]) -> Result<Self, String> {
+        let mut header = Self {
+            cells: DEFAULT_CELLS,
+            cell: cell::Kind::U8,
+            options: RunOptions {
+                fuel: Some(DEFAULT_FUEL),
+                args: Vec::new(),
+                ..RunOptions::default()
+            },
+        };
+        let mut start = 0;
+        while src[start..].starts_with(b"#") {
+            let end = src[start..]
+                .iter()
+                .position(|&byte| byte == b'\n')
+                .map_or(src.len(), |len| start + len);
+            let line = String::from_utf8_lossy(&src[start + 1..end]).into_owned();
+            header.set(line.trim())?;
+            for byte in &mut src[start..end] {
+                *byte = b' ';
+            }
+            start = (end + 1).min(src.len());
+        }
+        Ok(header)
+    }
+
+    fn set(&mut self, line: &str) -> Result<(), String> {
+        let (key, value) = line
+            .split_once(':')
+            .ok_or_else(|| format!("expected `key: value` in the header, found {:?}", line))?;
+        let value = value.trim();
+        let bad = |e: &dyn Error| format!("bad {}: {}", key, e);
+        match key.trim() {
+            "cells" => self.cells = value.parse().map_err(|e| bad(&e))?,
+            "cell" => self.cell = value.parse().map_err(|e| bad(&e))?,
+            "fuel" => self.options.fuel = Some(value.parse().map_err(|e| bad(&e))?),
+            "args" => {
+                self.options.args = value
+                    .split_whitespace()
+                    .map(|arg| arg.as_bytes().to_vec())
+                    .collect()
+            }
+            key => return Err(format!("unknown key in the header: {:?}", key)),
+        }
+        Ok(())
+    }
+}
+
+/// Runs a test on cells of whatever type its header says.
+struct Run<'a> {
+    program: &'a Program,
+    input: &'a [u8],
+    options: &'a RunOptions,
+}
+
+impl WithCell for Run<'_> {
+    type Output = (Vec<u8>, bool);
+
+    fn call<C: Cell>(self) -> Self::Output {
+        let result: RunResult<C> = run_captured(self.program, self.input, self.options);
+        (result.output, result.completed)
+    }
+}
+
+/// Whether `file` is a program with a `.out` file next to it, and not
+/// one of the `.in` and `.out` files of another, which a glob can take.
+fn is_test(file: &Path) -> bool {
+    let program = file
+        .extension()
+        .is_some_and(|ext| corpus::EXTENSIONS.iter().any(|&known| ext == known));
+    program && file.with_extension("out").is_file()
+}
+
+/// Runs the tests in `paths`, printing the ones that fail.
+pub fn run(paths: &[PathBuf]) -> Result<(), Box<dyn Error>> {
+    let tests: Vec<_> = bfrs_patterns::corpus::files(paths, &[])?
+        .into_iter()
+        .filter(|file| is_test(file))
+        .collect();
+    if tests.is_empty() {
+        return Err(
+            "no tests found: a test is a `.b` or `.bf` program with a `.out` file next to it"
+                .into(),
+        );
+    }
+
+    // a broken test doesn't stop the others from running.
+    let mut diagnostics = Diagnostics::new();
+    let (mut passed, mut failed) = (0, 0);
+    for test in tests.iter() {
+        match run_test(test) {
+            Ok(true) => passed += 1,
+            Ok(false) => failed += 1,
+            Err(diagnostic) => {
+                failed += 1;
+                println!("FAIL {}", test.display());
+                diagnostics.push(diagnostic.in_file(test.display().to_string()));
+            }
+        }
+    }
+    println!("{} passed, {} failed", passed, failed);
+    if failed > 0 {
+        diagnostics.push(Diagnostic::new(format!(
+            "{} of {} tests failed",
+            failed,
+            passed + failed
+        )));
+    }
+    Ok(diagnostics.into_result(())?)
+}
+
+/// Runs a test, returning whether it passed. A test that can't run at all
+/// is an error, which isn't printed yet.
+fn run_test(test: &Path) -> Result<bool, Box<Diagnostic>> {
+    let failed = |message| Box::new(Diagnostic::new(message));
+    let read =
+        |path: &Path| std::fs::read(path).map_err(|e| failed(format!("{}: {}", path.display(), e)));
+    let mut src = read(test)?;
+    let header = Header::strip(&mut src).map_err(failed)?;
+    let instructions = parser::parse_bytes(&src).map_err(|e| Box::new(e.diagnostic()))?;
+    let program = Program::from_instructions(instructions, header.cells);
+    if crate::Machine::<u8>::args_len(&header.options.args) > header.cells {
+        return Err(failed("the arguments don't fit in the tape".to_string()));
+    }
+    let input_path = test.with_extension("in");
+    let input = if input_path.is_file() {
+        read(&input_path)?
+    } else {
+        Vec::new()
+    };
+    let expected = read(&test.with_extension("out"))?;
+
+    let (output, completed) = header.cell.with(Run {
+        program: &program,
+        input: &input,
+        options: &header.options,
+    });
+    if !completed {
+        println!("FAIL {}: ran out of fuel", test.display());
+        return Ok(false);
+    }
+    match first_difference(&expected, &output) {
+        None => Ok(true),
+        Some(at) => {
+            println!("FAIL {}: the output differs at byte {}", test.display(), at);
+            print_diff(&expected, &output);
+            Ok(false)
+        }
+    }
+}
+
+/// Prints the lines of both outputs from the first one that differs.
+fn print_diff(expected: &[u8], actual: &[u8]) {
+    let expected: Vec<_> = expected.split(|&byte| byte == b'\n').collect();
+    let actual: Vec<_> = actual.split(|&byte| byte == b'\n').collect();
+    let from = first_difference(&expected, &actual).unwrap_or(0);
+    let show = |sign, lines: &[&[u8]]| {
+        for line in lines.iter().skip(from).take(DIFF_LINES) {
+            println!("\t{}{}", sign, String::from_utf8_lossy(line).escape_debug());
+        }
+    };
+    show('-', &expected);
+    show('+', &actual);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn only_programs_are_tests() {
+        let dir = std::env::temp_dir().join(format!("bfrs-golden-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for name in ["cat.b", "cat.in", "cat.out", "lone.bf"].iter() {
+            fs::write(dir.join(name), "").unwrap();
+        }
+        assert!(is_test(&dir.join("cat.b")));
+        assert!(!is_test(&dir.join("cat.in")));
+        assert!(!is_test(&dir.join("cat.out")));
+        assert!(!is_test(&dir.join("lone.bf")));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
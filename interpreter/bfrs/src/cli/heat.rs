@@ -0,0 +1,166 @@
+//! What `--profile=annotated` shows: the source, with every instruction
+//! colored by the times it ran, so the hot loops stand out.
+//!
+//! The times are on a logarithmic scale up to the instruction that ran the
+//! most, since the hottest loops run millions of times more than the rest.
+//! Without colors, every line is followed by the heat of its instructions,
+//! from `0` to `9`, with `.` for the ones that never ran.
+
+use super::coverage::locate;
+use crate::hooks::Profiler;
+use bfrs_common::{Position, PositionConfig};
+use std::collections::HashMap;
+use std::io;
+
+/// The colors of the heat, from the coldest to the hottest, of the
+/// 256 colors of terminals.
+const HEAT: [u8; 10] = [27, 33, 39, 44, 49, 118, 190, 220, 208, 196];
+
+/// The color of the instructions that never ran.
+const COLD: u8 = 240;
+
+/// Writes the source of the program made of the files `inputs`, where the
+/// instruction at every index is at `positions[index]`, with the heat of
+/// every instruction.
+pub fn write(
+    profiler: &Profiler,
+    positions: &[Position],
+    inputs: &[String],
+    config: PositionConfig,
+    color: bool,
+    out: &mut dyn io::Write,
+) -> io::Result<()> {
+    writeln!(out, "profile: {} instructions run", profiler.total())?;
+    let hottest = profiler.counts.iter().copied().max().unwrap_or(0);
+    let mut files: Vec<HashMap<_, _>> = inputs.iter().map(|_| HashMap::new()).collect();
+    let located = locate(positions, inputs, config)?;
+    for ((file, position), &count) in located.into_iter().zip(&profiler.counts) {
+        files[file].insert((position.line, position.column), heat(count, hottest));
+    }
+
+    for (name, heats) in inputs.iter().zip(&files) {
+        writeln!(out, "{}:", name)?;
+        let src = String::from_utf8_lossy(&std::fs::read(name)?).into_owned();
+        let first = Position::start(&config);
+        for (i, text) in src.lines().enumerate() {
+            let line = first.line + i;
+            let mut position = first;
+            // the heat of every character, if it's an instruction.
+            let chars: Vec<_> = text
+                .chars()
+                .map(|ch| {
+                    let heat = heats.get(&(line, position.column)).copied();
+                    position.advance_char_with(ch, &config);
+                    (ch, heat)
+                })
+                .collect();
+            if color {
+                for &(ch, heat) in &chars {
+                    match heat {
+                        Some(heat) => {
+                            let code = heat.map_or(COLD, |level| HEAT[level]);
+                            write!(out, "\x1b[38;5;{}m{}\x1b[0m", code, ch)?;
+                        }
+                        None => write!(out, "{}", ch)?,
+                    }
+                }
+                writeln!(out)?;
+            } else {
+                writeln!(out, "{}", text)?;
+                if chars.iter().any(|(_, heat)| heat.is_some()) {
+                    let marks: String = chars.iter().map(|&(ch, heat)| mark(ch, heat)).collect();
+                    writeln!(out, "{}", marks.trim_end())?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The level of heat of an instruction that ran `count` times,
+/// or `None` if it never ran.
+fn heat(count: u64, hottest: u64) -> Option<usize> {
+    if count == 0 {
+        return None;
+    }
+    let scale = ((count as f64).ln_1p() / (hottest as f64).ln_1p()) * (HEAT.len() - 1) as f64;
+    Some((scale.round() as usize).min(HEAT.len() - 1))
+}
+
+/// How the heat of a character is shown without colors, keeping
+/// tabs so the marks line up with the source.
+fn mark(ch: char, heat: Option<Option<usize>>) -> char {
+    match heat {
+        Some(Some(level)) => std::char::from_digit(level as u32, 10).expect("there are 10 levels"),
+        Some(None) => '.',
+        None if ch == '\t' => '\t',
+        None => ' ',
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Machine, Program};
+    use bfrs_common::dialect::Dialect;
+    use bfrs_common::parser;
+
+    /// The annotated profile of running `src` once from a file, without colors.
+    fn annotated(src: &str) -> String {
+        let path = std::env::temp_dir().join(format!("bfrs-heat-{}.b", std::process::id()));
+        std::fs::write(&path, src).unwrap();
+        let config = PositionConfig::default();
+        let (instructions, locations): (Vec<_>, Vec<_>) =
+            parser::parse_located_with(src.bytes().map(Ok), Dialect::default(), config)
+                .map(Result::unwrap)
+                .unzip();
+        let positions: Vec<_> = locations.iter().map(|location| location.position).collect();
+        let program = Program::from_instructions(instructions, 10);
+        let mut profiler = Profiler::new(&program);
+        let mut machine = Machine::<u8>::new(program.tape_size);
+        let mut next = 0;
+        while next < program.code.instructions.len() {
+            next = machine
+                .step_with_hooks(
+                    &program,
+                    next,
+                    &mut io::empty(),
+                    &mut io::sink(),
+                    &mut profiler,
+                )
+                .unwrap();
+        }
+        let mut out = Vec::new();
+        let inputs = [path.to_str().unwrap().to_string()];
+        write(&profiler, &positions, &inputs, config, false, &mut out).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        // without the name of the file, which changes from run to run
+        let mut lines: Vec<_> = out.lines().collect();
+        lines.remove(1);
+        lines.join("\n")
+    }
+
+    #[test]
+    fn sources_are_marked_with_their_heat() {
+        // the body of the first loop runs the most, and the second
+        // loop is skipped, jumping over the rest of it.
+        let src = "+++[-] a comment\n[.]";
+        let expected = "profile: 11 instructions run\n\
+                        +++[-] a comment\n\
+                        555599\n\
+                        [.]\n\
+                        5..";
+        assert_eq!(annotated(src), expected);
+    }
+
+    #[test]
+    fn heat_is_logarithmic() {
+        assert_eq!(heat(0, 10), None);
+        assert_eq!(heat(10, 10), Some(9));
+        assert_eq!(heat(1, 1), Some(9));
+        assert_eq!(heat(1_000, 1_000_000), Some(5));
+        assert_eq!(mark('\t', None), '\t');
+        assert_eq!(mark('x', None), ' ');
+    }
+}
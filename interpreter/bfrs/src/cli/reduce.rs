@@ -0,0 +1,148 @@
+//! `bfrs reduce`, shrinking a program while a command keeps failing on it.
+
+use crate::reduce::reduce;
+use bfrs_common::{parser, BFCommand};
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// What stands for the file of the program in the check command.
+const PLACEHOLDER: &str = "{}";
+
+/// How often a check is looked at while it runs.
+const POLL: Duration = Duration::from_millis(5);
+
+/// Reduces the program in `input` while `check` fails on it, writing
+/// the smallest one found to `output`, or stdout.
+pub fn run(
+    input: &Path,
+    check: &str,
+    timeout: Duration,
+    output: Option<&Path>,
+) -> Result<(), Box<dyn Error>> {
+    if !check.contains(PLACEHOLDER) {
+        return Err(format!("the check command takes the program as `{}`", PLACEHOLDER).into());
+    }
+    let program = parser::parse_bytes(&fs::read(input)?)?;
+    let candidate = candidate_path();
+    let command = check.replace(PLACEHOLDER, &candidate.display().to_string());
+    let interesting = |program: &[BFCommand]| -> io::Result<bool> {
+        fs::write(&candidate, to_source(program))?;
+        fails(&command, timeout)
+    };
+
+    let reduced = interesting(&program).and_then(|fails| {
+        if !fails {
+            let e = "the check doesn't fail on the program to begin with";
+            return Err(io::Error::other(e));
+        }
+        reduce(program.clone(), interesting)
+    });
+    // whatever happened, the candidates aren't needed anymore.
+    let _ = fs::remove_file(&candidate);
+    let reduced = reduced?;
+
+    eprintln!(
+        "reduced from {} to {} instructions",
+        program.len(),
+        reduced.len()
+    );
+    match output {
+        Some(path) => fs::write(path, to_source(&reduced) + "\n")?,
+        None => println!("{}", to_source(&reduced)),
+    }
+    Ok(())
+}
+
+/// Where the candidates are written for the check command to take them.
+fn candidate_path() -> PathBuf {
+    std::env::temp_dir().join(format!("bfrs-reduce-{}.b", std::process::id()))
+}
+
+fn to_source(program: &[BFCommand]) -> String {
+    program.iter().map(ToString::to_string).collect()
+}
+
+/// Whether `command` fails, which it doesn't if it's stopped for
+/// running longer than `timeout`.
+fn fails(command: &str, timeout: Duration) -> io::Result<bool> {
+    let mut child = shell(command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    let start = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(!status.success());
+        }
+        if start.elapsed() >= timeout {
+            stop(&mut child)?;
+            return Ok(false);
+        }
+        std::thread::sleep(POLL);
+    }
+}
+
+fn stop(child: &mut Child) -> io::Result<()> {
+    child.kill()?;
+    child.wait().map(drop)
+}
+
+#[cfg(unix)]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command);
+    shell
+}
+
+#[cfg(not(unix))]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.arg("/C").arg(command);
+    shell
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(unix)]
+    fn checks_fail_by_their_status() {
+        let timeout = Duration::from_secs(10);
+        assert!(fails("exit 3", timeout).unwrap());
+        assert!(!fails("true", timeout).unwrap());
+        // checks running for too long are stopped, and don't fail
+        assert!(!fails("sleep 10; exit 1", Duration::from_millis(50)).unwrap());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn programs_are_reduced_by_their_check() {
+        let dir = std::env::temp_dir().join(format!("bfrs-reduce-cli-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (input, output) = (dir.join("in.b"), dir.join("out.b"));
+        fs::write(&input, "++>[-]<.comment\n").unwrap();
+        let timeout = Duration::from_secs(10);
+        // fails while there's a `.` in the program
+        let check = "grep -q '\\.' {} && exit 1 || exit 0";
+        run(&input, check, timeout, Some(&output)).unwrap();
+        assert_eq!(fs::read_to_string(&output).unwrap(), ".\n");
+
+        let err = run(&input, "true {}", timeout, None).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "the check doesn't fail on the program to begin with"
+        );
+        let err = run(&input, "exit 1", timeout, None).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "the check command takes the program as `{}`"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,179 @@
+//! The self test: a few well known programs bundled with the interpreter,
+//! run as written, optimized and unrolled with every way it has to run
+//! them, which all have to print the same as the output bundled along. It
+//! checks a build, or a port to another platform, without any files around.
+//!
+//! The programs are in the `selftest` directory laid out as golden tests,
+//! so `bfrs test` can run them too.
+
+use super::first_difference;
+use crate::capture::{run_captured, RunOptions, RunResult};
+use crate::eval::{evaluate, EvalOptions};
+use crate::{Interpreter, Program, RunOutcome};
+use bfrs_common::BFCommand;
+use bfrs_patterns::ir::Ir;
+use bfrs_patterns::unroll;
+use std::error::Error;
+
+/// The instructions a run can take before it counts as hanging,
+/// well over what any of the programs needs.
+const FUEL: u64 = 100_000_000;
+
+/// The instructions run at once by the interpreter run a few at a time.
+const STEPS: usize = 4096;
+
+const CELLS: usize = 30000;
+
+/// A bundled program, with its input and what it prints.
+struct Case {
+    name: &'static str,
+    source: &'static [u8],
+    input: &'static [u8],
+    output: &'static [u8],
+}
+
+const CASES: &[Case] = &[
+    Case {
+        name: "hello",
+        source: include_bytes!("selftest/hello.b"),
+        input: b"",
+        output: include_bytes!("selftest/hello.out"),
+    },
+    Case {
+        name: "rot13",
+        source: include_bytes!("selftest/rot13.b"),
+        input: include_bytes!("selftest/rot13.in"),
+        output: include_bytes!("selftest/rot13.out"),
+    },
+    Case {
+        name: "quine",
+        source: include_bytes!("selftest/quine.b"),
+        input: b"",
+        output: include_bytes!("selftest/quine.out"),
+    },
+    Case {
+        name: "loops",
+        source: include_bytes!("selftest/loops.b"),
+        input: b"",
+        output: include_bytes!("selftest/loops.out"),
+    },
+];
+
+/// A way of running instructions on an input, returning what they print.
+type Backend = fn(&[BFCommand], &[u8]) -> Result<Vec<u8>, String>;
+
+const BACKENDS: &[(&str, Backend)] = &[
+    ("the machine", machine),
+    ("the stepping interpreter", stepping),
+    ("eval", eval),
+];
+
+/// Runs every bundled program every way, printing the ones that fail.
+pub fn run() -> Result<(), Box<dyn Error>> {
+    let (mut passed, mut failed) = (0, 0);
+    for case in CASES {
+        let written = bfrs_common::parser::parse_bytes(case.source)
+            .map_err(|e| format!("{}: {}", case.name, e))?;
+        let optimized = Ir::expand(&Ir::compile(&written).ops);
+        let unrolled = unroll::unroll(&written, unroll::BUDGET);
+        let mut failures = Vec::new();
+        for (level, instructions) in [
+            ("as written", &written),
+            ("optimized", &optimized),
+            ("unrolled", &unrolled),
+        ] {
+            for (backend, run) in BACKENDS {
+                let failure = match run(instructions, case.input) {
+                    Ok(output) => first_difference(case.output, &output)
+                        .map(|at| format!("the output differs at byte {}", at)),
+                    Err(e) => Some(e),
+                };
+                match failure {
+                    Some(failure) => {
+                        failures.push(format!("{} on {}: {}", level, backend, failure))
+                    }
+                    None => passed += 1,
+                }
+            }
+        }
+        if failures.is_empty() {
+            println!("ok {}", case.name);
+        }
+        for failure in failures.iter() {
+            println!("FAIL {}, {}", case.name, failure);
+        }
+        failed += failures.len();
+    }
+    println!("{} passed, {} failed", passed, failed);
+    if failed > 0 {
+        return Err(format!("{} of {} runs failed", failed, passed + failed).into());
+    }
+    Ok(())
+}
+
+fn machine(instructions: &[BFCommand], input: &[u8]) -> Result<Vec<u8>, String> {
+    let program = Program::from_instructions(instructions.to_vec(), CELLS);
+    let options = RunOptions {
+        fuel: Some(FUEL),
+        ..RunOptions::default()
+    };
+    let result: RunResult<u8> = run_captured(&program, input, &options);
+    match result.completed {
+        true => Ok(result.output),
+        false => Err("ran out of fuel".into()),
+    }
+}
+
+fn stepping(instructions: &[BFCommand], input: &[u8]) -> Result<Vec<u8>, String> {
+    let program = Program::from_instructions(instructions.to_vec(), CELLS);
+    let mut interpreter = Interpreter::<u8>::new(program);
+    interpreter.push_input(input);
+    interpreter.close_input();
+    let mut output = Vec::new();
+    for _ in 0..FUEL / STEPS as u64 {
+        let outcome = interpreter.run_for(STEPS);
+        output.extend(interpreter.take_output());
+        match outcome {
+            RunOutcome::Completed => return Ok(output),
+            RunOutcome::NeedsInput => return Err("waited for input after its end".into()),
+            RunOutcome::OutOfFuel => (),
+        }
+    }
+    Err("ran out of fuel".into())
+}
+
+fn eval(instructions: &[BFCommand], input: &[u8]) -> Result<Vec<u8>, String> {
+    let source: String = instructions.iter().map(|i| i.to_string()).collect();
+    let options = EvalOptions {
+        max_steps: FUEL,
+        max_tape: CELLS,
+        ..EvalOptions::default()
+    };
+    match evaluate(source.as_bytes(), input, &options) {
+        Ok(evaluation) => Ok(evaluation.output),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_bundled_programs_pass() {
+        assert!(run().is_ok());
+    }
+
+    #[test]
+    fn every_backend_runs_the_same() {
+        let instructions = bfrs_common::parser::parse_bytes(b",[.-]++.").unwrap();
+        for (backend, run) in BACKENDS {
+            assert_eq!(
+                run(&instructions, b"\x03").unwrap(),
+                [3, 2, 1, 2],
+                "{}",
+                backend
+            );
+        }
+    }
+}
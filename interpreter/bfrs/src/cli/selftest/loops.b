@@ -0,0 +1,13 @@
+A million increments in three nested loops of a hundred each
+which leave the cell at 64 before printing the A after it and a newline
+++++++++++[>++++++++++<-]>
+[
+    >++++++++++[>++++++++++<-]>
+    [
+        >++++++++++[>++++++++++<-]>
+        [>+<-]
+        <<-
+    ]
+    <<-
+]
+>>>>>+.[-]++++++++++.
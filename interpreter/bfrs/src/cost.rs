@@ -0,0 +1,186 @@
+//! A simulated cost of running a program, to rank programs by
+//! something steadier than the time they took.
+//!
+//! Every command costs some cycles, and every byte printed or read costs
+//! a penalty on top of its command. The costs can be read from a file with
+//! a command and its cycles on every line, and `io` for the penalty:
+//! ```text
+//! # loops are the expensive part
+//! [ 3
+//! ] 3
+//! io 10
+//! ```
+//! Commands left out of the file cost a cycle, and I/O doesn't
+//! cost anything else unless the file says.
+
+use crate::{Cell, ExecHooks, Machine, Program};
+use bfrs_common::errors::ErrorWithPosition;
+use bfrs_common::{BFCommand, Position};
+use std::fmt;
+
+/// The cycles every command costs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CostModel {
+    /// the cycles of every command, in the order of [`ORDER`](bfrs_common::dialect::ORDER)
+    cycles: [u64; 8],
+    /// the cycles every byte printed or read costs on top of its command
+    pub io_penalty: u64,
+}
+
+impl Default for CostModel {
+    fn default() -> Self {
+        Self {
+            cycles: [1; 8],
+            io_penalty: 0,
+        }
+    }
+}
+
+impl CostModel {
+    pub fn cycles(&self, command: BFCommand) -> u64 {
+        self.cycles[index(command)]
+    }
+
+    pub fn set_cycles(&mut self, command: BFCommand, cycles: u64) {
+        self.cycles[index(command)] = cycles;
+    }
+
+    /// Parses a file of costs, like the one in the [module docs](self).
+    pub fn parse(src: &str) -> Result<Self, ErrorWithPosition<CostError>> {
+        let mut model = Self::default();
+        for (line_i, line) in src.lines().enumerate() {
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue;
+            }
+            let position = Position {
+                line: line_i + 1,
+                column: line.chars().take_while(|ch| ch.is_whitespace()).count() + 1,
+            };
+            let error = |kind| ErrorWithPosition { kind, position };
+            let (key, cycles) = trimmed
+                .split_once(char::is_whitespace)
+                .ok_or_else(|| error(CostError::MissingCycles))?;
+            let cycles = cycles
+                .trim()
+                .parse()
+                .map_err(|_| error(CostError::BadCycles(cycles.trim().to_string())))?;
+            match key {
+                "io" => model.io_penalty = cycles,
+                _ => match key.as_bytes() {
+                    &[byte] => match BFCommand::from_u8(byte) {
+                        Some(command) => model.set_cycles(command, cycles),
+                        None => return Err(error(CostError::UnknownKey(key.to_string()))),
+                    },
+                    _ => return Err(error(CostError::UnknownKey(key.to_string()))),
+                },
+            }
+        }
+        Ok(model)
+    }
+
+    /// A hook adding up the cost of running `program`.
+    pub fn scorer(&self, program: &Program) -> Scorer {
+        Scorer {
+            costs: program
+                .code
+                .instructions
+                .iter()
+                .map(|&command| self.cycles(command))
+                .collect(),
+            io_penalty: self.io_penalty,
+            score: 0,
+        }
+    }
+}
+
+fn index(command: BFCommand) -> usize {
+    bfrs_common::dialect::ORDER
+        .iter()
+        .position(|&other| other == command)
+        .expect("every command is in the order")
+}
+
+/// Adds up the cost of the instructions of a program as they run.
+#[derive(Debug, Clone)]
+pub struct Scorer {
+    /// the cycles of every instruction, by index
+    costs: Vec<u64>,
+    io_penalty: u64,
+    /// the cycles spent so far
+    pub score: u64,
+}
+
+impl<C: Cell> ExecHooks<C> for Scorer {
+    fn on_instruction(&mut self, index: usize, _: &Machine<C>) {
+        self.score += self.costs[index];
+    }
+
+    fn on_output(&mut self, _: u8) {
+        self.score += self.io_penalty;
+    }
+
+    fn on_input(&mut self, _: Option<u8>) {
+        self.score += self.io_penalty;
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum CostError {
+    /// a key that's neither a command nor `io`
+    UnknownKey(String),
+    MissingCycles,
+    BadCycles(String),
+}
+
+impl fmt::Display for CostError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::UnknownKey(key) => write!(f, "Unknown key {:?}, expected a command or `io`", key),
+            Self::MissingCycles => write!(f, "Expected the cycles after the key"),
+            Self::BadCycles(cycles) => write!(f, "{:?} isn't a number of cycles", cycles),
+        }
+    }
+}
+
+impl std::error::Error for CostError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn costs_are_read_from_files() {
+        let model = CostModel::parse("# loops\n[ 3\n  ] 3\n\nio 10\n").unwrap();
+        assert_eq!(model.cycles(BFCommand::BeginLoop), 3);
+        assert_eq!(model.cycles(BFCommand::EndLoop), 3);
+        // the rest cost a cycle
+        assert_eq!(model.cycles(BFCommand::Increment), 1);
+        assert_eq!(model.io_penalty, 10);
+    }
+
+    #[test]
+    fn bad_costs_say_where_they_are() {
+        let error = |src| CostModel::parse(src).unwrap_err();
+        let e = error("+ 1\n  x 2");
+        assert_eq!(e.kind, CostError::UnknownKey("x".to_string()));
+        assert_eq!(e.position, Position { line: 2, column: 3 });
+        assert_eq!(error("+").kind, CostError::MissingCycles);
+        assert_eq!(error("+ -1").kind, CostError::BadCycles("-1".to_string()));
+        assert_eq!(error("++ 1").kind, CostError::UnknownKey("++".to_string()));
+    }
+
+    #[test]
+    fn programs_are_scored_as_they_run() {
+        let mut model = CostModel::parse("[ 3\nio 10").unwrap();
+        model.set_cycles(BFCommand::Decrement, 2);
+        let program =
+            Program::from_instructions(bfrs_common::parser::parse_str(",[-].").unwrap(), 1);
+        let mut scorer = model.scorer(&program);
+        Machine::<u8>::new(1)
+            .run_with_hooks(&program, &mut &b"\x02"[..], &mut Vec::new(), &mut scorer)
+            .unwrap();
+        // `,` and `.` cost 11, `[` 3, and the body runs twice with its `]`
+        assert_eq!(scorer.score, 11 + 3 + 2 * (2 + 1) + 11);
+    }
+}
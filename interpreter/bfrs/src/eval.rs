@@ -0,0 +1,263 @@
+//! Running programs that can't be trusted, like the ones of users of a
+//! playground or a bot: [`evaluate`] parses and runs a source within the
+//! limits of [`EvalOptions`], and never panics, whatever it's given.
+//! ```
+//! use bfrs::capture::Limit;
+//! use bfrs::eval::{evaluate, EvalError, EvalOptions};
+//!
+//! let options = EvalOptions::default();
+//! let evaluation = evaluate(b",+.,+.", b"HI", &options).unwrap();
+//! assert_eq!(evaluation.output, b"IJ");
+//!
+//! let options = EvalOptions {
+//!     max_output: 3,
+//!     ..EvalOptions::default()
+//! };
+//! match evaluate(b"+[.]", b"", &options) {
+//!     Err(EvalError::Limit(Limit::Output, partial)) => assert_eq!(partial.output, [1; 3]),
+//!     _ => unreachable!(),
+//! }
+//! ```
+
+use crate::capture::{Limit, RunOptions, RunStats};
+use crate::{Machine, Program};
+use bfrs_common::parser::{self, ParseError};
+use bfrs_common::BFCommand;
+use std::error::Error;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// The limits of a program run with [`evaluate`], and how it reads.
+/// Everything but time has a bound, which the default options keep small,
+/// and the bound on the instructions keeps the time short enough too.
+#[derive(Debug, Clone)]
+pub struct EvalOptions {
+    /// the most instructions to run
+    pub max_steps: u64,
+    /// the most bytes to print, stopping the program
+    /// right before it prints another one
+    pub max_output: usize,
+    /// the cells of the tape, which wraps around
+    pub max_tape: usize,
+    /// the longest to run for
+    pub timeout: Option<Duration>,
+    /// what reading past the end of the input leaves in the cell
+    pub eof: Eof,
+}
+
+impl Default for EvalOptions {
+    fn default() -> Self {
+        Self {
+            max_steps: 10_000_000,
+            max_output: 1 << 20,
+            max_tape: 30000,
+            timeout: None,
+            eof: Eof::default(),
+        }
+    }
+}
+
+/// What reading past the end of the input leaves in the cell,
+/// which programs disagree on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Eof {
+    /// 255, like the rest of the interpreter
+    #[default]
+    Max,
+    Zero,
+    /// the cell is left as it was
+    Unchanged,
+}
+
+/// What a program did, up to where it stopped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Evaluation {
+    pub output: Vec<u8>,
+    /// the tape once the program stopped
+    pub tape: Vec<u8>,
+    /// the cell the pointer was at once the program stopped
+    pub pointer: usize,
+    pub stats: RunStats,
+}
+
+/// Why [`evaluate`] didn't run a program to its end.
+#[derive(Debug, Clone)]
+pub enum EvalError {
+    /// the errors of the source, every one of them
+    Parse(Vec<ParseError>),
+    /// a tape of these many cells can't be made
+    Tape(usize),
+    /// the program ran into a limit, after doing what's in the evaluation
+    Limit(Limit, Evaluation),
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Parse(errors) => {
+                for (i, e) in errors.iter().enumerate() {
+                    if i > 0 {
+                        writeln!(f)?;
+                    }
+                    write!(f, "parse error: {}", e)?;
+                }
+                Ok(())
+            }
+            Self::Tape(cells) => write!(f, "a tape of {} cells can't be made", cells),
+            Self::Limit(Limit::Fuel, _) => write!(f, "the program ran too many instructions"),
+            Self::Limit(Limit::Output, _) => write!(f, "the program printed too much"),
+            Self::Limit(Limit::Time, _) => write!(f, "the program ran for too long"),
+        }
+    }
+}
+
+impl Error for EvalError {}
+
+/// Parses `source` and runs it on a blank tape, reading `input` and then
+/// its end, within the limits of `options`.
+pub fn evaluate(
+    source: &[u8],
+    input: &[u8],
+    options: &EvalOptions,
+) -> Result<Evaluation, EvalError> {
+    let recovered = parser::parse_recovering(source.iter().map(|&byte| Ok(byte)))
+        .expect("reading memory doesn't fail");
+    if !recovered.errors.is_empty() {
+        return Err(EvalError::Parse(recovered.errors));
+    }
+    // the tape is the only thing allocated up front, so a huge
+    // one is an error instead of aborting the process.
+    let mut tape = Vec::new();
+    if options.max_tape == 0 || tape.try_reserve_exact(options.max_tape).is_err() {
+        return Err(EvalError::Tape(options.max_tape));
+    }
+    tape.resize(options.max_tape, 0);
+    let mut machine = Machine::with_tape(tape.into());
+    let instructions = recovered
+        .instructions
+        .into_iter()
+        .map(|(instruction, _)| instruction)
+        .collect();
+    let program = Program::from_instructions(instructions, options.max_tape);
+
+    let limits = RunOptions {
+        fuel: Some(options.max_steps),
+        max_output: Some(options.max_output),
+        timeout: options.timeout,
+        ..RunOptions::default()
+    };
+    let started = Instant::now();
+    let instructions = &program.code.instructions;
+    let mut input = input;
+    let mut output = Vec::new();
+    let mut stats = RunStats::default();
+    let mut next = 0;
+    let mut limit = None;
+    while next < instructions.len() {
+        limit = limits.limit(instructions[next], &stats, output.len(), started);
+        if limit.is_some() {
+            break;
+        }
+        if instructions[next] == BFCommand::Read && input.is_empty() && options.eof != Eof::Max {
+            if options.eof == Eof::Zero {
+                machine.tape[machine.pivot] = 0;
+            }
+            stats.instructions += 1;
+            next += 1;
+            continue;
+        }
+        next = machine
+            .step_with_hooks(&program, next, &mut input, &mut output, &mut stats)
+            .expect("reading and writing memory doesn't fail");
+    }
+    let evaluation = Evaluation {
+        output,
+        tape: machine.tape.into_vec(),
+        pointer: machine.pivot,
+        stats,
+    };
+    match limit {
+        Some(limit) => Err(EvalError::Limit(limit, evaluation)),
+        None => Ok(evaluation),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(eof: Eof) -> EvalOptions {
+        EvalOptions {
+            max_tape: 4,
+            eof,
+            ..EvalOptions::default()
+        }
+    }
+
+    #[test]
+    fn the_end_of_the_input_is_read_as_configured() {
+        let src = b"+,.";
+        let read = |eof| evaluate(src, b"", &options(eof)).unwrap().output;
+        assert_eq!(read(Eof::Max), [255]);
+        assert_eq!(read(Eof::Zero), [0]);
+        assert_eq!(read(Eof::Unchanged), [1]);
+        let evaluation = evaluate(src, b"", &options(Eof::Zero)).unwrap();
+        assert_eq!(evaluation.stats.instructions, 3);
+        assert_eq!(evaluation.stats.bytes_read, 0);
+    }
+
+    #[test]
+    fn the_tape_wraps_around() {
+        let evaluation = evaluate(b"<+<<+>>>>+", b"", &options(Eof::Max)).unwrap();
+        assert_eq!(evaluation.tape, [0, 2, 0, 1]);
+        assert_eq!(evaluation.pointer, 1);
+    }
+
+    #[test]
+    fn programs_stop_at_their_limits() {
+        let options = EvalOptions {
+            max_steps: 5,
+            ..options(Eof::Max)
+        };
+        match evaluate(b"+[>+<]", b"", &options) {
+            Err(EvalError::Limit(Limit::Fuel, partial)) => {
+                assert_eq!(partial.stats.instructions, 5);
+                assert_eq!(partial.tape, [1, 1, 0, 0]);
+            }
+            res => panic!("expected to run out of steps, got {:?}", res),
+        }
+        let options = EvalOptions {
+            timeout: Some(Duration::from_millis(10)),
+            max_steps: u64::MAX,
+            ..options
+        };
+        assert!(matches!(
+            evaluate(b"+[]", b"", &options),
+            Err(EvalError::Limit(Limit::Time, _))
+        ));
+    }
+
+    #[test]
+    fn bad_programs_and_tapes_are_errors() {
+        let err = evaluate(b"]+[", b"", &EvalOptions::default()).unwrap_err();
+        match &err {
+            EvalError::Parse(errors) => assert_eq!(errors.len(), 2),
+            err => panic!("expected parse errors, got {:?}", err),
+        }
+        assert_eq!(err.to_string().lines().count(), 2);
+        let huge = EvalOptions {
+            max_tape: usize::MAX,
+            ..EvalOptions::default()
+        };
+        let err = evaluate(b"+", b"", &huge).unwrap_err();
+        assert!(matches!(err, EvalError::Tape(usize::MAX)));
+        let empty = EvalOptions {
+            max_tape: 0,
+            ..EvalOptions::default()
+        };
+        assert!(matches!(
+            evaluate(b"+", b"", &empty),
+            Err(EvalError::Tape(0))
+        ));
+    }
+}
@@ -0,0 +1,325 @@
+//! Watching a program while it runs, to build tracers, visualizers or
+//! coverage tools on top of the interpreter instead of next to it.
+//!
+//! [`Machine::run_with_hooks`] calls an [`ExecHooks`] as the program runs.
+//! The [`Profiler`] and the [`Heatmap`] of `bfrs --profile` and
+//! `bfrs --heatmap` are made this way too.
+
+use crate::{Cell, Machine, Program};
+use bfrs_common::BFCommand;
+use std::collections::BTreeMap;
+
+/// What happens while a program runs. Every method does nothing unless
+/// it's implemented, and running without hooks is `()`, which the compiler
+/// removes entirely.
+pub trait ExecHooks<C: Cell = u8> {
+    /// Called before running the instruction at `index`.
+    fn on_instruction(&mut self, _index: usize, _machine: &Machine<C>) {}
+
+    /// Called with every byte the program prints.
+    fn on_output(&mut self, _byte: u8) {}
+
+    /// Called with every byte the program reads, or `None` at the end of the input.
+    fn on_input(&mut self, _byte: Option<u8>) {}
+
+    /// Called when the loop opened at `start` runs its body, every time it does.
+    fn on_loop_enter(&mut self, _start: usize) {}
+
+    /// Called when the loop opened at `start` is left after running its body.
+    /// Loops skipped without running their body are neither entered nor left.
+    fn on_loop_exit(&mut self, _start: usize) {}
+}
+
+impl<C: Cell> ExecHooks<C> for () {}
+
+/// Both hooks, one after the other.
+impl<C: Cell, A: ExecHooks<C>, B: ExecHooks<C>> ExecHooks<C> for (A, B) {
+    fn on_instruction(&mut self, index: usize, machine: &Machine<C>) {
+        self.0.on_instruction(index, machine);
+        self.1.on_instruction(index, machine);
+    }
+
+    fn on_output(&mut self, byte: u8) {
+        self.0.on_output(byte);
+        self.1.on_output(byte);
+    }
+
+    fn on_input(&mut self, byte: Option<u8>) {
+        self.0.on_input(byte);
+        self.1.on_input(byte);
+    }
+
+    fn on_loop_enter(&mut self, start: usize) {
+        self.0.on_loop_enter(start);
+        self.1.on_loop_enter(start);
+    }
+
+    fn on_loop_exit(&mut self, start: usize) {
+        self.0.on_loop_exit(start);
+        self.1.on_loop_exit(start);
+    }
+}
+
+/// The hooks, if there are any.
+impl<C: Cell, H: ExecHooks<C>> ExecHooks<C> for Option<H> {
+    fn on_instruction(&mut self, index: usize, machine: &Machine<C>) {
+        if let Some(hooks) = self {
+            hooks.on_instruction(index, machine);
+        }
+    }
+
+    fn on_output(&mut self, byte: u8) {
+        if let Some(hooks) = self {
+            hooks.on_output(byte);
+        }
+    }
+
+    fn on_input(&mut self, byte: Option<u8>) {
+        if let Some(hooks) = self {
+            hooks.on_input(byte);
+        }
+    }
+
+    fn on_loop_enter(&mut self, start: usize) {
+        if let Some(hooks) = self {
+            hooks.on_loop_enter(start);
+        }
+    }
+
+    fn on_loop_exit(&mut self, start: usize) {
+        if let Some(hooks) = self {
+            hooks.on_loop_exit(start);
+        }
+    }
+}
+
+/// Counts how many times every instruction runs.
+#[derive(Debug, Clone)]
+pub struct Profiler {
+    /// the times every instruction ran, by index
+    pub counts: Vec<u64>,
+    /// the times the body of every loop ran, by the index of its `[`
+    pub iterations: Vec<u64>,
+}
+
+/// How long a loop of the program took.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoopProfile {
+    /// the index of its `[`
+    pub start: usize,
+    /// the index of its `]`
+    pub end: usize,
+    /// the times its body ran
+    pub iterations: u64,
+    /// the instructions run inside it, including the ones of inner loops
+    pub instructions: u64,
+}
+
+impl Profiler {
+    pub fn new(program: &Program) -> Self {
+        Self {
+            counts: vec![0; program.code.instructions.len()],
+            iterations: vec![0; program.code.instructions.len()],
+        }
+    }
+
+    /// The instructions run in total.
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Every loop of the program that ran, the ones taking the most
+    /// instructions first.
+    pub fn loops(&self, program: &Program) -> Vec<LoopProfile> {
+        let mut loops: Vec<_> = program
+            .code
+            .instructions
+            .iter()
+            .enumerate()
+            .filter(|&(start, &command)| {
+                command == BFCommand::BeginLoop && self.iterations[start] > 0
+            })
+            .map(|(start, _)| {
+                let end = program.code.jump(start);
+                LoopProfile {
+                    start,
+                    end,
+                    iterations: self.iterations[start],
+                    instructions: self.counts[start + 1..=end].iter().sum(),
+                }
+            })
+            .collect();
+        loops.sort_by(|a, b| {
+            b.instructions
+                .cmp(&a.instructions)
+                .then(a.start.cmp(&b.start))
+        });
+        loops
+    }
+
+    /// The instructions run under every chain of nested loops, in the
+    /// folded stacks of flamegraph tools: the frames of a chain are
+    /// separated by `;`, from `root` to the innermost loop, which is named
+    /// by `name` from the index of its `[`. Brackets count as part of the
+    /// loop they make.
+    pub fn folded(
+        &self,
+        program: &Program,
+        root: &str,
+        name: impl Fn(usize) -> String,
+    ) -> Vec<(String, u64)> {
+        let mut stacks = BTreeMap::new();
+        // a `;` would split the frame in two.
+        let mut chain = vec![root.replace(';', ":")];
+        for (i, &command) in program.code.instructions.iter().enumerate() {
+            if command == BFCommand::BeginLoop {
+                let inner = format!("{};{}", chain[chain.len() - 1], name(i).replace(';', ":"));
+                chain.push(inner);
+            }
+            if self.counts[i] > 0 {
+                *stacks.entry(chain[chain.len() - 1].clone()).or_insert(0) += self.counts[i];
+            }
+            if command == BFCommand::EndLoop {
+                chain.pop();
+            }
+        }
+        stacks.into_iter().collect()
+    }
+}
+
+impl<C: Cell> ExecHooks<C> for Profiler {
+    fn on_instruction(&mut self, index: usize, _: &Machine<C>) {
+        self.counts[index] += 1;
+    }
+
+    fn on_loop_enter(&mut self, start: usize) {
+        self.iterations[start] += 1;
+    }
+}
+
+/// Counts how many instructions run on every cell of the tape.
+#[derive(Debug, Clone)]
+pub struct Heatmap {
+    /// the instructions run with the pointer on every cell, by cell
+    pub cells: Vec<u64>,
+}
+
+impl Heatmap {
+    pub fn new(tape_size: usize) -> Self {
+        Self {
+            cells: vec![0; tape_size],
+        }
+    }
+
+    /// The cells after the last one that was used aren't part of the heatmap.
+    pub fn used(&self) -> &[u64] {
+        let used = self.cells.iter().rposition(|&count| count > 0);
+        &self.cells[..used.map_or(0, |last| last + 1)]
+    }
+}
+
+impl<C: Cell> ExecHooks<C> for Heatmap {
+    fn on_instruction(&mut self, _: usize, machine: &Machine<C>) {
+        self.cells[machine.pivot] += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn program(src: &str) -> Program {
+        Program::from_instructions(bfrs_common::parser::parse_str(src).unwrap(), 4)
+    }
+
+    fn run<H: ExecHooks>(program: &Program, input: &[u8], hooks: &mut H) -> Vec<u8> {
+        let mut output = Vec::new();
+        let mut input = input;
+        Machine::new(4)
+            .run_with_hooks(program, &mut input, &mut output, hooks)
+            .unwrap();
+        output
+    }
+
+    /// Every event but the instructions, as text.
+    #[derive(Default)]
+    struct Events(Vec<String>);
+
+    impl ExecHooks for Events {
+        fn on_output(&mut self, byte: u8) {
+            self.0.push(format!("out {}", byte));
+        }
+
+        fn on_input(&mut self, byte: Option<u8>) {
+            self.0.push(format!("in {:?}", byte));
+        }
+
+        fn on_loop_enter(&mut self, start: usize) {
+            self.0.push(format!("enter {}", start));
+        }
+
+        fn on_loop_exit(&mut self, start: usize) {
+            self.0.push(format!("exit {}", start));
+        }
+    }
+
+    #[test]
+    fn hooks_are_told_what_happens() {
+        let mut events = Events::default();
+        let output = run(&program(",[-.]>[,].,"), b"\x02", &mut events);
+        assert_eq!(output, [1, 0, 0]);
+        // the loop that's skipped is neither entered nor left
+        assert_eq!(
+            events.0,
+            [
+                "in Some(2)",
+                "enter 1",
+                "out 1",
+                "enter 1",
+                "out 0",
+                "exit 1",
+                "out 0",
+                "in None"
+            ]
+        );
+    }
+
+    #[test]
+    fn profiles_count_every_instruction() {
+        let program = program("++[>+<-]>.");
+        let mut hooks = (Profiler::new(&program), Some(Heatmap::new(4)));
+        run(&program, b"", &mut hooks);
+        let (profiler, heatmap) = hooks;
+        assert_eq!(profiler.counts, [1, 1, 1, 2, 2, 2, 2, 2, 1, 1]);
+        assert_eq!(profiler.total(), 15);
+        assert_eq!(
+            profiler.loops(&program),
+            [LoopProfile {
+                start: 2,
+                end: 7,
+                iterations: 2,
+                instructions: 10
+            }]
+        );
+        // the pointer is on the first cell for all but the
+        // instructions after every `>`
+        assert_eq!(heatmap.unwrap().used(), [10, 5]);
+        assert!(Heatmap::new(4).used().is_empty());
+    }
+
+    #[test]
+    fn folded_stacks_of_nested_loops() {
+        let program = program("+[>++[-]<-;]");
+        let mut profiler = Profiler::new(&program);
+        run(&program, b"", &mut profiler);
+        let folded = profiler.folded(&program, "a;b", |start| format!("loop@{}", start));
+        assert_eq!(
+            folded,
+            [
+                ("a:b".to_string(), 1),
+                ("a:b;loop@1".to_string(), 7),
+                ("a:b;loop@1;loop@5".to_string(), 5),
+            ]
+        );
+    }
+}
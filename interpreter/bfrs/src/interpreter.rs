@@ -0,0 +1,134 @@
+//! Running a program a few steps at a time, for hosts with an event loop
+//! of their own, like GUIs and async runtimes, which can't block on the
+//! input of a program or wait for it to finish.
+
+use crate::{Cell, Machine, Program};
+use bfrs_common::BFCommand;
+use std::collections::VecDeque;
+
+/// Why [`Interpreter::run_for`] stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunOutcome {
+    /// the program is done
+    Completed,
+    /// the program has to read, and there's no input yet, so it
+    /// waits for [`push_input`](Interpreter::push_input) or
+    /// [`close_input`](Interpreter::close_input)
+    NeedsInput,
+    /// the program ran all the steps it was given
+    OutOfFuel,
+}
+
+/// A program being run, which never touches the stdio of the process:
+/// its input is pushed to it and its output is taken from it.
+pub struct Interpreter<C: Cell = u8> {
+    pub program: Program,
+    pub machine: Machine<C>,
+    /// the index of the next instruction to run
+    next: usize,
+    input: VecDeque<u8>,
+    /// whether reading past the input is the end of it instead of waiting
+    input_closed: bool,
+    output: Vec<u8>,
+}
+
+impl<C: Cell> Interpreter<C> {
+    /// An interpreter running `program` from the start, on a blank tape.
+    pub fn new(program: Program) -> Self {
+        Self {
+            machine: Machine::new(program.tape_size),
+            program,
+            next: 0,
+            input: VecDeque::new(),
+            input_closed: false,
+            output: Vec::new(),
+        }
+    }
+
+    /// Adds bytes for the program to read.
+    pub fn push_input(&mut self, bytes: &[u8]) {
+        self.input.extend(bytes);
+    }
+
+    /// Ends the input, so the program reads the end of it instead of
+    /// waiting once the bytes pushed so far are read.
+    pub fn close_input(&mut self) {
+        self.input_closed = true;
+    }
+
+    /// Everything the program printed since the last time it was taken.
+    pub fn take_output(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.output)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.next >= self.program.code.instructions.len()
+    }
+
+    /// Runs at most `steps` instructions, stopping before reading if there's
+    /// nothing to read yet. It can be called again to go on from there.
+    pub fn run_for(&mut self, steps: usize) -> RunOutcome {
+        for _ in 0..steps {
+            if self.is_finished() {
+                return RunOutcome::Completed;
+            }
+            let waiting = self.input.is_empty() && !self.input_closed;
+            if waiting && self.program.code.instructions[self.next] == BFCommand::Read {
+                return RunOutcome::NeedsInput;
+            }
+            self.next = self
+                .machine
+                .step(&self.program, self.next, &mut self.input, &mut self.output)
+                .expect("reading and writing memory doesn't fail");
+        }
+        if self.is_finished() {
+            RunOutcome::Completed
+        } else {
+            RunOutcome::OutOfFuel
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bfrs_common::parser;
+
+    fn interpreter(src: &str) -> Interpreter {
+        let instructions = parser::parse_bytes(src.as_bytes()).unwrap();
+        Interpreter::new(Program::from_instructions(instructions, 8))
+    }
+
+    #[test]
+    fn programs_wait_for_their_input() {
+        // echoes bytes until a NUL
+        let mut interpreter = interpreter(",[.,]");
+        assert_eq!(interpreter.run_for(100), RunOutcome::NeedsInput);
+        interpreter.push_input(b"ab");
+        assert_eq!(interpreter.run_for(100), RunOutcome::NeedsInput);
+        assert_eq!(interpreter.take_output(), b"ab");
+        assert_eq!(interpreter.take_output(), b"");
+        interpreter.push_input(b"c\0");
+        assert_eq!(interpreter.run_for(100), RunOutcome::Completed);
+        assert_eq!(interpreter.take_output(), b"c");
+        assert!(interpreter.is_finished());
+    }
+
+    #[test]
+    fn closed_inputs_are_read_as_their_end() {
+        let mut interpreter = interpreter(",.");
+        interpreter.close_input();
+        assert_eq!(interpreter.run_for(100), RunOutcome::Completed);
+        assert_eq!(interpreter.take_output().len(), 1);
+    }
+
+    #[test]
+    fn runs_go_on_where_they_stopped() {
+        let mut interpreter = interpreter("+++.");
+        assert_eq!(interpreter.run_for(2), RunOutcome::OutOfFuel);
+        assert_eq!(interpreter.take_output(), b"");
+        assert_eq!(interpreter.run_for(2), RunOutcome::Completed);
+        assert_eq!(interpreter.take_output(), [3]);
+        assert_eq!(interpreter.run_for(2), RunOutcome::Completed);
+    }
+}
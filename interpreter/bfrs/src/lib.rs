@@ -0,0 +1,330 @@
+//! A simple brainfuck interpreter, used by the `bfrs` binary
+//! and by the `bf` multitool.
+//!
+//! The command line interface is behind the `cli` feature, which is
+//! on by default. Without it, the interpreter only needs `bfrs_common`,
+//! so it can be embedded, like in the WebAssembly bindings.
+//! [`Interpreter`] runs a program a few steps at a time, to embed
+//! it in hosts that can't block on it.
+//! [`capture::run_captured`] runs one on bytes in memory, to test it,
+//! and [`reduce::reduce`] shrinks one that makes something go wrong.
+//! [`eval::evaluate`] runs one that can't be trusted within limits.
+//! [`tapes::Tapes`] runs the dialects with several tapes or a grid.
+//! A [`Tape`] can be kept in a file with the `mmap` feature, on with `cli`.
+
+pub mod capture;
+pub mod cell;
+#[cfg(feature = "cli")]
+pub mod cli;
+pub mod cost;
+pub mod eval;
+pub mod hooks;
+pub mod interpreter;
+pub mod output;
+pub mod reduce;
+pub mod tape;
+pub mod tapes;
+mod threaded;
+pub mod viz;
+
+use bfrs_common::parser::{self, ParseError};
+use bfrs_common::theme::{self, Theme};
+use bfrs_common::BFCommand;
+pub use cell::Cell;
+pub use hooks::ExecHooks;
+pub use interpreter::{Interpreter, RunOutcome};
+use std::error::Error;
+use std::{fmt, io};
+pub use tape::Tape;
+
+/// A program to run, along with the cells of the tape it runs on.
+pub struct Program {
+    pub code: bfrs_common::program::Program,
+    pub tape_size: usize,
+}
+
+impl Program {
+    pub fn new(code: bfrs_common::program::Program, tape_size: usize) -> Self {
+        Self { code, tape_size }
+    }
+
+    /// # Panics
+    /// If the loops of the instructions aren't balanced.
+    pub fn from_instructions(instructions: Vec<BFCommand>, tape_size: usize) -> Self {
+        Self::new(bfrs_common::program::Program::new(instructions), tape_size)
+    }
+}
+
+/// The tape and the pointer, which are kept between programs
+/// so they can be run one after the other, like in the REPL.
+/// Cells are bytes unless another [`Cell`] is picked.
+pub struct Machine<C: Cell = u8> {
+    pub tape: Tape<C>,
+    pub pivot: usize,
+}
+
+impl<C: Cell> Machine<C> {
+    pub fn new(tape_size: usize) -> Self {
+        Self::with_tape(Tape::blank(tape_size))
+    }
+
+    /// A machine on a tape that may not be blank, like one kept in a file.
+    pub fn with_tape(tape: Tape<C>) -> Self {
+        Self { tape, pivot: 0 }
+    }
+
+    /// The cells `args` take with [`load_args`](Self::load_args).
+    pub fn args_len<A: AsRef<[u8]>>(args: &[A]) -> usize {
+        // the cell before them and the one ending the list.
+        args.iter().map(|arg| arg.as_ref().len() + 1).sum::<usize>() + 2
+    }
+
+    /// Writes `args` on the tape for the program to take, like the arguments
+    /// of a command: every one of them ends with a 0, and another 0 ends the
+    /// list. They start at cell 1, where the pointer is left, so cell 0 is a 0
+    /// to go back to with `[<]`. The tape has to fit [`args_len`](Self::args_len)
+    /// cells, and an argument with a 0 in it would end there.
+    pub fn load_args<A: AsRef<[u8]>>(&mut self, args: &[A]) {
+        let mut cell = 1;
+        for arg in args {
+            for &byte in arg.as_ref() {
+                self.tape[cell] = C::from_byte(byte);
+                cell += 1;
+            }
+            self.tape[cell] = C::zero();
+            cell += 1;
+        }
+        self.tape[cell] = C::zero();
+        self.pivot = 1;
+    }
+
+    /// Runs the program from the current state of the tape, writing every
+    /// byte it prints to `output`, which is flushed before reading and at
+    /// the end, and can buffer the rest, like an [`output::ProgramOutput`].
+    /// It runs on a threaded core, which is faster than stepping through
+    /// the program since it decodes every instruction once.
+    pub fn run(
+        &mut self,
+        target: &Program,
+        input: &mut dyn io::Read,
+        output: &mut dyn io::Write,
+    ) -> io::Result<()> {
+        threaded::run(target, &mut self.tape, &mut self.pivot, input, output)?;
+        output.flush()
+    }
+
+    /// Runs the program like [`run`](Self::run), telling `hooks`
+    /// everything it does.
+    pub fn run_with_hooks<H: ExecHooks<C>>(
+        &mut self,
+        target: &Program,
+        input: &mut dyn io::Read,
+        output: &mut dyn io::Write,
+        hooks: &mut H,
+    ) -> io::Result<()> {
+        let mut instruction_i = 0;
+        while instruction_i < target.code.instructions.len() {
+            instruction_i = self.step_with_hooks(target, instruction_i, input, output, hooks)?;
+        }
+        output.flush()
+    }
+
+    /// Runs the instruction at `instruction_i`, returning the index of the
+    /// next one, which is past the end of the program once it's done.
+    /// `output` is only flushed before reading.
+    #[inline]
+    pub fn step(
+        &mut self,
+        target: &Program,
+        instruction_i: usize,
+        input: &mut dyn io::Read,
+        output: &mut dyn io::Write,
+    ) -> io::Result<usize> {
+        self.step_with_hooks(target, instruction_i, input, output, &mut ())
+    }
+
+    /// Runs the instruction at `instruction_i` like [`step`](Self::step),
+    /// telling `hooks` what it does.
+    #[inline]
+    pub fn step_with_hooks<H: ExecHooks<C>>(
+        &mut self,
+        target: &Program,
+        mut instruction_i: usize,
+        input: &mut dyn io::Read,
+        output: &mut dyn io::Write,
+        hooks: &mut H,
+    ) -> io::Result<usize> {
+        hooks.on_instruction(instruction_i, self);
+        let tape: &mut [C] = &mut self.tape;
+        let pivot = &mut self.pivot;
+        let tape_size = tape.len();
+
+        match target.code.instructions[instruction_i] {
+            BFCommand::BeginLoop => {
+                if tape[*pivot].is_zero() {
+                    instruction_i = target.code.jump(instruction_i);
+                } else {
+                    hooks.on_loop_enter(instruction_i);
+                }
+            }
+            BFCommand::EndLoop => {
+                let start = target.code.jump(instruction_i);
+                if tape[*pivot].is_zero() {
+                    hooks.on_loop_exit(start);
+                } else {
+                    // going back to the start enters the loop again.
+                    hooks.on_loop_enter(start);
+                    instruction_i = start;
+                }
+            }
+            BFCommand::Decrement => tape[*pivot].sub(1),
+            BFCommand::Left => {
+                *pivot = if *pivot == 0 {
+                    tape_size - 1
+                } else {
+                    *pivot - 1
+                }
+            }
+            BFCommand::Right => {
+                *pivot = if *pivot == tape_size - 1 {
+                    0
+                } else {
+                    *pivot + 1
+                }
+            }
+            BFCommand::Increment => tape[*pivot].add(1),
+            BFCommand::Print => {
+                let byte = tape[*pivot].to_byte();
+                output.write_all(&[byte])?;
+                hooks.on_output(byte);
+            }
+            BFCommand::Read => {
+                // whatever the program asks for has to be shown first.
+                output.flush()?;
+                let mut byte = [0];
+                let amt_read = input.read(&mut byte)?;
+                tape[*pivot] = if amt_read == 0 {
+                    hooks.on_input(None);
+                    C::eof()
+                } else {
+                    hooks.on_input(Some(byte[0]));
+                    C::from_byte(byte[0])
+                };
+            }
+        }
+        Ok(instruction_i + 1)
+    }
+}
+
+/// Runs the program on a blank tape, printing to stdout, and returns the tape.
+pub fn interpret<C: Cell>(target: &Program, input: &mut dyn io::Read) -> io::Result<Vec<C>> {
+    let mut machine = Machine::new(target.tape_size);
+    machine.run(target, input, &mut io::stdout().lock())?;
+    Ok(machine.tape.into_vec())
+}
+
+/// Why [`run_with_fuel`] stopped before the end of the program.
+#[derive(Debug)]
+pub enum RunError {
+    Parse(ParseError),
+    /// the program ran `fuel` instructions without finishing,
+    /// after printing `output`.
+    OutOfFuel {
+        output: Vec<u8>,
+    },
+}
+
+impl fmt::Display for RunError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Parse(e) => e.fmt(f),
+            Self::OutOfFuel { .. } => write!(f, "the program ran out of fuel"),
+        }
+    }
+}
+
+impl Error for RunError {}
+
+/// Parses `source` and runs it on a blank tape of `cells` cells with
+/// `input`, for at most `fuel` instructions, returning what it printed.
+/// Unlike the rest of the interpreter, this never panics, whatever
+/// the bytes it's given, which is what fuzzers need.
+pub fn run_with_fuel(
+    source: &[u8],
+    input: &[u8],
+    cells: usize,
+    fuel: usize,
+) -> Result<Vec<u8>, RunError> {
+    let instructions = parser::parse_bytes(source).map_err(RunError::Parse)?;
+    let program = Program::from_instructions(instructions, cells.max(1));
+    let mut interpreter = Interpreter::<u8>::new(program);
+    interpreter.push_input(input);
+    interpreter.close_input();
+    match interpreter.run_for(fuel) {
+        RunOutcome::Completed => Ok(interpreter.take_output()),
+        RunOutcome::OutOfFuel | RunOutcome::NeedsInput => Err(RunError::OutOfFuel {
+            output: interpreter.take_output(),
+        }),
+    }
+}
+
+/// Prints the program, in the colors of `theme` if there's one.
+pub fn highlight_code(program: &bfrs_common::program::Program, theme: Option<&Theme>) {
+    let theme = match theme {
+        Some(theme) => theme,
+        None => {
+            let text: String = program.instructions.iter().map(|i| i.to_string()).collect();
+            println!("{}", text);
+            return;
+        }
+    };
+    let mut depth = 0;
+    let mut current = None;
+    for &instr in program.instructions.iter() {
+        if instr == BFCommand::BeginLoop {
+            depth += 1;
+        }
+        let color = theme.color_of(instr, depth);
+        if color != current {
+            print!("{}", theme.paint(color, None, false));
+            current = color;
+        }
+        print!("{}", instr);
+        if instr == BFCommand::EndLoop {
+            depth -= 1;
+        }
+    }
+    println!("{}", theme::RESET)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn args_are_loaded_for_the_program_to_take() {
+        let args = ["ab", "c"];
+        assert_eq!(Machine::<u8>::args_len(&args), 7);
+        let mut machine = Machine::<u8>::new(7);
+        machine.load_args(&args);
+        assert_eq!(machine.pivot, 1);
+        assert_eq!(&machine.tape[..], b"\0ab\0c\0\0");
+
+        // prints every argument, one after the other
+        let instructions = parser::parse_bytes(b"[[.>]>]").unwrap();
+        let program = Program::from_instructions(instructions, 7);
+        let mut output = Vec::new();
+        machine
+            .run(&program, &mut io::empty(), &mut output)
+            .unwrap();
+        assert_eq!(output, b"abc");
+    }
+
+    #[test]
+    fn no_args_are_an_empty_list() {
+        assert_eq!(Machine::<u8>::args_len::<&str>(&[]), 2);
+        let mut machine = Machine::<u8>::new(2);
+        machine.load_args::<&str>(&[]);
+        assert_eq!(&machine.tape[..], [0, 0]);
+    }
+}
@@ -0,0 +1,449 @@
+//! Buffering what a program prints, which is a byte at a time.
+//!
+//! Writing every byte on its own is most of the time some programs take,
+//! like the ones drawing fractals, so [`ProgramOutput`] keeps the bytes
+//! until it's time to show them, which [`Flush`] says. The interpreter
+//! always flushes before reading, so a prompt shows up before it's
+//! answered, and at the end of the program.
+//!
+//! What is printed can be transcoded on the way, to look at the output of
+//! programs printing binary in a terminal, like a hex dump does, and its
+//! line endings translated, as an [`OutputFormat`] and a [`Newline`] say.
+
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+/// The bytes kept at most before writing them anyway.
+const CAPACITY: usize = 8 * 1024;
+
+/// The bytes on every line of the `hex` and `decimal` formats.
+const BYTES_PER_LINE: usize = 16;
+
+/// When the output of a program is shown, other than before reading
+/// and at the end of the program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flush {
+    /// as soon as every byte is printed
+    Always,
+    /// at the end of every line
+    Line,
+    /// only when the buffer is full
+    Input,
+}
+
+impl Flush {
+    pub const VARIANTS: &'static [&'static str] = &["always", "line", "input"];
+}
+
+impl FromStr for Flush {
+    type Err = UnknownFlush;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "always" => Self::Always,
+            "line" => Self::Line,
+            "input" => Self::Input,
+            _ => return Err(UnknownFlush(s.to_string())),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct UnknownFlush(String);
+
+impl fmt::Display for UnknownFlush {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown flush setting: {:?}", self.0)
+    }
+}
+
+impl std::error::Error for UnknownFlush {}
+
+/// How the bytes a program prints are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// as they are
+    Raw,
+    /// as pairs of hex digits, 16 on every line
+    Hex,
+    /// as decimal numbers, 16 on every line
+    Decimal,
+    /// as text, with the bytes that aren't printable ASCII
+    /// escaped like `\x1b`, other than line breaks
+    Escaped,
+}
+
+impl OutputFormat {
+    pub const VARIANTS: &'static [&'static str] = &["raw", "hex", "decimal", "escaped"];
+}
+
+impl FromStr for OutputFormat {
+    type Err = UnknownOutputFormat;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "raw" => Self::Raw,
+            "hex" => Self::Hex,
+            "decimal" => Self::Decimal,
+            "escaped" => Self::Escaped,
+            _ => return Err(UnknownOutputFormat(s.to_string())),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct UnknownOutputFormat(String);
+
+impl fmt::Display for UnknownOutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown output format: {:?}", self.0)
+    }
+}
+
+impl std::error::Error for UnknownOutputFormat {}
+
+/// What the line endings a program prints are turned into,
+/// before its output is transcoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Newline {
+    /// nothing, they're kept as they are
+    Keep,
+    /// `\r\n` is turned into `\n`
+    Lf,
+    /// `\n` is turned into `\r\n`, unless it already was
+    Crlf,
+}
+
+impl Newline {
+    pub const VARIANTS: &'static [&'static str] = &["keep", "lf", "crlf"];
+}
+
+impl FromStr for Newline {
+    type Err = UnknownNewline;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "keep" => Self::Keep,
+            "lf" => Self::Lf,
+            "crlf" => Self::Crlf,
+            _ => return Err(UnknownNewline(s.to_string())),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct UnknownNewline(String);
+
+impl fmt::Display for UnknownNewline {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown newline setting: {:?}", self.0)
+    }
+}
+
+impl std::error::Error for UnknownNewline {}
+
+/// Writes what a program prints to `W`, when [`Flush`] says.
+/// Whatever is left is written when it's dropped.
+pub struct ProgramOutput<W: Write> {
+    inner: W,
+    buffer: Vec<u8>,
+    flush: Flush,
+    format: OutputFormat,
+    newline: Newline,
+    /// the last byte printed, to tell the line endings apart
+    last: Option<u8>,
+    /// the bytes on the line being written, in the `hex` and `decimal` formats
+    column: usize,
+}
+
+impl<W: Write> ProgramOutput<W> {
+    pub fn new(inner: W, flush: Flush) -> Self {
+        Self {
+            inner,
+            buffer: Vec::with_capacity(CAPACITY),
+            flush,
+            format: OutputFormat::Raw,
+            newline: Newline::Keep,
+            last: None,
+            column: 0,
+        }
+    }
+
+    /// Transcodes the output to `format`, after
+    /// translating its line endings as `newline` says.
+    /// ```
+    /// use bfrs::output::{Flush, Newline, OutputFormat, ProgramOutput};
+    /// use std::io::Write;
+    ///
+    /// let mut shown = Vec::new();
+    /// let mut output = ProgramOutput::new(&mut shown, Flush::Line)
+    ///     .transcoded(OutputFormat::Hex, Newline::Crlf);
+    /// output.write_all(b"hi\n").unwrap();
+    /// drop(output);
+    /// assert_eq!(shown, b"68 69 0d 0a\n");
+    /// ```
+    pub fn transcoded(mut self, format: OutputFormat, newline: Newline) -> Self {
+        self.format = format;
+        self.newline = newline;
+        self
+    }
+
+    /// Adds a byte the program printed to the buffer. With [`Newline::Lf`],
+    /// a `\r` is held back until what follows it is known.
+    fn push(&mut self, byte: u8) {
+        let last = self.last.replace(byte);
+        match self.newline {
+            Newline::Lf => {
+                if last == Some(b'\r') && byte != b'\n' {
+                    self.encode(b'\r');
+                }
+                if byte != b'\r' {
+                    self.encode(byte);
+                }
+            }
+            Newline::Crlf if byte == b'\n' && last != Some(b'\r') => {
+                self.encode(b'\r');
+                self.encode(b'\n');
+            }
+            _ => self.encode(byte),
+        }
+    }
+
+    fn encode(&mut self, byte: u8) {
+        match self.format {
+            OutputFormat::Raw => self.buffer.push(byte),
+            OutputFormat::Hex | OutputFormat::Decimal => {
+                if self.column > 0 {
+                    self.buffer.push(b' ');
+                }
+                let number = match self.format {
+                    OutputFormat::Hex => format!("{:02x}", byte),
+                    _ => byte.to_string(),
+                };
+                self.buffer.extend_from_slice(number.as_bytes());
+                self.column += 1;
+                if self.column == BYTES_PER_LINE {
+                    self.buffer.push(b'\n');
+                    self.column = 0;
+                }
+            }
+            OutputFormat::Escaped => match byte {
+                b'\n' => self.buffer.push(b'\n'),
+                b'\\' => self.buffer.extend_from_slice(b"\\\\"),
+                b'\t' => self.buffer.extend_from_slice(b"\\t"),
+                b'\r' => self.buffer.extend_from_slice(b"\\r"),
+                b' '..=b'~' => self.buffer.push(byte),
+                _ => self
+                    .buffer
+                    .extend_from_slice(format!("\\x{:02x}", byte).as_bytes()),
+            },
+        }
+    }
+
+    /// Adds what is held back to the buffer, and ends the line of numbers
+    /// being written, once the program is done printing.
+    fn finish(&mut self) {
+        if self.newline == Newline::Lf && self.last == Some(b'\r') {
+            self.encode(b'\r');
+        }
+        self.last = None;
+        if self.column > 0 {
+            self.buffer.push(b'\n');
+            self.column = 0;
+        }
+    }
+
+    fn write_buffer(&mut self) -> io::Result<()> {
+        if !self.buffer.is_empty() {
+            self.inner.write_all(&self.buffer)?;
+            self.buffer.clear();
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for ProgramOutput<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let start = self.buffer.len();
+        if self.format == OutputFormat::Raw && self.newline == Newline::Keep {
+            self.buffer.extend_from_slice(buf);
+        } else {
+            buf.iter().for_each(|&byte| self.push(byte));
+        }
+        let due = match self.flush {
+            Flush::Always => true,
+            // the lines written, which transcoding can move.
+            Flush::Line => self.buffer[start..].contains(&b'\n'),
+            Flush::Input => false,
+        };
+        if due {
+            self.flush()?;
+        } else if self.buffer.len() >= CAPACITY {
+            self.write_buffer()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_buffer()?;
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Drop for ProgramOutput<W> {
+    fn drop(&mut self) {
+        self.finish();
+        // like `BufWriter`, errors can't be reported from here.
+        let _ = self.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// What was written so far, which can be looked at while it's written to.
+    #[derive(Clone, Default)]
+    struct Shown(Rc<RefCell<Vec<u8>>>);
+
+    impl Shown {
+        fn len(&self) -> usize {
+            self.0.borrow().len()
+        }
+    }
+
+    impl Write for Shown {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn lines_are_shown_once_they_end() {
+        let shown = Shown::default();
+        let mut output = ProgramOutput::new(shown.clone(), Flush::Line);
+        output.write_all(b"a").unwrap();
+        output.write_all(b"b").unwrap();
+        assert_eq!(shown.len(), 0);
+        output.write_all(b"\n").unwrap();
+        assert_eq!(*shown.0.borrow(), b"ab\n");
+        // what's left is shown at the end
+        output.write_all(b"c").unwrap();
+        drop(output);
+        assert_eq!(*shown.0.borrow(), b"ab\nc");
+    }
+
+    #[test]
+    fn bytes_are_shown_as_the_setting_says() {
+        let shown = Shown::default();
+        let mut output = ProgramOutput::new(shown.clone(), Flush::Always);
+        output.write_all(b"a").unwrap();
+        assert_eq!(shown.len(), 1);
+
+        let shown = Shown::default();
+        let mut output = ProgramOutput::new(shown.clone(), Flush::Input);
+        output.write_all(b"a\n").unwrap();
+        assert_eq!(shown.len(), 0);
+        output.flush().unwrap();
+        assert_eq!(shown.len(), 2);
+        // a full buffer is written anyway
+        output.write_all(&[b'.'; CAPACITY]).unwrap();
+        assert_eq!(shown.len(), 2 + CAPACITY);
+    }
+
+    #[test]
+    fn flush_settings() {
+        assert_eq!("line".parse::<Flush>().unwrap(), Flush::Line);
+        assert_eq!(
+            "never".parse::<Flush>().unwrap_err().to_string(),
+            r#"unknown flush setting: "never""#
+        );
+    }
+
+    /// What printing `chunks` one after the other shows.
+    fn transcode(format: OutputFormat, newline: Newline, chunks: &[&[u8]]) -> String {
+        let mut shown = Vec::new();
+        let mut output = ProgramOutput::new(&mut shown, Flush::Input).transcoded(format, newline);
+        for chunk in chunks {
+            output.write_all(chunk).unwrap();
+        }
+        drop(output);
+        String::from_utf8(shown).unwrap()
+    }
+
+    #[test]
+    fn output_is_transcoded() {
+        let bytes: Vec<u8> = (0..18).collect();
+        let hex = transcode(OutputFormat::Hex, Newline::Keep, &[&bytes]);
+        assert_eq!(
+            hex,
+            "00 01 02 03 04 05 06 07 08 09 0a 0b 0c 0d 0e 0f\n10 11\n"
+        );
+        let decimal = transcode(OutputFormat::Decimal, Newline::Keep, &[b"a", b"\xff"]);
+        assert_eq!(decimal, "97 255\n");
+        let escaped = transcode(
+            OutputFormat::Escaped,
+            Newline::Keep,
+            &[b"a\\b\t\x1b\r\n\x80"],
+        );
+        assert_eq!(escaped, "a\\\\b\\t\\x1b\\r\n\\x80");
+        assert_eq!(
+            transcode(OutputFormat::Raw, Newline::Keep, &[b"\r\n"]),
+            "\r\n"
+        );
+    }
+
+    #[test]
+    fn line_endings_are_translated() {
+        let lf = |chunks: &[&[u8]]| transcode(OutputFormat::Raw, Newline::Lf, chunks);
+        // even when a line ending is split between writes
+        assert_eq!(lf(&[b"a\r", b"\nb\n"]), "a\nb\n");
+        assert_eq!(lf(&[b"a\rb\r"]), "a\rb\r");
+        let crlf = |chunks: &[&[u8]]| transcode(OutputFormat::Raw, Newline::Crlf, chunks);
+        assert_eq!(crlf(&[b"a\n", b"b\r", b"\n"]), "a\r\nb\r\n");
+        // before transcoding
+        assert_eq!(
+            transcode(OutputFormat::Hex, Newline::Lf, &[b"\r\n"]),
+            "0a\n"
+        );
+    }
+
+    #[test]
+    fn transcoded_lines_are_shown_once_they_end() {
+        let shown = Shown::default();
+        let mut output = ProgramOutput::new(shown.clone(), Flush::Line)
+            .transcoded(OutputFormat::Hex, Newline::Keep);
+        // a line break the program prints isn't one of the hex dump
+        output.write_all(b"\n").unwrap();
+        assert_eq!(shown.len(), 0);
+        output.write_all(&[b'a'; 15]).unwrap();
+        assert_eq!(shown.len(), 16 * 3);
+    }
+
+    #[test]
+    fn formats_parse() {
+        let formats: Vec<OutputFormat> = OutputFormat::VARIANTS
+            .iter()
+            .map(|format| format.parse().unwrap())
+            .collect();
+        assert_eq!(
+            formats,
+            [
+                OutputFormat::Raw,
+                OutputFormat::Hex,
+                OutputFormat::Decimal,
+                OutputFormat::Escaped
+            ]
+        );
+        assert_eq!("crlf".parse::<Newline>().unwrap(), Newline::Crlf);
+        let e = "octal".parse::<OutputFormat>().unwrap_err();
+        assert_eq!(e.to_string(), "unknown output format: \"octal\"");
+        let e = "cr".parse::<Newline>().unwrap_err();
+        assert_eq!(e.to_string(), "unknown newline setting: \"cr\"");
+    }
+}
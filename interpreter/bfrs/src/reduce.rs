@@ -0,0 +1,190 @@
+//! Shrinking a program while it still does something, like crashing a
+//! tool or running differently once optimized, down to the few
+//! instructions that make it do it. This is what `bfrs reduce` does.
+//!
+//! Every candidate is a valid program: only runs of instructions with
+//! balanced brackets are removed, and loops are either removed whole or
+//! unwrapped, keeping their body.
+
+use bfrs_common::BFCommand;
+
+/// The longest runs tried in every length and from every instruction.
+const SLIDING: usize = 4;
+
+/// The smallest program found by removing instructions from `program`
+/// for which `interesting` holds, which it has to hold for to begin with.
+/// `interesting` is tried on every candidate, and the first error it
+/// returns stops the reduction.
+pub fn reduce<E>(
+    mut program: Vec<BFCommand>,
+    mut interesting: impl FnMut(&[BFCommand]) -> Result<bool, E>,
+) -> Result<Vec<BFCommand>, E> {
+    loop {
+        let before = program.len();
+        remove_chunks(&mut program, &mut interesting)?;
+        remove_loops(&mut program, &mut interesting, false)?;
+        remove_loops(&mut program, &mut interesting, true)?;
+        if program.len() == before {
+            return Ok(program);
+        }
+    }
+}
+
+/// Removes the balanced runs of instructions that can go, from runs of
+/// half the program down to single instructions, like delta debugging.
+/// Short runs are tried in every length and from every instruction, instead
+/// of one after the other, since they're the ones canceling each other
+/// out, like `+-`.
+fn remove_chunks<E>(
+    program: &mut Vec<BFCommand>,
+    interesting: &mut impl FnMut(&[BFCommand]) -> Result<bool, E>,
+) -> Result<(), E> {
+    let mut size = (program.len() / 2).max(1);
+    loop {
+        let mut start = 0;
+        while start < program.len() {
+            let end = (start + size).min(program.len());
+            if balanced(&program[start..end]) {
+                let candidate: Vec<_> = program[..start]
+                    .iter()
+                    .chain(&program[end..])
+                    .copied()
+                    .collect();
+                if interesting(&candidate)? {
+                    *program = candidate;
+                    // the instructions after the chunk are at its start now.
+                    continue;
+                }
+            }
+            start += if size <= SLIDING { 1 } else { size };
+        }
+        if size == 1 {
+            return Ok(());
+        }
+        size = if size <= SLIDING { size - 1 } else { size / 2 };
+    }
+}
+
+/// Removes the loops that can go whole, or if `unwrap` is set, the
+/// brackets of the loops whose body can run without them.
+fn remove_loops<E>(
+    program: &mut Vec<BFCommand>,
+    interesting: &mut impl FnMut(&[BFCommand]) -> Result<bool, E>,
+    unwrap: bool,
+) -> Result<(), E> {
+    let mut start = 0;
+    while start < program.len() {
+        if program[start] != BFCommand::BeginLoop {
+            start += 1;
+            continue;
+        }
+        let end = matching(program, start);
+        let candidate: Vec<_> = if unwrap {
+            let body = &program[start + 1..end];
+            program[..start]
+                .iter()
+                .chain(body)
+                .chain(&program[end + 1..])
+                .copied()
+                .collect()
+        } else {
+            program[..start]
+                .iter()
+                .chain(&program[end + 1..])
+                .copied()
+                .collect()
+        };
+        if interesting(&candidate)? {
+            *program = candidate;
+        } else {
+            start += 1;
+        }
+    }
+    Ok(())
+}
+
+/// Whether the brackets of a run of instructions close each other,
+/// so removing it leaves the rest of the program valid.
+fn balanced(run: &[BFCommand]) -> bool {
+    let mut depth = 0usize;
+    for &command in run {
+        match command {
+            BFCommand::BeginLoop => depth += 1,
+            BFCommand::EndLoop => match depth.checked_sub(1) {
+                Some(outer) => depth = outer,
+                None => return false,
+            },
+            _ => (),
+        }
+    }
+    depth == 0
+}
+
+/// The index of the `]` closing the loop opened at `start`.
+fn matching(program: &[BFCommand], start: usize) -> usize {
+    let mut depth = 0;
+    for (i, &command) in program.iter().enumerate().skip(start) {
+        match command {
+            BFCommand::BeginLoop => depth += 1,
+            BFCommand::EndLoop => {
+                depth -= 1;
+                if depth == 0 {
+                    return i;
+                }
+            }
+            _ => (),
+        }
+    }
+    unreachable!("the program is valid")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::{run_captured, RunOptions, RunResult};
+    use crate::Program;
+    use bfrs_common::parser;
+
+    fn source(program: &[BFCommand]) -> String {
+        program.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn programs_are_reduced_to_what_matters() {
+        let options = RunOptions {
+            fuel: Some(1000),
+            ..RunOptions::default()
+        };
+        let program = parser::parse_str("+>+++[-<+>]<[>+<-]>-+.>[+.]").unwrap();
+        // whatever prints a 4
+        let reduced = reduce(program, |candidate| {
+            assert!(balanced(candidate), "{} isn't valid", source(candidate));
+            let program = Program::from_instructions(candidate.to_vec(), 4);
+            let result: RunResult = run_captured(&program, b"", &options);
+            Ok::<_, ()>(result.output == [4])
+        })
+        .unwrap();
+        assert_eq!(source(&reduced), "++++.");
+    }
+
+    #[test]
+    fn loops_are_unwrapped() {
+        let program = parser::parse_str("[[>]<]").unwrap();
+        let reduced = reduce(program, |candidate| {
+            Ok::<_, ()>(candidate.contains(&BFCommand::Right))
+        });
+        assert_eq!(source(&reduced.unwrap()), ">");
+    }
+
+    #[test]
+    fn errors_stop_reducing() {
+        let program = parser::parse_str("+++").unwrap();
+        let mut tries = 0;
+        let reduced = reduce(program, |_| {
+            tries += 1;
+            Err("the check broke")
+        });
+        assert_eq!(reduced, Err("the check broke"));
+        assert_eq!(tries, 1);
+    }
+}
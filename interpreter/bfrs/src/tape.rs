@@ -0,0 +1,146 @@
+//! Where the cells of a [`Machine`](crate::Machine) are kept: in memory,
+//! or, with the `mmap` feature, in a file mapped into memory, so what
+//! a program leaves on the tape is there for the next run, and only
+//! the cells it touches take memory.
+
+use crate::Cell;
+use std::ops::{Deref, DerefMut};
+use std::{fmt, io};
+
+/// The cells of a tape, used as a slice of them.
+pub struct Tape<C> {
+    storage: Storage<C>,
+}
+
+enum Storage<C> {
+    Owned(Vec<C>),
+    /// the cells are bytes, which is checked when it's mapped
+    #[cfg(feature = "mmap")]
+    Mapped(memmap2::MmapMut),
+}
+
+impl<C: Cell> Tape<C> {
+    /// `cells` cells, all of them zero.
+    pub fn blank(cells: usize) -> Self {
+        vec![C::zero(); cells].into()
+    }
+
+    /// Maps `file` as the tape, a cell for every byte of it, so every
+    /// change to the cells goes to the file. Only bytes can be kept there,
+    /// and the file has to be open for writing.
+    #[cfg(feature = "mmap")]
+    pub fn map(file: &std::fs::File) -> io::Result<Self> {
+        // SAFETY: the map is kept alive along with the tape. Other processes
+        // changing the file would change the cells under the program, which
+        // is the same hazard as for any other program mapping it.
+        let mut map = unsafe { memmap2::MmapMut::map_mut(file)? };
+        if C::from_bytes_mut(&mut map).is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "only tapes of bytes can be kept in a file",
+            ));
+        }
+        Ok(Self {
+            storage: Storage::Mapped(map),
+        })
+    }
+
+    /// Writes the cells out to the file the tape is mapped from, waiting
+    /// until they're there, to take a snapshot of them that outlives the
+    /// machine. Tapes in memory have nowhere to write them.
+    pub fn flush(&self) -> io::Result<()> {
+        match &self.storage {
+            Storage::Owned(_) => Ok(()),
+            #[cfg(feature = "mmap")]
+            Storage::Mapped(map) => map.flush(),
+        }
+    }
+
+    /// The cells, copied out of a mapped file if that's where they are.
+    pub fn into_vec(self) -> Vec<C> {
+        match self.storage {
+            Storage::Owned(cells) => cells,
+            #[cfg(feature = "mmap")]
+            Storage::Mapped(_) => self.to_vec(),
+        }
+    }
+}
+
+impl<C> From<Vec<C>> for Tape<C> {
+    fn from(cells: Vec<C>) -> Self {
+        Self {
+            storage: Storage::Owned(cells),
+        }
+    }
+}
+
+impl<C: Cell> Deref for Tape<C> {
+    type Target = [C];
+
+    fn deref(&self) -> &[C] {
+        match &self.storage {
+            Storage::Owned(cells) => cells,
+            #[cfg(feature = "mmap")]
+            Storage::Mapped(map) => C::from_bytes(map).expect("mapped tapes are of bytes"),
+        }
+    }
+}
+
+impl<C: Cell> DerefMut for Tape<C> {
+    fn deref_mut(&mut self) -> &mut [C] {
+        match &mut self.storage {
+            Storage::Owned(cells) => cells,
+            #[cfg(feature = "mmap")]
+            Storage::Mapped(map) => C::from_bytes_mut(map).expect("mapped tapes are of bytes"),
+        }
+    }
+}
+
+impl<C: Cell> fmt::Debug for Tape<C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tapes_in_memory() {
+        let mut tape = Tape::<i16>::blank(3);
+        tape[1] = -4;
+        assert_eq!(format!("{:?}", tape), "[0, -4, 0]");
+        assert!(tape.flush().is_ok());
+        assert_eq!(tape.into_vec(), [0, -4, 0]);
+        assert_eq!(Tape::from(vec![7u8])[..], [7]);
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn tapes_in_files() {
+        use crate::{Machine, Program};
+        use std::fs::OpenOptions;
+
+        let path = std::env::temp_dir().join(format!("bfrs-tape-{}", std::process::id()));
+        std::fs::write(&path, [1, 2, 0, 0]).unwrap();
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .unwrap();
+        assert!(Tape::<u16>::map(&file).is_err());
+
+        // the program starts from what the file has, and leaves its cells there
+        let mut machine = Machine::with_tape(Tape::<u8>::map(&file).unwrap());
+        let instructions = bfrs_common::parser::parse_str("[->>+<<]>>>+").unwrap();
+        let program = Program::from_instructions(instructions, 4);
+        machine
+            .run(&program, &mut std::io::empty(), &mut std::io::sink())
+            .unwrap();
+        machine.tape.flush().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), [0, 2, 1, 1]);
+        assert_eq!(machine.tape.into_vec(), [0, 2, 1, 1]);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,192 @@
+//! The tapes of dialects with more room than brainfuck's single one:
+//! the `tapes` extension's `~` switches to the next of several tapes,
+//! each with a pointer of its own, and the `grid` extension's `^` and
+//! `v` move the pointer up and down the rows of a grid of cells, which
+//! `<` and `>` move along. The pointer wraps around at every edge.
+//! ```
+//! use bfrs::tapes::Tapes;
+//! use bfrs_common::ext::{ExtCommand, Extensions};
+//! use bfrs_common::parser::parse_extended;
+//!
+//! let extensions = Extensions::builtin("grid").unwrap();
+//! let program: Vec<ExtCommand> = parse_extended("+v++>+++^".bytes().map(Ok), extensions)
+//!     .map(|res| res.unwrap().0)
+//!     .collect();
+//! let mut tapes = Tapes::<u8>::new(1, 9, 3);
+//! tapes.run(&program, &mut &b""[..], &mut Vec::new()).unwrap();
+//! assert_eq!(tapes.tapes[0], [1, 0, 0, 2, 3, 0, 0, 0, 0]);
+//! assert_eq!(tapes.pointers[0], 1);
+//! ```
+
+use crate::Cell;
+use bfrs_common::ext::ExtCommand;
+use bfrs_common::BFCommand;
+use std::io;
+
+/// Several tapes of cells laid out in rows, and a pointer on each of them.
+/// A single tape with a single row is brainfuck's.
+pub struct Tapes<C: Cell = u8> {
+    pub tapes: Vec<Vec<C>>,
+    /// the cell of every tape the pointer is at
+    pub pointers: Vec<usize>,
+    /// the tape the commands work on
+    pub current: usize,
+    /// the cells of every row
+    pub width: usize,
+}
+
+impl<C: Cell> Tapes<C> {
+    /// `count` blank tapes, in rows of `width` cells taking at least
+    /// `cells` of them, so every row is whole.
+    pub fn new(count: usize, cells: usize, width: usize) -> Self {
+        let width = width.max(1);
+        let cells = cells.max(1).div_ceil(width) * width;
+        Self {
+            tapes: vec![vec![C::zero(); cells]; count.max(1)],
+            pointers: vec![0; count.max(1)],
+            current: 0,
+            width,
+        }
+    }
+
+    /// Runs the program from the current state of the tapes, with
+    /// the same input and output as a [`Machine`](crate::Machine).
+    pub fn run(
+        &mut self,
+        instructions: &[ExtCommand],
+        input: &mut dyn io::Read,
+        output: &mut dyn io::Write,
+    ) -> io::Result<()> {
+        let jumps = jumps(instructions);
+        let mut instruction_i = 0;
+        while instruction_i < instructions.len() {
+            let zero = self.tapes[self.current][self.pointers[self.current]].is_zero();
+            match instructions[instruction_i] {
+                ExtCommand::Base(BFCommand::BeginLoop) if zero => {
+                    instruction_i = jumps[instruction_i];
+                }
+                ExtCommand::Base(BFCommand::EndLoop) if !zero => {
+                    instruction_i = jumps[instruction_i];
+                }
+                command => self.step(command, input, output)?,
+            }
+            instruction_i += 1;
+        }
+        output.flush()
+    }
+
+    /// Runs a command other than a bracket.
+    fn step(
+        &mut self,
+        command: ExtCommand,
+        input: &mut dyn io::Read,
+        output: &mut dyn io::Write,
+    ) -> io::Result<()> {
+        let tape = &mut self.tapes[self.current];
+        let pointer = &mut self.pointers[self.current];
+        let (cells, width) = (tape.len(), self.width);
+        let column = *pointer % width;
+        match command {
+            ExtCommand::Base(BFCommand::Increment) => tape[*pointer].add(1),
+            ExtCommand::Base(BFCommand::Decrement) => tape[*pointer].sub(1),
+            ExtCommand::Base(BFCommand::Right) => {
+                *pointer += (column + 1) % width;
+                *pointer -= column;
+            }
+            ExtCommand::Base(BFCommand::Left) => {
+                *pointer += (column + width - 1) % width;
+                *pointer -= column;
+            }
+            ExtCommand::Other(b'^') => *pointer = (*pointer + cells - width) % cells,
+            ExtCommand::Other(b'v') => *pointer = (*pointer + width) % cells,
+            ExtCommand::Other(b'~') => self.current = (self.current + 1) % self.tapes.len(),
+            ExtCommand::Base(BFCommand::Print) => output.write_all(&[tape[*pointer].to_byte()])?,
+            ExtCommand::Base(BFCommand::Read) => {
+                // whatever the program asks for has to be shown first.
+                output.flush()?;
+                let mut byte = [0];
+                tape[*pointer] = match input.read(&mut byte)? {
+                    0 => C::eof(),
+                    _ => C::from_byte(byte[0]),
+                };
+            }
+            // the brackets are run by `run`, and other extensions do nothing.
+            _ => (),
+        }
+        Ok(())
+    }
+}
+
+/// The index of the bracket matching every bracket of a program
+/// with balanced loops, and 0 for the rest.
+fn jumps(instructions: &[ExtCommand]) -> Vec<usize> {
+    let mut jumps = vec![0; instructions.len()];
+    let mut open = Vec::new();
+    for (i, instruction) in instructions.iter().enumerate() {
+        match instruction {
+            ExtCommand::Base(BFCommand::BeginLoop) => open.push(i),
+            ExtCommand::Base(BFCommand::EndLoop) => {
+                let start = open.pop().expect("the loops of a program are balanced");
+                jumps[start] = i;
+                jumps[i] = start;
+            }
+            _ => (),
+        }
+    }
+    jumps
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bfrs_common::ext::Extensions;
+    use bfrs_common::parser::parse_extended;
+
+    fn run(src: &str, extension: &str, tapes: &mut Tapes, input: &[u8]) -> Vec<u8> {
+        let extensions = Extensions::builtin(extension).unwrap();
+        let program: Vec<ExtCommand> = parse_extended(src.bytes().map(Ok), extensions)
+            .map(|res| res.unwrap().0)
+            .collect();
+        let mut output = Vec::new();
+        tapes.run(&program, &mut &input[..], &mut output).unwrap();
+        output
+    }
+
+    #[test]
+    fn rows_are_whole() {
+        let tapes = Tapes::<u8>::new(0, 10, 4);
+        assert_eq!(tapes.tapes, [[0; 12]]);
+        assert_eq!(tapes.pointers, [0]);
+        assert_eq!(Tapes::<u16>::new(3, 0, 0).tapes, [[0], [0], [0]]);
+    }
+
+    #[test]
+    fn several_tapes() {
+        let mut tapes = Tapes::new(3, 4, 4);
+        // every tape keeps its own pointer, and they start over after the last
+        let output = run("+>++~>>+++~,~.", "tapes", &mut tapes, b"x");
+        assert_eq!(output, b"\x02");
+        assert_eq!(tapes.tapes, [[1, 2, 0, 0], [0, 0, 3, 0], [b'x', 0, 0, 0]]);
+        assert_eq!(tapes.pointers, [1, 2, 0]);
+        assert_eq!(tapes.current, 0);
+    }
+
+    #[test]
+    fn grids_wrap_at_every_edge() {
+        let mut tapes = Tapes::new(1, 6, 3);
+        // left of the first column is the last one of the row, and
+        // above the first row is the last one
+        run("<+^++>>+++v>++++", "grid", &mut tapes, b"");
+        assert_eq!(tapes.tapes[0], [0, 0, 5, 0, 3, 2]);
+        assert_eq!(tapes.pointers[0], 2);
+    }
+
+    #[test]
+    fn loops_and_the_end_of_the_input() {
+        let mut tapes = Tapes::new(1, 9, 3);
+        // adds 3 to every cell of the column, and then reads past the input
+        let output = run("+++[-v+++v+++v]v.>,>,", "grid", &mut tapes, b"a");
+        assert_eq!(output, b"\x09");
+        assert_eq!(tapes.tapes[0], [0, 0, 0, 9, b'a', 255, 9, 0, 0]);
+    }
+}
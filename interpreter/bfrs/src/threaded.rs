@@ -0,0 +1,270 @@
+//! The core [`Machine::run`](crate::Machine::run) runs programs on when
+//! nothing watches them. Instructions are decoded up front into a dense
+//! array, every one with the function running it and its argument: the
+//! bracket it jumps to, or for a run of `+`, `-`, `<` or `>`, how long it
+//! is, which is run in one go. Every step is then a call through a pointer,
+//! like the computed gotos of threaded interpreters, instead of a match and
+//! a lookup of the jump. Programs aren't optimized otherwise, and run the
+//! same as stepping through them.
+
+use crate::{Cell, Program};
+use bfrs_common::BFCommand;
+use std::io;
+
+/// Where [`Step`]s go once something fails, past the end of any program.
+const STOP: usize = usize::MAX;
+
+/// Runs an instruction, given its index and its argument, returning
+/// the index of the next one, or [`STOP`].
+type Step<C> = fn(&mut Registers<C>, usize, usize) -> usize;
+
+/// An instruction, decoded. The ones in the middle of a run are skipped.
+struct Decoded<C> {
+    step: Step<C>,
+    /// the matching bracket of a bracket, the length of the run starting
+    /// at a `+`, `-`, `<` or `>`, and 0 for the rest
+    argument: usize,
+}
+
+/// What the steps work on.
+struct Registers<'a, C> {
+    tape: &'a mut [C],
+    pivot: usize,
+    input: &'a mut dyn io::Read,
+    output: &'a mut dyn io::Write,
+    /// why it stopped, if something failed
+    error: Option<io::Error>,
+}
+
+/// Runs the program on `tape` from the cell at `pivot`, leaving `pivot`
+/// where the pointer ends. `output` is only flushed before reading.
+pub(crate) fn run<C: Cell>(
+    target: &Program,
+    tape: &mut [C],
+    pivot: &mut usize,
+    input: &mut dyn io::Read,
+    output: &mut dyn io::Write,
+) -> io::Result<()> {
+    let code = decode::<C>(target);
+    let mut registers = Registers {
+        tape,
+        pivot: *pivot,
+        input,
+        output,
+        error: None,
+    };
+    let mut next = 0;
+    while let Some(decoded) = code.get(next) {
+        next = (decoded.step)(&mut registers, next, decoded.argument);
+    }
+    *pivot = registers.pivot;
+    match registers.error {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+fn decode<C: Cell>(target: &Program) -> Vec<Decoded<C>> {
+    let instructions = &target.code.instructions;
+    // how long the run starting at every instruction is, going from the
+    // end, and cells only take `u32` amounts at once.
+    let mut runs = vec![1; instructions.len()];
+    for i in (0..instructions.len().saturating_sub(1)).rev() {
+        if instructions[i] == instructions[i + 1] {
+            runs[i] = (runs[i + 1] + 1).min(u32::MAX as usize);
+        }
+    }
+    instructions
+        .iter()
+        .zip(runs)
+        .enumerate()
+        .map(|(i, (instruction, run))| {
+            // most runs are a single instruction, with steps of their own
+            // that don't pay for the length.
+            let step: Step<C> = match instruction {
+                BFCommand::Increment if run == 1 => increment_one,
+                BFCommand::Decrement if run == 1 => decrement_one,
+                BFCommand::Left if run == 1 => left_one,
+                BFCommand::Right if run == 1 => right_one,
+                BFCommand::Increment => increment,
+                BFCommand::Decrement => decrement,
+                BFCommand::Left => left,
+                BFCommand::Right => right,
+                BFCommand::BeginLoop => begin_loop,
+                BFCommand::EndLoop => end_loop,
+                BFCommand::Print => print,
+                BFCommand::Read => read,
+            };
+            let argument = match instruction {
+                BFCommand::BeginLoop | BFCommand::EndLoop => target.code.jump(i),
+                BFCommand::Print | BFCommand::Read => 0,
+                _ => run,
+            };
+            Decoded { step, argument }
+        })
+        .collect()
+}
+
+fn increment_one<C: Cell>(registers: &mut Registers<C>, i: usize, _: usize) -> usize {
+    registers.tape[registers.pivot].add(1);
+    i + 1
+}
+
+fn decrement_one<C: Cell>(registers: &mut Registers<C>, i: usize, _: usize) -> usize {
+    registers.tape[registers.pivot].sub(1);
+    i + 1
+}
+
+fn left_one<C: Cell>(registers: &mut Registers<C>, i: usize, _: usize) -> usize {
+    registers.pivot = match registers.pivot {
+        0 => registers.tape.len() - 1,
+        pivot => pivot - 1,
+    };
+    i + 1
+}
+
+fn right_one<C: Cell>(registers: &mut Registers<C>, i: usize, _: usize) -> usize {
+    registers.pivot += 1;
+    if registers.pivot == registers.tape.len() {
+        registers.pivot = 0;
+    }
+    i + 1
+}
+
+fn increment<C: Cell>(registers: &mut Registers<C>, i: usize, run: usize) -> usize {
+    registers.tape[registers.pivot].add(run as u32);
+    i + run
+}
+
+fn decrement<C: Cell>(registers: &mut Registers<C>, i: usize, run: usize) -> usize {
+    registers.tape[registers.pivot].sub(run as u32);
+    i + run
+}
+
+fn left<C: Cell>(registers: &mut Registers<C>, i: usize, run: usize) -> usize {
+    let cells = registers.tape.len();
+    registers.pivot = match registers.pivot.checked_sub(run) {
+        Some(pivot) => pivot,
+        None => (registers.pivot + cells - run % cells) % cells,
+    };
+    i + run
+}
+
+fn right<C: Cell>(registers: &mut Registers<C>, i: usize, run: usize) -> usize {
+    registers.pivot += run;
+    // dividing on every move would be slow.
+    if registers.pivot >= registers.tape.len() {
+        registers.pivot %= registers.tape.len();
+    }
+    i + run
+}
+
+fn begin_loop<C: Cell>(registers: &mut Registers<C>, i: usize, end: usize) -> usize {
+    if registers.tape[registers.pivot].is_zero() {
+        end + 1
+    } else {
+        i + 1
+    }
+}
+
+fn end_loop<C: Cell>(registers: &mut Registers<C>, i: usize, start: usize) -> usize {
+    if registers.tape[registers.pivot].is_zero() {
+        i + 1
+    } else {
+        start + 1
+    }
+}
+
+fn print<C: Cell>(registers: &mut Registers<C>, i: usize, _: usize) -> usize {
+    let byte = registers.tape[registers.pivot].to_byte();
+    match registers.output.write_all(&[byte]) {
+        Ok(()) => i + 1,
+        Err(e) => registers.fail(e),
+    }
+}
+
+fn read<C: Cell>(registers: &mut Registers<C>, i: usize, _: usize) -> usize {
+    // whatever the program asks for has to be shown first.
+    if let Err(e) = registers.output.flush() {
+        return registers.fail(e);
+    }
+    let mut byte = [0];
+    registers.tape[registers.pivot] = match registers.input.read(&mut byte) {
+        Ok(0) => C::eof(),
+        Ok(_) => C::from_byte(byte[0]),
+        Err(e) => return registers.fail(e),
+    };
+    i + 1
+}
+
+impl<C> Registers<'_, C> {
+    fn fail(&mut self, e: io::Error) -> usize {
+        self.error = Some(e);
+        STOP
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::{run_captured, RunOptions, RunResult};
+    use crate::Machine;
+    use bfrs_common::parser::parse_bytes;
+    use bfrs_testutil::Generator;
+
+    /// Runs `instructions` on the threaded core and a step at a time, checking
+    /// they print and leave the same, and returns what they printed.
+    fn run_both(instructions: Vec<BFCommand>, input: &[u8], cells: usize) -> Vec<u8> {
+        let program = Program::from_instructions(instructions, cells);
+        let stepped: RunResult = run_captured(&program, input, &RunOptions::default());
+        let mut machine = Machine::<u8>::new(cells);
+        let mut output = Vec::new();
+        machine.run(&program, &mut &*input, &mut output).unwrap();
+        assert_eq!(output, stepped.output);
+        assert_eq!(*machine.tape, *stepped.tape);
+        assert_eq!(machine.pivot, stepped.pointer);
+        output
+    }
+
+    fn run_source(source: &str, input: &[u8], cells: usize) -> Vec<u8> {
+        run_both(parse_bytes(source.as_bytes()).unwrap(), input, cells)
+    }
+
+    #[test]
+    fn runs() {
+        assert_eq!(run_source("+++.--.", b"", 10), [3, 1]);
+        // runs end at other instructions, and cells wrap.
+        assert_eq!(run_source("-.+++[-]--.", b"", 10), [255, 254]);
+        run_source(&"+".repeat(300), b"", 1);
+    }
+
+    #[test]
+    fn moves_wrap_around_the_tape() {
+        run_source("<+", b"", 5);
+        run_source("<<<<<<<<+", b"", 5);
+        run_source(">>>>>>>>>>>>+", b"", 5);
+        run_source(">>>+<<<<<<<<<<<<<<+", b"", 3);
+        run_source(&">".repeat(1000), b"", 7);
+    }
+
+    #[test]
+    fn loops_jump_to_their_brackets() {
+        assert_eq!(run_source("[.]+.", b"", 10), [1]);
+        assert_eq!(run_source("++[>+++[>++<-]<-]>>.", b"", 10), [12]);
+        assert_eq!(run_source("+[[-]]+[>]+.", b"", 10), [1]);
+    }
+
+    #[test]
+    fn reads_and_prints() {
+        assert_eq!(run_source(",.,+.,.", b"ab", 10), [b'a', b'c', 255]);
+    }
+
+    #[test]
+    fn random_programs() {
+        // without loops, random programs always end.
+        for seed in 0..200 {
+            let instructions = Generator::new(seed).length(300).max_depth(0).program();
+            run_both(instructions, b"some input", 8);
+        }
+    }
+}
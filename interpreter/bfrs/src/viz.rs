@@ -0,0 +1,539 @@
+//! Animations of the tape of a program as it runs, for talks and teaching.
+//!
+//! A [`Recorder`] takes a frame of the tape every few instructions while the
+//! program runs, and the frames are written as a GIF, with a square for
+//! every cell colored by its value and a mark below the pointer, as an
+//! asciinema cast, with the values themselves, or as a web page stepping
+//! through the frames with a slider, showing the instruction the program
+//! was at and what it had printed too. Since a program can run for
+//! billions of instructions, the recorder takes frames less often as they
+//! pile up, so the animation always covers the whole run.
+
+use crate::{Cell, ExecHooks, Machine};
+use bfrs_common::BFCommand;
+use std::io::{self, Write};
+use std::path::Path;
+
+/// The frames kept at most before keeping every other one.
+const MAX_FRAMES: usize = 500;
+/// The cells shown at most, from the first one.
+const MAX_CELLS: usize = 128;
+/// The bytes of the output kept at most, from the first one.
+const MAX_OUTPUT: usize = 1 << 16;
+
+/// The tape at some point of the run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    /// the cells shown, as the bytes they'd be printed as
+    pub cells: Vec<u8>,
+    pub pointer: usize,
+    /// the instruction about to run, unless the program is done
+    pub instruction: Option<usize>,
+    /// the bytes printed so far
+    pub printed: usize,
+}
+
+/// Takes a frame of the tape every `every` instructions, doubling
+/// `every` whenever the frames pile up.
+#[derive(Debug, Clone)]
+pub struct Recorder {
+    pub every: u64,
+    steps: u64,
+    /// the furthest cell the pointer got to
+    furthest: usize,
+    pub frames: Vec<Frame>,
+    /// what the program printed, up to [`MAX_OUTPUT`] bytes
+    pub output: Vec<u8>,
+    printed: usize,
+}
+
+impl Recorder {
+    pub fn new(every: u64) -> Self {
+        Self {
+            every: every.max(1),
+            steps: 0,
+            furthest: 0,
+            frames: Vec::new(),
+            output: Vec::new(),
+            printed: 0,
+        }
+    }
+
+    /// Takes the last frame, of the tape the program left.
+    pub fn finish<C: Cell>(&mut self, machine: &Machine<C>) {
+        self.record(None, machine);
+    }
+
+    fn record<C: Cell>(&mut self, instruction: Option<usize>, machine: &Machine<C>) {
+        self.furthest = self.furthest.max(machine.pivot);
+        let shown = (self.furthest + 1).min(MAX_CELLS).min(machine.tape.len());
+        self.frames.push(Frame {
+            cells: machine.tape[..shown].iter().map(Cell::to_byte).collect(),
+            pointer: machine.pivot,
+            instruction,
+            printed: self.printed,
+        });
+    }
+
+    /// The cells every frame shows, which is the most any of them does.
+    fn width(&self) -> usize {
+        let widest = self.frames.iter().map(|frame| frame.cells.len());
+        widest.max().unwrap_or(0).max(1)
+    }
+}
+
+impl<C: Cell> ExecHooks<C> for Recorder {
+    fn on_instruction(&mut self, index: usize, machine: &Machine<C>) {
+        self.furthest = self.furthest.max(machine.pivot);
+        if self.steps.is_multiple_of(self.every) {
+            self.record(Some(index), machine);
+            if self.frames.len() == MAX_FRAMES {
+                // the frames left are still every `every` instructions.
+                let kept = self.frames.drain(..).step_by(2).collect();
+                self.frames = kept;
+                self.every *= 2;
+            }
+        }
+        self.steps += 1;
+    }
+
+    fn on_output(&mut self, byte: u8) {
+        if self.output.len() < MAX_OUTPUT {
+            self.output.push(byte);
+        }
+        self.printed += 1;
+    }
+}
+
+/// What an animation is written as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VizFormat {
+    Gif,
+    /// an asciinema cast, version 2
+    Cast,
+    /// a web page of its own, without anything else to load
+    Html,
+}
+
+impl VizFormat {
+    /// The format of a file, by its extension.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "gif" => Some(Self::Gif),
+            "cast" => Some(Self::Cast),
+            "html" | "htm" => Some(Self::Html),
+            _ => None,
+        }
+    }
+}
+
+/// Writes the frames of `recorder` as an animation of the run
+/// of `instructions`.
+pub fn write(
+    recorder: &Recorder,
+    instructions: &[BFCommand],
+    format: VizFormat,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    match format {
+        VizFormat::Gif => write_gif(recorder, out),
+        VizFormat::Cast => write_cast(recorder, out),
+        VizFormat::Html => write_html(recorder, instructions, out),
+    }
+}
+
+/// The side of the square of every cell, in pixels.
+const CELL_SIZE: usize = 16;
+/// The height of the mark below the pointer, in pixels.
+const MARK_SIZE: usize = 4;
+/// The time every frame is shown, in hundredths of a second.
+const FRAME_DELAY: u16 = 4;
+/// The bits of the colors of the palette, which has 64.
+const COLOR_BITS: u8 = 6;
+/// The color of the background and of the mark below the pointer.
+const BACKGROUND: u8 = 0;
+const MARK: u8 = 1;
+
+/// The color of the palette a cell is shown with.
+fn color(value: u8) -> u8 {
+    let levels = (1 << COLOR_BITS) - 2;
+    2 + (usize::from(value) * (levels - 1) / 255) as u8
+}
+
+/// The palette: the background, the mark and then the values of the
+/// cells, from dark blue for 0, through red, to yellow for 255.
+fn palette() -> Vec<u8> {
+    let mut palette = vec![24, 24, 24, 255, 255, 255];
+    let levels = (1 << COLOR_BITS) - 2;
+    let stops = [
+        (20.0, 24.0, 72.0),
+        (220.0, 50.0, 50.0),
+        (255.0, 230.0, 80.0),
+    ];
+    for level in 0..levels {
+        let t = level as f64 / (levels - 1) as f64 * 2.0;
+        let (from, to) = if t < 1.0 {
+            (stops[0], stops[1])
+        } else {
+            (stops[1], stops[2])
+        };
+        let t = t - t.floor().min(1.0);
+        let mix = |a: f64, b: f64| (a + (b - a) * t).round() as u8;
+        palette.extend([mix(from.0, to.0), mix(from.1, to.1), mix(from.2, to.2)]);
+    }
+    palette
+}
+
+fn write_gif(recorder: &Recorder, out: &mut dyn Write) -> io::Result<()> {
+    let cells = recorder.width();
+    let width = (cells * CELL_SIZE) as u16;
+    let height = (CELL_SIZE + MARK_SIZE) as u16;
+    out.write_all(b"GIF89a")?;
+    out.write_all(&width.to_le_bytes())?;
+    out.write_all(&height.to_le_bytes())?;
+    // a global palette of 2^COLOR_BITS colors.
+    out.write_all(&[0xF0 | (COLOR_BITS - 1), BACKGROUND, 0])?;
+    out.write_all(&palette())?;
+    // loop forever.
+    out.write_all(b"\x21\xFF\x0BNETSCAPE2.0\x03\x01\x00\x00\x00")?;
+
+    for frame in recorder.frames.iter() {
+        out.write_all(&[0x21, 0xF9, 4, 0])?;
+        out.write_all(&FRAME_DELAY.to_le_bytes())?;
+        out.write_all(&[0, 0])?;
+        out.write_all(&[0x2C, 0, 0, 0, 0])?;
+        out.write_all(&width.to_le_bytes())?;
+        out.write_all(&height.to_le_bytes())?;
+        out.write_all(&[0])?;
+
+        let mut pixels = Vec::with_capacity(usize::from(width) * usize::from(height));
+        for y in 0..usize::from(height) {
+            for x in 0..usize::from(width) {
+                let cell = x / CELL_SIZE;
+                pixels.push(if y < CELL_SIZE {
+                    frame
+                        .cells
+                        .get(cell)
+                        .map_or(BACKGROUND, |&value| color(value))
+                } else if cell == frame.pointer {
+                    MARK
+                } else {
+                    BACKGROUND
+                });
+            }
+        }
+        write_lzw(&pixels, out)?;
+    }
+    out.write_all(&[0x3B])
+}
+
+/// Writes the pixels of an image as GIF data, without compressing them:
+/// every pixel is a code of its own, and the table of codes is cleared
+/// before its codes get any wider.
+fn write_lzw(pixels: &[u8], out: &mut dyn Write) -> io::Result<()> {
+    let clear = 1u16 << COLOR_BITS;
+    let end = clear + 1;
+    let width = COLOR_BITS + 1;
+    // every code after the first one since clearing adds to the table.
+    let run = usize::from(clear) - 3;
+
+    let mut bytes = Vec::new();
+    let (mut buffer, mut bits) = (0u32, 0u8);
+    let mut code = |code: u16| {
+        buffer |= u32::from(code) << bits;
+        bits += width;
+        while bits >= 8 {
+            bytes.push(buffer as u8);
+            buffer >>= 8;
+            bits -= 8;
+        }
+    };
+    for chunk in pixels.chunks(run) {
+        code(clear);
+        for &pixel in chunk {
+            code(u16::from(pixel));
+        }
+    }
+    code(end);
+    if bits > 0 {
+        bytes.push(buffer as u8);
+    }
+
+    out.write_all(&[COLOR_BITS])?;
+    for block in bytes.chunks(255) {
+        out.write_all(&[block.len() as u8])?;
+        out.write_all(block)?;
+    }
+    out.write_all(&[0])
+}
+
+/// The seconds every frame of a cast is shown.
+const CAST_DELAY: f64 = 0.04;
+
+fn write_cast(recorder: &Recorder, out: &mut dyn Write) -> io::Result<()> {
+    let cells = recorder.width();
+    // every cell takes 4 columns: a space and its value.
+    writeln!(
+        out,
+        r#"{{"version": 2, "width": {}, "height": 3}}"#,
+        cells * 4
+    )?;
+    for (i, frame) in recorder.frames.iter().enumerate() {
+        // from the top left corner of a clear screen.
+        let mut screen = String::from("\x1b[H\x1b[2J");
+        for &value in frame.cells.iter() {
+            // the grays of the 256 colors, with text that can be read on them.
+            let background = 232 + u16::from(value) * 23 / 255;
+            let text = if value > 127 { 30 } else { 97 };
+            screen += &format!("\x1b[48;5;{};{}m{:>4}", background, text, value);
+        }
+        screen += "\x1b[0m\r\n";
+        screen += &" ".repeat(frame.pointer.min(cells) * 4 + 3);
+        screen += "^";
+        writeln!(
+            out,
+            "[{:.2}, \"o\", \"{}\"]",
+            i as f64 * CAST_DELAY,
+            escape(&screen)
+        )?;
+    }
+    Ok(())
+}
+
+/// A string as the contents of a JSON string.
+fn escape(s: &str) -> String {
+    s.chars()
+        .map(|ch| match ch {
+            '"' => "\\\"".to_string(),
+            '\\' => "\\\\".to_string(),
+            ch if ch.is_control() => format!("\\u{:04x}", u32::from(ch)),
+            ch => ch.to_string(),
+        })
+        .collect()
+}
+
+/// The page of a run, whose script shows the frame the slider is at
+/// from the `DATA` it's given.
+const PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>bfrs run</title>
+<style>
+body { font-family: monospace; background: #181818; color: #ddd; margin: 2em; }
+pre { white-space: pre-wrap; word-break: break-all; background: #222; padding: 1em; }
+#cursor { background: #dc3232; color: #fff; }
+#tape { display: flex; flex-wrap: wrap; gap: 2px; margin: 1em 0; }
+#tape div { width: 3em; padding: 0.3em 0; text-align: center; background: #222; }
+#tape div.pointer { outline: 2px solid #ffe650; }
+input[type=range] { width: 100%; }
+</style>
+</head>
+<body>
+<div>
+<button id="previous">&lt;</button>
+<button id="play">play</button>
+<button id="next">&gt;</button>
+<span id="status"></span>
+</div>
+<input id="slider" type="range" min="0" value="0">
+<h3>program</h3>
+<pre id="program"><span id="before"></span><span id="cursor"></span><span id="after"></span></pre>
+<h3>tape</h3>
+<div id="tape"></div>
+<h3>output</h3>
+<pre id="output"></pre>
+<script>
+const DATA = /*DATA*/;
+const $ = (id) => document.getElementById(id);
+const slider = $("slider");
+slider.max = DATA.frames.length - 1;
+let playing = null;
+
+function show(n) {
+  const [instruction, pointer, printed, cells] = DATA.frames[n];
+  slider.value = n;
+  const at = instruction < 0 ? DATA.program.length : instruction;
+  $("before").textContent = DATA.program.slice(0, at);
+  $("cursor").textContent = DATA.program.slice(at, at + 1);
+  $("after").textContent = DATA.program.slice(at + 1);
+  $("status").textContent = "frame " + (n + 1) + " of " + DATA.frames.length + ", " +
+    (instruction < 0 ? "done" : "instruction " + instruction) + ", pointer at cell " + pointer;
+  const tape = $("tape");
+  tape.textContent = "";
+  cells.forEach((value, i) => {
+    const cell = document.createElement("div");
+    cell.textContent = value;
+    cell.title = "cell " + i;
+    if (i === pointer) cell.className = "pointer";
+    tape.appendChild(cell);
+  });
+  const shown = DATA.output.slice(0, printed);
+  $("output").textContent = String.fromCharCode(...shown) +
+    (printed > DATA.output.length ? "\n[" + (printed - DATA.output.length) + " more bytes]" : "");
+}
+
+function step(by) {
+  show(Math.max(0, Math.min(DATA.frames.length - 1, Number(slider.value) + by)));
+}
+
+slider.oninput = () => show(Number(slider.value));
+$("previous").onclick = () => step(-1);
+$("next").onclick = () => step(1);
+$("play").onclick = () => {
+  if (playing) {
+    clearInterval(playing);
+    playing = null;
+  } else {
+    playing = setInterval(() => {
+      if (Number(slider.value) >= DATA.frames.length - 1) $("play").click();
+      else step(1);
+    }, 100);
+  }
+  $("play").textContent = playing ? "pause" : "play";
+};
+document.onkeydown = (e) => {
+  if (e.key === "ArrowLeft") step(-1);
+  if (e.key === "ArrowRight") step(1);
+};
+show(0);
+</script>
+</body>
+</html>
+"#;
+
+fn write_html(
+    recorder: &Recorder,
+    instructions: &[BFCommand],
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    let program: String = instructions.iter().map(|i| i.to_string()).collect();
+    let list = |values: &mut dyn Iterator<Item = String>| values.collect::<Vec<_>>().join(",");
+    // every frame is `[instruction, pointer, printed, [cells]]`, with
+    // the instruction -1 once the program is done. The program is only
+    // commands and the rest numbers, which need no escaping.
+    let frames = list(&mut recorder.frames.iter().map(|frame| {
+        format!(
+            "[{},{},{},[{}]]",
+            frame.instruction.map_or(-1, |i| i as i64),
+            frame.pointer,
+            frame.printed,
+            list(&mut frame.cells.iter().map(u8::to_string))
+        )
+    }));
+    let output = list(&mut recorder.output.iter().map(u8::to_string));
+    let data = format!(
+        r#"{{"program":"{}","frames":[{}],"output":[{}]}}"#,
+        program, frames, output
+    );
+    out.write_all(PAGE.replace("/*DATA*/", &data).as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Program;
+    use bfrs_common::parser;
+
+    fn record(src: &str, every: u64) -> Recorder {
+        let program = Program::from_instructions(parser::parse_str(src).unwrap(), 8);
+        let mut machine = Machine::<u8>::new(program.tape_size);
+        let mut recorder = Recorder::new(every);
+        machine
+            .run_with_hooks(&program, &mut io::empty(), &mut io::sink(), &mut recorder)
+            .unwrap();
+        recorder.finish(&machine);
+        recorder
+    }
+
+    #[test]
+    fn frames_are_taken_every_few_instructions() {
+        let recorder = record("+>+.>+", 2);
+        let frames: Vec<_> = recorder
+            .frames
+            .iter()
+            .map(|frame| (frame.instruction, frame.pointer, frame.printed))
+            .collect();
+        assert_eq!(
+            frames,
+            [
+                (Some(0), 0, 0),
+                (Some(2), 1, 0),
+                (Some(4), 1, 1),
+                (None, 2, 1)
+            ]
+        );
+        // the cells the pointer got to, and no more
+        assert_eq!(recorder.frames[0].cells, [0]);
+        assert_eq!(recorder.frames[3].cells, [1, 1, 1]);
+        assert_eq!(recorder.output, [1]);
+    }
+
+    #[test]
+    fn frames_are_taken_less_often_as_they_pile_up() {
+        // runs a few thousand instructions
+        let recorder = record("++++++++[>++++++++[>++++<-]<-]", 1);
+        assert!(recorder.every > 1);
+        assert!(recorder.frames.len() < MAX_FRAMES);
+        assert!(recorder.frames.len() >= MAX_FRAMES / 4);
+    }
+
+    #[test]
+    fn animations_are_written_as_their_format() {
+        let recorder = record("+>++", 1);
+        assert_eq!(
+            VizFormat::from_path(Path::new("run.gif")),
+            Some(VizFormat::Gif)
+        );
+        assert_eq!(
+            VizFormat::from_path(Path::new("run.cast")),
+            Some(VizFormat::Cast)
+        );
+        assert_eq!(VizFormat::from_path(Path::new("run.png")), None);
+
+        let mut gif = Vec::new();
+        write(&recorder, &[], VizFormat::Gif, &mut gif).unwrap();
+        assert!(gif.starts_with(b"GIF89a"));
+        // two cells wide, and as high as a cell and the mark below it
+        assert_eq!(gif[6..10], [32, 0, 20, 0]);
+        assert_eq!(gif.last(), Some(&0x3B));
+        // every frame has its own image
+        let images = gif.windows(4).filter(|w| w == &[0x21, 0xF9, 4, 0]);
+        assert_eq!(images.count(), recorder.frames.len());
+
+        let mut cast = Vec::new();
+        write(&recorder, &[], VizFormat::Cast, &mut cast).unwrap();
+        let cast = String::from_utf8(cast).unwrap();
+        let lines: Vec<_> = cast.lines().collect();
+        assert_eq!(lines[0], r#"{"version": 2, "width": 8, "height": 3}"#);
+        assert_eq!(lines.len(), recorder.frames.len() + 1);
+        assert!(lines[1].starts_with(r#"[0.00, "o", "\u001b[H\u001b[2J"#));
+        // the last frame, with the pointer under the second cell
+        let last = r#"[0.16, "o", "\u001b[H\u001b[2J\u001b[48;5;232;97m   1\u001b[48;5;232;97m   2\u001b[0m\u000d\u000a       ^"]"#;
+        assert_eq!(lines[5], last);
+    }
+
+    #[test]
+    fn colors_span_the_palette() {
+        assert_eq!(color(0), 2);
+        assert_eq!(color(255), (1 << COLOR_BITS) - 1);
+        assert_eq!(palette().len(), 3 << COLOR_BITS);
+    }
+
+    #[test]
+    fn pages_step_through_the_run() {
+        let instructions = parser::parse_str("+.>+").unwrap();
+        let recorder = record("+.>+", 2);
+        assert_eq!(
+            VizFormat::from_path(Path::new("run.htm")),
+            Some(VizFormat::Html)
+        );
+        let mut html = Vec::new();
+        write(&recorder, &instructions, VizFormat::Html, &mut html).unwrap();
+        let html = String::from_utf8(html).unwrap();
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(!html.contains("/*DATA*/"));
+        let data = r#"const DATA = {"program":"+.>+","frames":[[0,0,0,[0]],[2,0,1,[1]],[-1,1,1,[1,1]]],"output":[1]};"#;
+        assert!(html.contains(data), "{}", html);
+    }
+}
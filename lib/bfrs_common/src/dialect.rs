@@ -0,0 +1,369 @@
+//! Trivial substitutions of brainfuck, where every command is
+//! spelled with some other token.
+//!
+//! A space in a token matches any amount of whitespace, so the
+//! two words of an Ook! command can be on different lines.
+//!
+//! Mapping files hold one command per line, followed by its token,
+//! and lines starting with `#` are comments:
+//! ```text
+//! # a more verbose brainfuck
+//! > right
+//! < left
+//! + inc
+//! - dec
+//! . out
+//! , in
+//! [ loop
+//! ] end
+//! ```
+
+use super::errors::{ErrorCode, ErrorWithPosition};
+use super::{BFCommand, Position};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+
+/// The commands in the order [`Dialect::from_symbols`] takes their tokens.
+pub const ORDER: [BFCommand; 8] = [
+    BFCommand::Right,
+    BFCommand::Left,
+    BFCommand::Increment,
+    BFCommand::Decrement,
+    BFCommand::Print,
+    BFCommand::Read,
+    BFCommand::BeginLoop,
+    BFCommand::EndLoop,
+];
+
+/// The tokens every command is spelled with.
+#[derive(Debug, Clone)]
+pub struct Dialect {
+    /// longest first, so a token that's a prefix of
+    /// another one doesn't take its place.
+    tokens: Vec<(Vec<u8>, BFCommand)>,
+    /// the command of every byte, when all the tokens are a single byte
+    single_bytes: Option<[Option<BFCommand>; 256]>,
+}
+
+impl Default for Dialect {
+    fn default() -> Self {
+        Self::brainfuck()
+    }
+}
+
+impl Dialect {
+    /// A dialect spelling the commands with the given tokens.
+    pub fn new(
+        tokens: impl IntoIterator<Item = (String, BFCommand)>,
+    ) -> Result<Self, DialectError> {
+        let mut tokens: Vec<(Vec<u8>, BFCommand)> = tokens
+            .into_iter()
+            .map(|(token, command)| (normalize(&token).into_bytes(), command))
+            .collect();
+        for (i, (token, _)) in tokens.iter().enumerate() {
+            if token.is_empty() {
+                return Err(DialectError::EmptyToken);
+            }
+            if tokens[..i].iter().any(|(other, _)| other == token) {
+                return Err(DialectError::DuplicateToken {
+                    token: String::from_utf8_lossy(token).into_owned(),
+                });
+            }
+        }
+        tokens.sort_by_key(|(token, _)| core::cmp::Reverse(token.len()));
+        let single_bytes = if tokens.iter().all(|(token, _)| token.len() == 1) {
+            let mut single_bytes = [None; 256];
+            for (token, command) in tokens.iter() {
+                single_bytes[token[0] as usize] = Some(*command);
+            }
+            Some(single_bytes)
+        } else {
+            None
+        };
+        Ok(Self {
+            tokens,
+            single_bytes,
+        })
+    }
+
+    /// A dialect with a token for every command, in the order of [`ORDER`]:
+    /// `> < + - . , [ ]`.
+    pub fn from_symbols(symbols: [&str; 8]) -> Result<Self, DialectError> {
+        Self::new(
+            symbols
+                .iter()
+                .zip(ORDER.iter())
+                .map(|(symbol, &command)| (symbol.to_string(), command)),
+        )
+    }
+
+    /// Plain brainfuck.
+    pub fn brainfuck() -> Self {
+        Self::from_symbols([">", "<", "+", "-", ".", ",", "[", "]"])
+            .expect("brainfuck is a valid dialect")
+    }
+
+    /// [Ook!](https://esolangs.org/wiki/Ook!), where every
+    /// command is two of `Ook.`, `Ook?` and `Ook!`.
+    pub fn ook() -> Self {
+        Self::two_words("Ook")
+    }
+
+    /// [Blub](https://esolangs.org/wiki/Blub), which is
+    /// Ook! with `Blub` instead of `Ook`.
+    pub fn blub() -> Self {
+        Self::two_words("Blub")
+    }
+
+    fn two_words(word: &str) -> Self {
+        let marks = [
+            ('.', '?'),
+            ('?', '.'),
+            ('.', '.'),
+            ('!', '!'),
+            ('!', '.'),
+            ('.', '!'),
+            ('!', '?'),
+            ('?', '!'),
+        ];
+        Self::new(
+            marks
+                .iter()
+                .zip(ORDER.iter())
+                .map(|(&(first, second), &command)| {
+                    (format!("{}{} {}{}", word, first, word, second), command)
+                }),
+        )
+        .expect("two word dialects are valid")
+    }
+
+    /// A built-in dialect by name: `brainfuck` (or `bf`), `ook` or `blub`.
+    pub fn builtin(name: &str) -> Option<Self> {
+        Some(match name {
+            "brainfuck" | "bf" => Self::brainfuck(),
+            "ook" => Self::ook(),
+            "blub" => Self::blub(),
+            _ => return None,
+        })
+    }
+
+    /// Parses a mapping file.
+    pub fn parse_mapping(src: &str) -> Result<Self, ErrorWithPosition<DialectError>> {
+        let mut tokens: Vec<(String, BFCommand)> = Vec::new();
+        for (line_i, line) in src.lines().enumerate() {
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue;
+            }
+            let position = Position {
+                line: line_i + 1,
+                column: line.chars().take_while(|ch| ch.is_whitespace()).count() + 1,
+            };
+            let error = |kind| ErrorWithPosition { kind, position };
+            let mut chars = trimmed.chars();
+            let command = chars.next().unwrap();
+            let command = match command
+                .is_ascii()
+                .then(|| BFCommand::from_u8(command as u8))
+            {
+                Some(Some(command)) => command,
+                _ => return Err(error(DialectError::UnknownCommand { command })),
+            };
+            let token = chars.as_str().trim();
+            if token.is_empty() {
+                return Err(error(DialectError::EmptyToken));
+            }
+            if tokens
+                .iter()
+                .any(|(other, _)| normalize(other) == normalize(token))
+            {
+                return Err(error(DialectError::DuplicateToken {
+                    token: token.to_string(),
+                }));
+            }
+            tokens.push((token.to_string(), command));
+        }
+        Self::new(tokens).map_err(|kind| ErrorWithPosition {
+            kind,
+            position: Position::default(),
+        })
+    }
+
+    /// The token a command is spelled with, with a single space
+    /// wherever it takes whitespace, or `None` if it has none.
+    pub fn token(&self, command: BFCommand) -> Option<&str> {
+        self.tokens
+            .iter()
+            .find(|&&(_, other)| other == command)
+            .and_then(|(token, _)| core::str::from_utf8(token).ok())
+    }
+
+    /// Whether every token is a single byte, so they can be
+    /// written one after the other without being mixed up.
+    pub fn is_single_byte(&self) -> bool {
+        self.single_bytes.is_some()
+    }
+
+    /// The command whose token `bytes` start with, along with how many bytes
+    /// it covers. `bytes` are asked for one at a time, from the first one,
+    /// and `None` is the end of the input.
+    pub fn match_token<E>(
+        &self,
+        mut bytes: impl FnMut(usize) -> Result<Option<u8>, E>,
+    ) -> Result<Option<(BFCommand, usize)>, E> {
+        if let Some(single_bytes) = &self.single_bytes {
+            return Ok(
+                bytes(0)?.and_then(|byte| single_bytes[byte as usize].map(|command| (command, 1)))
+            );
+        }
+        let first = match bytes(0)? {
+            Some(byte) => byte,
+            None => return Ok(None),
+        };
+        'tokens: for (token, command) in self.tokens.iter().filter(|(token, _)| token[0] == first) {
+            let mut len = 0;
+            for &expected in token.iter() {
+                if expected == b' ' {
+                    // any amount of whitespace, but at least some.
+                    if !bytes(len)?.is_some_and(|byte| byte.is_ascii_whitespace()) {
+                        continue 'tokens;
+                    }
+                    while bytes(len)?.is_some_and(|byte| byte.is_ascii_whitespace()) {
+                        len += 1;
+                    }
+                } else if bytes(len)? == Some(expected) {
+                    len += 1;
+                } else {
+                    continue 'tokens;
+                }
+            }
+            return Ok(Some((*command, len)));
+        }
+        Ok(None)
+    }
+}
+
+/// Collapses whitespace in a token to a single space, which matches any amount of it.
+fn normalize(token: &str) -> String {
+    token.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[derive(Debug)]
+pub enum DialectError {
+    /// a mapping line that doesn't start with one of `><+-.,[]`
+    UnknownCommand {
+        command: char,
+    },
+    EmptyToken,
+    DuplicateToken {
+        token: String,
+    },
+    /// a command that can't be written, since the dialect doesn't spell it
+    MissingToken {
+        command: BFCommand,
+    },
+}
+
+impl fmt::Display for DialectError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::UnknownCommand { command } => {
+                write!(f, "Expected one of `><+-.,[]`, found {:?}", command)
+            }
+            Self::EmptyToken => write!(f, "Expected a token for the command"),
+            Self::DuplicateToken { token } => {
+                write!(f, "Token {:?} is used for more than one command", token)
+            }
+            Self::MissingToken { command } => {
+                write!(f, "The dialect has no token for `{}`", command)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for DialectError {}
+
+impl ErrorCode for DialectError {
+    fn code(&self) -> &'static str {
+        match self {
+            Self::UnknownCommand { .. } => "BF0101",
+            Self::EmptyToken => "BF0102",
+            Self::DuplicateToken { .. } => "BF0103",
+            Self::MissingToken { .. } => "BF0104",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The commands `dialect` finds in `src`, skipping what isn't a token.
+    fn commands(dialect: &Dialect, src: &str) -> String {
+        let src = src.as_bytes();
+        let mut found = String::new();
+        let mut i = 0;
+        while i < src.len() {
+            let token = dialect.match_token(|n| Ok::<_, ()>(src.get(i + n).copied()));
+            match token.unwrap() {
+                Some((command, len)) => {
+                    found.push(command as u8 as char);
+                    i += len;
+                }
+                None => i += 1,
+            }
+        }
+        found
+    }
+
+    #[test]
+    fn tokens_of_the_builtin_dialects() {
+        assert_eq!(commands(&Dialect::brainfuck(), "a+[-]b"), "+[-]");
+        let ook = Dialect::ook();
+        assert!(!ook.is_single_byte());
+        assert_eq!(ook.token(BFCommand::Right), Some("Ook. Ook?"));
+        // the words may be apart, even on different lines
+        assert_eq!(commands(&ook, "Ook. Ook.  Ook!\n Ook. Ook!Ook?"), "+.");
+        assert_eq!(commands(&Dialect::blub(), "Blub! Blub?Blub? Blub!"), "[]");
+        assert!(Dialect::builtin("bf").unwrap().is_single_byte());
+        assert!(Dialect::builtin("lolcode").is_none());
+    }
+
+    #[test]
+    fn longer_tokens_come_first() {
+        let dialect = Dialect::new(
+            [
+                ("a".to_string(), BFCommand::Increment),
+                ("ab".to_string(), BFCommand::Decrement),
+            ]
+            .iter()
+            .cloned(),
+        )
+        .unwrap();
+        assert_eq!(commands(&dialect, "aab"), "+-");
+    }
+
+    #[test]
+    fn mappings() {
+        let dialect = Dialect::parse_mapping("# verbose\n> right\n+   inc\n\n[ loop\n").unwrap();
+        assert_eq!(dialect.token(BFCommand::Increment), Some("inc"));
+        assert_eq!(dialect.token(BFCommand::Print), None);
+        assert_eq!(commands(&dialect, "inc right loop"), "+>[");
+
+        let error = |src| Dialect::parse_mapping(src).expect_err(src);
+        let unknown = error("+ inc\n  x ex");
+        assert!(matches!(
+            unknown.kind,
+            DialectError::UnknownCommand { command: 'x' }
+        ));
+        assert_eq!(unknown.position, Position { line: 2, column: 3 });
+        assert!(matches!(error("+").kind, DialectError::EmptyToken));
+        assert!(matches!(
+            error("+ a  b\n- a b").kind,
+            DialectError::DuplicateToken { token } if token == "a b"
+        ));
+    }
+}
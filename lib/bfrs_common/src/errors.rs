@@ -1,6 +1,21 @@
-use super::Position;
+//! Errors found at some position of a source, and how to report them.
+//!
+//! Every kind of error has a stable code, so tools can tell them apart
+//! without looking at the messages, which may change:
+//! - `BF00xx`: brainfuck sources, like unmatched brackets
+//! - `BF01xx`: dialect mapping files
+//! - `BF02xx`: patterns, rewrite rules, libraries and specs
+//! - `BF09xx`: reading files
+
+use super::{Location, Position, PositionConfig};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+use core::iter::FromIterator;
+use core::str::FromStr;
+#[cfg(feature = "std")]
 use std::error::Error;
-use std::fmt;
 
 #[derive(Debug)]
 pub struct ErrorWithPosition<K> {
@@ -8,6 +23,7 @@ pub struct ErrorWithPosition<K> {
     pub position: Position,
 }
 
+#[cfg(feature = "std")]
 impl<K> Error for ErrorWithPosition<K>
 where
     K: Error + 'static,
@@ -25,3 +41,670 @@ where
         write!(f, "{}: {}", self.position, self.kind)
     }
 }
+
+/// An error about part of a source, from `start` up to right before `end`,
+/// so the whole thing can be pointed at. Errors about something that's
+/// missing start right where they end.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ErrorWithSpan<K> {
+    pub kind: K,
+    pub start: Location,
+    pub end: Location,
+}
+
+impl<K> ErrorWithSpan<K> {
+    pub fn new(kind: K, start: Location, end: Location) -> Self {
+        Self { kind, start, end }
+    }
+
+    /// An error about something missing at `location`.
+    pub fn at(kind: K, location: Location) -> Self {
+        Self::new(kind, location, location)
+    }
+
+    /// Where the error starts.
+    #[inline]
+    pub fn position(&self) -> Position {
+        self.start.position
+    }
+}
+
+#[cfg(feature = "std")]
+impl<K> Error for ErrorWithSpan<K>
+where
+    K: Error + 'static,
+{
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.kind)
+    }
+}
+
+impl<K> fmt::Display for ErrorWithSpan<K>
+where
+    K: fmt::Display,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.start.position, self.kind)
+    }
+}
+
+/// The stable code of an error, like `BF0001`.
+pub trait ErrorCode {
+    fn code(&self) -> &'static str;
+}
+
+/// The code of errors reading files.
+pub const IO_ERROR: &str = "BF0901";
+
+impl<K> ErrorCode for ErrorWithPosition<K>
+where
+    K: ErrorCode,
+{
+    fn code(&self) -> &'static str {
+        self.kind.code()
+    }
+}
+
+impl<K> ErrorCode for ErrorWithSpan<K>
+where
+    K: ErrorCode,
+{
+    fn code(&self) -> &'static str {
+        self.kind.code()
+    }
+}
+
+/// How bad a diagnostic is. Errors are worse than warnings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    /// something that's likely wrong, but doesn't stop anything
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Warning => "warning",
+            Self::Error => "error",
+        })
+    }
+}
+
+/// An error or a warning as it's reported, along with where it was found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// none for errors without an [`ErrorCode`]
+    pub code: Option<&'static str>,
+    pub message: String,
+    pub file: Option<String>,
+    pub position: Option<Position>,
+    /// where the part of the source the error is about ends,
+    /// right after it, when it's more than a position
+    pub end: Option<Position>,
+    /// other places related to the error, like where
+    /// an unclosed loop was opened
+    pub labels: Vec<Label>,
+}
+
+/// A place related to an error, other than where it was found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Label {
+    pub position: Position,
+    pub message: String,
+}
+
+impl Diagnostic {
+    /// An error.
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Error,
+            code: None,
+            message: message.into(),
+            file: None,
+            position: None,
+            end: None,
+            labels: Vec::new(),
+        }
+    }
+
+    /// A diagnostic for an error with a code, at its position.
+    pub fn from_error<K>(e: &ErrorWithPosition<K>) -> Self
+    where
+        K: ErrorCode + fmt::Display,
+    {
+        Self::new(e.kind.to_string())
+            .with_code(e.code())
+            .at(e.position)
+    }
+
+    /// A diagnostic for an error with a code, covering its span.
+    pub fn from_span_error<K>(e: &ErrorWithSpan<K>) -> Self
+    where
+        K: ErrorCode + fmt::Display,
+    {
+        Self::new(e.kind.to_string())
+            .with_code(e.code())
+            .at(e.start.position)
+            .with_end(e.end.position)
+    }
+
+    pub fn with_severity(self, severity: Severity) -> Self {
+        Self { severity, ..self }
+    }
+
+    pub fn with_code(self, code: &'static str) -> Self {
+        Self {
+            code: Some(code),
+            ..self
+        }
+    }
+
+    pub fn at(self, position: Position) -> Self {
+        Self {
+            position: Some(position),
+            ..self
+        }
+    }
+
+    /// Makes the diagnostic cover the source up to `end`, when it isn't
+    /// where it starts.
+    pub fn with_end(self, end: Position) -> Self {
+        Self {
+            end: (Some(end) != self.position).then_some(end),
+            ..self
+        }
+    }
+
+    pub fn in_file(self, file: impl Into<String>) -> Self {
+        Self {
+            file: Some(file.into()),
+            ..self
+        }
+    }
+
+    pub fn with_label(mut self, position: Position, message: impl Into<String>) -> Self {
+        self.labels.push(Label {
+            position,
+            message: message.into(),
+        });
+        self
+    }
+
+    /// The diagnostic as a JSON object, in a single line:
+    /// `{"severity":"error","code":"BF0001","message":"...","file":"a.bf","line":1,"column":3,"end_line":1,"end_column":4,"labels":[]}`,
+    /// where every label is `{"line":1,"column":1,"message":"..."}`.
+    /// The end is the position itself when the diagnostic has no end,
+    /// and whatever isn't known is `null`.
+    pub fn to_json(&self) -> String {
+        let string = |value: Option<&str>| value.map_or("null".to_string(), json_string);
+        let number = |value: Option<usize>| value.map_or("null".to_string(), |n| n.to_string());
+        let end = self.end.or(self.position);
+        let labels: Vec<_> = self
+            .labels
+            .iter()
+            .map(|label| {
+                format!(
+                    r#"{{"line":{},"column":{},"message":{}}}"#,
+                    label.position.line,
+                    label.position.column,
+                    json_string(&label.message)
+                )
+            })
+            .collect();
+        format!(
+            r#"{{"severity":"{}","code":{},"message":{},"file":{},"line":{},"column":{},"end_line":{},"end_column":{},"labels":[{}]}}"#,
+            self.severity,
+            string(self.code),
+            json_string(&self.message),
+            string(self.file.as_deref()),
+            number(self.position.map(|position| position.line)),
+            number(self.position.map(|position| position.column)),
+            number(end.map(|position| position.line)),
+            number(end.map(|position| position.column)),
+            labels.join(","),
+        )
+    }
+
+    /// The diagnostic along with the lines of `source` it points at, with
+    /// carets under the error and a dash under every label:
+    /// ```text
+    /// error[BF0002]: Unclosed loop: last opening was found at 1:2
+    ///  --> a.bf:2:2
+    ///   |
+    /// 1 | +[>
+    ///   |  - the loop was opened here
+    /// 2 | <
+    ///   |  ^
+    /// ```
+    /// The positions are counted the way `config` says, as the parser did.
+    pub fn render(&self, source: &[u8], config: &PositionConfig) -> String {
+        let mut rendered = self.severity.to_string();
+        if let Some(code) = self.code {
+            rendered += &format!("[{}]", code);
+        }
+        rendered += &format!(": {}\n", self.message);
+        let position = match self.position {
+            Some(position) => position,
+            None => {
+                if let Some(file) = &self.file {
+                    rendered += &format!(" --> {}\n", file);
+                }
+                return rendered;
+            }
+        };
+        match &self.file {
+            Some(file) => rendered += &format!(" --> {}:{}\n", file, position),
+            None => rendered += &format!(" --> {}\n", position),
+        }
+
+        // the caret goes first, then the labels in the order they are in the source.
+        let mut marks = Vec::with_capacity(self.labels.len() + 1);
+        marks.push((position, self.end, '^', ""));
+        let mut labels: Vec<_> = self.labels.iter().collect();
+        labels.sort_by_key(|label| (label.position.line, label.position.column));
+        marks.extend(
+            labels
+                .into_iter()
+                .map(|label| (label.position, None, '-', label.message.as_str())),
+        );
+        let mut lines: Vec<_> = marks.iter().map(|(position, ..)| position.line).collect();
+        lines.sort_unstable();
+        lines.dedup();
+
+        let first = Position::start(config);
+        let width = lines.last().map_or(1, |line| line.to_string().len());
+        let source_lines: Vec<_> = source.split(|&byte| byte == b'\n').collect();
+        rendered += &format!("{:width$} |\n", "", width = width);
+        for line in lines {
+            let text = line
+                .checked_sub(first.line)
+                .and_then(|i| source_lines.get(i))
+                .map_or_else(String::new, |bytes| expand_tabs(bytes, config));
+            let numbered = format!("{:>width$} | {}", line, text, width = width);
+            rendered += numbered.trim_end();
+            rendered.push('\n');
+            for &(position, end, mark, message) in marks.iter().filter(|(p, ..)| p.line == line) {
+                let offset = position.column.saturating_sub(first.column);
+                // spans going on to other lines are marked up to the end of this one.
+                let len = match end {
+                    Some(end) if end.line == line => end.column.saturating_sub(position.column),
+                    Some(end) if end.line > line => text.chars().count().saturating_sub(offset),
+                    _ => 1,
+                };
+                let marks: String = core::iter::repeat_n(mark, len.max(1)).collect();
+                let marker = format!("{:offset$}{}", "", marks, offset = offset);
+                let marker = if message.is_empty() {
+                    marker
+                } else {
+                    format!("{} {}", marker, message)
+                };
+                rendered += &format!("{:width$} | {}\n", "", marker, width = width);
+            }
+        }
+        rendered
+    }
+}
+
+/// A line of source as it's shown, with every character
+/// in the column the parser counted for it.
+fn expand_tabs(line: &[u8], config: &PositionConfig) -> String {
+    let mut text = String::new();
+    let mut position = Position::start(config);
+    for ch in String::from_utf8_lossy(line).chars() {
+        let column = position.column;
+        position.advance_char_with(ch, config);
+        match ch {
+            '\t' => text.extend(core::iter::repeat_n(' ', position.column - column)),
+            '\r' => (),
+            ch => text.push(ch),
+        }
+    }
+    text
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}:", file)?;
+        }
+        if let Some(position) = self.position {
+            write!(f, "{}:", position)?;
+        }
+        if self.file.is_some() || self.position.is_some() {
+            write!(f, " ")?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+/// The errors and warnings found along the way, instead of
+/// stopping at the first one, in the order they were found.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Diagnostics(Vec<Diagnostic>);
+
+impl Diagnostics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, diagnostic: Diagnostic) {
+        self.0.push(diagnostic)
+    }
+
+    pub fn iter(&self) -> core::slice::Iter<'_, Diagnostic> {
+        self.0.iter()
+    }
+
+    pub fn errors(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.with_severity(Severity::Error)
+    }
+
+    pub fn warnings(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.with_severity(Severity::Warning)
+    }
+
+    fn with_severity(&self, severity: Severity) -> impl Iterator<Item = &Diagnostic> {
+        self.0
+            .iter()
+            .filter(move |diagnostic| diagnostic.severity == severity)
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.errors().next().is_some()
+    }
+
+    /// The worst severity of all, if there's any diagnostic.
+    pub fn severity(&self) -> Option<Severity> {
+        self.0.iter().map(|diagnostic| diagnostic.severity).max()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// `value`, as long as there are no errors. Warnings don't count.
+    pub fn into_result<T>(self, value: T) -> Result<T, Self> {
+        if self.has_errors() {
+            Err(self)
+        } else {
+            Ok(value)
+        }
+    }
+
+    pub fn into_vec(self) -> Vec<Diagnostic> {
+        self.0
+    }
+}
+
+impl From<Vec<Diagnostic>> for Diagnostics {
+    fn from(diagnostics: Vec<Diagnostic>) -> Self {
+        Self(diagnostics)
+    }
+}
+
+impl FromIterator<Diagnostic> for Diagnostics {
+    fn from_iter<I: IntoIterator<Item = Diagnostic>>(iter: I) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
+
+impl Extend<Diagnostic> for Diagnostics {
+    fn extend<I: IntoIterator<Item = Diagnostic>>(&mut self, iter: I) {
+        self.0.extend(iter)
+    }
+}
+
+impl IntoIterator for Diagnostics {
+    type Item = Diagnostic;
+    type IntoIter = alloc::vec::IntoIter<Diagnostic>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Diagnostics {
+    type Item = &'a Diagnostic;
+    type IntoIter = core::slice::Iter<'a, Diagnostic>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+/// Every diagnostic in a line of its own, after its severity.
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, diagnostic) in self.0.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}: {}", diagnostic.severity, diagnostic)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl Error for Diagnostics {}
+
+fn json_string(s: &str) -> String {
+    let mut json = String::with_capacity(s.len() + 2);
+    json.push('"');
+    for ch in s.chars() {
+        match ch {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            ch if (ch as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", ch as u32)),
+            ch => json.push(ch),
+        }
+    }
+    json.push('"');
+    json
+}
+
+/// How the CLIs report errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorFormat {
+    /// messages for people
+    Human,
+    /// a [`Diagnostic::to_json`] object per line
+    Json,
+}
+
+impl ErrorFormat {
+    pub const VARIANTS: &'static [&'static str] = &["human", "json"];
+}
+
+impl FromStr for ErrorFormat {
+    type Err = UnknownErrorFormat;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "human" => Self::Human,
+            "json" => Self::Json,
+            _ => return Err(UnknownErrorFormat(s.to_string())),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct UnknownErrorFormat(String);
+
+impl fmt::Display for UnknownErrorFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown error format: {:?}", self.0)
+    }
+}
+
+#[cfg(feature = "std")]
+impl Error for UnknownErrorFormat {}
+
+/// The diagnostics of an error of this crate, or of any other error,
+/// which gets one without a code or position.
+#[cfg(feature = "std")]
+pub fn diagnose(e: &(dyn Error + 'static)) -> alloc::vec::Vec<Diagnostic> {
+    use super::dialect::DialectError;
+    use super::parser::{IOParserErr, ParseError, ParseErrors};
+    use alloc::vec;
+
+    if let Some(diagnostics) = e.downcast_ref::<Diagnostics>() {
+        diagnostics.iter().cloned().collect()
+    } else if let Some(ParseErrors(errors)) = e.downcast_ref() {
+        errors.iter().map(ParseError::diagnostic).collect()
+    } else if let Some(e) = e.downcast_ref::<ParseError>() {
+        vec![e.diagnostic()]
+    } else if let Some(IOParserErr::Parser(e)) = e.downcast_ref() {
+        vec![e.diagnostic()]
+    } else if let Some(IOParserErr::IO(e)) = e.downcast_ref() {
+        vec![Diagnostic::new(e.to_string()).with_code(IO_ERROR)]
+    } else if let Some(e) = e.downcast_ref::<std::io::Error>() {
+        vec![Diagnostic::new(e.to_string()).with_code(IO_ERROR)]
+    } else if let Some(e) = e.downcast_ref::<ErrorWithPosition<DialectError>>() {
+        vec![Diagnostic::from_error(e)]
+    } else {
+        vec![Diagnostic::new(e.to_string())]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_str;
+
+    fn pos(line: usize, column: usize) -> Position {
+        Position { line, column }
+    }
+
+    #[test]
+    fn codes_tell_errors_apart() {
+        assert_eq!(parse_str("]").unwrap_err().code(), "BF0001");
+        assert_eq!(parse_str("[").unwrap_err().code(), "BF0002");
+        assert_eq!("json".parse::<ErrorFormat>().unwrap(), ErrorFormat::Json);
+        assert_eq!(
+            "xml".parse::<ErrorFormat>().unwrap_err().to_string(),
+            r#"unknown error format: "xml""#
+        );
+    }
+
+    #[test]
+    fn diagnostics_as_json() {
+        let diagnostic = Diagnostic::new("bad \"thing\"\n")
+            .with_code("BF0001")
+            .in_file("a.bf")
+            .at(pos(1, 3));
+        assert_eq!(
+            diagnostic.to_json(),
+            r#"{"severity":"error","code":"BF0001","message":"bad \"thing\"\n","file":"a.bf","line":1,"column":3,"end_line":1,"end_column":3,"labels":[]}"#
+        );
+        // whatever isn't known is null
+        assert_eq!(
+            Diagnostic::new("\u{1}")
+                .with_severity(Severity::Warning)
+                .to_json(),
+            r#"{"severity":"warning","code":null,"message":"\u0001","file":null,"line":null,"column":null,"end_line":null,"end_column":null,"labels":[]}"#
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn any_error_can_be_diagnosed() {
+        let parse_error: Box<dyn Error> = Box::new(parse_str("+]").unwrap_err());
+        let diagnostics = diagnose(parse_error.as_ref());
+        assert_eq!(diagnostics[0].code, Some("BF0001"));
+        assert_eq!(diagnostics[0].position, Some(pos(1, 2)));
+
+        let io_error = std::io::Error::from(std::io::ErrorKind::NotFound);
+        assert_eq!(diagnose(&io_error)[0].code, Some(IO_ERROR));
+        let other = UnknownErrorFormat("xml".to_string());
+        assert_eq!(diagnose(&other)[0], Diagnostic::new(other.to_string()));
+    }
+
+    #[test]
+    fn rendered_with_the_lines_they_point_at() {
+        let diagnostic = parse_str("+[>\n<")
+            .unwrap_err()
+            .diagnostic()
+            .in_file("a.bf");
+        assert_eq!(
+            diagnostic.render(b"+[>\n<", &PositionConfig::default()),
+            "error[BF0002]: Unclosed loop: last opening was found at 1:2
+ --> a.bf:2:2
+  |
+1 | +[>
+  |  - the loop was opened here
+2 | <
+  |  ^
+"
+        );
+    }
+
+    #[test]
+    fn spans_are_marked_where_the_parser_counted_them() {
+        let config = PositionConfig {
+            tab_width: 4,
+            zero_based: false,
+        };
+        let diagnostic = Diagnostic::new("these")
+            .with_severity(Severity::Warning)
+            .at(pos(1, 5))
+            .with_end(pos(1, 7));
+        assert_eq!(
+            diagnostic.render(b"\t+-[", &config),
+            "warning: these\n --> 1:5\n  |\n1 |     +-[\n  |     ^^\n"
+        );
+        // without a position, there's nothing to show
+        let diagnostic = Diagnostic::new("no source").in_file("a.bf");
+        assert_eq!(
+            diagnostic.render(b"+", &config),
+            "error: no source\n --> a.bf\n"
+        );
+    }
+
+    #[test]
+    fn spans_cover_what_the_error_is_about() {
+        let unmatched = parse_str("+\n-]").unwrap_err();
+        assert_eq!(unmatched.position(), pos(2, 2));
+        assert_eq!(unmatched.to_string(), "2:2: Unmatched loop closing");
+        let diagnostic = Diagnostic::from_span_error(&unmatched);
+        assert_eq!(diagnostic.position, Some(pos(2, 2)));
+        assert_eq!(diagnostic.end, Some(pos(2, 3)));
+
+        // what's missing covers nothing, so there's no end
+        let missing = ErrorWithSpan::at(UnknownErrorFormat(String::new()), Location::default());
+        assert_eq!(missing.start, missing.end);
+        let diagnostic = Diagnostic::new("missing")
+            .at(missing.start.position)
+            .with_end(missing.end.position);
+        assert_eq!(diagnostic.end, None);
+    }
+
+    #[test]
+    fn warnings_are_not_errors() {
+        let warning = Diagnostic::new("odd")
+            .with_severity(Severity::Warning)
+            .at(pos(1, 2));
+        let mut diagnostics: Diagnostics = core::iter::once(warning).collect();
+        assert!(!diagnostics.has_errors());
+        assert_eq!(diagnostics.severity(), Some(Severity::Warning));
+        assert_eq!(diagnostics.clone().into_result(1), Ok(1));
+
+        diagnostics.push(Diagnostic::new("wrong").in_file("a.bf"));
+        assert_eq!(diagnostics.severity(), Some(Severity::Error));
+        assert_eq!(diagnostics.errors().count(), 1);
+        assert_eq!(diagnostics.warnings().count(), 1);
+        assert_eq!(
+            diagnostics.to_string(),
+            "warning: 1:2: odd\nerror: a.bf: wrong"
+        );
+        assert_eq!(diagnostics.into_result(1).unwrap_err().len(), 2);
+        assert_eq!(Diagnostics::new().severity(), None);
+    }
+}
@@ -0,0 +1,178 @@
+//! Instructions of dialects that add to brainfuck's eight, like the `#`
+//! many interpreters dump the tape with, or the `!` separating a program
+//! from its input.
+//!
+//! Which bytes are instructions is told by [`Extensions`], and the parser
+//! yields them as [`ExtCommand`]s along with where they are, like any other
+//! command, instead of skipping them as comments:
+//! ```
+//! # #[cfg(feature = "std")]
+//! # {
+//! use bfrs_common::ext::{ExtCommand, Extensions};
+//! use bfrs_common::BFCommand;
+//! use bfrs_common::parser::parse_extended;
+//!
+//! let src = "+#.";
+//! let extensions = Extensions::new([b'#']);
+//! let commands: Vec<_> = parse_extended(src.bytes().map(Ok), extensions)
+//!     .map(|res| res.unwrap().0)
+//!     .collect();
+//! assert_eq!(
+//!     commands,
+//!     [
+//!         ExtCommand::Base(BFCommand::Increment),
+//!         ExtCommand::Debug,
+//!         ExtCommand::Base(BFCommand::Print),
+//!     ]
+//! );
+//! # }
+//! ```
+
+use super::BFCommand;
+use core::fmt;
+
+/// A command of brainfuck, or of an extension of it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ExtCommand {
+    Base(BFCommand),
+    /// `#`, dumping the tape for debugging
+    Debug,
+    /// `!`, where the program ends and its input starts
+    InputSeparator,
+    /// any other byte an extension makes an instruction, like the
+    /// `?` of some debuggers or the `(` and `)` of procedures
+    Other(u8),
+}
+
+impl ExtCommand {
+    /// The command spelled with `byte`, which is an extension
+    /// if it isn't one of brainfuck's.
+    pub fn from_u8(byte: u8) -> Self {
+        match (BFCommand::from_u8(byte), byte) {
+            (Some(command), _) => Self::Base(command),
+            (None, b'#') => Self::Debug,
+            (None, b'!') => Self::InputSeparator,
+            (None, byte) => Self::Other(byte),
+        }
+    }
+
+    /// The byte the command is spelled with.
+    pub fn to_u8(self) -> u8 {
+        match self {
+            Self::Base(command) => command as u8,
+            Self::Debug => b'#',
+            Self::InputSeparator => b'!',
+            Self::Other(byte) => byte,
+        }
+    }
+
+    /// The command of brainfuck, if it's one.
+    pub fn base(self) -> Option<BFCommand> {
+        match self {
+            Self::Base(command) => Some(command),
+            _ => None,
+        }
+    }
+}
+
+impl From<BFCommand> for ExtCommand {
+    fn from(command: BFCommand) -> Self {
+        Self::Base(command)
+    }
+}
+
+impl fmt::Display for ExtCommand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_u8() as char)
+    }
+}
+
+/// The bytes a dialect makes instructions, other than the ones
+/// spelling brainfuck's commands, which always are.
+#[derive(Debug, Clone)]
+pub struct Extensions {
+    bytes: [bool; 256],
+}
+
+impl Default for Extensions {
+    /// No extensions at all, which is plain brainfuck.
+    fn default() -> Self {
+        Self {
+            bytes: [false; 256],
+        }
+    }
+}
+
+impl Extensions {
+    pub fn new(bytes: impl IntoIterator<Item = u8>) -> Self {
+        let mut extensions = Self::default();
+        for byte in bytes {
+            extensions.bytes[byte as usize] = true;
+        }
+        extensions
+    }
+
+    /// The extension of an interpreter by name: `debug` for `#`, `input`
+    /// for `!`, `pbrain` for the `(`, `)` and `:` of procedures, `tapes`
+    /// for the `~` switching to the next of several tapes, or `grid` for
+    /// the `^` and `v` moving up and down the rows of a grid of cells.
+    pub fn builtin(name: &str) -> Option<Self> {
+        Some(match name {
+            "debug" => Self::new([b'#']),
+            "input" => Self::new([b'!']),
+            "pbrain" => Self::new([b'(', b')', b':']),
+            "tapes" => Self::new([b'~']),
+            "grid" => Self::new([b'^', b'v']),
+            _ => return None,
+        })
+    }
+
+    /// The extensions of both.
+    pub fn with(mut self, other: &Self) -> Self {
+        for (byte, &other) in self.bytes.iter_mut().zip(other.bytes.iter()) {
+            *byte |= other;
+        }
+        self
+    }
+
+    /// The instruction spelled with `byte`, if it's one of the extensions.
+    #[inline]
+    pub fn command(&self, byte: u8) -> Option<ExtCommand> {
+        self.bytes[byte as usize].then(|| ExtCommand::from_u8(byte))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        !self.bytes.contains(&true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands_are_spelled_with_their_byte() {
+        for byte in 0..=255 {
+            assert_eq!(ExtCommand::from_u8(byte).to_u8(), byte);
+        }
+        assert_eq!(ExtCommand::from_u8(b'#'), ExtCommand::Debug);
+        assert_eq!(ExtCommand::from_u8(b'~'), ExtCommand::Other(b'~'));
+        assert_eq!(ExtCommand::from_u8(b'+').base(), Some(BFCommand::Increment));
+        assert_eq!(ExtCommand::InputSeparator.base(), None);
+    }
+
+    #[test]
+    fn extensions_are_combined() {
+        assert!(Extensions::default().is_empty());
+        assert!(Extensions::builtin("brainfork").is_none());
+        let pbrain = Extensions::builtin("pbrain").unwrap();
+        assert_eq!(pbrain.command(b':'), Some(ExtCommand::Other(b':')));
+        assert_eq!(pbrain.command(b'#'), None);
+        let both = pbrain.with(&Extensions::builtin("debug").unwrap());
+        assert_eq!(both.command(b'#'), Some(ExtCommand::Debug));
+        assert_eq!(both.command(b'('), Some(ExtCommand::Other(b'(')));
+        // brainfuck's commands aren't extensions of it
+        assert_eq!(both.command(b'+'), None);
+    }
+}
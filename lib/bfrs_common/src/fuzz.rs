@@ -0,0 +1,44 @@
+//! Inputs for fuzzers, behind the `arbitrary` feature.
+//!
+//! Any sequence of [`BFCommand`]s can be made with `Vec<BFCommand>`,
+//! but most of them have unbalanced loops, which every parser rejects
+//! right away. [`Balanced`] only makes programs that get past that.
+
+use super::BFCommand;
+use alloc::vec::Vec;
+use arbitrary::{Arbitrary, Result, Unstructured};
+
+/// A program whose loops are all closed, to exercise
+/// what runs after the parser, like the interpreter.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Balanced(pub Vec<BFCommand>);
+
+impl<'a> Arbitrary<'a> for Balanced {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let mut instructions = Vec::new();
+        let mut open = 0usize;
+        for instruction in u.arbitrary_iter::<BFCommand>()? {
+            let instruction = instruction?;
+            match instruction {
+                // a `]` without its `[` is dropped.
+                BFCommand::EndLoop if open == 0 => continue,
+                BFCommand::EndLoop => open -= 1,
+                BFCommand::BeginLoop => open += 1,
+                _ => (),
+            }
+            instructions.push(instruction);
+        }
+        instructions.extend(core::iter::repeat_n(BFCommand::EndLoop, open));
+        Ok(Self(instructions))
+    }
+}
+
+impl Balanced {
+    /// The program as source code, for the APIs that parse it.
+    pub fn to_source(&self) -> Vec<u8> {
+        self.0
+            .iter()
+            .map(|&instruction| instruction as u8)
+            .collect()
+    }
+}
@@ -0,0 +1,266 @@
+//! Keeping the instructions of a source up to date as it's edited,
+//! without parsing it again from the start.
+//!
+//! Only the replaced bytes are scanned on every edit. The instructions
+//! after them are shifted to their new location, and the loops are
+//! paired again from the instructions, which is much cheaper than reading
+//! the source. Since the source is being edited, the brackets don't have
+//! to be balanced: the ones without a pair are reported as errors, and
+//! are kept in the instructions.
+
+use super::parser::{ParseError, ParseErrorKind};
+use super::{BFCommand, Location, Position};
+use alloc::vec::Vec;
+use core::ops::Range;
+
+/// A source being edited, along with its instructions.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Document {
+    source: Vec<u8>,
+    instructions: Vec<(BFCommand, Location)>,
+    /// the index of the other bracket of every loop bracket
+    pairs: Vec<Option<usize>>,
+    errors: Vec<ParseError>,
+    /// the location right after the last byte
+    end: Location,
+}
+
+impl Document {
+    pub fn new(source: Vec<u8>) -> Self {
+        let start = Location {
+            position: Position::default(),
+            byte_offset: 0,
+        };
+        let mut document = Self {
+            source: Vec::new(),
+            instructions: Vec::new(),
+            pairs: Vec::new(),
+            errors: Vec::new(),
+            end: start,
+        };
+        document.edit(0..0, &source);
+        document
+    }
+
+    pub fn source(&self) -> &[u8] {
+        &self.source
+    }
+
+    /// Every instruction in the source, along with where it was found.
+    pub fn instructions(&self) -> &[(BFCommand, Location)] {
+        &self.instructions
+    }
+
+    /// The index of the bracket at the other end of the loop, for
+    /// the bracket at `index`, if it has one.
+    pub fn matching(&self, index: usize) -> Option<usize> {
+        self.pairs.get(index).copied().flatten()
+    }
+
+    /// The brackets without a pair, in the order they are in the source.
+    pub fn errors(&self) -> &[ParseError] {
+        &self.errors
+    }
+
+    /// Replaces the bytes in `range` with `replacement`, returning the
+    /// indices of the instructions found in the replacement.
+    ///
+    /// # Panics
+    /// If the range is out of the source.
+    pub fn edit(&mut self, range: Range<usize>, replacement: &[u8]) -> Range<usize> {
+        assert!(
+            range.start <= range.end && range.end <= self.source.len(),
+            "edit {:?} is out of the source, of length {}",
+            range,
+            self.source.len()
+        );
+        // a byte continuing a character right after the edit may not
+        // be continuing it anymore, or the other way around, so its
+        // column is counted again along with the replacement.
+        let continued = self
+            .source
+            .get(range.end)
+            .map_or(0, |&byte| (0x80..=0xBF).contains(&byte) as usize);
+        let start = self.location_at(range.start);
+        let old_end = self.location_at(range.end + continued);
+        let first = self
+            .instructions
+            .partition_point(|(_, location)| location.byte_offset < range.start);
+        let last = self
+            .instructions
+            .partition_point(|(_, location)| location.byte_offset < range.end);
+
+        let mut found = Vec::new();
+        let mut new_end = start;
+        let mut previous = self.previous_byte(range.start);
+        for &byte in replacement {
+            if let Some(instr) = BFCommand::from_u8(byte) {
+                found.push((instr, new_end));
+            }
+            advance(&mut new_end, byte, previous);
+            previous = byte;
+        }
+        for &byte in &self.source[range.end..range.end + continued] {
+            advance(&mut new_end, byte, previous);
+        }
+        let found_len = found.len();
+
+        for (_, location) in self.instructions[last..].iter_mut() {
+            *location = shift(*location, old_end, new_end);
+        }
+        self.end = shift(self.end, old_end, new_end);
+        self.instructions.splice(first..last, found);
+        self.source.splice(range, replacement.iter().copied());
+        self.pair_loops();
+        first..first + found_len
+    }
+
+    /// The location of the byte at `offset`, starting from the closest
+    /// instruction before it.
+    fn location_at(&self, offset: usize) -> Location {
+        let before = self
+            .instructions
+            .partition_point(|(_, location)| location.byte_offset <= offset);
+        let mut location = match before.checked_sub(1) {
+            Some(i) => self.instructions[i].1,
+            None => Location {
+                position: Position::default(),
+                byte_offset: 0,
+            },
+        };
+        let mut previous = self.previous_byte(location.byte_offset);
+        for &byte in &self.source[location.byte_offset..offset] {
+            advance(&mut location, byte, previous);
+            previous = byte;
+        }
+        location
+    }
+
+    /// The byte before `offset`, or `0` at the start.
+    fn previous_byte(&self, offset: usize) -> u8 {
+        offset.checked_sub(1).map_or(0, |i| self.source[i])
+    }
+
+    fn pair_loops(&mut self) {
+        self.pairs.clear();
+        self.pairs.resize(self.instructions.len(), None);
+        self.errors.clear();
+        let mut open = Vec::new();
+        for (i, (instr, location)) in self.instructions.iter().enumerate() {
+            match instr {
+                BFCommand::BeginLoop => open.push(i),
+                BFCommand::EndLoop => match open.pop() {
+                    Some(start) => {
+                        self.pairs[start] = Some(i);
+                        self.pairs[i] = Some(start);
+                    }
+                    None => self.errors.push(ParseError::new(
+                        ParseErrorKind::MissingLB,
+                        *location,
+                        location.after_command(),
+                    )),
+                },
+                _ => (),
+            }
+        }
+        let (instructions, end) = (&self.instructions, self.end);
+        self.errors.extend(open.into_iter().map(|start| {
+            ParseError::at(
+                ParseErrorKind::MissingRB(instructions[start].1.position),
+                end,
+            )
+        }));
+    }
+}
+
+/// Moves past a byte, the same way the parser does.
+fn advance(location: &mut Location, byte: u8, previous: u8) {
+    location.position.advance_byte(byte, previous);
+    location.byte_offset += 1;
+}
+
+/// Where something after an edit ends up, when the edit
+/// ended at `old_end` and now ends at `new_end`.
+fn shift(location: Location, old_end: Location, new_end: Location) -> Location {
+    let position = if location.position.line == old_end.position.line {
+        Position {
+            line: new_end.position.line,
+            column: location.position.column - old_end.position.column + new_end.position.column,
+        }
+    } else {
+        Position {
+            line: location.position.line - old_end.position.line + new_end.position.line,
+            column: location.position.column,
+        }
+    };
+    Location {
+        position,
+        byte_offset: location.byte_offset - old_end.byte_offset + new_end.byte_offset,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Applies the edits one by one, checking the document
+    /// against one made from the edited source every time.
+    fn check_edits(source: &str, edits: &[(Range<usize>, &str)]) -> Document {
+        let mut document = Document::new(source.as_bytes().to_vec());
+        for (range, replacement) in edits {
+            document.edit(range.clone(), replacement.as_bytes());
+            let fresh = Document::new(document.source().to_vec());
+            assert_eq!(document.instructions(), fresh.instructions(), "{:?}", range);
+            assert_eq!(document.end, fresh.end, "{:?}", range);
+            let pairs: Vec<_> = (0..fresh.instructions().len())
+                .map(|i| document.matching(i))
+                .collect();
+            assert_eq!(pairs, fresh.pairs, "{:?}", range);
+            let errors = |document: &Document| -> Vec<(Location, Location)> {
+                document.errors().iter().map(|e| (e.start, e.end)).collect()
+            };
+            assert_eq!(errors(&document), errors(&fresh), "{:?}", range);
+        }
+        document
+    }
+
+    #[test]
+    fn edits_match_parsing_again() {
+        let document = check_edits(
+            "+[->+<]\n.>\n,",
+            &[
+                (1..1, "\n\n"),
+                (0..3, "é["),
+                (5..8, ""),
+                (4..4, "[-]é\n"),
+                (12..15, "]]"),
+                (0..0, "ü"),
+            ],
+        );
+        assert_eq!(
+            core::str::from_utf8(document.source()).unwrap(),
+            "üé[[[-]é\n-]]]\n,"
+        );
+    }
+
+    #[test]
+    fn unpaired_brackets_are_kept() {
+        let mut document = Document::new(b"[-]".to_vec());
+        assert_eq!(document.matching(0), Some(2));
+        let found = document.edit(3..3, b" ]");
+        assert_eq!(found, 3..4);
+        assert_eq!(document.instructions().len(), 4);
+        assert_eq!(document.matching(3), None);
+        assert!(matches!(document.errors(), [e] if matches!(e.kind, ParseErrorKind::MissingLB)));
+        document.edit(0..0, b"[");
+        assert_eq!(document.matching(0), Some(4));
+        assert!(document.errors().is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "edit 2..4 is out of the source, of length 3")]
+    fn edits_stay_in_the_source() {
+        Document::new(b"+++".to_vec()).edit(2..4, b"");
+    }
+}
@@ -1,7 +1,21 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+pub mod dialect;
 pub mod errors;
+pub mod ext;
+#[cfg(feature = "arbitrary")]
+pub mod fuzz;
+pub mod incremental;
 pub mod parser;
+pub mod program;
+pub mod runs;
+pub mod theme;
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub enum BFCommand {
     BeginLoop = b'[',
     EndLoop = b']',
@@ -29,7 +43,7 @@ impl BFCommand {
     }
 }
 
-use std::fmt;
+use core::fmt;
 impl fmt::Display for BFCommand {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}", *self as u8 as char)
@@ -37,6 +51,7 @@ impl fmt::Display for BFCommand {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Position {
     pub column: usize,
     pub line: usize,
@@ -48,12 +63,43 @@ impl Default for Position {
     }
 }
 
+/// How positions are counted, so they line up with what an editor shows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PositionConfig {
+    /// the columns a tab moves to the next multiple of, 1 to count it as any other character
+    pub tab_width: usize,
+    /// count lines and columns from 0, like LSP does
+    pub zero_based: bool,
+}
+
+impl Default for PositionConfig {
+    fn default() -> Self {
+        Self {
+            tab_width: 1,
+            zero_based: false,
+        }
+    }
+}
+
 impl Position {
+    /// The first position of a source.
+    #[inline]
+    pub fn start(config: &PositionConfig) -> Self {
+        let first = if config.zero_based { 0 } else { 1 };
+        Self {
+            column: first,
+            line: first,
+        }
+    }
+
+    /// Moves past a character, where the line after a `\n` starts at
+    /// column 1, like the first one does.
     #[inline]
     pub fn advance_char(&mut self, ch: char) {
         if ch == '\n' {
             self.line += 1;
-            self.column = 0;
+            self.column = 1;
         } else {
             self.column += 1;
         }
@@ -63,10 +109,193 @@ impl Position {
     pub fn advance_col(&mut self) {
         self.column += 1;
     }
+
+    /// Moves past a character, counting tabs and the
+    /// first column the way `config` says.
+    #[inline]
+    pub fn advance_char_with(&mut self, ch: char, config: &PositionConfig) {
+        let first = Self::start(config).column;
+        match ch {
+            '\n' => {
+                self.line += 1;
+                self.column = first;
+            }
+            '\t' if config.tab_width > 1 => {
+                let width = config.tab_width;
+                self.column = (self.column - first) / width * width + width + first;
+            }
+            _ => self.column += 1,
+        }
+    }
+
+    /// Moves past a byte of UTF-8 text, where `previous` is the byte before
+    /// it, or `0` at the start. Only the first byte of a character takes a
+    /// column, and invalid bytes take one each, like editors show them.
+    #[inline]
+    pub fn advance_byte(&mut self, byte: u8, previous: u8) {
+        self.advance_byte_with(byte, previous, &PositionConfig::default())
+    }
+
+    /// Moves past a byte like [`advance_byte`](Self::advance_byte),
+    /// counting tabs and the first column the way `config` says.
+    #[inline]
+    pub fn advance_byte_with(&mut self, byte: u8, previous: u8, config: &PositionConfig) {
+        if byte.is_ascii() {
+            self.advance_char_with(byte as char, config)
+        } else if takes_column(byte, previous) {
+            self.advance_col()
+        }
+    }
+}
+
+/// Whether a byte of UTF-8 text takes a column of its own, which all
+/// of them do but the ones continuing a character. `previous` is the byte
+/// before it, so a stray continuation byte still takes a column.
+#[inline]
+pub fn takes_column(byte: u8, previous: u8) -> bool {
+    !(0x80..=0xBF).contains(&byte) || previous.is_ascii()
+}
+
+/// Where something is found in a source: its
+/// line and column, along with its byte offset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Location {
+    pub position: Position,
+    pub byte_offset: usize,
+}
+
+/// The start of a source.
+impl Default for Location {
+    fn default() -> Self {
+        Self {
+            position: Position::default(),
+            byte_offset: 0,
+        }
+    }
 }
 
-impl std::fmt::Display for Position {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+impl Location {
+    /// The location right after a command found at this location.
+    #[inline]
+    pub fn after_command(mut self) -> Self {
+        self.position.advance_col();
+        self.byte_offset += 1;
+        self
+    }
+
+    /// Moves past a character of text.
+    #[inline]
+    pub fn advance_char(&mut self, ch: char) {
+        self.position.advance_char(ch);
+        self.byte_offset += ch.len_utf8();
+    }
+
+    /// The location right after a character found at this location.
+    #[inline]
+    pub fn after_char(mut self, ch: char) -> Self {
+        self.advance_char(ch);
+        self
+    }
+
+    /// The location right after `text`, when it starts at this location.
+    pub fn after_str(self, text: &str) -> Self {
+        text.chars().fold(self, Self::after_char)
+    }
+}
+
+/// Something found in a source, along with where it starts and
+/// where it ends. The end is right after the last byte it covers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Spanned<T> {
+    pub node: T,
+    pub start: Location,
+    pub end: Location,
+}
+
+/// Shows the byte offset along with the line and column.
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} (byte {})", self.position, self.byte_offset)
+    }
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}:{}", self.line, self.column)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn after(text: &str) -> Position {
+        let mut position = Position::default();
+        text.chars().for_each(|ch| position.advance_char(ch));
+        position
+    }
+
+    #[test]
+    fn lines_start_at_column_1() {
+        assert_eq!(after(""), Position { line: 1, column: 1 });
+        assert_eq!(after("+-"), Position { line: 1, column: 3 });
+        assert_eq!(after("+-\n"), Position { line: 2, column: 1 });
+        assert_eq!(after("+-\n>"), Position { line: 2, column: 2 });
+    }
+
+    #[test]
+    fn lines_start_where_the_config_says() {
+        let config = PositionConfig {
+            tab_width: 4,
+            zero_based: true,
+        };
+        let mut position = Position::start(&config);
+        "+\n\t>"
+            .chars()
+            .for_each(|ch| position.advance_char_with(ch, &config));
+        assert_eq!(position, Position { line: 1, column: 5 });
+    }
+
+    #[test]
+    fn characters_take_a_column_however_many_bytes_they_are() {
+        let columns = |bytes: &[u8]| {
+            let mut position = Position::default();
+            let mut previous = 0;
+            for &byte in bytes {
+                position.advance_byte(byte, previous);
+                previous = byte;
+            }
+            position.column
+        };
+        assert_eq!(columns("é+".as_bytes()), 3);
+        assert_eq!(columns("→\n🦀".as_bytes()), 2);
+        // a stray continuation byte, and invalid ones, take a column each
+        assert_eq!(columns(b"+\x80"), 3);
+        assert_eq!(columns(b"\xff\xfe"), 3);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trips() {
+        use crate::incremental::Document;
+        use crate::parser::{parse_str, ParseErrorKind};
+
+        let document = Document::new(b"+[\n-]]".to_vec());
+        let json = serde_json::to_string(&document).unwrap();
+        let back: Document = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.instructions(), document.instructions());
+        assert_eq!(back.matching(1), Some(3));
+        assert_eq!(back.errors().len(), 1);
+
+        let error = parse_str("[").unwrap_err();
+        let json = serde_json::to_value(error).unwrap();
+        assert_eq!(json["start"]["byte_offset"], 1);
+        let back: crate::parser::ParseError = serde_json::from_value(json).unwrap();
+        assert!(matches!(
+            back.kind,
+            ParseErrorKind::MissingRB(Position { line: 1, column: 1 })
+        ));
+    }
+}
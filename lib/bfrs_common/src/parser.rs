@@ -1,32 +1,116 @@
-use super::{BFCommand, Position};
-use std::error::Error;
-use std::fmt;
-use std::io;
-
-pub type Result<T> = std::result::Result<T, IOParserErr>;
-
-pub fn parse<I>(input: I) -> BFParserIter<I>
-where
-    I: Iterator<Item = io::Result<u8>>,
-{
-    BFParser::new(input).into_iter()
+use super::errors::{Diagnostic, ErrorCode, ErrorWithSpan};
+use super::{takes_column, BFCommand, Location, Position};
+use alloc::vec::Vec;
+use core::fmt;
+
+#[cfg(feature = "std")]
+mod stream;
+#[cfg(feature = "std")]
+pub use stream::*;
+
+/// Parses a whole source already in memory.
+pub fn parse_str(src: &str) -> core::result::Result<Vec<BFCommand>, ParseError> {
+    parse_bytes(src.as_bytes())
 }
 
-pub fn parse_starting_at<I>(input: I, start_pos: Position) -> BFParserIter<I>
-where
-    I: Iterator<Item = io::Result<u8>>,
-{
-    BFParser::starting_at(input, start_pos).into_iter()
+/// Parses a whole source already in memory, which doesn't have to be UTF-8.
+/// Unlike the other parsers, this one doesn't need `std`, and it skips
+/// comments in bulk, which is much faster on sources that are mostly comments.
+pub fn parse_bytes(src: &[u8]) -> core::result::Result<Vec<BFCommand>, ParseError> {
+    let mut instructions = Vec::new();
+    let mut position = Position::default();
+    let mut loop_backlog = Vec::new();
+    let mut i = 0;
+    loop {
+        i += skip_comments(&src[i..], &mut position);
+        let instr = match src.get(i) {
+            Some(&byte) => BFCommand::from_u8(byte).expect("comments were skipped"),
+            None => break,
+        };
+        match instr {
+            BFCommand::BeginLoop => loop_backlog.push(position),
+            BFCommand::EndLoop if loop_backlog.pop().is_none() => {
+                let location = Location {
+                    position,
+                    byte_offset: i,
+                };
+                return Err(ParseError::new(
+                    ParseErrorKind::MissingLB,
+                    location,
+                    location.after_command(),
+                ));
+            }
+            _ => (),
+        }
+        instructions.push(instr);
+        position.advance_col();
+        i += 1;
+    }
+    // on EOF, there should be no dangling loops
+    match loop_backlog.pop() {
+        Some(lb_pos) => Err(ParseError::at(
+            ParseErrorKind::MissingRB(lb_pos),
+            Location {
+                position,
+                byte_offset: src.len(),
+            },
+        )),
+        None => Ok(instructions),
+    }
 }
 
-// note: maybe move this to a more generic thing?
-#[derive(Debug, Clone, Copy)]
-pub struct ParseError {
-    pub kind: ParseErrorKind,
-    pub position: Position,
+/// Whether every byte is a command.
+const IS_COMMAND: [bool; 256] = {
+    let mut table = [false; 256];
+    let commands = b"[].,+-><";
+    let mut i = 0;
+    while i < commands.len() {
+        table[commands[i] as usize] = true;
+        i += 1;
+    }
+    table
+};
+
+/// Skips the bytes before the first command, returning how many there
+/// are. Rather than going through them one by one, only the newlines are
+/// counted, and the columns of the bytes after the last one.
+fn skip_comments(src: &[u8], position: &mut Position) -> usize {
+    let len = src
+        .iter()
+        .position(|&byte| IS_COMMAND[byte as usize])
+        .unwrap_or(src.len());
+    let skipped = &src[..len];
+    match skipped.iter().rposition(|&byte| byte == b'\n') {
+        Some(last) => {
+            position.line += skipped[..last]
+                .iter()
+                .filter(|&&byte| byte == b'\n')
+                .count()
+                + 1;
+            position.column = 1 + columns(&skipped[last + 1..], b'\n');
+        }
+        // the comment comes after a command, or at the start.
+        None => position.column += columns(skipped, 0),
+    }
+    len
 }
 
+/// How many columns the bytes of a line take, where `previous` is the byte before them.
+fn columns(bytes: &[u8], previous: u8) -> usize {
+    let previous = core::iter::once(previous).chain(bytes.iter().copied());
+    bytes
+        .iter()
+        .zip(previous)
+        .filter(|&(&byte, previous)| takes_column(byte, previous))
+        .count()
+}
+
+/// An unmatched `]` covers the bracket, while an unclosed loop
+/// is found at the end of the source, and covers nothing.
+pub type ParseError = ErrorWithSpan<ParseErrorKind>;
+
 #[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ParseErrorKind {
     MissingLB,
     MissingRB(Position),
@@ -43,156 +127,82 @@ impl fmt::Display for ParseErrorKind {
     }
 }
 
-impl Error for ParseErrorKind {}
+#[cfg(feature = "std")]
+impl std::error::Error for ParseErrorKind {}
 
-impl fmt::Display for ParseError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}: {}", self.position, self.kind)
-    }
-}
-
-impl Error for ParseError {
-    fn source(&self) -> Option<&(dyn Error + 'static)> {
-        Some(&self.kind)
+impl ParseError {
+    /// The error as it's reported, pointing at where an unclosed loop was opened.
+    pub fn diagnostic(&self) -> Diagnostic {
+        let diagnostic = Diagnostic::from_span_error(self);
+        match self.kind {
+            ParseErrorKind::MissingRB(opened_at) => {
+                diagnostic.with_label(opened_at, "the loop was opened here")
+            }
+            ParseErrorKind::MissingLB => diagnostic,
+        }
     }
 }
 
-struct BFParser<I>
-where
-    I: Iterator<Item = io::Result<u8>>,
-{
-    input: std::iter::Fuse<I>,
-    current_position: Position,
-    loop_backlog: Vec<Position>,
-}
-
-#[derive(Debug)]
-pub enum IOParserErr {
-    IO(io::Error),
-    Parser(ParseError),
-}
-
-impl fmt::Display for IOParserErr {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+impl ErrorCode for ParseErrorKind {
+    fn code(&self) -> &'static str {
         match self {
-            Self::IO(e) => write!(f, "an IO error occurred while trying to read bytes: {}", e),
-            Self::Parser(p) => write!(f, "parse error: {}", p),
+            Self::MissingLB => "BF0001",
+            Self::MissingRB(_) => "BF0002",
         }
     }
 }
 
-impl Error for IOParserErr {
-    fn source(&self) -> Option<&(dyn Error + 'static)> {
-        Some(match self {
-            Self::IO(e) => e,
-            Self::Parser(e) => e,
-        })
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sources_in_memory() {
+        use BFCommand::*;
+        assert_eq!(
+            parse_str("+ [>] comment").unwrap(),
+            [Increment, BeginLoop, Right, EndLoop]
+        );
+        // the comments don't have to be UTF-8
+        assert_eq!(parse_bytes(b"\xff.\xfe,").unwrap(), [Print, Read]);
+        assert!(parse_str("").unwrap().is_empty());
     }
-}
 
-impl<I> BFParser<I>
-where
-    I: Iterator<Item = io::Result<u8>>,
-{
-    #[inline]
-    /// Starts parsing, setting the initial position to `start_pos`
-    fn starting_at(input: I, start_pos: Position) -> Self {
-        Self {
-            input: Iterator::fuse(input),
-            current_position: start_pos,
-            loop_backlog: Vec::new(),
-        }
-    }
-    #[inline]
-    fn new(input: I) -> Self {
-        Self::starting_at(input, Position::default())
+    #[test]
+    fn bracket_errors_in_memory() {
+        let unmatched = parse_str("+\n-]").unwrap_err();
+        assert!(matches!(unmatched.kind, ParseErrorKind::MissingLB));
+        assert_eq!(unmatched.start.position, Position { line: 2, column: 2 });
+        assert_eq!(unmatched.end.byte_offset, 4);
+        let unclosed = parse_str("[[]\n").unwrap_err();
+        assert!(matches!(
+            unclosed.kind,
+            ParseErrorKind::MissingRB(opened_at) if opened_at == Position { line: 1, column: 1 }
+        ));
+        assert_eq!(unclosed.start.position, Position { line: 2, column: 1 });
+        assert_eq!(unclosed.start.byte_offset, 4);
     }
-    fn next_instruction(&mut self) -> Result<Option<BFCommand>> {
-        // clippy can't distinguish that there is an early return, and that
-        // the iterator is not to be consumed on call.
-        #[allow(clippy::while_let_on_iterator)]
-        while let Some(next_byte) = self.input.next() {
-            let byte = next_byte.map_err(IOParserErr::IO)?;
-            let instruction = BFCommand::from_u8(byte);
-
-            if let Some(instr) = instruction {
-                // make sure we're matching loops correctly.
-                match instr {
-                    BFCommand::BeginLoop => self.loop_backlog.push(self.current_position),
-                    BFCommand::EndLoop => {
-                        if self.loop_backlog.pop().is_none() {
-                            return Err(self.error(ParseErrorKind::MissingLB));
-                        }
-                    }
-                    _ => (),
-                }
-            }
-
-            if byte.is_ascii() {
-                self.current_position.advance_char(byte as char)
-            } else {
-                self.current_position.advance_col()
-            }
 
-            if instruction.is_some() {
-                return Ok(instruction);
+    #[test]
+    fn comments_are_skipped_up_to_the_next_command() {
+        let mut position = Position::default();
+        assert_eq!(
+            skip_comments(b"a comment\nanother\n  one+-", &mut position),
+            23
+        );
+        assert_eq!(position, Position { line: 3, column: 6 });
+        // a comment right after a command carries on the same line
+        assert_eq!(skip_comments(b"ok.", &mut position), 2);
+        assert_eq!(position, Position { line: 3, column: 8 });
+        assert_eq!(skip_comments(b"no commands", &mut position), 11);
+        assert_eq!(skip_comments(b"", &mut position), 0);
+        assert_eq!(skip_comments(b"+", &mut position), 0);
+        assert_eq!(
+            position,
+            Position {
+                line: 3,
+                column: 19
             }
-        }
-
-        // on EOF, there should be no dangling loops
-        if let Some(lb_pos) = self.loop_backlog.pop() {
-            Err(self.error(ParseErrorKind::MissingRB(lb_pos)))
-        } else {
-            Ok(None)
-        }
-    }
-    #[inline]
-    fn error(&self, kind: ParseErrorKind) -> IOParserErr {
-        IOParserErr::Parser(ParseError {
-            kind,
-            position: self.current_position,
-        })
-    }
-}
-
-pub struct BFParserIter<I>
-where
-    I: Iterator<Item = io::Result<u8>>,
-{
-    parser: BFParser<I>,
-    finished: bool,
-}
-
-impl<I> Iterator for BFParserIter<I>
-where
-    I: Iterator<Item = io::Result<u8>>,
-{
-    type Item = Result<BFCommand>;
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.finished {
-            None
-        } else {
-            let res = self.parser.next_instruction();
-            if matches!(res, Ok(None) | Err(_)) {
-                self.finished = true;
-            }
-            res.transpose()
-        }
-    }
-}
-
-impl<I> std::iter::FusedIterator for BFParserIter<I> where I: Iterator<Item = io::Result<u8>> {}
-
-impl<I> IntoIterator for BFParser<I>
-where
-    I: Iterator<Item = io::Result<u8>>,
-{
-    type IntoIter = BFParserIter<I>;
-    type Item = <Self::IntoIter as Iterator>::Item;
-    fn into_iter(self) -> Self::IntoIter {
-        BFParserIter {
-            parser: self,
-            finished: false,
-        }
+        );
     }
 }
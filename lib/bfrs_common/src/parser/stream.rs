@@ -0,0 +1,1028 @@
+//! Parsing from a stream of bytes, as read from files or stdin.
+
+use super::{ParseError, ParseErrorKind};
+use crate::dialect::Dialect;
+use crate::errors::{Diagnostics, Severity};
+use crate::ext::{ExtCommand, Extensions};
+use crate::{BFCommand, Location, Position, PositionConfig, Spanned};
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+pub type Result<T> = std::result::Result<T, IOParserErr>;
+
+pub fn parse<I>(input: I) -> BFParserIter<I>
+where
+    I: Iterator<Item = io::Result<u8>>,
+{
+    BFParser::new(input).into_iter()
+}
+
+pub fn parse_starting_at<I>(input: I, start_pos: Position) -> BFParserIter<I>
+where
+    I: Iterator<Item = io::Result<u8>>,
+{
+    BFParser::starting_at(input, start_pos).into_iter()
+}
+
+/// Parses the input, yielding every instruction along
+/// with where it was found in the source.
+pub fn parse_located<I>(input: I) -> BFLocatedParserIter<I>
+where
+    I: Iterator<Item = io::Result<u8>>,
+{
+    BFParser::new(input).into_located_iter()
+}
+
+/// Parses the input, spelling the commands with the tokens of a [`Dialect`].
+pub fn parse_dialect<I>(input: I, dialect: Dialect) -> BFParserIter<I>
+where
+    I: Iterator<Item = io::Result<u8>>,
+{
+    BFParser::with_dialect(input, dialect).into_iter()
+}
+
+/// Parses the input like [`parse_located`], spelling the
+/// commands with the tokens of a [`Dialect`].
+pub fn parse_located_dialect<I>(input: I, dialect: Dialect) -> BFLocatedParserIter<I>
+where
+    I: Iterator<Item = io::Result<u8>>,
+{
+    parse_located_with(input, dialect, PositionConfig::default())
+}
+
+/// Parses the input like [`parse_located_dialect`], counting
+/// the positions the way `positions` says.
+pub fn parse_located_with<I>(
+    input: I,
+    dialect: Dialect,
+    positions: PositionConfig,
+) -> BFLocatedParserIter<I>
+where
+    I: Iterator<Item = io::Result<u8>>,
+{
+    BFParser::with_options(input, dialect, positions).into_located_iter()
+}
+
+/// Parses the input, yielding the instructions of the extensions along
+/// with brainfuck's, instead of skipping them as comments, and where
+/// every one of them was found in the source.
+pub fn parse_extended<I>(input: I, extensions: Extensions) -> BFExtendedParserIter<I>
+where
+    I: Iterator<Item = io::Result<u8>>,
+{
+    parse_extended_with(
+        input,
+        Dialect::default(),
+        extensions,
+        PositionConfig::default(),
+    )
+}
+
+/// Parses the input like [`parse_extended`], spelling brainfuck's commands
+/// with the tokens of a [`Dialect`] and counting the positions the way
+/// `positions` says. The tokens of the dialect come before the extensions.
+pub fn parse_extended_with<I>(
+    input: I,
+    dialect: Dialect,
+    extensions: Extensions,
+    positions: PositionConfig,
+) -> BFExtendedParserIter<I>
+where
+    I: Iterator<Item = io::Result<u8>>,
+{
+    BFExtendedParserIter {
+        parser: BFParser {
+            extensions,
+            ..BFParser::with_options(input, dialect, positions)
+        },
+        finished: false,
+    }
+}
+
+/// Parses the input, yielding every instruction along with the
+/// span of source it covers.
+pub fn parse_spanned<I>(input: I) -> BFSpannedParserIter<I>
+where
+    I: Iterator<Item = io::Result<u8>>,
+{
+    BFSpannedParserIter {
+        inner: parse_located(input),
+    }
+}
+
+/// What [`parse_events`] finds in the source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Event {
+    /// any instruction other than a bracket
+    Op(BFCommand, Location),
+    /// the opening bracket of a loop
+    LoopEnter(Location),
+    /// the closing bracket of a loop, always matching the last open one
+    LoopExit(Location),
+}
+
+/// Parses the input, yielding an event for every instruction, so
+/// the nesting of loops can be followed as the input is read.
+pub fn parse_events<I>(input: I) -> BFEventIter<I>
+where
+    I: Iterator<Item = io::Result<u8>>,
+{
+    BFEventIter {
+        inner: parse_located(input),
+    }
+}
+
+/// An instruction, or a whole loop, as found by [`parse_tree`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Node {
+    /// any instruction other than a bracket
+    Instruction(Spanned<BFCommand>),
+    /// the body of a loop, spanning from its opening bracket through its closing one
+    Loop(Spanned<Vec<Node>>),
+}
+
+impl fmt::Display for Node {
+    /// Writes the instructions of the node as plain brainfuck.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Instruction(instruction) => write!(f, "{}", instruction.node),
+            Self::Loop(body) => {
+                write!(f, "[")?;
+                for node in body.node.iter() {
+                    write!(f, "{}", node)?;
+                }
+                write!(f, "]")
+            }
+        }
+    }
+}
+
+/// Parses the input into a tree, where every loop holds the
+/// instructions of its body, so brackets are already paired.
+pub fn parse_tree<I>(input: I) -> Result<Vec<Node>>
+where
+    I: Iterator<Item = io::Result<u8>>,
+{
+    let mut nodes = Vec::new();
+    // the nodes of the enclosing loops, along with
+    // where the loop they contain starts.
+    let mut open = Vec::new();
+    for spanned in parse_spanned(input) {
+        let spanned = spanned?;
+        match spanned.node {
+            BFCommand::BeginLoop => open.push((std::mem::take(&mut nodes), spanned.start)),
+            BFCommand::EndLoop => {
+                // the parser already checks that brackets are balanced.
+                let (outer, start) = open.pop().unwrap();
+                let body = std::mem::replace(&mut nodes, outer);
+                nodes.push(Node::Loop(Spanned {
+                    node: body,
+                    start,
+                    end: spanned.end,
+                }));
+            }
+            _ => nodes.push(Node::Instruction(spanned)),
+        }
+    }
+    Ok(nodes)
+}
+
+/// A command, or the bytes between two commands, as found by [`parse_tokens`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Token {
+    Command(Spanned<BFCommand>),
+    /// an instruction of the extensions the parser was given, if any
+    Extension(Spanned<ExtCommand>),
+    /// everything between two commands, or before
+    /// the first one or after the last one
+    Comment(Spanned<Vec<u8>>),
+}
+
+/// Parses the input, yielding the comments along with the commands
+/// instead of skipping them, so the source can be rebuilt as it was.
+pub fn parse_tokens<I>(input: I) -> BFTokenIter<I>
+where
+    I: Iterator<Item = io::Result<u8>>,
+{
+    let mut parser = BFParser::new(input);
+    parser.comment = Some((parser.location(), Vec::new()));
+    BFTokenIter {
+        parser,
+        finished: false,
+    }
+}
+
+/// Parses the whole input, recovering from bracket errors instead of
+/// stopping at the first one, so all of them can be reported at once.
+/// Unmatched closings are skipped and unclosed openings are dropped,
+/// so the instructions are always balanced.
+pub fn parse_recovering<I>(input: I) -> io::Result<Recovered>
+where
+    I: Iterator<Item = io::Result<u8>>,
+{
+    parse_recovering_dialect(input, Dialect::default())
+}
+
+/// Parses the input like [`parse_recovering`], spelling the
+/// commands with the tokens of a [`Dialect`].
+pub fn parse_recovering_dialect<I>(input: I, dialect: Dialect) -> io::Result<Recovered>
+where
+    I: Iterator<Item = io::Result<u8>>,
+{
+    parse_recovering_with(input, dialect, PositionConfig::default())
+}
+
+/// Parses the input like [`parse_recovering_dialect`], counting
+/// the positions the way `positions` says.
+pub fn parse_recovering_with<I>(
+    input: I,
+    dialect: Dialect,
+    positions: PositionConfig,
+) -> io::Result<Recovered>
+where
+    I: Iterator<Item = io::Result<u8>>,
+{
+    recover(BFParser::with_options(input, dialect, positions), false)
+}
+
+/// Parses the whole input like [`parse_recovering`], but closes the
+/// unclosed loops at the end of the input instead of dropping them,
+/// which is closer to what a work in progress file means. The errors
+/// are only warnings then, since the instructions are always usable.
+pub fn parse_lenient<I>(input: I) -> io::Result<Recovered>
+where
+    I: Iterator<Item = io::Result<u8>>,
+{
+    recover(BFParser::new(input), true)
+}
+
+/// Parses the whole input, recording the errors. Unclosed loops
+/// are dropped, or closed at the end when `close_loops` is set.
+fn recover<I>(mut parser: BFParser<I>, close_loops: bool) -> io::Result<Recovered>
+where
+    I: Iterator<Item = io::Result<u8>>,
+{
+    parser.errors = Some(Vec::new());
+    let mut instructions = Vec::new();
+    while let Some(found) = parser.next_instruction().map_err(|e| match e {
+        IOParserErr::IO(e) => e,
+        IOParserErr::Parser(_) => unreachable!("parse errors are recorded while recovering"),
+    })? {
+        instructions.push(found);
+    }
+    let end = parser.location();
+    let errors = parser.errors.unwrap_or_default();
+    let unclosed: Vec<_> = errors
+        .iter()
+        .filter_map(|e| match e.kind {
+            ParseErrorKind::MissingRB(opened_at) => Some(opened_at),
+            ParseErrorKind::MissingLB => None,
+        })
+        .collect();
+    if close_loops {
+        instructions.extend(std::iter::repeat_n(
+            (BFCommand::EndLoop, end),
+            unclosed.len(),
+        ));
+    } else if !unclosed.is_empty() {
+        instructions.retain(|(instr, location)| {
+            *instr != BFCommand::BeginLoop || !unclosed.contains(&location.position)
+        });
+    }
+    Ok(Recovered {
+        instructions,
+        errors,
+        end,
+    })
+}
+
+/// The result of [`parse_recovering`] and [`parse_lenient`].
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Recovered {
+    pub instructions: Vec<(BFCommand, Location)>,
+    /// every error found, in the order they were found
+    pub errors: Vec<ParseError>,
+    /// where the input ends, right after its last byte
+    pub end: Location,
+}
+
+impl Recovered {
+    /// The instructions, as long as there were no errors.
+    pub fn into_result(self) -> std::result::Result<Vec<(BFCommand, Location)>, ParseErrors> {
+        if self.errors.is_empty() {
+            Ok(self.instructions)
+        } else {
+            Err(ParseErrors(self.errors))
+        }
+    }
+
+    /// The errors as diagnostics of the given severity, which would
+    /// be [`Warning`](Severity::Warning) for [`parse_lenient`].
+    pub fn diagnostics(&self, severity: Severity) -> Diagnostics {
+        self.errors
+            .iter()
+            .map(|e| e.diagnostic().with_severity(severity))
+            .collect()
+    }
+}
+
+/// All the errors found by [`parse_recovering`] or [`parse_lenient`].
+#[derive(Debug)]
+pub struct ParseErrors(pub Vec<ParseError>);
+
+impl fmt::Display for ParseErrors {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, e) in self.0.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "parse error: {}", e)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for ParseErrors {}
+
+struct BFParser<I>
+where
+    I: Iterator<Item = io::Result<u8>>,
+{
+    input: std::iter::Fuse<I>,
+    /// bytes read ahead to match the tokens of the dialect
+    lookahead: VecDeque<u8>,
+    dialect: Dialect,
+    /// the bytes that are instructions other than brainfuck's
+    extensions: Extensions,
+    current_position: Position,
+    byte_offset: usize,
+    loop_backlog: Vec<Position>,
+    /// when recovering from errors, the ones found so far
+    errors: Option<Vec<ParseError>>,
+    /// when keeping comments, the one being read and where it starts
+    comment: Option<(Location, Vec<u8>)>,
+    positions: PositionConfig,
+    /// the last byte consumed, to tell where UTF-8 characters start
+    last_byte: u8,
+}
+
+#[derive(Debug)]
+pub enum IOParserErr {
+    IO(io::Error),
+    Parser(ParseError),
+}
+
+impl fmt::Display for IOParserErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::IO(e) => write!(f, "an IO error occurred while trying to read bytes: {}", e),
+            Self::Parser(p) => write!(f, "parse error: {}", p),
+        }
+    }
+}
+
+impl Error for IOParserErr {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(match self {
+            Self::IO(e) => e,
+            Self::Parser(e) => e,
+        })
+    }
+}
+
+impl<I> BFParser<I>
+where
+    I: Iterator<Item = io::Result<u8>>,
+{
+    #[inline]
+    /// Starts parsing, setting the initial position to `start_pos`
+    fn starting_at(input: I, start_pos: Position) -> Self {
+        Self {
+            input: Iterator::fuse(input),
+            lookahead: VecDeque::new(),
+            dialect: Dialect::default(),
+            extensions: Extensions::default(),
+            current_position: start_pos,
+            byte_offset: 0,
+            loop_backlog: Vec::new(),
+            errors: None,
+            comment: None,
+            positions: PositionConfig::default(),
+            last_byte: 0,
+        }
+    }
+    #[inline]
+    fn new(input: I) -> Self {
+        Self::starting_at(input, Position::default())
+    }
+    #[inline]
+    fn with_dialect(input: I, dialect: Dialect) -> Self {
+        Self {
+            dialect,
+            ..Self::new(input)
+        }
+    }
+    #[inline]
+    fn with_options(input: I, dialect: Dialect, positions: PositionConfig) -> Self {
+        Self {
+            dialect,
+            positions,
+            ..Self::starting_at(input, Position::start(&positions))
+        }
+    }
+    fn next_instruction(&mut self) -> Result<Option<(BFCommand, Location)>> {
+        loop {
+            match self.next_token()? {
+                Some(Token::Command(command)) => return Ok(Some((command.node, command.start))),
+                Some(Token::Comment(_)) | Some(Token::Extension(_)) => continue,
+                None => return Ok(None),
+            }
+        }
+    }
+    fn next_extended(&mut self) -> Result<Option<(ExtCommand, Location)>> {
+        loop {
+            match self.next_token()? {
+                Some(Token::Command(command)) => {
+                    return Ok(Some((ExtCommand::Base(command.node), command.start)))
+                }
+                Some(Token::Extension(command)) => return Ok(Some((command.node, command.start))),
+                Some(Token::Comment(_)) => continue,
+                None => return Ok(None),
+            }
+        }
+    }
+    /// The next command, or the comment before it when keeping comments.
+    fn next_token(&mut self) -> Result<Option<Token>> {
+        loop {
+            let location = self.location();
+            let (input, lookahead) = (&mut self.input, &mut self.lookahead);
+            let found = self
+                .dialect
+                .match_token(|i| read_ahead(input, lookahead, i))?;
+            let (instr, len) = match found {
+                Some(found) => found,
+                None => match read_ahead(&mut self.input, &mut self.lookahead, 0)? {
+                    Some(byte) => {
+                        if let Some(command) = self.extensions.command(byte) {
+                            if let Some(comment) = self.take_comment() {
+                                return Ok(Some(Token::Comment(comment)));
+                            }
+                            self.consume(1);
+                            return Ok(Some(Token::Extension(Spanned {
+                                node: command,
+                                start: location,
+                                end: self.location(),
+                            })));
+                        }
+                        // anything else is a comment
+                        if let Some((start, comment)) = &mut self.comment {
+                            if comment.is_empty() {
+                                *start = location;
+                            }
+                            comment.push(byte);
+                        }
+                        self.consume(1);
+                        continue;
+                    }
+                    None => break,
+                },
+            };
+            if let Some(comment) = self.take_comment() {
+                return Ok(Some(Token::Comment(comment)));
+            }
+
+            // make sure we're matching loops correctly.
+            let unmatched = match instr {
+                BFCommand::BeginLoop => {
+                    self.loop_backlog.push(self.current_position);
+                    false
+                }
+                BFCommand::EndLoop => self.loop_backlog.pop().is_none(),
+                _ => false,
+            };
+
+            self.consume(len);
+            if unmatched {
+                let error = ParseError::new(ParseErrorKind::MissingLB, location, self.location());
+                match &mut self.errors {
+                    // skip it and go on
+                    Some(errors) => errors.push(error),
+                    None => return Err(IOParserErr::Parser(error)),
+                }
+            } else {
+                return Ok(Some(Token::Command(Spanned {
+                    node: instr,
+                    start: location,
+                    end: self.location(),
+                })));
+            }
+        }
+
+        if let Some(comment) = self.take_comment() {
+            return Ok(Some(Token::Comment(comment)));
+        }
+        // on EOF, there should be no dangling loops
+        let end = self.location();
+        if let Some(errors) = &mut self.errors {
+            errors.extend(
+                self.loop_backlog
+                    .drain(..)
+                    .map(|lb_pos| ParseError::at(ParseErrorKind::MissingRB(lb_pos), end)),
+            );
+            Ok(None)
+        } else if let Some(lb_pos) = self.loop_backlog.pop() {
+            Err(self.error(ParseErrorKind::MissingRB(lb_pos)))
+        } else {
+            Ok(None)
+        }
+    }
+    #[inline]
+    fn location(&self) -> Location {
+        Location {
+            position: self.current_position,
+            byte_offset: self.byte_offset,
+        }
+    }
+    /// the comment found so far, if keeping comments and there's any
+    fn take_comment(&mut self) -> Option<Spanned<Vec<u8>>> {
+        let end = self.location();
+        match &mut self.comment {
+            Some((start, comment)) if !comment.is_empty() => Some(Spanned {
+                node: std::mem::take(comment),
+                start: *start,
+                end,
+            }),
+            _ => None,
+        }
+    }
+    /// move past `len` bytes that were read ahead
+    fn consume(&mut self, len: usize) {
+        for byte in self.lookahead.drain(..len) {
+            self.current_position
+                .advance_byte_with(byte, self.last_byte, &self.positions);
+            self.last_byte = byte;
+            self.byte_offset += 1;
+        }
+    }
+    #[inline]
+    fn error(&self, kind: ParseErrorKind) -> IOParserErr {
+        IOParserErr::Parser(ParseError::at(kind, self.location()))
+    }
+}
+
+/// The byte `i` bytes ahead of the last consumed one, reading as many as needed.
+fn read_ahead<I>(
+    input: &mut std::iter::Fuse<I>,
+    lookahead: &mut VecDeque<u8>,
+    i: usize,
+) -> Result<Option<u8>>
+where
+    I: Iterator<Item = io::Result<u8>>,
+{
+    while lookahead.len() <= i {
+        match input.next() {
+            Some(byte) => lookahead.push_back(byte.map_err(IOParserErr::IO)?),
+            None => return Ok(None),
+        }
+    }
+    Ok(Some(lookahead[i]))
+}
+
+pub struct BFParserIter<I>
+where
+    I: Iterator<Item = io::Result<u8>>,
+{
+    inner: BFLocatedParserIter<I>,
+}
+
+impl<I> Iterator for BFParserIter<I>
+where
+    I: Iterator<Item = io::Result<u8>>,
+{
+    type Item = Result<BFCommand>;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner
+            .next()
+            .map(|res| res.map(|(instruction, _)| instruction))
+    }
+}
+
+impl<I> std::iter::FusedIterator for BFParserIter<I> where I: Iterator<Item = io::Result<u8>> {}
+
+pub struct BFSpannedParserIter<I>
+where
+    I: Iterator<Item = io::Result<u8>>,
+{
+    inner: BFLocatedParserIter<I>,
+}
+
+impl<I> Iterator for BFSpannedParserIter<I>
+where
+    I: Iterator<Item = io::Result<u8>>,
+{
+    type Item = Result<Spanned<BFCommand>>;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|res| {
+            res.map(|(instruction, location)| Spanned {
+                node: instruction,
+                start: location,
+                end: location.after_command(),
+            })
+        })
+    }
+}
+
+impl<I> std::iter::FusedIterator for BFSpannedParserIter<I> where I: Iterator<Item = io::Result<u8>> {}
+
+pub struct BFEventIter<I>
+where
+    I: Iterator<Item = io::Result<u8>>,
+{
+    inner: BFLocatedParserIter<I>,
+}
+
+impl<I> Iterator for BFEventIter<I>
+where
+    I: Iterator<Item = io::Result<u8>>,
+{
+    type Item = Result<Event>;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|res| {
+            res.map(|(instruction, location)| match instruction {
+                BFCommand::BeginLoop => Event::LoopEnter(location),
+                BFCommand::EndLoop => Event::LoopExit(location),
+                _ => Event::Op(instruction, location),
+            })
+        })
+    }
+}
+
+impl<I> std::iter::FusedIterator for BFEventIter<I> where I: Iterator<Item = io::Result<u8>> {}
+
+pub struct BFTokenIter<I>
+where
+    I: Iterator<Item = io::Result<u8>>,
+{
+    parser: BFParser<I>,
+    finished: bool,
+}
+
+impl<I> Iterator for BFTokenIter<I>
+where
+    I: Iterator<Item = io::Result<u8>>,
+{
+    type Item = Result<Token>;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            None
+        } else {
+            let res = self.parser.next_token();
+            if matches!(res, Ok(None) | Err(_)) {
+                self.finished = true;
+            }
+            res.transpose()
+        }
+    }
+}
+
+impl<I> std::iter::FusedIterator for BFTokenIter<I> where I: Iterator<Item = io::Result<u8>> {}
+
+pub struct BFLocatedParserIter<I>
+where
+    I: Iterator<Item = io::Result<u8>>,
+{
+    parser: BFParser<I>,
+    finished: bool,
+}
+
+impl<I> Iterator for BFLocatedParserIter<I>
+where
+    I: Iterator<Item = io::Result<u8>>,
+{
+    type Item = Result<(BFCommand, Location)>;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            None
+        } else {
+            let res = self.parser.next_instruction();
+            if matches!(res, Ok(None) | Err(_)) {
+                self.finished = true;
+            }
+            res.transpose()
+        }
+    }
+}
+
+impl<I> std::iter::FusedIterator for BFLocatedParserIter<I> where I: Iterator<Item = io::Result<u8>> {}
+
+pub struct BFExtendedParserIter<I>
+where
+    I: Iterator<Item = io::Result<u8>>,
+{
+    parser: BFParser<I>,
+    finished: bool,
+}
+
+impl<I> Iterator for BFExtendedParserIter<I>
+where
+    I: Iterator<Item = io::Result<u8>>,
+{
+    type Item = Result<(ExtCommand, Location)>;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            None
+        } else {
+            let res = self.parser.next_extended();
+            if matches!(res, Ok(None) | Err(_)) {
+                self.finished = true;
+            }
+            res.transpose()
+        }
+    }
+}
+
+impl<I> std::iter::FusedIterator for BFExtendedParserIter<I> where I: Iterator<Item = io::Result<u8>>
+{}
+
+impl<I> BFParser<I>
+where
+    I: Iterator<Item = io::Result<u8>>,
+{
+    fn into_located_iter(self) -> BFLocatedParserIter<I> {
+        BFLocatedParserIter {
+            parser: self,
+            finished: false,
+        }
+    }
+}
+
+impl<I> IntoIterator for BFParser<I>
+where
+    I: Iterator<Item = io::Result<u8>>,
+{
+    type IntoIter = BFParserIter<I>;
+    type Item = <Self::IntoIter as Iterator>::Item;
+    fn into_iter(self) -> Self::IntoIter {
+        BFParserIter {
+            inner: self.into_located_iter(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_bytes;
+
+    fn locations(src: &str) -> Vec<Location> {
+        parse_located(src.bytes().map(Ok))
+            .map(|res| res.unwrap().1)
+            .collect()
+    }
+
+    fn at(line: usize, column: usize, byte_offset: usize) -> Location {
+        Location {
+            position: Position { line, column },
+            byte_offset,
+        }
+    }
+
+    #[test]
+    fn instructions_are_located_past_comments() {
+        assert_eq!(
+            locations("+ a\n-\n\n[]"),
+            [at(1, 1, 0), at(2, 1, 4), at(4, 1, 7), at(4, 2, 8)]
+        );
+        assert_eq!(at(4, 2, 8).after_command(), at(4, 3, 9));
+    }
+
+    #[test]
+    fn instructions_span_their_byte() {
+        let spanned: Vec<_> = parse_spanned("a+\n ]".bytes().map(Ok)).collect();
+        assert!(matches!(
+            &spanned[0],
+            Ok(Spanned { node: BFCommand::Increment, start, end })
+                if *start == at(1, 2, 1) && *end == at(1, 3, 2)
+        ));
+        // errors come through, covering the unmatched bracket
+        assert!(matches!(
+            &spanned[1],
+            Err(IOParserErr::Parser(e)) if matches!(e.kind, ParseErrorKind::MissingLB)
+                && e.start == at(2, 2, 4) && e.end == at(2, 3, 5)
+        ));
+        assert_eq!(spanned.len(), 2);
+    }
+
+    fn text(instructions: &[(BFCommand, Location)]) -> String {
+        instructions
+            .iter()
+            .map(|&(instr, _)| instr as u8 as char)
+            .collect()
+    }
+
+    #[test]
+    fn every_bracket_error_is_recovered_from() {
+        let recovered = parse_recovering("+]-[>[<]\n.".bytes().map(Ok)).unwrap();
+        // the unmatched closings are skipped, and the unclosed opening dropped
+        assert_eq!(text(&recovered.instructions), "+->[<].");
+        let errors: Vec<_> = recovered.errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            errors,
+            [
+                "1:2: Unmatched loop closing",
+                "2:2: Unclosed loop: last opening was found at 1:4",
+            ]
+        );
+        assert_eq!(recovered.end, at(2, 2, 10));
+        assert!(recovered.into_result().is_err());
+
+        let fine = parse_recovering("+[-]".bytes().map(Ok)).unwrap();
+        assert_eq!(text(&fine.into_result().unwrap()), "+[-]");
+    }
+
+    #[test]
+    fn loops_hold_their_bodies() {
+        let tree = parse_tree("+[->[-]<] .".bytes().map(Ok)).unwrap();
+        assert_eq!(tree.len(), 3);
+        let body = match &tree[1] {
+            Node::Loop(body) => body,
+            node => panic!("expected a loop, got {:?}", node),
+        };
+        assert_eq!((body.start, body.end), (at(1, 2, 1), at(1, 10, 9)));
+        assert_eq!(body.node.len(), 4);
+        assert!(matches!(&body.node[2], Node::Loop(inner) if inner.start == at(1, 5, 4)));
+        let text: String = tree.iter().map(|node| node.to_string()).collect();
+        assert_eq!(text, "+[->[-]<].");
+
+        assert!(parse_tree("[[]".bytes().map(Ok)).is_err());
+    }
+
+    #[test]
+    fn dialects_are_located_at_their_tokens() {
+        let src = "Ook. Ook?\nOok! Ook? Ook? Ook!";
+        let located: Vec<_> = parse_located_dialect(src.bytes().map(Ok), Dialect::ook())
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(
+            located,
+            [
+                (BFCommand::Right, at(1, 1, 0)),
+                (BFCommand::BeginLoop, at(2, 1, 10)),
+                (BFCommand::EndLoop, at(2, 11, 20)),
+            ]
+        );
+        // errors cover the whole token
+        let unmatched = parse_dialect("Ook? Ook!".bytes().map(Ok), Dialect::ook())
+            .collect::<Result<Vec<_>>>()
+            .unwrap_err();
+        assert!(matches!(
+            unmatched,
+            IOParserErr::Parser(e) if e.start == at(1, 1, 0) && e.end == at(1, 10, 9)
+        ));
+    }
+
+    #[test]
+    fn tokens_keep_the_comments() {
+        let src = "a +\n[-] ";
+        let tokens: Vec<_> = parse_tokens(src.bytes().map(Ok))
+            .map(Result::unwrap)
+            .collect();
+        let rebuilt: Vec<u8> = tokens
+            .iter()
+            .flat_map(|token| match token {
+                Token::Command(command) => vec![command.node as u8],
+                Token::Comment(comment) => comment.node.clone(),
+                Token::Extension(_) => panic!("no extensions were asked for"),
+            })
+            .collect();
+        assert_eq!(rebuilt, src.as_bytes());
+        assert_eq!(tokens.len(), 7);
+        assert_eq!(
+            tokens[2],
+            Token::Comment(Spanned {
+                node: b"\n".to_vec(),
+                start: at(1, 4, 3),
+                end: at(2, 1, 4),
+            })
+        );
+        assert!(matches!(&tokens[6], Token::Comment(comment) if comment.node == b" "));
+    }
+
+    #[test]
+    fn events_follow_the_loops() {
+        let events: Vec<_> = parse_events("+[\n.]".bytes().map(Ok))
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(
+            events,
+            [
+                Event::Op(BFCommand::Increment, at(1, 1, 0)),
+                Event::LoopEnter(at(1, 2, 1)),
+                Event::Op(BFCommand::Print, at(2, 1, 3)),
+                Event::LoopExit(at(2, 2, 4)),
+            ]
+        );
+        let unclosed: Vec<_> = parse_events("[+".bytes().map(Ok)).collect();
+        assert!(matches!(unclosed.last(), Some(Err(IOParserErr::Parser(_)))));
+    }
+
+    #[test]
+    fn lenient_parsing_closes_loops_at_the_end() {
+        let recovered = parse_lenient("+[-[>".bytes().map(Ok)).unwrap();
+        assert_eq!(text(&recovered.instructions), "+[-[>]]");
+        // the closings are found at the end of the input
+        assert_eq!(recovered.instructions[5].1, at(1, 6, 5));
+        assert_eq!(recovered.errors.len(), 2);
+        let diagnostics = recovered.diagnostics(Severity::Warning);
+        assert!(diagnostics
+            .iter()
+            .all(|diagnostic| diagnostic.severity == Severity::Warning));
+        // unmatched closings are still skipped
+        let recovered = parse_lenient("+]".bytes().map(Ok)).unwrap();
+        assert_eq!(text(&recovered.instructions), "+");
+    }
+
+    #[test]
+    fn streams_agree_with_sources_in_memory() {
+        for &src in ["", "+[-]>", "a\n\t[é]\n.", "]", "+\n[[-]", "[]]"].iter() {
+            let streamed: Result<Vec<_>> = parse(src.bytes().map(Ok)).collect();
+            match (streamed, parse_bytes(src.as_bytes())) {
+                (Ok(streamed), Ok(in_memory)) => assert_eq!(streamed, in_memory, "{:?}", src),
+                (Err(IOParserErr::Parser(streamed)), Err(in_memory)) => {
+                    assert_eq!(streamed.to_string(), in_memory.to_string(), "{:?}", src);
+                    assert_eq!(
+                        (streamed.start, streamed.end),
+                        (in_memory.start, in_memory.end),
+                        "{:?}",
+                        src
+                    );
+                }
+                (streamed, in_memory) => panic!("{:?}: {:?} and {:?}", src, streamed, in_memory),
+            }
+        }
+    }
+
+    #[test]
+    fn positions_are_counted_as_configured() {
+        let config = PositionConfig {
+            tab_width: 4,
+            zero_based: true,
+        };
+        let located: Vec<_> =
+            parse_located_with("\t+\n \t-]".bytes().map(Ok), Dialect::default(), config).collect();
+        assert!(matches!(located[0], Ok((BFCommand::Increment, l)) if l == at(0, 4, 1)));
+        assert!(matches!(located[1], Ok((BFCommand::Decrement, l)) if l == at(1, 4, 5)));
+        // errors are located the same way
+        assert!(matches!(
+            &located[2],
+            Err(IOParserErr::Parser(e)) if e.start == at(1, 5, 6)
+        ));
+    }
+
+    #[test]
+    fn columns_count_characters() {
+        assert_eq!(locations("é+ → -"), [at(1, 2, 2), at(1, 6, 8)]);
+        let error = parse_bytes("ü\n½]".as_bytes()).unwrap_err();
+        assert_eq!(error.start, at(2, 2, 5));
+    }
+
+    #[test]
+    fn recovered_errors_as_diagnostics() {
+        let recovered = parse_lenient("+[\n]]".bytes().map(Ok)).unwrap();
+        let diagnostics = recovered.diagnostics(Severity::Warning);
+        assert!(!diagnostics.has_errors());
+        let diagnostic = diagnostics.iter().next().unwrap();
+        assert_eq!(diagnostic.code, Some("BF0001"));
+        assert_eq!(diagnostic.position, Some(Position { line: 2, column: 2 }));
+    }
+
+    #[test]
+    fn extensions_are_instructions_instead_of_comments() {
+        let src = "+ #x!.#";
+        let extensions = Extensions::builtin("input").unwrap();
+        let parsed: Vec<_> = parse_extended(src.bytes().map(Ok), extensions)
+            .map(Result::unwrap)
+            .collect();
+        let expected = [
+            (ExtCommand::Base(BFCommand::Increment), at(1, 1, 0)),
+            (ExtCommand::InputSeparator, at(1, 5, 4)),
+            (ExtCommand::Base(BFCommand::Print), at(1, 6, 5)),
+        ];
+        assert_eq!(parsed, expected);
+        // the plain parser skips them like before
+        assert_eq!(locations(src), [at(1, 1, 0), at(1, 6, 5)]);
+    }
+
+    #[test]
+    fn trees_are_written_as_brainfuck() {
+        let tree = parse_tree("+ [a[-]>] .".bytes().map(Ok)).unwrap();
+        let written: Vec<_> = tree.iter().map(ToString::to_string).collect();
+        assert_eq!(written, ["+", "[[-]>]", "."]);
+    }
+}
@@ -0,0 +1,173 @@
+//! A parsed program, along with what every tool working on one needs:
+//! where its instructions are in the source, and the bracket every
+//! bracket jumps to.
+
+use super::parser::{self, ParseError};
+use super::{BFCommand, Location};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use core::str::FromStr;
+
+/// The instructions of a program, with their loops balanced. It can be
+/// parsed from a string and written back as plain brainfuck, without
+/// the comments:
+/// ```
+/// use bfrs_common::program::Program;
+///
+/// let program: Program = "++ copy [->+<]".parse().unwrap();
+/// assert_eq!(program.jump(2), 7);
+/// assert_eq!(program.to_string(), "++[->+<]");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Program {
+    pub instructions: Vec<BFCommand>,
+    /// where every instruction is in the source, when it was parsed from one
+    pub locations: Option<Vec<Location>>,
+    /// the index of the bracket matching every bracket, and 0 for the rest
+    jumps: Vec<usize>,
+}
+
+impl Program {
+    /// A program out of instructions that weren't parsed from a source.
+    ///
+    /// # Panics
+    /// If its loops aren't balanced.
+    pub fn new(instructions: Vec<BFCommand>) -> Self {
+        let jumps = jumps(&instructions);
+        Self {
+            instructions,
+            locations: None,
+            jumps,
+        }
+    }
+
+    /// A program out of instructions along with where they are in the source,
+    /// like the ones of [`parser::parse_located`] or [`parser::Recovered`].
+    ///
+    /// # Panics
+    /// If its loops aren't balanced.
+    pub fn located(located: Vec<(BFCommand, Location)>) -> Self {
+        let (instructions, locations) = located.into_iter().unzip();
+        Self {
+            locations: Some(locations),
+            ..Self::new(instructions)
+        }
+    }
+
+    /// Parses a whole source already in memory, without the locations.
+    pub fn parse_bytes(src: &[u8]) -> Result<Self, ParseError> {
+        parser::parse_bytes(src).map(Self::new)
+    }
+
+    /// Parses a stream of bytes, keeping the location of every instruction.
+    #[cfg(feature = "std")]
+    pub fn parse_located<I>(input: I) -> parser::Result<Self>
+    where
+        I: Iterator<Item = std::io::Result<u8>>,
+    {
+        parser::parse_located(input)
+            .collect::<parser::Result<_>>()
+            .map(Self::located)
+    }
+
+    pub fn len(&self) -> usize {
+        self.instructions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instructions.is_empty()
+    }
+
+    /// The index of the bracket matching the one at `index`.
+    #[inline]
+    pub fn jump(&self, index: usize) -> usize {
+        debug_assert!(matches!(
+            self.instructions[index],
+            BFCommand::BeginLoop | BFCommand::EndLoop
+        ));
+        self.jumps[index]
+    }
+
+    /// Where the instruction at `index` is in the source, if it's known.
+    pub fn location(&self, index: usize) -> Option<Location> {
+        self.locations.as_ref()?.get(index).copied()
+    }
+}
+
+impl FromStr for Program {
+    type Err = ParseError;
+    fn from_str(src: &str) -> Result<Self, Self::Err> {
+        Self::parse_bytes(src.as_bytes())
+    }
+}
+
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for instruction in self.instructions.iter() {
+            write!(f, "{}", instruction)?;
+        }
+        Ok(())
+    }
+}
+
+/// The index of the bracket matching every bracket.
+fn jumps(instructions: &[BFCommand]) -> Vec<usize> {
+    let mut jumps = vec![0; instructions.len()];
+    let mut open = Vec::new();
+    for (i, &instruction) in instructions.iter().enumerate() {
+        match instruction {
+            BFCommand::BeginLoop => open.push(i),
+            BFCommand::EndLoop => {
+                let start = open.pop().expect("the loops of a program are balanced");
+                jumps[start] = i;
+                jumps[i] = start;
+            }
+            _ => (),
+        }
+    }
+    assert!(open.is_empty(), "the loops of a program are balanced");
+    jumps
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    #[test]
+    fn brackets_jump_to_each_other() {
+        let program = Program::parse_bytes(b"+[>[-]<]").unwrap();
+        assert_eq!((program.jump(1), program.jump(7)), (7, 1));
+        assert_eq!((program.jump(3), program.jump(5)), (5, 3));
+        assert_eq!(program.len(), 8);
+        assert_eq!(program.location(0), None);
+        assert!(Program::parse_bytes(b"+]").is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "the loops of a program are balanced")]
+    fn unbalanced_programs_panic() {
+        Program::new(vec![BFCommand::BeginLoop]);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn programs_keep_where_their_instructions_are() {
+        let program = Program::parse_located(b"+\n [-]".iter().map(|&byte| Ok(byte))).unwrap();
+        let location = program.location(2).unwrap();
+        assert_eq!((location.position.line, location.position.column), (2, 3));
+        assert_eq!(location.byte_offset, 4);
+        assert_eq!(program.location(4), None);
+        assert_eq!(program.jump(1), 3);
+    }
+
+    #[test]
+    fn programs_are_written_without_their_comments() {
+        let program: Program = "read and print a byte: ,.".parse().unwrap();
+        assert_eq!(program.to_string(), ",.");
+        assert_eq!(program, Program::parse_bytes(b",.").unwrap());
+        assert!("[".parse::<Program>().is_err());
+    }
+}
@@ -0,0 +1,120 @@
+//! Programs as runs of the same command, like `+++>` as `+` three times
+//! and then `>` once, which is how most tools working on a program want
+//! to see its arithmetic and its movement.
+//!
+//! [`runs`] folds the commands into runs and [`expand`] spells them back:
+//! ```
+//! use bfrs_common::runs::{expand, runs};
+//! use bfrs_common::BFCommand::{self, *};
+//!
+//! let program = [Increment, Increment, Increment, Right, Increment];
+//! let folded: Vec<_> = runs(program.iter().copied()).collect();
+//! assert_eq!(folded, [(Increment, 3), (Right, 1), (Increment, 1)]);
+//! let expanded: Vec<BFCommand> = expand(folded).collect();
+//! assert_eq!(expanded, program);
+//! ```
+
+use super::BFCommand;
+use core::iter::{FusedIterator, Peekable};
+
+/// The commands of a program folded into runs of the same command.
+pub fn runs<I>(commands: I) -> Runs<I::IntoIter>
+where
+    I: IntoIterator<Item = BFCommand>,
+{
+    Runs {
+        commands: commands.into_iter().peekable(),
+    }
+}
+
+/// The commands of a program folded into runs, spelled one by one.
+/// Runs of no commands are skipped.
+pub fn expand<I>(runs: I) -> Expand<I::IntoIter>
+where
+    I: IntoIterator<Item = (BFCommand, usize)>,
+{
+    Expand {
+        runs: runs.into_iter(),
+        current: None,
+    }
+}
+
+/// The iterator of [`runs`], yielding every command along
+/// with how many times in a row it's there.
+#[derive(Debug, Clone)]
+pub struct Runs<I: Iterator<Item = BFCommand>> {
+    commands: Peekable<I>,
+}
+
+impl<I: Iterator<Item = BFCommand>> Iterator for Runs<I> {
+    type Item = (BFCommand, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let command = self.commands.next()?;
+        let mut count = 1;
+        while self.commands.next_if_eq(&command).is_some() {
+            count += 1;
+        }
+        Some((command, count))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (min, max) = self.commands.size_hint();
+        (min.min(1), max)
+    }
+}
+
+impl<I: FusedIterator<Item = BFCommand>> FusedIterator for Runs<I> {}
+
+/// The iterator of [`expand`].
+#[derive(Debug, Clone)]
+pub struct Expand<I: Iterator<Item = (BFCommand, usize)>> {
+    runs: I,
+    /// the run being spelled, and how many of its commands are left
+    current: Option<(BFCommand, usize)>,
+}
+
+impl<I: Iterator<Item = (BFCommand, usize)>> Iterator for Expand<I> {
+    type Item = BFCommand;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.current {
+                Some((command, left)) if left > 0 => {
+                    self.current = Some((command, left - 1));
+                    return Some(command);
+                }
+                _ => self.current = Some(self.runs.next()?),
+            }
+        }
+    }
+}
+
+impl<I: FusedIterator<Item = (BFCommand, usize)>> FusedIterator for Expand<I> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+    use BFCommand::*;
+
+    #[test]
+    fn every_command_is_folded() {
+        let program = [BeginLoop, BeginLoop, Decrement, EndLoop, EndLoop, Print];
+        let folded: Vec<_> = runs(program.iter().copied()).collect();
+        assert_eq!(
+            folded,
+            [(BeginLoop, 2), (Decrement, 1), (EndLoop, 2), (Print, 1)]
+        );
+        assert_eq!(runs(core::iter::empty()).next(), None);
+        // at least one run, and at most one for every command
+        assert_eq!(runs(program.iter().copied()).size_hint(), (1, Some(6)));
+    }
+
+    #[test]
+    fn empty_runs_are_skipped() {
+        let expanded: Vec<_> = expand([(Left, 0), (Read, 2), (Left, 0)]).collect();
+        assert_eq!(expanded, [Read, Read]);
+        assert_eq!(expand(core::iter::empty()).next(), None);
+    }
+}
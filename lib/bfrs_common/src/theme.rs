@@ -0,0 +1,287 @@
+//! The colors programs are printed with in a terminal: the highlighter
+//! colors every command, and the brackets by how deep they are, and the
+//! matches of a search stand out from the source around them.
+//!
+//! A few themes are built in, and [`Theme::builtin`] finds them by name.
+//! ```
+//! use bfrs_common::theme::{Color, Theme};
+//!
+//! let theme = Theme::builtin("default").unwrap();
+//! assert_eq!(theme.paint(Some(Color::Indexed(1)), None, true), "\x1b[0;1;38;5;1m");
+//! assert_eq!("#ff0000".parse::<Color>().unwrap().indexed(), 196);
+//! ```
+
+use crate::BFCommand;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::str::FromStr;
+
+/// Ends whatever an escape sequence of a theme started.
+pub const RESET: &str = "\x1b[0m";
+
+/// The colors with a name, which are the first 16 of the palette.
+const NAMES: [&str; 16] = [
+    "black",
+    "red",
+    "green",
+    "yellow",
+    "blue",
+    "magenta",
+    "cyan",
+    "white",
+    "bright-black",
+    "bright-red",
+    "bright-green",
+    "bright-yellow",
+    "bright-blue",
+    "bright-magenta",
+    "bright-cyan",
+    "bright-white",
+];
+
+/// A color of the terminal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Color {
+    /// one of the 256 of the palette of the terminal, whose first 16
+    /// are the ones with a name, like `red` or `bright-blue`
+    Indexed(u8),
+    /// a 24 bit color, like `#268bd2`, which terminals without
+    /// them show as the closest one of the palette
+    Rgb(u8, u8, u8),
+}
+
+impl Color {
+    /// The closest color of the 256 of the palette.
+    pub fn indexed(self) -> u8 {
+        let (r, g, b) = match self {
+            Self::Indexed(index) => return index,
+            Self::Rgb(r, g, b) => (r, g, b),
+        };
+        if r == g && g == b {
+            // the grays have a ramp of their own, between black and white.
+            return match r {
+                0..=7 => 16,
+                249..=255 => 231,
+                _ => 232 + (r - 8) / 10,
+            };
+        }
+        // the rest is a cube of 6 levels of red, green and blue.
+        let level = |v: u8| match v {
+            0..=47 => 0,
+            48..=114 => 1,
+            _ => (v - 35) / 40,
+        };
+        16 + 36 * level(r) + 6 * level(g) + level(b)
+    }
+
+    /// The parameters of the escape sequence setting this color,
+    /// `layer` being 38 for the text and 48 for the background.
+    fn sgr(self, layer: u8, true_color: bool) -> String {
+        match self {
+            Self::Rgb(r, g, b) if true_color => format!("{};2;{};{};{}", layer, r, g, b),
+            color => format!("{};5;{}", layer, color.indexed()),
+        }
+    }
+}
+
+impl FromStr for Color {
+    type Err = UnknownColor;
+
+    /// A name, a number of the palette, or `#rrggbb`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let unknown = || UnknownColor(s.into());
+        if let Some(hex) = s.strip_prefix('#') {
+            if hex.len() != 6 || !hex.is_ascii() {
+                return Err(unknown());
+            }
+            let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| unknown());
+            return Ok(Self::Rgb(channel(0)?, channel(2)?, channel(4)?));
+        }
+        if let Some(index) = NAMES.iter().position(|&name| name == s) {
+            return Ok(Self::Indexed(index as u8));
+        }
+        s.parse().map(Self::Indexed).map_err(|_| unknown())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownColor(String);
+
+impl fmt::Display for UnknownColor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "unknown color `{}`: expected a name like `red`, a number up to 255 or `#rrggbb`",
+            self.0
+        )
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for UnknownColor {}
+
+/// The colors of a theme.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Theme {
+    /// the color of every command with one, other than the brackets,
+    /// over the one of the depth it's at
+    pub commands: Vec<(BFCommand, Color)>,
+    /// the colors of the brackets and of the commands between them,
+    /// from the top level in, starting over once they run out
+    pub depths: Vec<Color>,
+    /// the text and the background of the matches of a search,
+    /// which are bold too
+    pub matched: (Option<Color>, Option<Color>),
+    /// the numbers of the lines around the matches
+    pub line_number: Option<Color>,
+    /// whether the terminal shows 24 bit colors
+    pub true_color: bool,
+}
+
+impl Theme {
+    /// The names of the built-in themes.
+    pub const BUILTIN: &'static [&'static str] = &["default", "solarized", "mono"];
+
+    /// The built-in theme called `name`, if there's one.
+    pub fn builtin(name: &str) -> Option<Self> {
+        let rgb = |hex: &str| hex.parse::<Color>().expect("the built-in colors are valid");
+        Some(match name {
+            "default" => Self {
+                depths: [6, 5, 4, 3, 2, 1]
+                    .iter()
+                    .map(|&i| Color::Indexed(i))
+                    .collect(),
+                matched: (Some(Color::Indexed(1)), None),
+                line_number: Some(Color::Indexed(2)),
+                ..Self::default()
+            },
+            "solarized" => Self {
+                commands: [
+                    (BFCommand::Increment, "#859900"),
+                    (BFCommand::Decrement, "#dc322f"),
+                    (BFCommand::Right, "#268bd2"),
+                    (BFCommand::Left, "#268bd2"),
+                    (BFCommand::Print, "#b58900"),
+                    (BFCommand::Read, "#d33682"),
+                ]
+                .iter()
+                .map(|&(command, hex)| (command, rgb(hex)))
+                .collect(),
+                depths: ["#93a1a1", "#2aa198", "#6c71c4", "#cb4b16"]
+                    .iter()
+                    .map(|hex| rgb(hex))
+                    .collect(),
+                matched: (Some(rgb("#fdf6e3")), Some(rgb("#cb4b16"))),
+                line_number: Some(rgb("#586e75")),
+                ..Self::default()
+            },
+            // only the matches stand out, in bold.
+            "mono" => Self::default(),
+            _ => return None,
+        })
+    }
+
+    /// The color of `command` at `depth`, which for a bracket is the
+    /// depth of the loop it opens or closes.
+    pub fn color_of(&self, command: BFCommand, depth: usize) -> Option<Color> {
+        let own = match command {
+            BFCommand::BeginLoop | BFCommand::EndLoop => None,
+            _ => self.commands.iter().find(|(c, _)| *c == command),
+        };
+        own.map(|&(_, color)| color)
+            .or_else(|| self.depths.get(depth % self.depths.len().max(1)).copied())
+    }
+
+    /// The escape sequence printing in `foreground` on `background`,
+    /// in bold if it's `bold`, which starts by undoing the ones before.
+    pub fn paint(
+        &self,
+        foreground: Option<Color>,
+        background: Option<Color>,
+        bold: bool,
+    ) -> String {
+        let mut sgr = String::from("\x1b[0");
+        if bold {
+            sgr += ";1";
+        }
+        if let Some(color) = foreground {
+            sgr = sgr + ";" + &color.sgr(38, self.true_color);
+        }
+        if let Some(color) = background {
+            sgr = sgr + ";" + &color.sgr(48, self.true_color);
+        }
+        sgr + "m"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    #[test]
+    fn colors_parse() {
+        assert_eq!("red".parse(), Ok(Color::Indexed(1)));
+        assert_eq!("bright-white".parse(), Ok(Color::Indexed(15)));
+        assert_eq!("208".parse(), Ok(Color::Indexed(208)));
+        assert_eq!("#268bd2".parse(), Ok(Color::Rgb(0x26, 0x8b, 0xd2)));
+        for wrong in ["256", "#26", "#26 8bd", "#ééé", "purple"] {
+            let error = wrong.parse::<Color>().unwrap_err();
+            assert!(error.to_string().contains(wrong), "{}", error);
+        }
+    }
+
+    #[test]
+    fn rgb_approximates_the_palette() {
+        assert_eq!(Color::Indexed(42).indexed(), 42);
+        assert_eq!(Color::Rgb(0, 0, 0).indexed(), 16);
+        assert_eq!(Color::Rgb(255, 255, 255).indexed(), 231);
+        assert_eq!(Color::Rgb(128, 128, 128).indexed(), 244);
+        assert_eq!(Color::Rgb(0x26, 0x8b, 0xd2).indexed(), 32);
+    }
+
+    #[test]
+    fn the_depths_start_over() {
+        let theme = Theme::builtin("default").unwrap();
+        assert_eq!(
+            theme.color_of(BFCommand::BeginLoop, 0),
+            Some(Color::Indexed(6))
+        );
+        assert_eq!(
+            theme.color_of(BFCommand::Increment, 7),
+            Some(Color::Indexed(5))
+        );
+        assert_eq!(
+            Theme::builtin("mono")
+                .unwrap()
+                .color_of(BFCommand::Print, 3),
+            None
+        );
+        assert!(Theme::builtin("plain").is_none());
+    }
+
+    #[test]
+    fn commands_take_their_own_colors() {
+        let mut theme = Theme::builtin("solarized").unwrap();
+        let green = Color::Rgb(0x85, 0x99, 0x00);
+        assert_eq!(theme.color_of(BFCommand::Increment, 2), Some(green));
+        // the brackets only ever take the ones of the depths
+        theme.commands.push((BFCommand::EndLoop, green));
+        assert_eq!(theme.color_of(BFCommand::EndLoop, 0), Some(theme.depths[0]));
+    }
+
+    #[test]
+    fn painting() {
+        let mut theme = Theme::default();
+        let orange = Some(Color::Rgb(0xcb, 0x4b, 0x16));
+        assert_eq!(theme.paint(None, None, false), "\x1b[0m");
+        assert_eq!(theme.paint(None, orange, false), "\x1b[0;48;5;166m");
+        theme.true_color = true;
+        assert_eq!(
+            theme.paint(Some(Color::Indexed(7)), orange, true),
+            "\x1b[0;1;38;5;7;48;2;203;75;22m"
+        );
+    }
+}
@@ -0,0 +1,42 @@
+/* The C API of the bfrs interpreter, see src/lib.rs for the details. */
+#ifndef BFRS_H
+#define BFRS_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct bfrs_interpreter bfrs_interpreter;
+
+/* Returns the byte read, or -1 at the end of the input. */
+typedef int (*bfrs_read_fn)(void *user);
+/* Returns 0, or anything else to stop the program. */
+typedef int (*bfrs_write_fn)(void *user, uint8_t byte);
+
+bfrs_interpreter *bfrs_new(size_t cells);
+void bfrs_free(bfrs_interpreter *interpreter);
+
+int bfrs_load(bfrs_interpreter *interpreter, const uint8_t *source, size_t len);
+void bfrs_set_input(bfrs_interpreter *interpreter, bfrs_read_fn read, void *user);
+void bfrs_set_output(bfrs_interpreter *interpreter, bfrs_write_fn write, void *user);
+
+int bfrs_step(bfrs_interpreter *interpreter);
+int bfrs_run(bfrs_interpreter *interpreter);
+void bfrs_reset(bfrs_interpreter *interpreter);
+
+size_t bfrs_tape_len(const bfrs_interpreter *interpreter);
+int bfrs_read_cell(const bfrs_interpreter *interpreter, size_t cell);
+int bfrs_write_cell(bfrs_interpreter *interpreter, size_t cell, uint8_t value);
+size_t bfrs_pointer(const bfrs_interpreter *interpreter);
+size_t bfrs_instruction(const bfrs_interpreter *interpreter);
+
+const char *bfrs_last_error(const bfrs_interpreter *interpreter);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,472 @@
+//! A C API for the interpreter, to embed it in other programs.
+//!
+//! Every function takes the interpreter made by [`bfrs_new`], and the ones
+//! that can fail return `-1`, leaving a message for [`bfrs_last_error`].
+//! `include/bfrs.h` declares all of them.
+
+use bfrs::{Machine, Program};
+use bfrs_common::errors::Severity;
+use bfrs_common::parser;
+use std::ffi::{c_char, c_int, c_void, CString};
+use std::io::{self, Read, Write};
+use std::ptr;
+
+/// Reads a byte for the program, returning it or `-1` at the end of the
+/// input. Any other negative value is an error, which stops the program.
+pub type ReadFn = Option<unsafe extern "C" fn(user: *mut c_void) -> c_int>;
+
+/// Writes a byte the program printed, returning `0`, or anything
+/// else for an error, which stops the program.
+pub type WriteFn = Option<unsafe extern "C" fn(user: *mut c_void, byte: u8) -> c_int>;
+
+/// A program being run, along with its tape.
+pub struct Interpreter {
+    program: Program,
+    machine: Machine,
+    /// the index of the next instruction to run
+    next: usize,
+    input: Input,
+    output: Output,
+    error: Option<CString>,
+}
+
+impl Interpreter {
+    fn fail(&mut self, message: impl ToString) -> c_int {
+        // a message with a NUL in it is cut there.
+        let message = message.to_string();
+        let message = message.split('\0').next().unwrap_or_default();
+        self.error = CString::new(message).ok();
+        -1
+    }
+
+    fn step(&mut self) -> io::Result<()> {
+        self.next =
+            self.machine
+                .step(&self.program, self.next, &mut self.input, &mut self.output)?;
+        Ok(())
+    }
+
+    fn finished(&self) -> bool {
+        self.next >= self.program.code.instructions.len()
+    }
+}
+
+/// The input of the program, from a callback, or stdin without one.
+struct Input {
+    read: ReadFn,
+    user: *mut c_void,
+}
+
+impl Read for Input {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = match self.read {
+            Some(read) if !buf.is_empty() => read,
+            Some(_) => return Ok(0),
+            None => return io::stdin().read(buf),
+        };
+        // SAFETY: the caller of `bfrs_set_input` promised the
+        // callback can be called with its user pointer.
+        match unsafe { read(self.user) } {
+            -1 => Ok(0),
+            byte @ 0..=255 => {
+                buf[0] = byte as u8;
+                Ok(1)
+            }
+            code => Err(io::Error::other(format!(
+                "the input callback failed with {}",
+                code
+            ))),
+        }
+    }
+}
+
+/// The output of the program, to a callback, or stdout without one.
+struct Output {
+    write: WriteFn,
+    user: *mut c_void,
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let write = match self.write {
+            Some(write) => write,
+            // the interpreter doesn't flush what it prints, but stepping
+            // through a program should show it right away.
+            None => {
+                let mut stdout = io::stdout();
+                stdout.write_all(buf)?;
+                stdout.flush()?;
+                return Ok(buf.len());
+            }
+        };
+        for &byte in buf {
+            // SAFETY: the caller of `bfrs_set_output` promised the
+            // callback can be called with its user pointer.
+            match unsafe { write(self.user, byte) } {
+                0 => (),
+                code => {
+                    return Err(io::Error::other(format!(
+                        "the output callback failed with {}",
+                        code
+                    )))
+                }
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.write {
+            Some(_) => Ok(()),
+            None => io::stdout().flush(),
+        }
+    }
+}
+
+/// Makes an interpreter with a tape of `cells` cells and no program,
+/// reading from stdin and writing to stdout until it's told otherwise.
+/// It has to be freed with [`bfrs_free`].
+#[no_mangle]
+pub extern "C" fn bfrs_new(cells: usize) -> *mut Interpreter {
+    let cells = cells.max(1);
+    Box::into_raw(Box::new(Interpreter {
+        program: Program::from_instructions(Vec::new(), cells),
+        machine: Machine::new(cells),
+        next: 0,
+        input: Input {
+            read: None,
+            user: ptr::null_mut(),
+        },
+        output: Output {
+            write: None,
+            user: ptr::null_mut(),
+        },
+        error: None,
+    }))
+}
+
+/// Frees an interpreter. Null is ignored.
+///
+/// # Safety
+/// `interpreter` has to come from [`bfrs_new`], and not be used after this.
+#[no_mangle]
+pub unsafe extern "C" fn bfrs_free(interpreter: *mut Interpreter) {
+    if !interpreter.is_null() {
+        drop(Box::from_raw(interpreter));
+    }
+}
+
+/// Loads the `len` bytes of brainfuck at `source`, which run from the
+/// start on the tape as it is, so programs can be run one after the other.
+/// Returns `-1` if the program has errors, keeping the one loaded before.
+///
+/// # Safety
+/// `interpreter` has to be a live interpreter, and `source` has to point
+/// to `len` bytes, which are only read during the call.
+#[no_mangle]
+pub unsafe extern "C" fn bfrs_load(
+    interpreter: *mut Interpreter,
+    source: *const u8,
+    len: usize,
+) -> c_int {
+    let interpreter = match interpreter.as_mut() {
+        Some(interpreter) => interpreter,
+        None => return -1,
+    };
+    if source.is_null() && len > 0 {
+        return interpreter.fail("the source is null");
+    }
+    let source = if len == 0 {
+        &[]
+    } else {
+        std::slice::from_raw_parts(source, len)
+    };
+    let recovered = parser::parse_recovering(source.iter().copied().map(Ok))
+        .expect("reading from memory doesn't fail");
+    let diagnostics = recovered.diagnostics(Severity::Error);
+    if diagnostics.has_errors() {
+        return interpreter.fail(diagnostics);
+    }
+    let instructions = recovered
+        .instructions
+        .into_iter()
+        .map(|(instruction, _)| instruction)
+        .collect();
+    interpreter.program = Program::from_instructions(instructions, interpreter.machine.tape.len());
+    interpreter.next = 0;
+    0
+}
+
+/// Reads the program's input from `read`, called with `user`,
+/// or from stdin again when `read` is null.
+///
+/// # Safety
+/// `interpreter` has to be a live interpreter, and `read` has to be
+/// callable with `user` for as long as the interpreter runs programs.
+#[no_mangle]
+pub unsafe extern "C" fn bfrs_set_input(
+    interpreter: *mut Interpreter,
+    read: ReadFn,
+    user: *mut c_void,
+) {
+    if let Some(interpreter) = interpreter.as_mut() {
+        interpreter.input = Input { read, user };
+    }
+}
+
+/// Writes the program's output with `write`, called with `user`,
+/// or to stdout again when `write` is null.
+///
+/// # Safety
+/// `interpreter` has to be a live interpreter, and `write` has to be
+/// callable with `user` for as long as the interpreter runs programs.
+#[no_mangle]
+pub unsafe extern "C" fn bfrs_set_output(
+    interpreter: *mut Interpreter,
+    write: WriteFn,
+    user: *mut c_void,
+) {
+    if let Some(interpreter) = interpreter.as_mut() {
+        interpreter.output = Output { write, user };
+    }
+}
+
+/// Runs the next instruction, returning `1` if there are more
+/// to run, `0` once the program is done, or `-1` if the input
+/// or the output failed.
+///
+/// # Safety
+/// `interpreter` has to be a live interpreter.
+#[no_mangle]
+pub unsafe extern "C" fn bfrs_step(interpreter: *mut Interpreter) -> c_int {
+    let interpreter = match interpreter.as_mut() {
+        Some(interpreter) => interpreter,
+        None => return -1,
+    };
+    if interpreter.finished() {
+        return 0;
+    }
+    if let Err(e) = interpreter.step() {
+        return interpreter.fail(e);
+    }
+    (!interpreter.finished()) as c_int
+}
+
+/// Runs the rest of the program, returning `0`, or `-1`
+/// if the input or the output failed.
+///
+/// # Safety
+/// `interpreter` has to be a live interpreter.
+#[no_mangle]
+pub unsafe extern "C" fn bfrs_run(interpreter: *mut Interpreter) -> c_int {
+    loop {
+        match bfrs_step(interpreter) {
+            1 => (),
+            done => return done,
+        }
+    }
+}
+
+/// Clears the tape and moves the pointer back to the first
+/// cell, to run the loaded program again from the start.
+///
+/// # Safety
+/// `interpreter` has to be a live interpreter.
+#[no_mangle]
+pub unsafe extern "C" fn bfrs_reset(interpreter: *mut Interpreter) {
+    if let Some(interpreter) = interpreter.as_mut() {
+        interpreter.machine = Machine::new(interpreter.machine.tape.len());
+        interpreter.next = 0;
+    }
+}
+
+/// The amount of cells of the tape.
+///
+/// # Safety
+/// `interpreter` has to be a live interpreter.
+#[no_mangle]
+pub unsafe extern "C" fn bfrs_tape_len(interpreter: *const Interpreter) -> usize {
+    interpreter
+        .as_ref()
+        .map_or(0, |interpreter| interpreter.machine.tape.len())
+}
+
+/// The value of a cell, or `-1` if it's past the end of the tape.
+///
+/// # Safety
+/// `interpreter` has to be a live interpreter.
+#[no_mangle]
+pub unsafe extern "C" fn bfrs_read_cell(interpreter: *const Interpreter, cell: usize) -> c_int {
+    interpreter
+        .as_ref()
+        .and_then(|interpreter| interpreter.machine.tape.get(cell))
+        .map_or(-1, |&value| value as c_int)
+}
+
+/// Sets a cell, returning `-1` if it's past the end of the tape.
+///
+/// # Safety
+/// `interpreter` has to be a live interpreter.
+#[no_mangle]
+pub unsafe extern "C" fn bfrs_write_cell(
+    interpreter: *mut Interpreter,
+    cell: usize,
+    value: u8,
+) -> c_int {
+    match interpreter
+        .as_mut()
+        .and_then(|interpreter| interpreter.machine.tape.get_mut(cell))
+    {
+        Some(slot) => {
+            *slot = value;
+            0
+        }
+        None => -1,
+    }
+}
+
+/// The cell the pointer is at.
+///
+/// # Safety
+/// `interpreter` has to be a live interpreter.
+#[no_mangle]
+pub unsafe extern "C" fn bfrs_pointer(interpreter: *const Interpreter) -> usize {
+    interpreter
+        .as_ref()
+        .map_or(0, |interpreter| interpreter.machine.pivot)
+}
+
+/// The index of the next instruction to run, which is the
+/// amount of instructions once the program is done.
+///
+/// # Safety
+/// `interpreter` has to be a live interpreter.
+#[no_mangle]
+pub unsafe extern "C" fn bfrs_instruction(interpreter: *const Interpreter) -> usize {
+    interpreter
+        .as_ref()
+        .map_or(0, |interpreter| interpreter.next)
+}
+
+/// The message of the last error, which lives until the next one,
+/// or null if there hasn't been any.
+///
+/// # Safety
+/// `interpreter` has to be a live interpreter.
+#[no_mangle]
+pub unsafe extern "C" fn bfrs_last_error(interpreter: *const Interpreter) -> *const c_char {
+    interpreter
+        .as_ref()
+        .and_then(|interpreter| interpreter.error.as_ref())
+        .map_or(ptr::null(), |error| error.as_ptr())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CStr;
+
+    /// What the callbacks read from and write to, through their user pointer.
+    #[derive(Default)]
+    struct Io {
+        input: Vec<u8>,
+        output: Vec<u8>,
+        /// what the output callback returns
+        write_result: c_int,
+    }
+
+    unsafe extern "C" fn read(user: *mut c_void) -> c_int {
+        let io = &mut *(user as *mut Io);
+        match io.input.is_empty() {
+            true => -1,
+            false => io.input.remove(0) as c_int,
+        }
+    }
+
+    unsafe extern "C" fn write(user: *mut c_void, byte: u8) -> c_int {
+        let io = &mut *(user as *mut Io);
+        io.output.push(byte);
+        io.write_result
+    }
+
+    unsafe fn load(interpreter: *mut Interpreter, source: &str) -> c_int {
+        bfrs_load(interpreter, source.as_ptr(), source.len())
+    }
+
+    unsafe fn last_error(interpreter: *const Interpreter) -> String {
+        CStr::from_ptr(bfrs_last_error(interpreter))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    #[test]
+    fn programs_run_with_callbacks() {
+        let mut io = Io {
+            input: b"ab".to_vec(),
+            ..Io::default()
+        };
+        unsafe {
+            let interpreter = bfrs_new(4);
+            let user = &mut io as *mut Io as *mut c_void;
+            bfrs_set_input(interpreter, Some(read), user);
+            bfrs_set_output(interpreter, Some(write), user);
+            assert_eq!(load(interpreter, ",+.>,.,."), 0);
+            assert_eq!(bfrs_step(interpreter), 1);
+            assert_eq!(bfrs_instruction(interpreter), 1);
+            assert_eq!(bfrs_read_cell(interpreter, 0), b'a' as c_int);
+            assert_eq!(bfrs_run(interpreter), 0);
+            assert_eq!(bfrs_step(interpreter), 0);
+            assert_eq!(bfrs_pointer(interpreter), 1);
+            assert!(bfrs_last_error(interpreter).is_null());
+            bfrs_free(interpreter);
+        }
+        // the end of the input reads as 255
+        assert_eq!(io.output, b"bb\xff");
+    }
+
+    #[test]
+    fn programs_run_one_after_the_other() {
+        unsafe {
+            let interpreter = bfrs_new(2);
+            assert_eq!(bfrs_tape_len(interpreter), 2);
+            assert_eq!(bfrs_write_cell(interpreter, 1, 5), 0);
+            assert_eq!(bfrs_write_cell(interpreter, 2, 5), -1);
+            assert_eq!(bfrs_read_cell(interpreter, 2), -1);
+            assert_eq!(load(interpreter, ">[-<+>]"), 0);
+            assert_eq!(bfrs_run(interpreter), 0);
+            // the next program starts on the tape as it was left
+            assert_eq!(load(interpreter, "<+"), 0);
+            assert_eq!(bfrs_run(interpreter), 0);
+            assert_eq!(bfrs_read_cell(interpreter, 0), 6);
+            bfrs_reset(interpreter);
+            assert_eq!(bfrs_read_cell(interpreter, 0), 0);
+            assert_eq!(bfrs_instruction(interpreter), 0);
+            bfrs_free(interpreter);
+        }
+    }
+
+    #[test]
+    fn errors_are_left_for_last_error() {
+        let mut io = Io {
+            write_result: 3,
+            ..Io::default()
+        };
+        unsafe {
+            let interpreter = bfrs_new(1);
+            assert_eq!(load(interpreter, "+.]"), -1);
+            assert!(last_error(interpreter).contains("Unmatched loop closing"));
+            // the program loaded before is kept
+            assert_eq!(bfrs_run(interpreter), 0);
+
+            let user = &mut io as *mut Io as *mut c_void;
+            bfrs_set_output(interpreter, Some(write), user);
+            assert_eq!(load(interpreter, "+."), 0);
+            assert_eq!(bfrs_run(interpreter), -1);
+            assert_eq!(last_error(interpreter), "the output callback failed with 3");
+            assert_eq!(bfrs_load(interpreter, ptr::null(), 1), -1);
+            bfrs_free(interpreter);
+            bfrs_free(ptr::null_mut());
+            assert_eq!(bfrs_step(ptr::null_mut()), -1);
+        }
+    }
+}
@@ -0,0 +1,103 @@
+//! Reading input without blocking the thread, for async hosts.
+//! Only available with the `tokio` feature.
+
+use bfrs_common::parser::{self, Recovered};
+use std::io;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// Like [`BufferedBytes`](crate::bytes::BufferedBytes), but reading
+/// from an [`AsyncRead`], so getting a byte is `async`.
+pub struct AsyncBufferedBytes<R>
+where
+    R: AsyncRead + Unpin,
+{
+    reader: R,
+    buffer: Box<[u8]>,
+    /// the next byte in the buffer
+    start: usize,
+    /// the end of the bytes read into the buffer
+    end: usize,
+    eof: bool,
+}
+
+impl<R> AsyncBufferedBytes<R>
+where
+    R: AsyncRead + Unpin,
+{
+    /// Allocates a buffer with a specified capacity
+    pub fn with_capacity(cap: usize, reader: R) -> Self {
+        Self {
+            reader,
+            buffer: vec![0; cap.max(1)].into_boxed_slice(),
+            start: 0,
+            end: 0,
+            eof: false,
+        }
+    }
+    // Allocates a buffer of 1 Kb (1/4 page)
+    pub fn new(reader: R) -> Self {
+        Self::with_capacity(1024, reader)
+    }
+
+    /// The next byte, or `None` at the end of the input. Like an
+    /// iterator, it keeps returning `None` after the end or an error.
+    pub async fn next_byte(&mut self) -> Option<io::Result<u8>> {
+        if self.eof {
+            return None;
+        }
+        if self.start == self.end {
+            match self.reader.read(&mut self.buffer).await {
+                Ok(0) => {
+                    self.eof = true;
+                    return None;
+                }
+                Ok(read_len) => {
+                    self.start = 0;
+                    self.end = read_len;
+                }
+                Err(e) => {
+                    self.eof = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+        let byte = self.buffer[self.start];
+        self.start += 1;
+        Some(Ok(byte))
+    }
+}
+
+/// Reads the whole source without blocking, and parses it like
+/// [`parser::parse_recovering`]. The parse itself is done in memory,
+/// once everything is read.
+pub async fn parse_recovering_async<R>(mut reader: R) -> io::Result<Recovered>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut src = Vec::new();
+    reader.read_to_end(&mut src).await?;
+    parser::parse_recovering(src.into_iter().map(Ok))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn bytes_are_read_a_buffer_at_a_time() {
+        let mut bytes = AsyncBufferedBytes::with_capacity(2, &b"+-."[..]);
+        let mut read = Vec::new();
+        while let Some(byte) = bytes.next_byte().await {
+            read.push(byte.unwrap());
+        }
+        assert_eq!(read, b"+-.");
+        assert!(bytes.next_byte().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn sources_are_parsed_once_read() {
+        let recovered = parse_recovering_async(&b"+]-"[..]).await.unwrap();
+        assert_eq!(recovered.instructions.len(), 2);
+        assert_eq!(recovered.errors.len(), 1);
+    }
+}
@@ -1,20 +1,28 @@
 //! Common input methods to obtain iterators of characters.
 
 use std::io;
+use std::ops::DerefMut;
+use std::thread;
+use std::time::Duration;
 
-// NOTE: not for the scope of this project but could
-// be a good idea to make it allocator generic as well
-// inside an OS or RT application.
 /// Allows getting byte by byte from an input,
 /// using a buffer to minimize IO calls while
 /// maintaining a low memory cost. Allocates
 /// only at `new` or `with_capacity`. Will **never**
 /// try to extend itself.
-pub struct BufferedBytes<R>
+///
+/// The buffer can be any storage of bytes, so inside an OS or RT
+/// application it can be given with [`with_buffer`](Self::with_buffer),
+/// or allocated with a custom allocator with the `allocator` feature.
+///
+/// Reads interrupted by a signal are tried again, and what to do when a
+/// non-blocking reader has nothing yet is told with [`Retry`].
+pub struct BufferedBytes<R, B = Box<[u8]>>
 where
     R: io::Read,
+    B: DerefMut<Target = [u8]>,
 {
-    buffer: Buffered<R>,
+    buffer: Buffered<R, B>,
     eof: bool,
 }
 
@@ -24,10 +32,7 @@ where
 {
     /// Allocates a buffer with a specified capacity
     pub fn with_capacity(cap: usize, reader: R) -> Self {
-        Self {
-            buffer: Buffered::with_capacity(cap, reader),
-            eof: false,
-        }
+        Self::with_buffer(vec![0; cap.max(1)].into_boxed_slice(), reader)
     }
     // Allocates a buffer of 1 Kb (1/4 page)
     pub fn new(reader: R) -> Self {
@@ -35,11 +40,73 @@ where
     }
 }
 
-impl<R> std::iter::FusedIterator for BufferedBytes<R> where R: io::Read {}
+#[cfg(feature = "allocator")]
+impl<R, A> BufferedBytes<R, allocator_api2::boxed::Box<[u8], A>>
+where
+    R: io::Read,
+    A: allocator_api2::alloc::Allocator,
+{
+    /// Allocates a buffer with a specified capacity in `alloc`
+    pub fn with_capacity_in(cap: usize, reader: R, alloc: A) -> Self {
+        let mut buffer = allocator_api2::vec::Vec::with_capacity_in(cap.max(1), alloc);
+        buffer.resize(cap.max(1), 0);
+        Self::with_buffer(buffer.into_boxed_slice(), reader)
+    }
+}
 
-impl<R> Iterator for BufferedBytes<R>
+impl<R, B> BufferedBytes<R, B>
 where
     R: io::Read,
+    B: DerefMut<Target = [u8]>,
+{
+    /// Uses the given buffer, which can't be empty. Never allocates.
+    ///
+    /// # Panics
+    /// If the buffer is empty.
+    pub fn with_buffer(buffer: B, reader: R) -> Self {
+        Self {
+            buffer: Buffered::with_buffer(buffer, reader),
+            eof: false,
+        }
+    }
+
+    /// What to do when the reader has nothing to give yet,
+    /// which by default is giving the error back.
+    pub fn with_retry(mut self, retry: Retry) -> Self {
+        self.buffer.retry = retry;
+        self
+    }
+
+    /// The next byte, without consuming it.
+    pub fn peek(&mut self) -> io::Result<Option<u8>> {
+        self.peek_n(0)
+    }
+
+    /// The byte `n` bytes after the next one, without consuming
+    /// anything. `peek_n(0)` is the same as `peek()`.
+    ///
+    /// # Panics
+    /// If `n` doesn't fit in the buffer, as it won't be extended.
+    pub fn peek_n(&mut self, n: usize) -> io::Result<Option<u8>> {
+        if self.eof {
+            Ok(None)
+        } else {
+            self.buffer.peek_n(n)
+        }
+    }
+}
+
+impl<R, B> std::iter::FusedIterator for BufferedBytes<R, B>
+where
+    R: io::Read,
+    B: DerefMut<Target = [u8]>,
+{
+}
+
+impl<R, B> Iterator for BufferedBytes<R, B>
+where
+    R: io::Read,
+    B: DerefMut<Target = [u8]>,
 {
     type Item = io::Result<u8>;
     fn next(&mut self) -> Option<Self::Item> {
@@ -47,7 +114,15 @@ where
             None
         } else {
             let res = self.buffer.next_byte();
-            if matches!(res, Ok(None) | Err(_)) {
+            // a reader that would block, with `Retry::Fail`, may have more later.
+            let ended = match &res {
+                Ok(byte) => byte.is_none(),
+                Err(err) => !matches!(
+                    err.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted
+                ),
+            };
+            if ended {
                 self.eof = true;
             }
             res.transpose()
@@ -55,56 +130,239 @@ where
     }
 }
 
+/// What to do when a non-blocking reader has nothing to give yet, and
+/// fails with [`io::ErrorKind::WouldBlock`]. A read of no bytes is always
+/// the end of the input, so it's never tried again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Retry {
+    /// give the error back, leaving the bytes after it to be read later
+    #[default]
+    Fail,
+    /// try again right away
+    Spin,
+    /// wait before trying again, starting with `start` and doubling
+    /// the wait every time, up to `max`
+    BackOff { start: Duration, max: Duration },
+}
+
+impl Retry {
+    /// Waits before trying again, if it should, after having waited
+    /// `waited` the last time. Returns whether to try again.
+    fn wait(self, waited: &mut Option<Duration>) -> bool {
+        match self {
+            Self::Fail => return false,
+            Self::Spin => std::hint::spin_loop(),
+            Self::BackOff { start, max } => {
+                let wait = waited.map_or(start, |waited| (waited * 2).min(max));
+                thread::sleep(wait);
+                *waited = Some(wait);
+            }
+        }
+        true
+    }
+}
+
 /// A structure with a buffer to
 /// obtain byte-by-byte. Won't implement
 /// [`io::Read`] nor [`io::BufRead`] as its
 /// purpose is not to be a generic reader.
-struct Buffered<R>
+///
+/// The buffer is a ring: the bytes left go from `head` on,
+/// wrapping around, and new bytes are read right after them.
+struct Buffered<R, B>
 where
     R: io::Read,
+    B: DerefMut<Target = [u8]>,
 {
-    buffer: Vec<u8>,
+    buffer: B,
+    /// the next byte in the buffer
+    head: usize,
+    /// how many bytes are left in the buffer
+    len: usize,
     reader: R,
+    retry: Retry,
 }
 
-impl<R> Buffered<R>
+impl<R, B> Buffered<R, B>
 where
     R: io::Read,
+    B: DerefMut<Target = [u8]>,
 {
-    /// Allocates a buffer with a specified capacity
-    pub fn with_capacity(cap: usize, reader: R) -> Self {
+    pub fn with_buffer(buffer: B, reader: R) -> Self {
+        assert!(!buffer.is_empty(), "the buffer can't be empty");
         Self {
-            buffer: Vec::with_capacity(cap),
+            buffer,
+            head: 0,
+            len: 0,
             reader,
+            retry: Retry::default(),
         }
     }
 
     pub fn next_byte(&mut self) -> io::Result<Option<u8>> {
-        match self.buffer.pop() {
-            Some(byte) => Ok(Some(byte)),
-            None => {
-                if self.read_buffer()? {
-                    self.next_byte()
-                } else {
-                    Ok(None)
-                }
+        if self.len == 0 && !self.read_more()? {
+            return Ok(None);
+        }
+        let byte = self.buffer[self.head];
+        self.head = (self.head + 1) % self.buffer.len();
+        self.len -= 1;
+        Ok(Some(byte))
+    }
+
+    pub fn peek_n(&mut self, n: usize) -> io::Result<Option<u8>> {
+        assert!(
+            n < self.buffer.len(),
+            "can't peek {} bytes ahead with a buffer of {}",
+            n,
+            self.buffer.len()
+        );
+        while self.len <= n {
+            if !self.read_more()? {
+                return Ok(None);
             }
         }
+        Ok(Some(self.buffer[(self.head + n) % self.buffer.len()]))
     }
 
-    fn read_buffer(&mut self) -> io::Result<bool> {
-        // set the buffer length as full capacity.
-        unsafe { self.buffer.set_len(self.buffer.capacity()) };
-        // read from the reader
-        let read_len = self.reader.read(self.buffer.as_mut_slice())?;
-        // set the buffer length to what was read
-        // SAFETY: the buffer length is initially set to its capacity to let
-        // the reader write correctly. The reader returns how many bytes did
-        // it read (initialize) into the buffer, so the length of the buffer
-        // upon exiting the function is always set correctly to what is currently
-        // initialized.
-        unsafe { self.buffer.set_len(read_len) };
-        self.buffer.reverse();
+    /// Reads after the bytes left in the buffer, without
+    /// discarding them. Returns whether anything was read.
+    fn read_more(&mut self) -> io::Result<bool> {
+        let cap = self.buffer.len();
+        if self.len == 0 {
+            // start over, to read as much as possible at once.
+            self.head = 0;
+        }
+        let tail = (self.head + self.len) % cap;
+        // the free space up to the end of the buffer, or up to
+        // the head when the bytes left already wrap around.
+        let free_end = if tail < self.head { self.head } else { cap };
+        let mut waited = None;
+        let read_len = loop {
+            match self.reader.read(&mut self.buffer[tail..free_end]) {
+                Ok(read_len) => break read_len,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => (),
+                Err(err)
+                    if err.kind() == io::ErrorKind::WouldBlock && self.retry.wait(&mut waited) => {}
+                Err(err) => return Err(err),
+            }
+        };
+        self.len += read_len;
         Ok(read_len > 0)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Gives its chunks one read at a time, failing with
+    /// `WouldBlock` for the empty ones.
+    struct Chunks(Vec<&'static [u8]>);
+
+    impl io::Read for Chunks {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() {
+                return Ok(0);
+            }
+            match self.0.remove(0) {
+                [] => Err(io::ErrorKind::WouldBlock.into()),
+                chunk => {
+                    buf[..chunk.len()].copy_from_slice(chunk);
+                    Ok(chunk.len())
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn would_block_is_not_the_end() {
+        let mut bytes = BufferedBytes::new(Chunks(vec![b"a", b"", b"b"]));
+        assert_eq!(bytes.next().unwrap().unwrap(), b'a');
+        let err = bytes.next().unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+        assert_eq!(bytes.next().unwrap().unwrap(), b'b');
+        assert!(bytes.next().is_none());
+        assert!(bytes.next().is_none());
+    }
+
+    #[test]
+    fn spinning_waits_for_the_bytes() {
+        let reader = Chunks(vec![b"", b"", b"ab"]);
+        let bytes = BufferedBytes::new(reader).with_retry(Retry::Spin);
+        let read: Vec<u8> = bytes.map(Result::unwrap).collect();
+        assert_eq!(read, b"ab");
+    }
+
+    #[test]
+    fn other_errors_end_it() {
+        struct Broken;
+        impl io::Read for Broken {
+            fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+                Err(io::ErrorKind::BrokenPipe.into())
+            }
+        }
+        let mut bytes = BufferedBytes::new(Broken);
+        assert!(bytes.next().unwrap().is_err());
+        assert!(bytes.next().is_none());
+    }
+
+    #[test]
+    fn peeking_reads_ahead_without_consuming() {
+        let mut bytes = BufferedBytes::with_capacity(4, Chunks(vec![b"ab", b"cd"]));
+        assert_eq!(bytes.peek().unwrap(), Some(b'a'));
+        // the second chunk is read after the first, which is kept
+        assert_eq!(bytes.peek_n(2).unwrap(), Some(b'c'));
+        assert_eq!(bytes.next().unwrap().unwrap(), b'a');
+        assert_eq!(bytes.peek_n(2).unwrap(), Some(b'd'));
+        assert_eq!(bytes.peek_n(3).unwrap(), None);
+        let read: Vec<u8> = bytes.map(Result::unwrap).collect();
+        assert_eq!(read, b"bcd");
+    }
+
+    #[test]
+    #[should_panic(expected = "can't peek 4 bytes ahead with a buffer of 4")]
+    fn peeking_past_the_buffer() {
+        let _ = BufferedBytes::with_capacity(4, Chunks(vec![b"abcde"])).peek_n(4);
+    }
+
+    #[test]
+    fn bytes_wrap_around_the_buffer() {
+        let mut buffered = Buffered::with_buffer(vec![0; 4], &b"abcdefg"[..]);
+        assert_eq!(buffered.peek_n(3).unwrap(), Some(b'd'));
+        for &expected in b"abc" {
+            assert_eq!(buffered.next_byte().unwrap(), Some(expected));
+        }
+        // the rest is read in front of the `d` left at the end of the buffer
+        assert_eq!(buffered.peek_n(2).unwrap(), Some(b'f'));
+        assert_eq!(buffered.buffer, b"efgd");
+        let mut rest = Vec::new();
+        while let Some(byte) = buffered.next_byte().unwrap() {
+            rest.push(byte);
+        }
+        assert_eq!(rest, b"defg");
+    }
+
+    #[test]
+    fn any_storage_can_be_the_buffer() {
+        let mut storage = [0; 2];
+        let bytes = BufferedBytes::with_buffer(&mut storage[..], &b"+-."[..]);
+        let read: Vec<u8> = bytes.map(Result::unwrap).collect();
+        assert_eq!(read, b"+-.");
+        // the last byte is read over the first, once both were taken
+        assert_eq!(storage, *b".-");
+    }
+
+    #[test]
+    #[should_panic(expected = "the buffer can't be empty")]
+    fn empty_buffers() {
+        BufferedBytes::with_buffer(&mut [][..], &b"+"[..]);
+    }
+
+    #[cfg(feature = "allocator")]
+    #[test]
+    fn buffers_in_an_allocator() {
+        let bytes = BufferedBytes::with_capacity_in(2, &b"+-."[..], allocator_api2::alloc::Global);
+        let read: Vec<u8> = bytes.map(Result::unwrap).collect();
+        assert_eq!(read, b"+-.");
+    }
+}
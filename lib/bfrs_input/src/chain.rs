@@ -0,0 +1,179 @@
+//! Reading several inputs one after the other, as a single program,
+//! while still telling which one every position comes from.
+
+use crate::mmap::InputBytes;
+use bfrs_common::{Location, Position, PositionConfig};
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+/// An input of a chain, and where it starts in the whole program.
+#[derive(Debug, Clone)]
+pub struct ChainedInput {
+    pub name: String,
+    pub start: Location,
+}
+
+/// The bytes of several inputs, one after the other. The positions
+/// found while parsing them are in the whole program, and [`locate`]
+/// turns them into positions in the input they come from.
+///
+/// [`locate`]: Self::locate
+pub struct ChainedBytes<I>
+where
+    I: Iterator<Item = io::Result<u8>>,
+{
+    pending: VecDeque<(String, I)>,
+    current: Option<I>,
+    /// the inputs read so far, or being read
+    inputs: Vec<ChainedInput>,
+    config: PositionConfig,
+    /// where the next byte is in the whole program
+    location: Location,
+    previous: u8,
+}
+
+impl<I> ChainedBytes<I>
+where
+    I: Iterator<Item = io::Result<u8>>,
+{
+    /// Chains the inputs, along with their names, in order.
+    pub fn new(inputs: impl IntoIterator<Item = (String, I)>) -> Self {
+        Self::with_config(inputs, PositionConfig::default())
+    }
+
+    /// Chains the inputs, counting the positions the way the parser
+    /// does with `config`, so they can be located.
+    pub fn with_config(
+        inputs: impl IntoIterator<Item = (String, I)>,
+        config: PositionConfig,
+    ) -> Self {
+        Self {
+            pending: inputs.into_iter().collect(),
+            current: None,
+            inputs: Vec::new(),
+            config,
+            location: Location {
+                position: Position::start(&config),
+                byte_offset: 0,
+            },
+            previous: 0,
+        }
+    }
+
+    /// The inputs read so far, which are all of them once the bytes run out.
+    pub fn inputs(&self) -> &[ChainedInput] {
+        &self.inputs
+    }
+
+    /// The input a position of the whole program comes from,
+    /// along with the position in that input.
+    pub fn locate(&self, position: Position) -> Option<(&str, Position)> {
+        let key = |position: Position| (position.line, position.column);
+        let i = self
+            .inputs
+            .partition_point(|input| key(input.start.position) <= key(position))
+            .checked_sub(1)?;
+        let input = &self.inputs[i];
+        let start = input.start.position;
+        let first = Position::start(&self.config);
+        let local = if position.line == start.line {
+            Position {
+                line: first.line,
+                column: position.column - start.column + first.column,
+            }
+        } else {
+            Position {
+                line: position.line - start.line + first.line,
+                column: position.column,
+            }
+        };
+        Some((&input.name, local))
+    }
+}
+
+impl ChainedBytes<InputBytes<File>> {
+    /// Chains the files, named after their paths, counting
+    /// the positions the way the parser does with `config`.
+    pub fn open<P>(paths: impl IntoIterator<Item = P>, config: PositionConfig) -> io::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let inputs = paths
+            .into_iter()
+            .map(|path| {
+                let path = path.as_ref();
+                let bytes = InputBytes::open(File::open(path)?)?;
+                Ok((path.display().to_string(), bytes))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        Ok(Self::with_config(inputs, config))
+    }
+}
+
+impl<I> Iterator for ChainedBytes<I>
+where
+    I: Iterator<Item = io::Result<u8>>,
+{
+    type Item = io::Result<u8>;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(current) = &mut self.current {
+                match current.next() {
+                    Some(Ok(byte)) => {
+                        self.location
+                            .position
+                            .advance_byte_with(byte, self.previous, &self.config);
+                        self.location.byte_offset += 1;
+                        self.previous = byte;
+                        return Some(Ok(byte));
+                    }
+                    Some(Err(e)) => return Some(Err(e)),
+                    None => self.current = None,
+                }
+            }
+            let (name, next) = self.pending.pop_front()?;
+            self.inputs.push(ChainedInput {
+                name,
+                start: self.location,
+            });
+            self.current = Some(next);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pos(line: usize, column: usize) -> Position {
+        Position { line, column }
+    }
+
+    #[test]
+    fn positions_are_located_in_their_input() {
+        let inputs = [("a", "+\n-"), ("b", ">"), ("empty", ""), ("d", "\n<")];
+        let mut chain = ChainedBytes::new(
+            inputs
+                .iter()
+                .map(|&(name, src)| (name.to_string(), src.bytes().map(Ok))),
+        );
+        let read: Vec<u8> = chain.by_ref().map(Result::unwrap).collect();
+        assert_eq!(read, b"+\n->\n<");
+        let names: Vec<_> = chain.inputs().iter().map(|input| &input.name).collect();
+        assert_eq!(names, ["a", "b", "empty", "d"]);
+        assert_eq!(chain.inputs()[3].start.byte_offset, 4);
+
+        assert_eq!(chain.locate(pos(2, 1)), Some(("a", pos(2, 1))));
+        // the first line of an input starts after the previous one ends
+        assert_eq!(chain.locate(pos(2, 2)), Some(("b", pos(1, 1))));
+        assert_eq!(chain.locate(pos(3, 1)), Some(("d", pos(2, 1))));
+    }
+
+    #[test]
+    fn nothing_is_located_before_the_first_input() {
+        let chain = ChainedBytes::<std::vec::IntoIter<io::Result<u8>>>::new(None);
+        assert_eq!(chain.locate(pos(1, 1)), None);
+    }
+}
@@ -0,0 +1,107 @@
+//! Decoding UTF-8 from an input, to get it character by character.
+
+use crate::bytes::BufferedBytes;
+use std::io;
+
+/// Allows getting char by char from an input, decoding it as UTF-8
+/// on top of [`BufferedBytes`]. Invalid UTF-8 yields an error of kind
+/// [`io::ErrorKind::InvalidData`], and decoding goes on after it.
+pub struct BufferedChars<R>
+where
+    R: io::Read,
+{
+    bytes: BufferedBytes<R>,
+}
+
+impl<R> BufferedChars<R>
+where
+    R: io::Read,
+{
+    pub fn new(reader: R) -> Self {
+        Self::from_bytes(BufferedBytes::new(reader))
+    }
+
+    pub fn from_bytes(bytes: BufferedBytes<R>) -> Self {
+        Self { bytes }
+    }
+
+    fn next_char(&mut self) -> io::Result<Option<char>> {
+        let first = match self.bytes.next().transpose()? {
+            Some(byte) => byte,
+            None => return Ok(None),
+        };
+        let len = match first {
+            0x00..=0x7F => return Ok(Some(first as char)),
+            0xC2..=0xDF => 2,
+            0xE0..=0xEF => 3,
+            0xF0..=0xF4 => 4,
+            _ => return Err(invalid(first)),
+        };
+        let mut encoded = [first, 0, 0, 0];
+        for continuation in encoded[1..len].iter_mut() {
+            // only take the bytes that continue the character, so
+            // the one that doesn't is decoded on its own afterwards.
+            match self.bytes.peek()? {
+                Some(byte @ 0x80..=0xBF) => *continuation = byte,
+                _ => return Err(invalid(first)),
+            }
+            self.bytes.next();
+        }
+        match std::str::from_utf8(&encoded[..len]) {
+            Ok(decoded) => Ok(decoded.chars().next()),
+            Err(_) => Err(invalid(first)),
+        }
+    }
+}
+
+fn invalid(first: u8) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("invalid UTF-8 sequence starting with {:#04x}", first),
+    )
+}
+
+impl<R> std::iter::FusedIterator for BufferedChars<R> where R: io::Read {}
+
+impl<R> Iterator for BufferedChars<R>
+where
+    R: io::Read,
+{
+    type Item = io::Result<char>;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_char().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(bytes: &'static [u8]) -> Vec<Result<char, String>> {
+        BufferedChars::new(bytes)
+            .map(|res| res.map_err(|e| e.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn characters_are_decoded() {
+        let chars = decode("a→🦀é".as_bytes());
+        assert_eq!(chars, [Ok('a'), Ok('→'), Ok('🦀'), Ok('é')]);
+    }
+
+    #[test]
+    fn decoding_goes_on_after_invalid_bytes() {
+        assert_eq!(
+            decode(b"\xff+\xe2\x86-"),
+            [
+                Err("invalid UTF-8 sequence starting with 0xff".to_string()),
+                Ok('+'),
+                Err("invalid UTF-8 sequence starting with 0xe2".to_string()),
+                Ok('-'),
+            ]
+        );
+        // a character cut short at the end
+        assert_eq!(decode(b"\xc3").len(), 1);
+        assert!(decode(b"\xc3")[0].is_err());
+    }
+}
@@ -1,3 +1,11 @@
 //! Common input methods
+#[cfg(feature = "tokio")]
+pub mod async_bytes;
 pub mod bytes;
+pub mod chain;
 pub mod chars;
+pub mod mmap;
+pub mod positioned;
+#[cfg(unix)]
+pub mod raw;
+pub mod tee;
@@ -0,0 +1,126 @@
+//! Reading files by mapping them into memory, which saves the read
+//! calls and the copies into a buffer, for big sources.
+
+use crate::bytes::BufferedBytes;
+use memmap2::Mmap;
+use std::fs::File;
+use std::io;
+
+/// The bytes of a file mapped into memory.
+pub struct MappedBytes {
+    map: Mmap,
+    offset: usize,
+}
+
+impl MappedBytes {
+    /// Maps the whole file into memory.
+    ///
+    /// The file shouldn't be changed while it's mapped, as the
+    /// bytes would change under the iterator.
+    pub fn map(file: &File) -> io::Result<Self> {
+        // SAFETY: the map is only read, and is kept alive along with
+        // the iterator. Other processes changing the file is the only
+        // hazard, which is the same as for any other program mapping it.
+        let map = unsafe { Mmap::map(file)? };
+        Ok(Self { map, offset: 0 })
+    }
+
+    /// Maps the file if it's a regular file. Others, like pipes or
+    /// terminals, can't be mapped and have to be read instead.
+    pub fn map_regular(file: &File) -> io::Result<Option<Self>> {
+        if file.metadata()?.is_file() {
+            Self::map(file).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// The bytes that haven't been iterated yet.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.map[self.offset..]
+    }
+}
+
+impl Iterator for MappedBytes {
+    type Item = io::Result<u8>;
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        let byte = *self.map.get(self.offset)?;
+        self.offset += 1;
+        Some(Ok(byte))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.map.len() - self.offset;
+        (len, Some(len))
+    }
+}
+
+impl std::iter::FusedIterator for MappedBytes {}
+
+/// Bytes from a file that's mapped when possible, and
+/// read through a buffer otherwise.
+pub enum InputBytes<R>
+where
+    R: io::Read,
+{
+    Mapped(MappedBytes),
+    Buffered(BufferedBytes<R>),
+}
+
+impl InputBytes<File> {
+    /// Maps the file if it's a regular one, or reads it otherwise.
+    pub fn open(file: File) -> io::Result<Self> {
+        Ok(match MappedBytes::map_regular(&file)? {
+            Some(mapped) => Self::Mapped(mapped),
+            None => Self::Buffered(BufferedBytes::new(file)),
+        })
+    }
+}
+
+impl<R> Iterator for InputBytes<R>
+where
+    R: io::Read,
+{
+    type Item = io::Result<u8>;
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Self::Mapped(bytes) => bytes.next(),
+            Self::Buffered(bytes) => bytes.next(),
+        }
+    }
+}
+
+impl<R> std::iter::FusedIterator for InputBytes<R> where R: io::Read {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn regular_files_are_mapped() {
+        let path = std::env::temp_dir().join(format!("bfrs-mmap-{}", std::process::id()));
+        File::create(&path).unwrap().write_all(b"+[-]").unwrap();
+
+        let mut mapped = MappedBytes::map(&File::open(&path).unwrap()).unwrap();
+        assert_eq!(mapped.next().unwrap().unwrap(), b'+');
+        assert_eq!(mapped.as_bytes(), b"[-]");
+        assert_eq!(mapped.size_hint(), (3, Some(3)));
+
+        let input = InputBytes::open(File::open(&path).unwrap()).unwrap();
+        assert!(matches!(input, InputBytes::Mapped(_)));
+        let read: Vec<u8> = input.map(Result::unwrap).collect();
+        assert_eq!(read, b"+[-]");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    // directories can only be opened as files on unix
+    #[cfg(unix)]
+    #[test]
+    fn other_files_are_read() {
+        let dir = File::open(std::env::temp_dir()).unwrap();
+        assert!(MappedBytes::map_regular(&dir).unwrap().is_none());
+    }
+}
@@ -0,0 +1,127 @@
+//! Keeping track of where every byte of an input is.
+
+use bfrs_common::{takes_column, Location, Position, PositionConfig};
+use std::io;
+
+/// Yields every byte of an input along with its position,
+/// counting the columns of UTF-8 characters the way editors do.
+pub struct PositionedBytes<I>
+where
+    I: Iterator<Item = io::Result<u8>>,
+{
+    inner: I,
+    config: PositionConfig,
+    /// where the next byte is
+    location: Location,
+    previous: u8,
+    /// where the character of the last byte starts
+    char_start: Position,
+}
+
+impl<I> PositionedBytes<I>
+where
+    I: Iterator<Item = io::Result<u8>>,
+{
+    pub fn new(inner: I) -> Self {
+        Self::with_config(inner, PositionConfig::default())
+    }
+
+    /// Counts the positions the way `config` says.
+    pub fn with_config(inner: I, config: PositionConfig) -> Self {
+        Self {
+            inner,
+            config,
+            location: Location {
+                position: Position::start(&config),
+                byte_offset: 0,
+            },
+            previous: 0,
+            char_start: Position::start(&config),
+        }
+    }
+
+    /// The position of the next byte, or the end of the input after the last one.
+    pub fn position(&self) -> Position {
+        self.location.position
+    }
+
+    /// The location of the next byte, like [`position`](Self::position)
+    /// along with how many bytes were read so far.
+    pub fn location(&self) -> Location {
+        self.location
+    }
+}
+
+impl<I> Iterator for PositionedBytes<I>
+where
+    I: Iterator<Item = io::Result<u8>>,
+{
+    type Item = io::Result<(u8, Position)>;
+    fn next(&mut self) -> Option<Self::Item> {
+        let byte = match self.inner.next()? {
+            Ok(byte) => byte,
+            Err(e) => return Some(Err(e)),
+        };
+        // the bytes continuing a character are where it starts.
+        if byte.is_ascii() || takes_column(byte, self.previous) {
+            self.char_start = self.location.position;
+        }
+        let position = self.char_start;
+        self.location
+            .position
+            .advance_byte_with(byte, self.previous, &self.config);
+        self.location.byte_offset += 1;
+        self.previous = byte;
+        Some(Ok((byte, position)))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<I> std::iter::FusedIterator for PositionedBytes<I> where
+    I: std::iter::FusedIterator<Item = io::Result<u8>>
+{
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pos(line: usize, column: usize) -> Position {
+        Position { line, column }
+    }
+
+    #[test]
+    fn bytes_continuing_a_character_are_where_it_starts() {
+        let mut bytes = PositionedBytes::new("a\né+".bytes().map(Ok));
+        let positions: Vec<_> = bytes.by_ref().map(|res| res.unwrap().1).collect();
+        assert_eq!(
+            positions,
+            [pos(1, 1), pos(1, 2), pos(2, 1), pos(2, 1), pos(2, 2)]
+        );
+        assert_eq!(bytes.position(), pos(2, 3));
+        assert_eq!(bytes.location().byte_offset, 5);
+    }
+
+    #[test]
+    fn positions_are_counted_as_configured() {
+        let config = PositionConfig {
+            tab_width: 4,
+            zero_based: true,
+        };
+        let bytes = PositionedBytes::with_config("\t+\n-".bytes().map(Ok), config);
+        let positions: Vec<_> = bytes.map(|res| res.unwrap().1).collect();
+        assert_eq!(positions, [pos(0, 0), pos(0, 4), pos(0, 5), pos(1, 0)]);
+    }
+
+    #[test]
+    fn errors_come_through() {
+        let input = vec![Ok(b'+'), Err(io::ErrorKind::Other.into()), Ok(b'-')];
+        let mut bytes = PositionedBytes::new(input.into_iter());
+        assert!(bytes.next().unwrap().is_ok());
+        assert!(bytes.next().unwrap().is_err());
+        assert_eq!(bytes.next().unwrap().unwrap(), (b'-', pos(1, 2)));
+    }
+}
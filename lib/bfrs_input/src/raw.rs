@@ -0,0 +1,166 @@
+//! Reading single keystrokes from the terminal, for interactive programs.
+//!
+//! Terminals usually hand over input a line at a time, once enter is
+//! pressed, and echo it as it's typed. [`RawInput`] turns both off while
+//! it's alive, so every key reaches the program as soon as it's pressed.
+//! Signals still stop the program, like ctrl-c does, but the terminal is
+//! put back the way it was first, since nothing is dropped when they do.
+
+use std::io;
+use std::mem::MaybeUninit;
+use std::sync::OnceLock;
+
+/// The signals that stop the program by default, which restore the
+/// terminal while it's raw.
+const SIGNALS: [libc::c_int; 4] = [libc::SIGINT, libc::SIGTERM, libc::SIGHUP, libc::SIGQUIT];
+
+/// The terminal settings to restore on a signal, the ones it had before
+/// it was first made raw.
+static ORIGINAL: OnceLock<libc::termios> = OnceLock::new();
+
+/// Standard input, read a keystroke at a time. When standard input
+/// isn't a terminal, it's read as it is.
+pub struct RawInput {
+    /// the terminal settings to restore, if they were changed
+    original: Option<libc::termios>,
+    /// the handlers of [`SIGNALS`] before restoring the terminal on them
+    handlers: Vec<(libc::c_int, libc::sigaction)>,
+}
+
+impl RawInput {
+    pub fn new() -> io::Result<Self> {
+        let fd = libc::STDIN_FILENO;
+        // SAFETY: `isatty` only inspects the descriptor.
+        if unsafe { libc::isatty(fd) } == 0 {
+            return Ok(Self {
+                original: None,
+                handlers: Vec::new(),
+            });
+        }
+        let mut termios = MaybeUninit::uninit();
+        // SAFETY: `tcgetattr` initializes the struct when it succeeds.
+        let original = unsafe {
+            if libc::tcgetattr(fd, termios.as_mut_ptr()) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            termios.assume_init()
+        };
+        ORIGINAL.get_or_init(|| original);
+        let handlers = restore_on_signals();
+        let mut raw = original;
+        raw.c_lflag &= !(libc::ICANON | libc::ECHO);
+        // every read waits for a single byte.
+        raw.c_cc[libc::VMIN] = 1;
+        raw.c_cc[libc::VTIME] = 0;
+        // dropping it puts back the handlers, even if it fails from here.
+        let input = Self {
+            original: Some(original),
+            handlers,
+        };
+        // SAFETY: `raw` is a valid termios, copied from the current one.
+        if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &raw) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(input)
+    }
+}
+
+/// Handles the [`SIGNALS`] that aren't ignored with [`restore_and_raise`],
+/// returning the handlers they had.
+fn restore_on_signals() -> Vec<(libc::c_int, libc::sigaction)> {
+    // SAFETY: a zeroed `sigaction` is valid, with an empty mask once
+    // `sigemptyset` fills it, and the handler only calls functions that
+    // are safe to call from one.
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = restore_and_raise as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        libc::sigemptyset(&mut action.sa_mask);
+        let mut handlers = Vec::with_capacity(SIGNALS.len());
+        for &signal in SIGNALS.iter() {
+            let mut previous: libc::sigaction = std::mem::zeroed();
+            if libc::sigaction(signal, std::ptr::null(), &mut previous) != 0
+                || previous.sa_sigaction == libc::SIG_IGN
+            {
+                continue;
+            }
+            if libc::sigaction(signal, &action, std::ptr::null_mut()) == 0 {
+                handlers.push((signal, previous));
+            }
+        }
+        handlers
+    }
+}
+
+/// Restores the terminal and raises `signal` again with its default
+/// handler, which stops the program once this one returns.
+extern "C" fn restore_and_raise(signal: libc::c_int) {
+    // SAFETY: `tcsetattr`, `signal` and `raise` are async-signal-safe,
+    // and `ORIGINAL` is set before the handler is.
+    unsafe {
+        if let Some(original) = ORIGINAL.get() {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, original);
+        }
+        libc::signal(signal, libc::SIG_DFL);
+        libc::raise(signal);
+    }
+}
+
+impl io::Read for RawInput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        io::stdin().lock().read(buf)
+    }
+}
+
+impl Drop for RawInput {
+    fn drop(&mut self) {
+        if let Some(original) = &self.original {
+            // SAFETY: `original` is the termios read when starting.
+            unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, original) };
+        }
+        for (signal, previous) in &self.handlers {
+            // SAFETY: `previous` is the action `sigaction` gave for `signal`.
+            unsafe { libc::sigaction(*signal, previous, std::ptr::null_mut()) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The local modes of the terminal, if standard input is one.
+    fn local_modes() -> Option<libc::tcflag_t> {
+        let mut termios = MaybeUninit::uninit();
+        // SAFETY: `tcgetattr` initializes the struct when it succeeds.
+        unsafe {
+            if libc::tcgetattr(libc::STDIN_FILENO, termios.as_mut_ptr()) != 0 {
+                return None;
+            }
+            Some(termios.assume_init().c_lflag)
+        }
+    }
+
+    fn interrupt_handler() -> libc::sighandler_t {
+        // SAFETY: a zeroed `sigaction` is valid to be filled in.
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            libc::sigaction(libc::SIGINT, std::ptr::null(), &mut action);
+            action.sa_sigaction
+        }
+    }
+
+    #[test]
+    fn everything_is_put_back_when_dropped() {
+        let modes = local_modes();
+        let handler = interrupt_handler();
+        let input = RawInput::new().unwrap();
+        // only a terminal is made raw, tests may run with or without one
+        assert_eq!(input.original.is_some(), modes.is_some());
+        if modes.is_some() {
+            assert_eq!(local_modes().unwrap() & (libc::ICANON | libc::ECHO), 0);
+        }
+        drop(input);
+        assert_eq!(local_modes(), modes);
+        assert_eq!(interrupt_handler(), handler);
+    }
+}
@@ -0,0 +1,60 @@
+//! Recording an input as it's read, to replay it later.
+
+use std::io;
+
+/// Reads from `reader`, writing everything that's read into `recording`.
+/// Only what's actually read is recorded, so a recording of an interactive
+/// session can be fed back to the same program.
+pub struct Tee<R, W>
+where
+    R: io::Read,
+    W: io::Write,
+{
+    reader: R,
+    recording: W,
+}
+
+impl<R, W> Tee<R, W>
+where
+    R: io::Read,
+    W: io::Write,
+{
+    pub fn new(reader: R, recording: W) -> Self {
+        Self { reader, recording }
+    }
+
+    /// The reader and the recording back.
+    pub fn into_inner(self) -> (R, W) {
+        (self.reader, self.recording)
+    }
+}
+
+impl<R, W> io::Read for Tee<R, W>
+where
+    R: io::Read,
+    W: io::Write,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read_len = self.reader.read(buf)?;
+        self.recording.write_all(&buf[..read_len])?;
+        // keep the recording whole even if the program doesn't finish.
+        self.recording.flush()?;
+        Ok(read_len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn only_what_is_read_is_recorded() {
+        let mut tee = Tee::new(&b"abcdef"[..], Vec::new());
+        let mut buf = [0; 4];
+        assert_eq!(tee.read(&mut buf).unwrap(), 4);
+        let (rest, recording) = tee.into_inner();
+        assert_eq!(recording, b"abcd");
+        assert_eq!(rest, b"ef");
+    }
+}
@@ -0,0 +1,170 @@
+//! Random brainfuck programs, for property tests and for benchmarking
+//! the optimizer and the matcher on code of a given shape.
+//!
+//! Programs are made from a seed, so the same one always gives the same
+//! program, and a failing case can be reproduced from its seed alone:
+//! ```
+//! use bfrs_testutil::Generator;
+//!
+//! let program = Generator::new(42).length(200).max_depth(3).program();
+//! assert_eq!(program.len(), 200);
+//! ```
+//! Every program is syntactically valid, but nothing keeps it from looping
+//! forever, so it should be run with a bound, like `bfrs::run_with_fuel`.
+
+use bfrs_common::BFCommand;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+/// The commands that neither open nor close a loop, nor do any I/O.
+const PLAIN: [BFCommand; 4] = [
+    BFCommand::Increment,
+    BFCommand::Decrement,
+    BFCommand::Right,
+    BFCommand::Left,
+];
+
+/// Makes random programs with balanced loops.
+pub struct Generator {
+    rng: ChaCha8Rng,
+    length: usize,
+    max_depth: usize,
+    loop_density: f64,
+    io_density: f64,
+}
+
+impl Generator {
+    /// A generator of programs of 100 instructions, nested up to 4 loops deep.
+    pub fn new(seed: u64) -> Self {
+        Self {
+            rng: ChaCha8Rng::seed_from_u64(seed),
+            length: 100,
+            max_depth: 4,
+            loop_density: 0.1,
+            io_density: 0.05,
+        }
+    }
+
+    /// The amount of instructions of every program.
+    pub fn length(mut self, length: usize) -> Self {
+        self.length = length;
+        self
+    }
+
+    /// How many loops can be open at once, 0 for programs without loops.
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// The chance of every instruction opening a loop, and of
+    /// closing one that's open, between 0 and 1.
+    pub fn loop_density(mut self, loop_density: f64) -> Self {
+        self.loop_density = loop_density.clamp(0.0, 1.0);
+        self
+    }
+
+    /// The chance of every instruction being a `.` or a `,`, between 0 and 1.
+    pub fn io_density(mut self, io_density: f64) -> Self {
+        self.io_density = io_density.clamp(0.0, 1.0);
+        self
+    }
+
+    /// The next program, which has exactly the configured length.
+    /// Loops that are still open near the end are closed there.
+    pub fn program(&mut self) -> Vec<BFCommand> {
+        let mut program = Vec::with_capacity(self.length);
+        let mut open = 0;
+        while program.len() < self.length {
+            let left = self.length - program.len();
+            // the loops still open have to be closed in what's left.
+            let instruction = if open == left || open > 0 && self.rng.gen_bool(self.loop_density) {
+                BFCommand::EndLoop
+            } else if open < self.max_depth
+                // the loop has to fit, along with the ones already open.
+                && left >= open + 2
+                && self.rng.gen_bool(self.loop_density)
+            {
+                BFCommand::BeginLoop
+            } else if self.rng.gen_bool(self.io_density) {
+                if self.rng.gen() {
+                    BFCommand::Print
+                } else {
+                    BFCommand::Read
+                }
+            } else {
+                PLAIN[self.rng.gen_range(0..PLAIN.len())]
+            };
+            match instruction {
+                BFCommand::BeginLoop => open += 1,
+                BFCommand::EndLoop => open -= 1,
+                _ => (),
+            }
+            program.push(instruction);
+        }
+        program
+    }
+
+    /// The next program, as source code.
+    pub fn source(&mut self) -> String {
+        self.program()
+            .into_iter()
+            .map(|instruction| instruction as u8 as char)
+            .collect()
+    }
+}
+
+impl Iterator for Generator {
+    type Item = Vec<BFCommand>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.program())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bfrs_common::parser::parse_str;
+
+    fn depth(program: &[BFCommand]) -> usize {
+        let (mut depth, mut deepest) = (0, 0);
+        for instruction in program {
+            match instruction {
+                BFCommand::BeginLoop => {
+                    depth += 1;
+                    deepest = deepest.max(depth);
+                }
+                BFCommand::EndLoop => depth -= 1,
+                _ => (),
+            }
+        }
+        deepest
+    }
+
+    #[test]
+    fn programs_are_valid_and_as_configured() {
+        for seed in 0..50 {
+            let mut generator = Generator::new(seed)
+                .length(seed as usize)
+                .max_depth(2)
+                .loop_density(0.5);
+            let source = generator.source();
+            assert_eq!(source.len(), seed as usize);
+            assert!(depth(&parse_str(&source).unwrap()) <= 2, "{}", source);
+        }
+        let program = Generator::new(1).max_depth(0).io_density(0.0).program();
+        assert!(program
+            .iter()
+            .all(|instruction| PLAIN.contains(instruction)));
+    }
+
+    #[test]
+    fn seeds_give_the_same_programs() {
+        let first: Vec<_> = Generator::new(7).take(3).collect();
+        let again: Vec<_> = Generator::new(7).take(3).collect();
+        assert_eq!(first, again);
+        assert_ne!(first[0], first[1]);
+        assert_ne!(Generator::new(8).program(), first[0]);
+    }
+}
@@ -0,0 +1,250 @@
+//! WebAssembly bindings of the interpreter, for the browser.
+//!
+//! The program's input and output go through JavaScript callbacks,
+//! so a playground decides where they come from and go to.
+
+use bfrs::{Machine, Program};
+use bfrs_common::errors::Severity;
+use bfrs_common::{parser, BFCommand};
+use js_sys::Function;
+use std::io;
+use wasm_bindgen::prelude::*;
+
+/// The commands of a program, as text. Throws the diagnostics of
+/// every error in it, as a JSON array, when it has any.
+#[wasm_bindgen]
+pub fn parse(source: &str) -> Result<String, JsValue> {
+    let instructions = instructions(source)?;
+    Ok(instructions.iter().map(|&i| i as u8 as char).collect())
+}
+
+fn instructions(source: &str) -> Result<Vec<BFCommand>, JsValue> {
+    let recovered = parser::parse_recovering(source.bytes().map(Ok))
+        .expect("reading from a string doesn't fail");
+    let diagnostics = recovered.diagnostics(Severity::Error);
+    if diagnostics.has_errors() {
+        let json: Vec<_> = diagnostics.iter().map(|d| d.to_json()).collect();
+        return Err(JsValue::from_str(&format!("[{}]", json.join(","))));
+    }
+    Ok(recovered
+        .instructions
+        .into_iter()
+        .map(|(instruction, _)| instruction)
+        .collect())
+}
+
+/// A program being run, along with its tape.
+#[wasm_bindgen]
+pub struct Interpreter {
+    program: Program,
+    machine: Machine,
+    /// the index of the next instruction to run
+    next: usize,
+    input: JsInput,
+    output: JsOutput,
+}
+
+#[wasm_bindgen]
+impl Interpreter {
+    /// Parses the program, to run it on a tape of `cells` cells.
+    /// Throws like [`parse`] when the program has errors.
+    #[wasm_bindgen(constructor)]
+    pub fn new(source: &str, cells: usize) -> Result<Interpreter, JsValue> {
+        let cells = cells.max(1);
+        Ok(Self {
+            program: Program::from_instructions(instructions(source)?, cells),
+            machine: Machine::new(cells),
+            next: 0,
+            input: JsInput {
+                callback: None,
+                thrown: None,
+            },
+            output: JsOutput {
+                callback: None,
+                pending: Vec::new(),
+                thrown: None,
+            },
+        })
+    }
+
+    /// Sets the function called for every `,`, which returns the byte
+    /// read, or `null` or `undefined` at the end of the input.
+    #[wasm_bindgen(js_name = setInput)]
+    pub fn set_input(&mut self, callback: Function) {
+        self.input.callback = Some(callback);
+    }
+
+    /// Sets the function called with every byte the program prints.
+    #[wasm_bindgen(js_name = setOutput)]
+    pub fn set_output(&mut self, callback: Function) {
+        self.output.callback = Some(callback);
+    }
+
+    /// The bytes printed since the last call, when there's no output callback.
+    #[wasm_bindgen(js_name = takeOutput)]
+    pub fn take_output(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.output.pending)
+    }
+
+    /// Runs the next instruction, returning whether there are more to run.
+    /// Throws whatever the callbacks throw.
+    pub fn step(&mut self) -> Result<bool, JsValue> {
+        if !self.finished() {
+            let stepped =
+                self.machine
+                    .step(&self.program, self.next, &mut self.input, &mut self.output);
+            self.next = stepped.map_err(|e| {
+                self.input
+                    .thrown
+                    .take()
+                    .or_else(|| self.output.thrown.take())
+                    .unwrap_or_else(|| JsValue::from_str(&e.to_string()))
+            })?;
+        }
+        Ok(!self.finished())
+    }
+
+    /// Runs the rest of the program.
+    pub fn run(&mut self) -> Result<(), JsValue> {
+        while self.step()? {}
+        Ok(())
+    }
+
+    pub fn finished(&self) -> bool {
+        self.next >= self.program.code.instructions.len()
+    }
+
+    /// Starts the program again, on a blank tape.
+    pub fn reset(&mut self) {
+        self.machine = Machine::new(self.machine.tape.len());
+        self.next = 0;
+    }
+
+    /// A copy of the tape.
+    pub fn tape(&self) -> Vec<u8> {
+        self.machine.tape.to_vec()
+    }
+
+    /// The cell the pointer is at.
+    pub fn pointer(&self) -> usize {
+        self.machine.pivot
+    }
+
+    /// The index of the next instruction to run.
+    pub fn instruction(&self) -> usize {
+        self.next
+    }
+
+    /// Sets a cell of the tape, to prepare the input of a program.
+    #[wasm_bindgen(js_name = setCell)]
+    pub fn set_cell(&mut self, cell: usize, value: u8) {
+        if let Some(cell) = self.machine.tape.get_mut(cell) {
+            *cell = value;
+        }
+    }
+}
+
+/// The input of the program, read from a callback, if there's one.
+struct JsInput {
+    callback: Option<Function>,
+    /// what the callback threw, which can't go through an `io::Error`
+    thrown: Option<JsValue>,
+}
+
+impl io::Read for JsInput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let Self { callback, thrown } = self;
+        let callback = match callback {
+            Some(callback) if !buf.is_empty() => callback,
+            _ => return Ok(0),
+        };
+        let byte = callback
+            .call0(&JsValue::NULL)
+            .map_err(|value| threw(thrown, value))?;
+        match byte.as_f64() {
+            Some(byte) => {
+                buf[0] = byte as u8;
+                Ok(1)
+            }
+            None => Ok(0),
+        }
+    }
+}
+
+/// The output of the program, given to a callback, or kept until it's taken.
+struct JsOutput {
+    callback: Option<Function>,
+    pending: Vec<u8>,
+    thrown: Option<JsValue>,
+}
+
+impl io::Write for JsOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let Self {
+            callback,
+            pending,
+            thrown,
+        } = self;
+        match callback {
+            Some(callback) => {
+                for &byte in buf {
+                    callback
+                        .call1(&JsValue::NULL, &JsValue::from(byte))
+                        .map_err(|value| threw(thrown, value))?;
+                }
+            }
+            None => pending.extend_from_slice(buf),
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Keeps what a callback threw, to throw it again once the interpreter stops.
+fn threw(thrown: &mut Option<JsValue>, value: JsValue) -> io::Error {
+    *thrown = Some(value);
+    io::Error::other("a callback threw")
+}
+
+// JavaScript values can only be made on wasm, so these only take the
+// paths that don't need any, which are the ones without callbacks.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn programs_are_parsed_into_their_commands() {
+        assert_eq!(parse("+ add [-] then print .").unwrap(), "+[-].");
+    }
+
+    #[test]
+    fn programs_are_run_a_step_at_a_time() {
+        let mut interpreter = Interpreter::new("+>++[-<+>]<.", 4).unwrap();
+        assert!(interpreter.step().unwrap());
+        assert_eq!(interpreter.tape(), [1, 0, 0, 0]);
+        assert_eq!(interpreter.instruction(), 1);
+        interpreter.run().unwrap();
+        assert!(interpreter.finished());
+        assert!(!interpreter.step().unwrap());
+        assert_eq!(interpreter.pointer(), 0);
+        // the output is kept without a callback, until it's taken
+        assert_eq!(interpreter.take_output(), [3]);
+        assert!(interpreter.take_output().is_empty());
+    }
+
+    #[test]
+    fn programs_start_again_on_a_blank_tape() {
+        let mut interpreter = Interpreter::new(",.", 2).unwrap();
+        interpreter.set_cell(0, 7);
+        interpreter.set_cell(5, 7);
+        interpreter.run().unwrap();
+        // without an input callback, reading is the end of the input, as 255
+        assert_eq!(interpreter.take_output(), [255]);
+        interpreter.reset();
+        assert_eq!(interpreter.instruction(), 0);
+        assert_eq!(interpreter.tape(), [0, 0]);
+    }
+}
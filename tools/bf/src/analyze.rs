@@ -0,0 +1,260 @@
+//! A summary of a program: how many of every command it has, how
+//! deep its loops go, how many runs of the same command it has, which
+//! of the common idioms it uses and what its cells are used for.
+//!
+//! With `--layout`, it shows the values every cell can hold and the
+//! instructions touching it too, as [`bfrs_patterns::layout`] tells
+//! them without running the program, and with `--run`, as running it
+//! shows them.
+
+use bfrs::cli::Source;
+use bfrs::{ExecHooks, Machine, Program};
+use bfrs_common::runs::runs;
+use bfrs_common::BFCommand;
+use bfrs_patterns::automaton::CompiledLibrary;
+use bfrs_patterns::cli::Common;
+use bfrs_patterns::layout::{self, Range};
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::fs::File;
+use std::io;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+pub struct Opt {
+    #[structopt(flatten)]
+    source: Source,
+
+    /// Show the values every cell can hold and the instructions touching it
+    #[structopt(long)]
+    layout: bool,
+
+    /// Run the program, which has to end, to show the values every cell held
+    /// and the instructions that touched it along with the layout
+    #[structopt(long, requires = "layout")]
+    run: bool,
+
+    /// The file the program reads when it's run, instead of nothing
+    #[structopt(long, parse(from_os_str), requires = "run")]
+    input: Option<PathBuf>,
+}
+
+/// The cells of the tape the program is run on, like `bfrs`.
+const CELLS: usize = 30000;
+
+pub fn run(opt: Opt, common: &Common) -> Result<(), Box<dyn Error>> {
+    let (instructions, filename) = opt.source.load(common.positions())?;
+    println!("{}: {} instructions", filename, instructions.len());
+
+    let counts: Vec<_> = bfrs_common::dialect::ORDER
+        .iter()
+        .map(|&command| {
+            let count = instructions
+                .iter()
+                .filter(|&&other| other == command)
+                .count();
+            format!("`{}` {}", command, count)
+        })
+        .collect();
+    println!("commands: {}", counts.join(", "));
+
+    let (mut loops, mut depth, mut deepest) = (0, 0, 0);
+    for instruction in instructions.iter() {
+        match instruction {
+            BFCommand::BeginLoop => {
+                loops += 1;
+                depth += 1;
+                deepest = deepest.max(depth);
+            }
+            BFCommand::EndLoop => depth -= 1,
+            _ => (),
+        }
+    }
+    println!("loops: {}, nested up to {} deep", loops, deepest);
+
+    let folded: Vec<_> = runs(instructions.iter().copied()).collect();
+    if let Some(&(command, longest)) = folded.iter().max_by_key(|&&(_, count)| count) {
+        println!(
+            "runs: {}, the longest of {} `{}`",
+            folded.len(),
+            longest,
+            command
+        );
+    }
+
+    let library = bfrs_patterns::library::idioms();
+    let matches = CompiledLibrary::new(&library).find_all(&instructions);
+    let stats = bfrs_patterns::output::stats(&matches, &library, instructions.len());
+    println!(
+        "idioms: {} matches, covering {} instructions ({:.2}%)",
+        stats.matches, stats.covered, stats.coverage
+    );
+    for pattern in stats.patterns.iter().filter(|pattern| pattern.matches > 0) {
+        println!("\t{}: {}", pattern.name, pattern.matches);
+    }
+
+    // named like `bf run decompile` names them.
+    let roles = bfrs_patterns::decompile::roles(&instructions);
+    let names = bfrs_patterns::decompile::names(&roles);
+    println!("cells: {} with a known use", names.len());
+    for (offset, name) in names.iter() {
+        println!("\t{}: cell[{}], {}", name, offset, roles[offset]);
+    }
+
+    if opt.layout {
+        let observed = match opt.run {
+            true => Some(observe(&instructions, opt.input)?),
+            false => None,
+        };
+        print_layout(&instructions, &names, observed.as_ref());
+    }
+    Ok(())
+}
+
+fn print_layout(
+    instructions: &[BFCommand],
+    names: &BTreeMap<isize, String>,
+    observed: Option<&Observer>,
+) {
+    let layout = layout::analyze(instructions);
+    let mut offsets: BTreeSet<isize> = layout.cells.keys().copied().collect();
+    if let Some(observed) = observed {
+        offsets.extend(observed.cells.keys().map(|&cell| cell as isize));
+    }
+    println!("layout, from the cell the program starts at:");
+    for offset in offsets {
+        let analyzed = layout.cells.get(&offset);
+        let ran = observed.and_then(|observed| observed.cells.get(&(offset as usize)));
+        let name = match names.get(&offset) {
+            Some(name) => format!(" ({})", name),
+            None => String::new(),
+        };
+        let mut facts = Vec::new();
+        if let Some(analyzed) = analyzed {
+            facts.push(format!("can hold {}", analyzed.range));
+        }
+        if let Some((range, _)) = ran {
+            facts.push(format!("held {}", range));
+        }
+        let touched: BTreeSet<usize> = analyzed
+            .into_iter()
+            .flat_map(|analyzed| analyzed.touched.iter().copied())
+            .chain(
+                ran.into_iter()
+                    .flat_map(|(_, touched)| touched.iter().copied()),
+            )
+            .collect();
+        facts.push(format!("touched by instructions {}", regions(&touched)));
+        println!("\tcell[{}]{}: {}", offset, name, facts.join(", "));
+    }
+    if let Some(start) = layout.lost_at {
+        println!(
+            "\tthe cells the loop at instruction {} moves to can't be told without running it{}",
+            start,
+            if observed.is_some() { "" } else { " (--run)" }
+        );
+    }
+}
+
+/// Indices like `0-7, 12`, merging the ones next to each other.
+fn regions(indices: &BTreeSet<usize>) -> String {
+    let mut regions: Vec<(usize, usize)> = Vec::new();
+    for &index in indices {
+        match regions.last_mut() {
+            Some((_, end)) if *end + 1 == index => *end = index,
+            _ => regions.push((index, index)),
+        }
+    }
+    let regions: Vec<_> = regions
+        .iter()
+        .map(|&(start, end)| match start == end {
+            true => start.to_string(),
+            false => format!("{}-{}", start, end),
+        })
+        .collect();
+    regions.join(", ")
+}
+
+/// Runs the program on `input`, or nothing, watching its cells.
+fn observe(instructions: &[BFCommand], input: Option<PathBuf>) -> io::Result<Observer<'_>> {
+    let program = Program::from_instructions(instructions.to_vec(), CELLS);
+    let mut input: Box<dyn io::Read> = match input {
+        Some(path) => Box::new(File::open(path)?),
+        None => Box::new(io::empty()),
+    };
+    let mut observer = Observer {
+        instructions,
+        cells: BTreeMap::new(),
+        last: None,
+    };
+    let mut machine = Machine::new(CELLS);
+    machine.run_with_hooks(&program, &mut input, &mut io::sink(), &mut observer)?;
+    if let Some(cell) = observer.last.take() {
+        observer.saw(cell, machine.tape[cell]);
+    }
+    Ok(observer)
+}
+
+/// The values every cell held while the program ran, and the
+/// instructions that touched it, by its index.
+struct Observer<'a> {
+    instructions: &'a [BFCommand],
+    cells: BTreeMap<usize, (Range, BTreeSet<usize>)>,
+    /// the cell the last instruction touched, which it may have changed
+    last: Option<usize>,
+}
+
+impl Observer<'_> {
+    fn saw(&mut self, cell: usize, value: u8) {
+        let (range, _) = self
+            .cells
+            .entry(cell)
+            .or_insert_with(|| (Range::exactly(value), BTreeSet::new()));
+        *range = range.join(Range::exactly(value));
+    }
+}
+
+impl ExecHooks for Observer<'_> {
+    fn on_instruction(&mut self, index: usize, machine: &Machine) {
+        if let Some(cell) = self.last.take() {
+            self.saw(cell, machine.tape[cell]);
+        }
+        if let BFCommand::Right | BFCommand::Left = self.instructions[index] {
+            return;
+        }
+        let cell = machine.pivot;
+        self.saw(cell, machine.tape[cell]);
+        self.cells
+            .get_mut(&cell)
+            .expect("just seen")
+            .1
+            .insert(index);
+        self.last = Some(cell);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bfrs_common::parser;
+
+    #[test]
+    fn regions_merge_indices_next_to_each_other() {
+        let indices = [0, 1, 2, 5, 7, 8].iter().copied().collect();
+        assert_eq!(regions(&indices), "0-2, 5, 7-8");
+        assert_eq!(regions(&BTreeSet::new()), "");
+    }
+
+    #[test]
+    fn runs_show_what_cells_held() {
+        let instructions = parser::parse_str("++>+<[-]").unwrap();
+        let observed = observe(&instructions, None).unwrap();
+        let (range, touched) = &observed.cells[&0];
+        assert_eq!(*range, Range { lo: 0, hi: 2 });
+        assert_eq!(touched.iter().copied().collect::<Vec<_>>(), [0, 1, 5, 6, 7]);
+        let (range, touched) = &observed.cells[&1];
+        assert_eq!(*range, Range { lo: 0, hi: 1 });
+        assert_eq!(touched.iter().copied().collect::<Vec<_>>(), [3]);
+    }
+}
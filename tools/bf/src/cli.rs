@@ -0,0 +1,54 @@
+//! The arguments of `bf`, shared by the binary and the
+//! generators of its completions and manual page.
+
+use crate::{analyze, cmp, compile, convert, explain, fmt, repl, serve, stdlib};
+use bfrs_patterns::cli::{search, Common};
+use structopt::clap::Shell;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "bf", about = "run, format, search and compile brainfuck")]
+pub struct Opt {
+    #[structopt(flatten)]
+    pub common: Common,
+
+    #[structopt(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum Command {
+    /// run a program, like `bfrs`
+    Run(bfrs::cli::Opt),
+    /// print a program with every loop on its own lines, indented
+    Fmt(fmt::Opt),
+    /// detect patterns within a program, like `bfrs_patterns`
+    Match(search::Opt),
+    /// translate a program to C
+    Compile(compile::Opt),
+    /// translate a program to another dialect, like from Ook! to brainfuck
+    Convert(convert::Opt),
+    /// count the instructions, loops and idioms of a program, and show what
+    /// its cells are used for
+    Analyze(analyze::Opt),
+    /// run two programs on the same random inputs, and show the first
+    /// one they print something different on
+    Cmp(cmp::Opt),
+    /// show what is at a spot of a program: the instruction, the loops
+    /// around it and the idioms it's part of
+    Explain(explain::Opt),
+    /// run brainfuck a line at a time, keeping the tape between lines
+    Repl(repl::Opt),
+    /// run programs sent over HTTP, answering with what they printed as JSON
+    Serve(serve::Opt),
+    /// print snippets of brainfuck for common tasks, like copying a cell
+    /// or printing it in decimal, to build programs out of
+    Stdlib(stdlib::Opt),
+    /// print the completions of `bf` for a shell
+    Completions {
+        #[structopt(possible_values = &Shell::variants())]
+        shell: Shell,
+    },
+    /// print the manual page of `bf`, to be read with `man -l -`
+    Man,
+}
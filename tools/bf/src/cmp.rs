@@ -0,0 +1,202 @@
+//! Telling two programs apart by running them: both are run on the same
+//! random inputs, and the first input they print something different on
+//! is reported, to check a rewrite of a program, or an optimizer, where
+//! proving them the same is out of reach.
+//!
+//! Inputs are made from a seed, so the same one always gives the same
+//! inputs, and the first one is always empty. A program getting to its
+//! end within the fuel where the other doesn't is a difference, and two
+//! that don't are compared on what they printed until then, which has to
+//! be the start of what the other one printed.
+
+use bfrs::capture::{run_captured, RunOptions, RunResult};
+use bfrs::cli::Source;
+use bfrs::Program;
+use bfrs_patterns::cli::Common;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use std::error::Error;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+pub struct Opt {
+    /// the program to compare
+    first: String,
+
+    /// the program to compare it with
+    second: String,
+
+    /// the dialect both programs are written in, like `bf run -d`
+    #[structopt(short, long, default_value = "brainfuck")]
+    dialect: String,
+
+    /// how many inputs to run them on
+    #[structopt(long, default_value = "100")]
+    random_inputs: usize,
+
+    /// the seed of the inputs, the same one making the same inputs
+    #[structopt(long, default_value = "0")]
+    seed: u64,
+
+    /// the most bytes of an input
+    #[structopt(long, default_value = "32")]
+    max_input: usize,
+
+    /// only make inputs of printable ASCII, for programs reading text
+    #[structopt(long)]
+    printable: bool,
+
+    /// the most instructions a program runs on every input
+    #[structopt(long, default_value = "10000000")]
+    fuel: u64,
+
+    /// amount of cells of the tape
+    #[structopt(short, long, default_value = "30000")]
+    cells: usize,
+}
+
+/// Compares the programs, returning whether they printed the same on every input.
+pub fn run(opt: Opt, common: &Common) -> Result<bool, Box<dyn Error>> {
+    let load = |path: &str| -> Result<Program, Box<dyn Error>> {
+        let source = Source {
+            inputs: vec![path.to_string()],
+            dialect: opt.dialect.clone(),
+        };
+        let (instructions, _) = source.load(common.positions())?;
+        Ok(Program::from_instructions(instructions, opt.cells))
+    };
+    let (first, second) = (load(&opt.first)?, load(&opt.second)?);
+    let options = RunOptions {
+        fuel: Some(opt.fuel),
+        ..RunOptions::default()
+    };
+    let mut rng = ChaCha8Rng::seed_from_u64(opt.seed);
+    let mut unfinished = 0;
+    for n in 0..opt.random_inputs {
+        let input = match n {
+            0 => Vec::new(),
+            _ => random_input(&mut rng, opt.max_input, opt.printable),
+        };
+        let _span = tracing::debug_span!("input", n, len = input.len()).entered();
+        let a: RunResult = run_captured(&first, &input, &options);
+        let b: RunResult = run_captured(&second, &input, &options);
+        if !a.completed || !b.completed {
+            unfinished += 1;
+        }
+        if let Some(divergence) = divergence(&a, &b) {
+            println!(
+                "{} and {} differ on input {} (seed {}): \"{}\"",
+                opt.first,
+                opt.second,
+                n + 1,
+                opt.seed,
+                input.escape_ascii()
+            );
+            match divergence {
+                Divergence::Printed(at) => println!("first different byte printed: {}", at),
+                Divergence::Finished => println!("only one of them got to its end"),
+            }
+            for (name, result) in [(&opt.first, &a), (&opt.second, &b)].iter() {
+                let fuel = if result.completed {
+                    ""
+                } else {
+                    " (ran out of fuel)"
+                };
+                println!(
+                    "{} printed: \"{}\"{}",
+                    name,
+                    result.output.escape_ascii(),
+                    fuel
+                );
+            }
+            return Ok(false);
+        }
+    }
+    println!(
+        "{} and {} printed the same on {} inputs",
+        opt.first, opt.second, opt.random_inputs
+    );
+    if unfinished > 0 {
+        println!(
+            "{} of them ran out of fuel in both, and were only compared until then",
+            unfinished
+        );
+    }
+    Ok(true)
+}
+
+/// An input of at most `max` bytes.
+fn random_input(rng: &mut ChaCha8Rng, max: usize, printable: bool) -> Vec<u8> {
+    let len = rng.gen_range(0..=max);
+    (0..len)
+        .map(|_| {
+            if printable {
+                rng.gen_range(b' '..=b'~')
+            } else {
+                rng.gen()
+            }
+        })
+        .collect()
+}
+
+/// How two runs on the same input differ.
+#[derive(Debug, PartialEq, Eq)]
+enum Divergence {
+    /// they printed something different at this offset
+    Printed(usize),
+    /// one got to its end, and the other ran out of fuel
+    Finished,
+}
+
+/// How the runs differ, if they do, where two runs that stopped early only
+/// have to have printed the start of what the other one did.
+fn divergence(a: &RunResult, b: &RunResult) -> Option<Divergence> {
+    let differs = a.output.iter().zip(&b.output).position(|(a, b)| a != b);
+    let shorter = a.output.len().min(b.output.len());
+    match differs {
+        Some(at) => Some(Divergence::Printed(at)),
+        None if a.completed != b.completed => Some(Divergence::Finished),
+        None if a.output.len() == b.output.len() || !a.completed => None,
+        None => Some(Divergence::Printed(shorter)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bfrs::capture::RunStats;
+
+    fn result(output: &[u8], completed: bool) -> RunResult {
+        RunResult {
+            output: output.to_vec(),
+            tape: Vec::new(),
+            pointer: 0,
+            stats: RunStats::default(),
+            completed,
+            limit: None,
+        }
+    }
+
+    #[test]
+    fn finished_runs() {
+        let same = divergence(&result(b"hi", true), &result(b"hi", true));
+        assert_eq!(same, None);
+        let different = divergence(&result(b"hi", true), &result(b"ho", true));
+        assert_eq!(different, Some(Divergence::Printed(1)));
+        let shorter = divergence(&result(b"hi", true), &result(b"hi!", true));
+        assert_eq!(shorter, Some(Divergence::Printed(2)));
+    }
+
+    #[test]
+    fn runs_out_of_fuel() {
+        // a program that ends against one looping forever.
+        let one = divergence(&result(b"", true), &result(b"", false));
+        assert_eq!(one, Some(Divergence::Finished));
+        let one = divergence(&result(b"hi", false), &result(b"hi!", true));
+        assert_eq!(one, Some(Divergence::Finished));
+        let both = divergence(&result(b"hi", false), &result(b"hi!", false));
+        assert_eq!(both, None);
+        let both = divergence(&result(b"ho", false), &result(b"hi!", false));
+        assert_eq!(both, Some(Divergence::Printed(1)));
+    }
+}
@@ -0,0 +1,179 @@
+//! Translating a program to C, which any C compiler can then turn into
+//! an executable. Runs of `+-` and `<>` become a single statement.
+//! With `--elf`, it's translated straight to an executable instead.
+
+mod elf;
+
+use bfrs::cli::Source;
+use bfrs_common::runs::runs;
+use bfrs_common::BFCommand;
+use bfrs_patterns::cli::Common;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+pub struct Opt {
+    #[structopt(flatten)]
+    source: Source,
+
+    /// amount of cells of the tape, which wraps around like the interpreter's
+    #[structopt(short, long, default_value = "30000")]
+    cells: usize,
+
+    /// the file to write the C source to, instead of stdout
+    #[structopt(short, long, parse(from_os_str))]
+    output: Option<PathBuf>,
+
+    /// write a Linux x86-64 executable to --output instead, which
+    /// needs neither a C compiler nor libc
+    #[structopt(long, requires = "output")]
+    elf: bool,
+}
+
+pub fn run(opt: Opt, common: &Common) -> Result<(), Box<dyn Error>> {
+    let (instructions, _) = opt.source.load(common.positions())?;
+    if opt.elf {
+        return write_elf(&instructions, &opt);
+    }
+    let mut out: Box<dyn Write> = match &opt.output {
+        Some(path) => Box::new(io::BufWriter::new(File::create(path)?)),
+        None => Box::new(io::stdout().lock()),
+    };
+    to_c(&instructions, opt.cells.max(1), &mut out)?;
+    Ok(out.flush()?)
+}
+
+/// Writes the executable of the program to the output, which only its
+/// owner can write but anyone can run, like a linker does.
+fn write_elf(instructions: &[BFCommand], opt: &Opt) -> Result<(), Box<dyn Error>> {
+    if opt.cells > elf::MAX_CELLS {
+        return Err(format!(
+            "an executable can't have more than {} cells",
+            elf::MAX_CELLS
+        )
+        .into());
+    }
+    let path = opt.output.as_ref().expect("--elf requires --output");
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o755);
+    let mut file = options.open(path)?;
+    file.write_all(&elf::to_elf(instructions, opt.cells.max(1)))?;
+    Ok(())
+}
+
+fn to_c(instructions: &[BFCommand], cells: usize, out: &mut dyn Write) -> io::Result<()> {
+    writeln!(out, "#include <stdio.h>")?;
+    writeln!(out)?;
+    writeln!(out, "#define CELLS {}", cells)?;
+    writeln!(out)?;
+    writeln!(out, "static unsigned char tape[CELLS];")?;
+    writeln!(out)?;
+    writeln!(out, "int main(void) {{")?;
+    writeln!(out, "    size_t p = 0;")?;
+    if instructions.contains(&BFCommand::Read) {
+        writeln!(out, "    int c;")?;
+    }
+    let mut depth = 1;
+    for (instruction, run) in runs(instructions.iter().copied()) {
+        let indent = depth * 4;
+        let statement = match instruction {
+            // the tape and the cells wrap around, so a run
+            // one way is the same as a shorter one the other.
+            BFCommand::Increment => format!("tape[p] += {};", run % 256),
+            BFCommand::Decrement => format!("tape[p] -= {};", run % 256),
+            BFCommand::Right => format!("p = (p + {}) % CELLS;", run % cells),
+            BFCommand::Left => format!("p = (p + CELLS - {}) % CELLS;", run % cells),
+            _ => {
+                for _ in 0..run {
+                    depth = control(out, instruction, depth)?;
+                }
+                continue;
+            }
+        };
+        writeln!(out, "{:indent$}{}", "", statement, indent = indent)?;
+    }
+    writeln!(out, "    return 0;")?;
+    writeln!(out, "}}")
+}
+
+/// Writes an instruction other than arithmetic or movement, which
+/// aren't folded, returning the depth of the loops after it.
+fn control(out: &mut dyn Write, instruction: BFCommand, depth: usize) -> io::Result<usize> {
+    let indent = depth * 4;
+    match instruction {
+        BFCommand::Print => {
+            writeln!(out, "{:indent$}putchar(tape[p]);", "", indent = indent)?;
+        }
+        BFCommand::Read => {
+            // EOF reads as 255, like in the interpreter.
+            writeln!(
+                out,
+                "{:indent$}c = getchar(); tape[p] = c == EOF ? 255 : c;",
+                "",
+                indent = indent
+            )?;
+        }
+        BFCommand::BeginLoop => {
+            writeln!(out, "{:indent$}while (tape[p]) {{", "", indent = indent)?;
+            return Ok(depth + 1);
+        }
+        BFCommand::EndLoop => {
+            writeln!(out, "{:indent$}}}", "", indent = (depth - 1) * 4)?;
+            return Ok(depth - 1);
+        }
+        _ => unreachable!("arithmetic and movement are folded into runs"),
+    }
+    Ok(depth)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bfrs_common::parser::parse_str;
+
+    fn c(src: &str, cells: usize) -> String {
+        let mut out = Vec::new();
+        to_c(&parse_str(src).unwrap(), cells, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn runs_are_single_statements() {
+        let source = c("+++>>[-<<]", 10);
+        let body: Vec<_> = source
+            .lines()
+            .skip_while(|line| !line.contains("size_t p"))
+            .skip(1)
+            .collect();
+        assert_eq!(
+            body,
+            [
+                "    tape[p] += 3;",
+                "    p = (p + 2) % CELLS;",
+                "    while (tape[p]) {",
+                "        tape[p] -= 1;",
+                "        p = (p + CELLS - 2) % CELLS;",
+                "    }",
+                "    return 0;",
+                "}",
+            ]
+        );
+        assert!(source.contains("#define CELLS 10\n"));
+        // the variable reads go to is only there when there are any
+        assert!(!source.contains("int c;"));
+        assert!(c(",.", 10).contains(
+            "int c;\n    c = getchar(); tape[p] = c == EOF ? 255 : c;\n    putchar(tape[p]);\n"
+        ));
+    }
+
+    #[test]
+    fn wrapping_runs_are_shortened() {
+        assert!(c(&"+".repeat(257), 10).contains("tape[p] += 1;"));
+        assert!(c(&">".repeat(12), 10).contains("p = (p + 2) % CELLS;"));
+    }
+}
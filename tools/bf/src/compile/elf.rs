@@ -0,0 +1,223 @@
+//! Translating a program straight to a Linux x86-64 executable, without
+//! libc or anything else to link with: the program talks to the kernel
+//! with syscalls, and the file is little more than its instructions.
+//!
+//! The tape is in memory the kernel zeroes when loading the executable,
+//! with its address in `rbx` and the cell of the pointer in `r12`, which
+//! syscalls leave alone. Like the C translation, runs of `+-` and `<>`
+//! become a single instruction, and the tape wraps around.
+
+use bfrs_common::runs::runs;
+use bfrs_common::BFCommand;
+
+/// Where the executable is loaded.
+const BASE: u64 = 0x40_0000;
+const PAGE: u64 = 0x1000;
+const ELF_HEADER: usize = 64;
+const PROGRAM_HEADER: usize = 56;
+/// the code starts right after the headers
+const CODE: usize = ELF_HEADER + 2 * PROGRAM_HEADER;
+
+/// The most cells a tape can have, as they're counted in 32 bits.
+pub const MAX_CELLS: usize = i32::MAX as usize;
+
+/// The executable of a program running on a tape of `cells` cells,
+/// which has to be between 1 and [`MAX_CELLS`].
+pub fn to_elf(instructions: &[BFCommand], cells: usize) -> Vec<u8> {
+    assert!((1..=MAX_CELLS).contains(&cells));
+    let code = code(instructions, cells);
+    let end = BASE + (CODE + code.len()) as u64;
+    // the tape, on its own pages after the code.
+    let tape = end.next_multiple_of(PAGE);
+
+    let mut file = Vec::with_capacity(CODE + code.len());
+    // the identification: 64 bits, little endian, version 1, System V.
+    file.extend_from_slice(b"\x7fELF\x02\x01\x01\x00");
+    file.extend_from_slice(&[0; 8]);
+    put16(&mut file, 2); // an executable
+    put16(&mut file, 0x3e); // for x86-64
+    put32(&mut file, 1);
+    put64(&mut file, BASE + CODE as u64); // the entry point
+    put64(&mut file, ELF_HEADER as u64); // the program headers
+    put64(&mut file, 0); // no section headers
+    put32(&mut file, 0);
+    put16(&mut file, ELF_HEADER as u16);
+    put16(&mut file, PROGRAM_HEADER as u16);
+    put16(&mut file, 2);
+    put16(&mut file, 64);
+    put16(&mut file, 0);
+    put16(&mut file, 0);
+    // the whole file, readable and executable.
+    segment(&mut file, 0b101, 0, BASE, end - BASE, end - BASE);
+    // the tape, readable and writable, which isn't in the file.
+    segment(&mut file, 0b110, 0, tape, 0, cells as u64);
+    debug_assert_eq!(file.len(), CODE);
+
+    // the code has to know where the tape is, right at its start.
+    file.extend_from_slice(&code);
+    file[CODE + 2..CODE + 10].copy_from_slice(&tape.to_le_bytes());
+    file
+}
+
+/// The instructions of the program, starting with a `mov rbx` whose
+/// address of the tape is left for [`to_elf`] to fill in.
+fn code(instructions: &[BFCommand], cells: usize) -> Vec<u8> {
+    // mov rbx, tape; xor r12d, r12d
+    let mut code = vec![0x48, 0xbb, 0, 0, 0, 0, 0, 0, 0, 0, 0x45, 0x31, 0xe4];
+    let cells32 = cells as u32;
+    // where the jump of every open loop is, to fill in once it's closed.
+    let mut open = Vec::new();
+    for (instruction, run) in runs(instructions.iter().copied()) {
+        match instruction {
+            // add/sub byte [rbx + r12], run
+            BFCommand::Increment => code.extend_from_slice(&[0x42, 0x80, 0x04, 0x23, run as u8]),
+            BFCommand::Decrement => code.extend_from_slice(&[0x42, 0x80, 0x2c, 0x23, run as u8]),
+            BFCommand::Right => {
+                // add r12, run; cmp r12, cells; jb over; sub r12, cells
+                code.extend_from_slice(&[0x49, 0x81, 0xc4]);
+                put32(&mut code, (run % cells) as u32);
+                code.extend_from_slice(&[0x49, 0x81, 0xfc]);
+                put32(&mut code, cells32);
+                code.extend_from_slice(&[0x72, 0x07, 0x49, 0x81, 0xec]);
+                put32(&mut code, cells32);
+            }
+            BFCommand::Left => {
+                // sub r12, run; jae over; add r12, cells
+                code.extend_from_slice(&[0x49, 0x81, 0xec]);
+                put32(&mut code, (run % cells) as u32);
+                code.extend_from_slice(&[0x73, 0x07, 0x49, 0x81, 0xc4]);
+                put32(&mut code, cells32);
+            }
+            BFCommand::Print => {
+                for _ in 0..run {
+                    // write(1, rbx + r12, 1)
+                    syscall(&mut code, 1, 1);
+                }
+            }
+            BFCommand::Read => {
+                for _ in 0..run {
+                    // read(0, rbx + r12, 1), and then EOF reads as 255, like in
+                    // the interpreter: test rax, rax; jg over; mov byte [rbx + r12], 255
+                    syscall(&mut code, 0, 0);
+                    code.extend_from_slice(&[0x48, 0x85, 0xc0, 0x7f, 0x05]);
+                    code.extend_from_slice(&[0x42, 0xc6, 0x04, 0x23, 0xff]);
+                }
+            }
+            BFCommand::BeginLoop => {
+                for _ in 0..run {
+                    // cmp byte [rbx + r12], 0; je after the loop
+                    code.extend_from_slice(&[0x42, 0x80, 0x3c, 0x23, 0x00, 0x0f, 0x84, 0, 0, 0, 0]);
+                    open.push(code.len());
+                }
+            }
+            BFCommand::EndLoop => {
+                for _ in 0..run {
+                    // cmp byte [rbx + r12], 0; jne to the start of the loop
+                    let start = open.pop().expect("the loops of a program are balanced");
+                    code.extend_from_slice(&[0x42, 0x80, 0x3c, 0x23, 0x00, 0x0f, 0x85]);
+                    let end = code.len() + 4;
+                    put32(&mut code, (start as i64 - end as i64) as u32);
+                    code[start - 4..start].copy_from_slice(&((end - start) as u32).to_le_bytes());
+                }
+            }
+        }
+    }
+    // exit(0)
+    code.extend_from_slice(&[0xb8, 60, 0, 0, 0, 0x31, 0xff, 0x0f, 0x05]);
+    code
+}
+
+/// A `read` or `write` of the cell at the pointer, with the `number` of
+/// the syscall and the file descriptor `fd`.
+fn syscall(code: &mut Vec<u8>, number: u8, fd: u8) {
+    // mov eax, number; mov edi, fd; lea rsi, [rbx + r12]; mov edx, 1; syscall
+    code.extend_from_slice(&[0xb8, number, 0, 0, 0, 0xbf, fd, 0, 0, 0]);
+    code.extend_from_slice(&[0x4a, 0x8d, 0x34, 0x23, 0xba, 1, 0, 0, 0, 0x0f, 0x05]);
+}
+
+/// A loadable segment, with its `flags` of the permissions it's mapped with.
+fn segment(file: &mut Vec<u8>, flags: u32, offset: u64, address: u64, size: u64, memory: u64) {
+    put32(file, 1);
+    put32(file, flags);
+    put64(file, offset);
+    put64(file, address);
+    put64(file, address);
+    put64(file, size);
+    put64(file, memory);
+    put64(file, PAGE);
+}
+
+fn put16(out: &mut Vec<u8>, n: u16) {
+    out.extend_from_slice(&n.to_le_bytes());
+}
+
+fn put32(out: &mut Vec<u8>, n: u32) {
+    out.extend_from_slice(&n.to_le_bytes());
+}
+
+fn put64(out: &mut Vec<u8>, n: u64) {
+    out.extend_from_slice(&n.to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bfrs_common::parser::parse_str;
+    use std::convert::TryInto;
+
+    #[test]
+    fn executables_are_loaded_with_a_tape() {
+        let elf = to_elf(&parse_str("+[.]").unwrap(), 100);
+        assert!(elf.starts_with(b"\x7fELF\x02\x01\x01"));
+        let read64 = |at: usize| u64::from_le_bytes(elf[at..at + 8].try_into().unwrap());
+        assert_eq!(read64(24), BASE + CODE as u64);
+        // the tape is as big as it was asked for, and isn't in the file
+        let tape = ELF_HEADER + PROGRAM_HEADER;
+        assert_eq!(read64(tape + 32), 0);
+        assert_eq!(read64(tape + 40), 100);
+        assert_eq!(read64(tape + 16) % PAGE, 0);
+        // and the code knows where it is
+        assert_eq!(elf[CODE..CODE + 2], [0x48, 0xbb]);
+        assert_eq!(read64(CODE + 2), read64(tape + 16));
+    }
+
+    #[test]
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    fn executables_run_like_the_interpreter() {
+        use std::io::Write;
+        use std::os::unix::fs::OpenOptionsExt;
+        use std::process::{Command, Stdio};
+
+        let dir = std::env::temp_dir().join(format!("bfrs-elf-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let run = |src: &str, cells: usize, input: &[u8]| {
+            let path = dir.join("program");
+            let mut file = std::fs::OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .mode(0o755)
+                .open(&path)
+                .unwrap();
+            file.write_all(&to_elf(&parse_str(src).unwrap(), cells))
+                .unwrap();
+            drop(file);
+            let mut child = Command::new(&path)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .spawn()
+                .unwrap();
+            child.stdin.take().unwrap().write_all(input).unwrap();
+            let output = child.wait_with_output().unwrap();
+            assert!(output.status.success());
+            output.stdout
+        };
+        // the end of the input reads as 255, which ends the loop
+        assert_eq!(run(",+[-.,+]", 10, b"echo"), b"echo");
+        assert_eq!(run(",+.", 10, b""), [0]);
+        // the tape wraps around both ways, and so do the cells
+        assert_eq!(run("<+>>>+.<<<.--.>>.", 4, b""), [1, 1, 255, 0]);
+        assert_eq!(run("++++++++[>++++++++<-]>+.", 2, b""), b"A");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,108 @@
+//! Translating a program between dialects, like from Ook! to brainfuck,
+//! so it can be fed to tools that only read one of them. Only the
+//! commands are kept.
+
+use bfrs::cli::{load_dialect, Source};
+use bfrs_common::dialect::{Dialect, DialectError};
+use bfrs_common::BFCommand;
+use bfrs_patterns::cli::Common;
+use std::error::Error;
+use std::io::{self, Write};
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+pub struct Opt {
+    /// Input files, converted as a single program made of all of them in order
+    #[structopt()]
+    inputs: Vec<String>,
+
+    /// the dialect the program is written in: `brainfuck` (or `bf`),
+    /// `ook`, `blub` or a file mapping every command to its token
+    #[structopt(long, default_value = "brainfuck")]
+    from: String,
+
+    /// the dialect to write the program in, like `--from`
+    #[structopt(long, default_value = "brainfuck")]
+    to: String,
+
+    /// the most characters on a line, 0 to write the whole program on one
+    #[structopt(short, long, default_value = "80")]
+    width: usize,
+}
+
+pub fn run(opt: Opt, common: &Common) -> Result<(), Box<dyn Error>> {
+    let to = load_dialect(&opt.to)?;
+    let source = Source {
+        inputs: opt.inputs,
+        dialect: opt.from,
+    };
+    let (instructions, _) = source.load(common.positions())?;
+    let mut out = io::stdout().lock();
+    write(&instructions, &to, opt.width, &mut out)?;
+    Ok(out.flush()?)
+}
+
+/// Writes the commands in `dialect`, on lines of at most `width` characters,
+/// unless a single token is longer. Tokens are separated by spaces, unless
+/// they're all a single byte.
+fn write(
+    instructions: &[BFCommand],
+    dialect: &Dialect,
+    width: usize,
+    out: &mut dyn Write,
+) -> Result<(), Box<dyn Error>> {
+    let separator = if dialect.is_single_byte() { "" } else { " " };
+    let mut line = String::new();
+    for &command in instructions {
+        let token = dialect
+            .token(command)
+            .ok_or(DialectError::MissingToken { command })?;
+        if !line.is_empty() {
+            let len = line.chars().count() + separator.len() + token.chars().count();
+            if width > 0 && len > width {
+                writeln!(out, "{}", line)?;
+                line.clear();
+            } else {
+                line.push_str(separator);
+            }
+        }
+        line.push_str(token);
+    }
+    if !line.is_empty() {
+        writeln!(out, "{}", line)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bfrs_common::parser::parse_str;
+
+    fn converted(src: &str, dialect: &Dialect, width: usize) -> String {
+        let mut out = Vec::new();
+        write(&parse_str(src).unwrap(), dialect, width, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn tokens_are_separated_unless_single_bytes() {
+        assert_eq!(
+            converted("+[-]", &Dialect::ook(), 0),
+            "Ook. Ook. Ook! Ook? Ook! Ook! Ook? Ook!\n"
+        );
+        assert_eq!(converted("+[-] .", &Dialect::brainfuck(), 0), "+[-].\n");
+        assert_eq!(converted("", &Dialect::brainfuck(), 0), "");
+    }
+
+    #[test]
+    fn lines_fit_in_the_width() {
+        assert_eq!(
+            converted("+-+", &Dialect::ook(), 10),
+            "Ook. Ook.\nOok! Ook!\nOok. Ook.\n"
+        );
+        // a token longer than the width is still written whole
+        assert_eq!(converted("+", &Dialect::ook(), 3), "Ook. Ook.\n");
+        assert_eq!(converted("+++", &Dialect::brainfuck(), 2), "++\n+\n");
+    }
+}
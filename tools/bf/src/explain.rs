@@ -0,0 +1,226 @@
+//! What is at a spot of a program: the instruction there, the bracket
+//! it jumps to, the loops around it and the idioms it's part of, for
+//! an editor to show what's under the cursor.
+
+use bfrs::cli::Source;
+use bfrs_common::program::Program;
+use bfrs_common::{BFCommand, Location, Position};
+use bfrs_patterns::automaton::CompiledLibrary;
+use bfrs_patterns::cli::Common;
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+pub struct Opt {
+    #[structopt(flatten)]
+    source: Source,
+
+    /// the spot to explain, as a byte offset or as `line:column`, counted
+    /// like the positions of the program
+    #[structopt(long)]
+    at: At,
+}
+
+/// A spot of a source.
+#[derive(Debug, Clone, Copy)]
+enum At {
+    Offset(usize),
+    Position(Position),
+}
+
+impl FromStr for At {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let number = |n: &str| {
+            n.parse()
+                .map_err(|_| format!("`{}` isn't a byte offset or a `line:column`", s))
+        };
+        Ok(match s.split_once(':') {
+            Some((line, column)) => Self::Position(Position {
+                line: number(line)?,
+                column: number(column)?,
+            }),
+            None => Self::Offset(number(s)?),
+        })
+    }
+}
+
+impl fmt::Display for At {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Offset(offset) => write!(f, "byte {}", offset),
+            Self::Position(position) => write!(f, "{}", position),
+        }
+    }
+}
+
+impl At {
+    /// Whether the spot is after `location`.
+    fn is_after(self, location: &Location) -> bool {
+        match self {
+            Self::Offset(offset) => offset > location.byte_offset,
+            Self::Position(position) => {
+                (position.line, position.column)
+                    > (location.position.line, location.position.column)
+            }
+        }
+    }
+
+    /// Whether the spot is at `location`.
+    fn is_at(self, location: &Location) -> bool {
+        match self {
+            Self::Offset(offset) => offset == location.byte_offset,
+            Self::Position(position) => position == location.position,
+        }
+    }
+}
+
+/// The index of the first instruction at the spot or after it, out of the
+/// ones at `locations`, of a source that ends at `end`.
+fn first_from(locations: &[Location], end: &Location, at: At) -> Result<usize, String> {
+    if at.is_after(end) || at.is_at(end) {
+        return Err(format!("the program ends at {}, before {}", end, at));
+    }
+    Ok(locations.partition_point(|location| at.is_after(location)))
+}
+
+pub fn run(opt: Opt, common: &Common) -> Result<(), Box<dyn Error>> {
+    let (program, filename, end) = opt.source.load_program_to_end(common.positions())?;
+    let locations = program.locations.as_deref().unwrap_or_default();
+    let index = first_from(locations, &end, opt.at)?;
+    let found = locations
+        .get(index)
+        .filter(|location| opt.at.is_at(location))
+        .map(|_| index);
+
+    match found {
+        Some(index) => {
+            let instruction = program.instructions[index];
+            println!("{}: `{}` at {}", filename, instruction, locations[index]);
+            if let BFCommand::BeginLoop | BFCommand::EndLoop = instruction {
+                let jump = program.jump(index);
+                println!(
+                    "matching bracket: `{}` at {}",
+                    program.instructions[jump], locations[jump]
+                );
+            }
+        }
+        None => println!("{}: no instruction, only comments", filename),
+    }
+
+    let mut enclosing = open_before(&program, index);
+    if found.is_some() && program.instructions[index] == BFCommand::EndLoop {
+        // the loop it closes isn't around it.
+        enclosing.pop();
+    }
+    println!("depth: {}", enclosing.len());
+    if let Some(&start) = enclosing.last() {
+        println!(
+            "in the loop from {} to {}",
+            locations[start],
+            locations[program.jump(start)]
+        );
+    }
+
+    if let Some(index) = found {
+        let library = bfrs_patterns::library::idioms();
+        let matches = CompiledLibrary::new(&library).find_all(&program.instructions);
+        for found_match in matches.iter() {
+            let result = &found_match.result;
+            if (result.start..result.start + result.commands.len()).contains(&index) {
+                let (start, end) = result.locate(locations);
+                println!(
+                    "idiom: {} from {} to {}",
+                    library.entries[found_match.pattern].name, start, end
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The opening brackets of the loops still open right before
+/// the instruction at `index`, from the outermost in.
+fn open_before(program: &Program, index: usize) -> Vec<usize> {
+    let mut open = Vec::new();
+    for (i, &instruction) in program.instructions[..index].iter().enumerate() {
+        match instruction {
+            BFCommand::BeginLoop => open.push(i),
+            BFCommand::EndLoop => {
+                open.pop();
+            }
+            _ => (),
+        }
+    }
+    open
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bfrs_common::dialect::Dialect;
+    use bfrs_common::{parser, PositionConfig};
+
+    /// The instruction at `at` in `src`, if any, with positions counted the
+    /// way `positions` says, or the error for a spot past its end.
+    fn instruction_at(
+        src: &str,
+        at: &str,
+        positions: PositionConfig,
+    ) -> Result<Option<usize>, String> {
+        let recovered =
+            parser::parse_recovering_with(src.bytes().map(Ok), Dialect::default(), positions)
+                .unwrap();
+        let end = recovered.end;
+        let program = Program::located(recovered.into_result().unwrap());
+        let locations = program.locations.unwrap();
+        let at: At = at.parse()?;
+        let index = first_from(&locations, &end, at)?;
+        Ok(locations
+            .get(index)
+            .filter(|location| at.is_at(location))
+            .map(|_| index))
+    }
+
+    #[test]
+    fn offsets_on_later_lines() {
+        let src = "+ add\n\t[-] clear\n.";
+        let positions = PositionConfig::default();
+        assert_eq!(instruction_at(src, "7", positions), Ok(Some(1)));
+        assert_eq!(instruction_at(src, "17", positions), Ok(Some(4)));
+        // the tab before the loop is a comment.
+        assert_eq!(instruction_at(src, "6", positions), Ok(None));
+    }
+
+    #[test]
+    fn positions_are_counted_like_the_program() {
+        let src = "+ add\n\t[-] clear\n.";
+        let positions = PositionConfig::default();
+        assert_eq!(instruction_at(src, "2:2", positions), Ok(Some(1)));
+        let wide = PositionConfig {
+            tab_width: 4,
+            ..positions
+        };
+        assert_eq!(instruction_at(src, "2:5", wide), Ok(Some(1)));
+        assert_eq!(instruction_at(src, "2:2", wide), Ok(None));
+        let zero_based = PositionConfig {
+            zero_based: true,
+            ..positions
+        };
+        assert_eq!(instruction_at(src, "1:1", zero_based), Ok(Some(1)));
+        assert_eq!(instruction_at(src, "2:0", zero_based), Ok(Some(4)));
+    }
+
+    #[test]
+    fn spots_past_the_end_are_errors() {
+        let src = "+ add\n\t[-] clear\n.";
+        let positions = PositionConfig::default();
+        assert!(instruction_at(src, "18", positions).is_err());
+        assert!(instruction_at(src, "1000", positions).is_err());
+        assert!(instruction_at(src, "3:2", positions).is_err());
+        assert!(instruction_at(src, "9:1", positions).is_err());
+        assert!(instruction_at("", "0", positions).is_err());
+    }
+}
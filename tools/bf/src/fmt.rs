@@ -0,0 +1,97 @@
+//! Printing a program with every loop on its own lines, indented
+//! by how deep it is. Only the commands are kept.
+
+use bfrs::cli::Source;
+use bfrs_common::BFCommand;
+use bfrs_patterns::cli::Common;
+use std::error::Error;
+use std::io::{self, Write};
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+pub struct Opt {
+    #[structopt(flatten)]
+    source: Source,
+
+    /// spaces every loop is indented with
+    #[structopt(long, default_value = "2")]
+    indent: usize,
+
+    /// the most commands on a line, not counting the indentation
+    #[structopt(short, long, default_value = "80")]
+    width: usize,
+}
+
+pub fn run(opt: Opt, common: &Common) -> Result<(), Box<dyn Error>> {
+    let (instructions, _) = opt.source.load(common.positions())?;
+    let mut out = io::stdout().lock();
+    format(&instructions, opt.indent, opt.width.max(1), &mut out)?;
+    Ok(out.flush()?)
+}
+
+/// Writes the commands, with the brackets of every loop on lines of their
+/// own and the commands between them on lines of at most `width`.
+fn format(
+    instructions: &[BFCommand],
+    indent: usize,
+    width: usize,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    let mut depth = 0;
+    let mut line = String::new();
+    let flush = |line: &mut String, depth: usize, out: &mut dyn Write| {
+        if line.is_empty() {
+            return Ok(());
+        }
+        writeln!(out, "{:indent$}{}", "", line, indent = depth * indent)?;
+        line.clear();
+        Ok(())
+    };
+    for &instruction in instructions {
+        match instruction {
+            BFCommand::BeginLoop => {
+                flush(&mut line, depth, out)?;
+                writeln!(out, "{:indent$}[", "", indent = depth * indent)?;
+                depth += 1;
+            }
+            BFCommand::EndLoop => {
+                flush(&mut line, depth, out)?;
+                depth -= 1;
+                writeln!(out, "{:indent$}]", "", indent = depth * indent)?;
+            }
+            _ => {
+                if line.len() == width {
+                    flush(&mut line, depth, out)?;
+                }
+                line.push(instruction as u8 as char);
+            }
+        }
+    }
+    flush(&mut line, depth, out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bfrs_common::parser::parse_str;
+
+    fn formatted(src: &str, width: usize) -> String {
+        let mut out = Vec::new();
+        format(&parse_str(src).unwrap(), 2, width, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn loops_are_indented() {
+        assert_eq!(
+            formatted("++[>+[-]<-].", 80),
+            "++\n[\n  >+\n  [\n    -\n  ]\n  <-\n]\n.\n"
+        );
+    }
+
+    #[test]
+    fn lines_are_at_most_as_wide_as_asked() {
+        assert_eq!(formatted("+++++ comment ++", 3), "+++\n+++\n+\n");
+        assert_eq!(formatted("", 3), "");
+    }
+}
@@ -0,0 +1,62 @@
+use bfrs_patterns::cli::{self as common_cli, search, Common};
+use cli::{Command, Opt};
+use std::error::Error;
+use std::io;
+use structopt::StructOpt;
+
+mod analyze;
+mod cli;
+mod cmp;
+mod compile;
+mod convert;
+mod explain;
+mod fmt;
+mod man;
+mod repl;
+mod serve;
+mod stdlib;
+
+fn main() {
+    let mut args: Vec<_> = std::env::args_os().collect();
+    // only what comes after `run` can be a program named like a subcommand of it.
+    if let Some(run) = args.iter().position(|arg| arg == "run") {
+        bfrs::cli::prefer_files(&mut args[run + 1..]);
+    }
+    let opt = Opt::from_iter_safe(args).unwrap_or_else(|e| common_cli::usage_error(e));
+    let common = opt.common;
+    common.init_logging();
+    // like grep, 1 is left for `match` finding nothing, and `cmp` telling
+    // programs apart, like cmp, and errors are 2.
+    match run(opt.command, &common) {
+        Ok(true) => (),
+        Ok(false) => ::std::process::exit(common_cli::NOT_FOUND),
+        Err(ref e) => {
+            common.report(&**e);
+            ::std::process::exit(common_cli::FAILED)
+        }
+    }
+}
+
+/// Runs the subcommand, returning whether it found anything, which only
+/// `match` can fail to do, or for `cmp`, whether the programs agreed.
+fn run(command: Command, common: &Common) -> Result<bool, Box<dyn Error>> {
+    let done = match command {
+        Command::Run(run) => bfrs::cli::run(run, common),
+        Command::Fmt(fmt) => fmt::run(fmt, common),
+        Command::Match(search) => return search::run(search, common),
+        Command::Cmp(cmp) => return cmp::run(cmp, common),
+        Command::Compile(compile) => compile::run(compile, common),
+        Command::Convert(convert) => convert::run(convert, common),
+        Command::Analyze(analyze) => analyze::run(analyze, common),
+        Command::Explain(explain) => explain::run(explain, common),
+        Command::Repl(repl) => repl::run(repl, common),
+        Command::Serve(serve) => serve::run(serve, common),
+        Command::Stdlib(stdlib) => stdlib::run(stdlib),
+        Command::Completions { shell } => {
+            Opt::clap().gen_completions_to("bf", shell, &mut io::stdout());
+            Ok(())
+        }
+        Command::Man => man::write(Opt::clap(), &mut io::stdout().lock()).map_err(Into::into),
+    };
+    done.map(|()| true)
+}
@@ -0,0 +1,180 @@
+//! The manual page of `bf`, in roff, made from the help of every
+//! subcommand, so it's always in sync with the arguments.
+
+use std::io::{self, Write};
+use structopt::clap::{self, App};
+
+/// Writes the manual page of `app` and all of its subcommands.
+pub fn write(app: App, out: &mut dyn Write) -> io::Result<()> {
+    // without wrapping, every argument is on a line of its own.
+    let app = app.set_term_width(0);
+    let main = Help::parse(&help(&app, &[]));
+    writeln!(
+        out,
+        ".TH {} 1 \"\" \"{}\"",
+        main.name.to_uppercase(),
+        escape(&main.version)
+    )?;
+    writeln!(out, ".SH NAME")?;
+    writeln!(out, "{} \\- {}", main.name, escape(&main.about))?;
+    write_sections(&main, "SH", out)?;
+    write_subcommands(&app, &main, &mut Vec::new(), out)
+}
+
+/// Writes a section for every subcommand of the command at `path`, and their own ones.
+fn write_subcommands(
+    app: &App,
+    command: &Help,
+    path: &mut Vec<String>,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    let subcommands = command
+        .sections
+        .iter()
+        .filter(|(heading, _)| heading == "SUBCOMMANDS")
+        .flat_map(|(_, entries)| entries.iter().map(|(name, _)| name))
+        .filter(|&name| name != "help");
+    for subcommand in subcommands {
+        path.push(subcommand.clone());
+        let sub = Help::parse(&help(app, path));
+        writeln!(
+            out,
+            ".SH \"{}\"",
+            std::iter::once(app.get_name())
+                .chain(path.iter().map(String::as_str))
+                .collect::<Vec<_>>()
+                .join(" ")
+                .to_uppercase()
+        )?;
+        writeln!(out, "{}", escape(&sub.about))?;
+        write_sections(&sub, "SS", out)?;
+        write_subcommands(app, &sub, path, out)?;
+        path.pop();
+    }
+    Ok(())
+}
+
+/// The help of a subcommand, as `bf` would print it.
+fn help(app: &App, subcommands: &[String]) -> String {
+    let args = std::iter::once(app.get_name())
+        .chain(subcommands.iter().map(String::as_str))
+        .chain(std::iter::once("--help"));
+    match app.clone().get_matches_from_safe(args) {
+        Err(e) if e.kind == clap::ErrorKind::HelpDisplayed => e.message,
+        _ => unreachable!("--help always shows the help"),
+    }
+}
+
+/// The parts of the help of a command.
+struct Help {
+    name: String,
+    version: String,
+    about: String,
+    /// every section, like `FLAGS`, along with its entries and their descriptions
+    sections: Vec<(String, Vec<(String, String)>)>,
+}
+
+impl Help {
+    fn parse(help: &str) -> Self {
+        let mut lines = help.lines();
+        let (name, version) = lines
+            .next()
+            .and_then(|line| line.rsplit_once(' '))
+            .map_or((String::new(), String::new()), |(name, version)| {
+                (name.to_string(), version.to_string())
+            });
+        let about = lines
+            .by_ref()
+            .take_while(|line| !line.is_empty())
+            .collect::<Vec<_>>()
+            .join(" ");
+        let mut sections: Vec<(String, Vec<(String, String)>)> = Vec::new();
+        for line in lines {
+            if let Some(heading) = line.strip_suffix(':').filter(|_| !line.starts_with(' ')) {
+                sections.push((heading.to_string(), Vec::new()));
+            } else if let Some((_, entries)) = sections.last_mut() {
+                let line = line.trim();
+                if line.is_empty() {
+                    continue;
+                }
+                let (entry, description) = line.split_once("  ").unwrap_or((line, ""));
+                entries.push((entry.to_string(), description.trim().to_string()));
+            }
+        }
+        Self {
+            name,
+            version,
+            about,
+            sections,
+        }
+    }
+}
+
+/// Writes every section of the help, with the usage first, under `macro` headings.
+fn write_sections(help: &Help, r#macro: &str, out: &mut dyn Write) -> io::Result<()> {
+    for (heading, entries) in help.sections.iter() {
+        if heading == "USAGE" {
+            writeln!(out, ".{} SYNOPSIS", r#macro)?;
+            for (usage, _) in entries {
+                writeln!(out, "{}", escape(usage))?;
+                writeln!(out, ".br")?;
+            }
+            continue;
+        }
+        writeln!(out, ".{} {}", r#macro, heading)?;
+        for (entry, description) in entries {
+            writeln!(out, ".TP")?;
+            writeln!(out, "\\fB{}\\fR", escape(entry))?;
+            if !description.is_empty() {
+                writeln!(out, "{}", escape(description))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Escapes the characters roff would take as its own.
+fn escape(text: &str) -> String {
+    let text = text.replace('\\', "\\e").replace('-', "\\-");
+    if text.starts_with('.') || text.starts_with('\'') {
+        format!("\\&{}", text)
+    } else {
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::Opt;
+    use structopt::StructOpt;
+
+    #[test]
+    fn roff_is_escaped() {
+        assert_eq!(escape(r"a-b\c"), r"a\-b\ec");
+        assert_eq!(escape(".start"), r"\&.start");
+    }
+
+    #[test]
+    fn every_subcommand_has_a_section() {
+        let mut out = Vec::new();
+        write(Opt::clap(), &mut out).unwrap();
+        let page = String::from_utf8(out).unwrap();
+        assert!(page.starts_with(".TH BF 1"));
+        for section in [".SH \"BF RUN\"", ".SH \"BF FMT\"", ".SH \"BF MAN\""].iter() {
+            assert!(page.contains(section), "no {} in the page", section);
+        }
+        assert!(!page.contains(".SH \"BF HELP\""));
+        // the arguments of the subcommands are in them
+        assert!(page.contains("\\fB\\-\\-indent <indent>\\fR"));
+    }
+
+    #[test]
+    fn completions_know_the_subcommands() {
+        let mut out = Vec::new();
+        Opt::clap().gen_completions_to("bf", clap::Shell::Bash, &mut out);
+        let script = String::from_utf8(out).unwrap();
+        assert!(script.contains("fmt"));
+        assert!(script.contains("--indent"));
+    }
+}
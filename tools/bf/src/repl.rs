@@ -0,0 +1,370 @@
+//! Running brainfuck a line at a time, keeping the tape between lines.
+//!
+//! A line with a loop that isn't closed yet keeps reading lines
+//! until it is. Lines starting with `:` are commands for the REPL:
+//! `:tape` shows the cells around the pointer, `:reset` clears the
+//! tape and `:quit` leaves, like the end of the input does.
+//!
+//! In a terminal, lines are edited with history, and the bracket under
+//! the cursor stands out along with the one matching it.
+
+use bfrs::{Machine, Program};
+use bfrs_common::parser::{self, ParseErrorKind};
+use bfrs_common::theme::{Theme, RESET};
+use bfrs_patterns::cli::Common;
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::{ValidationContext, ValidationResult, Validator};
+use rustyline::{Editor, Helper};
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::error::Error;
+use std::io::{self, IsTerminal, Write};
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+pub struct Opt {
+    /// amount of cells to use
+    #[structopt(short, long, default_value = "30000")]
+    cells: usize,
+
+    /// keep the history of the lines in this file, between sessions
+    #[structopt(long, parse(from_os_str))]
+    history: Option<PathBuf>,
+}
+
+/// Cells shown at each side of the pointer by `:tape`.
+const TAPE_AROUND: usize = 8;
+
+pub fn run(opt: Opt, common: &Common) -> Result<(), Box<dyn Error>> {
+    let mut session = Session {
+        machine: Machine::new(opt.cells.max(1)),
+        cells: opt.cells.max(1),
+        prompt: io::stdin().is_terminal(),
+    };
+    if session.prompt {
+        edit(&mut session, &opt, common)
+    } else {
+        read(&mut session, common)
+    }
+}
+
+/// Reads the lines to run off stdin, as they come.
+fn read(session: &mut Session, common: &Common) -> Result<(), Box<dyn Error>> {
+    let stdin = io::stdin();
+    let mut source = String::new();
+    loop {
+        let mut line = String::new();
+        if stdin.read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        if source.is_empty() {
+            match session.command(line.trim()) {
+                Some(true) => continue,
+                Some(false) => return Ok(()),
+                None => (),
+            }
+        }
+        source.push_str(&line);
+        match parser::parse_str(&source) {
+            Err(e) if matches!(e.kind, ParseErrorKind::MissingRB(_)) => continue,
+            parsed => session.execute(parsed, common)?,
+        }
+        source.clear();
+    }
+}
+
+/// Edits the lines to run in the terminal, keeping them in the history.
+fn edit(session: &mut Session, opt: &Opt, common: &Common) -> Result<(), Box<dyn Error>> {
+    let mut editor: Editor<Brackets, DefaultHistory> = Editor::new()?;
+    editor.set_helper(Some(Brackets {
+        theme: common.theme(true)?,
+        shown: Cell::new(None),
+    }));
+    if let Some(history) = &opt.history {
+        // there's no history the first time.
+        if history.exists() {
+            editor.load_history(history)?;
+        }
+    }
+    loop {
+        let entry = match editor.readline("bf> ") {
+            Ok(entry) => entry,
+            // ^C drops what was being written, like in a shell.
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e.into()),
+        };
+        if !entry.trim().is_empty() {
+            editor.add_history_entry(entry.as_str())?;
+        }
+        match session.command(entry.trim()) {
+            Some(true) => continue,
+            Some(false) => break,
+            None => session.execute(parser::parse_str(&entry), common)?,
+        }
+    }
+    if let Some(history) = &opt.history {
+        editor.save_history(history)?;
+    }
+    Ok(())
+}
+
+/// The tape of a REPL, which is kept between lines.
+struct Session {
+    machine: Machine,
+    cells: usize,
+    /// whether there's someone at a terminal to show prompts to
+    prompt: bool,
+}
+
+impl Session {
+    /// Runs the command on `line`, if it's one, returning whether to keep going.
+    fn command(&mut self, line: &str) -> Option<bool> {
+        match line {
+            ":quit" => Some(false),
+            ":reset" => {
+                self.machine = Machine::new(self.cells);
+                Some(true)
+            }
+            ":tape" => {
+                print_tape(&self.machine);
+                Some(true)
+            }
+            _ => None,
+        }
+    }
+
+    /// Runs the instructions of a line on the tape, or reports why
+    /// they couldn't be read.
+    fn execute(
+        &mut self,
+        parsed: Result<Vec<bfrs_common::BFCommand>, parser::ParseError>,
+        common: &Common,
+    ) -> Result<(), Box<dyn Error>> {
+        let instructions = match parsed {
+            Ok(instructions) => instructions,
+            Err(e) => {
+                common.report(&e);
+                return Ok(());
+            }
+        };
+        let program = Program::from_instructions(instructions, self.cells);
+        let mut output = LastByte::new(io::stdout().lock());
+        self.machine.run(&program, &mut io::stdin(), &mut output)?;
+        // the prompt goes on a line of its own.
+        if self.prompt && output.last.is_some_and(|byte| byte != b'\n') {
+            writeln!(output)?;
+        }
+        Ok(())
+    }
+}
+
+fn print_tape(machine: &Machine) {
+    let start = machine.pivot.saturating_sub(TAPE_AROUND);
+    let end = (machine.pivot + TAPE_AROUND + 1).min(machine.tape.len());
+    let cells: Vec<_> = (start..end)
+        .map(|i| {
+            if i == machine.pivot {
+                format!("[{}]", machine.tape[i])
+            } else {
+                machine.tape[i].to_string()
+            }
+        })
+        .collect();
+    println!("{}: {}", start, cells.join(" "));
+}
+
+/// Helps the editor with brackets: a line with a loop that isn't closed
+/// yet goes on to the next one, and the bracket under the cursor, or right
+/// before it, is shown along with the one matching it, in the colors the
+/// theme gives matches.
+struct Brackets {
+    /// no theme is no colors
+    theme: Option<Theme>,
+    /// the byte offsets of the brackets shown last
+    shown: Cell<Option<(usize, usize)>>,
+}
+
+impl Brackets {
+    /// The bracket at the cursor and the one matching it, if there's one.
+    fn at_cursor(line: &str, pos: usize) -> Option<(usize, usize)> {
+        let bytes = line.as_bytes();
+        let bracket = [pos, pos.wrapping_sub(1)]
+            .iter()
+            .copied()
+            .find(|&at| matches!(bytes.get(at), Some(b'[' | b']')))?;
+        // going back from a `]`, the brackets swap roles.
+        let (open, close) = match bytes[bracket] {
+            b'[' => (b'[', b']'),
+            _ => (b']', b'['),
+        };
+        let mut depth = 0;
+        let mut around: Box<dyn Iterator<Item = usize>> = match open {
+            b'[' => Box::new(bracket..bytes.len()),
+            _ => Box::new((0..=bracket).rev()),
+        };
+        let other = around.find(|&at| {
+            match bytes[at] {
+                byte if byte == open => depth += 1,
+                byte if byte == close => depth -= 1,
+                _ => (),
+            }
+            depth == 0
+        })?;
+        Some((bracket.min(other), bracket.max(other)))
+    }
+}
+
+impl Highlighter for Brackets {
+    fn highlight<'l>(&self, line: &'l str, _pos: usize) -> Cow<'l, str> {
+        let (theme, (open, close)) = match (&self.theme, self.shown.get()) {
+            (Some(theme), Some(shown)) => (theme, shown),
+            _ => return Cow::Borrowed(line),
+        };
+        let (foreground, background) = theme.matched;
+        let paint = theme.paint(foreground, background, true);
+        let bracket = |at: usize| format!("{}{}{}", paint, &line[at..=at], RESET);
+        Cow::Owned(format!(
+            "{}{}{}{}{}",
+            &line[..open],
+            bracket(open),
+            &line[open + 1..close],
+            bracket(close),
+            &line[close + 1..]
+        ))
+    }
+
+    fn highlight_char(&self, line: &str, pos: usize, forced: bool) -> bool {
+        let before = self.shown.get();
+        self.shown.set(match forced {
+            // the line is done, so nothing stands out once it's left behind.
+            true => None,
+            false => Self::at_cursor(line, pos),
+        });
+        self.theme.is_some() && (before.is_some() || self.shown.get().is_some())
+    }
+}
+
+impl Validator for Brackets {
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        Ok(match parser::parse_str(ctx.input()) {
+            Err(e) if matches!(e.kind, ParseErrorKind::MissingRB(_)) => {
+                ValidationResult::Incomplete
+            }
+            // other errors are reported once the line is run.
+            _ => ValidationResult::Valid(None),
+        })
+    }
+}
+
+impl Completer for Brackets {
+    type Candidate = String;
+}
+
+impl Hinter for Brackets {
+    type Hint = String;
+}
+
+impl Helper for Brackets {}
+
+/// A writer that remembers the last byte written to it.
+struct LastByte<W> {
+    inner: W,
+    last: Option<u8>,
+}
+
+impl<W> LastByte<W> {
+    fn new(inner: W) -> Self {
+        Self { inner, last: None }
+    }
+}
+
+impl<W: Write> Write for LastByte<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        if written > 0 {
+            self.last = Some(buf[written - 1]);
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matching_brackets() {
+        let line = "+[>[-]<]";
+        assert_eq!(Brackets::at_cursor(line, 1), Some((1, 7)));
+        assert_eq!(Brackets::at_cursor(line, 8), Some((1, 7)));
+        assert_eq!(Brackets::at_cursor(line, 4), Some((3, 5)));
+        assert_eq!(Brackets::at_cursor(line, 0), None);
+        // a loop that isn't closed yet
+        assert_eq!(Brackets::at_cursor("[[-]", 0), None);
+        assert_eq!(Brackets::at_cursor("[[-]", 1), Some((1, 3)));
+    }
+
+    #[test]
+    fn brackets_stand_out() {
+        let brackets = Brackets {
+            theme: Theme::builtin("default"),
+            shown: Cell::new(None),
+        };
+        assert!(!brackets.highlight_char("+-", 1, false));
+        assert_eq!(brackets.highlight("+-", 1), "+-");
+        assert!(brackets.highlight_char("[-]", 0, false));
+        let bracket = |b| format!("\x1b[0;1;38;5;1m{}{}", b, RESET);
+        assert_eq!(
+            brackets.highlight("[-]", 0),
+            format!("{}-{}", bracket('['), bracket(']'))
+        );
+        // and stop standing out once the line is done
+        assert!(brackets.highlight_char("[-]", 0, true));
+        assert_eq!(brackets.highlight("[-]", 0), "[-]");
+
+        let plain = Brackets {
+            theme: None,
+            shown: Cell::new(None),
+        };
+        assert!(!plain.highlight_char("[-]", 0, false));
+        assert_eq!(plain.highlight("[-]", 0), "[-]");
+    }
+
+    #[test]
+    fn commands() {
+        let mut session = Session {
+            machine: Machine::new(4),
+            cells: 4,
+            prompt: false,
+        };
+        session.machine.tape[1] = 7;
+        session.machine.pivot = 1;
+        assert_eq!(session.command("++"), None);
+        assert_eq!(session.command(":reset"), Some(true));
+        assert_eq!(session.machine.tape[..], [0; 4]);
+        assert_eq!(session.machine.pivot, 0);
+        assert_eq!(session.command(":quit"), Some(false));
+    }
+
+    #[test]
+    fn the_last_byte_is_remembered() {
+        let mut output = LastByte::new(Vec::new());
+        assert_eq!(output.last, None);
+        output.write_all(b"ab\n").unwrap();
+        assert_eq!(output.last, Some(b'\n'));
+        output.write_all(b"").unwrap();
+        output.write_all(b"c").unwrap();
+        assert_eq!(output.last, Some(b'c'));
+        assert_eq!(output.inner, b"ab\nc");
+    }
+}
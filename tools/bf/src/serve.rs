@@ -0,0 +1,287 @@
+//! An HTTP API running programs, to back a web playground or a grader
+//! with the interpreter.
+//!
+//! `POST /run` takes a JSON object with the `program` and its `input`,
+//! and answers with what it printed, how much it did and its errors:
+//! ```text
+//! $ curl -d '{"program": ",.,.", "input": "hi"}' localhost:8080/run
+//! {"output":"hi","completed":true,"limit":null,"stats":{"instructions":4,"bytes_read":2,"bytes_printed":2},"errors":[]}
+//! ```
+//! Every program runs with the limits of the server, which a request
+//! can lower with `max_steps`, `max_time` and `max_output`. A program
+//! stopped by one of them has the name of the limit in `limit`.
+//! Requests are answered one at a time, in the order they come.
+
+use bfrs::capture::Limit;
+use bfrs::eval::{evaluate, Eof, EvalError, EvalOptions};
+use bfrs_common::errors::Diagnostic;
+use bfrs_patterns::cli::Common;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::io::Read;
+use std::time::Duration;
+use structopt::StructOpt;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+/// The longest body of a request, in bytes.
+const MAX_BODY: u64 = 1 << 20;
+
+#[derive(Debug, StructOpt)]
+pub struct Opt {
+    /// the port to listen on
+    #[structopt(short, long, default_value = "8080")]
+    port: u16,
+
+    /// the address to listen on, which only lets this machine in by default
+    #[structopt(long, default_value = "127.0.0.1")]
+    host: String,
+
+    /// amount of cells of the tape programs run on
+    #[structopt(short, long, default_value = "30000")]
+    cells: usize,
+
+    /// the most instructions a program runs
+    #[structopt(long, default_value = "100000000")]
+    max_steps: u64,
+
+    /// the longest a program runs, in milliseconds
+    #[structopt(long, default_value = "1000")]
+    max_time: u64,
+
+    /// the most bytes a program prints
+    #[structopt(long, default_value = "65536")]
+    max_output: usize,
+}
+
+/// The body of `POST /run`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RunRequest {
+    program: String,
+    #[serde(default)]
+    input: String,
+    /// limits lower than the ones of the server
+    max_steps: Option<u64>,
+    max_time: Option<u64>,
+    max_output: Option<usize>,
+}
+
+/// The answer to `POST /run`.
+#[derive(Debug, Default, Serialize)]
+struct RunResponse {
+    /// what the program printed, with the bytes that aren't UTF-8 replaced
+    output: String,
+    completed: bool,
+    /// the limit that stopped the program, `steps`, `time` or `output`
+    limit: Option<&'static str>,
+    stats: Option<Stats>,
+    /// the diagnostics of the errors in the program or the request
+    errors: Vec<serde_json::Value>,
+}
+
+#[derive(Debug, Serialize)]
+struct Stats {
+    instructions: u64,
+    bytes_read: u64,
+    bytes_printed: u64,
+}
+
+impl RunResponse {
+    /// The answer to a request that couldn't be run.
+    fn error(message: impl Into<String>) -> Self {
+        Self {
+            errors: vec![json(&Diagnostic::new(message))],
+            ..Self::default()
+        }
+    }
+}
+
+pub fn run(opt: Opt, _: &Common) -> Result<(), Box<dyn Error>> {
+    let server = Server::http((opt.host.as_str(), opt.port)).map_err(|e| e.to_string())?;
+    eprintln!("listening on http://{}:{}", opt.host, opt.port);
+    for mut request in server.incoming_requests() {
+        let _span = tracing::info_span!("request", url = %request.url()).entered();
+        let (status, body) = match read_body(&mut request) {
+            Ok(body) => answer(&opt, &body),
+            Err((status, message)) => (status, RunResponse::error(message)),
+        };
+        let content_type =
+            Header::from_bytes("Content-Type", "application/json").expect("the header is valid");
+        let response = Response::from_string(serde_json::to_string(&body)?)
+            .with_status_code(status)
+            .with_header(content_type);
+        tracing::info!(status, completed = body.completed, "answered");
+        // a client going away isn't a reason to stop serving the rest.
+        if let Err(e) = request.respond(response) {
+            tracing::warn!("error answering a request: {}", e);
+        }
+    }
+    Ok(())
+}
+
+/// The body of a request, if it's a `POST /run` with one that can
+/// be read, or the status and the error to answer it with.
+fn read_body(request: &mut Request) -> Result<String, (u16, String)> {
+    match (request.method(), request.url()) {
+        (Method::Post, "/run") => (),
+        (_, "/run") => return Err((405, "only POST can be used on /run".into())),
+        (_, url) => return Err((404, format!("there is nothing at {}", url))),
+    }
+    let mut body = String::new();
+    match request
+        .as_reader()
+        .take(MAX_BODY + 1)
+        .read_to_string(&mut body)
+    {
+        Ok(len) if len as u64 > MAX_BODY => Err((413, "the request is too long".into())),
+        Ok(_) => Ok(body),
+        Err(e) => Err((400, format!("can't read the request: {}", e))),
+    }
+}
+
+/// Runs the program of a request, returning the status
+/// and the body to answer it with.
+fn answer(opt: &Opt, body: &str) -> (u16, RunResponse) {
+    let request: RunRequest = match serde_json::from_str(body) {
+        Ok(request) => request,
+        Err(e) => return (400, RunResponse::error(format!("bad request: {}", e))),
+    };
+    let options = EvalOptions {
+        max_steps: lowered(request.max_steps, opt.max_steps),
+        max_output: lowered(request.max_output, opt.max_output),
+        max_tape: opt.cells,
+        timeout: Some(Duration::from_millis(lowered(
+            request.max_time,
+            opt.max_time,
+        ))),
+        eof: Eof::default(),
+    };
+    let (evaluation, limit) = match evaluate(
+        request.program.as_bytes(),
+        request.input.as_bytes(),
+        &options,
+    ) {
+        Ok(evaluation) => (evaluation, None),
+        Err(EvalError::Limit(limit, evaluation)) => (evaluation, Some(limit)),
+        Err(EvalError::Parse(errors)) => {
+            let response = RunResponse {
+                errors: errors.iter().map(|e| json(&e.diagnostic())).collect(),
+                ..RunResponse::default()
+            };
+            return (422, response);
+        }
+        Err(e) => return (500, RunResponse::error(e.to_string())),
+    };
+    let response = RunResponse {
+        output: String::from_utf8_lossy(&evaluation.output).into_owned(),
+        completed: limit.is_none(),
+        limit: limit.map(|limit| match limit {
+            Limit::Fuel => "steps",
+            Limit::Output => "output",
+            Limit::Time => "time",
+        }),
+        stats: Some(Stats {
+            instructions: evaluation.stats.instructions,
+            bytes_read: evaluation.stats.bytes_read,
+            bytes_printed: evaluation.stats.bytes_printed,
+        }),
+        errors: Vec::new(),
+    };
+    (200, response)
+}
+
+/// The limit a request asked for, as long as it's lower than the server's.
+fn lowered<T: Ord>(asked: Option<T>, max: T) -> T {
+    match asked {
+        Some(asked) => asked.min(max),
+        None => max,
+    }
+}
+
+/// A diagnostic as JSON, like `--error-format json` reports it.
+fn json(diagnostic: &Diagnostic) -> serde_json::Value {
+    serde_json::from_str(&diagnostic.to_json()).expect("diagnostics are valid JSON")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::io::Write;
+    use std::net::TcpStream;
+
+    fn opt(args: &[&str]) -> Opt {
+        Opt::from_iter_safe(std::iter::once(&"serve").chain(args)).unwrap()
+    }
+
+    /// The status and the JSON answer to a request with `body`.
+    fn post(opt: &Opt, body: serde_json::Value) -> (u16, serde_json::Value) {
+        let (status, response) = answer(opt, &body.to_string());
+        (status, serde_json::to_value(&response).unwrap())
+    }
+
+    #[test]
+    fn programs_are_run_on_their_input() {
+        let (status, response) = post(&opt(&[]), json!({"program": ",.,.", "input": "hi"}));
+        assert_eq!(status, 200);
+        assert_eq!(
+            response,
+            json!({
+                "output": "hi",
+                "completed": true,
+                "limit": null,
+                "stats": {"instructions": 4, "bytes_read": 2, "bytes_printed": 2},
+                "errors": [],
+            })
+        );
+    }
+
+    #[test]
+    fn requests_only_lower_the_limits() {
+        let opt = opt(&["--max-steps", "10"]);
+        let (status, response) = post(&opt, json!({"program": "+[.]", "max_steps": 1000}));
+        assert_eq!(status, 200);
+        assert_eq!(response["completed"], false);
+        assert_eq!(response["limit"], "steps");
+        assert_eq!(response["stats"]["instructions"], 10);
+        let (_, response) = post(&opt, json!({"program": "+[.]", "max_output": 3}));
+        assert_eq!(response["limit"], "output");
+        assert_eq!(response["output"], "\u{1}\u{1}\u{1}");
+    }
+
+    #[test]
+    fn bad_requests_are_told_why() {
+        let opt = opt(&[]);
+        let (status, response) = post(&opt, json!({"program": "+]"}));
+        assert_eq!(status, 422);
+        assert_eq!(response["errors"][0]["code"], "BF0001");
+        assert_eq!(response["stats"], serde_json::Value::Null);
+        let (status, response) = post(&opt, json!({"source": "+"}));
+        assert_eq!(status, 400);
+        let message = response["errors"][0]["message"].as_str().unwrap();
+        assert!(message.starts_with("bad request: unknown field `source`"));
+    }
+
+    /// What is read from the request `raw` sent to a server.
+    fn body_of(raw: &str) -> Result<String, (u16, String)> {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let address = server.server_addr().to_ip().unwrap();
+        let mut client = TcpStream::connect(address).unwrap();
+        client.write_all(raw.as_bytes()).unwrap();
+        let mut request = server.recv().unwrap();
+        read_body(&mut request)
+    }
+
+    #[test]
+    fn only_posts_to_run_are_read() {
+        let post = "POST /run HTTP/1.1\r\nContent-Length: 2\r\n\r\n{}";
+        assert_eq!(body_of(post), Ok("{}".to_string()));
+        let get = "GET /run HTTP/1.1\r\n\r\n";
+        assert_eq!(body_of(get).unwrap_err().0, 405);
+        let elsewhere = "POST /eval HTTP/1.1\r\nContent-Length: 2\r\n\r\n{}";
+        assert_eq!(
+            body_of(elsewhere),
+            Err((404, "there is nothing at /eval".to_string()))
+        );
+    }
+}
@@ -0,0 +1,337 @@
+//! Snippets of brainfuck for the tasks most programs are made of, like
+//! copying a cell or printing it in decimal, to build programs out of
+//! pieces known to work. They're written as operations of the IR, with
+//! the cells they work on relative to the pointer, which they always
+//! leave where they found it.
+
+use bfrs_common::parser;
+use bfrs_patterns::ir::{Ir, Op};
+use std::error::Error;
+use structopt::StructOpt;
+
+/// Prints an 8 bit cell in decimal, leaving it as it was, from a loop
+/// taking the digits off it and then printing them back to front. It
+/// uses the 9 cells to its right, which have to be empty, and are left so.
+const PRINT_DECIMAL: &str = ">[-]>[-]+>[-]+<[>[-<-<<[->+>+<<]>[-<+>]>>]++++++++++>[-]+>[-]>[-]>[-]\
+                             <<<<<[->-[>+>>]>[[-<+>]+>+>>]<<<<<]>>-[-<<+>>]<[-]++++++++[-<++++++>]\
+                             >>[-<<+>>]<<]<[.[-]<]<";
+
+#[derive(Debug, StructOpt)]
+pub enum Opt {
+    /// list the snippets, with what they do
+    List,
+    /// print a snippet, with the cells and amounts given
+    Emit(Emit),
+}
+
+#[derive(Debug, StructOpt)]
+pub struct Emit {
+    /// the name of the snippet, as `bf stdlib list` lists them
+    name: String,
+
+    /// the amount `add-const` and `set-const` add to the cell
+    #[structopt(long, allow_hyphen_values = true)]
+    n: Option<isize>,
+
+    /// the cell the snippet works on, relative to the pointer
+    #[structopt(long, default_value = "0", allow_hyphen_values = true)]
+    at: isize,
+
+    /// the cell `move-cell` and `copy-cell` add the one at `--at` to
+    #[structopt(long, allow_hyphen_values = true)]
+    to: Option<isize>,
+
+    /// the empty cell `copy-cell` moves the one at `--at` through
+    #[structopt(long, allow_hyphen_values = true)]
+    temp: Option<isize>,
+
+    /// the code `if-nonzero` runs, which has to leave the pointer where it found it
+    #[structopt(long, default_value = "")]
+    body: String,
+}
+
+/// A snippet, made of operations by `build` from the options.
+struct Snippet {
+    name: &'static str,
+    about: &'static str,
+    build: fn(&Emit, &mut Code) -> Result<(), String>,
+}
+
+const SNIPPETS: &[Snippet] = &[
+    Snippet {
+        name: "add-const",
+        about: "adds --n to the cell --at",
+        build: |emit, code| {
+            code.add(emit.at, required(emit.n, "add-const", "n")?);
+            Ok(())
+        },
+    },
+    Snippet {
+        name: "set-const",
+        about: "sets the cell --at to --n",
+        build: |emit, code| {
+            code.go(emit.at);
+            code.push(Op::SetZero);
+            code.add(emit.at, required(emit.n, "set-const", "n")?);
+            Ok(())
+        },
+    },
+    Snippet {
+        name: "clear",
+        about: "clears the cell --at",
+        build: |emit, code| {
+            code.go(emit.at);
+            code.push(Op::SetZero);
+            Ok(())
+        },
+    },
+    Snippet {
+        name: "move-cell",
+        about: "adds the cell --at to the cell --to, clearing it",
+        build: |emit, code| {
+            let to = required(emit.to, "move-cell", "to")?;
+            distinct(&[("at", emit.at), ("to", to)])?;
+            code.go(emit.at);
+            code.push(Op::BeginLoop);
+            code.add(emit.at, -1);
+            code.add(to, 1);
+            code.go(emit.at);
+            code.push(Op::EndLoop);
+            Ok(())
+        },
+    },
+    Snippet {
+        name: "copy-cell",
+        about: "adds the cell --at to the cell --to, through the empty cell --temp",
+        build: |emit, code| {
+            let to = required(emit.to, "copy-cell", "to")?;
+            let temp = required(emit.temp, "copy-cell", "temp")?;
+            distinct(&[("at", emit.at), ("to", to), ("temp", temp)])?;
+            for &(from, into) in [(emit.at, &[to, temp][..]), (temp, &[emit.at][..])].iter() {
+                code.go(from);
+                code.push(Op::BeginLoop);
+                code.add(from, -1);
+                for &cell in into {
+                    code.add(cell, 1);
+                }
+                code.go(from);
+                code.push(Op::EndLoop);
+            }
+            Ok(())
+        },
+    },
+    Snippet {
+        name: "if-nonzero",
+        about: "runs --body once if the cell --at isn't zero, clearing it",
+        build: |emit, code| {
+            let body = parser::parse_str(&emit.body).map_err(|e| format!("--body: {}", e))?;
+            code.go(emit.at);
+            code.push(Op::BeginLoop);
+            code.go(0);
+            for op in Ir::compile(&body).ops {
+                code.push(op);
+            }
+            code.go(emit.at);
+            code.push(Op::SetZero);
+            code.push(Op::EndLoop);
+            Ok(())
+        },
+    },
+    Snippet {
+        name: "print-decimal",
+        about: "prints the cell --at in decimal, with the 9 empty cells to its right",
+        build: |emit, code| {
+            let instructions = parser::parse_str(PRINT_DECIMAL).expect("the snippet is valid");
+            code.go(emit.at);
+            for op in Ir::compile(&instructions).ops {
+                code.push(op);
+            }
+            Ok(())
+        },
+    },
+];
+
+/// Operations written along with the cell the pointer is at,
+/// relative to the one it started at.
+#[derive(Default)]
+struct Code {
+    ops: Vec<Op>,
+    pointer: isize,
+}
+
+impl Code {
+    /// Adds `op`, folding it into the one before if they're both moves.
+    fn push(&mut self, op: Op) {
+        match (self.ops.last_mut(), op) {
+            (Some(Op::Move(before)), Op::Move(amount)) => {
+                *before += amount;
+                if *before == 0 {
+                    self.ops.pop();
+                }
+            }
+            _ => self.ops.push(op),
+        }
+    }
+
+    /// Moves the pointer to `cell`.
+    fn go(&mut self, cell: isize) {
+        if cell != self.pointer {
+            self.push(Op::Move(cell - self.pointer));
+            self.pointer = cell;
+        }
+    }
+
+    fn add(&mut self, cell: isize, amount: isize) {
+        self.go(cell);
+        if amount != 0 {
+            self.push(Op::Add(amount));
+        }
+    }
+}
+
+/// The value of the option `name`, which `snippet` can't do without.
+fn required(value: Option<isize>, snippet: &str, name: &str) -> Result<isize, String> {
+    value.ok_or_else(|| format!("`{}` takes --{}", snippet, name))
+}
+
+/// Checks the `cells` given as options are all different.
+fn distinct(cells: &[(&str, isize)]) -> Result<(), String> {
+    for (i, (name, cell)) in cells.iter().enumerate() {
+        if let Some((other, _)) = cells[..i].iter().find(|(_, other)| other == cell) {
+            return Err(format!(
+                "--{} and --{} have to be different cells",
+                other, name
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// The snippet the options say, which starts and ends at the pointer.
+pub fn emit(options: &Emit) -> Result<Vec<Op>, String> {
+    let snippet = SNIPPETS
+        .iter()
+        .find(|snippet| snippet.name == options.name)
+        .ok_or_else(|| {
+            let names: Vec<_> = SNIPPETS.iter().map(|snippet| snippet.name).collect();
+            format!(
+                "unknown snippet `{}`: expected {}",
+                options.name,
+                names.join(", ")
+            )
+        })?;
+    let mut code = Code::default();
+    (snippet.build)(options, &mut code)?;
+    code.go(0);
+    Ok(code.ops)
+}
+
+pub fn run(opt: Opt) -> Result<(), Box<dyn Error>> {
+    match opt {
+        Opt::List => {
+            let width = SNIPPETS.iter().map(|snippet| snippet.name.len()).max();
+            for snippet in SNIPPETS {
+                println!(
+                    "{:width$}  {}",
+                    snippet.name,
+                    snippet.about,
+                    width = width.unwrap_or(0)
+                );
+            }
+        }
+        Opt::Emit(options) => {
+            let code: String = Ir::expand(&emit(&options)?)
+                .iter()
+                .map(|instruction| instruction.to_string())
+                .collect();
+            println!("{}", code);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bfrs::eval::{evaluate, EvalOptions};
+
+    fn snippet(args: &[&str]) -> Result<String, String> {
+        let options = Emit::from_iter_safe(std::iter::once(&"emit").chain(args)).unwrap();
+        let ops = emit(&options)?;
+        Ok(Ir::expand(&ops).iter().map(|i| i.to_string()).collect())
+    }
+
+    /// The first cells of the tape, and the pointer, after running
+    /// `setup` and then the snippet, along with what it printed.
+    fn run(setup: &str, args: &[&str]) -> (Vec<u8>, usize, String) {
+        let source = format!("{}{}", setup, snippet(args).unwrap());
+        let evaluation = evaluate(source.as_bytes(), b"", &EvalOptions::default()).unwrap();
+        let output = String::from_utf8(evaluation.output).unwrap();
+        (evaluation.tape[..12].to_vec(), evaluation.pointer, output)
+    }
+
+    #[test]
+    fn snippets_come_back_to_the_pointer() {
+        assert_eq!(
+            snippet(&["add-const", "--n", "-3", "--at", "2"]).unwrap(),
+            ">>---<<"
+        );
+        assert_eq!(snippet(&["clear", "--at", "-1"]).unwrap(), "<[-]>");
+        assert_eq!(
+            snippet(&["move-cell", "--at", "1", "--to", "-1"]).unwrap(),
+            ">[-<<+>>]<"
+        );
+    }
+
+    #[test]
+    fn snippets_do_what_they_say() {
+        let cells = |setup, args| {
+            let (tape, pointer, _) = run(setup, args);
+            (tape[..4].to_vec(), pointer)
+        };
+        assert_eq!(
+            cells("+++++", &["set-const", "--n", "2"]),
+            (vec![2, 0, 0, 0], 0)
+        );
+        assert_eq!(
+            cells(">>+++", &["move-cell", "--to", "-2"]),
+            (vec![3, 0, 0, 0], 2)
+        );
+        assert_eq!(
+            cells("+++++", &["copy-cell", "--to", "3", "--temp", "1"]),
+            (vec![5, 0, 0, 5], 0)
+        );
+        let body = ["if-nonzero", "--at", "1", "--body", "+++>+<"];
+        assert_eq!(cells(">++<", &body), (vec![3, 0, 0, 0], 0));
+        assert_eq!(cells("", &body), (vec![0, 0, 0, 0], 0));
+    }
+
+    #[test]
+    fn decimals_are_printed() {
+        for n in [0usize, 7, 42, 123, 255] {
+            let setup = format!(">{}", "+".repeat(n));
+            let (tape, pointer, output) = run(&setup, &["print-decimal", "--at", "0"]);
+            assert_eq!(output, n.to_string());
+            // the cell is left as it was, and the ones to its right empty
+            assert_eq!(tape[1], n as u8);
+            assert!(tape[2..].iter().all(|&cell| cell == 0), "{:?}", tape);
+            assert_eq!(pointer, 1);
+        }
+    }
+
+    #[test]
+    fn wrong_options() {
+        let error = |args: &[&str]| snippet(args).unwrap_err();
+        assert!(error(&["swap"]).starts_with("unknown snippet `swap`: expected add-const, "));
+        assert_eq!(error(&["add-const"]), "`add-const` takes --n");
+        assert_eq!(
+            error(&["copy-cell", "--to", "1"]),
+            "`copy-cell` takes --temp"
+        );
+        assert_eq!(
+            error(&["copy-cell", "--to", "1", "--temp", "0"]),
+            "--at and --temp have to be different cells"
+        );
+        assert!(error(&["if-nonzero", "--body", "[+"]).starts_with("--body: "));
+    }
+}
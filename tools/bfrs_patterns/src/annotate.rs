@@ -0,0 +1,229 @@
+//! What every loop of a program does, for a guided reading of one: the
+//! idiom of a library it is, if it's one, along with what its body says
+//! about it, like the cells it copies its own to or where it moves the
+//! pointer. [`print`] shows it under the loops of the source:
+//! ```text
+//! 1 | ++++++++[->++++<]>[-]
+//!   |         ^ `multiply` idiom: multiplies the cell into 1 other
+//!   |                   ^ `clear` idiom: clears the cell
+//! ```
+
+use crate::automaton::CompiledLibrary;
+use crate::decompile::multiplication;
+use crate::ir::{Ir, Op};
+use crate::library::PatternLibrary;
+use bfrs_common::{BFCommand, Location};
+use std::io::{self, Write};
+
+/// What a loop does.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoopNote {
+    /// the index of its `[`
+    pub start: usize,
+    /// the index of its `]`
+    pub end: usize,
+    /// the name of the idiom of the library it's part of, if it's one
+    pub idiom: Option<String>,
+    /// what telling its body apart says it does
+    pub what: String,
+}
+
+/// What every loop of `instructions` does, in the order they start,
+/// with the idioms of `library`.
+pub fn annotate(instructions: &[BFCommand], library: &PatternLibrary) -> Vec<LoopNote> {
+    let matches = CompiledLibrary::new(library).find_all(instructions);
+    let mut notes = Vec::new();
+    let mut open = Vec::new();
+    for (i, &instruction) in instructions.iter().enumerate() {
+        match instruction {
+            BFCommand::BeginLoop => open.push(i),
+            BFCommand::EndLoop => {
+                let start = open.pop().expect("the loops of a program are balanced");
+                let idiom = matches.iter().find(|found| {
+                    let result = &found.result;
+                    result.start <= start && i < result.start + result.commands.len()
+                });
+                notes.push(LoopNote {
+                    start,
+                    end: i,
+                    idiom: idiom.map(|found| library.entries[found.pattern].name.clone()),
+                    what: describe(&instructions[start + 1..i]),
+                });
+            }
+            _ => (),
+        }
+    }
+    notes.sort_by_key(|note| note.start);
+    notes
+}
+
+/// What a loop with `body` between its brackets does.
+pub fn describe(body: &[BFCommand]) -> String {
+    let ops = Ir::compile(body).ops;
+    let plural = |n: usize, one: &str, many: &str| match n {
+        1 => format!("{} {}", n, one),
+        n => format!("{} {}", n, many),
+    };
+    match ops[..] {
+        [] => return "never ends once it starts, as it does nothing".into(),
+        [Op::Add(amount)] if amount % 2 != 0 => return "clears the cell".into(),
+        _ => (),
+    }
+    if let Some(effects) = multiplication(&ops) {
+        let targets = effects.len();
+        let once = effects.values().all(|&times| times == 1);
+        return match targets {
+            0 => "clears the cell".into(),
+            1 if once => "moves the cell to another one".into(),
+            _ if once => format!("copies the cell to {} others", targets),
+            _ => format!(
+                "multiplies the cell into {}",
+                plural(targets, "other", "others")
+            ),
+        };
+    }
+    if ops.iter().all(|op| matches!(op, Op::Move(_))) {
+        let amount: isize = ops
+            .iter()
+            .map(|op| match op {
+                Op::Move(amount) => *amount,
+                _ => 0,
+            })
+            .sum();
+        let direction = if amount < 0 { "left" } else { "right" };
+        return format!(
+            "scans {} {} at a time, until a zero cell",
+            direction,
+            plural(amount.unsigned_abs(), "cell", "cells")
+        );
+    }
+    if ops.iter().all(|op| matches!(op, Op::Move(_) | Op::Print)) {
+        return "prints cells until a zero one".into();
+    }
+    match net_move(&ops) {
+        Some(0) if ops.last() == Some(&Op::SetZero) => "runs at most once, like an `if`".into(),
+        Some(0) => "leaves the pointer where it was on every iteration".into(),
+        Some(amount) => format!("moves the pointer by {} on every iteration", amount),
+        None => "moves the pointer by an amount that depends on the cells".into(),
+    }
+}
+
+/// How much `ops` move the pointer, if it can be told without running them.
+fn net_move(ops: &[Op]) -> Option<isize> {
+    let mut net = 0;
+    // the moves of every loop still open, which have to cancel out.
+    let mut inner = Vec::new();
+    for op in ops {
+        match op {
+            Op::Move(amount) => match inner.last_mut() {
+                Some(moved) => *moved += amount,
+                None => net += amount,
+            },
+            Op::BeginLoop => inner.push(0),
+            Op::EndLoop => {
+                inner.pop().filter(|&moved| moved == 0)?;
+            }
+            _ => (),
+        }
+    }
+    Some(net)
+}
+
+/// Prints `source` with a note under every loop, pointing at its `[`.
+/// `locations` are the ones of the instructions the notes are about.
+pub fn print(
+    source: &[u8],
+    locations: &[Location],
+    notes: &[LoopNote],
+    out: &mut impl Write,
+) -> io::Result<()> {
+    let lines: Vec<&[u8]> = source.split(|&byte| byte == b'\n').collect();
+    let width = lines.len().to_string().len();
+    let mut notes = notes.iter().peekable();
+    let mut line_start = 0;
+    for (n, line) in lines.iter().enumerate() {
+        let line_end = line_start + line.len();
+        if line_end == source.len() && line.is_empty() {
+            // the newline at the end of the last line.
+            break;
+        }
+        write!(out, "{:>width$} | ", n + 1, width = width)?;
+        out.write_all(line)?;
+        writeln!(out)?;
+        while let Some(note) = notes.next_if(|note| locations[note.start].byte_offset <= line_end) {
+            // tabs before the bracket stay tabs, so the caret lines up with it.
+            let column = locations[note.start].byte_offset - line_start;
+            let padding: String = String::from_utf8_lossy(&line[..column])
+                .chars()
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+            let what = match &note.idiom {
+                Some(idiom) => format!("`{}` idiom: {}", idiom, note.what),
+                None => note.what.clone(),
+            };
+            writeln!(out, "{:>width$} | {}^ {}", "", padding, what, width = width)?;
+        }
+        line_start = line_end + 1;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::idioms;
+    use bfrs_common::parser::parse_str;
+    use bfrs_common::program::Program;
+
+    fn what(body: &str) -> String {
+        describe(&parse_str(body).unwrap())
+    }
+
+    #[test]
+    fn kinds_of_loops() {
+        assert_eq!(what(""), "never ends once it starts, as it does nothing");
+        assert_eq!(what("---"), "clears the cell");
+        assert_eq!(what("->+<"), "moves the cell to another one");
+        assert_eq!(what("->+>+<<"), "copies the cell to 2 others");
+        assert_eq!(what(">++++<-"), "multiplies the cell into 1 other");
+        assert_eq!(
+            what("<<"),
+            "scans left 2 cells at a time, until a zero cell"
+        );
+        assert_eq!(what(".>"), "prints cells until a zero one");
+        assert_eq!(what("->[-]"), "moves the pointer by 1 on every iteration");
+        assert_eq!(what(">+<[-]"), "runs at most once, like an `if`");
+        assert_eq!(
+            what(">,<,"),
+            "leaves the pointer where it was on every iteration"
+        );
+        assert_eq!(
+            what("-[>]"),
+            "moves the pointer by an amount that depends on the cells"
+        );
+    }
+
+    #[test]
+    fn loops_in_idioms() {
+        let notes = annotate(&parse_str("+[-]>[[-]<]").unwrap(), &idioms());
+        let spans: Vec<_> = notes.iter().map(|note| (note.start, note.end)).collect();
+        assert_eq!(spans, [(1, 3), (5, 10), (6, 8)]);
+        assert_eq!(notes[0].idiom.as_deref(), Some("clear"));
+        assert_eq!(notes[0].what, "clears the cell");
+        assert_eq!(notes[1].what, "moves the pointer by -1 on every iteration");
+    }
+
+    #[test]
+    fn printing() {
+        let source = b"++++++++[->++++<]>\n\t[-]\n";
+        let program = Program::parse_located(source.iter().map(|&byte| Ok(byte))).unwrap();
+        let notes = annotate(&program.instructions, &idioms());
+        let mut out = Vec::new();
+        print(source, &program.locations.unwrap(), &notes, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "1 | ++++++++[->++++<]>\n  |         ^ `multiply` idiom: multiplies the cell into 1 other\n\
+             2 | \t[-]\n  | \t^ `clear` idiom: clears the cell\n"
+        );
+    }
+}
@@ -0,0 +1,335 @@
+//! A text encoding of compiled programs, safe to paste in chats and issues.
+//!
+//! The operations of the [`Ir`](crate::ir::Ir) are packed in bytes and
+//! written in base64, between markers and after some headers, along
+//! with a checksum that catches most mangled copies:
+//! ```text
+//! -----BEGIN BRAINFUCK PROGRAM-----
+//! Version: 1
+//! Cells: 30000
+//! Name: a.b
+//!
+//! ABADAQIAEAEBAAEEAQIAAgUCABQF
+//! =0d/AMw==
+//! -----END BRAINFUCK PROGRAM-----
+//! ```
+//! Every operation is a byte with its kind, in the order of [`Op`], and
+//! runs are followed by their amount, zigzag encoded and in LEB128.
+//! Headers other than `Version` are optional, and unknown ones are skipped.
+
+use crate::ir::Op;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use bfrs_common::errors::{Diagnostic, ErrorCode};
+use std::fmt;
+
+const BEGIN: &str = "-----BEGIN BRAINFUCK PROGRAM-----";
+const END: &str = "-----END BRAINFUCK PROGRAM-----";
+const VERSION: u32 = 1;
+/// the base64 characters on every line
+const LINE_WIDTH: usize = 64;
+
+/// A compiled program along with what it needs to be run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Armored {
+    pub ops: Vec<Op>,
+    /// the cells of the tape the program was meant to run on
+    pub cells: Option<usize>,
+    /// the file the program was compiled from
+    pub name: Option<String>,
+}
+
+impl Armored {
+    /// The program as armored text, ending with a newline.
+    pub fn encode(&self) -> String {
+        let payload = pack(&self.ops);
+        let mut out = format!("{}\nVersion: {}\n", BEGIN, VERSION);
+        if let Some(cells) = self.cells {
+            out += &format!("Cells: {}\n", cells);
+        }
+        if let Some(name) = &self.name {
+            // a name can't break out of its header.
+            out += &format!("Name: {}\n", name.replace(['\r', '\n'], " "));
+        }
+        out.push('\n');
+        let encoded = STANDARD.encode(&payload);
+        for line in encoded.as_bytes().chunks(LINE_WIDTH) {
+            out += std::str::from_utf8(line).expect("base64 is ASCII");
+            out.push('\n');
+        }
+        let checksum = crc32fast::hash(&payload).to_be_bytes();
+        out += &format!("={}\n{}\n", STANDARD.encode(checksum), END);
+        out
+    }
+
+    /// Reads the first armored program in `text`, ignoring anything
+    /// around it, like the rest of a message it was pasted in.
+    pub fn decode(text: &str) -> Result<Self, ArmorError> {
+        let mut lines = text
+            .lines()
+            .map(str::trim)
+            .skip_while(|&line| line != BEGIN);
+        lines.next().ok_or(ArmorError::MissingBegin)?;
+        let mut version = None;
+        let mut armored = Self {
+            ops: Vec::new(),
+            cells: None,
+            name: None,
+        };
+        for line in lines.by_ref().take_while(|line| !line.is_empty()) {
+            let (key, value) = line
+                .split_once(':')
+                .ok_or_else(|| ArmorError::BadHeader(line.to_string()))?;
+            let value = value.trim();
+            let bad_header = || ArmorError::BadHeader(line.to_string());
+            match key.trim() {
+                "Version" => version = Some(value.parse().map_err(|_| bad_header())?),
+                "Cells" => armored.cells = Some(value.parse().map_err(|_| bad_header())?),
+                "Name" => armored.name = Some(value.to_string()),
+                _ => (),
+            }
+        }
+        match version {
+            Some(VERSION) => (),
+            Some(version) => return Err(ArmorError::UnsupportedVersion(version)),
+            None => return Err(ArmorError::MissingVersion),
+        }
+        let mut encoded = String::new();
+        let mut checksum = None;
+        let mut ended = false;
+        for line in lines {
+            if line == END {
+                ended = true;
+                break;
+            } else if let Some(line) = line.strip_prefix('=') {
+                checksum = Some(line.to_string());
+            } else {
+                encoded += line;
+            }
+        }
+        if !ended {
+            return Err(ArmorError::MissingEnd);
+        }
+        let payload = STANDARD
+            .decode(encoded)
+            .map_err(|_| ArmorError::BadBase64)?;
+        if let Some(checksum) = checksum {
+            let checksum = STANDARD
+                .decode(checksum)
+                .map_err(|_| ArmorError::BadBase64)?;
+            if checksum != crc32fast::hash(&payload).to_be_bytes() {
+                return Err(ArmorError::ChecksumMismatch);
+            }
+        }
+        armored.ops = unpack(&payload)?;
+        Ok(armored)
+    }
+}
+
+/// The operations in bytes.
+fn pack(ops: &[Op]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for &op in ops {
+        let (kind, amount) = match op {
+            Op::Add(amount) => (0, Some(amount)),
+            Op::Move(amount) => (1, Some(amount)),
+            Op::SetZero => (2, None),
+            Op::BeginLoop => (3, None),
+            Op::EndLoop => (4, None),
+            Op::Print => (5, None),
+            Op::Read => (6, None),
+        };
+        bytes.push(kind);
+        if let Some(amount) = amount {
+            let mut zigzag = ((amount << 1) ^ (amount >> (isize::BITS - 1))) as usize;
+            while zigzag >= 0x80 {
+                bytes.push(zigzag as u8 | 0x80);
+                zigzag >>= 7;
+            }
+            bytes.push(zigzag as u8);
+        }
+    }
+    bytes
+}
+
+/// The operations packed in `bytes`, which have to have balanced loops.
+fn unpack(bytes: &[u8]) -> Result<Vec<Op>, ArmorError> {
+    let mut ops = Vec::new();
+    let mut depth = 0usize;
+    let mut i = 0;
+    while let Some(&kind) = bytes.get(i) {
+        let offset = i;
+        i += 1;
+        let mut amount = || {
+            let mut zigzag = 0usize;
+            for shift in (0..usize::BITS).step_by(7) {
+                let byte = *bytes.get(i).ok_or(ArmorError::BadOp { offset })?;
+                i += 1;
+                zigzag |= ((byte & 0x7f) as usize) << shift;
+                if byte & 0x80 == 0 {
+                    return Ok((zigzag >> 1) as isize ^ -((zigzag & 1) as isize));
+                }
+            }
+            Err(ArmorError::BadOp { offset })
+        };
+        let op = match kind {
+            0 => Op::Add(amount()?),
+            1 => Op::Move(amount()?),
+            2 => Op::SetZero,
+            3 => {
+                depth += 1;
+                Op::BeginLoop
+            }
+            4 => {
+                depth = depth.checked_sub(1).ok_or(ArmorError::Unbalanced)?;
+                Op::EndLoop
+            }
+            5 => Op::Print,
+            6 => Op::Read,
+            _ => return Err(ArmorError::BadOp { offset }),
+        };
+        ops.push(op);
+    }
+    if depth > 0 {
+        return Err(ArmorError::Unbalanced);
+    }
+    Ok(ops)
+}
+
+#[derive(Debug)]
+pub enum ArmorError {
+    MissingBegin,
+    MissingEnd,
+    /// a header that isn't `key: value`, or whose value is wrong
+    BadHeader(String),
+    MissingVersion,
+    UnsupportedVersion(u32),
+    BadBase64,
+    ChecksumMismatch,
+    /// an operation that can't be read, at this offset of the decoded bytes
+    BadOp {
+        offset: usize,
+    },
+    Unbalanced,
+}
+
+impl fmt::Display for ArmorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::MissingBegin => write!(f, "Expected a line with `{}`", BEGIN),
+            Self::MissingEnd => write!(f, "Expected a line with `{}`", END),
+            Self::BadHeader(header) => write!(f, "Bad header {:?}", header),
+            Self::MissingVersion => write!(f, "Expected a `Version` header"),
+            Self::UnsupportedVersion(version) => write!(
+                f,
+                "Version {} isn't supported, only version {} is",
+                version, VERSION
+            ),
+            Self::BadBase64 => write!(f, "The program isn't valid base64"),
+            Self::ChecksumMismatch => write!(
+                f,
+                "The checksum doesn't match, the program was changed after it was encoded"
+            ),
+            Self::BadOp { offset } => write!(f, "Bad operation at byte {}", offset),
+            Self::Unbalanced => write!(f, "The loops of the program aren't balanced"),
+        }
+    }
+}
+
+impl std::error::Error for ArmorError {}
+
+impl ErrorCode for ArmorError {
+    fn code(&self) -> &'static str {
+        match self {
+            Self::MissingBegin => "BF0301",
+            Self::MissingEnd => "BF0302",
+            Self::BadHeader(_) => "BF0303",
+            Self::MissingVersion => "BF0304",
+            Self::UnsupportedVersion(_) => "BF0305",
+            Self::BadBase64 => "BF0306",
+            Self::ChecksumMismatch => "BF0307",
+            Self::BadOp { .. } => "BF0308",
+            Self::Unbalanced => "BF0309",
+        }
+    }
+}
+
+impl ArmorError {
+    pub fn diagnostic(&self) -> Diagnostic {
+        Diagnostic::new(self.to_string()).with_code(self.code())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn armored(ops: Vec<Op>) -> Armored {
+        Armored {
+            ops,
+            cells: Some(30000),
+            name: Some("a.b".to_string()),
+        }
+    }
+
+    #[test]
+    fn programs_round_trip() {
+        let program = armored(vec![
+            Op::Add(3),
+            Op::BeginLoop,
+            Op::Move(-1),
+            Op::Add(isize::MIN),
+            Op::Move(isize::MAX),
+            Op::EndLoop,
+            Op::SetZero,
+            Op::Print,
+            Op::Read,
+        ]);
+        let text = program.encode();
+        assert!(text.starts_with(&format!(
+            "{}\nVersion: 1\nCells: 30000\nName: a.b\n\n",
+            BEGIN
+        )));
+        assert!(text.ends_with(&format!("{}\n", END)));
+        // it's found in the middle of a message
+        let message = format!("here it is:\n\n  {}\nthanks", text.replace('\n', "\n  "));
+        assert_eq!(Armored::decode(&message).unwrap(), program);
+    }
+
+    #[test]
+    fn amounts_are_zigzag_encoded() {
+        assert_eq!(pack(&[Op::Add(1), Op::Add(-1)]), [0, 2, 0, 1]);
+        assert_eq!(pack(&[Op::Move(64)]), [1, 0x80, 1]);
+    }
+
+    #[test]
+    fn mangled_programs_are_errors() {
+        let text = armored(vec![Op::Add(1), Op::Print]).encode();
+        let error = |text: &str| Armored::decode(text).unwrap_err().code();
+        assert_eq!(error("nothing here"), "BF0301");
+        assert_eq!(error(text.trim_end().trim_end_matches(END)), "BF0302");
+        assert_eq!(
+            error(&text.replace("Cells: 30000", "Cells: many")),
+            "BF0303"
+        );
+        assert_eq!(error(&text.replace("Version: 1\n", "")), "BF0304");
+        assert_eq!(error(&text.replace("Version: 1", "Version: 2")), "BF0305");
+        let payload = STANDARD.encode(pack(&[Op::Add(1), Op::Print]));
+        assert_eq!(error(&text.replace(&payload, "!!")), "BF0306");
+        let changed = STANDARD.encode(pack(&[Op::Add(2), Op::Print]));
+        assert_eq!(error(&text.replace(&payload, &changed)), "BF0307");
+        // without a checksum, the operations themselves are checked
+        let unchecked = |bytes: &[u8]| {
+            format!(
+                "{}\nVersion: 1\n\n{}\n{}\n",
+                BEGIN,
+                STANDARD.encode(bytes),
+                END
+            )
+        };
+        assert_eq!(error(&unchecked(&[9])), "BF0308");
+        assert_eq!(error(&unchecked(&[0, 0x80])), "BF0308");
+        assert_eq!(error(&unchecked(&[3])), "BF0309");
+        assert_eq!(error(&unchecked(&[4])), "BF0309");
+    }
+}
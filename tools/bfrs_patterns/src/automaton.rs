@@ -0,0 +1,309 @@
+//! Pattern libraries compiled into a single automaton.
+//!
+//! Every pattern has an *anchor*: the literal instructions it starts with,
+//! after any leading bindings. The anchors of the whole library are compiled
+//! into an Aho-Corasick automaton, so a single pass through the instructions
+//! finds every offset where a pattern could possibly start. The full matcher
+//! only runs at those offsets, instead of trying every pattern everywhere.
+
+use crate::library::PatternLibrary;
+use crate::pattern::Pattern;
+use crate::r#match::{LibraryMatch, MatchSM};
+use bfrs_common::BFCommand;
+use std::collections::VecDeque;
+
+/// amount of different instructions
+const SYMBOLS: usize = 8;
+
+#[inline]
+fn symbol(instr: BFCommand) -> usize {
+    match instr {
+        BFCommand::BeginLoop => 0,
+        BFCommand::EndLoop => 1,
+        BFCommand::Print => 2,
+        BFCommand::Read => 3,
+        BFCommand::Increment => 4,
+        BFCommand::Decrement => 5,
+        BFCommand::Right => 6,
+        BFCommand::Left => 7,
+    }
+}
+
+struct Node {
+    next: [usize; SYMBOLS],
+    /// the patterns whose anchor ends at this node, either directly
+    /// or through a suffix.
+    outputs: Vec<usize>,
+}
+
+/// Where a pattern may start, relative to an occurrence of its anchor.
+struct Anchor {
+    len: usize,
+    /// patterns starting with bindings may start before their anchor,
+    /// at the beginning of the movement run that leads to it.
+    leading_moves: bool,
+}
+
+pub struct CompiledLibrary<'l> {
+    library: &'l PatternLibrary,
+    nodes: Vec<Node>,
+    anchors: Vec<Anchor>,
+    /// patterns without an anchor, which may start anywhere.
+    unanchored: Vec<usize>,
+}
+
+impl<'l> CompiledLibrary<'l> {
+    pub fn new(library: &'l PatternLibrary) -> Self {
+        const NONE: usize = usize::MAX;
+        let mut nodes = vec![Node {
+            next: [NONE; SYMBOLS],
+            outputs: Vec::new(),
+        }];
+        let mut anchors = Vec::with_capacity(library.entries.len());
+        let mut unanchored = Vec::new();
+
+        // build the trie of anchors
+        for (pattern, entry) in library.entries.iter().enumerate() {
+            let (anchor, leading_moves) = find_anchor(&entry.scope.patterns);
+            anchors.push(Anchor {
+                len: anchor.len(),
+                leading_moves,
+            });
+            if anchor.is_empty() {
+                unanchored.push(pattern);
+                continue;
+            }
+            let mut node = 0;
+            for instr in anchor {
+                let sym = symbol(instr);
+                if nodes[node].next[sym] == NONE {
+                    nodes.push(Node {
+                        next: [NONE; SYMBOLS],
+                        outputs: Vec::new(),
+                    });
+                    nodes[node].next[sym] = nodes.len() - 1;
+                }
+                node = nodes[node].next[sym];
+            }
+            nodes[node].outputs.push(pattern);
+        }
+
+        // turn the trie into a complete automaton, following
+        // the failure links breadth-first.
+        let mut fail = vec![0; nodes.len()];
+        let mut queue = VecDeque::new();
+        for sym in 0..SYMBOLS {
+            match nodes[0].next[sym] {
+                NONE => nodes[0].next[sym] = 0,
+                child => queue.push_back(child),
+            }
+        }
+        while let Some(node) = queue.pop_front() {
+            let inherited = nodes[fail[node]].outputs.clone();
+            nodes[node].outputs.extend(inherited);
+            for sym in 0..SYMBOLS {
+                let fallback = nodes[fail[node]].next[sym];
+                match nodes[node].next[sym] {
+                    NONE => nodes[node].next[sym] = fallback,
+                    child => {
+                        fail[child] = fallback;
+                        queue.push_back(child);
+                    }
+                }
+            }
+        }
+
+        Self {
+            library,
+            nodes,
+            anchors,
+            unanchored,
+        }
+    }
+
+    pub fn library(&self) -> &'l PatternLibrary {
+        self.library
+    }
+
+    /// Obtain the same matches as [`MatchSM::find_all_in_library`],
+    /// scanning the instructions for anchors only once.
+    pub fn find_all<'a>(&self, instructions: &'a [BFCommand]) -> Vec<LibraryMatch<'a>> {
+        let _span = tracing::debug_span!("match", instructions = instructions.len()).entered();
+        let candidates = self.candidates(instructions);
+        let mut machine = MatchSM::new(instructions);
+        let mut offset = 0;
+        let mut result = Vec::new();
+        while offset < instructions.len() {
+            if let Some(found) = self.match_at(&mut machine, offset, &candidates[offset]) {
+                offset += found.result.skip();
+                result.push(found);
+            } else {
+                offset += 1;
+            }
+        }
+        tracing::debug!(matches = result.len(), "matched the library");
+        result
+    }
+
+    /// Obtain the same matches as [`CompiledLibrary::find_all`], searching
+    /// chunks of `chunk_size` instructions in parallel.
+    pub fn par_find_all<'a>(
+        &self,
+        instructions: &'a [BFCommand],
+        chunk_size: usize,
+    ) -> Vec<LibraryMatch<'a>> {
+        let candidates = self.candidates(instructions);
+        crate::parallel::find_all_chunked(
+            instructions.len(),
+            chunk_size,
+            || MatchSM::new(instructions),
+            |machine, offset| {
+                self.match_at(machine, offset, &candidates[offset])
+                    .map(|found| (found.result.skip(), found))
+            },
+        )
+    }
+
+    fn match_at<'a>(
+        &self,
+        machine: &mut MatchSM<'a>,
+        offset: usize,
+        candidates: &[usize],
+    ) -> Option<LibraryMatch<'a>> {
+        // patterns are tried in library order, like in `find_all_in_library`
+        candidates.iter().find_map(|&pattern| {
+            machine
+                .match_at(offset, &self.library.entries[pattern].scope)
+                .map(|res| LibraryMatch {
+                    pattern,
+                    result: res,
+                })
+        })
+    }
+
+    /// Run the automaton through the instructions, collecting the
+    /// patterns that may start at every offset, in library order.
+    fn candidates(&self, instructions: &[BFCommand]) -> Vec<Vec<usize>> {
+        let mut candidates = vec![Vec::new(); instructions.len()];
+        let mut node = 0;
+        for (i, &instr) in instructions.iter().enumerate() {
+            node = self.nodes[node].next[symbol(instr)];
+            for &pattern in self.nodes[node].outputs.iter() {
+                let anchor = &self.anchors[pattern];
+                let mut start = i + 1 - anchor.len;
+                candidates[start].push(pattern);
+                if anchor.leading_moves {
+                    while start > 0
+                        && matches!(instructions[start - 1], BFCommand::Left | BFCommand::Right)
+                    {
+                        start -= 1;
+                        candidates[start].push(pattern);
+                    }
+                }
+            }
+        }
+        for offset_candidates in candidates.iter_mut() {
+            offset_candidates.extend_from_slice(&self.unanchored);
+            offset_candidates.sort_unstable();
+            offset_candidates.dedup();
+        }
+        candidates
+    }
+}
+
+/// The literal instructions a pattern starts with, after its leading
+/// bindings, and whether there are any leading bindings.
+fn find_anchor(patterns: &[Pattern]) -> (Vec<BFCommand>, bool) {
+    let mut anchor = Vec::new();
+    let mut leading_moves = false;
+    collect_anchor(patterns, &mut anchor, &mut leading_moves);
+    (anchor, leading_moves)
+}
+
+/// Returns `false` once the anchor can't grow any longer.
+fn collect_anchor(
+    patterns: &[Pattern],
+    anchor: &mut Vec<BFCommand>,
+    leading_moves: &mut bool,
+) -> bool {
+    for pat in patterns {
+        match pat {
+            Pattern::Binding { .. } if anchor.is_empty() => *leading_moves = true,
+            Pattern::Instruction(instr) => anchor.push(*instr),
+            Pattern::Loop(_) | Pattern::AnyLoop | Pattern::Zero => {
+                anchor.push(BFCommand::BeginLoop);
+                return false;
+            }
+            Pattern::Repeat { instruction, .. } => {
+                anchor.push(*instruction);
+                return false;
+            }
+            // a run or a class may start with either instruction of its kind,
+            // and wildcards with any.
+            Pattern::Binding { .. }
+            | Pattern::Run { .. }
+            | Pattern::Class(_)
+            | Pattern::AnyInstruction
+            | Pattern::Wildcard { .. }
+            | Pattern::Backreference(_) => return false,
+            Pattern::Capture { body, .. } => {
+                if !collect_anchor(body, anchor, leading_moves) {
+                    return false;
+                }
+            }
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::parse_library;
+    use crate::pattern::parse_pattern;
+    use bfrs_common::parser::parse_str;
+
+    /// The pattern, start and end of every match.
+    fn spots(found: &[LibraryMatch]) -> Vec<(usize, usize, usize)> {
+        found
+            .iter()
+            .map(|found| {
+                let start = found.result.start;
+                (found.pattern, start, start + found.result.commands.len())
+            })
+            .collect()
+    }
+
+    fn anchor(pattern: &str) -> (String, bool) {
+        let scope = parse_pattern(pattern).unwrap();
+        let (anchor, leading_moves) = find_anchor(&scope.patterns);
+        let anchor = anchor.into_iter().map(|i| i as u8 as char).collect();
+        (anchor, leading_moves)
+    }
+
+    #[test]
+    fn anchors_are_the_leading_instructions() {
+        assert_eq!(anchor("+.-"), ("+.-".to_string(), false));
+        assert_eq!(anchor("x[-y+x]"), ("[".to_string(), true));
+        assert_eq!(anchor("(a: ,.)_"), (",.".to_string(), false));
+        assert_eq!(anchor("+-*n."), ("+-".to_string(), false));
+        assert_eq!(anchor("_*."), (String::new(), false));
+    }
+
+    #[test]
+    fn same_matches_as_trying_every_pattern() {
+        // the anchor of `inc` ends the one of `right`, `move` may
+        // start before its anchor and `any` anywhere.
+        let library =
+            parse_library("right = >>+\ninc = >+\nprint = ..\nmove = x[-y+x]\nany = _{2}").unwrap();
+        let instructions = parse_str("..>>[-<+>]>>+>+-.,").unwrap();
+        let expected = MatchSM::find_all_in_library(&instructions, &library);
+        let compiled = CompiledLibrary::new(&library);
+        let found = compiled.find_all(&instructions);
+        assert_eq!(spots(&found), spots(&expected));
+        assert_eq!(
+            spots(&found),
+            [(2, 0, 2), (3, 2, 10), (0, 10, 13), (1, 13, 15), (4, 15, 17)]
+        );
+    }
+}
@@ -0,0 +1,209 @@
+//! Canonical programs, so two variants of the same program can be told
+//! apart from two different ones by comparing or hashing them, like to
+//! remove the duplicates of a corpus.
+//!
+//! Between loops and I/O, a program only adds to some cells, clears some
+//! and moves, which is spelled in a single way: every cell it changes from
+//! left to right, cleared with `[-]` and added to with `+` or `-`, and then
+//! the net move. Whatever cancels out goes away, and so do clear loops on
+//! cells known to be zero and loops that can't run, at the start of the
+//! program or right after another loop.
+
+use crate::ir::{Ir, Op};
+use bfrs_common::BFCommand;
+use std::collections::BTreeMap;
+
+/// The canonical form of a program, which does the same.
+pub fn canonicalize(instructions: &[BFCommand]) -> Vec<BFCommand> {
+    let ops = Ir::compile(instructions).ops;
+    let mut canonical = Vec::with_capacity(ops.len());
+    let mut block = Block::default();
+    let mut known = Known::Everything;
+    let mut i = 0;
+    while i < ops.len() {
+        match ops[i] {
+            Op::Add(amount) => block.effect(block.offset).add(amount),
+            Op::Move(amount) => block.offset += amount,
+            Op::SetZero => block.effect(block.offset).clear(),
+            Op::BeginLoop if block.is_zero(block.offset, known) => {
+                // it never runs, and leaves everything as it was.
+                i = end_of_loop(&ops, i);
+            }
+            op => {
+                block.emit(known, &mut canonical);
+                canonical.push(op);
+                block = Block::default();
+                known = match op {
+                    Op::EndLoop => Known::Current,
+                    _ => Known::Nothing,
+                };
+            }
+        }
+        i += 1;
+    }
+    block.emit(known, &mut canonical);
+    Ir::expand(&canonical)
+}
+
+/// The cells known to be zero at the start of a block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Known {
+    /// every cell, at the start of the program
+    Everything,
+    /// the current one, after a loop
+    Current,
+    Nothing,
+}
+
+impl Known {
+    fn is_zero(self, offset: isize) -> bool {
+        match self {
+            Self::Everything => true,
+            Self::Current => offset == 0,
+            Self::Nothing => false,
+        }
+    }
+}
+
+/// What a run of operations without loops or I/O does to a cell.
+#[derive(Debug, Clone, Copy, Default)]
+struct Effect {
+    /// whether it's cleared before adding to it
+    clear: bool,
+    add: isize,
+}
+
+impl Effect {
+    fn add(&mut self, amount: isize) {
+        self.add += amount;
+    }
+
+    fn clear(&mut self) {
+        // whatever was added before is lost.
+        *self = Self {
+            clear: true,
+            add: 0,
+        };
+    }
+}
+
+/// A run of operations without loops or I/O, by what it does to
+/// every cell, relative to where the pointer was at its start.
+#[derive(Debug, Default)]
+struct Block {
+    effects: BTreeMap<isize, Effect>,
+    /// where the pointer is
+    offset: isize,
+}
+
+impl Block {
+    fn effect(&mut self, offset: isize) -> &mut Effect {
+        self.effects.entry(offset).or_default()
+    }
+
+    /// Whether the cell at `offset` is known to be zero after the block.
+    fn is_zero(&self, offset: isize, known: Known) -> bool {
+        match self.effects.get(&offset) {
+            Some(effect) => (effect.clear || known.is_zero(offset)) && effect.add == 0,
+            None => known.is_zero(offset),
+        }
+    }
+
+    /// Writes the operations doing what the block does, in canonical order.
+    fn emit(&self, known: Known, ops: &mut Vec<Op>) {
+        let mut at = 0;
+        for (&offset, effect) in self.effects.iter() {
+            // clearing a cell that's zero already does nothing.
+            let clear = effect.clear && !known.is_zero(offset);
+            if !clear && effect.add == 0 {
+                continue;
+            }
+            if offset != at {
+                ops.push(Op::Move(offset - at));
+                at = offset;
+            }
+            if clear {
+                ops.push(Op::SetZero);
+            }
+            if effect.add != 0 {
+                ops.push(Op::Add(effect.add));
+            }
+        }
+        if self.offset != at {
+            ops.push(Op::Move(self.offset - at));
+        }
+    }
+}
+
+/// The index of the `]` closing the loop opened at `start`.
+pub(crate) fn end_of_loop(ops: &[Op], start: usize) -> usize {
+    let mut depth = 0;
+    for (i, op) in ops.iter().enumerate().skip(start) {
+        match op {
+            Op::BeginLoop => depth += 1,
+            Op::EndLoop => {
+                depth -= 1;
+                if depth == 0 {
+                    return i;
+                }
+            }
+            _ => (),
+        }
+    }
+    unreachable!("the loops of a program are balanced")
+}
+
+/// A hash of a canonical program, to tell programs apart by it. It's
+/// FNV-1a, which unlike the hashers of the standard library, is the same
+/// across versions and platforms, so it can be stored.
+pub fn hash(instructions: &[BFCommand]) -> u64 {
+    let mut hash = 0xcbf2_9ce4_8422_2325_u64;
+    for instruction in instructions {
+        hash ^= *instruction as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bfrs_common::parser;
+
+    fn canonical(src: &str) -> String {
+        let canonical = canonicalize(&parser::parse_str(src).unwrap());
+        canonical.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn variants_have_the_same_canonical_form() {
+        let variants = [",>+<+>.", ",+>+.", ",>+<<>+>.", ",+-+>+-+<>."];
+        for src in variants.iter() {
+            assert_eq!(canonical(src), ",+>+.", "{}", src);
+        }
+        // cells are changed from left to right, and then the pointer moves
+        assert_eq!(canonical(",>>-<<<+>."), ",<+>>>-<<.");
+    }
+
+    #[test]
+    fn what_does_nothing_goes_away() {
+        // every cell starts at zero
+        assert_eq!(canonical("[-]+[>]"), "+[>]");
+        assert_eq!(canonical("[.]+."), "+.");
+        // the cell a loop ends on is zero
+        assert_eq!(canonical(",[-][+][>]."), ",[-].");
+        assert_eq!(canonical(",[-]>[-]<."), ",[-]>[-]<.");
+        // clearing a cell drops what was added to it
+        assert_eq!(canonical(",+++[-]."), ",[-].");
+        assert_eq!(canonical("+-<>"), "");
+    }
+
+    #[test]
+    fn hashes_tell_programs_apart() {
+        let canonical = |src| canonicalize(&parser::parse_str(src).unwrap());
+        assert_eq!(hash(&canonical(",>+<+>.")), hash(&canonical(",+>+.")));
+        assert_ne!(hash(&canonical(",+>+.")), hash(&canonical(",+>+,")));
+        // it's the FNV-1a offset basis, stable across versions
+        assert_eq!(hash(&[]), 0xcbf2_9ce4_8422_2325);
+    }
+}
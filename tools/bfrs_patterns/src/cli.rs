@@ -0,0 +1,198 @@
+//! The command line interfaces of the tools, shared by their own binaries
+//! and by the `bf` multitool, along with the options they all take.
+
+pub mod search;
+
+use crate::{errors, theme};
+use bfrs_common::errors::ErrorFormat;
+use bfrs_common::theme::Theme;
+use bfrs_common::PositionConfig;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use structopt::clap;
+use structopt::StructOpt;
+use tracing::Level;
+use tracing_subscriber::fmt::format::FmtSpan;
+
+/// The exit code of a search that didn't find anything.
+pub const NOT_FOUND: i32 = 1;
+/// The exit code of errors, like grep's.
+pub const FAILED: i32 = 2;
+
+/// Exits because of the arguments, with the code of errors, so a usage
+/// error can't be taken for a search without matches. Asking for the
+/// help or the version still succeeds.
+pub fn usage_error(e: clap::Error) -> ! {
+    if !e.use_stderr() {
+        e.exit()
+    }
+    eprintln!("{}", e.message);
+    std::process::exit(FAILED)
+}
+
+// The options every tool takes, for reporting errors and coloring the output.
+// Not a doc comment, which would become the about of the tools flattening it.
+#[derive(Debug, StructOpt)]
+pub struct Common {
+    /// how to report errors
+    #[structopt(
+        long,
+        global = true,
+        default_value = "human",
+        possible_values = ErrorFormat::VARIANTS
+    )]
+    pub error_format: ErrorFormat,
+
+    /// columns a tab takes in the positions of the program
+    #[structopt(long, global = true, default_value = "1")]
+    pub tab_width: usize,
+
+    /// count lines and columns from 0 in the positions of the program
+    #[structopt(long, global = true)]
+    pub zero_based: bool,
+
+    /// when to color the output
+    #[structopt(
+        long,
+        global = true,
+        default_value = "auto",
+        possible_values = Color::VARIANTS
+    )]
+    pub color: Color,
+
+    /// the colors to use, `default`, `solarized`, `mono` or a TOML file
+    #[structopt(long, global = true, default_value = "default")]
+    pub theme: String,
+
+    /// log what the tool does on stderr, and how long every step of it takes,
+    /// in more detail every time it's given. It has no `-v`, since every tool
+    /// shares it and that's `--invert` in searches.
+    #[structopt(long, global = true, parse(from_occurrences))]
+    pub verbose: u8,
+
+    /// how to write the logs of --verbose
+    #[structopt(
+        long,
+        global = true,
+        default_value = "human",
+        possible_values = ErrorFormat::VARIANTS
+    )]
+    pub log_format: ErrorFormat,
+}
+
+impl Common {
+    pub fn positions(&self) -> PositionConfig {
+        PositionConfig {
+            tab_width: self.tab_width.max(1),
+            zero_based: self.zero_based,
+        }
+    }
+
+    /// Whether to color output going to a stream, which may be a terminal.
+    pub fn colored(&self, terminal: bool) -> bool {
+        match self.color {
+            Color::Auto => terminal,
+            Color::Always => true,
+            Color::Never => false,
+        }
+    }
+
+    /// The colors of output going to a stream, which may be a terminal,
+    /// unless it isn't colored.
+    pub fn theme(&self, terminal: bool) -> Result<Option<Theme>, Box<dyn Error>> {
+        if !self.colored(terminal) {
+            return Ok(None);
+        }
+        let mut theme = theme::load(&self.theme)?;
+        theme.true_color = theme::true_color();
+        Ok(Some(theme))
+    }
+
+    /// Starts logging on stderr, as much as `--verbose` asks for,
+    /// which without it is only the warnings.
+    pub fn init_logging(&self) {
+        let level = match self.verbose {
+            0 => Level::WARN,
+            1 => Level::INFO,
+            2 => Level::DEBUG,
+            _ => Level::TRACE,
+        };
+        let logger = tracing_subscriber::fmt()
+            .with_max_level(level)
+            .with_span_events(FmtSpan::CLOSE)
+            .with_target(false)
+            .with_writer(io::stderr);
+        // only a logger set before, like by tests, makes it fail.
+        let _ = match self.log_format {
+            ErrorFormat::Human => logger.try_init(),
+            ErrorFormat::Json => logger.json().try_init(),
+        };
+    }
+
+    /// Reports an error on stderr, the way the options say.
+    pub fn report(&self, e: &(dyn Error + 'static)) {
+        errors::report_with(
+            e,
+            &errors::diagnose(e),
+            self.error_format,
+            &self.positions(),
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Color {
+    /// only when writing to a terminal
+    Auto,
+    Always,
+    Never,
+}
+
+impl Color {
+    pub const VARIANTS: &'static [&'static str] = &["auto", "always", "never"];
+}
+
+impl FromStr for Color {
+    type Err = UnknownColor;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "auto" => Self::Auto,
+            "always" => Self::Always,
+            "never" => Self::Never,
+            _ => return Err(UnknownColor(s.to_string())),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct UnknownColor(String);
+
+impl fmt::Display for UnknownColor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown color setting: {:?}", self.0)
+    }
+}
+
+impl Error for UnknownColor {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verbosity_is_counted() {
+        let common = |args: &[&str]| {
+            Common::from_iter_safe(std::iter::once(&"bfrs_patterns").chain(args)).unwrap()
+        };
+        assert_eq!(common(&[]).verbose, 0);
+        assert_eq!(common(&["--verbose", "--verbose"]).verbose, 2);
+        assert_eq!(
+            common(&["--log-format", "json"]).log_format,
+            ErrorFormat::Json
+        );
+        // since `-v` is `--invert` in searches
+        assert!(Common::from_iter_safe(["bfrs_patterns", "-v"].iter()).is_err());
+    }
+}
@@ -0,0 +1,1249 @@
+//! Searching a program for patterns, which is what `bfrs_patterns` and
+//! `bf match` do, along with running spec files.
+
+use super::Common;
+use crate::automaton::CompiledLibrary;
+use crate::context;
+use crate::fuzzy;
+use crate::ir::Ir;
+use crate::library::{NamedPattern, PatternLibrary};
+use crate::output;
+use crate::pattern::{Bound, PatternScope};
+use crate::r#match::{LibraryMatch, MatchResult, MatchSM};
+use crate::region::{Mask, Region};
+use crate::tape::PointerTrace;
+use bfrs_common::dialect::Dialect;
+use bfrs_common::errors::{Diagnostic, Diagnostics, Severity};
+use bfrs_common::{parser, BFCommand, Location, Position};
+use bfrs_input::mmap::InputBytes;
+use serde_json::json;
+use std::error::Error;
+use std::io::IsTerminal;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use structopt::clap::{self, AppSettings};
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+#[structopt(settings = &[AppSettings::SubcommandsNegateReqs, AppSettings::ArgsNegateSubcommands])]
+pub struct Opt {
+    /// a pattern to search for, optionally labeled as `name=pattern`, or
+    /// `@file` to read it from a file, or `-` to read it from stdin.
+    /// Can be repeated to search for several patterns at once
+    #[structopt(
+        short,
+        long,
+        number_of_values = 1,
+        required_unless_one = &["library", "idioms", "suggest"]
+    )]
+    pattern: Vec<String>,
+
+    /// a file of named patterns (`name = pattern`, one per line) to search for
+    #[structopt(short, long, parse(from_os_str), conflicts_with = "pattern")]
+    library: Option<PathBuf>,
+
+    /// search for the built-in library of common idioms (clear, copy,
+    /// move, multiply, print string and scan loops)
+    #[structopt(short, long, conflicts_with_all = &["pattern", "library"])]
+    idioms: bool,
+
+    /// suggest replacements for code that could be simpler,
+    /// or that an optimizer would replace
+    #[structopt(
+        short,
+        long,
+        conflicts_with_all = &["pattern", "library", "idioms", "invert", "count", "stats", "explain"]
+    )]
+    suggest: bool,
+
+    /// how to print the matches
+    #[structopt(
+        short,
+        long,
+        default_value = "text",
+        possible_values = output::Format::VARIANTS
+    )]
+    format: output::Format,
+
+    /// search with this many threads, splitting the program in chunks
+    #[structopt(short, long)]
+    jobs: Option<usize>,
+
+    /// match runs of `+-<>` by their effect, so operations on different
+    /// cells may be in any order. Always searches with a single thread
+    #[structopt(short = "m", long)]
+    commutative: bool,
+
+    /// only try to match where a run of `+-`/`<>` or any other instruction
+    /// starts, which is faster for patterns made of runs (`@add`, `@move`,
+    /// `@zero`). Always searches with a single thread
+    #[structopt(long, conflicts_with = "commutative")]
+    ir: bool,
+
+    /// only report matches of at least this many instructions
+    #[structopt(long)]
+    min_len: Option<usize>,
+
+    /// only report matches of at most this many instructions, which also
+    /// bounds how far wildcards like `_*` look
+    #[structopt(long)]
+    max_len: Option<usize>,
+
+    /// only report matches all in this region: `loops` for the bodies of
+    /// the loops, `lines:A-B` or `bytes:A-B` for a part of the source, or
+    /// `pattern:P` for the matches of another pattern. Only that part of
+    /// the program is searched. Can be repeated, keeping the matches in
+    /// all of them
+    #[structopt(
+        long,
+        number_of_values = 1,
+        conflicts_with_all = &["suggest", "replace", "invert", "explain"]
+    )]
+    within: Vec<Region>,
+
+    /// only report matches not all in this region, which takes the same
+    /// ones as `--within`, so `--outside loops` keeps to the top level.
+    /// Can be repeated
+    #[structopt(
+        long,
+        number_of_values = 1,
+        conflicts_with_all = &["suggest", "replace", "invert", "explain"]
+    )]
+    outside: Vec<Region>,
+
+    /// replace every match of the pattern with this template, printing the
+    /// program it makes, with its comments kept between the matches
+    #[structopt(
+        long,
+        conflicts_with_all = &["library", "idioms", "invert", "count", "stats", "explain", "correlate"]
+    )]
+    replace: Option<String>,
+
+    /// write the program `--replace` makes to the file it was read from
+    #[structopt(long, requires = "replace")]
+    in_place: bool,
+
+    /// write the program `--replace` makes to this file
+    #[structopt(
+        short,
+        long,
+        parse(from_os_str),
+        requires = "replace",
+        conflicts_with = "in-place"
+    )]
+    output: Option<PathBuf>,
+
+    /// report the parts of the program that no pattern matches, instead of the matches
+    #[structopt(short = "v", long)]
+    invert: bool,
+
+    /// only print how many times every pattern matched
+    #[structopt(short = "c", long, conflicts_with_all = &["invert", "stats"])]
+    count: bool,
+
+    /// only print how many times every pattern matched, and
+    /// how much of the program the matches cover
+    #[structopt(long, conflicts_with = "invert")]
+    stats: bool,
+
+    /// report the clusters of matches of a pattern with a binding on the
+    /// same cell instead of the matches, to find the cells the program keeps
+    /// the same things in. The cells are counted from `--start-cell`, or 0
+    #[structopt(long, conflicts_with_all = &["invert", "count", "stats", "explain"])]
+    correlate: bool,
+
+    /// the least matches a cluster reported by `--correlate` has, 2 by default
+    // Not a default value, which clap takes as given, requiring
+    // --correlate every time.
+    #[structopt(long, requires = "correlate")]
+    min_cluster: Option<usize>,
+
+    /// rank the patterns by how often they match across all the files, in a
+    /// table, instead of printing the matches. With `--format json`, how
+    /// often every one matched in every file is printed too
+    #[structopt(
+        long,
+        conflicts_with_all = &["invert", "count", "stats", "explain", "correlate", "replace"]
+    )]
+    rank: bool,
+
+    /// also report matches this many edits away from the patterns, counting
+    /// the instructions to insert, delete or substitute, the closest first.
+    /// Patterns are only matched by the instructions every part of them can
+    /// take, so bindings, counts and backreferences don't have to agree
+    #[structopt(
+        long,
+        conflicts_with_all = &[
+            "suggest", "replace", "invert", "count", "stats", "correlate", "rank", "explain",
+            "commutative", "ir", "quiet", "files-with-matches", "context", "start-cell"
+        ]
+    )]
+    fuzzy: Option<usize>,
+
+    /// try every pattern at this instruction offset, explaining why they don't match
+    #[structopt(long, conflicts_with_all = &["invert", "count", "stats"])]
+    explain: Option<usize>,
+
+    /// show this many lines of source around every match, highlighting it.
+    /// Only used by the text format
+    #[structopt(short = "C", long)]
+    context: Option<usize>,
+
+    /// the cell the pointer starts at, to report the cell of every binding
+    /// on the tape, as long as the pointer can be followed up to the match
+    #[structopt(long, allow_hyphen_values = true)]
+    start_cell: Option<isize>,
+
+    /// the amount of instructions in every chunk when searching with several threads
+    #[structopt(long, default_value = "65536")]
+    chunk_size: usize,
+
+    /// print nothing, only exiting with 0 if anything matched, 1 if
+    /// nothing did and 2 on errors, stopping at the first match
+    #[structopt(
+        short,
+        long,
+        conflicts_with_all = &["count", "stats", "explain", "context"]
+    )]
+    quiet: bool,
+
+    /// only print the path of every file with a match, one per line
+    #[structopt(long, conflicts_with_all = &["count", "stats", "explain", "context"])]
+    files_with_matches: bool,
+
+    /// skip the files matching this glob when searching directories and globs.
+    /// Can be repeated
+    #[structopt(long, number_of_values = 1)]
+    ignore: Vec<String>,
+
+    /// the files to search in, or directories and globs (like `corpus/**/*.b`)
+    /// to search every program in, recursively. Required unless running a subcommand
+    #[structopt(parse(from_os_str))]
+    files: Vec<PathBuf>,
+
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum Command {
+    /// run the spec files in the given files or directories, checking
+    /// that their patterns match what they should
+    Test {
+        /// spec files, or directories to run every `.spec` file of
+        #[structopt(parse(from_os_str), required = true)]
+        paths: Vec<PathBuf>,
+    },
+}
+
+/// Searches the programs the options say for patterns, returning whether
+/// anything was found, so the exit code can tell it like grep's does.
+pub fn run(opt: Opt, common: &Common) -> Result<bool, Box<dyn Error>> {
+    if let Some(Command::Test { paths }) = &opt.command {
+        return test(paths).map(|()| true);
+    }
+    if opt.files.is_empty() {
+        super::usage_error(clap::Error::with_description(
+            "the file to search in is required",
+            clap::ErrorKind::MissingRequiredArgument,
+        ))
+    }
+    let (mut library, labeled) = library(&opt)?;
+    for entry in library.entries.iter_mut() {
+        entry.scope.length = Bound {
+            min: opt.min_len.map(|min| min as isize),
+            max: opt.max_len.map(|max| max as isize),
+        };
+    }
+    if let Some(template) = &opt.replace {
+        return replace(&opt, common, library, template);
+    }
+    if opt.quiet || opt.files_with_matches {
+        return list_matching(&opt, common, &library);
+    }
+    if let Some(max_distance) = opt.fuzzy {
+        return search_fuzzy(&opt, common, &library, labeled, max_distance);
+    }
+    match opt.files.as_slice() {
+        [file] if file.is_file() && !opt.rank => search(&opt, common, &library, labeled, file),
+        _ => search_corpus(&opt, common, &library, labeled),
+    }
+}
+
+/// Replaces the matches of the single pattern of `library` in the single
+/// file of the options with `template`, returning whether that changed it.
+fn replace(
+    opt: &Opt,
+    common: &Common,
+    mut library: PatternLibrary,
+    template: &str,
+) -> Result<bool, Box<dyn Error>> {
+    if library.entries.len() != 1 {
+        return Err("--replace takes a single pattern".into());
+    }
+    let file = match opt.files.as_slice() {
+        [file] if file.is_file() => file,
+        _ => return Err("--replace can only be used with a single file".into()),
+    };
+    let scope = library.entries.remove(0).scope;
+    let template = crate::rewrite::parse_template(template, &scope)?;
+    let rule = crate::rewrite::Rule { scope, template };
+
+    let src = std::fs::read(file)?;
+    let (instructions, locations) = load(file, common)?;
+    let rewritten = crate::rewrite::rewrite_source(&src, &instructions, &locations, &rule);
+    let matched = rewritten != src;
+    match (&opt.output, opt.in_place) {
+        (Some(output), _) => std::fs::write(output, &rewritten)?,
+        // an unchanged file isn't written, so its modification time stays.
+        (None, true) if matched => std::fs::write(file, &rewritten)?,
+        (None, true) => (),
+        (None, false) => std::io::Write::write_all(&mut std::io::stdout().lock(), &rewritten)?,
+    }
+    Ok(matched)
+}
+
+/// The patterns to search for, and whether their matches are labeled.
+fn library(opt: &Opt) -> Result<(PatternLibrary, bool), Box<dyn Error>> {
+    // patterns from the command line are searched for as a library of their
+    // own, the only difference being that a single unlabeled pattern's
+    // matches aren't labeled.
+    if opt.idioms {
+        return Ok((crate::library::idioms(), true));
+    } else if let Some(library_path) = &opt.library {
+        return Ok((crate::library::load_library(library_path)?, true));
+    }
+    if opt.pattern.iter().filter(|&arg| arg == "-").count() > 1 {
+        return Err("only one pattern can be read from stdin".into());
+    }
+    let mut library = PatternLibrary::default();
+    let mut labeled = opt.pattern.len() > 1;
+    for arg in opt.pattern.iter() {
+        let (pattern, file) = read_pattern(arg)?;
+        // bounds like `@add(>=2)` have a `=` too, so only
+        // a name before the first one makes it a label.
+        let label = pattern.split_once('=').map(|(name, _)| name.trim());
+        let entry = if label.is_some_and(crate::library::is_identifier) {
+            labeled = true;
+            crate::library::parse_named_pattern(&pattern)
+        } else {
+            crate::pattern::parse_pattern(&pattern).map(|scope| NamedPattern {
+                scope,
+                // a pattern from a file is named after it, rather than all of its lines.
+                name: file.clone().unwrap_or_else(|| pattern.clone()),
+            })
+        };
+        let entry = match (entry, file) {
+            (Ok(entry), _) => entry,
+            (Err(e), Some(file)) => {
+                let diagnostics: Diagnostics = crate::errors::diagnose(&e)
+                    .into_iter()
+                    .map(|diagnostic| diagnostic.in_file(&*file))
+                    .collect();
+                return Err(diagnostics.into());
+            }
+            (Err(e), None) => return Err(e.into()),
+        };
+        library.insert(entry, Location::default())?;
+    }
+    Ok((library, labeled))
+}
+
+/// The pattern a `-p` stands for, along with the file it was read from:
+/// the one after a `@`, or `<stdin>` for `-`. Runs like `@add(>2)` are
+/// patterns themselves, unless there's a file with that name.
+fn read_pattern(arg: &str) -> std::io::Result<(String, Option<String>)> {
+    if arg == "-" {
+        let mut pattern = String::new();
+        std::io::Read::read_to_string(&mut std::io::stdin(), &mut pattern)?;
+        return Ok((pattern, Some(String::from("<stdin>"))));
+    }
+    let path = match arg.strip_prefix('@') {
+        Some(path) => path,
+        None => return Ok((arg.to_string(), None)),
+    };
+    let run = path.split(|ch: char| !ch.is_alphanumeric()).next();
+    if matches!(run, Some("add" | "move" | "zero" | "loop")) && !Path::new(path).is_file() {
+        return Ok((arg.to_string(), None));
+    }
+    let pattern = std::fs::read_to_string(path)
+        .map_err(|e| std::io::Error::new(e.kind(), format!("could not read {}: {}", path, e)))?;
+    Ok((pattern, Some(path.to_string())))
+}
+
+/// The instructions of a file, along with where every one of them is.
+fn load(file: &Path, common: &Common) -> Result<(Vec<BFCommand>, Vec<Location>), Box<dyn Error>> {
+    let src = InputBytes::open(std::fs::File::open(file)?)?;
+    let recovered = parser::parse_recovering_with(src, Dialect::default(), common.positions())?;
+    let diagnostics: Diagnostics = recovered
+        .diagnostics(Severity::Error)
+        .into_iter()
+        .map(|diagnostic| diagnostic.in_file(file.display().to_string()))
+        .collect();
+    Ok(diagnostics
+        .into_result(recovered.instructions)?
+        .into_iter()
+        .unzip())
+}
+
+/// The matches of every pattern, searched for the way the options say,
+/// in the regions they keep to.
+fn find<'a>(
+    opt: &Opt,
+    library: &PatternLibrary,
+    compiled: &CompiledLibrary,
+    instructions: &'a [BFCommand],
+    locations: &[Location],
+) -> Result<Vec<LibraryMatch<'a>>, Box<dyn Error>> {
+    let mask = Mask::new(&opt.within, &opt.outside, instructions, locations);
+    let bounds = mask.bounds(instructions.len());
+    let searched = &instructions[bounds.clone()];
+    let chunk_size = opt.chunk_size.max(1);
+    let mut matches = match opt.jobs {
+        // the anchors of the compiled library are literal, so
+        // they can't be used when the instructions may be reordered.
+        _ if opt.commutative => MatchSM::new(searched)
+            .commutative(true)
+            .search_library(library),
+        _ if opt.ir => Ir::compile(searched).find_all_in_library(searched, library),
+        Some(jobs) if jobs > 1 => rayon::ThreadPoolBuilder::new()
+            .num_threads(jobs)
+            .build()?
+            .install(|| compiled.par_find_all(searched, chunk_size)),
+        _ => compiled.find_all(searched),
+    };
+    for found in matches.iter_mut() {
+        found.result.start += bounds.start;
+    }
+    matches.retain(|found| {
+        let start = found.result.start;
+        mask.keeps(start..start + found.result.commands.len())
+    });
+    Ok(matches)
+}
+
+/// Searches every file for matches close to the patterns, printing
+/// them the closest first, along with how far they are.
+fn search_fuzzy(
+    opt: &Opt,
+    common: &Common,
+    library: &PatternLibrary,
+    labeled: bool,
+    max_distance: usize,
+) -> Result<bool, Box<dyn Error>> {
+    if matches!(opt.format, output::Format::Sarif) {
+        return Err("--fuzzy prints text or json".into());
+    }
+    let single = matches!(opt.files.as_slice(), [file] if file.is_file());
+    let files = crate::corpus::files(&opt.files, &opt.ignore)?;
+    let mut diagnostics = Diagnostics::new();
+    let length = Bound {
+        min: opt.min_len.map(|min| min as isize),
+        max: opt.max_len.map(|max| max as isize),
+    };
+    // every match, with the file it's in and where.
+    let mut found = Vec::new();
+    for file in files.iter() {
+        let (instructions, locations) = match load(file, common) {
+            Ok(loaded) => loaded,
+            Err(e) => {
+                let path = file.display().to_string();
+                let errors = crate::errors::diagnose(&*e);
+                diagnostics.extend(errors.into_iter().map(|found| match found.file {
+                    Some(_) => found,
+                    None => found.in_file(&*path),
+                }));
+                continue;
+            }
+        };
+        let mask = Mask::new(&opt.within, &opt.outside, &instructions, &locations);
+        let bounds = mask.bounds(instructions.len());
+        for mut close in fuzzy::find_all(&instructions[bounds.clone()], library, max_distance) {
+            close.range = close.range.start + bounds.start..close.range.end + bounds.start;
+            if mask.keeps(close.range.clone()) && length.contains(close.range.len() as isize) {
+                let start = locations[close.range.start];
+                let end = locations[close.range.end - 1].after_command();
+                let text = to_text(&instructions[close.range.clone()]);
+                found.push((file, close, start, end, text));
+            }
+        }
+    }
+    // the closest first, across every file.
+    found.sort_by_key(|(_, close, ..)| close.distance);
+    match opt.format {
+        output::Format::Text => {
+            for (file, close, start, end, text) in found.iter() {
+                if !single {
+                    print!("{}: ", file.display());
+                }
+                if labeled {
+                    print!("`{}`: ", library.entries[close.pattern].name);
+                }
+                println!(
+                    "result at {}-{}: `{}` ({} away)",
+                    start.position, end.position, text, close.distance
+                );
+            }
+        }
+        output::Format::Json | output::Format::Sarif => {
+            let report: Vec<_> = found
+                .iter()
+                .map(|(file, close, start, end, text)| {
+                    let mut report = json!({
+                        "start": start.byte_offset,
+                        "end": end.byte_offset,
+                        "start_line": start.position.line,
+                        "start_column": start.position.column,
+                        "end_line": end.position.line,
+                        "end_column": end.position.column,
+                        "text": text,
+                        "distance": close.distance,
+                    });
+                    if labeled {
+                        report["pattern"] = json!(library.entries[close.pattern].name);
+                    }
+                    if !single {
+                        report["file"] = json!(file.display().to_string());
+                    }
+                    report
+                })
+                .collect();
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        }
+    }
+    Ok(diagnostics.into_result(!found.is_empty())?)
+}
+
+/// Searches a single file, printing its matches the way the options say.
+fn search(
+    opt: &Opt,
+    common: &Common,
+    library: &PatternLibrary,
+    labeled: bool,
+    file: &Path,
+) -> Result<bool, Box<dyn Error>> {
+    let (instructions, locations) = load(file, common)?;
+    if opt.suggest {
+        return Ok(print_suggestions(&instructions, &locations, ""));
+    }
+
+    if let Some(offset) = opt.explain {
+        let machine = MatchSM::new(&instructions).commutative(opt.commutative);
+        return explain(machine, &locations, library, offset);
+    }
+
+    let compiled = CompiledLibrary::new(library);
+    let matches = find(opt, library, &compiled, &instructions, &locations)?;
+
+    if opt.count || opt.stats {
+        let stats = output::stats(&matches, library, instructions.len());
+        match opt.format {
+            output::Format::Text => {
+                print_stats(&stats, opt.stats, labeled, "");
+                if opt.stats {
+                    println!(
+                        "total: {} matches, covering {} of {} instructions ({:.2}%)",
+                        stats.matches, stats.covered, stats.instructions, stats.coverage
+                    );
+                }
+            }
+            // the stats are the same for every machine readable format
+            output::Format::Json | output::Format::Sarif => {
+                println!("{}", serde_json::to_string_pretty(&stats)?);
+            }
+        }
+        return Ok(!matches.is_empty());
+    }
+
+    if opt.correlate {
+        let trace = PointerTrace::new(&instructions, opt.start_cell.unwrap_or(0));
+        let clusters =
+            crate::correlate::clusters(&matches, library, &trace, opt.min_cluster.unwrap_or(2));
+        match opt.format {
+            output::Format::Text => print_clusters(&clusters, &locations),
+            output::Format::Json | output::Format::Sarif => {
+                println!("{}", serde_json::to_string_pretty(&clusters)?);
+            }
+        }
+        return Ok(!clusters.is_empty());
+    }
+
+    if opt.invert {
+        let gaps = crate::r#match::uncovered(
+            matches.iter().map(|found| &found.result),
+            instructions.len(),
+        );
+        match opt.format {
+            output::Format::Text => print_gaps(&gaps, &instructions, &locations, ""),
+            output::Format::Json => {
+                let report = output::json_gaps_report(&gaps, &instructions, &locations);
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            }
+            output::Format::Sarif => {
+                let report = output::sarif_gaps_report(&gaps, &file.to_string_lossy(), &locations);
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            }
+        }
+        return Ok(!gaps.is_empty());
+    }
+
+    let trace = opt
+        .start_cell
+        .map(|start| PointerTrace::new(&instructions, start));
+    match opt.format {
+        output::Format::Text => {
+            let source = opt.context.map(|_| std::fs::read(file)).transpose()?;
+            let context = source_context(opt, common, source.as_deref())?;
+            print_matches(
+                library,
+                labeled,
+                &matches,
+                &locations,
+                context.as_ref(),
+                trace.as_ref(),
+                "",
+            )?;
+        }
+        output::Format::Json => {
+            let report =
+                output::json_report(&matches, library, labeled, &locations, trace.as_ref());
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        }
+        output::Format::Sarif => {
+            let report =
+                output::sarif_report(&matches, library, &file.to_string_lossy(), &locations);
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        }
+    }
+
+    Ok(!matches.is_empty())
+}
+
+/// Searches every file in the directories and globs the options say,
+/// printing the matches of every file along with its path, and then
+/// how many there are in all of them. A file that can't be read or
+/// parsed is reported, without stopping the search.
+fn search_corpus(
+    opt: &Opt,
+    common: &Common,
+    library: &PatternLibrary,
+    labeled: bool,
+) -> Result<bool, Box<dyn Error>> {
+    if opt.explain.is_some() {
+        return Err("--explain can only be used with a single file".into());
+    }
+    if opt.correlate {
+        return Err("--correlate can only be used with a single file".into());
+    }
+    let files = crate::corpus::files(&opt.files, &opt.ignore)?;
+    let compiled = CompiledLibrary::new(library);
+    let mut diagnostics = Diagnostics::new();
+    let mut total = output::stats(&[], library, 0);
+    let mut ranking = output::Ranking::new(library);
+    let (mut searched, mut matched) = (0, 0);
+    // whether there's a suggestion or an uncovered region anywhere.
+    let mut found = false;
+    // what's printed at the end for the machine readable formats.
+    let mut reports = Vec::new();
+    let mut sarif_results = Vec::new();
+    for file in files.iter() {
+        let path = file.display().to_string();
+        let _span = tracing::info_span!("file", path = %path).entered();
+        let (instructions, locations) = match load(file, common) {
+            Ok(loaded) => loaded,
+            Err(e) => {
+                let found = crate::errors::diagnose(&*e);
+                diagnostics.extend(found.into_iter().map(|found| match found.file {
+                    Some(_) => found,
+                    None => found.in_file(&*path),
+                }));
+                continue;
+            }
+        };
+        searched += 1;
+        let prefix = format!("{}: ", path);
+        if opt.suggest {
+            found |= print_suggestions(&instructions, &locations, &prefix);
+            continue;
+        }
+        let matches = find(opt, library, &compiled, &instructions, &locations)?;
+        let stats = output::stats(&matches, library, instructions.len());
+        total.add(&stats);
+        if !matches.is_empty() {
+            matched += 1;
+        }
+        if opt.rank {
+            ranking.add(&path, &stats);
+        } else if opt.count || opt.stats {
+            match opt.format {
+                output::Format::Text => print_stats(&stats, opt.stats, labeled, &prefix),
+                output::Format::Json | output::Format::Sarif => {
+                    reports.push(json!({ "file": path, "stats": stats }));
+                }
+            }
+        } else if opt.invert {
+            let gaps = crate::r#match::uncovered(
+                matches.iter().map(|found| &found.result),
+                instructions.len(),
+            );
+            found |= !gaps.is_empty();
+            match opt.format {
+                output::Format::Text => print_gaps(&gaps, &instructions, &locations, &prefix),
+                output::Format::Json => {
+                    let report = output::json_gaps_report(&gaps, &instructions, &locations);
+                    reports.push(json!({ "file": path, "uncovered": report }));
+                }
+                output::Format::Sarif => {
+                    sarif_results.extend(output::sarif_gaps_results(&gaps, &path, &locations));
+                }
+            }
+        } else {
+            let trace = opt
+                .start_cell
+                .map(|start| PointerTrace::new(&instructions, start));
+            match opt.format {
+                output::Format::Text => {
+                    let source = opt.context.map(|_| std::fs::read(file)).transpose()?;
+                    let context = source_context(opt, common, source.as_deref())?;
+                    print_matches(
+                        library,
+                        labeled,
+                        &matches,
+                        &locations,
+                        context.as_ref(),
+                        trace.as_ref(),
+                        &prefix,
+                    )?;
+                }
+                output::Format::Json => {
+                    let report =
+                        output::json_report(&matches, library, labeled, &locations, trace.as_ref());
+                    reports.push(json!({ "file": path, "matches": report }));
+                }
+                output::Format::Sarif => {
+                    sarif_results
+                        .extend(output::sarif_results(&matches, library, &path, &locations));
+                }
+            }
+        }
+    }
+
+    match opt.format {
+        _ if opt.suggest => (),
+        output::Format::Text if opt.rank => {
+            ranking.sort();
+            print_ranking(&ranking);
+        }
+        output::Format::Json | output::Format::Sarif if opt.rank => {
+            ranking.sort();
+            println!("{}", serde_json::to_string_pretty(&ranking)?);
+        }
+        output::Format::Text => {
+            if opt.count || opt.stats {
+                print_stats(&total, opt.stats, labeled, "total: ");
+            }
+            println!(
+                "{} matches in {} of {} files",
+                total.matches, matched, searched
+            );
+            if opt.stats {
+                println!(
+                    "covering {} of {} instructions ({:.2}%)",
+                    total.covered, total.instructions, total.coverage
+                );
+            }
+        }
+        output::Format::Sarif if !(opt.count || opt.stats) => {
+            let rules = if opt.invert {
+                vec![json!({ "id": "uncovered" })]
+            } else {
+                output::sarif_rules(library)
+            };
+            let log = output::sarif_log(rules, sarif_results);
+            println!("{}", serde_json::to_string_pretty(&log)?);
+        }
+        output::Format::Json | output::Format::Sarif => {
+            let report = json!({ "files": reports, "total": total });
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        }
+    }
+    let found = match () {
+        _ if opt.suggest || opt.invert => found,
+        _ => total.matches > 0,
+    };
+    Ok(diagnostics.into_result(found)?)
+}
+
+/// Only finds out which files have matches, printing their paths for
+/// `--files-with-matches`, and stopping at the first one for `--quiet`.
+fn list_matching(
+    opt: &Opt,
+    common: &Common,
+    library: &PatternLibrary,
+) -> Result<bool, Box<dyn Error>> {
+    let files = crate::corpus::files(&opt.files, &opt.ignore)?;
+    let compiled = CompiledLibrary::new(library);
+    let mut diagnostics = Diagnostics::new();
+    let mut found = false;
+    for file in files.iter() {
+        let (instructions, locations) = match load(file, common) {
+            Ok(loaded) => loaded,
+            Err(e) => {
+                let path = file.display().to_string();
+                let found = crate::errors::diagnose(&*e);
+                diagnostics.extend(found.into_iter().map(|found| match found.file {
+                    Some(_) => found,
+                    None => found.in_file(&*path),
+                }));
+                continue;
+            }
+        };
+        let matched = if opt.suggest {
+            let rules = crate::suggest::rules();
+            !crate::suggest::suggest(&instructions, &rules).is_empty()
+        } else {
+            let matches = find(opt, library, &compiled, &instructions, &locations)?;
+            if opt.invert {
+                let results = matches.iter().map(|found| &found.result);
+                !crate::r#match::uncovered(results, instructions.len()).is_empty()
+            } else {
+                !matches.is_empty()
+            }
+        };
+        if matched {
+            // like grep, a match is enough to succeed when quiet, even after an error.
+            if opt.quiet {
+                return Ok(true);
+            }
+            found = true;
+            println!("{}", file.display());
+        }
+    }
+    Ok(diagnostics.into_result(found)?)
+}
+
+/// Prints how simpler every part of the program could be, starting
+/// every suggestion with `prefix`, returning whether there was any.
+fn print_suggestions(instructions: &[BFCommand], locations: &[Location], prefix: &str) -> bool {
+    let rules = crate::suggest::rules();
+    let suggestions = crate::suggest::suggest(instructions, &rules);
+    for suggestion in suggestions.iter() {
+        let (start, end) = suggestion.result.locate(locations);
+        let (first, last) = (start.position.line, end.position.line);
+        if first == last {
+            print!("{}line {}: ", prefix, first);
+        } else {
+            print!("{}lines {}-{}: ", prefix, first, last);
+        }
+        println!("{}", rules[suggestion.rule].message);
+        if suggestion.changes() {
+            println!(
+                "\treplace `{}` with `{}`",
+                to_text(suggestion.result.commands),
+                to_text(&suggestion.replacement)
+            );
+        }
+    }
+    !suggestions.is_empty()
+}
+
+/// Prints how many times every pattern matched, and how
+/// much they covered with `covered`, after `prefix`.
+fn print_stats(stats: &output::Stats, covered: bool, labeled: bool, prefix: &str) {
+    for pattern in stats.patterns.iter() {
+        print!("{}", prefix);
+        if labeled {
+            print!("{}: ", pattern.name);
+        }
+        if covered {
+            println!(
+                "{} matches, {} instructions",
+                pattern.matches, pattern.covered
+            );
+        } else {
+            println!("{}", pattern.matches);
+        }
+    }
+}
+
+/// Prints the patterns of a ranking in a table, and how many matches
+/// there are in how many files.
+fn print_ranking(ranking: &output::Ranking) {
+    let name_width = ranking
+        .patterns
+        .iter()
+        .map(|pattern| pattern.name.chars().count())
+        .chain(std::iter::once("pattern".len()))
+        .max()
+        .unwrap_or(0);
+    println!(
+        "rank  {:<width$}  {:>8}  {:>6}  {:>12}",
+        "pattern",
+        "matches",
+        "files",
+        "instructions",
+        width = name_width
+    );
+    for (i, pattern) in ranking.patterns.iter().enumerate() {
+        println!(
+            "{:>4}  {:<width$}  {:>8}  {:>6}  {:>12}",
+            i + 1,
+            pattern.name,
+            pattern.matches,
+            pattern.files,
+            pattern.covered,
+            width = name_width
+        );
+    }
+    let matches: usize = ranking.patterns.iter().map(|pattern| pattern.matches).sum();
+    println!("{} matches in {} files", matches, ranking.files);
+}
+
+/// Prints the parts of the program no pattern matched, after `prefix`.
+fn print_gaps(
+    gaps: &[Range<usize>],
+    instructions: &[BFCommand],
+    locations: &[Location],
+    prefix: &str,
+) {
+    for gap in gaps.iter() {
+        let (start, end) = output::locate_range(gap, locations);
+        println!(
+            "{}uncovered at {}-{}: `{}`",
+            prefix,
+            start.position,
+            end.position,
+            to_text(&instructions[gap.clone()])
+        );
+    }
+}
+
+/// The lines around every match, when the options ask for them.
+fn source_context<'s>(
+    opt: &Opt,
+    common: &Common,
+    source: Option<&'s [u8]>,
+) -> Result<Option<context::SourceContext<'s>>, Box<dyn Error>> {
+    let (source, lines) = match (source, opt.context) {
+        (Some(source), Some(lines)) => (source, lines),
+        _ => return Ok(None),
+    };
+    let theme = common.theme(std::io::stdout().is_terminal())?;
+    Ok(Some(context::SourceContext::new(source, lines, theme)))
+}
+
+/// Prints every match, starting them with `prefix`.
+fn print_matches(
+    library: &PatternLibrary,
+    labeled: bool,
+    matches: &[LibraryMatch],
+    locations: &[Location],
+    context: Option<&context::SourceContext>,
+    trace: Option<&PointerTrace>,
+    prefix: &str,
+) -> std::io::Result<()> {
+    for (i, found) in matches.iter().enumerate() {
+        let entry = &library.entries[found.pattern];
+        if context.is_some() && i > 0 {
+            println!("--");
+        }
+        print!("{}", prefix);
+        if labeled {
+            print!("`{}`: ", entry.name);
+        }
+        print_match(&found.result, &entry.scope, locations, context, trace)?;
+    }
+    Ok(())
+}
+
+/// Prints every cluster as the binding, the cell and where its matches are.
+fn print_clusters(clusters: &[crate::correlate::Cluster], locations: &[Location]) {
+    for cluster in clusters {
+        let starts: Vec<_> = cluster
+            .starts
+            .iter()
+            .map(|&start| locations[start].position.to_string())
+            .collect();
+        println!(
+            "`{}` of `{}` at cell {}, in {} matches: {}",
+            cluster.binding,
+            cluster.pattern,
+            cluster.cell,
+            cluster.starts.len(),
+            starts.join(", ")
+        );
+    }
+}
+
+/// Try every pattern at `offset`, reporting where and why the ones that don't match fail.
+fn explain(
+    mut machine: MatchSM,
+    locations: &[Location],
+    library: &PatternLibrary,
+    offset: usize,
+) -> Result<bool, Box<dyn Error>> {
+    if offset >= locations.len() {
+        return Err(format!(
+            "offset {} is past the end of the program ({} instructions)",
+            offset,
+            locations.len()
+        )
+        .into());
+    }
+    let mut found = false;
+    for entry in library.entries.iter() {
+        print!("`{}`: ", entry.name);
+        match machine.match_at(offset, &entry.scope) {
+            Some(res) => {
+                found = true;
+                let (start, end) = res.locate(locations);
+                let str: String = res.commands.iter().map(|&i| i as u8 as char).collect();
+                println!("matches at {}-{}: `{}`", start.position, end.position, str);
+            }
+            None => {
+                let mismatch = machine.mismatch().unwrap();
+                // a mismatch may happen right after the last instruction
+                let position = match locations.get(mismatch.offset) {
+                    Some(location) => location.position,
+                    None => locations.last().unwrap().after_command().position,
+                };
+                println!(
+                    "fails at {} (instruction {}): {}",
+                    position,
+                    mismatch.offset,
+                    mismatch.describe(&entry.scope)
+                );
+            }
+        }
+    }
+    Ok(found)
+}
+
+/// Run the spec files in `paths`, printing the cases that fail.
+fn test(paths: &[PathBuf]) -> Result<(), Box<dyn Error>> {
+    let mut files = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            let mut specs = Vec::new();
+            for entry in std::fs::read_dir(path)? {
+                let entry = entry?.path();
+                if entry.extension().is_some_and(|ext| ext == "spec") {
+                    specs.push(entry);
+                }
+            }
+            specs.sort();
+            files.extend(specs);
+        } else {
+            files.push(path.clone());
+        }
+    }
+
+    // a broken spec file doesn't stop the others from running.
+    let mut diagnostics = Diagnostics::new();
+    let (mut passed, mut failed) = (0, 0);
+    for file in files.iter() {
+        let specs = match crate::spec::parse_spec(&std::fs::read_to_string(file)?) {
+            Ok(specs) => specs,
+            Err(e) => {
+                let file = file.display().to_string();
+                let found = crate::errors::diagnose(&e);
+                diagnostics.extend(found.into_iter().map(|found| found.in_file(&*file)));
+                continue;
+            }
+        };
+        for spec in specs.iter() {
+            for case in spec.cases.iter() {
+                let failures = case.check(&spec.scope);
+                if failures.is_empty() {
+                    passed += 1;
+                    continue;
+                }
+                failed += 1;
+                print_failure(file, &spec.pattern, case.position, &failures, &spec.scope);
+            }
+        }
+    }
+    println!("{} passed, {} failed", passed, failed);
+    if failed > 0 {
+        diagnostics.push(Diagnostic::new(format!(
+            "{} of {} cases failed",
+            failed,
+            passed + failed
+        )));
+    }
+    Ok(diagnostics.into_result(())?)
+}
+
+fn print_failure(
+    file: &Path,
+    pattern: &str,
+    position: Position,
+    failures: &[crate::spec::Failure],
+    scope: &PatternScope,
+) {
+    println!("FAIL {}:{}: `{}`", file.display(), position, pattern);
+    for failure in failures {
+        println!("\t{}", failure.describe(scope));
+    }
+}
+
+fn print_match(
+    res: &MatchResult,
+    scope: &PatternScope,
+    locations: &[Location],
+    context: Option<&context::SourceContext>,
+    trace: Option<&PointerTrace>,
+) -> std::io::Result<()> {
+    let (start, end) = res.locate(locations);
+    if let Some(context) = context {
+        println!("result at {}-{}:", start.position, end.position);
+        context.print(start, end, &mut std::io::stdout().lock())?;
+        if !scope.bindings.is_empty() {
+            let cells: Vec<_> = (0..scope.bindings.len())
+                .map(|key| {
+                    format!(
+                        "`{}` at {:+}",
+                        scope.bindings.get_by_left(&key).unwrap(),
+                        res.cells[key]
+                    )
+                })
+                .collect();
+            println!("bindings: {}", cells.join(", "));
+        }
+    } else {
+        let str: String = res.commands.iter().map(|&i| i as u8 as char).collect();
+        println!("result at {}-{}: `{}`", start.position, end.position, str);
+    }
+    for index in 0..scope.captures.len() {
+        let name = scope.captures.get_by_left(&index).unwrap();
+        if let Some(commands) = res.capture(index) {
+            let str: String = commands.iter().map(|&i| i as u8 as char).collect();
+            println!("capture `{}`: `{}`", name, str);
+        }
+    }
+    for (key, offsets) in res.named(scope).relative_offsets {
+        println!("offsets for `{}`", key);
+        for (other, offt) in offsets {
+            println!("\t`{}` -> {}", other, offt);
+        }
+    }
+    if let Some(trace) = trace.filter(|_| !scope.bindings.is_empty()) {
+        match trace.cells(res) {
+            Some(cells) => {
+                let cells: Vec<_> = cells
+                    .iter()
+                    .enumerate()
+                    .map(|(key, cell)| {
+                        format!("`{}` = {}", scope.bindings.get_by_left(&key).unwrap(), cell)
+                    })
+                    .collect();
+                println!("cells: {}", cells.join(", "));
+            }
+            None => println!("cells: unknown, a loop before the match moves the pointer"),
+        }
+    }
+    Ok(())
+}
+
+fn to_text(commands: &[BFCommand]) -> String {
+    commands.iter().map(|&i| i as u8 as char).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn opt(args: &[&str]) -> Opt {
+        Opt::from_iter_safe(std::iter::once("bfrs_patterns").chain(args.iter().copied())).unwrap()
+    }
+
+    #[test]
+    fn patterns_are_read_from_files() {
+        let path = std::env::temp_dir().join(format!("bfrs-pattern-{}", std::process::id()));
+        std::fs::write(&path, "[\n  -\n]\n").unwrap();
+        let file = format!("@{}", path.display());
+
+        let (patterns, labeled) = library(&opt(&["-p", &file, "a.b"])).unwrap();
+        assert!(!labeled);
+        // named after the file, rather than all of its lines
+        assert_eq!(patterns.entries[0].name, path.display().to_string());
+
+        // runs are patterns themselves
+        assert_eq!(
+            read_pattern("@add(>2)").unwrap(),
+            ("@add(>2)".to_string(), None)
+        );
+        assert_eq!(read_pattern("[-]").unwrap(), ("[-]".to_string(), None));
+
+        std::fs::write(&path, "[-").unwrap();
+        let e = library(&opt(&["-p", &file, "a.b"])).unwrap_err();
+        let diagnostics = e.downcast_ref::<Diagnostics>().unwrap();
+        let diagnostic = diagnostics.iter().next().unwrap();
+        assert_eq!(diagnostic.file, Some(path.display().to_string()));
+        assert_eq!(diagnostic.code, Some("BF0203"));
+        std::fs::remove_file(&path).unwrap();
+
+        let e = read_pattern(&file).unwrap_err();
+        assert!(e
+            .to_string()
+            .starts_with(&format!("could not read {}", path.display())));
+    }
+
+    #[test]
+    fn stdin_is_read_once() {
+        let e = library(&opt(&["-p", "-", "-p", "-", "a.b"])).unwrap_err();
+        assert_eq!(e.to_string(), "only one pattern can be read from stdin");
+    }
+
+    #[test]
+    fn quiet_searches_only_tell_whether_anything_matched() {
+        let dir = std::env::temp_dir().join(format!("bfrs-quiet-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("clear.b"), "+[-]").unwrap();
+        std::fs::write(dir.join("add.b"), "++").unwrap();
+        let dir_arg = dir.display().to_string();
+        let common = Common::from_iter_safe(["bfrs_patterns"].iter()).unwrap();
+        let found = |args: &[&str]| run(opt(args), &common).unwrap();
+
+        assert!(found(&["-q", "-p", "[-]", &dir_arg]));
+        assert!(!found(&["-q", "-p", ",", &dir_arg]));
+        // an error in one file doesn't hide the matches of the others
+        std::fs::write(dir.join("broken.b"), "]").unwrap();
+        assert!(found(&["-q", "-p", "[-]", &dir_arg]));
+        let e = run(
+            opt(&["--files-with-matches", "-p", "[-]", &dir_arg]),
+            &common,
+        )
+        .unwrap_err();
+        assert!(e.to_string().contains("broken.b"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn matches_are_kept_to_regions() {
+        let instructions = bfrs_common::parser::parse_str("[-]+[[-]>]").unwrap();
+        let starts = |args: &[&str]| {
+            let opt = opt(args);
+            let (library, _) = library(&opt).unwrap();
+            let compiled = CompiledLibrary::new(&library);
+            let matches = find(&opt, &library, &compiled, &instructions, &[]).unwrap();
+            matches
+                .iter()
+                .map(|found| found.result.start)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(starts(&["-p", "[-]", "a.b"]), [0, 5]);
+        assert_eq!(starts(&["-p", "[-]", "--within", "loops", "a.b"]), [5]);
+        assert_eq!(starts(&["-p", "[-]", "--outside", "loops", "a.b"]), [0]);
+        let both = [
+            "-p",
+            "[-]",
+            "--within",
+            "loops",
+            "--outside",
+            "pattern:[[-]>]",
+            "a.b",
+        ];
+        assert!(starts(&both).is_empty());
+    }
+}
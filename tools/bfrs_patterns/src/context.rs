@@ -0,0 +1,143 @@
+//! Printing matches along with the source around them.
+
+use bfrs_common::theme::{Theme, RESET};
+use bfrs_common::Location;
+use std::io::{self, Write};
+
+/// The source of the searched file, split in lines.
+pub struct SourceContext<'s> {
+    source: &'s [u8],
+    /// byte offset where every line starts
+    line_starts: Vec<usize>,
+    /// amount of lines to show before and after a match
+    lines: usize,
+    /// the colors of the matches and the line numbers, if they're colored
+    theme: Option<Theme>,
+}
+
+impl<'s> SourceContext<'s> {
+    pub fn new(source: &'s [u8], lines: usize, theme: Option<Theme>) -> Self {
+        let mut line_starts: Vec<_> = std::iter::once(0)
+            .chain(
+                source
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, &byte)| byte == b'\n')
+                    .map(|(i, _)| i + 1),
+            )
+            .collect();
+        // a trailing newline ends the last line instead of starting another one
+        if line_starts.len() > 1 && line_starts.last() == Some(&source.len()) {
+            line_starts.pop();
+        }
+        Self {
+            source,
+            line_starts,
+            lines,
+            theme,
+        }
+    }
+
+    /// Print the lines of a match, and the ones around it, highlighting
+    /// the matched bytes. Lines with part of the match have their number
+    /// followed by `:`, the ones around it by `-`.
+    pub fn print(&self, start: Location, end: Location, out: &mut impl Write) -> io::Result<()> {
+        // `end` is right after the match, which may be a line below.
+        let last_matched = self.line_of(end.byte_offset.saturating_sub(1).max(start.byte_offset));
+        let first_matched = start.position.line;
+        let first = first_matched.saturating_sub(self.lines).max(1);
+        let last = (last_matched + self.lines).min(self.line_starts.len());
+        for line in first..=last {
+            let line_start = self.line_starts[line - 1];
+            let line_end = self.source[line_start..]
+                .iter()
+                .position(|&byte| byte == b'\n')
+                .map_or(self.source.len(), |len| line_start + len);
+            let separator = if (first_matched..=last_matched).contains(&line) {
+                ':'
+            } else {
+                '-'
+            };
+            if let Some(theme) = &self.theme {
+                let color = theme.paint(theme.line_number, None, false);
+                write!(out, "{}{}{}{}", color, line, RESET, separator)?;
+            } else {
+                write!(out, "{}{}", line, separator)?;
+            }
+            let highlight_start = start.byte_offset.clamp(line_start, line_end);
+            let highlight_end = end.byte_offset.clamp(line_start, line_end);
+            out.write_all(&self.source[line_start..highlight_start])?;
+            if let (Some(theme), true) = (&self.theme, highlight_start < highlight_end) {
+                let (foreground, background) = theme.matched;
+                out.write_all(theme.paint(foreground, background, true).as_bytes())?;
+                out.write_all(&self.source[highlight_start..highlight_end])?;
+                out.write_all(RESET.as_bytes())?;
+            } else {
+                out.write_all(&self.source[highlight_start..highlight_end])?;
+            }
+            out.write_all(&self.source[highlight_end..line_end])?;
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+
+    /// the line a byte is in, starting at 1
+    fn line_of(&self, byte_offset: usize) -> usize {
+        match self.line_starts.binary_search(&byte_offset) {
+            Ok(line) => line + 1,
+            Err(line) => line,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bfrs_common::Position;
+
+    fn at(line: usize, column: usize, byte_offset: usize) -> Location {
+        Location {
+            position: Position { line, column },
+            byte_offset,
+        }
+    }
+
+    fn printed(context: &SourceContext, start: Location, end: Location) -> String {
+        let mut out = Vec::new();
+        context.print(start, end, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    const SOURCE: &[u8] = b"a\nb [-]\nc [\n-]\nd\n";
+
+    #[test]
+    fn lines_around_the_match() {
+        let context = SourceContext::new(SOURCE, 1, None);
+        assert_eq!(
+            printed(&context, at(2, 3, 4), at(2, 6, 7)),
+            "1-a\n2:b [-]\n3-c [\n"
+        );
+        // a match across lines marks all of them
+        assert_eq!(
+            printed(&context, at(3, 3, 10), at(4, 3, 14)),
+            "2-b [-]\n3:c [\n4:-]\n5-d\n"
+        );
+        let context = SourceContext::new(SOURCE, 0, None);
+        assert_eq!(printed(&context, at(5, 1, 15), at(5, 2, 16)), "5:d\n");
+    }
+
+    #[test]
+    fn matches_are_highlighted() {
+        let theme = Theme::builtin("default").unwrap();
+        let context = SourceContext::new(SOURCE, 0, Some(theme.clone()));
+        let (foreground, background) = theme.matched;
+        let expected = format!(
+            "{}2{}:b {}[-]{}\n",
+            theme.paint(theme.line_number, None, false),
+            RESET,
+            theme.paint(foreground, background, true),
+            RESET,
+        );
+        assert_eq!(printed(&context, at(2, 3, 4), at(2, 6, 7)), expected);
+    }
+}
@@ -0,0 +1,144 @@
+//! Finding the programs of a corpus, to search all of them at once.
+//!
+//! Directories are walked recursively, taking the files with one of the
+//! [`EXTENSIONS`], and skipping the hidden ones and the ones ignored by
+//! `.gitignore`, `.ignore` or `.bfignore` files, like `ripgrep` does.
+//! Paths with `*`, `?`, `[` or `{` in them are globs, like `corpus/**/*.b`,
+//! which take every file they match, whatever its extension.
+
+use globset::GlobBuilder;
+use ignore::overrides::OverrideBuilder;
+use ignore::WalkBuilder;
+use std::error::Error;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The extensions of the files taken from directories.
+pub const EXTENSIONS: &[&str] = &["b", "bf"];
+
+/// The files `paths` stand for, in order, without the ones matching any of
+/// the `ignore` globs. Files given on their own are always taken.
+pub fn files(paths: &[PathBuf], ignore: &[String]) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut files = Vec::new();
+    for path in paths {
+        if path.is_file() {
+            files.push(path.clone());
+        } else if path.is_dir() {
+            let walked = walk(path, ignore)?;
+            files.extend(walked.into_iter().filter(|file| {
+                file.extension()
+                    .is_some_and(|ext| EXTENSIONS.iter().any(|&known| ext == known))
+            }));
+        } else if is_glob(path) {
+            let glob = GlobBuilder::new(&path.to_string_lossy())
+                .literal_separator(true)
+                .build()?
+                .compile_matcher();
+            let root = glob_root(path);
+            // a glob without a directory is matched against paths without one either.
+            let walked = if root.as_os_str().is_empty() {
+                walk(Path::new("."), ignore)?
+                    .into_iter()
+                    .map(|file| {
+                        file.strip_prefix(".")
+                            .map_or(file.clone(), Path::to_path_buf)
+                    })
+                    .collect()
+            } else {
+                walk(&root, ignore)?
+            };
+            files.extend(walked.into_iter().filter(|file| glob.is_match(file)));
+        } else {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{}: no such file or directory", path.display()),
+            )
+            .into());
+        }
+    }
+    Ok(files)
+}
+
+/// Every file under `root`, sorted by path.
+fn walk(root: &Path, ignore: &[String]) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut overrides = OverrideBuilder::new(root);
+    for glob in ignore {
+        overrides.add(&format!("!{}", glob))?;
+    }
+    let mut files = Vec::new();
+    let walker = WalkBuilder::new(root)
+        .overrides(overrides.build()?)
+        .add_custom_ignore_filename(".bfignore")
+        // a corpus doesn't have to be in a git repository to use its `.gitignore`.
+        .require_git(false)
+        .sort_by_file_path(|a, b| a.cmp(b))
+        .build();
+    for entry in walker {
+        let entry = entry?;
+        if entry.file_type().is_some_and(|kind| kind.is_file()) {
+            files.push(entry.into_path());
+        }
+    }
+    Ok(files)
+}
+
+fn is_glob(path: &Path) -> bool {
+    path.to_string_lossy().contains(['*', '?', '[', '{'])
+}
+
+/// The directory a glob starts matching from, made of the
+/// components before the first one with a wildcard.
+fn glob_root(path: &Path) -> PathBuf {
+    path.components()
+        .take_while(|component| !is_glob(Path::new(component.as_os_str())))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn corpora_are_walked_like_ripgrep_does() {
+        let dir = std::env::temp_dir().join(format!("bfrs-corpus-{}", std::process::id()));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        for file in [
+            "a.b",
+            "notes.txt",
+            ".hidden.b",
+            "skipped.b",
+            "sub/c.bf",
+            "sub/d.b",
+        ]
+        .iter()
+        {
+            fs::write(dir.join(file), "+").unwrap();
+        }
+        fs::write(dir.join(".bfignore"), "skipped.b\n").unwrap();
+
+        let found = files(std::slice::from_ref(&dir), &["d.b".to_string()]).unwrap();
+        assert_eq!(found, [dir.join("a.b"), dir.join("sub/c.bf")]);
+        // globs take whatever they match, and files given on their own are taken
+        let glob = dir.join("**/*.txt");
+        let found = files(&[glob, dir.join("skipped.b")], &[]).unwrap();
+        assert_eq!(found, [dir.join("notes.txt"), dir.join("skipped.b")]);
+
+        let missing = files(&[dir.join("missing.b")], &[]).unwrap_err();
+        assert!(missing
+            .to_string()
+            .ends_with("missing.b: no such file or directory"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn globs_start_from_their_directory() {
+        assert_eq!(
+            glob_root(Path::new("corpus/old/**/*.b")),
+            Path::new("corpus/old")
+        );
+        assert_eq!(glob_root(Path::new("*.b")), Path::new(""));
+        assert!(is_glob(Path::new("a{1,2}.b")));
+        assert!(!is_glob(Path::new("a.b")));
+    }
+}
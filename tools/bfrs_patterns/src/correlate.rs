@@ -0,0 +1,102 @@
+//! Clusters of matches working on the same cells, which is what
+//! `bfrs_patterns --correlate` reports.
+//!
+//! A program tends to keep the same things in the same cells, so the
+//! matches of a pattern whose bindings land on the same cell over and over,
+//! like every copy loop copying from cell 3, tell where its recurring
+//! structures are. Only the matches whose cells can be told, as long as
+//! the pointer can be followed up to them, are put in clusters.
+
+use crate::library::PatternLibrary;
+use crate::r#match::LibraryMatch;
+use crate::tape::PointerTrace;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// The matches of a pattern with a binding on the same cell.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Cluster<'l> {
+    pub pattern: &'l str,
+    pub binding: &'l str,
+    /// the cell of the binding, on the tape
+    pub cell: isize,
+    /// the instruction every match in it starts at, in order
+    pub starts: Vec<usize>,
+}
+
+/// The clusters of at least `min_size` matches, biggest first, and then by
+/// pattern, binding and cell.
+pub fn clusters<'l>(
+    matches: &[LibraryMatch],
+    library: &'l PatternLibrary,
+    trace: &PointerTrace,
+    min_size: usize,
+) -> Vec<Cluster<'l>> {
+    // the matches of every binding of every pattern, by its cell.
+    let mut found: BTreeMap<(usize, usize, isize), Vec<usize>> = BTreeMap::new();
+    for found_match in matches.iter() {
+        let cells = match trace.cells(&found_match.result) {
+            Some(cells) => cells,
+            None => continue,
+        };
+        for (binding, cell) in cells.into_iter().enumerate() {
+            found
+                .entry((found_match.pattern, binding, cell))
+                .or_default()
+                .push(found_match.result.start);
+        }
+    }
+    let mut clusters: Vec<_> = found
+        .into_iter()
+        .filter(|(_, starts)| starts.len() >= min_size)
+        .map(|((pattern, binding, cell), starts)| {
+            let entry = &library.entries[pattern];
+            Cluster {
+                pattern: &entry.name,
+                binding: entry.scope.bindings.get_by_left(&binding).unwrap(),
+                cell,
+                starts,
+            }
+        })
+        .collect();
+    // the sort is stable, so clusters of the same size stay in key order.
+    clusters.sort_by_key(|cluster| std::cmp::Reverse(cluster.starts.len()));
+    clusters
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::parse_library;
+    use crate::r#match::MatchSM;
+    use bfrs_common::parser::parse_str;
+
+    #[test]
+    fn matches_on_the_same_cells_are_clustered() {
+        let library = parse_library("copy = x[-y+x]\nclear = [-]\n").unwrap();
+        // copies cell 1 to cell 2 twice, and cell 3 to cell 4 once
+        let instructions = parse_str(">[->+<]<[-]>[->+<]>>[->+<]").unwrap();
+        let matches = MatchSM::new(&instructions).search_library(&library);
+        let trace = PointerTrace::new(&instructions, 0);
+        // the matches start with the move to `x`
+        let cluster = |binding, cell, starts: &[usize]| Cluster {
+            pattern: "copy",
+            binding,
+            cell,
+            starts: starts.to_vec(),
+        };
+        assert_eq!(
+            clusters(&matches, &library, &trace, 2),
+            [cluster("x", 1, &[0, 11]), cluster("y", 2, &[0, 11])]
+        );
+        let all = clusters(&matches, &library, &trace, 1);
+        // `clear` has no bindings to cluster by
+        assert_eq!(all.len(), 4);
+        assert_eq!(all[2], cluster("x", 3, &[18]));
+        // the cells of matches after the pointer is lost can't be told
+        let instructions = parse_str("[>][->+<][->+<]").unwrap();
+        let matches = MatchSM::new(&instructions).search_library(&library);
+        let trace = PointerTrace::new(&instructions, 0);
+        assert_eq!(clusters(&matches, &library, &trace, 1), []);
+    }
+}
@@ -0,0 +1,492 @@
+//! Pseudocode for a program, to read one nobody explained:
+//! ```text
+//! cell[0] += 8;
+//! while cell[0] != 0 {
+//!     cell[1] += 4;
+//!     ...
+//! }
+//! ```
+//!
+//! Cells are numbered from the one the program starts at, for as long as
+//! where the pointer is can be told without running the program. Loops
+//! moving it by an amount that can't, like `[>]`, leave it in `p`, and the
+//! cells after them are numbered from there, like `cell[p + 1]`.
+//!
+//! Loops are shown by what they do when it's simple enough: loops adding
+//! the value of a cell to others as many times as it's decremented are
+//! multiplications, loops clearing their cell at the end run at most once
+//! and are `if`s, and loops only moving are scans.
+//!
+//! The cells numbered from the start are named by what they're used for,
+//! when it can be told, like `counter_a` for the cell a loop runs on, and
+//! the names are listed at the top.
+
+use crate::ir::{Ir, Op};
+use bfrs_common::BFCommand;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// The pseudocode of a program, with the cells named by their [`Role`]
+/// if `named` is set, instead of numbered.
+pub fn decompile(instructions: &[BFCommand], named: bool) -> String {
+    let ops = Ir::compile(instructions).ops;
+    let names = if named {
+        names(&roles_of(&ops))
+    } else {
+        BTreeMap::new()
+    };
+    let mut out = String::new();
+    for (offset, name) in names.iter() {
+        writeln!(out, "// {}: cell[{}]", name, offset).expect("writing to a string doesn't fail");
+    }
+    if !names.is_empty() {
+        out.push('\n');
+    }
+    out + &Decompiler::new(&ops, names).run().out
+}
+
+/// What a cell is used for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    /// the cell a loop runs on, or that's multiplied into others
+    Counter,
+    /// a cell others are multiplied into
+    Accumulator,
+    /// a cell others are multiplied into, and that's multiplied into
+    /// others in turn, like to copy a cell through it
+    Temp,
+    /// a cell read into or printed
+    Buffer,
+}
+
+impl Role {
+    fn from_usage(usage: Usage) -> Option<Self> {
+        if usage.io {
+            Some(Self::Buffer)
+        } else if usage.multiplied && usage.multiplied_into {
+            Some(Self::Temp)
+        } else if usage.condition || usage.multiplied {
+            Some(Self::Counter)
+        } else if usage.multiplied_into {
+            Some(Self::Accumulator)
+        } else {
+            None
+        }
+    }
+
+    /// The name of the cell of this role that's `nth` from the left.
+    fn name(self, nth: usize) -> String {
+        match self {
+            Self::Counter => format!("counter_{}", letters(nth)),
+            Self::Accumulator => format!("acc_{}", letters(nth)),
+            Self::Temp => format!("tmp{}", nth),
+            Self::Buffer => format!("buf{}", nth),
+        }
+    }
+}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Counter => "counter",
+            Self::Accumulator => "accumulator",
+            Self::Temp => "temporary",
+            Self::Buffer => "I/O buffer",
+        })
+    }
+}
+
+/// `a` to `z`, and then `aa`, `ab` and so on, like spreadsheet columns.
+fn letters(mut nth: usize) -> String {
+    let mut letters = Vec::new();
+    loop {
+        letters.push(b'a' + (nth % 26) as u8);
+        if nth < 26 {
+            break;
+        }
+        nth = nth / 26 - 1;
+    }
+    letters.iter().rev().map(|&letter| letter as char).collect()
+}
+
+/// The role of every cell whose one can be told, by its offset from the
+/// one the program starts at. Only cells that offset is known for while
+/// the program uses them have one.
+pub fn roles(instructions: &[BFCommand]) -> BTreeMap<isize, Role> {
+    roles_of(&Ir::compile(instructions).ops)
+}
+
+fn roles_of(ops: &[Op]) -> BTreeMap<isize, Role> {
+    let usage = Decompiler::new(ops, BTreeMap::new()).run().usage;
+    usage
+        .into_iter()
+        .filter_map(|(offset, usage)| Some((offset, Role::from_usage(usage)?)))
+        .collect()
+}
+
+/// The name of every cell with a role, numbering the cells of every
+/// role from the left.
+pub fn names(roles: &BTreeMap<isize, Role>) -> BTreeMap<isize, String> {
+    let mut counts = BTreeMap::new();
+    roles
+        .iter()
+        .map(|(&offset, &role)| {
+            let nth = counts.entry(role).or_insert(0);
+            *nth += 1;
+            (offset, role.name(*nth - 1))
+        })
+        .collect()
+}
+
+/// How a cell is used.
+#[derive(Debug, Clone, Copy, Default)]
+struct Usage {
+    /// whether a loop runs on it
+    condition: bool,
+    /// whether it's multiplied into other cells
+    multiplied: bool,
+    /// whether other cells are multiplied into it
+    multiplied_into: bool,
+    /// whether it's read into or printed
+    io: bool,
+}
+
+/// Where the pointer is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Pointer {
+    /// at a cell from the one the program started at
+    Known(isize),
+    /// at a cell from `p`
+    Relative(isize),
+}
+
+impl Pointer {
+    fn shift(self, amount: isize) -> Self {
+        match self {
+            Self::Known(offset) => Self::Known(offset + amount),
+            Self::Relative(offset) => Self::Relative(offset + amount),
+        }
+    }
+}
+
+impl std::fmt::Display for Pointer {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            Self::Known(offset) => write!(f, "cell[{}]", offset),
+            Self::Relative(0) => write!(f, "cell[p]"),
+            Self::Relative(offset) if offset < 0 => write!(f, "cell[p - {}]", -offset),
+            Self::Relative(offset) => write!(f, "cell[p + {}]", offset),
+        }
+    }
+}
+
+struct Decompiler<'a> {
+    ops: &'a [Op],
+    /// the index of the bracket closing or opening every loop, by the other one
+    jumps: Vec<usize>,
+    /// the names of the cells numbered from the start, by their offset
+    names: BTreeMap<isize, String>,
+    /// how the cells numbered from the start are used, by their offset
+    usage: BTreeMap<isize, Usage>,
+    out: String,
+    pointer: Pointer,
+    /// how deep in loops the next line is
+    depth: usize,
+}
+
+impl<'a> Decompiler<'a> {
+    fn new(ops: &'a [Op], names: BTreeMap<isize, String>) -> Self {
+        Self {
+            jumps: jumps(ops),
+            ops,
+            names,
+            usage: BTreeMap::new(),
+            out: String::new(),
+            pointer: Pointer::Known(0),
+            depth: 0,
+        }
+    }
+
+    fn run(mut self) -> Self {
+        self.block(0, self.ops.len());
+        self
+    }
+
+    /// How a cell is spelled, recording how it's used with `used`.
+    fn cell(&mut self, cell: Pointer, used: impl FnOnce(&mut Usage)) -> String {
+        match cell {
+            Pointer::Known(offset) => {
+                used(self.usage.entry(offset).or_default());
+                match self.names.get(&offset) {
+                    Some(name) => name.clone(),
+                    None => cell.to_string(),
+                }
+            }
+            Pointer::Relative(_) => cell.to_string(),
+        }
+    }
+
+    fn line(&mut self, line: impl std::fmt::Display) {
+        writeln!(self.out, "{:indent$}{}", "", line, indent = self.depth * 4)
+            .expect("writing to a string doesn't fail");
+    }
+
+    /// Writes the operations from `start` until `end`.
+    fn block(&mut self, start: usize, end: usize) {
+        let mut i = start;
+        while i < end {
+            let pointer = self.pointer;
+            let cell = self.cell(pointer, |_| ());
+            match self.ops[i] {
+                Op::Add(amount) if amount < 0 => {
+                    self.line(format_args!("{} -= {};", cell, -amount))
+                }
+                Op::Add(amount) => self.line(format_args!("{} += {};", cell, amount)),
+                Op::Move(amount) => self.pointer = pointer.shift(amount),
+                Op::SetZero => match self.ops.get(i + 1) {
+                    Some(&Op::Add(amount)) if i + 1 < end => {
+                        self.line(format_args!("{} = {};", cell, amount));
+                        i += 1;
+                    }
+                    _ => self.line(format_args!("{} = 0;", cell)),
+                },
+                Op::Print => {
+                    self.cell(pointer, |usage| usage.io = true);
+                    self.line(format_args!("print({});", cell));
+                }
+                Op::Read => {
+                    self.cell(pointer, |usage| usage.io = true);
+                    self.line(format_args!("{} = read();", cell));
+                }
+                Op::BeginLoop => {
+                    let end = self.jumps[i];
+                    self.write_loop(i, end);
+                    i = end;
+                }
+                Op::EndLoop => unreachable!("loops are written whole"),
+            }
+            i += 1;
+        }
+    }
+
+    /// Writes the loop from the `[` at `start` to the `]` at `end`.
+    fn write_loop(&mut self, start: usize, end: usize) {
+        let pointer = self.pointer;
+        let body = start + 1..end;
+        match self.net_move(body.start, body.end) {
+            Some(0) => {
+                if let Some(effects) = multiplication(&self.ops[body.clone()]) {
+                    let cell = self.cell(pointer, |usage| usage.multiplied = true);
+                    for (offset, times) in effects {
+                        let target =
+                            self.cell(pointer.shift(offset), |usage| usage.multiplied_into = true);
+                        match times {
+                            1 => self.line(format_args!("{} += {};", target, cell)),
+                            -1 => self.line(format_args!("{} -= {};", target, cell)),
+                            times if times < 0 => {
+                                self.line(format_args!("{} -= {} * {};", target, cell, -times))
+                            }
+                            times => self.line(format_args!("{} += {} * {};", target, cell, times)),
+                        }
+                    }
+                    self.line(format_args!("{} = 0;", cell));
+                    return;
+                }
+                // the loop cell is cleared at the end, where the pointer is back at it.
+                let keyword = if self.ops[end - 1] == Op::SetZero {
+                    "if"
+                } else {
+                    "while"
+                };
+                let cell = self.cell(pointer, |usage| usage.condition = true);
+                self.line(format_args!("{} {} != 0 {{", keyword, cell));
+                self.depth += 1;
+                self.block(body.start, body.end);
+                self.depth -= 1;
+                self.line("}");
+            }
+            Some(amount)
+                if self.ops[body.clone()]
+                    .iter()
+                    .all(|op| matches!(op, Op::Move(_))) =>
+            {
+                self.settle();
+                let step = if amount < 0 {
+                    format!("p -= {}", -amount)
+                } else {
+                    format!("p += {}", amount)
+                };
+                let cell = self.pointer;
+                self.line(format_args!("while {} != 0 {{ {}; }}", cell, step));
+            }
+            _ => {
+                // every iteration starts wherever the last one left the pointer.
+                self.settle();
+                let cell = self.pointer;
+                self.line(format_args!("while {} != 0 {{", cell));
+                self.depth += 1;
+                self.block(body.start, body.end);
+                self.settle();
+                self.depth -= 1;
+                self.line("}");
+            }
+        }
+    }
+
+    /// Writes where the pointer is to `p`, to number the cells from there.
+    fn settle(&mut self) {
+        match self.pointer {
+            Pointer::Known(offset) => self.line(format_args!("p = {};", offset)),
+            Pointer::Relative(0) => (),
+            Pointer::Relative(offset) if offset < 0 => self.line(format_args!("p -= {};", -offset)),
+            Pointer::Relative(offset) => self.line(format_args!("p += {};", offset)),
+        }
+        self.pointer = Pointer::Relative(0);
+    }
+
+    /// How much the operations from `start` until `end` move the pointer,
+    /// if it can be told without running them.
+    fn net_move(&self, start: usize, end: usize) -> Option<isize> {
+        let mut net = 0;
+        let mut i = start;
+        while i < end {
+            match self.ops[i] {
+                Op::Move(amount) => net += amount,
+                Op::BeginLoop => {
+                    if self.net_move(i + 1, self.jumps[i])? != 0 {
+                        return None;
+                    }
+                    i = self.jumps[i];
+                }
+                _ => (),
+            }
+            i += 1;
+        }
+        Some(net)
+    }
+}
+
+/// What the body of a loop adds to every cell other than its own, by
+/// its offset, if it only adds to cells and decrements its own once.
+pub(crate) fn multiplication(body: &[Op]) -> Option<BTreeMap<isize, isize>> {
+    let mut effects = BTreeMap::new();
+    let mut offset = 0;
+    for &op in body {
+        match op {
+            Op::Add(amount) => *effects.entry(offset).or_insert(0) += amount,
+            Op::Move(amount) => offset += amount,
+            _ => return None,
+        }
+    }
+    if offset != 0 || effects.remove(&0) != Some(-1) {
+        return None;
+    }
+    effects.retain(|_, &mut times| times != 0);
+    Some(effects)
+}
+
+/// The index of the bracket matching every bracket.
+fn jumps(ops: &[Op]) -> Vec<usize> {
+    let mut jumps = vec![0; ops.len()];
+    let mut open = Vec::new();
+    for (i, op) in ops.iter().enumerate() {
+        match op {
+            Op::BeginLoop => open.push(i),
+            Op::EndLoop => {
+                let start = open.pop().expect("the loops of a program are balanced");
+                jumps[start] = i;
+                jumps[i] = start;
+            }
+            _ => (),
+        }
+    }
+    jumps
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bfrs_common::parser;
+
+    fn numbered(src: &str) -> String {
+        decompile(&parser::parse_str(src).unwrap(), false)
+    }
+
+    #[test]
+    fn loops_are_shown_by_what_they_do() {
+        let expected = "\
+cell[0] += 8;
+cell[1] += cell[0] * 4;
+cell[0] = 0;
+cell[1] = read();
+if cell[1] != 0 {
+    print(cell[1]);
+    cell[1] = 0;
+}
+while cell[0] != 0 {
+    cell[0] -= 2;
+    cell[1] = 3;
+}
+";
+        assert_eq!(numbered("++++++++[->++++<]>,[.[-]]<[-->[-]+++<]"), expected);
+    }
+
+    #[test]
+    fn cells_are_numbered_from_the_pointer_once_it_is_lost() {
+        let expected = "\
+cell[1] += 1;
+p = 1;
+while cell[p] != 0 { p += 2; }
+cell[p - 1] = read();
+while cell[p] != 0 {
+    cell[p] -= 1;
+    p += 1;
+}
+print(cell[p]);
+";
+        assert_eq!(numbered(">+[>>]<,>[->]."), expected);
+    }
+
+    #[test]
+    fn cells_are_named_by_their_role() {
+        // copies cell 0 to cell 2 through cell 1, and prints cell 3
+        let src = ",[->+>+<<]>[-<+>]>>,.";
+        let roles = roles(&parser::parse_str(src).unwrap());
+        let expected = [
+            (0, Role::Buffer),
+            (1, Role::Temp),
+            (2, Role::Accumulator),
+            (3, Role::Buffer),
+        ];
+        assert_eq!(roles.into_iter().collect::<Vec<_>>(), expected);
+
+        let expected = "\
+// buf0: cell[0]
+// tmp0: cell[1]
+// acc_a: cell[2]
+// buf1: cell[3]
+
+buf0 = read();
+tmp0 += buf0;
+acc_a += buf0;
+buf0 = 0;
+buf0 += tmp0;
+tmp0 = 0;
+buf1 = read();
+print(buf1);
+";
+        assert_eq!(decompile(&parser::parse_str(src).unwrap(), true), expected);
+    }
+
+    #[test]
+    fn names_are_lettered_like_spreadsheet_columns() {
+        let lettered: Vec<_> = [0, 1, 25, 26, 27, 701, 702]
+            .iter()
+            .map(|&nth| letters(nth))
+            .collect();
+        assert_eq!(lettered, ["a", "b", "z", "aa", "ab", "zz", "aaa"]);
+        let roles = (0..3).map(|offset| (offset, Role::Counter)).collect();
+        let names: Vec<_> = names(&roles).into_values().collect();
+        assert_eq!(names, ["counter_a", "counter_b", "counter_c"]);
+    }
+}
@@ -0,0 +1,130 @@
+//! Reporting the errors of patterns, along with the ones of
+//! [`bfrs_common`], for the command line tools.
+
+use crate::armor::ArmorError;
+use crate::library::{LibraryError, LibraryErrorKind};
+use crate::pattern::ParseError;
+use bfrs_common::errors::{
+    self as bfrs_errors, Diagnostic, Diagnostics, ErrorFormat, ErrorWithSpan,
+};
+use bfrs_common::PositionConfig;
+use std::error::Error;
+
+/// The diagnostics of an error of patterns or [`bfrs_common`], or
+/// of any other error, which gets one without a code or position.
+pub fn diagnose(e: &(dyn Error + 'static)) -> Vec<Diagnostic> {
+    if let Some(e) = e.downcast_ref::<LibraryError>() {
+        let diagnostic = e.diagnostic();
+        vec![match &e.kind {
+            LibraryErrorKind::Parse(kind) => labeled(diagnostic, kind),
+            LibraryErrorKind::Io { .. } => diagnostic,
+        }]
+    } else if let Some(e) = e.downcast_ref::<ErrorWithSpan<ParseError>>() {
+        vec![labeled(Diagnostic::from_span_error(e), &e.kind)]
+    } else if let Some(e) = e.downcast_ref::<ArmorError>() {
+        vec![e.diagnostic()]
+    } else {
+        bfrs_errors::diagnose(e)
+    }
+}
+
+/// Points at where the unclosed loops and captures were opened.
+fn labeled(diagnostic: Diagnostic, kind: &ParseError) -> Diagnostic {
+    match kind {
+        ParseError::UnclosedLoop { opened_at } => {
+            diagnostic.with_label(*opened_at, "the loop was opened here")
+        }
+        ParseError::UnclosedCapture { opened_at } => {
+            diagnostic.with_label(*opened_at, "the capture was opened here")
+        }
+        _ => diagnostic,
+    }
+}
+
+/// Reports an error the way `format` says, on stderr.
+pub fn report(e: &(dyn Error + 'static), format: ErrorFormat) {
+    report_with(e, &diagnose(e), format, &PositionConfig::default())
+}
+
+/// Reports an error whose diagnostics are already known, with
+/// the positions counted the way `config` says.
+///
+/// Diagnostics are shown along with the lines they point at when they are
+/// in files that can still be read, and as a message otherwise.
+pub fn report_with(
+    e: &(dyn Error + 'static),
+    diagnostics: &[Diagnostic],
+    format: ErrorFormat,
+    config: &PositionConfig,
+) {
+    match format {
+        ErrorFormat::Human => {
+            let sources: Vec<_> = diagnostics
+                .iter()
+                .map(|diagnostic| {
+                    diagnostic.position?;
+                    std::fs::read(diagnostic.file.as_ref()?).ok()
+                })
+                .collect();
+            if sources.iter().all(Option::is_none) {
+                match e.downcast_ref::<Diagnostics>() {
+                    Some(diagnostics) => eprintln!("{}", diagnostics),
+                    None => eprintln!("Error: {}", e),
+                }
+                return;
+            }
+            for (diagnostic, source) in diagnostics.iter().zip(sources) {
+                match source {
+                    Some(source) => eprintln!("{}", diagnostic.render(&source, config)),
+                    None => eprintln!("{}: {}\n", diagnostic.severity, diagnostic),
+                }
+            }
+        }
+        ErrorFormat::Json => {
+            for diagnostic in diagnostics {
+                eprintln!("{}", diagnostic.to_json());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern::parse_pattern;
+    use bfrs_common::Position;
+
+    fn diagnose_pattern(src: &str) -> Diagnostic {
+        let e = parse_pattern(src).unwrap_err();
+        let mut diagnostics = diagnose(&e);
+        assert_eq!(diagnostics.len(), 1);
+        diagnostics.remove(0)
+    }
+
+    #[test]
+    fn pattern_errors_have_their_codes() {
+        assert_eq!(diagnose_pattern("+]").code, Some("BF0202"));
+        assert_eq!(diagnose_pattern("+ ~").code, Some("BF0201"));
+        // errors of bfrs_common keep theirs
+        let e = bfrs_common::parser::parse_str("]").unwrap_err();
+        assert_eq!(diagnose(&e)[0].code, Some("BF0001"));
+    }
+
+    #[test]
+    fn unclosed_loops_point_at_their_opening() {
+        let diagnostic = diagnose_pattern("+\n[-");
+        assert_eq!(diagnostic.code, Some("BF0203"));
+        assert_eq!(diagnostic.labels.len(), 1);
+        assert_eq!(
+            diagnostic.labels[0].position,
+            Position { line: 2, column: 1 }
+        );
+    }
+
+    #[test]
+    fn pattern_errors_cover_what_they_are_about() {
+        let diagnostic = diagnose_pattern("+ ~");
+        assert_eq!(diagnostic.position, Some(Position { line: 1, column: 3 }));
+        assert_eq!(diagnostic.end, Some(Position { line: 1, column: 4 }));
+    }
+}
@@ -0,0 +1,83 @@
+//! Inputs for fuzzers, behind the `arbitrary` feature.
+//!
+//! Random bytes are rarely a pattern, so [`PatternSource`] writes
+//! patterns out of the tokens of their syntax instead, which mostly
+//! parse and so get to exercise the matcher.
+
+use arbitrary::{Arbitrary, Result, Unstructured};
+
+/// The tokens patterns are made of, other than the groups,
+/// which are kept balanced on their own.
+const TOKENS: &[&str] = &[
+    "+",
+    "-",
+    ">",
+    "<",
+    ".",
+    ",",
+    "x",
+    "y",
+    "z",
+    "x!",
+    "y!>",
+    "z!<",
+    "x!>=2",
+    "y!-1",
+    "+*n",
+    "-*m",
+    ">*n",
+    "@add",
+    "@add(>=10)",
+    "@add(<0)",
+    "@move",
+    "@move(>0)",
+    "@move(2)",
+    "@zero",
+    "@loop",
+    "%arith",
+    "%move",
+    "%io",
+    "_",
+    "_*",
+    "_{,5}",
+    "_{0,3}",
+];
+
+/// The source of a pattern, made of valid tokens, with its loops
+/// and capture groups balanced and every capture named differently.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PatternSource(pub String);
+
+impl<'a> Arbitrary<'a> for PatternSource {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let mut src = String::new();
+        // the closing character of every open group.
+        let mut open = Vec::new();
+        let mut captures = 0;
+        while !u.is_empty() {
+            match u.int_in_range(0..=3)? {
+                0 => {
+                    open.push(']');
+                    src.push('[');
+                }
+                1 => {
+                    open.push(')');
+                    src.push_str(&format!("(c{}: ", captures));
+                    captures += 1;
+                }
+                2 => {
+                    if let Some(close) = open.pop() {
+                        src.push(close);
+                    }
+                }
+                _ => {
+                    // a space keeps a run from taking a group after it as its bound.
+                    src.push_str(u.choose(TOKENS)?);
+                    src.push(' ');
+                }
+            }
+        }
+        src.extend(open.into_iter().rev());
+        Ok(Self(src))
+    }
+}
@@ -0,0 +1,293 @@
+//! Approximate matching, to find the variants of a pattern that were
+//! slightly massaged, like an idiom with a stray instruction in it or
+//! one of its own missing, which exact matching misses.
+//!
+//! A pattern is matched by its shape: the instructions every part of it
+//! can take, in order. Single instructions and classes take one of theirs,
+//! and runs, repetitions, bindings and wildcards any number of theirs, but
+//! the values they capture don't have to agree, so `x[-y+x]` is matched as
+//! a `[` and a `-` between any moves, and so on. The distance of a match is
+//! the fewest instructions to insert, delete or substitute in it to make it
+//! take that shape, found with the dynamic programming of approximate
+//! string matching, where a match can start anywhere.
+
+use crate::library::PatternLibrary;
+use crate::pattern::Pattern;
+use bfrs_common::BFCommand;
+use std::ops::Range;
+
+/// A part of the pattern between others where an instruction can be.
+#[derive(Debug, Clone, Copy)]
+struct Item {
+    /// the instructions it takes, as bits of [`bit`]
+    takes: u8,
+    /// whether it takes any number of them, or a single one
+    many: bool,
+}
+
+/// A match within a distance of a pattern of a library.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FuzzyMatch {
+    /// index of the pattern in the library entries
+    pub pattern: usize,
+    /// the instructions matched, out of the ones searched
+    pub range: Range<usize>,
+    /// the fewest edits that make them match the pattern exactly
+    pub distance: usize,
+}
+
+/// The matches of every pattern of `library` in `instructions` at most
+/// `max_distance` edits away from it, the closest first, and then in
+/// order. The matches of a pattern don't overlap, closer ones winning.
+/// ```
+/// use bfrs_common::parser::parse_str;
+/// use bfrs_patterns::fuzzy::find_all;
+/// use bfrs_patterns::library::parse_library;
+///
+/// let library = parse_library("copy = x[-y+z+x]").unwrap();
+/// // the copy loop is missing a `+`.
+/// let instructions = parse_str("+++[->+>>+<<<]>[->+<<]").unwrap();
+/// let found = find_all(&instructions, &library, 1);
+/// assert_eq!(found[0].range, 3..14);
+/// assert_eq!(found[0].distance, 0);
+/// assert_eq!(found[1].range, 15..22);
+/// assert_eq!(found[1].distance, 1);
+/// ```
+pub fn find_all(
+    instructions: &[BFCommand],
+    library: &PatternLibrary,
+    max_distance: usize,
+) -> Vec<FuzzyMatch> {
+    let _span = tracing::debug_span!("fuzzy", max_distance).entered();
+    let mut found = Vec::new();
+    for (pattern, entry) in library.entries.iter().enumerate() {
+        let mut items = Vec::new();
+        shape(&entry.scope.patterns, &mut items);
+        found.extend(closest(instructions, &items, max_distance).into_iter().map(
+            |(range, distance)| FuzzyMatch {
+                pattern,
+                range,
+                distance,
+            },
+        ));
+    }
+    found.sort_by_key(|found| (found.distance, found.range.start, found.pattern));
+    found
+}
+
+/// The instructions `instruction` is, as a bit.
+fn bit(instruction: BFCommand) -> u8 {
+    1 << match instruction {
+        BFCommand::BeginLoop => 0,
+        BFCommand::EndLoop => 1,
+        BFCommand::Print => 2,
+        BFCommand::Read => 3,
+        BFCommand::Increment => 4,
+        BFCommand::Decrement => 5,
+        BFCommand::Right => 6,
+        BFCommand::Left => 7,
+    }
+}
+
+/// Adds the items the instructions of `patterns` can be in.
+fn shape(patterns: &[Pattern], items: &mut Vec<Item>) {
+    let any = u8::MAX;
+    let moves = bit(BFCommand::Right) | bit(BFCommand::Left);
+    let adds = bit(BFCommand::Increment) | bit(BFCommand::Decrement);
+    let one = |takes| Item { takes, many: false };
+    let many = |takes| Item { takes, many: true };
+    for pattern in patterns {
+        match pattern {
+            Pattern::Instruction(instruction) => items.push(one(bit(*instruction))),
+            Pattern::Binding { movement, .. } => {
+                if !movement.allows(0) {
+                    items.push(one(moves));
+                }
+                items.push(many(moves));
+            }
+            Pattern::Loop(body) => {
+                items.push(one(bit(BFCommand::BeginLoop)));
+                shape(body, items);
+                items.push(one(bit(BFCommand::EndLoop)));
+            }
+            Pattern::AnyLoop => items.extend([
+                one(bit(BFCommand::BeginLoop)),
+                many(any),
+                one(bit(BFCommand::EndLoop)),
+            ]),
+            Pattern::Run { kind, .. } => {
+                let takes = match kind {
+                    crate::pattern::RunKind::Add => adds,
+                    crate::pattern::RunKind::Move => moves,
+                };
+                items.extend([one(takes), many(takes)]);
+            }
+            Pattern::Zero => items.extend([
+                one(bit(BFCommand::BeginLoop)),
+                one(adds),
+                one(bit(BFCommand::EndLoop)),
+            ]),
+            Pattern::Class(class) => {
+                let [first, second] = class.commands();
+                items.push(one(bit(first) | bit(second)));
+            }
+            Pattern::AnyInstruction => items.push(one(any
+                & !bit(BFCommand::BeginLoop)
+                & !bit(BFCommand::EndLoop))),
+            Pattern::Wildcard { length, .. } => {
+                // a missing minimum is 1.
+                if length.min.is_none_or(|min| min > 0) {
+                    items.push(one(any));
+                }
+                items.push(many(any));
+            }
+            // what it has to repeat is only known once matching.
+            Pattern::Backreference(_) => items.push(many(any)),
+            Pattern::Repeat { instruction, .. } => {
+                items.extend([one(bit(*instruction)), many(bit(*instruction))])
+            }
+            Pattern::Capture { body, .. } => shape(body, items),
+        }
+    }
+}
+
+/// The cost of the cheapest way to have gone through some items, and
+/// where it started, later starts winning ties, for shorter matches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct Cost {
+    edits: usize,
+    /// the start, negated so the smaller is the later
+    from: std::cmp::Reverse<usize>,
+}
+
+impl Cost {
+    /// more than any way there is, even after adding to it.
+    const UNREACHED: Self = Self {
+        edits: usize::MAX / 2,
+        from: std::cmp::Reverse(0),
+    };
+
+    fn plus(self, edits: usize) -> Self {
+        Self {
+            edits: self.edits + edits,
+            ..self
+        }
+    }
+}
+
+/// The ranges of `instructions` taking the shape of `items` with at most
+/// `max_distance` edits, which don't overlap, along with their distance.
+fn closest(
+    instructions: &[BFCommand],
+    items: &[Item],
+    max_distance: usize,
+) -> Vec<(Range<usize>, usize)> {
+    // the cheapest way to have gone through the first `i` items, before
+    // the instruction the search is at, where only none can be free.
+    let start = |at| {
+        let mut costs = vec![Cost::UNREACHED; items.len() + 1];
+        costs[0] = Cost {
+            edits: 0,
+            from: std::cmp::Reverse(at),
+        };
+        costs
+    };
+    let mut costs = start(0);
+    settle(&mut costs, items);
+    let mut candidates = Vec::new();
+    for (at, &instruction) in instructions.iter().enumerate() {
+        let bit = bit(instruction);
+        let mut next = start(at + 1);
+        for (i, item) in items.iter().enumerate() {
+            let taken = item.takes & bit != 0;
+            // taking it as the item, or as a substitute for it.
+            if !item.many {
+                next[i + 1] = next[i + 1].min(costs[i].plus(usize::from(!taken)));
+            }
+            // taking it as one more of the item, or inserting it before.
+            next[i] = next[i].min(costs[i].plus(usize::from(!(item.many && taken))));
+        }
+        settle(&mut next, items);
+        costs = next;
+        let end = costs[items.len()];
+        let from = end.from.0;
+        if end.edits <= max_distance && from <= at {
+            candidates.push((from..at + 1, end.edits));
+        }
+    }
+    // the closest first, and then the first, keeping the ones that fit.
+    candidates.sort_by_key(|(range, edits)| (*edits, range.start, range.end));
+    let mut kept: Vec<(Range<usize>, usize)> = Vec::new();
+    for (range, edits) in candidates {
+        let overlaps = kept
+            .iter()
+            .any(|(other, _)| range.start < other.end && other.start < range.end);
+        if !overlaps {
+            kept.push((range, edits));
+        }
+    }
+    kept.sort_by_key(|(range, _)| range.start);
+    kept
+}
+
+/// Goes past the items that can be skipped: any number of an item can be
+/// none, and a single one can be deleted.
+fn settle(costs: &mut [Cost], items: &[Item]) {
+    for (i, item) in items.iter().enumerate() {
+        costs[i + 1] = costs[i + 1].min(costs[i].plus(usize::from(!item.many)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::parse_library;
+    use bfrs_common::parser::parse_str;
+
+    /// The matches of `pattern` in `source` at most `max_distance` away,
+    /// in order, with where they start and end.
+    fn found(pattern: &str, source: &str, max_distance: usize) -> Vec<(usize, usize, usize)> {
+        let library = parse_library(&format!("p = {}", pattern)).unwrap();
+        let instructions = parse_str(source).unwrap();
+        let mut found: Vec<_> = find_all(&instructions, &library, max_distance)
+            .into_iter()
+            .map(|found| (found.range.start, found.range.end, found.distance))
+            .collect();
+        found.sort_unstable();
+        found
+    }
+
+    #[test]
+    fn exact_matches_are_0_away() {
+        assert_eq!(found("[-]", "+[-]>", 0), [(1, 4, 0)]);
+        assert_eq!(found("x[-y+x]", "[->>+<<]", 0), [(0, 8, 0)]);
+    }
+
+    #[test]
+    fn every_edit_counts_once() {
+        // substituted, inserted and deleted.
+        assert_eq!(found("[-]", "[+]", 1), [(0, 3, 1)]);
+        assert_eq!(found("x[-y+x]", "[->.+<]", 1), [(0, 7, 1)]);
+        assert_eq!(found("x[-y+x]", "[->]", 1), [(0, 4, 1)]);
+        assert_eq!(found("x[-y+x]", "[>]", 2), [(0, 3, 2)]);
+    }
+
+    #[test]
+    fn matches_further_away_are_left_out() {
+        assert_eq!(found("x[-y+z+x]", "[->+<<]", 0), []);
+        assert_eq!(found("x[-y+z+x]", "[->+<<]", 1), [(0, 7, 1)]);
+        assert_eq!(found("[-]", "....", 1), []);
+    }
+
+    #[test]
+    fn closer_matches_win_overlaps() {
+        let found = find_all(
+            &parse_str("[-][->+<]").unwrap(),
+            &parse_library("clear = [-]").unwrap(),
+            1,
+        );
+        assert_eq!(found[0].range, 0..3);
+        assert_eq!(found[0].distance, 0);
+        assert!(found[1..].iter().all(|other| other.range.start >= 3));
+    }
+}
@@ -0,0 +1,296 @@
+//! Run-length encoded instructions.
+//!
+//! Runs of `+-` and `<>` are folded into a single operation with their
+//! net amount, and clear loops into [`Op::SetZero`]. Patterns made of runs
+//! (`@add`, `@move`, `@zero`) can be searched over the operations, which only
+//! tries to match at the start of every operation instead of at every
+//! instruction.
+
+use crate::library::PatternLibrary;
+use crate::pattern::PatternScope;
+use crate::r#match::{LibraryMatch, MatchResult, MatchSM};
+use bfrs_common::BFCommand;
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Op {
+    /// net amount of a run of `+` and `-`
+    Add(isize),
+    /// net amount of a run of `>` and `<`
+    Move(isize),
+    /// `[-]` or `[+]`
+    SetZero,
+    BeginLoop,
+    EndLoop,
+    Print,
+    Read,
+}
+
+/// Operations along with the instructions they were folded from.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Ir {
+    pub ops: Vec<Op>,
+    /// the range of instructions every operation comes from
+    pub spans: Vec<Range<usize>>,
+}
+
+impl Ir {
+    pub fn compile(instructions: &[BFCommand]) -> Self {
+        let _span = tracing::debug_span!("compile", instructions = instructions.len()).entered();
+        let mut ir = Self::default();
+        let mut i = 0;
+        while i < instructions.len() {
+            let start = i;
+            let op = match instructions[i] {
+                BFCommand::Increment | BFCommand::Decrement => {
+                    let mut amount = 0;
+                    while let Some(instr) = instructions.get(i) {
+                        match instr {
+                            BFCommand::Increment => amount += 1,
+                            BFCommand::Decrement => amount -= 1,
+                            _ => break,
+                        }
+                        i += 1;
+                    }
+                    Op::Add(amount)
+                }
+                BFCommand::Right | BFCommand::Left => {
+                    let mut amount = 0;
+                    while let Some(instr) = instructions.get(i) {
+                        match instr {
+                            BFCommand::Right => amount += 1,
+                            BFCommand::Left => amount -= 1,
+                            _ => break,
+                        }
+                        i += 1;
+                    }
+                    Op::Move(amount)
+                }
+                BFCommand::BeginLoop if is_clear(&instructions[i..]) => {
+                    i += 3;
+                    Op::SetZero
+                }
+                instr => {
+                    i += 1;
+                    match instr {
+                        BFCommand::BeginLoop => Op::BeginLoop,
+                        BFCommand::EndLoop => Op::EndLoop,
+                        BFCommand::Print => Op::Print,
+                        _ => Op::Read,
+                    }
+                }
+            };
+            ir.ops.push(op);
+            ir.spans.push(start..i);
+        }
+        tracing::debug!(ops = ir.ops.len(), "compiled the program");
+        ir
+    }
+
+    /// The instructions the operations stand for, which aren't the ones
+    /// they were compiled from, but do the same: runs are spelled with
+    /// their net amount, and clear loops as `[-]`.
+    pub fn expand(ops: &[Op]) -> Vec<BFCommand> {
+        let mut instructions = Vec::with_capacity(ops.len());
+        for &op in ops {
+            let (instruction, amount) = match op {
+                Op::Add(amount) if amount < 0 => (BFCommand::Decrement, -amount),
+                Op::Add(amount) => (BFCommand::Increment, amount),
+                Op::Move(amount) if amount < 0 => (BFCommand::Left, -amount),
+                Op::Move(amount) => (BFCommand::Right, amount),
+                Op::SetZero => {
+                    instructions.extend([
+                        BFCommand::BeginLoop,
+                        BFCommand::Decrement,
+                        BFCommand::EndLoop,
+                    ]);
+                    continue;
+                }
+                Op::BeginLoop => (BFCommand::BeginLoop, 1),
+                Op::EndLoop => (BFCommand::EndLoop, 1),
+                Op::Print => (BFCommand::Print, 1),
+                Op::Read => (BFCommand::Read, 1),
+            };
+            instructions.extend(std::iter::repeat_n(instruction, amount.unsigned_abs()));
+        }
+        instructions
+    }
+
+    /// Obtain the same kind of matches as [`MatchSM::find_all`], but only
+    /// starting at the beginning of an operation and skipping whole
+    /// operations when nothing matches. `instructions` have to be the
+    /// ones the operations were compiled from.
+    pub fn find_all<'a>(
+        &self,
+        instructions: &'a [BFCommand],
+        scope: &PatternScope,
+    ) -> Vec<MatchResult<'a>> {
+        let mut machine = MatchSM::new(instructions);
+        self.search(|start| machine.match_at(start, scope).map(|res| (res.skip(), res)))
+    }
+
+    /// Obtain the matches of every pattern in a library, like
+    /// [`MatchSM::find_all_in_library`], the way [`Ir::find_all`] does.
+    pub fn find_all_in_library<'a>(
+        &self,
+        instructions: &'a [BFCommand],
+        library: &PatternLibrary,
+    ) -> Vec<LibraryMatch<'a>> {
+        let mut machine = MatchSM::new(instructions);
+        self.search(|start| {
+            library
+                .entries
+                .iter()
+                .enumerate()
+                .find_map(|(pattern, entry)| {
+                    machine.match_at(start, &entry.scope).map(|res| {
+                        (
+                            res.skip(),
+                            LibraryMatch {
+                                pattern,
+                                result: res,
+                            },
+                        )
+                    })
+                })
+        })
+    }
+
+    /// `match_at` returns how far the search moves on after the match at an
+    /// instruction offset, if any, see [`MatchResult::skip`]
+    fn search<M>(&self, mut match_at: impl FnMut(usize) -> Option<(usize, M)>) -> Vec<M> {
+        let mut op = 0;
+        let mut result = Vec::new();
+        while op < self.ops.len() {
+            let start = self.spans[op].start;
+            match match_at(start) {
+                Some((len, found)) => {
+                    // a pattern may end mid-run, so
+                    // continue at the next operation.
+                    while op < self.ops.len() && self.spans[op].start < start + len {
+                        op += 1;
+                    }
+                    result.push(found);
+                }
+                None => op += 1,
+            }
+        }
+        result
+    }
+}
+
+/// whether the instructions start with a clear loop
+fn is_clear(instructions: &[BFCommand]) -> bool {
+    matches!(
+        instructions,
+        [
+            BFCommand::BeginLoop,
+            BFCommand::Increment | BFCommand::Decrement,
+            BFCommand::EndLoop,
+            ..
+        ]
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern::parse_pattern;
+    use bfrs_common::parser::parse_str;
+
+    fn text(instructions: &[BFCommand]) -> String {
+        instructions.iter().map(|&i| i as u8 as char).collect()
+    }
+
+    #[test]
+    fn runs_fold_into_operations() {
+        let instructions = parse_str("++-+>><<<[+]..[->+<],").unwrap();
+        let ir = Ir::compile(&instructions);
+        assert_eq!(
+            ir.ops,
+            [
+                Op::Add(2),
+                Op::Move(-1),
+                Op::SetZero,
+                Op::Print,
+                Op::Print,
+                Op::BeginLoop,
+                Op::Add(-1),
+                Op::Move(1),
+                Op::Add(1),
+                Op::Move(-1),
+                Op::EndLoop,
+                Op::Read,
+            ]
+        );
+        assert_eq!(ir.spans[..3], [0..4, 4..9, 9..12]);
+        assert_eq!(ir.spans.last(), Some(&(20..21)));
+        // expanding spells runs by their net amount
+        assert_eq!(text(&Ir::expand(&ir.ops)), "++<[-]..[->+<],");
+    }
+
+    #[test]
+    fn matches_start_at_operations() {
+        let instructions = parse_str("+++>>[-]+-+<").unwrap();
+        let ir = Ir::compile(&instructions);
+        let starts = |pattern| -> Vec<(usize, usize)> {
+            let scope = parse_pattern(pattern).unwrap();
+            ir.find_all(&instructions, &scope)
+                .iter()
+                .map(|res| (res.start, res.start + res.commands.len()))
+                .collect()
+        };
+        assert_eq!(starts("@add"), [(0, 3), (8, 11)]);
+        assert_eq!(starts("@move@zero"), [(3, 8)]);
+        // a `+` in the middle of a run isn't where an operation starts
+        assert_eq!(starts("+>"), []);
+        // a match ending mid-run moves on to the next operation
+        assert_eq!(starts("+"), [(0, 1), (8, 9)]);
+    }
+
+    #[test]
+    fn operations_round_trip_through_serde() {
+        let ir = Ir::compile(&parse_str("++[-]>>[<]").unwrap());
+        let json = serde_json::to_string(&ir).unwrap();
+        let back: Ir = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.ops, ir.ops);
+        assert_eq!(back.spans, ir.spans);
+    }
+
+    /// A writer of logs everyone can look at, like stderr.
+    #[derive(Clone, Default)]
+    struct Logs(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Logs {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn compiling_is_logged() {
+        let logs = Logs::default();
+        let writer = logs.clone();
+        let logger = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::DEBUG)
+            .with_target(false)
+            .with_ansi(false)
+            .json()
+            .with_writer(move || writer.clone())
+            .finish();
+        tracing::subscriber::with_default(logger, || {
+            Ir::compile(&parse_str("+++[-]").unwrap());
+        });
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        let event: serde_json::Value = serde_json::from_str(logs.trim()).unwrap();
+        assert_eq!(event["fields"]["message"], "compiled the program");
+        assert_eq!(event["fields"]["ops"], 2);
+        assert_eq!(event["span"]["instructions"], 6);
+    }
+}
@@ -0,0 +1,341 @@
+//! Where a program keeps what, without running it: the values every cell
+//! can hold and the instructions touching it, which is what
+//! `bf analyze --layout` shows.
+//!
+//! The values are ranges of bytes, which every cell starts in as 0. Adding
+//! to a range shifts it, unless it wraps around, which makes it any byte,
+//! and reading makes a cell any byte too. Loops multiplying a cell into
+//! others add the range of the product, and the rest run until the ranges
+//! they leave stop growing, leaving their cell at 0.
+//!
+//! Cells are numbered from the one the program starts at, which can only
+//! be done until a loop moves the pointer by an amount that can't be told
+//! without running it, like `[>]`, where the analysis stops.
+
+use crate::decompile;
+use crate::ir::Ir;
+use bfrs_common::program::Program;
+use bfrs_common::BFCommand;
+use std::collections::BTreeMap;
+
+/// The times the body of a loop is gone through before giving up on the
+/// ranges it changes growing to a fixed point, and making them any byte.
+const WIDEN_AFTER: usize = 3;
+
+/// The bytes from `lo` to `hi`, both included.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Range {
+    pub lo: u8,
+    pub hi: u8,
+}
+
+impl Range {
+    pub const ZERO: Self = Self::exactly(0);
+    pub const ANY: Self = Self { lo: 0, hi: 255 };
+
+    pub const fn exactly(value: u8) -> Self {
+        Self {
+            lo: value,
+            hi: value,
+        }
+    }
+
+    /// The bytes in either range, and the ones between them.
+    pub fn join(self, other: Self) -> Self {
+        Self {
+            lo: self.lo.min(other.lo),
+            hi: self.hi.max(other.hi),
+        }
+    }
+
+    /// The range from `lo` to `hi`, or any byte if they don't fit one.
+    fn wrapping(lo: i64, hi: i64) -> Self {
+        if lo < 0 || hi > 255 {
+            Self::ANY
+        } else {
+            Self {
+                lo: lo as u8,
+                hi: hi as u8,
+            }
+        }
+    }
+
+    fn add(self, amount: i64) -> Self {
+        match Self::wrapping(i64::from(self.lo) + amount, i64::from(self.hi) + amount) {
+            // a single value wraps around to a single value.
+            Self::ANY if self.lo == self.hi => {
+                Self::exactly((i64::from(self.lo) + amount).rem_euclid(256) as u8)
+            }
+            range => range,
+        }
+    }
+}
+
+impl std::fmt::Display for Range {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            Self::ANY => write!(f, "any byte"),
+            Self { lo, hi } if lo == hi => write!(f, "{}", lo),
+            Self { lo, hi } => write!(f, "{}..={}", lo, hi),
+        }
+    }
+}
+
+/// What the program does with a cell.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CellLayout {
+    /// every value it can hold while the program runs
+    pub range: Range,
+    /// the index of every instruction touching it, in order
+    pub touched: Vec<usize>,
+}
+
+/// What the program does with every cell, by its offset from the one it
+/// starts at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Layout {
+    pub cells: BTreeMap<isize, CellLayout>,
+    /// the index of the loop the analysis stopped at, if it did
+    pub lost_at: Option<usize>,
+}
+
+/// The layout of a program, until a loop moves the pointer by an amount
+/// that can't be told.
+pub fn analyze(instructions: &[BFCommand]) -> Layout {
+    let program = Program::new(instructions.to_vec());
+    let mut analysis = Analysis {
+        program: &program,
+        seen: BTreeMap::new(),
+    };
+    let mut state = State::default();
+    let lost_at = analysis.block(0, instructions.len(), &mut state).err();
+
+    let mut cells: BTreeMap<isize, CellLayout> = BTreeMap::new();
+    let mut pointer = 0;
+    for (i, &instruction) in instructions[..lost_at.unwrap_or(instructions.len())]
+        .iter()
+        .enumerate()
+    {
+        match instruction {
+            BFCommand::Right => pointer += 1,
+            BFCommand::Left => pointer -= 1,
+            _ => cells
+                .entry(pointer)
+                .or_insert_with(|| CellLayout {
+                    range: analysis.seen.get(&pointer).copied().unwrap_or(Range::ZERO),
+                    touched: Vec::new(),
+                })
+                .touched
+                .push(i),
+        }
+    }
+    Layout { cells, lost_at }
+}
+
+/// The ranges of the cells at some point, by their offset, and where the
+/// pointer is. The cells missing are still 0.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct State {
+    cells: BTreeMap<isize, Range>,
+    pointer: isize,
+}
+
+impl State {
+    fn get(&self, offset: isize) -> Range {
+        self.cells.get(&offset).copied().unwrap_or(Range::ZERO)
+    }
+
+    /// The state if the program could be in either one, which
+    /// have the pointer at the same cell.
+    fn join(&self, other: &Self) -> Self {
+        let mut joined = self.clone();
+        for (&offset, &range) in other.cells.iter() {
+            joined.cells.insert(offset, self.get(offset).join(range));
+        }
+        for (&offset, range) in self.cells.iter() {
+            if !other.cells.contains_key(&offset) {
+                joined.cells.insert(offset, range.join(Range::ZERO));
+            }
+        }
+        joined
+    }
+}
+
+struct Analysis<'a> {
+    program: &'a Program,
+    /// every value every cell held, by its offset
+    seen: BTreeMap<isize, Range>,
+}
+
+impl Analysis<'_> {
+    fn set(&mut self, state: &mut State, offset: isize, range: Range) {
+        state.cells.insert(offset, range);
+        self.saw(offset, range);
+    }
+
+    fn saw(&mut self, offset: isize, range: Range) {
+        let seen = self.seen.entry(offset).or_insert(Range::ZERO);
+        *seen = seen.join(range);
+    }
+
+    /// Runs the instructions from `start` until `end` on `state`, failing
+    /// with the index of the loop the pointer can't be told after.
+    fn block(&mut self, start: usize, end: usize, state: &mut State) -> Result<(), usize> {
+        let mut i = start;
+        while i < end {
+            let pointer = state.pointer;
+            match self.program.instructions[i] {
+                BFCommand::Increment => self.set(state, pointer, state.get(pointer).add(1)),
+                BFCommand::Decrement => self.set(state, pointer, state.get(pointer).add(-1)),
+                BFCommand::Right => state.pointer += 1,
+                BFCommand::Left => state.pointer -= 1,
+                BFCommand::Read => self.set(state, pointer, Range::ANY),
+                BFCommand::Print => (),
+                BFCommand::BeginLoop => {
+                    let end = self.program.jump(i);
+                    if self.net_move(i + 1, end) != Some(0) {
+                        return Err(i);
+                    }
+                    self.run_loop(i + 1, end, state)?;
+                    i = end;
+                }
+                BFCommand::EndLoop => unreachable!("loops are run whole"),
+            }
+            i += 1;
+        }
+        Ok(())
+    }
+
+    /// Runs the loop with its body from `start` until `end` on `state`.
+    fn run_loop(&mut self, start: usize, end: usize, state: &mut State) -> Result<(), usize> {
+        let pointer = state.pointer;
+        if state.get(pointer) == Range::ZERO {
+            // it never runs.
+            return Ok(());
+        }
+        let body = Ir::compile(&self.program.instructions[start..end]).ops;
+        if let Some(effects) = decompile::multiplication(&body) {
+            let times = state.get(pointer);
+            for (offset, factor) in effects {
+                let factor = factor as i64;
+                let target = state.get(pointer + offset);
+                let (a, b) = (i64::from(times.lo) * factor, i64::from(times.hi) * factor);
+                let range = Range::wrapping(
+                    i64::from(target.lo) + a.min(b),
+                    i64::from(target.hi) + a.max(b),
+                );
+                self.set(state, pointer + offset, range);
+            }
+            self.set(state, pointer, Range::ZERO);
+            return Ok(());
+        }
+
+        let mut head = state.clone();
+        for round in 0.. {
+            // the body only runs while the cell isn't 0.
+            let mut body = head.clone();
+            let current = body.get(pointer);
+            if current.lo == 0 && current.hi > 0 {
+                body.cells.insert(pointer, Range { lo: 1, ..current });
+            }
+            self.block(start, end, &mut body)?;
+            let mut next = head.join(&body);
+            if next == head {
+                break;
+            }
+            if round >= WIDEN_AFTER {
+                for (offset, range) in next.cells.iter_mut() {
+                    if head.get(*offset) != *range {
+                        *range = Range::ANY;
+                    }
+                }
+            }
+            for (&offset, &range) in next.cells.iter() {
+                self.saw(offset, range);
+            }
+            head = next;
+        }
+        *state = head;
+        self.set(state, pointer, Range::ZERO);
+        Ok(())
+    }
+
+    /// How much the instructions from `start` until `end` move the pointer,
+    /// if it can be told without running them.
+    fn net_move(&self, start: usize, end: usize) -> Option<isize> {
+        let mut net = 0;
+        let mut i = start;
+        while i < end {
+            match self.program.instructions[i] {
+                BFCommand::Right => net += 1,
+                BFCommand::Left => net -= 1,
+                BFCommand::BeginLoop => {
+                    if self.net_move(i + 1, self.program.jump(i))? != 0 {
+                        return None;
+                    }
+                    i = self.program.jump(i);
+                }
+                _ => (),
+            }
+            i += 1;
+        }
+        Some(net)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bfrs_common::parser;
+
+    fn layout(src: &str) -> Layout {
+        analyze(&parser::parse_str(src).unwrap())
+    }
+
+    fn cell(lo: u8, hi: u8, touched: &[usize]) -> CellLayout {
+        CellLayout {
+            range: Range { lo, hi },
+            touched: touched.to_vec(),
+        }
+    }
+
+    #[test]
+    fn multiplications_add_their_product() {
+        let layout = layout("+++[->++<]>.");
+        assert_eq!(layout.cells[&0], cell(0, 3, &[0, 1, 2, 3, 4, 9]));
+        assert_eq!(layout.cells[&1], cell(0, 6, &[6, 7, 11]));
+        assert_eq!(layout.lost_at, None);
+    }
+
+    #[test]
+    fn loops_run_until_their_ranges_stop_growing() {
+        // the loop can't run with a 0, and the other one clears its cell
+        let cleared = layout("[>+<]++[-]");
+        assert_eq!(cleared.cells[&0], cell(0, 2, &[0, 4, 5, 6, 7, 8, 9]));
+        assert_eq!(cleared.cells[&1], cell(0, 0, &[2]));
+        // and this one adds 3 to the next cell until it's any byte
+        let widened = layout(",[>+++<--]");
+        assert_eq!(widened.cells[&0].range, Range::ANY);
+        assert_eq!(widened.cells[&1].range, Range::ANY);
+    }
+
+    #[test]
+    fn the_analysis_stops_where_the_pointer_is_lost() {
+        let layout = layout(",[>]+");
+        assert_eq!(layout.lost_at, Some(1));
+        assert_eq!(layout.cells.len(), 1);
+        assert_eq!(layout.cells[&0], cell(0, 255, &[0]));
+    }
+
+    #[test]
+    fn ranges_wrap_around() {
+        assert_eq!(Range::exactly(255).add(1), Range::ZERO);
+        assert_eq!(Range::ZERO.add(-2), Range::exactly(254));
+        assert_eq!(Range { lo: 1, hi: 255 }.add(1), Range::ANY);
+        assert_eq!(
+            Range::exactly(3).join(Range::exactly(7)).to_string(),
+            "3..=7"
+        );
+        assert_eq!(Range::ANY.to_string(), "any byte");
+    }
+}
@@ -1,2 +1,28 @@
+pub mod annotate;
+pub mod armor;
+pub mod automaton;
+pub mod canon;
+pub mod cli;
+pub mod context;
+pub mod corpus;
+pub mod correlate;
+pub mod decompile;
+pub mod errors;
+#[cfg(feature = "arbitrary")]
+pub mod fuzz;
+pub mod fuzzy;
+pub mod ir;
+pub mod layout;
+pub mod library;
 pub mod r#match;
+pub mod output;
+pub mod parallel;
 pub mod pattern;
+pub mod region;
+pub mod rewrite;
+pub mod spec;
+pub mod stream;
+pub mod suggest;
+pub mod tape;
+pub mod theme;
+pub mod unroll;
@@ -0,0 +1,541 @@
+//! Named pattern collections, loaded from library files.
+//!
+//! A library file holds one definition per line, lines starting
+//! with `#` are comments:
+//! ```text
+//! # zero out the current cell
+//! clear = [-]
+//! copy = a[-b+c+a]
+//! ```
+//!
+//! Library files loaded with [`load_library`] can also include the
+//! definitions of other files, relative to the file including them:
+//! ```text
+//! include "base_patterns.bfl"
+//! ```
+//! A file is only loaded once, even if more than one file includes it.
+
+use crate::pattern::{
+    definition_lines, parse_pattern_starting_at, ParseError, ParseResult, PatternScope,
+};
+use bfrs_common::errors as bfrs_errors;
+use bfrs_common::{Location, Position};
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub struct NamedPattern {
+    pub name: String,
+    pub scope: PatternScope,
+}
+
+#[derive(Debug, Default)]
+pub struct PatternLibrary {
+    pub entries: Vec<NamedPattern>,
+}
+
+impl PatternLibrary {
+    pub fn get(&self, name: &str) -> Option<&NamedPattern> {
+        self.entries.iter().find(|entry| entry.name == name)
+    }
+
+    /// Add a pattern to the library, as long as there isn't another
+    /// one with the same name. `name_pos` is where the name of the
+    /// pattern is defined, to point errors at it.
+    pub fn insert(&mut self, entry: NamedPattern, name_pos: Location) -> ParseResult<()> {
+        if self.get(&entry.name).is_some() {
+            let name_end = name_pos.after_str(&entry.name);
+            return Err(bfrs_errors::ErrorWithSpan::new(
+                ParseError::DuplicatePatternName { name: entry.name },
+                name_pos,
+                name_end,
+            ));
+        }
+        self.entries.push(entry);
+        Ok(())
+    }
+}
+
+/// The source of the built-in idioms library.
+pub const IDIOMS: &str = include_str!("idioms.bfl");
+
+/// A library of common idioms: clear, copy, move, multiply,
+/// print string and scan loops.
+pub fn idioms() -> PatternLibrary {
+    parse_library(IDIOMS).expect("the built-in idioms are valid")
+}
+
+/// Parses a library. As it isn't read from a file, it can't include others.
+pub fn parse_library(src: &str) -> ParseResult<PatternLibrary> {
+    let mut library = PatternLibrary::default();
+    parse_library_into(src, &mut library, |_, _, start, end| {
+        Err(bfrs_errors::ErrorWithSpan::new(
+            ParseError::IncludeWithoutFile,
+            start,
+            end,
+        ))
+    })?;
+    Ok(library)
+}
+
+/// Parses the definitions in `src` into `library`, calling `include`
+/// with the library, the path and where every include starts and ends.
+fn parse_library_into<E>(
+    src: &str,
+    library: &mut PatternLibrary,
+    mut include: impl FnMut(&mut PatternLibrary, &str, Location, Location) -> Result<(), E>,
+) -> Result<(), E>
+where
+    E: From<bfrs_errors::ErrorWithSpan<ParseError>>,
+{
+    for (line, line_start) in definition_lines(src) {
+        let trimmed = line.trim();
+        let name_pos = line_start.after_str(&line[..line.len() - line.trim_start().len()]);
+        if let Some(path) = parse_include(trimmed, name_pos)? {
+            include(library, path, name_pos, name_pos.after_str(trimmed))?;
+            continue;
+        }
+        let entry = parse_named_pattern_starting_at(line, line_start)?;
+        library.insert(entry, name_pos)?;
+    }
+    Ok(())
+}
+
+/// The path of an `include "path"` line, if it is one.
+fn parse_include(line: &str, start: Location) -> ParseResult<Option<&str>> {
+    let rest = match line.strip_prefix("include") {
+        Some(rest) if rest.starts_with(char::is_whitespace) => rest.trim(),
+        _ => return Ok(None),
+    };
+    // a pattern can be named `include` too.
+    if rest.starts_with('=') {
+        return Ok(None);
+    }
+    match rest
+        .strip_prefix('"')
+        .and_then(|rest| rest.strip_suffix('"'))
+    {
+        Some(path) if !path.is_empty() => Ok(Some(path)),
+        _ => Err(bfrs_errors::ErrorWithSpan::new(
+            ParseError::BadInclude,
+            start,
+            start.after_str(line),
+        )),
+    }
+}
+
+/// Loads a library file, along with the files it includes.
+pub fn load_library(path: impl AsRef<Path>) -> Result<PatternLibrary, LibraryError> {
+    let mut library = PatternLibrary::default();
+    let mut loader = Loader::default();
+    loader.load(path.as_ref(), &mut library)?;
+    Ok(library)
+}
+
+#[derive(Default)]
+struct Loader {
+    /// the files being loaded, outermost first: their path, their canonical
+    /// path and where they include the next one
+    stack: Vec<(PathBuf, PathBuf, Position)>,
+    /// canonical paths of the files already loaded
+    loaded: Vec<PathBuf>,
+}
+
+impl Loader {
+    fn load(&mut self, path: &Path, library: &mut PatternLibrary) -> Result<(), LibraryError> {
+        let read = std::fs::canonicalize(path)
+            .and_then(|canonical| Ok((std::fs::read_to_string(path)?, canonical)));
+        let (src, canonical) = match read {
+            Ok(read) => read,
+            Err(error) => {
+                return Err(self.error(LibraryErrorKind::Io {
+                    path: path.to_path_buf(),
+                    error,
+                }))
+            }
+        };
+        if self.loaded.contains(&canonical) {
+            return Ok(());
+        }
+        self.loaded.push(canonical.clone());
+        self.stack
+            .push((path.to_path_buf(), canonical, Position::default()));
+        let dir = path.parent().unwrap_or_else(|| Path::new("")).to_path_buf();
+        parse_library_into(&src, library, |library, included, start, end| {
+            self.stack.last_mut().unwrap().2 = start.position;
+            let included = dir.join(included);
+            let is_open = std::fs::canonicalize(&included)
+                .is_ok_and(|canonical| self.stack.iter().any(|(_, open, _)| *open == canonical));
+            if is_open {
+                let cycle = self.error(LibraryErrorKind::Parse(ParseError::IncludeCycle {
+                    path: included.display().to_string(),
+                }));
+                return Err(LibraryError {
+                    span: Some((start.position, end.position)),
+                    ..cycle
+                });
+            }
+            self.load(&included, library)
+        })
+        .map_err(|mut e| {
+            // errors in this file don't know which file they're in yet.
+            if e.file.is_none() {
+                let located = self.error(e.kind);
+                e = LibraryError {
+                    span: e.span,
+                    ..located
+                };
+            }
+            e
+        })?;
+        self.stack.pop();
+        Ok(())
+    }
+
+    /// An error in the innermost file, at the position of its last include.
+    fn error(&self, kind: LibraryErrorKind) -> LibraryError {
+        let mut included_from: Vec<_> = self
+            .stack
+            .iter()
+            .map(|(path, _, position)| (path.clone(), *position))
+            .collect();
+        let (file, span) = match included_from.pop() {
+            Some((file, position)) => (Some(file), Some((position, position))),
+            None => (None, None),
+        };
+        included_from.reverse();
+        LibraryError {
+            file,
+            span,
+            kind,
+            included_from: included_from.into(),
+        }
+    }
+}
+
+/// An error found while loading a library file, along with
+/// the chain of includes that led to it.
+#[derive(Debug)]
+pub struct LibraryError {
+    /// the file the error is in, none when the library file itself can't be read
+    pub file: Option<PathBuf>,
+    /// where the part of the file the error is about starts, and where
+    /// it ends, right after it
+    pub span: Option<(Position, Position)>,
+    pub kind: LibraryErrorKind,
+    /// the files including `file`, innermost first,
+    /// along with where they include the next one
+    pub included_from: Box<[(PathBuf, Position)]>,
+}
+
+#[derive(Debug)]
+pub enum LibraryErrorKind {
+    Io { path: PathBuf, error: io::Error },
+    Parse(ParseError),
+}
+
+impl fmt::Display for LibraryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}:", file.display())?;
+            if let Some((position, _)) = self.span {
+                write!(f, "{}:", position)?;
+            }
+            write!(f, " ")?;
+        }
+        match &self.kind {
+            LibraryErrorKind::Io { path, error } => {
+                write!(f, "could not read {}: {}", path.display(), error)?
+            }
+            LibraryErrorKind::Parse(e) => write!(f, "{}", e)?,
+        }
+        for (file, position) in self.included_from.iter() {
+            write!(f, "\n\tincluded from {}:{}", file.display(), position)?;
+        }
+        Ok(())
+    }
+}
+
+impl bfrs_errors::ErrorCode for LibraryError {
+    fn code(&self) -> &'static str {
+        match &self.kind {
+            LibraryErrorKind::Io { .. } => bfrs_errors::IO_ERROR,
+            LibraryErrorKind::Parse(e) => e.code(),
+        }
+    }
+}
+
+impl LibraryError {
+    /// The error as it's reported, with the includes that led
+    /// to it at the end of the message.
+    pub fn diagnostic(&self) -> bfrs_errors::Diagnostic {
+        use bfrs_errors::ErrorCode;
+        let mut message = match &self.kind {
+            LibraryErrorKind::Io { path, error } => {
+                format!("could not read {}: {}", path.display(), error)
+            }
+            LibraryErrorKind::Parse(e) => e.to_string(),
+        };
+        for (file, position) in self.included_from.iter() {
+            message += &format!("\n\tincluded from {}:{}", file.display(), position);
+        }
+        let mut diagnostic = bfrs_errors::Diagnostic::new(message).with_code(self.code());
+        if let Some(file) = &self.file {
+            diagnostic = diagnostic.in_file(file.display().to_string());
+        }
+        if let Some((position, end)) = self.span {
+            diagnostic = diagnostic.at(position).with_end(end);
+        }
+        diagnostic
+    }
+}
+
+impl From<bfrs_errors::ErrorWithSpan<ParseError>> for LibraryError {
+    fn from(e: bfrs_errors::ErrorWithSpan<ParseError>) -> Self {
+        Self {
+            file: None,
+            span: Some((e.start.position, e.end.position)),
+            kind: LibraryErrorKind::Parse(e.kind),
+            included_from: Box::default(),
+        }
+    }
+}
+
+impl Error for LibraryError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(match &self.kind {
+            LibraryErrorKind::Io { error, .. } => error,
+            LibraryErrorKind::Parse(e) => e,
+        })
+    }
+}
+
+/// Parses a single `name = pattern` definition.
+pub fn parse_named_pattern(src: &str) -> ParseResult<NamedPattern> {
+    parse_named_pattern_starting_at(src, Location::default())
+}
+
+/// Parses a single `name = pattern` definition, setting
+/// the initial location to `start_pos`.
+pub fn parse_named_pattern_starting_at(
+    src: &str,
+    start_pos: Location,
+) -> ParseResult<NamedPattern> {
+    let (name, pattern) = match src.split_once('=') {
+        Some(split) => split,
+        None => {
+            return Err(bfrs_errors::ErrorWithSpan::new(
+                ParseError::MissingAssignment,
+                start_pos,
+                start_pos.after_str(src.trim_end()),
+            ))
+        }
+    };
+    let name_pos = start_pos.after_str(&name[..name.len() - name.trim_start().len()]);
+    let name = name.trim();
+    if !is_identifier(name) {
+        return Err(bfrs_errors::ErrorWithSpan::new(
+            ParseError::BadPatternName {
+                name: name.to_string(),
+            },
+            name_pos,
+            name_pos.after_str(name),
+        ));
+    }
+
+    // the pattern starts right after the `=`.
+    let pattern_pos = start_pos.after_str(&src[..src.len() - pattern.len()]);
+    let scope = parse_pattern_starting_at(pattern, pattern_pos)?;
+    Ok(NamedPattern {
+        name: name.to_string(),
+        scope,
+    })
+}
+
+/// Whether a name can be used for a pattern
+pub fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(ch) if ch.is_alphabetic() || ch == '_')
+        && chars.all(|ch| ch.is_alphanumeric() || ch == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(library: &PatternLibrary) -> Vec<&str> {
+        library
+            .entries
+            .iter()
+            .map(|entry| entry.name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn definitions_in_order() {
+        let library = parse_library("# clears\nclear = [-]\n\n  copy = a[-b+c+a]\n").unwrap();
+        assert_eq!(names(&library), ["clear", "copy"]);
+        assert_eq!(library.get("copy").unwrap().scope.bindings.len(), 3);
+        assert!(library.get("move").is_none());
+    }
+
+    #[test]
+    fn bad_definitions_are_errors() {
+        let error = |src| parse_library(src).expect_err(src);
+        assert!(matches!(
+            error("clear [-]").kind,
+            ParseError::MissingAssignment
+        ));
+        assert!(matches!(
+            error("2clear = [-]").kind,
+            ParseError::BadPatternName { name } if name == "2clear"
+        ));
+        let duplicate = error("clear = [-]\nclear = [+]");
+        assert!(matches!(
+            duplicate.kind,
+            ParseError::DuplicatePatternName { name } if name == "clear"
+        ));
+        assert_eq!(duplicate.start.position, Position { line: 2, column: 1 });
+    }
+
+    #[test]
+    fn labeled_patterns() {
+        let mut library = PatternLibrary::default();
+        let clear = parse_named_pattern(" clear = [-]").unwrap();
+        assert_eq!(clear.name, "clear");
+        library.insert(clear, Location::default()).unwrap();
+        let again = parse_named_pattern("clear=[+]").unwrap();
+        let duplicate = library.insert(again, Location::default()).unwrap_err();
+        assert!(matches!(
+            duplicate.kind,
+            ParseError::DuplicatePatternName { name } if name == "clear"
+        ));
+        assert_eq!(duplicate.end.byte_offset, 5);
+        assert_eq!(names(&library), ["clear"]);
+        // the pattern is located after the `=`
+        let error = parse_named_pattern("loop = [-").unwrap_err();
+        assert!(matches!(
+            error.kind,
+            ParseError::UnclosedLoop { opened_at } if opened_at.column == 8
+        ));
+    }
+
+    #[test]
+    fn idioms_match_their_code() {
+        let library = idioms();
+        assert_eq!(
+            names(&library),
+            ["clear", "copy", "move", "multiply", "print_string", "scan"]
+        );
+        let idiom = |src: &str| {
+            let instructions = bfrs_common::parser::parse_str(src).unwrap();
+            let found = crate::r#match::MatchSM::find_all_in_library(&instructions, &library);
+            assert_eq!(found.len(), 1, "{}", src);
+            assert_eq!(
+                found[0].result.commands.len(),
+                instructions.len(),
+                "{}",
+                src
+            );
+            library.entries[found[0].pattern].name.as_str()
+        };
+        assert_eq!(idiom("[+]"), "clear");
+        assert_eq!(idiom("[->+>+<<]"), "copy");
+        assert_eq!(idiom("[-<<+>>]"), "move");
+        assert_eq!(idiom("[->+++<]"), "multiply");
+        assert_eq!(idiom("[.>]"), "print_string");
+        assert_eq!(idiom("[<<<]"), "scan");
+    }
+
+    /// A directory of library files for a test, by name and contents.
+    fn library_dir(test: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("bfrs-patterns-{}-{}", test, std::process::id()));
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        for (name, src) in files {
+            std::fs::write(dir.join(name), src).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn includes_are_loaded_once() {
+        let dir = library_dir(
+            "once",
+            &[
+                (
+                    "main.bfl",
+                    "include \"sub/moves.bfl\"\nclear = [-]\ninclude \"base.bfl\"",
+                ),
+                ("sub/moves.bfl", "include \"../base.bfl\"\nmove = x[-y+x]"),
+                ("base.bfl", "print = ."),
+            ],
+        );
+        let library = load_library(dir.join("main.bfl")).unwrap();
+        assert_eq!(names(&library), ["print", "move", "clear"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn include_errors_say_where_they_come_from() {
+        let dir = library_dir(
+            "errors",
+            &[
+                ("main.bfl", "clear = [-]\n  include \"sub/a.bfl\""),
+                ("sub/a.bfl", "a = +\ninclude \"b.bfl\""),
+                ("sub/b.bfl", "include \"a.bfl\""),
+                ("bad.bfl", "\ninclude \"sub/c.bfl\""),
+                ("sub/c.bfl", "c = [+"),
+            ],
+        );
+        let cycle = load_library(dir.join("main.bfl")).unwrap_err();
+        assert!(matches!(
+            &cycle.kind,
+            LibraryErrorKind::Parse(ParseError::IncludeCycle { .. })
+        ));
+        assert_eq!(cycle.file, Some(dir.join("sub/b.bfl")));
+        assert_eq!(cycle.span.unwrap().0, Position { line: 1, column: 1 });
+        assert_eq!(
+            cycle.included_from[..],
+            [
+                (dir.join("sub/a.bfl"), Position { line: 2, column: 1 }),
+                (dir.join("main.bfl"), Position { line: 2, column: 3 }),
+            ]
+        );
+
+        let unclosed = load_library(dir.join("bad.bfl")).unwrap_err();
+        assert_eq!(unclosed.file, Some(dir.join("sub/c.bfl")));
+        assert_eq!(
+            unclosed.to_string(),
+            format!(
+                "{}:1:7: {}\n\tincluded from {}:2:1",
+                dir.join("sub/c.bfl").display(),
+                ParseError::UnclosedLoop {
+                    opened_at: Position { line: 1, column: 5 }
+                },
+                dir.join("bad.bfl").display()
+            )
+        );
+
+        let missing = load_library(dir.join("none.bfl")).unwrap_err();
+        assert!(matches!(missing.kind, LibraryErrorKind::Io { .. }));
+        assert!(missing.file.is_none());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn parsed_libraries_include_nothing() {
+        assert!(matches!(
+            parse_library("include \"a.bfl\"").unwrap_err().kind,
+            ParseError::IncludeWithoutFile
+        ));
+        assert!(matches!(
+            parse_library("include a.bfl").unwrap_err().kind,
+            ParseError::BadInclude
+        ));
+        // unless it's a pattern named `include`
+        assert_eq!(names(&parse_library("include = +").unwrap()), ["include"]);
+    }
+}
@@ -1,38 +1,235 @@
+use crate::library::PatternLibrary;
 use crate::pattern::PatternScope;
+use crate::rewrite::Rule;
 
-use super::pattern::Pattern;
-use bfrs_common::BFCommand;
-use std::collections::HashMap;
+use super::pattern::{Bound, CommandClass, Movement, Pattern, RunKind};
+use bfrs_common::{BFCommand, Location};
+use std::collections::BTreeMap;
+use std::ops::Range;
 
 /// A match result contains the group of instructions
 /// from the source that matched, along with the bindings
 /// and their relative offsets.
+///
+/// Bindings, counts and captures are indexed like in
+/// the scope of the pattern that matched.
 pub struct MatchResult<'a> {
     pub commands: &'a [BFCommand],
-    pub relative_offsets: HashMap<usize, HashMap<usize, isize>>,
+    /// offset of the first matched instruction in the
+    /// searched instructions, zero for [`MatchSM::match_single`].
+    pub start: usize,
+    /// the cell of every binding, relative to the
+    /// pointer at the start of the match
+    pub cells: Vec<isize>,
+    /// the captured instruction counts
+    pub counts: Vec<usize>,
+    /// the range of `commands` matched by every capture group
+    pub captures: Vec<Range<usize>>,
+    /// where the pointer is left after the match, relative
+    /// to the pointer at the start of the match
+    pub end_pointer: isize,
 }
+
+impl<'a> MatchResult<'a> {
+    /// The offset from the cell of the binding `other`
+    /// to the cell of the binding `key`.
+    #[inline]
+    pub fn relative_offset(&self, key: usize, other: usize) -> isize {
+        self.cells[key] - self.cells[other]
+    }
+
+    /// The instructions matched by a capture group
+    pub fn capture(&self, index: usize) -> Option<&'a [BFCommand]> {
+        let commands = self.commands;
+        self.captures
+            .get(index)
+            .map(|range| &commands[range.clone()])
+    }
+
+    /// Obtain where the match starts and ends in the source, given the location
+    /// of every searched instruction, such as the ones from [`parser::parse_located`].
+    /// The end is the location right after the last matched instruction,
+    /// and a match of no instructions starts and ends where it is.
+    ///
+    /// [`parser::parse_located`]: bfrs_common::parser::parse_located
+    pub fn locate(&self, locations: &[Location]) -> (Location, Location) {
+        let start = locations[self.start];
+        let end = match self.commands.last() {
+            Some(_) => locations[self.start + self.commands.len() - 1].after_command(),
+            None => start,
+        };
+        (start, end)
+    }
+
+    /// How far a search moves on after this match, which is past it, or
+    /// to the next instruction if it matched none so it isn't found again.
+    #[inline]
+    pub fn skip(&self) -> usize {
+        self.commands.len().max(1)
+    }
+
+    /// Resolve the names of the bindings, counts and captures through
+    /// the scope of the pattern that matched.
+    pub fn named<'s>(&self, scope: &'s PatternScope) -> NamedMatchResult<'a, 's> {
+        let binding = |index| scope.bindings.get_by_left(&index).unwrap().as_str();
+        NamedMatchResult {
+            commands: self.commands,
+            start: self.start,
+            relative_offsets: (0..self.cells.len())
+                .map(|key| {
+                    let offsets = (0..self.cells.len())
+                        .filter(|&other| other != key)
+                        .map(|other| (binding(other), self.relative_offset(key, other)))
+                        .collect();
+                    (binding(key), offsets)
+                })
+                .collect(),
+            cells: (0..self.cells.len())
+                .map(|key| (binding(key), self.cells[key]))
+                .collect(),
+            counts: scope
+                .counts
+                .iter()
+                .map(|(&index, name)| (name.as_str(), self.counts[index]))
+                .collect(),
+            captures: scope
+                .captures
+                .iter()
+                .filter_map(|(&index, name)| Some((name.as_str(), self.capture(index)?)))
+                .collect(),
+            end_pointer: self.end_pointer,
+        }
+    }
+}
+
+/// A [`MatchResult`] with everything keyed by the
+/// names used in the pattern, see [`MatchResult::named`].
+#[derive(Debug)]
+pub struct NamedMatchResult<'a, 's> {
+    pub commands: &'a [BFCommand],
+    pub start: usize,
+    /// the offsets from every binding to the other ones
+    pub relative_offsets: BTreeMap<&'s str, BTreeMap<&'s str, isize>>,
+    /// the cell of every binding, relative to the
+    /// pointer at the start of the match
+    pub cells: BTreeMap<&'s str, isize>,
+    pub counts: BTreeMap<&'s str, usize>,
+    /// the instructions matched by every capture group
+    pub captures: BTreeMap<&'s str, &'a [BFCommand]>,
+    pub end_pointer: isize,
+}
+
+/// The ranges of instructions, out of `len` searched instructions,
+/// that none of the matches cover, in order. Matches of no instructions
+/// don't cover any, so they don't split the ranges around them.
+pub fn uncovered<'r, 'a: 'r>(
+    matches: impl IntoIterator<Item = &'r MatchResult<'a>>,
+    len: usize,
+) -> Vec<Range<usize>> {
+    let mut gaps = Vec::new();
+    let mut covered = 0;
+    for res in matches.into_iter().filter(|res| !res.commands.is_empty()) {
+        if res.start > covered {
+            gaps.push(covered..res.start);
+        }
+        covered = covered.max(res.start + res.commands.len());
+    }
+    if covered < len {
+        gaps.push(covered..len);
+    }
+    gaps
+}
+
+/// A match of one of the patterns of a library.
+pub struct LibraryMatch<'a> {
+    /// index of the pattern in the library entries
+    pub pattern: usize,
+    pub result: MatchResult<'a>,
+}
+
 /// A state machine to keep track of local state
-/// in a matching context
+/// in a matching context.
+///
+/// Bindings, counts and captures are dense in their scope, so
+/// the state is kept in vectors indexed by them. The same machine
+/// is reused through a whole search, so the vectors are only
+/// allocated once instead of once per match attempt.
 pub struct MatchSM<'a> {
     instructions: &'a [BFCommand],
-    registry: HashMap<usize, HashMap<usize, isize>>,
+    cells: Vec<Option<isize>>,
+    counts: Vec<Option<usize>>,
+    captures: Vec<Option<Range<usize>>>,
+    /// the pointer, relative to where it was when the attempt started
+    pointer: isize,
     offset: usize,
-    last_binding: Option<usize>,
+    /// instructions at or past this offset are out of reach, used
+    /// to confine a loop body to its own brackets.
+    limit: usize,
+    /// why the last attempt failed
+    mismatch: Option<Mismatch>,
+    /// match straight-line code by its effect, see [`MatchSM::commutative`]
+    commutative: bool,
+    /// the longest run every wildcard can take in this attempt
+    caps: Vec<usize>,
+    /// the length of the run every wildcard took in this attempt
+    taken: Vec<Option<usize>>,
+    /// the lengths of the balanced runs from the start of every wildcard,
+    /// shortest first, kept between attempts along with that start and
+    /// the limit they end by
+    runs: Vec<(usize, usize, Vec<usize>)>,
 }
 
 impl<'a> MatchSM<'a> {
     /// Obtain all possible matches from the same pattern group
     // NOTE: make pattern groups a distinction from a pattern itself.
     pub fn find_all(instructions: &'a [BFCommand], scope: &PatternScope) -> Vec<MatchResult<'a>> {
+        Self::matches(instructions, scope).collect()
+    }
+    /// Iterate over the same matches as [`MatchSM::find_all`], finding
+    /// each one as it's asked for, so the search can stop at any point.
+    pub fn matches<'s>(instructions: &'a [BFCommand], scope: &'s PatternScope) -> Matches<'a, 's> {
+        Self::new(instructions).into_matches(scope)
+    }
+    /// Obtain the same matches as [`MatchSM::find_all`], searching chunks of
+    /// `chunk_size` instructions in parallel.
+    pub fn par_find_all(
+        instructions: &'a [BFCommand],
+        scope: &PatternScope,
+        chunk_size: usize,
+    ) -> Vec<MatchResult<'a>> {
+        crate::parallel::find_all_chunked(
+            instructions.len(),
+            chunk_size,
+            || Self::new(instructions),
+            |machine, offset| machine.match_at(offset, scope).map(|res| (res.skip(), res)),
+        )
+    }
+    /// Obtain the matches of every pattern in a library in a single pass
+    /// through the instructions. When more than one pattern matches at the
+    /// same offset, the one defined first in the library wins.
+    pub fn find_all_in_library(
+        instructions: &'a [BFCommand],
+        library: &PatternLibrary,
+    ) -> Vec<LibraryMatch<'a>> {
+        Self::new(instructions).search_library(library)
+    }
+    /// Replace every match of the rule's pattern with its template,
+    /// copying the instructions in between as they are.
+    pub fn rewrite(instructions: &'a [BFCommand], rule: &Rule) -> Vec<BFCommand> {
+        let mut machine = Self::new(instructions);
         let mut offset = 0;
-        let mut result = Vec::new();
+        let mut result = Vec::with_capacity(instructions.len());
         while offset < instructions.len() {
-            if let Some(res) = Self::match_single(&instructions[offset..], scope) {
-                // advance by the match length.
-                offset += res.commands.len();
-                result.push(res);
-            } else {
-                offset += 1;
+            match machine.match_at(offset, &rule.scope) {
+                // there's nothing to replace in a match of no instructions.
+                Some(res) if !res.commands.is_empty() => {
+                    offset += res.commands.len();
+                    rule.template.render(&res, &mut result);
+                }
+                _ => {
+                    result.push(instructions[offset]);
+                    offset += 1;
+                }
             }
         }
         result
@@ -42,239 +239,1263 @@ impl<'a> MatchSM<'a> {
         instructions: &'a [BFCommand],
         scope: &PatternScope,
     ) -> Option<MatchResult<'a>> {
-        let mut machine = Self::new(instructions);
-        for pat in scope.patterns.iter() {
-            if let Some(optional_action) = machine.match_target(pat) {
-                if let Some(action) = optional_action {
-                    machine.run_action(action);
-                }
+        Self::new(instructions).match_at(0, scope)
+    }
+    /// Create a machine to match patterns against the instructions. It can
+    /// be used for any number of attempts, with patterns from any scope.
+    pub fn new(instructions: &'a [BFCommand]) -> Self {
+        Self {
+            instructions,
+            cells: Vec::new(),
+            counts: Vec::new(),
+            captures: Vec::new(),
+            pointer: 0,
+            offset: 0,
+            limit: instructions.len(),
+            mismatch: None,
+            commutative: false,
+            caps: Vec::new(),
+            taken: Vec::new(),
+            runs: Vec::new(),
+        }
+    }
+    /// Match runs of `+-<>` and bindings by their effect on the tape
+    /// instead of literally, so independent operations on different cells
+    /// may appear in any order: `x[-y+x]` matches both `[->+<]` and `[>+<-]`.
+    ///
+    /// Only the runs right before a loop, an I/O instruction or the end of
+    /// the pattern (or loop body) are matched this way, and they have to
+    /// cover the whole run of `+-<>` in the source.
+    pub fn commutative(mut self, commutative: bool) -> Self {
+        self.commutative = commutative;
+        self
+    }
+    /// Iterate over the matches of a pattern, like
+    /// [`MatchSM::matches`], using this machine's settings.
+    pub fn into_matches<'s>(self, scope: &'s PatternScope) -> Matches<'a, 's> {
+        Matches {
+            machine: self,
+            scope,
+            offset: 0,
+        }
+    }
+    /// Obtain the matches of every pattern in a library, like
+    /// [`MatchSM::find_all_in_library`], using this machine's settings.
+    pub fn search_library(&mut self, library: &PatternLibrary) -> Vec<LibraryMatch<'a>> {
+        let mut offset = 0;
+        let mut result = Vec::new();
+        while offset < self.instructions.len() {
+            let found = library
+                .entries
+                .iter()
+                .enumerate()
+                .find_map(|(pattern, entry)| {
+                    self.match_at(offset, &entry.scope)
+                        .map(|res| (pattern, res))
+                });
+            if let Some((pattern, res)) = found {
+                offset += res.skip();
+                result.push(LibraryMatch {
+                    pattern,
+                    result: res,
+                });
             } else {
-                return None;
+                offset += 1;
             }
         }
+        result
+    }
+    /// Match a pattern starting at `offset`. Only successful
+    /// attempts allocate, to build their result.
+    pub fn match_at(&mut self, offset: usize, scope: &PatternScope) -> Option<MatchResult<'a>> {
+        self.caps.clear();
+        self.caps.resize(scope.wildcards, usize::MAX);
+        loop {
+            self.reset(offset, scope);
+            if self.match_patterns(&scope.patterns) && self.long_enough(offset, scope) {
+                break;
+            }
+            // try again with the last wildcard that matched taking a shorter
+            // run, and the ones after it taking the longest one again.
+            let shorter = (0..scope.wildcards)
+                .rev()
+                .find(|&wildcard| self.taken[wildcard].is_some_and(|len| len > 0))?;
+            self.caps[shorter] = self.taken[shorter].unwrap() - 1;
+            for cap in self.caps[shorter + 1..].iter_mut() {
+                *cap = usize::MAX;
+            }
+        }
+        // the whole pattern matched, so every binding,
+        // count and capture of the scope was found.
         Some(MatchResult {
-            commands: if machine.offset == 0 {
-                instructions
-            } else {
-                &instructions[..machine.offset]
-            },
-            relative_offsets: machine.registry,
+            commands: &self.instructions[offset..self.offset],
+            start: offset,
+            cells: self.cells.iter().map(|cell| cell.unwrap()).collect(),
+            counts: self.counts.iter().map(|count| count.unwrap()).collect(),
+            captures: self
+                .captures
+                .iter()
+                .map(|range| {
+                    let range = range.as_ref().unwrap();
+                    range.start - offset..range.end - offset
+                })
+                .collect(),
+            end_pointer: self.pointer,
         })
     }
-    fn new(instructions: &'a [BFCommand]) -> Self {
-        Self {
-            instructions,
-            registry: HashMap::new(),
-            offset: 0,
-            last_binding: None,
+
+    /// forget the previous attempt, keeping the buffers
+    fn reset(&mut self, offset: usize, scope: &PatternScope) {
+        self.cells.clear();
+        self.cells.resize(scope.bindings.len(), None);
+        self.counts.clear();
+        self.counts.resize(scope.counts.len(), None);
+        self.captures.clear();
+        self.captures.resize(scope.captures.len(), None);
+        self.taken.clear();
+        self.taken.resize(scope.wildcards, None);
+        self.runs
+            .resize(scope.wildcards, (usize::MAX, 0, Vec::new()));
+        self.pointer = 0;
+        self.offset = offset;
+        self.limit = match scope.length.max {
+            Some(max) => (offset + max.max(0) as usize).min(self.instructions.len()),
+            None => self.instructions.len(),
+        };
+        self.mismatch = None;
+    }
+
+    /// whether the match that ends at the current offset is as long
+    /// as the scope requires, which the limit can't make sure of
+    fn long_enough(&mut self, offset: usize, scope: &PatternScope) -> bool {
+        let length = scope.length;
+        if length.contains((self.offset - offset) as isize) {
+            return true;
         }
+        self.fail(MismatchKind::BadLength {
+            capture: None,
+            length,
+        })
+    }
+
+    /// Why the last attempt failed, if it did.
+    pub fn mismatch(&self) -> Option<Mismatch> {
+        self.mismatch
     }
-    fn run_action(&mut self, action: MatchSMAction) {
-        match action {
-            MatchSMAction::AdvanceInput { amount } => self.offset += amount,
-            MatchSMAction::SetLastBinding { binding } => self.last_binding = Some(binding),
-            MatchSMAction::NewBinding {
-                offset_from_last,
-                binding: name,
-            } => {
-                match self.last_binding {
-                    None => {
-                        // without a known last, the `offset_from_last` parameter
-                        // is ignored and the binding is created with a single reference
-                        // to itself.
-                        self.registry.insert(name, {
-                            let mut map = HashMap::new();
-                            map.insert(name, 0);
-                            map
-                        });
-                    }
-                    Some(ref last) => {
-                        // calculate the offsets from the name to the
-                        // others, using its offset from the last one
-                        // as the only common thing between them.
-                        let this_offsets: HashMap<usize, isize> = {
-                            let mut initial: HashMap<_, _> = self
-                                .registry
-                                .iter()
-                                .map(|(other_k, other_map)| {
-                                    (*other_k, other_map[last] + offset_from_last)
-                                })
-                                .collect();
-
-                            initial.insert(name, 0);
-                            initial
-                        };
-
-                        // now make edges in the opposite direction.
-                        for (other_k, other_map) in self.registry.iter_mut() {
-                            other_map.insert(name, -this_offsets[other_k]);
-                        }
 
-                        self.registry.insert(name, this_offsets);
+    /// record why the attempt failed at the current offset
+    #[inline]
+    fn fail(&mut self, kind: MismatchKind) -> bool {
+        self.mismatch = Some(Mismatch {
+            offset: self.offset,
+            kind,
+        });
+        false
+    }
+
+    /// Match a sequence of patterns, updating the state as they succeed.
+    fn match_patterns(&mut self, patterns: &[Pattern]) -> bool {
+        let mut i = 0;
+        while i < patterns.len() {
+            if self.commutative {
+                let run = patterns[i..]
+                    .iter()
+                    .take_while(|pat| is_run_item(pat))
+                    .count();
+                if run > 0 && patterns.get(i + run).is_none_or(ends_run) {
+                    if !self.match_run(&patterns[i..i + run]) {
+                        return false;
                     }
+                    i += run;
+                    continue;
                 }
             }
-            MatchSMAction::Chain(chain) => {
-                // a chain of actions... it's simple to deal with.
-                for action in chain {
-                    self.run_action(action);
+            if !self.match_pattern(&patterns[i]) {
+                return false;
+            }
+            i += 1;
+        }
+        true
+    }
+
+    fn match_pattern(&mut self, pat: &Pattern) -> bool {
+        match pat {
+            Pattern::Instruction(instr) => self.match_instruction(*instr),
+            Pattern::Binding { index, movement } => self.match_binding(*index, *movement),
+            Pattern::Loop(body) => self.match_loop(body),
+            Pattern::AnyLoop => self.match_any_loop(),
+            Pattern::Run { kind, bound } => self.match_run_of(*kind, *bound),
+            Pattern::Zero => self.match_zero(),
+            Pattern::Class(class) => self.match_class(*class),
+            Pattern::AnyInstruction => self.match_any_instruction(),
+            Pattern::Wildcard { index, length } => self.match_wildcard(*index, *length),
+            Pattern::Backreference(index) => self.match_backreference(*index),
+            Pattern::Repeat { instruction, count } => self.match_repeat(*instruction, *count),
+            Pattern::Capture {
+                index,
+                body,
+                length,
+            } => {
+                let start = self.offset;
+                // the body can't go past the longest the capture can be.
+                let outer_limit = self.limit;
+                if let Some(max) = length.max {
+                    self.limit = self.limit.min(start + max.max(0) as usize);
+                }
+                let matched = self.match_patterns(body);
+                self.limit = outer_limit;
+                self.captures[*index] = Some(start..self.offset);
+                if matched && !length.contains((self.offset - start) as isize) {
+                    return self.fail(MismatchKind::BadLength {
+                        capture: Some(*index),
+                        length: *length,
+                    });
                 }
+                matched
             }
         }
     }
 
-    fn match_target(&self, target: &Pattern) -> Option<Option<MatchSMAction>> {
-        match target {
-            Pattern::Instruction(instr) => self.match_instruction(*instr).map(Some),
-            Pattern::Binding { index, strict } => {
-                let binding = *index;
-                let strict = *strict;
-                let (offt, a) = self.calculate_offset();
-                if strict && offt == 0 {
-                    return None;
+    /// Match a run of the pattern by its effect: the whole run of `+-<>` at
+    /// the current offset has to change the same cells by the same amounts,
+    /// and leave the pointer at the same place.
+    fn match_run(&mut self, items: &[Pattern]) -> bool {
+        let len = (self.offset..self.limit)
+            .take_while(|&i| is_run_item(&Pattern::Instruction(self.instructions[i])))
+            .count();
+        let mut effects = Vec::new();
+        let mut end = self.pointer;
+        for instr in &self.instructions[self.offset..self.offset + len] {
+            end += movement(*instr);
+            add_effect(&mut effects, end, change(*instr));
+        }
+        effects.retain(|&(_, delta)| delta != 0);
+        effects.sort_unstable();
+        let mut found = Vec::with_capacity(effects.len());
+        if !self.solve_run(items, self.pointer, &mut found, &effects, end) {
+            return self.fail(MismatchKind::DifferentEffect);
+        }
+        self.advance(len);
+        true
+    }
+
+    /// Try to reproduce the `effects` of a run with the pattern `items`,
+    /// starting with the pointer at `pointer`. New bindings are tried at
+    /// every cell that could make the effects line up.
+    fn solve_run(
+        &mut self,
+        items: &[Pattern],
+        pointer: isize,
+        found: &mut Vec<(isize, isize)>,
+        effects: &[(isize, isize)],
+        end: isize,
+    ) -> bool {
+        let (item, rest) = match items.split_first() {
+            Some(split) => split,
+            None => {
+                let mut found: Vec<_> = found
+                    .iter()
+                    .copied()
+                    .filter(|&(_, delta)| delta != 0)
+                    .collect();
+                found.sort_unstable();
+                return pointer == end && found == effects;
+            }
+        };
+        match *item {
+            Pattern::Instruction(instr) => {
+                let delta = change(instr);
+                add_effect(found, pointer, delta);
+                let solved = self.solve_run(rest, pointer + movement(instr), found, effects, end);
+                add_effect(found, pointer, -delta);
+                solved
+            }
+            Pattern::Binding { index, movement } => match self.cells[index] {
+                Some(cell) => {
+                    movement.allows(cell - pointer)
+                        && self.solve_run(rest, cell, found, effects, end)
                 }
-                self.match_binding(binding, offt).map(|b| {
-                    MatchSMAction::chain_optionals(a, b)
-                        .map(|c| c.chain_with(MatchSMAction::SetLastBinding { binding }))
-                })
+                None => {
+                    // literal moves after the binding may take the pointer
+                    // from its cell to any of the touched ones.
+                    let moves = rest.iter().filter(|pat| movement_of(pat) != 0).count() as isize;
+                    let mut candidates: Vec<_> = effects
+                        .iter()
+                        .map(|&(cell, _)| cell)
+                        .chain(std::iter::once(end))
+                        .flat_map(|cell| (-moves..=moves).map(move |d| cell + d))
+                        .chain(std::iter::once(pointer))
+                        .collect();
+                    candidates.sort_unstable();
+                    candidates.dedup();
+                    for cell in candidates {
+                        if !movement.allows(cell - pointer) {
+                            continue;
+                        }
+                        self.cells[index] = Some(cell);
+                        if self.solve_run(rest, cell, found, effects, end) {
+                            return true;
+                        }
+                    }
+                    self.cells[index] = None;
+                    false
+                }
+            },
+            _ => unreachable!("runs only have instructions and bindings"),
+        }
+    }
+
+    /// A loop matches when the source has a loop at the current offset
+    /// and the body consumes everything up to its matching closing bracket.
+    /// Bindings are shared with the enclosing scope, so offsets recorded inside
+    /// the loop stay valid outside of it.
+    fn match_loop(&mut self, body: &[Pattern]) -> bool {
+        let close = match self.loop_at_offset() {
+            Some(close) => close,
+            None => return false,
+        };
+        let outer_limit = self.limit;
+        self.offset += 1;
+        self.limit = close;
+        let matched = self.match_patterns(body)
+            && (self.offset == close || self.fail(MismatchKind::LoopNotExhausted));
+        self.limit = outer_limit;
+        if matched {
+            self.offset = close + 1;
+        }
+        matched
+    }
+
+    /// Any loop matches as a whole, up to its matching closing bracket.
+    fn match_any_loop(&mut self) -> bool {
+        match self.loop_at_offset() {
+            Some(close) => {
+                self.advance(close + 1 - self.offset);
+                true
             }
+            None => false,
         }
     }
 
-    fn match_binding(
-        &self,
-        binding: usize,
-        offset_from_last: isize,
-    ) -> Option<Option<MatchSMAction>> {
-        match self.last_binding {
-            Some(ref last) => {
-                if !self.registry.contains_key(&binding) {
-                    // a first-time binding will always match,
-                    // as there is no older position to compare it to.
-                    Some(Some(MatchSMAction::NewBinding {
-                        offset_from_last,
-                        binding,
-                    }))
-                } else {
-                    // with a known last for reference, the offset
-                    // can be checked for consistency with the previously
-                    // recorded offset.
-                    if self.registry[&binding][last] == offset_from_last {
-                        // success, but nothing to do.
-                        Some(None)
-                    } else {
-                        None
+    /// A run consumes every `+-` (or `<>`) in front of it, as a single
+    /// operation would, and matches when their net amount is within bounds.
+    fn match_run_of(&mut self, kind: RunKind, bound: Bound) -> bool {
+        let (up, down) = match kind {
+            RunKind::Add => (BFCommand::Increment, BFCommand::Decrement),
+            RunKind::Move => (BFCommand::Right, BFCommand::Left),
+        };
+        let mut amount = 0;
+        let mut len = 0;
+        while let Some(&instr) = self.instruction_at(self.offset + len) {
+            match instr {
+                _ if instr == up => amount += 1,
+                _ if instr == down => amount -= 1,
+                _ => break,
+            }
+            len += 1;
+        }
+        if len == 0 {
+            let found = self.instruction_at(self.offset).copied();
+            return self.fail(MismatchKind::ExpectedRun { kind, found });
+        }
+        if !bound.contains(amount) {
+            return self.fail(MismatchKind::OutOfBounds { kind, amount });
+        }
+        self.advance(len);
+        true
+    }
+
+    /// `[-]` or `[+]`
+    fn match_zero(&mut self) -> bool {
+        let found = self.instruction_at(self.offset).copied();
+        let cleared = found == Some(BFCommand::BeginLoop)
+            && matches!(
+                self.instruction_at(self.offset + 1),
+                Some(BFCommand::Increment | BFCommand::Decrement)
+            )
+            && self.instruction_at(self.offset + 2) == Some(&BFCommand::EndLoop);
+        if !cleared {
+            return self.fail(MismatchKind::ExpectedZero { found });
+        }
+        self.advance(3);
+        true
+    }
+
+    /// the closing bracket of the loop at the current offset, if there is one
+    fn loop_at_offset(&mut self) -> Option<usize> {
+        let found = self.instruction_at(self.offset).copied();
+        if found != Some(BFCommand::BeginLoop) {
+            self.fail(MismatchKind::ExpectedLoop { found });
+            return None;
+        }
+        let close = self.matching_close(self.offset);
+        if close.is_none() {
+            self.fail(MismatchKind::UnclosedLoop);
+        }
+        close
+    }
+
+    /// find the closing bracket that matches the loop opened at `open`
+    fn matching_close(&self, open: usize) -> Option<usize> {
+        let mut depth = 0usize;
+        for i in open..self.limit {
+            match self.instructions[i] {
+                BFCommand::BeginLoop => depth += 1,
+                BFCommand::EndLoop => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Some(i);
                     }
                 }
+                _ => (),
             }
-            None => {
-                // with no known last, a first-time binding will always
-                // match, as it's certain that there is no registry of the
-                // binding itself, nor is a registry of any other binding to
-                // compare its solidity.
-                Some(Some(MatchSMAction::NewBinding {
-                    offset_from_last,
-                    binding,
-                }))
+        }
+        None
+    }
+
+    #[inline]
+    fn instruction_at(&self, index: usize) -> Option<&BFCommand> {
+        if index < self.limit {
+            self.instructions.get(index)
+        } else {
+            None
+        }
+    }
+
+    /// consume `amount` instructions, keeping track of the pointer
+    fn advance(&mut self, amount: usize) {
+        for instr in &self.instructions[self.offset..self.offset + amount] {
+            match instr {
+                BFCommand::Right => self.pointer += 1,
+                BFCommand::Left => self.pointer -= 1,
+                _ => (),
             }
         }
+        self.offset += amount
     }
 
     /// literal instructions are checked directly against the source
-    fn match_instruction(&self, instruction: BFCommand) -> Option<MatchSMAction> {
-        self.instructions
-            .get(self.offset)
-            .filter(|&&i| i == instruction)
-            .map(|_| MatchSMAction::AdvanceInput { amount: 1 })
+    fn match_instruction(&mut self, instruction: BFCommand) -> bool {
+        let found = self.instruction_at(self.offset).copied();
+        if found != Some(instruction) {
+            return self.fail(MismatchKind::ExpectedInstruction {
+                expected: instruction,
+                found,
+            });
+        }
+        self.advance(1);
+        true
     }
 
-    /// calculate an offset from the current source. The first direction instruction
-    /// dictates what direction is the offset going, and the rest
-    /// will be matched according to that. If no direction instruction is recorded,
-    /// the offset will be reported as zero.
-    fn calculate_offset(&self) -> (isize, Option<MatchSMAction>) {
-        let mut local_offset = 0;
-        if let Some(direction) = self
-            .instructions
-            .get(self.offset)
-            .filter(|i| matches!(i, BFCommand::Left | BFCommand::Right))
-        {
-            local_offset += 1;
-            while self
-                .instructions
-                .get(self.offset + local_offset)
-                .filter(|&i| i == direction)
-                .is_some()
-            {
-                local_offset += 1;
+    fn match_class(&mut self, class: CommandClass) -> bool {
+        let found = self.instruction_at(self.offset).copied();
+        if !found.is_some_and(|found| class.contains(found)) {
+            return self.fail(MismatchKind::ExpectedClass { class, found });
+        }
+        self.advance(1);
+        true
+    }
+
+    fn match_any_instruction(&mut self) -> bool {
+        let found = self.instruction_at(self.offset).copied();
+        if matches!(
+            found,
+            None | Some(BFCommand::BeginLoop | BFCommand::EndLoop)
+        ) {
+            return self.fail(MismatchKind::ExpectedAnyInstruction { found });
+        }
+        self.advance(1);
+        true
+    }
+
+    /// A wildcard takes the longest balanced run within its cap.
+    fn match_wildcard(&mut self, index: usize, length: Bound) -> bool {
+        if self.runs[index].0 != self.offset || self.runs[index].1 != self.limit {
+            let lengths = self.balanced_runs();
+            self.runs[index] = (self.offset, self.limit, lengths);
+        }
+        let lengths = &self.runs[index].2;
+        let cap = match length.max {
+            Some(max) => self.caps[index].min(max.max(0) as usize),
+            None => self.caps[index],
+        };
+        let fitting = lengths.partition_point(|&len| len <= cap);
+        let len = match fitting.checked_sub(1).map(|i| lengths[i]) {
+            Some(len) if length.contains(len as isize) => len,
+            _ => {
+                let found = self.instruction_at(self.offset).copied();
+                return self.fail(MismatchKind::ExpectedBalanced { found });
+            }
+        };
+        self.taken[index] = Some(len);
+        self.advance(len);
+        true
+    }
+
+    /// the lengths of the runs with balanced brackets from the current
+    /// offset, shortest first, starting with the empty one
+    fn balanced_runs(&self) -> Vec<usize> {
+        let mut lengths = vec![0];
+        let mut depth = 0usize;
+        for i in self.offset..self.limit {
+            match self.instructions[i] {
+                BFCommand::BeginLoop => depth += 1,
+                BFCommand::EndLoop => match depth.checked_sub(1) {
+                    Some(outer) => depth = outer,
+                    // the end of the loop the run is in.
+                    None => break,
+                },
+                _ => (),
+            }
+            if depth == 0 {
+                lengths.push(i + 1 - self.offset);
+            }
+        }
+        lengths
+    }
+
+    fn match_backreference(&mut self, index: usize) -> bool {
+        let captured = match self.captures[index].clone() {
+            Some(range) => range,
+            None => return self.fail(MismatchKind::DifferentFromCapture { capture: index }),
+        };
+        let end = self.offset + captured.len();
+        if end > self.limit || self.instructions[self.offset..end] != self.instructions[captured] {
+            return self.fail(MismatchKind::DifferentFromCapture { capture: index });
+        }
+        self.advance(end - self.offset);
+        true
+    }
+
+    fn match_repeat(&mut self, instruction: BFCommand, count: usize) -> bool {
+        let amount = (self.offset..self.limit)
+            .take_while(|&i| self.instructions[i] == instruction)
+            .count();
+        if amount == 0 {
+            let found = self.instruction_at(self.offset).copied();
+            return self.fail(MismatchKind::ExpectedInstruction {
+                expected: instruction,
+                found,
+            });
+        }
+        if let Some(expected) = self.counts[count].filter(|&c| c != amount) {
+            return self.fail(MismatchKind::CountMismatch {
+                count,
+                expected,
+                found: amount,
+            });
+        }
+        self.advance(amount);
+        self.counts[count] = Some(amount);
+        true
+    }
+
+    /// A binding consumes the movement in front of it. A first-time binding
+    /// will always match, as there is no older position to compare it to.
+    /// Otherwise the pointer has to be back at the binding's cell, which keeps
+    /// its offsets to every other binding consistent.
+    fn match_binding(&mut self, binding: usize, movement: Movement) -> bool {
+        let moved = self.consume_movement();
+        if !movement.allows(moved) {
+            return self.fail(MismatchKind::BadMovement {
+                binding,
+                movement,
+                moved,
+            });
+        }
+        match self.cells[binding] {
+            Some(cell) if cell != self.pointer => self.fail(MismatchKind::BindingMoved {
+                binding,
+                cell,
+                pointer: self.pointer,
+            }),
+            Some(_) => true,
+            None => {
+                self.cells[binding] = Some(self.pointer);
+                true
             }
-            (
-                local_offset as isize
-                    * match direction {
-                        BFCommand::Left => -1,
-                        BFCommand::Right => 1,
-                        _ => unreachable!(),
-                    },
-                Some(MatchSMAction::AdvanceInput {
-                    amount: local_offset,
-                }),
+        }
+    }
+
+    /// consume a movement from the source. The first direction instruction
+    /// dictates what direction is the movement going, and the rest
+    /// will be matched according to that. If no direction instruction is recorded,
+    /// the movement will be reported as zero.
+    fn consume_movement(&mut self) -> isize {
+        let direction = match self.instruction_at(self.offset) {
+            Some(&direction @ (BFCommand::Left | BFCommand::Right)) => direction,
+            _ => return 0,
+        };
+        let mut amount = 1;
+        while self.instruction_at(self.offset + amount) == Some(&direction) {
+            amount += 1;
+        }
+        let before = self.pointer;
+        self.advance(amount);
+        self.pointer - before
+    }
+}
+
+/// Items that can be reordered in a run, see [`MatchSM::commutative`]
+fn is_run_item(pat: &Pattern) -> bool {
+    matches!(
+        pat,
+        Pattern::Binding { .. }
+            | Pattern::Instruction(
+                BFCommand::Increment | BFCommand::Decrement | BFCommand::Left | BFCommand::Right
             )
-        } else {
-            (0, None)
+    )
+}
+
+/// Items a run can be followed by to be matched by its effect
+fn ends_run(pat: &Pattern) -> bool {
+    matches!(
+        pat,
+        Pattern::Loop(_)
+            | Pattern::AnyLoop
+            | Pattern::Instruction(BFCommand::BeginLoop | BFCommand::Print | BFCommand::Read)
+            | Pattern::Class(CommandClass::Io)
+    )
+}
+
+fn movement(instr: BFCommand) -> isize {
+    match instr {
+        BFCommand::Right => 1,
+        BFCommand::Left => -1,
+        _ => 0,
+    }
+}
+
+fn movement_of(pat: &Pattern) -> isize {
+    match pat {
+        Pattern::Instruction(instr) => movement(*instr),
+        _ => 0,
+    }
+}
+
+fn change(instr: BFCommand) -> isize {
+    match instr {
+        BFCommand::Increment => 1,
+        BFCommand::Decrement => -1,
+        _ => 0,
+    }
+}
+
+/// add `delta` to the change of `cell`
+fn add_effect(effects: &mut Vec<(isize, isize)>, cell: isize, delta: isize) {
+    match effects.iter_mut().find(|(c, _)| *c == cell) {
+        Some((_, total)) => *total += delta,
+        None => effects.push((cell, delta)),
+    }
+}
+
+fn run_name(kind: RunKind) -> &'static str {
+    match kind {
+        RunKind::Add => "an addition",
+        RunKind::Move => "a movement",
+    }
+}
+
+fn describe_movement(movement: Movement) -> String {
+    let bound = match movement {
+        Movement::Any => return "any movement".to_string(),
+        Movement::Required => return "a movement".to_string(),
+        Movement::Within(bound) => bound,
+    };
+    match (bound.min, bound.max) {
+        (Some(min), Some(max)) if min == max => format!("a movement of {:+}", min),
+        (Some(min), Some(max)) => format!("a movement between {:+} and {:+}", min, max),
+        (Some(min), None) => format!("a movement of at least {:+}", min),
+        (None, Some(max)) => format!("a movement of at most {:+}", max),
+        (None, None) => "any movement".to_string(),
+    }
+}
+
+fn length_name(length: Bound) -> String {
+    match (length.min.unwrap_or(0), length.max) {
+        (min, Some(max)) if min == max => format!("{}", min),
+        (min, Some(max)) => format!("{} to {}", min, max),
+        (min, None) => format!("at least {}", min),
+    }
+}
+
+/// A lazy search for the matches of a pattern, see [`MatchSM::matches`].
+pub struct Matches<'a, 's> {
+    machine: MatchSM<'a>,
+    scope: &'s PatternScope,
+    /// where to look for the next match
+    offset: usize,
+}
+
+impl<'a> Iterator for Matches<'a, '_> {
+    type Item = MatchResult<'a>;
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.offset < self.machine.instructions.len() {
+            if let Some(res) = self.machine.match_at(self.offset, self.scope) {
+                // advance by the match length.
+                self.offset += res.skip();
+                return Some(res);
+            }
+            self.offset += 1;
         }
+        None
     }
 }
 
-/// Models the different state mutation actions
-/// that the state machine has available. Lets you control exactly when the state is modified
-#[derive(Debug)]
-enum MatchSMAction {
-    /// a new binding was discovered.
-    NewBinding {
-        offset_from_last: isize,
-        binding: usize,
+impl std::iter::FusedIterator for Matches<'_, '_> {}
+
+/// Why a pattern didn't match.
+#[derive(Debug, Clone, Copy)]
+pub struct Mismatch {
+    /// offset of the instruction where matching failed
+    pub offset: usize,
+    pub kind: MismatchKind,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum MismatchKind {
+    /// a literal instruction didn't match. `found` is `None` past
+    /// the end of the instructions or of a loop body.
+    ExpectedInstruction {
+        expected: BFCommand,
+        found: Option<BFCommand>,
+    },
+    ExpectedLoop {
+        found: Option<BFCommand>,
+    },
+    /// the loop isn't closed within reach
+    UnclosedLoop,
+    /// there's no instruction of the run's kind at the offset
+    ExpectedRun {
+        kind: RunKind,
+        found: Option<BFCommand>,
+    },
+    /// the net amount of a run isn't within its bound
+    OutOfBounds {
+        kind: RunKind,
+        amount: isize,
+    },
+    /// there's no clear loop at the offset
+    ExpectedZero {
+        found: Option<BFCommand>,
+    },
+    /// the instruction at the offset isn't of the class
+    ExpectedClass {
+        class: CommandClass,
+        found: Option<BFCommand>,
+    },
+    /// there's no instruction but a bracket at the offset
+    ExpectedAnyInstruction {
+        found: Option<BFCommand>,
     },
-    AdvanceInput {
-        amount: usize,
+    /// there's no run with balanced brackets at the offset that the wildcard can take
+    ExpectedBalanced {
+        found: Option<BFCommand>,
     },
-    SetLastBinding {
+    /// the instructions at the offset aren't the ones the capture group matched
+    DifferentFromCapture {
+        capture: usize,
+    },
+    /// a capture group, or the whole match if it's `None`,
+    /// isn't as long as its length requires
+    BadLength {
+        capture: Option<usize>,
+        length: Bound,
+    },
+    /// a run of `+-<>` doesn't have the same effect as the
+    /// pattern, in any order, see [`MatchSM::commutative`]
+    DifferentEffect,
+    /// the body of a loop matched, but didn't get to its closing bracket
+    LoopNotExhausted,
+    /// a repetition was found a different amount of times
+    /// than the first time its count was captured
+    CountMismatch {
+        count: usize,
+        expected: usize,
+        found: usize,
+    },
+    /// a binding whose movement in front of it isn't the one it requires
+    BadMovement {
+        binding: usize,
+        movement: Movement,
+        moved: isize,
+    },
+    /// the pointer isn't at the cell the binding was first found at,
+    /// so its offsets to the other bindings aren't consistent
+    BindingMoved {
         binding: usize,
+        cell: isize,
+        pointer: isize,
     },
-    Chain(Vec<MatchSMAction>),
 }
 
-impl MatchSMAction {
-    #[inline]
-    pub fn chain_with(self, other: Self) -> Self {
-        match (self, other) {
-            (Self::Chain(mut a_chain), Self::Chain(mut b_chain)) => Self::Chain({
-                a_chain.reserve(b_chain.len());
-                let drain = b_chain.drain(..);
-                a_chain.extend(drain);
-                a_chain
-            }),
-            (Self::Chain(mut chain), other) | (other, Self::Chain(mut chain)) => Self::Chain({
-                chain.push(other);
-                chain
-            }),
-            (a, b) => Self::Chain(vec![a, b]),
+impl Mismatch {
+    /// Explain the mismatch, resolving names through the
+    /// scope of the pattern that failed to match.
+    pub fn describe(&self, scope: &PatternScope) -> String {
+        let instr = |instr: Option<BFCommand>| match instr {
+            Some(instr) => format!("`{}`", instr),
+            None => "nothing".to_string(),
+        };
+        match self.kind {
+            MismatchKind::ExpectedInstruction { expected, found } => {
+                format!("expected `{}`, found {}", expected, instr(found))
+            }
+            MismatchKind::ExpectedLoop { found } => {
+                format!("expected a loop, found {}", instr(found))
+            }
+            MismatchKind::UnclosedLoop => "the loop is never closed".to_string(),
+            MismatchKind::ExpectedRun { kind, found } => {
+                format!("expected {}, found {}", run_name(kind), instr(found))
+            }
+            MismatchKind::OutOfBounds { kind, amount } => format!(
+                "{} of {} is out of the pattern's bounds",
+                run_name(kind),
+                amount
+            ),
+            MismatchKind::ExpectedZero { found } => {
+                format!("expected a clear loop, found {}", instr(found))
+            }
+            MismatchKind::ExpectedClass { class, found } => {
+                let [a, b] = class.commands();
+                format!("expected `{}` or `{}`, found {}", a, b, instr(found))
+            }
+            MismatchKind::ExpectedAnyInstruction { found } => {
+                format!(
+                    "expected an instruction but a bracket, found {}",
+                    instr(found)
+                )
+            }
+            MismatchKind::ExpectedBalanced { found } => {
+                format!(
+                    "expected instructions with balanced brackets, found {}",
+                    instr(found)
+                )
+            }
+            MismatchKind::DifferentFromCapture { capture } => format!(
+                "the instructions aren't the ones captured by `{}`",
+                scope.captures.get_by_left(&capture).unwrap()
+            ),
+            MismatchKind::BadLength { capture, length } => format!(
+                "{} is not {} instructions long",
+                match capture {
+                    Some(capture) => format!(
+                        "the capture `{}`",
+                        scope.captures.get_by_left(&capture).unwrap()
+                    ),
+                    None => "the match".to_string(),
+                },
+                length_name(length)
+            ),
+            MismatchKind::DifferentEffect => {
+                "the instructions don't have the same effect as the pattern".to_string()
+            }
+            MismatchKind::LoopNotExhausted => {
+                "the loop body matched, but the loop goes on".to_string()
+            }
+            MismatchKind::CountMismatch {
+                count,
+                expected,
+                found,
+            } => format!(
+                "count `{}` was {} but is {} here",
+                scope.counts.get_by_left(&count).unwrap(),
+                expected,
+                found
+            ),
+            MismatchKind::BadMovement {
+                binding,
+                movement,
+                moved,
+            } => format!(
+                "binding `{}` needs {} in front of it, but the pointer moves {:+}",
+                scope.bindings.get_by_left(&binding).unwrap(),
+                describe_movement(movement),
+                moved
+            ),
+            MismatchKind::BindingMoved {
+                binding,
+                cell,
+                pointer,
+            } => format!(
+                "binding `{}` was found at cell {:+}, but the pointer is at {:+} here, {:+} off",
+                scope.bindings.get_by_left(&binding).unwrap(),
+                cell,
+                pointer,
+                pointer - cell
+            ),
         }
     }
+}
 
-    #[inline]
-    pub fn optional_chain(self, other: Option<Self>) -> Self {
-        if let Some(other) = other {
-            self.chain_with(other)
-        } else {
-            self
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::parse_library;
+    use crate::pattern::{parse_pattern, ParseError};
+    use crate::rewrite::parse_rule;
+    use bfrs_common::parser::{parse_located, parse_str};
+    use bfrs_common::Position;
+
+    const NONE: [(usize, usize); 0] = [];
+
+    /// Where the matches of `pattern` in `source` start and end.
+    fn ranges(pattern: &str, source: &str, commutative: bool) -> Vec<(usize, usize)> {
+        let scope = parse_pattern(pattern).unwrap();
+        let instructions = parse_str(source).unwrap();
+        MatchSM::new(&instructions)
+            .commutative(commutative)
+            .into_matches(&scope)
+            .map(|res| (res.start, res.start + res.commands.len()))
+            .collect()
     }
 
-    #[inline]
-    pub fn chain_optionals(a: Option<Self>, b: Option<Self>) -> Option<Self> {
-        match (a, b) {
-            (Some(a), b) | (b, Some(a)) => Some(a.optional_chain(b)),
-            _ => None,
-        }
+    #[test]
+    fn literal_matches() {
+        assert_eq!(ranges("[-]", "+[-]>[-]", false), [(1, 4), (5, 8)]);
+        assert_eq!(ranges("x[-y+x]", "+[->+<]", false), [(1, 7)]);
+        assert_eq!(ranges("x[-y+x]", "+[>+<-]", false), NONE);
+    }
+
+    #[test]
+    fn bindings_keep_their_cells() {
+        let scope = parse_pattern("x[-y+z+x]").unwrap();
+        let instructions = parse_str("[->+>>+<<<]").unwrap();
+        let res = MatchSM::match_single(&instructions, &scope).unwrap();
+        assert_eq!(res.cells, [0, 1, 3]);
+        assert_eq!(res.end_pointer, 0);
+    }
+
+    #[test]
+    fn commutative_matches_any_order() {
+        assert_eq!(ranges("x[-y+x]", "+[>+<-]", true), [(1, 7)]);
+        assert_eq!(ranges("x[-y+x]", "+[->+<]", true), [(1, 7)]);
+        assert_eq!(ranges("x[-y+z+x]", "[>>>+<<+<-]", true), [(0, 11)]);
+        // the effect has to be the same, not only the instructions.
+        assert_eq!(ranges("x[-y+x]", "[>+<--]", true), NONE);
+        assert_eq!(ranges("x[-y+x]", "[>+<->]", true), NONE);
+    }
+
+    #[test]
+    fn commutative_runs_cover_the_whole_run() {
+        // `x[-y+x]` is a transfer of one cell into another, and `[->+>+<<]`
+        // touches a third one, so it can't be matched by leaving it out.
+        assert_eq!(ranges("x[-y+x]", "[->+>+<<]", true), NONE);
+    }
+
+    #[test]
+    fn runs_and_wildcards() {
+        assert_eq!(ranges("@add(>=3).", "++.+++.", false), [(3, 7)]);
+        assert_eq!(ranges("@zero", "[-]>[+]", false), [(0, 3), (4, 7)]);
+        assert_eq!(ranges("[_*]", "[>.<][]", false), [(0, 5)]);
+        assert_eq!(ranges("[_{0,}]", "[>.<][]", false), [(0, 5), (5, 7)]);
+        assert_eq!(ranges("(a: _{2})\\a", "+>+>", false), [(0, 4)]);
+    }
+
+    #[test]
+    fn empty_matches_take_no_instructions() {
+        let scope = parse_pattern("x").unwrap();
+        let instructions = parse_str("+>-").unwrap();
+        let res = MatchSM::match_single(&instructions, &scope).unwrap();
+        assert!(res.commands.is_empty());
+        // the search moves on by one after them, and finds them again further on.
+        assert_eq!(ranges("x", "+>-", false), [(0, 0), (1, 2), (2, 2)]);
+        let parallel: Vec<_> = MatchSM::par_find_all(&instructions, &scope, 2)
+            .iter()
+            .map(|res| (res.start, res.start + res.commands.len()))
+            .collect();
+        assert_eq!(parallel, [(0, 0), (1, 2), (2, 2)]);
+    }
+
+    /// What `--invert` reports of `source` for `pattern`.
+    fn gaps(pattern: &str, source: &str) -> Vec<(usize, usize)> {
+        let scope = parse_pattern(pattern).unwrap();
+        let instructions = parse_str(source).unwrap();
+        let found = MatchSM::find_all(&instructions, &scope);
+        uncovered(&found, instructions.len())
+            .into_iter()
+            .map(|gap| (gap.start, gap.end))
+            .collect()
+    }
+
+    #[test]
+    fn empty_matches_cover_nothing() {
+        assert_eq!(gaps("x", "+>-"), [(0, 1), (2, 3)]);
+        assert_eq!(gaps("x", "+-+"), [(0, 3)]);
+        assert_eq!(gaps("_*", "+>-"), NONE);
+        assert_eq!(gaps("[_{0,}]", "+[]-"), [(0, 1), (3, 4)]);
+    }
+
+    #[test]
+    fn any_instruction_but_a_bracket() {
+        assert_eq!(ranges("_", "+[-]", false), [(0, 1), (2, 3)]);
+        assert_eq!(ranges("[_]", "[-][>>]", false), [(0, 3)]);
+    }
+
+    #[test]
+    fn wildcards_take_balanced_runs() {
+        assert_eq!(ranges("_*", "+[-]>", false), [(0, 5)]);
+        assert_eq!(ranges("[_*]", "[+[-]]", false), [(0, 6)]);
+        // the run would have to end inside the loop, or take its `[` alone.
+        assert_eq!(ranges("+_*.", "+[.]", false), NONE);
+        assert_eq!(ranges(">_*<", ">[<]", false), NONE);
+        assert_eq!(ranges("_{2}", "+[-]->", false), [(4, 6)]);
+    }
+
+    #[test]
+    fn wildcards_give_back_what_the_rest_needs() {
+        assert_eq!(ranges("_*.", "+.+.", false), [(0, 4)]);
+        assert_eq!(ranges("_*>_*.", "+>->.", false), [(0, 5)]);
+        assert_eq!(ranges("[_*]_*[-]", "[>][<][-]", false), [(0, 9)]);
+        let scope = parse_pattern("(a: _*)(b: _*).").unwrap();
+        let instructions = parse_str("+->.").unwrap();
+        let res = MatchSM::match_single(&instructions, &scope).unwrap();
+        // the first one takes as much as it can.
+        assert_eq!(res.captures, [0..2, 2..3]);
+    }
+
+    #[test]
+    fn backreferences() {
+        assert_eq!(ranges("(a: _)\\a", "++", false), [(0, 2)]);
+        assert_eq!(ranges("(a: _)\\a", "+-+.", false), NONE);
+        assert_eq!(ranges("(a: _*)\\a", "+>+>", false), [(0, 4)]);
+        let scope = parse_pattern("(a: _)\\a").unwrap();
+        let instructions = parse_str("+-").unwrap();
+        let mut machine = MatchSM::new(&instructions);
+        assert!(machine.match_at(0, &scope).is_none());
+        assert!(matches!(
+            machine.mismatch().unwrap().kind,
+            MismatchKind::DifferentFromCapture { capture: 0 }
+        ));
+    }
+
+    /// `source` with every match of the pattern of `rule` replaced.
+    fn rewritten(rule: &str, source: &str) -> String {
+        let rule = parse_rule(rule).unwrap();
+        let instructions = parse_str(source).unwrap();
+        MatchSM::rewrite(&instructions, &rule)
+            .iter()
+            .map(|&instr| instr as u8 as char)
+            .collect()
+    }
+
+    #[test]
+    fn templates_move_to_bindings() {
+        assert_eq!(
+            rewritten("x[-y+x] => y[-]x[-y+x]", "+[->+<]."),
+            "+>[-]<[->+<]."
+        );
+        // the pointer ends where the matched code left it.
+        assert_eq!(rewritten("x[-]>y => y+x", "[-]>>."), ">>+<<>>.");
+    }
+
+    #[test]
+    fn templates_repeat_counts_and_emit_captures() {
+        assert_eq!(rewritten("+*n> => >+*n", "+++>-"), ">+++-");
+        assert_eq!(rewritten("(a: _*)[-] => [-](a)", "+>[-]"), "[-]+>");
+        assert_eq!(rewritten("(a: _)(b: _) => (b)(a)", "+-.,"), "-+,.");
+    }
+
+    #[test]
+    fn templates_only_use_names_of_the_pattern() {
+        let error = |src| parse_rule(src).expect_err(src).kind;
+        assert!(matches!(
+            error("x[-] => y"),
+            ParseError::UnknownBinding { name } if name == "y"
+        ));
+        assert!(matches!(
+            error("+*n => +*m"),
+            ParseError::UnknownCount { name } if name == "m"
+        ));
+        assert!(matches!(
+            error("(a: +) => (b)"),
+            ParseError::UnknownCapture { name } if name == "b"
+        ));
+        assert!(matches!(error("+*n => +*"), ParseError::MissingCountName));
+        assert!(matches!(error("x[-]"), ParseError::MissingRewriteArrow));
+        assert!(matches!(error("x => ]"), ParseError::UnmatchedLoopClose));
+    }
+
+    #[test]
+    fn loops_match_whole_loops() {
+        // the `]` of the pattern can't be the one of the inner loop.
+        assert_eq!(ranges("[-]", "[-[-]]", false), [(2, 5)]);
+        assert_eq!(ranges("[[-]]", "[[-]][[-]-]", false), [(0, 5)]);
+        let scope = parse_pattern("[-]").unwrap();
+        let instructions = parse_str("[--]").unwrap();
+        let mut machine = MatchSM::new(&instructions);
+        assert!(machine.match_at(0, &scope).is_none());
+        assert!(matches!(
+            machine.mismatch().unwrap().kind,
+            MismatchKind::LoopNotExhausted
+        ));
+    }
+
+    #[test]
+    fn matches_are_located_in_the_source() {
+        let (instructions, locations): (Vec<_>, Vec<_>) =
+            parse_located("+\n [-] >".bytes().map(Ok))
+                .map(Result::unwrap)
+                .unzip();
+        let scope = parse_pattern("[-]").unwrap();
+        let found = MatchSM::find_all(&instructions, &scope);
+        assert_eq!(found.len(), 1);
+        let found = &found[0];
+        assert_eq!(found.start, 1);
+        let (start, end) = found.locate(&locations);
+        assert_eq!((start.byte_offset, end.byte_offset), (3, 6));
+        assert_eq!(start.position, Position { line: 2, column: 2 });
+        assert_eq!(end.position, Position { line: 2, column: 5 });
+    }
+
+    #[test]
+    fn captures_take_what_their_bodies_matched() {
+        let scope = parse_pattern("x[-(body: y+(step: _{2}))x]").unwrap();
+        let instructions = parse_str("+[->+>.<<]").unwrap();
+        let found = MatchSM::find_all(&instructions, &scope);
+        assert_eq!(found.len(), 1);
+        let named = found[0].named(&scope);
+        let text = |name| -> String {
+            named.captures[name]
+                .iter()
+                .map(|&i| i as u8 as char)
+                .collect()
+        };
+        assert_eq!(text("body"), ">+>.");
+        assert_eq!(text("step"), ">.");
+        // ranges are relative to the match
+        assert_eq!(found[0].captures, [2..6, 4..6]);
+    }
+
+    #[test]
+    fn attempts_start_from_a_clean_state() {
+        // the failed attempt at 0 counts two `+`, which
+        // mustn't be what the next attempts compare with.
+        assert_eq!(ranges("+*n-*n", "++-+-", false), [(1, 3), (3, 5)]);
+        assert_eq!(ranges("(a: +)\\a", "+-++", false), [(2, 4)]);
+        let library = parse_library("pair = +*n-*n\nany = (a: _)").unwrap();
+        let instructions = parse_str("++-.").unwrap();
+        let found = MatchSM::find_all_in_library(&instructions, &library);
+        let spots: Vec<_> = found
+            .iter()
+            .map(|found| {
+                (
+                    found.pattern,
+                    found.result.start,
+                    found.result.counts.clone(),
+                )
+            })
+            .collect();
+        assert_eq!(spots, [(1, 0, vec![]), (0, 1, vec![1]), (1, 3, vec![])]);
+        assert_eq!(found[2].result.capture(0), Some(&[BFCommand::Print][..]));
+    }
+
+    #[test]
+    fn any_loop_takes_a_whole_loop() {
+        assert_eq!(ranges("@loop", "+[-[>]]>[]", false), [(1, 7), (8, 10)]);
+        assert_eq!(ranges("+@loop.", "+[.].+[].", false), [(0, 5), (5, 9)]);
+        assert_eq!(ranges("@loop", "+-<>.,", false), NONE);
+    }
+
+    /// Where and why `pattern` doesn't match at the start of `source`.
+    fn mismatch(pattern: &str, source: &str) -> (usize, String) {
+        let scope = parse_pattern(pattern).unwrap();
+        let instructions = parse_str(source).unwrap();
+        let mut machine = MatchSM::new(&instructions);
+        assert!(machine.match_at(0, &scope).is_none());
+        let mismatch = machine.mismatch().unwrap();
+        (mismatch.offset, mismatch.describe(&scope))
+    }
+
+    #[test]
+    fn mismatches_say_where_and_why() {
+        let expected = |offset, why: &str| (offset, why.to_string());
+        assert_eq!(
+            mismatch("+-.", "+-,"),
+            expected(2, "expected `.`, found `,`")
+        );
+        assert_eq!(
+            mismatch("+-", "+"),
+            expected(1, "expected `-`, found nothing")
+        );
+        assert_eq!(
+            mismatch("+[-]", "++"),
+            expected(1, "expected a loop, found `+`")
+        );
+        assert_eq!(
+            mismatch("[-]", "[-+]"),
+            expected(2, "the loop body matched, but the loop goes on")
+        );
+        assert_eq!(
+            mismatch("+*n.-*n", "++.-"),
+            expected(3, "count `n` was 2 but is 1 here")
+        );
+        assert_eq!(
+            mismatch("x[-y+x]", "[->+<<]"),
+            expected(
+                6,
+                "binding `x` was found at cell +0, but the pointer is at -1 here, -1 off"
+            )
+        );
+    }
+
+    #[test]
+    fn results_keyed_by_name() {
+        let scope = parse_pattern("x[-y+*n z-x]").unwrap();
+        let instructions = parse_str("+[->+++<<->]").unwrap();
+        let found = MatchSM::find_all(&instructions, &scope);
+        assert_eq!(found.len(), 1);
+        let named = found[0].named(&scope);
+        assert_eq!(named.start, 1);
+        assert_eq!(named.commands.len(), 11);
+        let cells: Vec<_> = named.cells.into_iter().collect();
+        assert_eq!(cells, [("x", 0), ("y", 1), ("z", -1)]);
+        assert_eq!(named.relative_offsets["y"]["x"], 1);
+        assert_eq!(named.relative_offsets["z"]["y"], -2);
+        assert!(!named.relative_offsets["x"].contains_key("x"));
+        assert_eq!(named.counts["n"], 3);
+        assert_eq!(named.end_pointer, 0);
+    }
+
+    #[test]
+    fn matches_are_found_as_they_are_asked_for() {
+        let scope = parse_pattern("[-]").unwrap();
+        let instructions = parse_str("[-]+[-]>[-]").unwrap();
+        let mut matches = MatchSM::matches(&instructions, &scope);
+        assert_eq!(matches.next().map(|res| res.start), Some(0));
+        // the rest continue after the first one, like `find_all`
+        let rest: Vec<_> = matches.by_ref().map(|res| res.start).collect();
+        assert_eq!(rest, [4, 8]);
+        assert!(matches.next().is_none());
+        let all = MatchSM::find_all(&instructions, &scope);
+        assert_eq!(all.len(), 3);
+
+        // a machine's settings are kept
+        let scope = parse_pattern("x[-y+x]").unwrap();
+        let instructions = parse_str("[>+<-]").unwrap();
+        assert_eq!(MatchSM::matches(&instructions, &scope).count(), 0);
+        let machine = MatchSM::new(&instructions).commutative(true);
+        assert_eq!(machine.into_matches(&scope).count(), 1);
+    }
+
+    #[test]
+    fn strict_bindings_need_their_movement() {
+        // `x` may be found without moving, `x!` may not
+        assert_eq!(ranges("+x.", "+.+>.", false), [(0, 2), (2, 5)]);
+        assert_eq!(ranges("+x!.", "+.+>.", false), [(2, 5)]);
+        assert_eq!(ranges("+x!<.", "+>.+<.+<<.", false), [(3, 6), (6, 10)]);
+        assert_eq!(ranges("+x!>=2.", "+>.+>>.+>>>.", false), [(3, 7), (7, 12)]);
+        assert_eq!(ranges("+x!-2.", "+<.+<<.+<<<.", false), [(3, 7)]);
+    }
+
+    #[test]
+    fn classes_match_any_of_their_instructions() {
+        assert_eq!(ranges("%arith%move", "+>-<.", false), [(0, 2), (2, 4)]);
+        assert_eq!(ranges("[%arith]", "[+][-][>]", false), [(0, 3), (3, 6)]);
+        assert_eq!(ranges("%io%io", ",..", false), [(0, 2)]);
+        assert_eq!(
+            mismatch("+%io", "++"),
+            (1, "expected `.` or `,`, found `+`".to_string())
+        );
     }
 }
@@ -0,0 +1,631 @@
+//! Machine readable output formats for the matches.
+
+use crate::library::PatternLibrary;
+use crate::r#match::LibraryMatch;
+use crate::tape::PointerTrace;
+use bfrs_common::{BFCommand, Location};
+use serde::Serialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::Range;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Format {
+    Text,
+    Json,
+    Sarif,
+}
+
+impl Format {
+    pub const VARIANTS: &'static [&'static str] = &["text", "json", "sarif"];
+}
+
+impl FromStr for Format {
+    type Err = UnknownFormat;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "text" => Self::Text,
+            "json" => Self::Json,
+            "sarif" => Self::Sarif,
+            _ => return Err(UnknownFormat(s.to_string())),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct UnknownFormat(String);
+
+impl fmt::Display for UnknownFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown output format: {:?}", self.0)
+    }
+}
+
+impl std::error::Error for UnknownFormat {}
+
+#[derive(Serialize)]
+pub struct JsonMatch<'l> {
+    /// the name of the pattern that matched, only for libraries
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<&'l str>,
+    /// byte offset of the first matched instruction
+    pub start: usize,
+    /// byte offset right after the last matched instruction
+    pub end: usize,
+    pub start_line: usize,
+    pub start_column: usize,
+    pub end_line: usize,
+    pub end_column: usize,
+    pub text: String,
+    /// the offsets from every binding to the others
+    pub bindings: BTreeMap<&'l str, BTreeMap<&'l str, isize>>,
+    /// the cell of every binding on the tape, only when
+    /// given a start cell and the pointer can be followed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cells: Option<BTreeMap<&'l str, isize>>,
+    /// the text matched by every capture group
+    pub captures: BTreeMap<&'l str, String>,
+}
+
+/// A region of the source that no pattern matched.
+#[derive(Serialize)]
+pub struct JsonRegion {
+    /// byte offset of the first instruction in the region
+    pub start: usize,
+    /// byte offset right after the last instruction in the region
+    pub end: usize,
+    pub start_line: usize,
+    pub start_column: usize,
+    pub end_line: usize,
+    pub end_column: usize,
+    pub text: String,
+}
+
+/// How often a pattern matched.
+#[derive(Serialize)]
+pub struct PatternStats<'l> {
+    pub name: &'l str,
+    pub matches: usize,
+    /// amount of instructions covered by the matches
+    pub covered: usize,
+}
+
+/// A summary of the matches of a library.
+#[derive(Serialize)]
+pub struct Stats<'l> {
+    /// the stats of every pattern, in library order
+    pub patterns: Vec<PatternStats<'l>>,
+    pub matches: usize,
+    pub covered: usize,
+    /// amount of instructions searched
+    pub instructions: usize,
+    /// percentage of the instructions covered by a match
+    pub coverage: f64,
+}
+
+pub fn stats<'l>(
+    matches: &[LibraryMatch],
+    library: &'l PatternLibrary,
+    instructions: usize,
+) -> Stats<'l> {
+    let mut patterns: Vec<_> = library
+        .entries
+        .iter()
+        .map(|entry| PatternStats {
+            name: &entry.name,
+            matches: 0,
+            covered: 0,
+        })
+        .collect();
+    for found in matches.iter() {
+        let stats = &mut patterns[found.pattern];
+        stats.matches += 1;
+        stats.covered += found.result.commands.len();
+    }
+    let covered = patterns.iter().map(|stats| stats.covered).sum();
+    Stats {
+        patterns,
+        matches: matches.len(),
+        covered,
+        instructions,
+        coverage: coverage(covered, instructions),
+    }
+}
+
+impl Stats<'_> {
+    /// Adds the stats of another search for the same library,
+    /// like the one of another file of a corpus.
+    pub fn add(&mut self, other: &Stats) {
+        for (stats, other) in self.patterns.iter_mut().zip(other.patterns.iter()) {
+            stats.matches += other.matches;
+            stats.covered += other.covered;
+        }
+        self.matches += other.matches;
+        self.covered += other.covered;
+        self.instructions += other.instructions;
+        self.coverage = coverage(self.covered, self.instructions);
+    }
+}
+
+/// How often every pattern of a library matched across a corpus.
+#[derive(Serialize)]
+pub struct Ranking<'l> {
+    /// the patterns, in library order until [`Ranking::sort`]
+    pub patterns: Vec<RankedPattern<'l>>,
+    /// amount of files searched
+    pub files: usize,
+}
+
+#[derive(Serialize)]
+pub struct RankedPattern<'l> {
+    pub name: &'l str,
+    pub matches: usize,
+    /// amount of instructions covered by the matches
+    pub covered: usize,
+    /// amount of files it matched in
+    pub files: usize,
+    /// how often it matched in every file it matched in, in search order
+    pub by_file: Vec<FileMatches>,
+}
+
+#[derive(Serialize)]
+pub struct FileMatches {
+    pub file: String,
+    pub matches: usize,
+}
+
+impl<'l> Ranking<'l> {
+    pub fn new(library: &'l PatternLibrary) -> Self {
+        let patterns = library
+            .entries
+            .iter()
+            .map(|entry| RankedPattern {
+                name: &entry.name,
+                matches: 0,
+                covered: 0,
+                files: 0,
+                by_file: Vec::new(),
+            })
+            .collect();
+        Self { patterns, files: 0 }
+    }
+
+    /// Adds the stats of the search of a file.
+    pub fn add(&mut self, file: &str, stats: &Stats) {
+        self.files += 1;
+        for (ranked, stats) in self.patterns.iter_mut().zip(stats.patterns.iter()) {
+            if stats.matches == 0 {
+                continue;
+            }
+            ranked.matches += stats.matches;
+            ranked.covered += stats.covered;
+            ranked.files += 1;
+            ranked.by_file.push(FileMatches {
+                file: file.to_string(),
+                matches: stats.matches,
+            });
+        }
+    }
+
+    /// Puts the patterns that matched the most first, and of those
+    /// that matched as often, the ones that did in more files.
+    pub fn sort(&mut self) {
+        self.patterns
+            .sort_by_key(|pattern| std::cmp::Reverse((pattern.matches, pattern.files)));
+    }
+}
+
+fn coverage(covered: usize, instructions: usize) -> f64 {
+    if instructions == 0 {
+        0.0
+    } else {
+        covered as f64 * 100.0 / instructions as f64
+    }
+}
+
+/// Prepare the matches to be serialized. `locations` holds the location
+/// in the source of every instruction.
+pub fn json_report<'l>(
+    matches: &[LibraryMatch],
+    library: &'l PatternLibrary,
+    labeled: bool,
+    locations: &[Location],
+    trace: Option<&PointerTrace>,
+) -> Vec<JsonMatch<'l>> {
+    matches
+        .iter()
+        .map(|found| {
+            let entry = &library.entries[found.pattern];
+            let named = found.result.named(&entry.scope);
+            let (start, end) = found.result.locate(locations);
+            JsonMatch {
+                pattern: Some(entry.name.as_str()).filter(|_| labeled),
+                start: start.byte_offset,
+                end: end.byte_offset,
+                start_line: start.position.line,
+                start_column: start.position.column,
+                end_line: end.position.line,
+                end_column: end.position.column,
+                text: found
+                    .result
+                    .commands
+                    .iter()
+                    .map(|&i| i as u8 as char)
+                    .collect(),
+                bindings: named.relative_offsets,
+                cells: trace
+                    .and_then(|trace| trace.cells(&found.result))
+                    .map(|cells| {
+                        cells
+                            .into_iter()
+                            .enumerate()
+                            .map(|(key, cell)| {
+                                (
+                                    entry.scope.bindings.get_by_left(&key).unwrap().as_str(),
+                                    cell,
+                                )
+                            })
+                            .collect()
+                    }),
+                captures: named
+                    .captures
+                    .into_iter()
+                    .map(|(name, commands)| {
+                        (name, commands.iter().map(|&i| i as u8 as char).collect())
+                    })
+                    .collect(),
+            }
+        })
+        .collect()
+}
+
+/// Prepare the regions no pattern matched to be serialized, given
+/// as ranges of instructions.
+pub fn json_gaps_report(
+    gaps: &[Range<usize>],
+    instructions: &[BFCommand],
+    locations: &[Location],
+) -> Vec<JsonRegion> {
+    gaps.iter()
+        .map(|gap| {
+            let (start, end) = locate_range(gap, locations);
+            JsonRegion {
+                start: start.byte_offset,
+                end: end.byte_offset,
+                start_line: start.position.line,
+                start_column: start.position.column,
+                end_line: end.position.line,
+                end_column: end.position.column,
+                text: instructions[gap.clone()]
+                    .iter()
+                    .map(|&i| i as u8 as char)
+                    .collect(),
+            }
+        })
+        .collect()
+}
+
+/// Where a range of instructions starts and ends in the source.
+pub fn locate_range(range: &Range<usize>, locations: &[Location]) -> (Location, Location) {
+    (
+        locations[range.start],
+        locations[range.end - 1].after_command(),
+    )
+}
+
+/// Build a SARIF 2.1.0 log with a result for every match, so
+/// they show up as annotations in tools that understand the format.
+pub fn sarif_report(
+    matches: &[LibraryMatch],
+    library: &PatternLibrary,
+    path: &str,
+    locations: &[Location],
+) -> serde_json::Value {
+    sarif_log(
+        sarif_rules(library),
+        sarif_results(matches, library, path, locations),
+    )
+}
+
+/// A SARIF rule for every pattern of the library.
+pub fn sarif_rules(library: &PatternLibrary) -> Vec<serde_json::Value> {
+    library
+        .entries
+        .iter()
+        .map(|entry| json!({ "id": entry.name }))
+        .collect()
+}
+
+/// The SARIF results of the matches in a file, to put in
+/// a log along with the ones of other files.
+pub fn sarif_results(
+    matches: &[LibraryMatch],
+    library: &PatternLibrary,
+    path: &str,
+    locations: &[Location],
+) -> Vec<serde_json::Value> {
+    matches
+        .iter()
+        .map(|found| {
+            let name = &library.entries[found.pattern].name;
+            let (start, end) = found.result.locate(locations);
+            json!({
+                "ruleId": name,
+                "ruleIndex": found.pattern,
+                "level": "note",
+                "message": { "text": format!("matched pattern `{}`", name) },
+                "locations": [sarif_location(path, start, end)]
+            })
+        })
+        .collect()
+}
+
+/// Build a SARIF 2.1.0 log with a result for every region no pattern matched.
+pub fn sarif_gaps_report(
+    gaps: &[Range<usize>],
+    path: &str,
+    locations: &[Location],
+) -> serde_json::Value {
+    sarif_log(
+        vec![json!({ "id": "uncovered" })],
+        sarif_gaps_results(gaps, path, locations),
+    )
+}
+
+/// The SARIF results of the regions of a file no pattern matched.
+pub fn sarif_gaps_results(
+    gaps: &[Range<usize>],
+    path: &str,
+    locations: &[Location],
+) -> Vec<serde_json::Value> {
+    gaps.iter()
+        .map(|gap| {
+            let (start, end) = locate_range(gap, locations);
+            json!({
+                "ruleId": "uncovered",
+                "ruleIndex": 0,
+                "level": "note",
+                "message": { "text": "not matched by any pattern" },
+                "locations": [sarif_location(path, start, end)]
+            })
+        })
+        .collect()
+}
+
+fn sarif_location(path: &str, start: Location, end: Location) -> serde_json::Value {
+    json!({
+        "physicalLocation": {
+            "artifactLocation": { "uri": path },
+            "region": {
+                "startLine": start.position.line,
+                "startColumn": start.position.column,
+                "endLine": end.position.line,
+                "endColumn": end.position.column,
+                "byteOffset": start.byte_offset,
+                "byteLength": end.byte_offset - start.byte_offset,
+            }
+        }
+    })
+}
+
+/// A SARIF 2.1.0 log with a single run of the tool.
+pub fn sarif_log(
+    rules: Vec<serde_json::Value>,
+    results: Vec<serde_json::Value>,
+) -> serde_json::Value {
+    json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "bfrs_patterns",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "rules": rules,
+                }
+            },
+            "results": results,
+        }]
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::parse_library;
+    use crate::r#match::MatchSM;
+    use bfrs_common::parser::{parse_located, parse_str};
+
+    #[test]
+    fn empty_matches_count_but_cover_nothing() {
+        let library = parse_library("binding = x").unwrap();
+        let instructions = parse_str("+>-").unwrap();
+        let found = MatchSM::find_all_in_library(&instructions, &library);
+        let stats = stats(&found, &library, instructions.len());
+        assert_eq!(stats.matches, 3);
+        assert_eq!(stats.covered, 1);
+        assert_eq!(stats.patterns[0].covered, 1);
+    }
+
+    fn locations(src: &str) -> (Vec<BFCommand>, Vec<Location>) {
+        parse_located(src.bytes().map(Ok))
+            .map(Result::unwrap)
+            .unzip()
+    }
+
+    #[test]
+    fn json_matches() {
+        let library = parse_library("clear = [-]\nmove = a[-b+a](rest: _*)").unwrap();
+        let (instructions, locations) = locations("+\n[-] [->+<]>");
+        let found = MatchSM::find_all_in_library(&instructions, &library);
+        let report = json_report(&found, &library, true, &locations, None);
+        let report = serde_json::to_value(&report).unwrap();
+        assert_eq!(
+            report,
+            json!([
+                {
+                    "pattern": "clear",
+                    "start": 2, "end": 5,
+                    "start_line": 2, "start_column": 1,
+                    "end_line": 2, "end_column": 4,
+                    "text": "[-]",
+                    "bindings": {},
+                    "captures": {},
+                },
+                {
+                    "pattern": "move",
+                    "start": 6, "end": 13,
+                    "start_line": 2, "start_column": 5,
+                    "end_line": 2, "end_column": 12,
+                    "text": "[->+<]>",
+                    "bindings": { "a": { "b": -1 }, "b": { "a": 1 } },
+                    "captures": { "rest": ">" },
+                },
+            ])
+        );
+
+        let unlabeled = json_report(&found[..1], &library, false, &locations, None);
+        assert!(serde_json::to_value(&unlabeled).unwrap()[0]
+            .get("pattern")
+            .is_none());
+    }
+
+    #[test]
+    fn json_gaps() {
+        let (instructions, locations) = locations("+\n[-]>");
+        let report = json_gaps_report(&[0..1, 4..5], &instructions, &locations);
+        let report = serde_json::to_value(&report).unwrap();
+        assert_eq!(report[0]["text"], "+");
+        assert_eq!(report[1]["text"], ">");
+        assert_eq!(report[1]["start_line"], 2);
+        assert_eq!(report[1]["start_column"], 4);
+        assert_eq!(report[1]["end"], 6);
+    }
+
+    #[test]
+    fn sarif_results_point_at_the_matches() {
+        let library = parse_library("clear = [-]\nreset = [+]").unwrap();
+        let (instructions, locations) = locations("+\n[+]");
+        let found = MatchSM::find_all_in_library(&instructions, &library);
+        let log = sarif_report(&found, &library, "a.b", &locations);
+        assert_eq!(log["version"], "2.1.0");
+        let run = &log["runs"][0];
+        assert_eq!(
+            run["tool"]["driver"]["rules"],
+            json!([{ "id": "clear" }, { "id": "reset" }])
+        );
+        assert_eq!(
+            run["results"],
+            json!([{
+                "ruleId": "reset",
+                "ruleIndex": 1,
+                "level": "note",
+                "message": { "text": "matched pattern `reset`" },
+                "locations": [{
+                    "physicalLocation": {
+                        "artifactLocation": { "uri": "a.b" },
+                        "region": {
+                            "startLine": 2, "startColumn": 1,
+                            "endLine": 2, "endColumn": 4,
+                            "byteOffset": 2, "byteLength": 3,
+                        }
+                    }
+                }]
+            }])
+        );
+    }
+
+    #[test]
+    fn sarif_gaps() {
+        let (_, locations) = locations("+[-]>>");
+        let log = sarif_gaps_report(&[0..1, 4..6], "a.b", &locations);
+        let run = &log["runs"][0];
+        assert_eq!(
+            run["tool"]["driver"]["rules"],
+            json!([{ "id": "uncovered" }])
+        );
+        let results = run["results"].as_array().unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[1]["ruleId"], "uncovered");
+        let region = &results[1]["locations"][0]["physicalLocation"]["region"];
+        assert_eq!(region["startColumn"], 5);
+        assert_eq!(region["byteOffset"], 4);
+        assert_eq!(region["byteLength"], 2);
+    }
+
+    #[test]
+    fn stats_of_every_pattern() {
+        let library = parse_library("clear = [-]\nmove = x[-y+x]\nprint = .").unwrap();
+        let instructions = parse_str("[-]>[->+<]>[-]++").unwrap();
+        let found = MatchSM::find_all_in_library(&instructions, &library);
+        let mut stats = stats(&found, &library, instructions.len());
+        let summary = |stats: &Stats| -> Vec<(String, usize, usize)> {
+            stats
+                .patterns
+                .iter()
+                .map(|pattern| (pattern.name.to_string(), pattern.matches, pattern.covered))
+                .collect()
+        };
+        assert_eq!(
+            summary(&stats),
+            [
+                ("clear".to_string(), 2, 6),
+                ("move".to_string(), 1, 7),
+                ("print".to_string(), 0, 0)
+            ]
+        );
+        assert_eq!(
+            (stats.matches, stats.covered, stats.instructions),
+            (3, 13, 16)
+        );
+        assert_eq!(stats.coverage, 81.25);
+
+        // the stats of a corpus add up the ones of its files
+        let more = parse_str("[-]..").unwrap();
+        let found = MatchSM::find_all_in_library(&more, &library);
+        stats.add(&super::stats(&found, &library, more.len()));
+        assert_eq!(summary(&stats)[0], ("clear".to_string(), 3, 9));
+        assert_eq!(summary(&stats)[2], ("print".to_string(), 2, 2));
+        assert_eq!(
+            (stats.matches, stats.covered, stats.instructions),
+            (6, 18, 21)
+        );
+    }
+
+    #[test]
+    fn patterns_are_ranked_by_their_matches() {
+        let library = parse_library("clear = [-]\nprint = .\nread = ,").unwrap();
+        let mut ranking = Ranking::new(&library);
+        for (file, src) in [("a.b", "[-].."), ("b.b", "[-]."), ("c.b", "[-]")].iter() {
+            let instructions = parse_str(src).unwrap();
+            let found = MatchSM::find_all_in_library(&instructions, &library);
+            ranking.add(file, &stats(&found, &library, instructions.len()));
+        }
+        ranking.sort();
+        let ranked: Vec<_> = ranking
+            .patterns
+            .iter()
+            .map(|pattern| {
+                (
+                    pattern.name,
+                    pattern.matches,
+                    pattern.files,
+                    pattern.covered,
+                )
+            })
+            .collect();
+        // as many matches as `print`, but in more files
+        assert_eq!(
+            ranked,
+            [("clear", 3, 3, 9), ("print", 3, 2, 3), ("read", 0, 0, 0)]
+        );
+        assert_eq!(ranking.files, 3);
+        let json = serde_json::to_value(&ranking).unwrap();
+        assert_eq!(
+            json["patterns"][1]["by_file"],
+            json!([{ "file": "a.b", "matches": 2 }, { "file": "b.b", "matches": 1 }])
+        );
+    }
+}
@@ -0,0 +1,161 @@
+//! Splitting a search across threads.
+//!
+//! The instructions are split in chunks, and every chunk is searched on its
+//! own, starting at its first instruction. A match found in a chunk may
+//! run past its end, overlapping the next chunk. When merging, the results
+//! of a chunk are only kept from the point where its search lines up with
+//! where the previous match actually ended, the gap between both is searched
+//! again. The result is the same as searching sequentially.
+
+use rayon::prelude::*;
+
+/// Find every leftmost-first, non-overlapping match in `len` instructions,
+/// searching chunks of `chunk_size` instructions in parallel. `match_at`
+/// returns how far the search moves on after the match at an offset, if
+/// there is one, using scratch state created by `init` once for every chunk.
+pub(crate) fn find_all_chunked<M, S, I, F>(
+    len: usize,
+    chunk_size: usize,
+    init: I,
+    match_at: F,
+) -> Vec<M>
+where
+    M: Send,
+    I: Fn() -> S + Sync,
+    F: Fn(&mut S, usize) -> Option<(usize, M)> + Sync,
+{
+    assert!(chunk_size > 0, "chunks can't be empty");
+    let chunks: Vec<Vec<(usize, usize, M)>> = (0..len)
+        .step_by(chunk_size)
+        .collect::<Vec<_>>()
+        .into_par_iter()
+        .map(|chunk_start| {
+            let chunk_end = (chunk_start + chunk_size).min(len);
+            let mut state = init();
+            let mut offset = chunk_start;
+            let mut found = Vec::new();
+            while offset < chunk_end {
+                if let Some((match_len, res)) = match_at(&mut state, offset) {
+                    found.push((offset, offset + match_len, res));
+                    offset += match_len;
+                } else {
+                    offset += 1;
+                }
+            }
+            found
+        })
+        .collect();
+
+    let mut state = init();
+    let mut result = Vec::new();
+    // where the sequential search would continue
+    let mut next = 0;
+    for (chunk_i, found) in chunks.into_iter().enumerate() {
+        let chunk_end = ((chunk_i + 1) * chunk_size).min(len);
+        let mut found = found.into_iter().peekable();
+        while next < chunk_end {
+            // matches of the chunk that end before the search
+            // point are already covered by the previous ones.
+            while found.peek().is_some_and(|(_, end, _)| *end <= next) {
+                found.next();
+            }
+            match found.peek() {
+                // the search point is in the middle of a match of the chunk,
+                // so the chunk isn't in sync yet: search sequentially.
+                Some((start, _, _)) if *start < next => {
+                    if let Some((match_len, res)) = match_at(&mut state, next) {
+                        result.push(res);
+                        next += match_len;
+                    } else {
+                        next += 1;
+                    }
+                }
+                // the chunk searched through the same point, so the
+                // rest of its matches are the same as searching sequentially.
+                _ => {
+                    for (_, end, res) in found.by_ref() {
+                        result.push(res);
+                        next = end;
+                    }
+                    break;
+                }
+            }
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::automaton::CompiledLibrary;
+    use crate::library::parse_library;
+    use crate::pattern::parse_pattern;
+    use crate::r#match::{LibraryMatch, MatchSM};
+    use bfrs_common::parser::parse_str;
+
+    /// Matches runs of the same letter, from 2 to 4 long, so matches
+    /// depend on where the search starts and cross chunk boundaries.
+    fn runs(text: &str, chunk_size: usize) -> Vec<(usize, usize)> {
+        let text = text.as_bytes();
+        find_all_chunked(
+            text.len(),
+            chunk_size,
+            || (),
+            |_, offset| {
+                let len = text[offset..]
+                    .iter()
+                    .take(4)
+                    .take_while(|&&ch| ch == text[offset])
+                    .count();
+                Some((len, (offset, offset + len))).filter(|_| len >= 2)
+            },
+        )
+    }
+
+    #[test]
+    fn same_as_searching_sequentially() {
+        let text = "aaaaabbbcabbbbbbaaccccc";
+        let expected = runs(text, text.len());
+        assert_eq!(
+            expected,
+            [(0, 4), (5, 8), (10, 14), (14, 16), (16, 18), (18, 22)]
+        );
+        for chunk_size in 1..text.len() {
+            assert_eq!(runs(text, chunk_size), expected, "{}", chunk_size);
+        }
+    }
+
+    #[test]
+    fn matchers_search_in_parallel() {
+        let instructions = parse_str("[->+<]>>[-]>[->>+<<]+[-]-[->+<]").unwrap();
+        let scope = parse_pattern("x[-y+x]").unwrap();
+        let expected: Vec<_> = MatchSM::find_all(&instructions, &scope)
+            .iter()
+            .map(|res| res.start)
+            .collect();
+        assert_eq!(expected, [0, 11, 25]);
+        for &chunk_size in [1, 3, 7, 100].iter() {
+            let found = MatchSM::par_find_all(&instructions, &scope, chunk_size);
+            let starts: Vec<_> = found.iter().map(|res| res.start).collect();
+            assert_eq!(starts, expected);
+        }
+
+        let library = parse_library("clear = [-]\nmove = x[-y+x]").unwrap();
+        let compiled = CompiledLibrary::new(&library);
+        let spots = |found: &[LibraryMatch]| -> Vec<(usize, usize)> {
+            found
+                .iter()
+                .map(|found| (found.pattern, found.result.start))
+                .collect()
+        };
+        let expected = spots(&compiled.find_all(&instructions));
+        assert_eq!(expected, [(1, 0), (0, 8), (1, 11), (0, 21), (1, 25)]);
+        for &chunk_size in [1, 3, 7, 100].iter() {
+            assert_eq!(
+                spots(&compiled.par_find_all(&instructions, chunk_size)),
+                expected
+            );
+        }
+    }
+}
@@ -1,7 +1,12 @@
-//! New way of managing patterns
+//! The pattern model and its parser.
+//!
+//! A pattern is a tree: loops and capture groups hold the patterns of
+//! their body, and every name used in it (bindings, counts and captures)
+//! is registered in a single [`PatternScope`] table, so a binding means
+//! the same cell inside and outside of a loop.
 
 use bfrs_common::errors as bfrs_errors;
-use bfrs_common::{BFCommand, Position};
+use bfrs_common::{BFCommand, Location, Position};
 use bimap::BiMap;
 use std::error::Error;
 use std::fmt;
@@ -9,6 +14,14 @@ use std::fmt;
 #[derive(Debug)]
 pub struct PatternScope {
     pub bindings: BiMap<usize, String>,
+    /// names of the instruction counts captured by [`Pattern::Repeat`]
+    pub counts: BiMap<usize, String>,
+    /// names of the sub-ranges captured by [`Pattern::Capture`]
+    pub captures: BiMap<usize, String>,
+    /// how many [`Pattern::Wildcard`]s there are, numbered in order
+    pub wildcards: usize,
+    /// the lengths a whole match can have, in instructions
+    pub length: Bound,
     pub patterns: Vec<Pattern>,
 }
 
@@ -19,75 +32,890 @@ pub enum Pattern {
     /// A single binding
     Binding {
         index: usize,
-        /// A strict binding match ends with `!` and requires
-        /// to have an offset with the last binding encountered more than zero.
-        strict: bool,
+        /// what the movement right before the binding has to be
+        movement: Movement,
     },
+    /// A balanced loop, written `[ ... ]`. The body must match
+    /// everything between the loop's opening and its *matching* closing
+    /// bracket, so inner loops can't be closed by accident.
+    Loop(Vec<Pattern>),
+    /// Any balanced loop, written `@loop`. The loop is matched
+    /// as an opaque unit, whatever its body is.
+    AnyLoop,
+    /// A folded run of instructions, like the ones in [`ir::Op`], written
+    /// `@add` or `@move`. The whole run of `+-` or `<>` is matched, and its
+    /// net amount has to be within the bound, written like `@add(>=10)`.
+    ///
+    /// [`ir::Op`]: crate::ir::Op
+    Run { kind: RunKind, bound: Bound },
+    /// A loop clearing the current cell, `[-]` or `[+]`, written `@zero`.
+    Zero,
+    /// A single instruction of a class, like `+` or `-`, written `%arith`.
+    Class(CommandClass),
+    /// Any single instruction but a bracket, written `_`.
+    AnyInstruction,
+    /// Any run of one or more instructions with balanced brackets, written
+    /// `_*`. The longest one the rest of the pattern matches after is taken.
+    /// Its length can be bounded instead, written like `_{2,10}`, `_{,20}` or `_{5}`,
+    /// where a missing minimum is 1, and `_{0,..}` can be empty.
+    Wildcard { index: usize, length: Bound },
+    /// The same instructions a capture group closed before matched,
+    /// written `\name`.
+    Backreference(usize),
+    /// One or more repetitions of an instruction, written `+*n`.
+    /// The amount is captured under the name after the `*`, and must
+    /// be the same everywhere that name is used.
+    Repeat {
+        instruction: BFCommand,
+        count: usize,
+    },
+    /// A named group, written `(name: ...)`. The instructions
+    /// matched by the body are captured under that name, and how many
+    /// they are can be bounded, written like `(name: ...){,20}`.
+    Capture {
+        index: usize,
+        body: Vec<Pattern>,
+        length: Bound,
+    },
+}
+
+impl PatternScope {
+    /// Start building a pattern in code, instead of parsing it.
+    pub fn builder() -> PatternBuilder {
+        PatternBuilder::default()
+    }
+}
+
+/// Builds a [`PatternScope`] item by item, registering the names
+/// as they're used, the same way the parser does:
+/// ```
+/// use bfrs_common::BFCommand;
+/// use bfrs_patterns::pattern::PatternScope;
+///
+/// // x[-y+x]
+/// let scope = PatternScope::builder()
+///     .binding("x")
+///     .group(|body| {
+///         body.instr(BFCommand::Decrement)
+///             .binding("y")
+///             .instr(BFCommand::Increment)
+///             .binding("x")
+///     })
+///     .build();
+/// assert_eq!(scope.bindings.len(), 2);
+/// ```
+#[derive(Debug, Default)]
+pub struct PatternBuilder {
+    bindings: BiMap<usize, String>,
+    counts: BiMap<usize, String>,
+    captures: BiMap<usize, String>,
+    wildcards: usize,
+    patterns: Vec<Pattern>,
+}
+
+impl PatternBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A single instruction, like `+`
+    pub fn instr(mut self, instr: BFCommand) -> Self {
+        self.patterns.push(Pattern::Instruction(instr));
+        self
+    }
+
+    /// A binding, like `x`
+    pub fn binding(mut self, name: &str) -> Self {
+        let index = index_of(&mut self.bindings, name.to_string());
+        self.patterns.push(Pattern::Binding {
+            index,
+            movement: Movement::Any,
+        });
+        self
+    }
+
+    /// A strict binding, like `x!`
+    pub fn strict_binding(self, name: &str) -> Self {
+        self.binding_moving(name, Movement::Required)
+    }
+
+    /// A binding requiring some movement before it, like `x!>=2`
+    pub fn binding_moving(mut self, name: &str, movement: Movement) -> Self {
+        let index = index_of(&mut self.bindings, name.to_string());
+        self.patterns.push(Pattern::Binding { index, movement });
+        self
+    }
+
+    /// Repetitions of an instruction, like `+*n`
+    pub fn repeat(mut self, instruction: BFCommand, count: &str) -> Self {
+        let count = index_of(&mut self.counts, count.to_string());
+        self.patterns.push(Pattern::Repeat { instruction, count });
+        self
+    }
+
+    /// A balanced loop, like `[ ... ]`, whose body is built by `body`.
+    pub fn group(mut self, body: impl FnOnce(Self) -> Self) -> Self {
+        let outer = std::mem::take(&mut self.patterns);
+        let mut built = body(self);
+        let body = std::mem::replace(&mut built.patterns, outer);
+        built.patterns.push(Pattern::Loop(body));
+        built
+    }
+
+    /// Any balanced loop, like `@loop`
+    pub fn any_loop(mut self) -> Self {
+        self.patterns.push(Pattern::AnyLoop);
+        self
+    }
+
+    /// A folded run of instructions, like `@add(>=10)`
+    pub fn run(mut self, kind: RunKind, bound: Bound) -> Self {
+        self.patterns.push(Pattern::Run { kind, bound });
+        self
+    }
+
+    /// A loop clearing the current cell, like `@zero`
+    pub fn zero(mut self) -> Self {
+        self.patterns.push(Pattern::Zero);
+        self
+    }
+
+    /// A single instruction of a class, like `%arith`
+    pub fn class(mut self, class: CommandClass) -> Self {
+        self.patterns.push(Pattern::Class(class));
+        self
+    }
+
+    /// Any single instruction but a bracket, like `_`
+    pub fn any_instruction(mut self) -> Self {
+        self.patterns.push(Pattern::AnyInstruction);
+        self
+    }
+
+    /// Any balanced run of instructions, like `_*`
+    pub fn wildcard(self) -> Self {
+        self.wildcard_within(Bound {
+            min: Some(1),
+            max: None,
+        })
+    }
+
+    /// Any balanced run of instructions within a length, like `_{2,10}`
+    pub fn wildcard_within(mut self, length: Bound) -> Self {
+        self.patterns.push(Pattern::Wildcard {
+            index: self.wildcards,
+            length,
+        });
+        self.wildcards += 1;
+        self
+    }
+
+    /// The instructions a capture group matched, like `\name`.
+    ///
+    /// # Panics
+    /// If there's no capture group with that name.
+    pub fn backreference(mut self, name: &str) -> Self {
+        let index = *self
+            .captures
+            .get_by_right(name)
+            .unwrap_or_else(|| panic!("capture `{}` is not defined", name));
+        self.patterns.push(Pattern::Backreference(index));
+        self
+    }
+
+    /// A capture group, like `(name: ...)`, whose body is built by `body`.
+    ///
+    /// # Panics
+    /// If there already is a capture group with the same name.
+    pub fn capture(self, name: &str, body: impl FnOnce(Self) -> Self) -> Self {
+        let length = Bound {
+            min: Some(0),
+            max: None,
+        };
+        self.capture_within(name, length, body)
+    }
+
+    /// A capture group matching a number of instructions within
+    /// `length`, like `(name: ...){,20}`.
+    ///
+    /// # Panics
+    /// If there already is a capture group with the same name.
+    pub fn capture_within(
+        mut self,
+        name: &str,
+        length: Bound,
+        body: impl FnOnce(Self) -> Self,
+    ) -> Self {
+        assert!(
+            !self.captures.contains_right(name),
+            "capture `{}` is defined more than once",
+            name
+        );
+        let index = index_of(&mut self.captures, name.to_string());
+        let outer = std::mem::take(&mut self.patterns);
+        let mut built = body(self);
+        let body = std::mem::replace(&mut built.patterns, outer);
+        built.patterns.push(Pattern::Capture {
+            index,
+            body,
+            length,
+        });
+        built
+    }
+
+    pub fn build(self) -> PatternScope {
+        PatternScope {
+            bindings: self.bindings,
+            counts: self.counts,
+            captures: self.captures,
+            wildcards: self.wildcards,
+            length: Bound::default(),
+            patterns: self.patterns,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunKind {
+    /// `+` and `-`
+    Add,
+    /// `>` and `<`
+    Move,
+}
+
+/// The instructions a [`Pattern::Class`] matches any one of.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandClass {
+    /// `+` and `-`, written `%arith`
+    Arith,
+    /// `>` and `<`, written `%move`
+    Move,
+    /// `.` and `,`, written `%io`
+    Io,
+}
+
+impl CommandClass {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "arith" => Some(Self::Arith),
+            "move" => Some(Self::Move),
+            "io" => Some(Self::Io),
+            _ => None,
+        }
+    }
+
+    pub fn commands(self) -> [BFCommand; 2] {
+        match self {
+            Self::Arith => [BFCommand::Increment, BFCommand::Decrement],
+            Self::Move => [BFCommand::Right, BFCommand::Left],
+            Self::Io => [BFCommand::Print, BFCommand::Read],
+        }
+    }
+
+    pub fn contains(self, instr: BFCommand) -> bool {
+        self.commands().contains(&instr)
+    }
+}
+
+/// What a [`Pattern::Binding`] requires of the movement right before it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Movement {
+    /// any movement, or none, written `x`
+    #[default]
+    Any,
+    /// any movement but none, written `x!`
+    Required,
+    /// a movement within a bound, positive to the right, written after
+    /// the `!`: `x!>` moves right, `x!<` left, `x!>=2` at least two
+    /// cells right and `x!0` doesn't move.
+    Within(Bound),
+}
+
+impl Movement {
+    pub fn allows(&self, moved: isize) -> bool {
+        match self {
+            Self::Any => true,
+            Self::Required => moved != 0,
+            Self::Within(bound) => bound.contains(moved),
+        }
+    }
+}
+
+/// Inclusive limits for the amount of a [`Pattern::Run`], or for a length.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Bound {
+    pub min: Option<isize>,
+    pub max: Option<isize>,
+}
+
+impl Bound {
+    pub fn contains(&self, amount: isize) -> bool {
+        self.min.is_none_or(|min| amount >= min) && self.max.is_none_or(|max| amount <= max)
+    }
+
+    /// Parses `n`, `>=n`, `<=n`, `>n` or `<n`.
+    fn parse(src: &str) -> Option<Self> {
+        let src = src.trim();
+        let (op, amount) = match src.find(|ch: char| ch == '-' || ch.is_ascii_digit()) {
+            Some(i) => src.split_at(i),
+            None => return None,
+        };
+        let amount: isize = amount.parse().ok()?;
+        Some(match op.trim() {
+            "" => Self {
+                min: Some(amount),
+                max: Some(amount),
+            },
+            ">=" => Self {
+                min: Some(amount),
+                max: None,
+            },
+            "<=" => Self {
+                min: None,
+                max: Some(amount),
+            },
+            ">" => Self {
+                min: Some(amount.checked_add(1)?),
+                max: None,
+            },
+            "<" => Self {
+                min: None,
+                max: Some(amount.checked_sub(1)?),
+            },
+            _ => return None,
+        })
+    }
+}
+
+/// Parses what comes after the `!` of a binding: `>` or `<` with an optional
+/// amount, like `>=2`, or an exact amount, like `0`. A `-` is only part of the
+/// movement when a digit follows it, so `x!-` is still a strict binding and a `-`.
+fn parse_movement(
+    src: &[char],
+    offset_i: &mut usize,
+    current_pos: &mut Location,
+) -> ParseResult<Movement> {
+    let start = *offset_i;
+    let start_pos = *current_pos;
+    let mut end = start;
+    if matches!(src.get(end), Some(&'>') | Some(&'<')) {
+        end += 1;
+        if matches!(src.get(end), Some(&'=')) {
+            end += 1;
+        }
+    }
+    let amount_start = end;
+    if matches!(src.get(end), Some(&'-')) && src.get(end + 1).is_some_and(char::is_ascii_digit) {
+        end += 1;
+    }
+    while src.get(end).is_some_and(char::is_ascii_digit) {
+        end += 1;
+    }
+    if end == start {
+        return Ok(Movement::Required);
+    }
+    let op: String = src[start..amount_start].iter().collect();
+    let amount: String = src[amount_start..end].iter().collect();
+    let bound = match (op.as_str(), amount.is_empty()) {
+        // a bare direction is any movement that way.
+        (">", true) => Bound::parse(">0"),
+        ("<", true) => Bound::parse("<0"),
+        (_, true) => None,
+        _ => Bound::parse(&(op + &amount)),
+    };
+    for &ch in &src[start..end] {
+        current_pos.advance_char(ch);
+    }
+    *offset_i = end;
+    match bound {
+        Some(bound) => Ok(Movement::Within(bound)),
+        None => Err(bfrs_errors::ErrorWithSpan::new(
+            ParseError::BadMovement,
+            start_pos,
+            *current_pos,
+        )),
+    }
+}
+
+/// Parses the length after a wildcard or a capture group, if there's one:
+/// `{a,b}` for `a` to `b` instructions, where either can be missing, or
+/// `{n}` for exactly `n`. A missing minimum is `min`.
+fn parse_length(
+    src: &[char],
+    offset_i: &mut usize,
+    current_pos: &mut Location,
+    min: isize,
+) -> ParseResult<Bound> {
+    if !matches!(src.get(*offset_i), Some(&'{')) {
+        return Ok(Bound {
+            min: Some(min),
+            max: None,
+        });
+    }
+    let start_pos = *current_pos;
+    // the length goes up to the `}`, or the end of the pattern without one.
+    let len = src[*offset_i..].iter().position(|&ch| ch == '}');
+    let end = *offset_i + len.map_or(src.len() - *offset_i, |len| len + 1);
+    for &ch in &src[*offset_i..end] {
+        current_pos.advance_char(ch);
+    }
+    let inner: Option<String> = len.map(|len| src[*offset_i + 1..*offset_i + len].iter().collect());
+    *offset_i = end;
+    let amount = |src: &str| match src.trim() {
+        "" => Some(None),
+        // lengths can't be negative, and have to fit in an `isize`.
+        src => src.parse::<isize>().ok().filter(|n| *n >= 0).map(Some),
+    };
+    let length = inner.and_then(|inner| match inner.split_once(',') {
+        Some((lo, hi)) => Some(Bound {
+            min: Some(amount(lo)?.unwrap_or(min)),
+            max: amount(hi)?,
+        }),
+        None => amount(&inner)?.map(|n| Bound {
+            min: Some(n),
+            max: Some(n),
+        }),
+    });
+    match length {
+        Some(length) if length.min <= length.max || length.max.is_none() => Ok(length),
+        _ => Err(bfrs_errors::ErrorWithSpan::new(
+            ParseError::BadLength,
+            start_pos,
+            *current_pos,
+        )),
+    }
+}
+
+/// A group being parsed
+enum Group {
+    Loop,
+    Capture(usize),
 }
 
 // NOTE: will have to refactor this to
 // a structure and state management enums
 // so the parser can be streamlined
 pub fn parse_pattern(src: &str) -> ParseResult<PatternScope> {
-    let mut current_pos = Position::default();
+    parse_pattern_starting_at(src, Location::default())
+}
+
+/// Parses a pattern that doesn't have to be UTF-8, replacing the bytes
+/// that aren't with `U+FFFD`, which is then reported as an unknown character.
+pub fn parse_pattern_bytes(src: &[u8]) -> ParseResult<PatternScope> {
+    parse_pattern(&String::from_utf8_lossy(src))
+}
+
+/// Parses a pattern, setting the initial location to `start_pos`
+/// so errors point to the right place when the pattern is
+/// embedded in a bigger source, such as a library file.
+pub fn parse_pattern_starting_at(src: &str, start_pos: Location) -> ParseResult<PatternScope> {
+    let mut current_pos = start_pos;
     let src: Vec<_> = src.chars().collect();
     let mut offset_i = 0;
     let mut bindings = BiMap::new();
+    let mut counts = BiMap::new();
+    let mut captures = BiMap::new();
+    let mut wildcards = 0;
     let mut patterns = Vec::new();
+    // the patterns of every enclosing group, along with the
+    // location where that group was opened.
+    let mut group_backlog: Vec<(Group, Vec<Pattern>, Location)> = Vec::new();
     while let Some(&ch) = src.get(offset_i) {
         if ch.is_ascii() {
             if let Some(instr) = BFCommand::from_u8(ch as u8) {
+                match instr {
+                    BFCommand::BeginLoop => {
+                        group_backlog.push((
+                            Group::Loop,
+                            std::mem::take(&mut patterns),
+                            current_pos,
+                        ));
+                    }
+                    BFCommand::EndLoop => match group_backlog.pop() {
+                        Some((Group::Loop, outer, _)) => {
+                            let body = std::mem::replace(&mut patterns, outer);
+                            patterns.push(Pattern::Loop(body));
+                        }
+                        Some((Group::Capture(_), _, opened_at)) => {
+                            return Err(bfrs_errors::ErrorWithSpan::new(
+                                ParseError::UnclosedCapture {
+                                    opened_at: opened_at.position,
+                                },
+                                current_pos,
+                                current_pos.after_char(ch),
+                            ))
+                        }
+                        None => {
+                            return Err(bfrs_errors::ErrorWithSpan::new(
+                                ParseError::UnmatchedLoopClose,
+                                current_pos,
+                                current_pos.after_char(ch),
+                            ))
+                        }
+                    },
+                    _ if matches!(src.get(offset_i + 1), Some(&'*')) => {
+                        current_pos.advance_char(ch);
+                        current_pos.advance_char('*');
+                        offset_i += 2;
+                        if !src.get(offset_i).is_some_and(|ch| ch.is_alphabetic()) {
+                            return Err(bfrs_errors::ErrorWithSpan::at(
+                                ParseError::MissingCountName,
+                                current_pos,
+                            ));
+                        }
+                        let name = take_name(&src, &mut offset_i, &mut current_pos);
+                        patterns.push(Pattern::Repeat {
+                            instruction: instr,
+                            count: index_of(&mut counts, name),
+                        });
+                        continue;
+                    }
+                    _ => patterns.push(Pattern::Instruction(instr)),
+                }
                 current_pos.advance_char(ch);
                 offset_i += 1;
-                patterns.push(Pattern::Instruction(instr));
                 continue;
             }
         }
-        // as long as you don't interfere with any instruction, you can name your
-        // shit whatever you want.
-        if ch.is_alphabetic() {
-            let mut str = String::new();
-            str.push(ch);
+        if ch == '_' {
+            current_pos.advance_char(ch);
+            offset_i += 1;
+            // `_*` is any length, and `_{a,b}` one within a bound.
+            if matches!(src.get(offset_i), Some(&'*') | Some(&'{')) {
+                if src[offset_i] == '*' {
+                    current_pos.advance_char('*');
+                    offset_i += 1;
+                }
+                let length = parse_length(&src, &mut offset_i, &mut current_pos, 1)?;
+                patterns.push(Pattern::Wildcard {
+                    index: wildcards,
+                    length,
+                });
+                wildcards += 1;
+            } else {
+                patterns.push(Pattern::AnyInstruction);
+            }
+            continue;
+        } else if ch == '\\' {
+            let token_pos = current_pos;
+            current_pos.advance_char(ch);
+            offset_i += 1;
+            let name = take_name(&src, &mut offset_i, &mut current_pos);
+            // the group has to be closed already, to know what it matched.
+            let closed = captures.get_by_right(&name).filter(|&&index| {
+                !group_backlog
+                    .iter()
+                    .any(|(group, _, _)| matches!(group, Group::Capture(open) if *open == index))
+            });
+            match closed {
+                Some(&index) => patterns.push(Pattern::Backreference(index)),
+                None => {
+                    return Err(bfrs_errors::ErrorWithSpan::new(
+                        ParseError::UnknownBackreference { name },
+                        token_pos,
+                        current_pos,
+                    ))
+                }
+            }
+            continue;
+        } else if ch == '%' {
+            let token_pos = current_pos;
+            current_pos.advance_char(ch);
+            offset_i += 1;
+            let name = take_name(&src, &mut offset_i, &mut current_pos);
+            match CommandClass::from_name(&name) {
+                Some(class) => patterns.push(Pattern::Class(class)),
+                None => {
+                    return Err(bfrs_errors::ErrorWithSpan::new(
+                        ParseError::UnknownClass { name },
+                        token_pos,
+                        current_pos,
+                    ))
+                }
+            }
+            continue;
+        } else if ch == '@' {
+            let token_pos = current_pos;
+            current_pos.advance_char(ch);
+            offset_i += 1;
+            let name = take_name(&src, &mut offset_i, &mut current_pos);
+            let kind = match name.as_str() {
+                "loop" => {
+                    patterns.push(Pattern::AnyLoop);
+                    continue;
+                }
+                "zero" => {
+                    patterns.push(Pattern::Zero);
+                    continue;
+                }
+                "add" => RunKind::Add,
+                "move" => RunKind::Move,
+                _ => {
+                    return Err(bfrs_errors::ErrorWithSpan::new(
+                        ParseError::UnknownToken { name },
+                        token_pos,
+                        current_pos,
+                    ))
+                }
+            };
+            let mut bound = Bound::default();
+            if matches!(src.get(offset_i), Some(&'(')) {
+                let bound_pos = current_pos;
+                // the bound goes up to the `)`, or the end of the pattern without one.
+                let len = src[offset_i..].iter().position(|&ch| ch == ')');
+                let bound_end = offset_i + len.map_or(src.len() - offset_i, |len| len + 1);
+                for &ch in &src[offset_i..bound_end] {
+                    current_pos.advance_char(ch);
+                }
+                let bound_src: Option<String> =
+                    len.map(|len| src[offset_i + 1..offset_i + len].iter().collect());
+                bound = match bound_src.as_deref().and_then(Bound::parse) {
+                    Some(bound) => bound,
+                    None => {
+                        return Err(bfrs_errors::ErrorWithSpan::new(
+                            ParseError::BadBound,
+                            bound_pos,
+                            current_pos,
+                        ))
+                    }
+                };
+                offset_i = bound_end;
+            }
+            patterns.push(Pattern::Run { kind, bound });
+            continue;
+        } else if ch == '(' {
+            let opened_at = current_pos;
             current_pos.advance_char(ch);
             offset_i += 1;
-            while let Some(&ch) = src.get(offset_i).filter(|&&ch| ch.is_alphanumeric()) {
-                str.push(ch);
+            while let Some(&ch) = src.get(offset_i).filter(|ch| ch.is_whitespace()) {
                 current_pos.advance_char(ch);
                 offset_i += 1;
             }
-            let strict = if matches!(src.get(offset_i), Some(&'!')) {
+            let name_pos = current_pos;
+            let name = take_name(&src, &mut offset_i, &mut current_pos);
+            if name.is_empty() || !matches!(src.get(offset_i), Some(&':')) {
+                return Err(bfrs_errors::ErrorWithSpan::at(
+                    ParseError::MissingCaptureName,
+                    current_pos,
+                ));
+            }
+            if captures.contains_right(&name) {
+                return Err(bfrs_errors::ErrorWithSpan::new(
+                    ParseError::DuplicateCapture { name },
+                    name_pos,
+                    current_pos,
+                ));
+            }
+            current_pos.advance_char(':');
+            offset_i += 1;
+            let index = index_of(&mut captures, name);
+            group_backlog.push((
+                Group::Capture(index),
+                std::mem::take(&mut patterns),
+                opened_at,
+            ));
+            continue;
+        } else if ch == ')' {
+            let (index, outer) = match group_backlog.pop() {
+                Some((Group::Capture(index), outer, _)) => (index, outer),
+                Some((Group::Loop, _, opened_at)) => {
+                    return Err(bfrs_errors::ErrorWithSpan::new(
+                        ParseError::UnclosedLoop {
+                            opened_at: opened_at.position,
+                        },
+                        current_pos,
+                        current_pos.after_char(ch),
+                    ))
+                }
+                None => {
+                    return Err(bfrs_errors::ErrorWithSpan::new(
+                        ParseError::UnmatchedCaptureClose,
+                        current_pos,
+                        current_pos.after_char(ch),
+                    ))
+                }
+            };
+            current_pos.advance_char(ch);
+            offset_i += 1;
+            let length = parse_length(&src, &mut offset_i, &mut current_pos, 0)?;
+            let body = std::mem::replace(&mut patterns, outer);
+            patterns.push(Pattern::Capture {
+                index,
+                body,
+                length,
+            });
+            continue;
+        }
+        // as long as you don't interfere with any instruction, you can name your
+        // shit whatever you want.
+        if ch.is_alphabetic() {
+            let str = take_name(&src, &mut offset_i, &mut current_pos);
+            let movement = if matches!(src.get(offset_i), Some(&'!')) {
                 offset_i += 1;
                 current_pos.advance_char('!');
-                true
+                parse_movement(&src, &mut offset_i, &mut current_pos)?
             } else {
-                false
+                Movement::Any
             };
-            let index = if let Some(i) = bindings.get_by_right(&str) {
-                *i
-            } else {
-                let len = bindings.len();
-                bindings.insert(len, str);
-                len
-            };
-            patterns.push(Pattern::Binding { index, strict });
+            let index = index_of(&mut bindings, str);
+            patterns.push(Pattern::Binding { index, movement });
             continue;
         } else if !ch.is_whitespace() {
-            return Err(bfrs_errors::ErrorWithPosition {
-                kind: ParseError::UnknownChar { bad_char: ch },
-                position: current_pos,
-            });
+            return Err(bfrs_errors::ErrorWithSpan::new(
+                ParseError::UnknownChar { bad_char: ch },
+                current_pos,
+                current_pos.after_char(ch),
+            ));
         }
         current_pos.advance_char(ch);
         offset_i += 1;
     }
-    Ok(PatternScope { bindings, patterns })
+    if let Some((group, _, opened_at)) = group_backlog.pop() {
+        let opened_at = opened_at.position;
+        return Err(bfrs_errors::ErrorWithSpan::at(
+            match group {
+                Group::Loop => ParseError::UnclosedLoop { opened_at },
+                Group::Capture(_) => ParseError::UnclosedCapture { opened_at },
+            },
+            current_pos,
+        ));
+    }
+    Ok(PatternScope {
+        bindings,
+        counts,
+        captures,
+        wildcards,
+        length: Bound::default(),
+        patterns,
+    })
 }
 
-type ParseResult<T> = Result<T, bfrs_errors::ErrorWithPosition<ParseError>>;
+/// Reads an alphanumeric name, starting at `offset_i`.
+pub(crate) fn take_name(src: &[char], offset_i: &mut usize, current_pos: &mut Location) -> String {
+    let mut str = String::new();
+    while let Some(&ch) = src.get(*offset_i).filter(|&&ch| ch.is_alphanumeric()) {
+        str.push(ch);
+        current_pos.advance_char(ch);
+        *offset_i += 1;
+    }
+    str
+}
+
+/// Obtains the index of a name, registering it if it's new.
+fn index_of(names: &mut BiMap<usize, String>, name: String) -> usize {
+    if let Some(i) = names.get_by_right(&name) {
+        *i
+    } else {
+        let len = names.len();
+        names.insert(len, name);
+        len
+    }
+}
+
+pub type ParseResult<T> = Result<T, bfrs_errors::ErrorWithSpan<ParseError>>;
+
+/// The lines of a file of definitions along with where they start,
+/// skipping the blank ones and the `#` comments.
+pub(crate) fn definition_lines(src: &str) -> impl Iterator<Item = (&str, Location)> {
+    let mut byte_offset = 0;
+    src.split_inclusive('\n')
+        .enumerate()
+        .filter_map(move |(line_i, line)| {
+            let start = Location {
+                position: Position {
+                    line: line_i + 1,
+                    column: 1,
+                },
+                byte_offset,
+            };
+            byte_offset += line.len();
+            let line = line.strip_suffix('\n').unwrap_or(line);
+            let line = line.strip_suffix('\r').unwrap_or(line);
+            let trimmed = line.trim();
+            (!trimmed.is_empty() && !trimmed.starts_with('#')).then_some((line, start))
+        })
+}
 
 #[derive(Debug)]
 pub enum ParseError {
-    UnknownChar { bad_char: char },
+    UnknownChar {
+        bad_char: char,
+    },
+    UnmatchedLoopClose,
+    UnclosedLoop {
+        opened_at: Position,
+    },
+    UnmatchedCaptureClose,
+    UnclosedCapture {
+        opened_at: Position,
+    },
+    /// a `(` that isn't followed by `name:`
+    MissingCaptureName,
+    DuplicateCapture {
+        name: String,
+    },
+    /// a `*` that isn't followed by the name of the count
+    MissingCountName,
+    /// an `@` that isn't followed by a known token, such as `@loop`
+    UnknownToken {
+        name: String,
+    },
+    /// a `%` that isn't followed by a known class, such as `%arith`
+    UnknownClass {
+        name: String,
+    },
+    /// a `\` that isn't followed by the name of a capture group closed before it
+    UnknownBackreference {
+        name: String,
+    },
+    /// a run bound that isn't `(n)`, `(>=n)`, `(<=n)`, `(>n)` or `(<n)`
+    BadBound,
+    /// a length that isn't `{a,b}`, `{a,}`, `{,b}` or `{n}`, or whose
+    /// minimum is more than its maximum
+    BadLength,
+    /// a binding movement that isn't `>`, `<`, `>=n`, `<=n`, `>n`, `<n` or `n`
+    BadMovement,
+    /// a rule without the `pattern => template` form
+    MissingRewriteArrow,
+    /// a template referencing a binding its pattern doesn't have
+    UnknownBinding {
+        name: String,
+    },
+    /// a template referencing a count its pattern doesn't capture
+    UnknownCount {
+        name: String,
+    },
+    /// a template referencing a capture its pattern doesn't have
+    UnknownCapture {
+        name: String,
+    },
+    /// a library entry without the `name = pattern` form
+    MissingAssignment,
+    /// a library entry whose name isn't a valid identifier
+    BadPatternName {
+        name: String,
+    },
+    DuplicatePatternName {
+        name: String,
+    },
+    /// an `include` that isn't followed by a quoted path
+    BadInclude,
+    /// an `include` outside of a library file
+    IncludeWithoutFile,
+    /// a library file including itself, through any amount of other files
+    IncludeCycle {
+        path: String,
+    },
+    /// a spec line that isn't `pattern`, `source` or `match`
+    UnknownSpecKey {
+        key: String,
+    },
+    /// a spec source or match before any pattern
+    MissingSpecPattern,
+    /// a spec match before any source
+    MissingSpecSource,
+    /// a spec match that isn't `start..end name=value ...`
+    BadExpectedMatch,
 }
 
 impl fmt::Display for ParseError {
@@ -96,8 +924,362 @@ impl fmt::Display for ParseError {
             Self::UnknownChar { bad_char } => {
                 write!(f, "Unknown character in source: {:?}", bad_char)
             }
+            Self::UnmatchedLoopClose => write!(f, "Unmatched loop closing"),
+            Self::UnclosedLoop { opened_at } => {
+                write!(f, "Unclosed loop: last opening was found at {}", opened_at)
+            }
+            Self::UnmatchedCaptureClose => write!(f, "Unmatched capture closing"),
+            Self::UnclosedCapture { opened_at } => {
+                write!(
+                    f,
+                    "Unclosed capture: last opening was found at {}",
+                    opened_at
+                )
+            }
+            Self::MissingCaptureName => write!(f, "Expected `name:` after `(`"),
+            Self::DuplicateCapture { name } => {
+                write!(f, "Capture `{}` is defined more than once", name)
+            }
+            Self::MissingCountName => write!(f, "Expected a count name after `*`"),
+            Self::UnknownToken { name } => write!(f, "Unknown token: `@{}`", name),
+            Self::UnknownClass { name } => write!(
+                f,
+                "Unknown class: `%{}`, expected `%arith`, `%move` or `%io`",
+                name
+            ),
+            Self::UnknownBackreference { name } => write!(
+                f,
+                "`\\{}` doesn't refer to a capture group closed before it",
+                name
+            ),
+            Self::BadBound => write!(f, "Expected a bound like `(>=2)` after the run"),
+            Self::BadLength => write!(f, "Expected a length like `{{2,10}}`, `{{,20}}` or `{{5}}`"),
+            Self::BadMovement => {
+                write!(f, "Expected a movement like `>`, `>=2` or `0` after `!`")
+            }
+            Self::MissingRewriteArrow => write!(f, "Expected a `pattern => template` rule"),
+            Self::UnknownBinding { name } => {
+                write!(f, "`{}` is not a binding of the pattern", name)
+            }
+            Self::UnknownCount { name } => {
+                write!(f, "`{}` is not a count captured by the pattern", name)
+            }
+            Self::UnknownCapture { name } => {
+                write!(f, "`{}` is not a capture group of the pattern", name)
+            }
+            Self::MissingAssignment => write!(f, "Expected a `name = pattern` definition"),
+            Self::BadPatternName { name } => write!(f, "Invalid pattern name: {:?}", name),
+            Self::DuplicatePatternName { name } => {
+                write!(f, "Pattern `{}` is defined more than once", name)
+            }
+            Self::BadInclude => write!(f, "Expected a quoted path after `include`"),
+            Self::IncludeWithoutFile => {
+                write!(f, "Only library files can include other files")
+            }
+            Self::IncludeCycle { path } => write!(f, "{} includes itself", path),
+            Self::UnknownSpecKey { key } => {
+                write!(
+                    f,
+                    "Unknown spec key {:?}, expected `pattern`, `source` or `match`",
+                    key
+                )
+            }
+            Self::MissingSpecPattern => write!(f, "Expected a `pattern` before any source"),
+            Self::MissingSpecSource => write!(f, "Expected a `source` before any match"),
+            Self::BadExpectedMatch => {
+                write!(f, "Expected a `start..end name=value ...` match")
+            }
         }
     }
 }
 
 impl Error for ParseError {}
+
+impl bfrs_errors::ErrorCode for ParseError {
+    fn code(&self) -> &'static str {
+        match self {
+            Self::UnknownChar { .. } => "BF0201",
+            Self::UnmatchedLoopClose => "BF0202",
+            Self::UnclosedLoop { .. } => "BF0203",
+            Self::UnmatchedCaptureClose => "BF0204",
+            Self::UnclosedCapture { .. } => "BF0205",
+            Self::MissingCaptureName => "BF0206",
+            Self::DuplicateCapture { .. } => "BF0207",
+            Self::MissingCountName => "BF0208",
+            Self::UnknownToken { .. } => "BF0209",
+            Self::BadBound => "BF0210",
+            Self::BadMovement => "BF0211",
+            Self::MissingRewriteArrow => "BF0212",
+            Self::UnknownBinding { .. } => "BF0213",
+            Self::UnknownCount { .. } => "BF0214",
+            Self::UnknownCapture { .. } => "BF0215",
+            Self::MissingAssignment => "BF0216",
+            Self::BadPatternName { .. } => "BF0217",
+            Self::DuplicatePatternName { .. } => "BF0218",
+            Self::BadInclude => "BF0219",
+            Self::IncludeWithoutFile => "BF0220",
+            Self::IncludeCycle { .. } => "BF0221",
+            Self::UnknownSpecKey { .. } => "BF0222",
+            Self::MissingSpecPattern => "BF0223",
+            Self::MissingSpecSource => "BF0224",
+            Self::BadExpectedMatch => "BF0225",
+            Self::UnknownClass { .. } => "BF0226",
+            Self::UnknownBackreference { .. } => "BF0227",
+            Self::BadLength => "BF0228",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The bound of the only run of `src`.
+    fn run_bound(src: &str) -> Bound {
+        match parse_pattern(src).unwrap().patterns.as_slice() {
+            [Pattern::Run { bound, .. }] => *bound,
+            patterns => panic!("expected a single run, got {:?}", patterns),
+        }
+    }
+
+    fn error(src: &str) -> ParseError {
+        parse_pattern(src).expect_err(src).kind
+    }
+
+    #[test]
+    fn bounds() {
+        let bound = |min, max| Bound { min, max };
+        assert_eq!(run_bound("@add(3)"), bound(Some(3), Some(3)));
+        assert_eq!(run_bound("@add(>=-2)"), bound(Some(-2), None));
+        assert_eq!(run_bound("@move(<=4)"), bound(None, Some(4)));
+        assert_eq!(run_bound("@add(>2)"), bound(Some(3), None));
+        assert_eq!(run_bound("@add(<2)"), bound(None, Some(1)));
+        assert_eq!(run_bound("@add"), Bound::default());
+    }
+
+    #[test]
+    fn bounds_past_the_ends_are_errors() {
+        assert!(matches!(
+            error("@add(>9223372036854775807)"),
+            ParseError::BadBound
+        ));
+        assert!(matches!(
+            error("@add(<-9223372036854775808)"),
+            ParseError::BadBound
+        ));
+        assert!(matches!(
+            error("@add(99999999999999999999)"),
+            ParseError::BadBound
+        ));
+        assert!(matches!(error("@add(=>2)"), ParseError::BadBound));
+    }
+
+    /// The length of the only wildcard of `src`.
+    fn wildcard_length(src: &str) -> Bound {
+        match parse_pattern(src).unwrap().patterns.as_slice() {
+            [Pattern::Wildcard { length, .. }] => *length,
+            patterns => panic!("expected a single wildcard, got {:?}", patterns),
+        }
+    }
+
+    #[test]
+    fn lengths() {
+        let bound = |min, max| Bound { min, max };
+        assert_eq!(wildcard_length("_*"), bound(Some(1), None));
+        assert_eq!(wildcard_length("_{2,5}"), bound(Some(2), Some(5)));
+        assert_eq!(wildcard_length("_{,5}"), bound(Some(1), Some(5)));
+        assert_eq!(wildcard_length("_{0,}"), bound(Some(0), None));
+        assert_eq!(wildcard_length("_{3}"), bound(Some(3), Some(3)));
+        assert_eq!(
+            wildcard_length("_{9223372036854775807}"),
+            bound(Some(isize::MAX), Some(isize::MAX))
+        );
+    }
+
+    #[test]
+    fn lengths_out_of_range_are_errors() {
+        for src in [
+            "_{18446744073709551615}",
+            "_{9223372036854775808}",
+            "_{,18446744073709551615}",
+            "_{-1}",
+            "_{5,2}",
+            "_{2",
+        ]
+        .iter()
+        {
+            assert!(matches!(error(src), ParseError::BadLength), "{}", src);
+        }
+    }
+
+    /// The inputs the `pattern` fuzz target panicked on once.
+    #[test]
+    fn fuzz_regressions() {
+        let dir = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../../fuzz/regressions/pattern"
+        );
+        for file in std::fs::read_dir(dir).unwrap() {
+            let source = std::fs::read(file.unwrap().path()).unwrap();
+            let _ = parse_pattern_bytes(&source);
+        }
+    }
+
+    #[test]
+    fn movements() {
+        let movement = |src| match parse_pattern(src).unwrap().patterns.as_slice() {
+            [Pattern::Binding { movement, .. }] => *movement,
+            patterns => panic!("expected a single binding, got {:?}", patterns),
+        };
+        let within = |min, max| Movement::Within(Bound { min, max });
+        assert_eq!(movement("x"), Movement::Any);
+        assert_eq!(movement("x!"), Movement::Required);
+        assert_eq!(movement("x!>"), within(Some(1), None));
+        assert_eq!(movement("x!<"), within(None, Some(-1)));
+        assert_eq!(movement("x!>=2"), within(Some(2), None));
+        assert_eq!(movement("x!-3"), within(Some(-3), Some(-3)));
+    }
+
+    #[test]
+    fn movements_past_the_ends_are_errors() {
+        assert!(matches!(
+            error("x!>9223372036854775807"),
+            ParseError::BadMovement
+        ));
+        assert!(matches!(
+            error("x!<-9223372036854775808"),
+            ParseError::BadMovement
+        ));
+        assert!(matches!(
+            error("x!99999999999999999999"),
+            ParseError::BadMovement
+        ));
+    }
+
+    #[test]
+    fn loops_nest() {
+        let scope = parse_pattern("[-[+]]").unwrap();
+        let body = match scope.patterns.as_slice() {
+            [Pattern::Loop(body)] => body,
+            patterns => panic!("expected a single loop, got {:?}", patterns),
+        };
+        assert!(matches!(
+            body.as_slice(),
+            [Pattern::Instruction(BFCommand::Decrement), Pattern::Loop(inner)]
+                if matches!(inner.as_slice(), [Pattern::Instruction(BFCommand::Increment)])
+        ));
+    }
+
+    #[test]
+    fn unbalanced_loops_are_errors() {
+        assert!(matches!(error("[-]]"), ParseError::UnmatchedLoopClose));
+        assert!(matches!(
+            error("+[[-]"),
+            ParseError::UnclosedLoop { opened_at } if opened_at.column == 2
+        ));
+    }
+
+    #[test]
+    fn captures_name_their_bodies() {
+        let scope = parse_pattern("(body: +(inner: -))x").unwrap();
+        assert_eq!(scope.captures.get_by_right("body"), Some(&0));
+        assert_eq!(scope.captures.get_by_right("inner"), Some(&1));
+        assert!(matches!(
+            scope.patterns.as_slice(),
+            [Pattern::Capture { index: 0, body, .. }, Pattern::Binding { .. }]
+                if matches!(body.as_slice(), [_, Pattern::Capture { index: 1, .. }])
+        ));
+    }
+
+    #[test]
+    fn bad_captures_are_errors() {
+        assert!(matches!(error("+)"), ParseError::UnmatchedCaptureClose));
+        assert!(matches!(
+            error("+(a: -"),
+            ParseError::UnclosedCapture { opened_at } if opened_at.column == 2
+        ));
+        assert!(matches!(error("(+)"), ParseError::MissingCaptureName));
+        assert!(matches!(error("(a +)"), ParseError::MissingCaptureName));
+        assert!(matches!(
+            error("(a: +)(a: -)"),
+            ParseError::DuplicateCapture { name } if name == "a"
+        ));
+    }
+
+    #[test]
+    fn tokens() {
+        let scope = parse_pattern("@loop@zero").unwrap();
+        assert!(matches!(
+            scope.patterns.as_slice(),
+            [Pattern::AnyLoop, Pattern::Zero]
+        ));
+        assert!(matches!(
+            error("+@loops"),
+            ParseError::UnknownToken { name } if name == "loops"
+        ));
+    }
+
+    #[test]
+    fn built_like_parsed() {
+        let built = PatternScope::builder()
+            .binding("x")
+            .group(|body| {
+                body.instr(BFCommand::Decrement)
+                    .binding("y")
+                    .repeat(BFCommand::Increment, "n")
+                    .binding("x")
+            })
+            .capture("rest", |body| body.wildcard())
+            .backreference("rest")
+            .build();
+        let parsed = parse_pattern("x[-y+*n x](rest: _*)\\rest").unwrap();
+        assert_eq!(
+            format!("{:?}", built.patterns),
+            format!("{:?}", parsed.patterns)
+        );
+        for name in ["x", "y"].iter() {
+            assert_eq!(
+                built.bindings.get_by_right(*name),
+                parsed.bindings.get_by_right(*name)
+            );
+        }
+        assert_eq!(built.counts.get_by_right("n"), Some(&0));
+        assert_eq!(built.captures.get_by_right("rest"), Some(&0));
+        assert_eq!(built.wildcards, parsed.wildcards);
+    }
+
+    #[test]
+    #[should_panic(expected = "capture `a` is not defined")]
+    fn built_backreferences_need_their_capture() {
+        PatternScope::builder().backreference("a");
+    }
+
+    #[test]
+    #[should_panic(expected = "capture `a` is defined more than once")]
+    fn built_captures_are_unique() {
+        PatternScope::builder()
+            .capture("a", |body| body.any_instruction())
+            .capture("a", |body| body.any_instruction());
+    }
+
+    #[test]
+    fn classes() {
+        let scope = parse_pattern("%arith %move %io").unwrap();
+        assert!(matches!(
+            scope.patterns.as_slice(),
+            [
+                Pattern::Class(CommandClass::Arith),
+                Pattern::Class(CommandClass::Move),
+                Pattern::Class(CommandClass::Io)
+            ]
+        ));
+        assert!(CommandClass::Move.contains(BFCommand::Left));
+        assert!(!CommandClass::Move.contains(BFCommand::Increment));
+        let err = parse_pattern("+%loop").unwrap_err();
+        assert_eq!(
+            err.kind.to_string(),
+            "Unknown class: `%loop`, expected `%arith`, `%move` or `%io`"
+        );
+        assert_eq!((err.start.position.column, err.end.position.column), (2, 7));
+    }
+}
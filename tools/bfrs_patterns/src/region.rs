@@ -0,0 +1,274 @@
+//! Parts of a program a search can be kept to, or kept away from, so
+//! only the part of a large program that matters is searched:
+//! - `loops`: the bodies of the loops, so keeping away from them leaves
+//!   the top level, loops there included
+//! - `lines:A-B` and `bytes:A-B`: the instructions from line or byte
+//!   offset `A` to `B`, both included, either of which can be left out
+//! - `pattern:P`: the matches of another pattern, like `pattern:[-]`
+//!
+//! A match is in a region when all of it is, so a loop at the top level
+//! isn't in the `loops` region, even though its body is.
+//! ```
+//! use bfrs_common::parser::parse_str;
+//! use bfrs_patterns::region::{Mask, Region};
+//!
+//! let instructions = parse_str("+[->+<]").unwrap();
+//! let loops: Region = "loops".parse().unwrap();
+//! let mask = Mask::new(&[loops], &[], &instructions, &[]);
+//! assert!(mask.keeps(2..4));
+//! assert!(!mask.keeps(1..7));
+//! ```
+
+use crate::automaton::CompiledLibrary;
+use crate::library::{NamedPattern, PatternLibrary};
+use crate::pattern::parse_pattern;
+use bfrs_common::{BFCommand, Location};
+use std::fmt;
+use std::ops::Range;
+use std::str::FromStr;
+
+/// A part of a program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Region {
+    /// the bodies of the loops, between their brackets
+    Loops,
+    /// the instructions on these lines, both included
+    Lines(usize, usize),
+    /// the instructions at these byte offsets, both included
+    Bytes(usize, usize),
+    /// the matches of this pattern
+    Pattern(String),
+}
+
+impl Region {
+    /// The ranges of `instructions` in the region, sorted and apart from
+    /// each other. `locations` are the ones of the instructions, which
+    /// are only needed for lines and byte offsets.
+    pub fn spans(&self, instructions: &[BFCommand], locations: &[Location]) -> Vec<Range<usize>> {
+        let within = |first: usize, last: usize, at: &dyn Fn(&Location) -> usize| {
+            let start = locations.partition_point(|location| at(location) < first);
+            let end = locations.partition_point(|location| at(location) <= last);
+            std::iter::once(start..end.max(start)).collect()
+        };
+        match self {
+            Self::Loops => {
+                // the bodies of the loops at the top level hold all the others.
+                let mut spans = Vec::new();
+                let mut depth = 0usize;
+                for (i, instruction) in instructions.iter().enumerate() {
+                    match instruction {
+                        BFCommand::BeginLoop => {
+                            if depth == 0 {
+                                spans.push(i + 1..instructions.len());
+                            }
+                            depth += 1;
+                        }
+                        BFCommand::EndLoop => {
+                            depth = depth.saturating_sub(1);
+                            if let Some(span) = spans.last_mut().filter(|_| depth == 0) {
+                                span.end = i;
+                            }
+                        }
+                        _ => (),
+                    }
+                }
+                spans
+            }
+            Self::Lines(first, last) => within(*first, *last, &|location| location.position.line),
+            Self::Bytes(first, last) => within(*first, *last, &|location| location.byte_offset),
+            Self::Pattern(pattern) => {
+                let scope = parse_pattern(pattern).expect("the pattern was parsed with the region");
+                let mut library = PatternLibrary::default();
+                let entry = NamedPattern {
+                    name: pattern.clone(),
+                    scope,
+                };
+                library
+                    .insert(entry, Location::default())
+                    .expect("a single pattern has a name of its own");
+                let mut spans: Vec<Range<usize>> = Vec::new();
+                for found in CompiledLibrary::new(&library).find_all(instructions) {
+                    let span = found.result.start..found.result.start + found.result.commands.len();
+                    match spans.last_mut() {
+                        Some(last) if span.start < last.end => last.end = last.end.max(span.end),
+                        _ => spans.push(span),
+                    }
+                }
+                spans
+            }
+        }
+    }
+}
+
+impl FromStr for Region {
+    type Err = String;
+
+    /// `loops`, `lines:A-B`, `bytes:A-B` or `pattern:P`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, value) = s.split_once(':').unwrap_or((s, ""));
+        let range = || -> Result<(usize, usize), String> {
+            let (first, last) = value
+                .split_once('-')
+                .ok_or_else(|| format!("expected a range like `{}:10-20` in `{}`", kind, s))?;
+            let bound = |bound: &str, or: usize| match bound.trim() {
+                "" => Ok(or),
+                bound => bound
+                    .parse()
+                    .map_err(|_| format!("`{}` isn't a number in `{}`", bound, s)),
+            };
+            Ok((bound(first, 0)?, bound(last, usize::MAX)?))
+        };
+        match kind {
+            "loops" if value.is_empty() => Ok(Self::Loops),
+            "lines" => range().map(|(first, last)| Self::Lines(first, last)),
+            "bytes" => range().map(|(first, last)| Self::Bytes(first, last)),
+            "pattern" => match parse_pattern(value) {
+                Ok(_) => Ok(Self::Pattern(value.into())),
+                Err(e) => Err(format!("in the pattern of `{}`: {}", s, e)),
+            },
+            _ => Err(format!(
+                "unknown region `{}`: expected `loops`, `lines:A-B`, `bytes:A-B` or `pattern:P`",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Loops => write!(f, "loops"),
+            Self::Lines(first, last) => write!(f, "lines:{}-{}", first, last),
+            Self::Bytes(first, last) => write!(f, "bytes:{}-{}", first, last),
+            Self::Pattern(pattern) => write!(f, "pattern:{}", pattern),
+        }
+    }
+}
+
+/// The regions matches have to be in, and the ones they can't be in.
+#[derive(Debug, Clone, Default)]
+pub struct Mask {
+    within: Vec<Vec<Range<usize>>>,
+    outside: Vec<Vec<Range<usize>>>,
+}
+
+impl Mask {
+    /// The mask keeping matches in every region of `within`, and
+    /// in none of `outside`, in `instructions` at `locations`.
+    pub fn new(
+        within: &[Region],
+        outside: &[Region],
+        instructions: &[BFCommand],
+        locations: &[Location],
+    ) -> Self {
+        let spans = |regions: &[Region]| {
+            regions
+                .iter()
+                .map(|region| region.spans(instructions, locations))
+                .collect()
+        };
+        Self {
+            within: spans(within),
+            outside: spans(outside),
+        }
+    }
+
+    /// Whether a match of the instructions in `span` is kept.
+    pub fn keeps(&self, span: Range<usize>) -> bool {
+        self.within.iter().all(|spans| contains(spans, &span))
+            && !self.outside.iter().any(|spans| contains(spans, &span))
+    }
+
+    /// The part of the `len` instructions every match kept is in,
+    /// which is all a search has to look at.
+    pub fn bounds(&self, len: usize) -> Range<usize> {
+        let mut bounds = 0..len;
+        for spans in self.within.iter() {
+            let (start, end) = match (spans.first(), spans.last()) {
+                (Some(first), Some(last)) => (first.start, last.end),
+                _ => return 0..0,
+            };
+            bounds = bounds.start.max(start)..bounds.end.min(end);
+        }
+        bounds.start.min(bounds.end)..bounds.end
+    }
+}
+
+/// Whether `span` is all in one of the sorted `spans`.
+fn contains(spans: &[Range<usize>], span: &Range<usize>) -> bool {
+    let after = spans.partition_point(|s| s.start <= span.start);
+    after > 0 && span.end <= spans[after - 1].end
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bfrs_common::parser::parse_str;
+    use bfrs_common::program::Program;
+
+    fn located(src: &str) -> (Vec<BFCommand>, Vec<Location>) {
+        let program = Program::parse_located(src.bytes().map(Ok)).unwrap();
+        (program.instructions, program.locations.unwrap())
+    }
+
+    #[test]
+    fn regions_parse() {
+        for region in ["loops", "lines:3-7", "bytes:0-12", "pattern:[-]"] {
+            assert_eq!(region.parse::<Region>().unwrap().to_string(), region);
+        }
+        assert_eq!("lines:-4".parse(), Ok(Region::Lines(0, 4)));
+        assert_eq!("bytes:10-".parse(), Ok(Region::Bytes(10, usize::MAX)));
+        let error = |s: &str| s.parse::<Region>().unwrap_err();
+        assert!(error("lines:4").starts_with("expected a range like `lines:10-20`"));
+        assert!(error("bytes:a-4").starts_with("`a` isn't a number"));
+        assert!(error("pattern:[").starts_with("in the pattern of `pattern:[`"));
+        assert!(error("loops:1-2").starts_with("unknown region `loops:1-2`"));
+    }
+
+    #[test]
+    fn loop_bodies() {
+        let instructions = parse_str("+[->[+]<]>[.]").unwrap();
+        assert_eq!(Region::Loops.spans(&instructions, &[]), [2..8, 11..12]);
+    }
+
+    #[test]
+    fn parts_of_the_source() {
+        let (instructions, locations) = located("++\ncomment\n[-]\n>>.");
+        // a part of the source is a single span of instructions
+        let span = |region: Region| match &region.spans(&instructions, &locations)[..] {
+            [span] => span.clone(),
+            spans => panic!("{:?}", spans),
+        };
+        assert_eq!(span(Region::Lines(3, 3)), 2..5);
+        assert_eq!(span(Region::Lines(2, 2)), 2..2);
+        assert_eq!(span(Region::Lines(3, usize::MAX)), 2..8);
+        assert_eq!(span(Region::Bytes(1, 12)), 1..4);
+    }
+
+    #[test]
+    fn matches_of_a_pattern() {
+        let instructions = parse_str("[-]>[-][-]>+[-]").unwrap();
+        let region: Region = "pattern:[-]".parse().unwrap();
+        // the matches next to each other stay apart
+        assert_eq!(
+            region.spans(&instructions, &[]),
+            [0..3, 4..7, 7..10, 12..15]
+        );
+    }
+
+    #[test]
+    fn masks() {
+        let (instructions, locations) = located("+[->+<]\n>[-]");
+        let within = [Region::Lines(1, 1)];
+        let mask = Mask::new(&within, &[Region::Loops], &instructions, &locations);
+        assert!(mask.keeps(0..1));
+        assert!(mask.keeps(1..7));
+        assert!(!mask.keeps(2..4));
+        assert!(!mask.keeps(7..8));
+        assert_eq!(mask.bounds(instructions.len()), 0..7);
+        assert_eq!(Mask::default().bounds(11), 0..11);
+        let nowhere = Mask::new(&[Region::Loops], &[], &parse_str("+>+").unwrap(), &[]);
+        assert_eq!(nowhere.bounds(3), 0..0);
+        assert!(!nowhere.keeps(0..1));
+    }
+}
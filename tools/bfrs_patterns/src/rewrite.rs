@@ -0,0 +1,351 @@
+//! Replacement templates, to turn matches into new code.
+//!
+//! A template uses the same syntax as a pattern, but instead of being
+//! matched it's *emitted* using the bindings and counts of a match:
+//!   - instructions are emitted as they are.
+//!   - a binding name moves the pointer to the cell of that binding.
+//!   - `+*n` emits `+` as many times as the count `n` captured.
+//!   - `(name)` emits the instructions captured by the group `name`.
+//!
+//! After the template is emitted, the pointer is moved to where the
+//! matched code left it, so the rest of the program is unaffected.
+//!
+//! A rule joins a pattern and a template with `=>`:
+//! ```text
+//! x[-y+x] => y[-]x[-y+x]
+//! ```
+//!
+//! Rules files hold one rule per line, lines starting with `#` are comments.
+
+use crate::pattern::{
+    definition_lines, parse_pattern_starting_at, take_name, ParseError, ParseResult, PatternScope,
+};
+use crate::r#match::{MatchResult, MatchSM};
+use bfrs_common::errors as bfrs_errors;
+use bfrs_common::{BFCommand, Location};
+
+#[derive(Debug)]
+pub enum TemplateItem {
+    Instruction(BFCommand),
+    /// move to the cell of a binding
+    Binding(usize),
+    /// the instructions matched by a capture group
+    Capture(usize),
+    /// an instruction, as many times as a captured count
+    Repeat {
+        instruction: BFCommand,
+        count: usize,
+    },
+}
+
+#[derive(Debug)]
+pub struct Template {
+    pub items: Vec<TemplateItem>,
+}
+
+/// A pattern along with what to replace its matches with.
+#[derive(Debug)]
+pub struct Rule {
+    pub scope: PatternScope,
+    pub template: Template,
+}
+
+impl Template {
+    /// Emit the template for a match of the scope the template was parsed with.
+    pub fn render(&self, result: &MatchResult, out: &mut Vec<BFCommand>) {
+        let mut pointer = 0;
+        for item in self.items.iter() {
+            match item {
+                TemplateItem::Instruction(instr) => {
+                    pointer += movement(*instr);
+                    out.push(*instr);
+                }
+                TemplateItem::Binding(binding) => {
+                    move_pointer(&mut pointer, result.cells[*binding], out)
+                }
+                TemplateItem::Capture(capture) => {
+                    let commands = result.capture(*capture).unwrap_or_default();
+                    pointer += commands.iter().map(|&instr| movement(instr)).sum::<isize>();
+                    out.extend_from_slice(commands);
+                }
+                TemplateItem::Repeat { instruction, count } => {
+                    let amount = result.counts[*count];
+                    pointer += movement(*instruction) * amount as isize;
+                    out.extend(std::iter::repeat_n(*instruction, amount));
+                }
+            }
+        }
+        move_pointer(&mut pointer, result.end_pointer, out);
+    }
+}
+
+fn movement(instr: BFCommand) -> isize {
+    match instr {
+        BFCommand::Right => 1,
+        BFCommand::Left => -1,
+        _ => 0,
+    }
+}
+
+fn move_pointer(pointer: &mut isize, target: isize, out: &mut Vec<BFCommand>) {
+    let direction = if target > *pointer {
+        BFCommand::Right
+    } else {
+        BFCommand::Left
+    };
+    out.extend(std::iter::repeat_n(
+        direction,
+        (target - *pointer).unsigned_abs(),
+    ));
+    *pointer = target;
+}
+
+/// Parses a rules file.
+pub fn parse_rules(src: &str) -> ParseResult<Vec<Rule>> {
+    let mut rules = Vec::new();
+    for (line, line_start) in definition_lines(src) {
+        rules.push(parse_rule_starting_at(line, line_start)?);
+    }
+    Ok(rules)
+}
+
+/// Rewrite the instructions with every rule, in order.
+pub fn apply_rules(mut instructions: Vec<BFCommand>, rules: &[Rule]) -> Vec<BFCommand> {
+    let _span = tracing::debug_span!("rewrite", rules = rules.len()).entered();
+    for (i, rule) in rules.iter().enumerate() {
+        let before = instructions.len();
+        instructions = MatchSM::rewrite(&instructions, rule);
+        tracing::trace!(
+            rule = i,
+            before,
+            after = instructions.len(),
+            "applied a rule"
+        );
+    }
+    instructions
+}
+
+/// Rewrite the source of a program with a rule, replacing the bytes of
+/// every match with its template. What's between matches, comments
+/// included, is kept as it is, but the comments inside a match are lost.
+/// Matches of no instructions have nothing to replace, so they're left
+/// out. The locations are the ones of the instructions in the source.
+pub fn rewrite_source(
+    src: &[u8],
+    instructions: &[BFCommand],
+    locations: &[Location],
+    rule: &Rule,
+) -> Vec<u8> {
+    let mut machine = MatchSM::new(instructions);
+    let mut result = Vec::with_capacity(src.len());
+    // the end of the bytes copied to the result so far.
+    let mut copied = 0;
+    let mut offset = 0;
+    let mut rendered = Vec::new();
+    while offset < instructions.len() {
+        match machine.match_at(offset, &rule.scope) {
+            Some(res) if !res.commands.is_empty() => {
+                let (start, end) = res.locate(locations);
+                result.extend_from_slice(&src[copied..start.byte_offset]);
+                rendered.clear();
+                rule.template.render(&res, &mut rendered);
+                result.extend(rendered.iter().map(|&instr| instr as u8));
+                copied = end.byte_offset;
+                offset += res.commands.len();
+            }
+            _ => offset += 1,
+        }
+    }
+    result.extend_from_slice(&src[copied..]);
+    result
+}
+
+pub fn parse_rule(src: &str) -> ParseResult<Rule> {
+    parse_rule_starting_at(src, Location::default())
+}
+
+/// Parses a rule, setting the initial location to `start_pos`
+pub fn parse_rule_starting_at(src: &str, start_pos: Location) -> ParseResult<Rule> {
+    let (pattern, template) = match src.split_once("=>") {
+        Some(split) => split,
+        None => {
+            return Err(bfrs_errors::ErrorWithSpan::new(
+                ParseError::MissingRewriteArrow,
+                start_pos,
+                start_pos.after_str(src.trim_end()),
+            ))
+        }
+    };
+    let scope = parse_pattern_starting_at(pattern, start_pos)?;
+    let template_pos = start_pos.after_str(pattern).after_str("=>");
+    let template = parse_template_starting_at(template, &scope, template_pos)?;
+    Ok(Rule { scope, template })
+}
+
+/// Parses a template, resolving its names with the scope of the
+/// pattern it replaces.
+pub fn parse_template(src: &str, scope: &PatternScope) -> ParseResult<Template> {
+    parse_template_starting_at(src, scope, Location::default())
+}
+
+/// Parses a template, setting the initial location to `start_pos`
+pub fn parse_template_starting_at(
+    src: &str,
+    scope: &PatternScope,
+    start_pos: Location,
+) -> ParseResult<Template> {
+    let mut current_pos = start_pos;
+    let src: Vec<_> = src.chars().collect();
+    let mut offset_i = 0;
+    let mut items = Vec::new();
+    // the emitted code has to stay balanced.
+    let mut loop_backlog = Vec::new();
+    while let Some(&ch) = src.get(offset_i) {
+        if ch.is_ascii() {
+            if let Some(instr) = BFCommand::from_u8(ch as u8) {
+                match instr {
+                    BFCommand::BeginLoop => loop_backlog.push(current_pos.position),
+                    BFCommand::EndLoop if loop_backlog.pop().is_none() => {
+                        return Err(bfrs_errors::ErrorWithSpan::new(
+                            ParseError::UnmatchedLoopClose,
+                            current_pos,
+                            current_pos.after_char(ch),
+                        ));
+                    }
+                    _ => (),
+                }
+                current_pos.advance_char(ch);
+                offset_i += 1;
+                let is_loop = matches!(instr, BFCommand::BeginLoop | BFCommand::EndLoop);
+                if !is_loop && matches!(src.get(offset_i), Some(&'*')) {
+                    current_pos.advance_char('*');
+                    offset_i += 1;
+                    let name_pos = current_pos;
+                    let name = take_name(&src, &mut offset_i, &mut current_pos);
+                    let count = match scope.counts.get_by_right(&name) {
+                        Some(&count) => count,
+                        None if name.is_empty() => {
+                            return Err(bfrs_errors::ErrorWithSpan::at(
+                                ParseError::MissingCountName,
+                                name_pos,
+                            ))
+                        }
+                        None => {
+                            return Err(bfrs_errors::ErrorWithSpan::new(
+                                ParseError::UnknownCount { name },
+                                name_pos,
+                                current_pos,
+                            ))
+                        }
+                    };
+                    items.push(TemplateItem::Repeat {
+                        instruction: instr,
+                        count,
+                    });
+                } else {
+                    items.push(TemplateItem::Instruction(instr));
+                }
+                continue;
+            }
+        }
+        if ch == '(' {
+            current_pos.advance_char(ch);
+            offset_i += 1;
+            let name_pos = current_pos;
+            let name = take_name(&src, &mut offset_i, &mut current_pos);
+            if !matches!(src.get(offset_i), Some(&')')) {
+                return Err(bfrs_errors::ErrorWithSpan::at(
+                    ParseError::UnmatchedCaptureClose,
+                    current_pos,
+                ));
+            }
+            let name_end = current_pos;
+            current_pos.advance_char(')');
+            offset_i += 1;
+            match scope.captures.get_by_right(&name) {
+                Some(&capture) => items.push(TemplateItem::Capture(capture)),
+                None => {
+                    return Err(bfrs_errors::ErrorWithSpan::new(
+                        ParseError::UnknownCapture { name },
+                        name_pos,
+                        name_end,
+                    ))
+                }
+            }
+            continue;
+        }
+        if ch.is_alphabetic() {
+            let name_pos = current_pos;
+            let name = take_name(&src, &mut offset_i, &mut current_pos);
+            match scope.bindings.get_by_right(&name) {
+                Some(&binding) => items.push(TemplateItem::Binding(binding)),
+                None => {
+                    return Err(bfrs_errors::ErrorWithSpan::new(
+                        ParseError::UnknownBinding { name },
+                        name_pos,
+                        current_pos,
+                    ))
+                }
+            }
+            continue;
+        } else if !ch.is_whitespace() {
+            return Err(bfrs_errors::ErrorWithSpan::new(
+                ParseError::UnknownChar { bad_char: ch },
+                current_pos,
+                current_pos.after_char(ch),
+            ));
+        }
+        current_pos.advance_char(ch);
+        offset_i += 1;
+    }
+    if let Some(opened_at) = loop_backlog.pop() {
+        return Err(bfrs_errors::ErrorWithSpan::at(
+            ParseError::UnclosedLoop { opened_at },
+            current_pos,
+        ));
+    }
+    Ok(Template { items })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bfrs_common::parser::parse_located;
+
+    /// `src` rewritten with `rule`, the way `--replace` does.
+    fn replaced(rule: &str, src: &str) -> String {
+        let rule = parse_rule(rule).unwrap();
+        let (instructions, locations): (Vec<_>, Vec<_>) = parse_located(src.bytes().map(Ok))
+            .map(Result::unwrap)
+            .unzip();
+        let rewritten = rewrite_source(src.as_bytes(), &instructions, &locations, &rule);
+        String::from_utf8(rewritten).unwrap()
+    }
+
+    #[test]
+    fn matches_are_replaced_and_the_rest_kept() {
+        assert_eq!(
+            replaced("x[-y+x] => y[-]x[-y+x]", "+ copy [->+<] done"),
+            "+ copy >[-]<[->+<] done"
+        );
+        // the pointer is left where the match left it.
+        assert_eq!(replaced("[-]> => ", "[-]>+"), ">+");
+    }
+
+    #[test]
+    fn empty_matches_are_left_alone() {
+        // `x` only takes the `>`, and matches nothing before the others.
+        assert_eq!(replaced("x => ", "+>-"), "+>-");
+        assert_eq!(replaced("x => .", "+>-"), "+.>-");
+        assert_eq!(replaced("[_{0,}] => .", "+[]-"), "+.-");
+    }
+
+    #[test]
+    fn wildcards_take_the_whole_program() {
+        // a balanced `_*` takes all of it, only the move
+        // to where the pointer ended up is left.
+        assert_eq!(replaced("_* => ", "+>-"), ">");
+        assert_eq!(replaced("_* => ", "+[-]-"), "");
+        assert_eq!(replaced("_{0,} => .", "+>-"), ".>");
+    }
+}
@@ -0,0 +1,421 @@
+//! Spec files, to check that patterns keep matching what they should.
+//!
+//! A spec file holds a pattern, followed by the sources to search it in
+//! and the matches expected in each one. Every `match` line gives the
+//! range of instructions a match covers, optionally followed by the cell
+//! of some bindings, relative to where the match starts, and the value of
+//! some counts. A source without `match` lines expects no matches, and
+//! lines starting with `#` are comments:
+//! ```text
+//! pattern = x[-y+x]
+//!
+//! # move to the left
+//! source = [-<+>]
+//! match = 0..6 y=-1
+//!
+//! source = +++
+//! ```
+//! Another `pattern` line starts a new spec in the same file.
+
+use crate::pattern::{
+    definition_lines, parse_pattern_starting_at, ParseError, ParseResult, PatternScope,
+};
+use crate::r#match::{MatchResult, MatchSM};
+use bfrs_common::errors as bfrs_errors;
+use bfrs_common::parser::{self, IOParserErr, ParseErrorKind};
+use bfrs_common::{BFCommand, Location, Position};
+use std::ops::Range;
+
+/// A pattern, along with the sources it's checked against.
+#[derive(Debug)]
+pub struct Spec {
+    /// the pattern as written in the file
+    pub pattern: String,
+    pub scope: PatternScope,
+    pub cases: Vec<Case>,
+}
+
+/// A source, and the matches of the pattern expected in it.
+#[derive(Debug)]
+pub struct Case {
+    /// where the source is defined
+    pub position: Position,
+    pub instructions: Vec<BFCommand>,
+    pub matches: Vec<ExpectedMatch>,
+}
+
+#[derive(Debug)]
+pub struct ExpectedMatch {
+    pub range: Range<usize>,
+    /// bindings and the cell they should be at
+    pub cells: Vec<(usize, isize)>,
+    /// counts and the amount they should have
+    pub counts: Vec<(usize, usize)>,
+}
+
+/// A difference between the expected matches of a case and the ones found.
+#[derive(Debug)]
+pub enum Failure {
+    MatchCount {
+        expected: usize,
+        found: usize,
+    },
+    /// the match at `index` covers other instructions
+    Range {
+        index: usize,
+        expected: Range<usize>,
+        found: Range<usize>,
+    },
+    Cell {
+        index: usize,
+        binding: usize,
+        expected: isize,
+        found: isize,
+    },
+    Count {
+        index: usize,
+        count: usize,
+        expected: usize,
+        found: usize,
+    },
+}
+
+impl Failure {
+    /// Describe the failure, with the names used in the pattern.
+    pub fn describe(&self, scope: &PatternScope) -> String {
+        match self {
+            Self::MatchCount { expected, found } => {
+                format!("expected {} matches, found {}", expected, found)
+            }
+            Self::Range {
+                index,
+                expected,
+                found,
+            } => format!(
+                "match {}: expected instructions {}..{}, found {}..{}",
+                index + 1,
+                expected.start,
+                expected.end,
+                found.start,
+                found.end
+            ),
+            Self::Cell {
+                index,
+                binding,
+                expected,
+                found,
+            } => format!(
+                "match {}: expected `{}` at {:+}, found {:+}",
+                index + 1,
+                scope.bindings.get_by_left(binding).unwrap(),
+                expected,
+                found
+            ),
+            Self::Count {
+                index,
+                count,
+                expected,
+                found,
+            } => format!(
+                "match {}: expected `{}` to be {}, found {}",
+                index + 1,
+                scope.counts.get_by_left(count).unwrap(),
+                expected,
+                found
+            ),
+        }
+    }
+}
+
+impl Case {
+    /// Search the pattern in the source, and compare
+    /// the matches with the expected ones.
+    pub fn check(&self, scope: &PatternScope) -> Vec<Failure> {
+        let found = MatchSM::find_all(&self.instructions, scope);
+        let mut failures = Vec::new();
+        if found.len() != self.matches.len() {
+            failures.push(Failure::MatchCount {
+                expected: self.matches.len(),
+                found: found.len(),
+            });
+        }
+        for (index, (expected, res)) in self.matches.iter().zip(found.iter()).enumerate() {
+            expected.compare(index, res, &mut failures);
+        }
+        failures
+    }
+}
+
+impl ExpectedMatch {
+    fn compare(&self, index: usize, res: &MatchResult, failures: &mut Vec<Failure>) {
+        let found = res.start..res.start + res.commands.len();
+        if found != self.range {
+            failures.push(Failure::Range {
+                index,
+                expected: self.range.clone(),
+                found,
+            });
+        }
+        for &(binding, expected) in self.cells.iter() {
+            if res.cells[binding] != expected {
+                failures.push(Failure::Cell {
+                    index,
+                    binding,
+                    expected,
+                    found: res.cells[binding],
+                });
+            }
+        }
+        for &(count, expected) in self.counts.iter() {
+            if res.counts[count] != expected {
+                failures.push(Failure::Count {
+                    index,
+                    count,
+                    expected,
+                    found: res.counts[count],
+                });
+            }
+        }
+    }
+}
+
+pub fn parse_spec(src: &str) -> ParseResult<Vec<Spec>> {
+    let mut specs: Vec<Spec> = Vec::new();
+    for (line, line_start) in definition_lines(src) {
+        let (key, value) = match line.split_once('=') {
+            Some(split) => split,
+            None => {
+                return Err(bfrs_errors::ErrorWithSpan::new(
+                    ParseError::MissingAssignment,
+                    line_start,
+                    line_start.after_str(line.trim_end()),
+                ))
+            }
+        };
+        let key_pos = line_start.after_str(&key[..key.len() - key.trim_start().len()]);
+        // errors about the key cover it.
+        let error =
+            |kind| bfrs_errors::ErrorWithSpan::new(kind, key_pos, key_pos.after_str(key.trim()));
+        // the value starts right after the `=`.
+        let value_pos = line_start.after_str(key).after_char('=');
+        match key.trim() {
+            "pattern" => specs.push(Spec {
+                pattern: value.trim().to_string(),
+                scope: parse_pattern_starting_at(value, value_pos)?,
+                cases: Vec::new(),
+            }),
+            "source" => {
+                let spec = match specs.last_mut() {
+                    Some(spec) => spec,
+                    None => return Err(error(ParseError::MissingSpecPattern)),
+                };
+                spec.cases.push(Case {
+                    position: line_start.position,
+                    instructions: parse_source(value, value_pos)?,
+                    matches: Vec::new(),
+                });
+            }
+            "match" => {
+                let spec = match specs.last_mut() {
+                    Some(spec) => spec,
+                    None => return Err(error(ParseError::MissingSpecPattern)),
+                };
+                let case = match spec.cases.last_mut() {
+                    Some(case) => case,
+                    None => return Err(error(ParseError::MissingSpecSource)),
+                };
+                case.matches
+                    .push(parse_expected_match(value, &spec.scope, value_pos)?);
+            }
+            key => {
+                return Err(error(ParseError::UnknownSpecKey {
+                    key: key.to_string(),
+                }))
+            }
+        }
+    }
+    Ok(specs)
+}
+
+/// Parses the source of a case, where anything that isn't an instruction is a comment.
+fn parse_source(src: &str, start_pos: Location) -> ParseResult<Vec<BFCommand>> {
+    // the parser counts the bytes from the start of `src`.
+    let shift = |location: Location| Location {
+        byte_offset: location.byte_offset + start_pos.byte_offset,
+        ..location
+    };
+    parser::parse_starting_at(src.bytes().map(Ok), start_pos.position)
+        .collect::<Result<_, _>>()
+        .map_err(|e| match e {
+            IOParserErr::Parser(e) => bfrs_errors::ErrorWithSpan::new(
+                match e.kind {
+                    ParseErrorKind::MissingLB => ParseError::UnmatchedLoopClose,
+                    ParseErrorKind::MissingRB(opened_at) => ParseError::UnclosedLoop { opened_at },
+                },
+                shift(e.start),
+                shift(e.end),
+            ),
+            IOParserErr::IO(_) => unreachable!("a string can't fail to be read"),
+        })
+}
+
+/// Parses a `start..end name=value ...` match.
+fn parse_expected_match(
+    src: &str,
+    scope: &PatternScope,
+    start_pos: Location,
+) -> ParseResult<ExpectedMatch> {
+    let mut words = Vec::new();
+    let mut current_pos = start_pos;
+    let mut word_start = None;
+    for (i, ch) in src.char_indices().chain(std::iter::once((src.len(), ' '))) {
+        match (ch.is_whitespace(), word_start) {
+            (false, None) => word_start = Some((i, current_pos)),
+            (true, Some((start, location))) => {
+                words.push((&src[start..i], location));
+                word_start = None;
+            }
+            _ => (),
+        }
+        current_pos.advance_char(ch);
+    }
+
+    let mut words = words.into_iter();
+    // errors about a word cover it.
+    let bad_match = |word: &str, start: Location| {
+        bfrs_errors::ErrorWithSpan::new(ParseError::BadExpectedMatch, start, start.after_str(word))
+    };
+    let range = match words.next() {
+        Some((word, start)) => word
+            .split_once("..")
+            .and_then(|(start, end)| Some(start.parse().ok()?..end.parse().ok()?))
+            .ok_or_else(|| bad_match(word, start))?,
+        None => return Err(bad_match("", start_pos)),
+    };
+
+    let mut expected = ExpectedMatch {
+        range,
+        cells: Vec::new(),
+        counts: Vec::new(),
+    };
+    for (word, start) in words {
+        let (name, value) = word.split_once('=').ok_or_else(|| bad_match(word, start))?;
+        if let Some(&binding) = scope.bindings.get_by_right(name) {
+            let cell = value.parse().map_err(|_| bad_match(word, start))?;
+            expected.cells.push((binding, cell));
+        } else if let Some(&count) = scope.counts.get_by_right(name) {
+            let amount = value.parse().map_err(|_| bad_match(word, start))?;
+            expected.counts.push((count, amount));
+        } else {
+            return Err(bfrs_errors::ErrorWithSpan::new(
+                ParseError::UnknownBinding {
+                    name: name.to_string(),
+                },
+                start,
+                start.after_str(name),
+            ));
+        }
+    }
+    Ok(expected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPEC: &str = "\
+pattern = x[-y+*n x]
+
+# move to the left
+source = [-<+>] +++ [->>++<<]
+match = 0..6 y=-1 n=1
+match = 9..18 y=2 n=2
+
+source = +++
+pattern = [-]
+";
+
+    fn failures(spec: &Spec) -> Vec<Vec<String>> {
+        spec.cases
+            .iter()
+            .map(|case| {
+                case.check(&spec.scope)
+                    .iter()
+                    .map(|failure| failure.describe(&spec.scope))
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn specs_and_their_cases() {
+        let specs = parse_spec(SPEC).unwrap();
+        assert_eq!(specs.len(), 2);
+        assert_eq!(specs[0].pattern, "x[-y+*n x]");
+        assert_eq!(specs[0].cases.len(), 2);
+        assert_eq!(specs[0].cases[0].position.line, 4);
+        assert_eq!(specs[0].cases[0].matches.len(), 2);
+        assert!(specs[0].cases[1].matches.is_empty());
+        assert!(specs[1].cases.is_empty());
+        assert_eq!(failures(&specs[0]), [Vec::<String>::new(), Vec::new()]);
+    }
+
+    #[test]
+    fn failures_say_what_differs() {
+        let specs =
+            parse_spec("pattern = x[-y+*n x]\nsource = [-<+>] [->>++<<]\nmatch = 0..7 y=1 n=1\n")
+                .unwrap();
+        assert_eq!(
+            failures(&specs[0]),
+            [[
+                "expected 1 matches, found 2",
+                "match 1: expected instructions 0..7, found 0..6",
+                "match 1: expected `y` at +1, found -1",
+            ]]
+        );
+        let specs = parse_spec("pattern = +*n\nsource = ++\nmatch = 0..2 n=3").unwrap();
+        assert_eq!(
+            failures(&specs[0]),
+            [["match 1: expected `n` to be 3, found 2"]]
+        );
+    }
+
+    #[test]
+    fn bad_specs_are_errors() {
+        let error = |src| parse_spec(src).expect_err(src);
+        assert!(matches!(
+            error("source = +").kind,
+            ParseError::MissingSpecPattern
+        ));
+        assert!(matches!(
+            error("pattern = +\nmatch = 0..1").kind,
+            ParseError::MissingSpecSource
+        ));
+        assert!(matches!(
+            error("pattern = +\nsources = +").kind,
+            ParseError::UnknownSpecKey { key } if key == "sources"
+        ));
+        assert!(matches!(
+            error("pattern = +\nsource = +\nmatch = 0-1").kind,
+            ParseError::BadExpectedMatch
+        ));
+        let unknown = error("pattern = x+\nsource = +\nmatch = 0..1 y=2");
+        assert!(matches!(
+            unknown.kind,
+            ParseError::UnknownBinding { name } if name == "y"
+        ));
+        assert_eq!(
+            unknown.start.position,
+            Position {
+                line: 3,
+                column: 14
+            }
+        );
+        // sources are located in the file
+        let unclosed = error("pattern = +\nsource = +[");
+        assert!(matches!(
+            unclosed.kind,
+            ParseError::UnclosedLoop { opened_at } if opened_at == Position { line: 2, column: 11 }
+        ));
+    }
+}
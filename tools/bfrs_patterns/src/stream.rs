@@ -0,0 +1,165 @@
+//! Matching over a stream of instructions, without
+//! having the whole program in memory.
+
+use crate::pattern::PatternScope;
+use crate::r#match::{MatchResult, MatchSM};
+use bfrs_common::BFCommand;
+use std::ops::Range;
+
+/// A match that owns its instructions, as the stream
+/// they come from is discarded as it's consumed.
+#[derive(Debug)]
+pub struct OwnedMatchResult {
+    pub commands: Vec<BFCommand>,
+    /// offset of the first matched instruction in the stream
+    pub start: usize,
+    pub cells: Vec<isize>,
+    pub counts: Vec<usize>,
+    pub captures: Vec<Range<usize>>,
+    pub end_pointer: isize,
+}
+
+impl From<MatchResult<'_>> for OwnedMatchResult {
+    fn from(res: MatchResult) -> Self {
+        Self {
+            commands: res.commands.to_vec(),
+            start: res.start,
+            cells: res.cells,
+            counts: res.counts,
+            captures: res.captures,
+            end_pointer: res.end_pointer,
+        }
+    }
+}
+
+/// Finds the matches of a pattern in a stream of instructions, keeping
+/// only a window of them in memory. A match can't be longer than the
+/// window, so the window has to be big enough for the pattern
+/// (loops included) to fit in it. Other than that, the matches are
+/// the same as the ones from [`MatchSM::find_all`].
+pub struct StreamMatcher<'s, I>
+where
+    I: Iterator<Item = BFCommand>,
+{
+    input: std::iter::Fuse<I>,
+    scope: &'s PatternScope,
+    buffer: Vec<BFCommand>,
+    /// start of the window in the buffer
+    head: usize,
+    /// offset of the start of the window in the stream
+    offset: usize,
+    window: usize,
+}
+
+impl<'s, I> StreamMatcher<'s, I>
+where
+    I: Iterator<Item = BFCommand>,
+{
+    /// Matches with a window of `window` instructions.
+    pub fn with_window(window: usize, input: I, scope: &'s PatternScope) -> Self {
+        assert!(window > 0, "the matching window can't be empty");
+        Self {
+            input: input.fuse(),
+            scope,
+            buffer: Vec::with_capacity(window * 2),
+            head: 0,
+            offset: 0,
+            window,
+        }
+    }
+    /// Matches with a window of 4096 instructions.
+    pub fn new(input: I, scope: &'s PatternScope) -> Self {
+        Self::with_window(4096, input, scope)
+    }
+
+    fn fill_window(&mut self) {
+        while self.buffer.len() - self.head < self.window {
+            match self.input.next() {
+                Some(instr) => self.buffer.push(instr),
+                None => break,
+            }
+        }
+    }
+
+    fn advance(&mut self, amount: usize) {
+        self.head += amount;
+        self.offset += amount;
+        // only move the instructions back once in a while, so
+        // it's not done every single time the window slides.
+        if self.head >= self.window {
+            self.buffer.drain(..self.head);
+            self.head = 0;
+        }
+    }
+}
+
+impl<I> Iterator for StreamMatcher<'_, I>
+where
+    I: Iterator<Item = BFCommand>,
+{
+    type Item = OwnedMatchResult;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            self.fill_window();
+            if self.head == self.buffer.len() {
+                return None;
+            }
+            let found = MatchSM::match_single(&self.buffer[self.head..], self.scope)
+                .map(OwnedMatchResult::from);
+            match found {
+                Some(mut res) => {
+                    res.start = self.offset;
+                    self.advance(res.commands.len().max(1));
+                    return Some(res);
+                }
+                None => self.advance(1),
+            }
+        }
+    }
+}
+
+impl<I> std::iter::FusedIterator for StreamMatcher<'_, I> where I: Iterator<Item = BFCommand> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern::parse_pattern;
+    use bfrs_common::parser::parse_str;
+
+    fn starts(window: usize, pattern: &str, source: &str) -> Vec<(usize, usize)> {
+        let scope = parse_pattern(pattern).unwrap();
+        let instructions = parse_str(source).unwrap();
+        StreamMatcher::with_window(window, instructions.into_iter(), &scope)
+            .map(|res| (res.start, res.start + res.commands.len()))
+            .collect()
+    }
+
+    #[test]
+    fn same_matches_as_the_whole_program() {
+        let pattern = "x[-y+x]";
+        let source = "[->+<] +++ [-<<+>>] >> [-] [->+<]";
+        let scope = parse_pattern(pattern).unwrap();
+        let instructions = parse_str(source).unwrap();
+        let expected: Vec<_> = MatchSM::find_all(&instructions, &scope)
+            .iter()
+            .map(|res| (res.start, res.start + res.commands.len()))
+            .collect();
+        assert_eq!(expected, [(0, 6), (9, 17), (22, 28)]);
+        // small windows slide and get moved back many times
+        for &window in [8, 9, 16, 4096].iter() {
+            assert_eq!(starts(window, pattern, source), expected, "{}", window);
+        }
+    }
+
+    #[test]
+    fn matches_fit_in_the_window() {
+        assert_eq!(starts(5, "x[-y+x]", "[->+<]"), []);
+        assert_eq!(starts(6, "x[-y+x]", "[->+<]"), [(0, 6)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "the matching window can't be empty")]
+    fn windows_hold_instructions() {
+        starts(0, "+", "+");
+    }
+}
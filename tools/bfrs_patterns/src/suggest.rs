@@ -0,0 +1,133 @@
+//! Suggestions for code that could be simpler, or that an optimizer
+//! would replace, made of the idioms and rewrite rules.
+
+use crate::r#match::{MatchResult, MatchSM};
+use crate::rewrite::{parse_rule, Rule};
+use bfrs_common::BFCommand;
+
+/// The built-in suggestions: name, rule and message.
+/// Rules are tried in this order, the first one that matches wins.
+const SUGGESTIONS: &[(&str, &str, &str)] = &[
+    (
+        "dead_add",
+        "@add@zero => [-]",
+        "the cell is changed right before clearing it",
+    ),
+    (
+        "double_clear",
+        "@zero@zero => [-]",
+        "the cell is cleared twice",
+    ),
+    (
+        "copy",
+        "x[-y+z+x] => x[-y+z+x]",
+        "copy loop, could be replaced by the optimizer",
+    ),
+    (
+        "move",
+        "x[-y+x] => x[-y+x]",
+        "move loop, could be replaced by the optimizer",
+    ),
+    (
+        "multiply",
+        "x[-y+*n x] => x[-y+*n x]",
+        "multiply loop, could be replaced by the optimizer",
+    ),
+];
+
+pub struct SuggestionRule {
+    pub name: &'static str,
+    pub message: &'static str,
+    pub rule: Rule,
+}
+
+/// A match of a suggestion rule, along with its replacement.
+pub struct Suggestion<'a> {
+    /// index of the rule that matched
+    pub rule: usize,
+    pub result: MatchResult<'a>,
+    pub replacement: Vec<BFCommand>,
+}
+
+impl Suggestion<'_> {
+    /// Whether the replacement is any different from the matched code.
+    pub fn changes(&self) -> bool {
+        self.replacement != self.result.commands
+    }
+}
+
+pub fn rules() -> Vec<SuggestionRule> {
+    SUGGESTIONS
+        .iter()
+        .map(|&(name, rule, message)| SuggestionRule {
+            name,
+            message,
+            rule: parse_rule(rule).expect("the built-in suggestions are valid"),
+        })
+        .collect()
+}
+
+/// Find the code the rules apply to. The instructions are matched
+/// commutatively, so the replacement also puts loop bodies in order.
+pub fn suggest<'a>(instructions: &'a [BFCommand], rules: &[SuggestionRule]) -> Vec<Suggestion<'a>> {
+    let mut machine = MatchSM::new(instructions).commutative(true);
+    let mut offset = 0;
+    let mut result = Vec::new();
+    while offset < instructions.len() {
+        let found = rules.iter().enumerate().find_map(|(rule, suggestion)| {
+            machine
+                .match_at(offset, &suggestion.rule.scope)
+                .map(|res| (rule, res))
+        });
+        match found {
+            Some((rule, res)) => {
+                offset += res.skip();
+                let mut replacement = Vec::with_capacity(res.commands.len());
+                rules[rule].rule.template.render(&res, &mut replacement);
+                result.push(Suggestion {
+                    rule,
+                    result: res,
+                    replacement,
+                });
+            }
+            None => offset += 1,
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bfrs_common::parser::parse_str;
+
+    fn text(instructions: &[BFCommand]) -> String {
+        instructions.iter().map(|&i| i as u8 as char).collect()
+    }
+
+    #[test]
+    fn suggestions_and_their_replacements() {
+        let rules = rules();
+        let instructions = parse_str("+++[-][+][-]>[>+<-]>[->+<]").unwrap();
+        let found: Vec<_> = suggest(&instructions, &rules)
+            .iter()
+            .map(|found| {
+                (
+                    rules[found.rule].name,
+                    text(found.result.commands),
+                    text(&found.replacement),
+                    found.changes(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            found,
+            [
+                ("dead_add", "+++[-]".into(), "[-]".into(), true),
+                ("double_clear", "[+][-]".into(), "[-]".into(), true),
+                ("move", ">[>+<-]".into(), ">[->+<]".into(), true),
+                ("move", ">[->+<]".into(), ">[->+<]".into(), false),
+            ]
+        );
+    }
+}
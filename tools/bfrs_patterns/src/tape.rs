@@ -0,0 +1,130 @@
+//! Following the pointer through a program, to tell which cells
+//! of the tape a match works on.
+//!
+//! The pointer can be followed as long as every loop it goes through
+//! leaves it where it was, such as `[->+<]`. After a loop that moves it,
+//! like `[>]`, where it ends up depends on the tape, so it's unknown from
+//! there on.
+
+use crate::r#match::MatchResult;
+use bfrs_common::BFCommand;
+
+/// The cell the pointer is at before every instruction, when it's known.
+#[derive(Debug)]
+pub struct PointerTrace {
+    positions: Vec<Option<isize>>,
+}
+
+impl PointerTrace {
+    /// Follow the pointer through the instructions, starting at the cell `start`.
+    /// The loops have to be balanced.
+    pub fn new(instructions: &[BFCommand], start: isize) -> Self {
+        let moving = moving_loops(instructions);
+        let mut positions = Vec::with_capacity(instructions.len());
+        let mut pointer = Some(start);
+        for (i, instr) in instructions.iter().enumerate() {
+            positions.push(pointer);
+            pointer = match instr {
+                BFCommand::Right => pointer.map(|cell| cell + 1),
+                BFCommand::Left => pointer.map(|cell| cell - 1),
+                BFCommand::BeginLoop if moving[i] => None,
+                _ => pointer,
+            };
+        }
+        Self { positions }
+    }
+
+    /// The cell the pointer is at before the instruction at `offset`.
+    pub fn pointer_at(&self, offset: usize) -> Option<isize> {
+        self.positions.get(offset).copied().flatten()
+    }
+
+    /// The cell of every binding of a match, indexed like [`MatchResult::cells`],
+    /// as long as the pointer is known where the match starts.
+    pub fn cells(&self, res: &MatchResult) -> Option<Vec<isize>> {
+        let pointer = self.pointer_at(res.start)?;
+        Some(res.cells.iter().map(|cell| pointer + cell).collect())
+    }
+}
+
+/// Whether every instruction is the start of a loop that may move the pointer,
+/// either by itself or through a loop inside it.
+fn moving_loops(instructions: &[BFCommand]) -> Vec<bool> {
+    let mut moving = vec![false; instructions.len()];
+    // the start of every open loop, the pointer movement
+    // of its body so far and whether it's moving already
+    let mut open: Vec<(usize, isize, bool)> = Vec::new();
+    for (i, instr) in instructions.iter().enumerate() {
+        match instr {
+            BFCommand::BeginLoop => open.push((i, 0, false)),
+            BFCommand::EndLoop => {
+                if let Some((start, movement, inner)) = open.pop() {
+                    moving[start] = inner || movement != 0;
+                    if let Some(outer) = open.last_mut() {
+                        outer.2 |= moving[start];
+                    }
+                }
+            }
+            BFCommand::Right => {
+                if let Some(current) = open.last_mut() {
+                    current.1 += 1;
+                }
+            }
+            BFCommand::Left => {
+                if let Some(current) = open.last_mut() {
+                    current.1 -= 1;
+                }
+            }
+            _ => (),
+        }
+    }
+    moving
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern::parse_pattern;
+    use crate::r#match::MatchSM;
+    use bfrs_common::parser::parse_str;
+
+    fn pointers(src: &str, start: isize) -> Vec<Option<isize>> {
+        let instructions = parse_str(src).unwrap();
+        let trace = PointerTrace::new(&instructions, start);
+        (0..=instructions.len())
+            .map(|offset| trace.pointer_at(offset))
+            .collect()
+    }
+
+    #[test]
+    fn balanced_loops_keep_the_pointer_known() {
+        let cells = [2, 3, 3, 3, 4, 4, 3, 3];
+        let expected: Vec<_> = cells.iter().map(|&cell| Some(cell)).collect();
+        // past the end there's no instruction to be before
+        assert_eq!(pointers(">[->+<]<", 2), [expected, vec![None]].concat());
+    }
+
+    #[test]
+    fn moving_loops_lose_the_pointer() {
+        assert_eq!(
+            pointers(">[>]<", 0),
+            [Some(0), Some(1), None, None, None, None]
+        );
+        // a loop moving the pointer moves the ones around it too
+        assert_eq!(pointers("[[>]<>]+", 0)[7], None);
+    }
+
+    #[test]
+    fn cells_of_the_bindings() {
+        let instructions = parse_str(">>[-<+>]").unwrap();
+        let scope = parse_pattern("[-y+x]").unwrap();
+        let found = MatchSM::find_all(&instructions, &scope);
+        let trace = PointerTrace::new(&instructions, 10);
+        assert_eq!(trace.cells(&found[0]), Some(vec![11, 12]));
+
+        let instructions = parse_str("[>][-<+>]").unwrap();
+        let found = MatchSM::find_all(&instructions, &scope);
+        let trace = PointerTrace::new(&instructions, 0);
+        assert_eq!(trace.cells(&found[0]), None);
+    }
+}
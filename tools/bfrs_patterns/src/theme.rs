@@ -0,0 +1,156 @@
+//! Color themes in TOML files, for `--theme` to take along with the
+//! built-in ones:
+//! ```toml
+//! # the brackets, and what's between them, from the top level in
+//! depths = ["cyan", "magenta", "#6c71c4"]
+//! line_number = "green"
+//!
+//! # the commands with a color of their own
+//! [commands]
+//! "+" = "green"
+//! "-" = "red"
+//!
+//! # the matches of a search, which are bold too
+//! [matched]
+//! foreground = "bright-white"
+//! background = "#cb4b16"
+//! ```
+//! Colors are names like `red` or `bright-blue`, numbers of the
+//! palette up to 255, or `#rrggbb`. Everything can be left out.
+
+use bfrs_common::theme::Theme;
+use bfrs_common::BFCommand;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::error::Error;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ThemeFile {
+    depths: Vec<String>,
+    line_number: Option<String>,
+    commands: BTreeMap<String, String>,
+    matched: Matched,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Matched {
+    foreground: Option<String>,
+    background: Option<String>,
+}
+
+/// The built-in theme called `name`, or the one in the file at that path.
+pub fn load(name: &str) -> Result<Theme, Box<dyn Error>> {
+    if let Some(theme) = Theme::builtin(name) {
+        return Ok(theme);
+    }
+    let text = std::fs::read_to_string(name).map_err(|e| {
+        format!(
+            "unknown theme `{}`: expected {} or a TOML file ({})",
+            name,
+            Theme::BUILTIN.join(", "),
+            e
+        )
+    })?;
+    parse(&text).map_err(|e| format!("{}: {}", name, e).into())
+}
+
+/// Reads a theme off the text of a file.
+pub fn parse(text: &str) -> Result<Theme, Box<dyn Error>> {
+    let file: ThemeFile = toml::from_str(text)?;
+    let color = |s: &Option<String>| s.as_deref().map(str::parse).transpose();
+    let mut commands = Vec::new();
+    for (command, value) in file.commands.iter() {
+        let command = match command.as_bytes() {
+            [byte] => BFCommand::from_u8(*byte),
+            _ => None,
+        }
+        .filter(|command| !matches!(command, BFCommand::BeginLoop | BFCommand::EndLoop))
+        .ok_or_else(|| {
+            format!(
+                "`{}` isn't a command with a color of its own: the brackets take the ones of `depths`",
+                command
+            )
+        })?;
+        commands.push((command, value.parse()?));
+    }
+    Ok(Theme {
+        commands,
+        depths: file
+            .depths
+            .iter()
+            .map(|s| s.parse())
+            .collect::<Result<_, _>>()?,
+        matched: (
+            color(&file.matched.foreground)?,
+            color(&file.matched.background)?,
+        ),
+        line_number: color(&file.line_number)?,
+        true_color: false,
+    })
+}
+
+/// Whether the terminal says it shows 24 bit colors, like most do
+/// nowadays, which the ones of the palette can only approximate.
+pub fn true_color() -> bool {
+    matches!(
+        std::env::var("COLORTERM").as_deref(),
+        Ok("truecolor") | Ok("24bit")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bfrs_common::theme::Color;
+
+    #[test]
+    fn theme_files() {
+        let theme = parse(
+            r##"
+            depths = ["cyan", "#6c71c4"]
+
+            [commands]
+            "." = "3"
+
+            [matched]
+            background = "red"
+            "##,
+        )
+        .unwrap();
+        assert_eq!(theme.commands, [(BFCommand::Print, Color::Indexed(3))]);
+        assert_eq!(
+            theme.depths,
+            [Color::Indexed(6), Color::Rgb(0x6c, 0x71, 0xc4)]
+        );
+        assert_eq!(theme.matched, (None, Some(Color::Indexed(1))));
+        assert_eq!(theme.line_number, None);
+        assert_eq!(parse("").unwrap(), Theme::default());
+    }
+
+    #[test]
+    fn wrong_theme_files() {
+        let error = |text| parse(text).unwrap_err().to_string();
+        assert!(error("[commands]\n\"[\" = \"red\"").contains("`[` isn't a command"));
+        assert!(error("[commands]\n\"a\" = \"red\"").contains("`a` isn't a command"));
+        assert!(error("line_number = \"teal\"").contains("unknown color `teal`"));
+        assert!(error("colors = []").contains("unknown field"));
+    }
+
+    #[test]
+    fn loading() {
+        assert_eq!(
+            load("solarized").unwrap(),
+            Theme::builtin("solarized").unwrap()
+        );
+        let error = load("no-such-theme.toml").unwrap_err().to_string();
+        assert!(error
+            .starts_with("unknown theme `no-such-theme.toml`: expected default, solarized, mono"));
+        let path = std::env::temp_dir().join(format!("bfrs-theme-{}.toml", std::process::id()));
+        std::fs::write(&path, "depths = [\"green\"]").unwrap();
+        let theme = load(path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(theme.unwrap().depths, [Color::Indexed(2)]);
+    }
+}
@@ -0,0 +1,226 @@
+//! Unrolling loops that run a number of times known without running the
+//! program, like the ones of generated code setting up cells with
+//! `++++++++[>++++++++<-]`.
+//!
+//! The values cells hold are followed from the start of the program, where
+//! they're all 0, through what adds to them and clears them. A loop without
+//! others in it, which doesn't move the pointer and adds the same amount to
+//! its cell every time, runs until that cell gets to 0, so when the value it
+//! starts with is known, so is the number of times it runs, and it's replaced
+//! by its body that many times. Values are only trusted up to 255, so the
+//! loop runs the same times on cells of any size.
+//!
+//! What the unrolled bodies leave next to each other is then fused like
+//! [`canon`](crate::canon) does, into a single run of operations, which
+//! can make the loops around them ones to unroll too.
+
+use crate::canon;
+use crate::ir::{Ir, Op};
+use bfrs_common::BFCommand;
+use std::collections::BTreeMap;
+
+/// The operations a loop can be unrolled into, unless told otherwise.
+pub const BUDGET: usize = 256;
+
+/// The biggest value a counter can start at to be trusted, which is
+/// the same on cells of any size.
+const MAX_COUNTER: isize = 255;
+
+/// The program with the loops that run a known number of times unrolled,
+/// as long as that takes at most `budget` operations for each of them,
+/// and then fused. It does the same as the program.
+/// ```
+/// use bfrs_common::parser::parse_str;
+/// use bfrs_patterns::unroll::{unroll, BUDGET};
+///
+/// let instructions = parse_str("++++[>+++<-]>.").unwrap();
+/// let unrolled: String = unroll(&instructions, BUDGET)
+///     .iter()
+///     .map(ToString::to_string)
+///     .collect();
+/// assert_eq!(unrolled, ">++++++++++++.");
+/// ```
+pub fn unroll(instructions: &[BFCommand], budget: usize) -> Vec<BFCommand> {
+    let _span = tracing::debug_span!("unroll", instructions = instructions.len()).entered();
+    let mut instructions = instructions.to_vec();
+    // every time a loop is unrolled there's one less, so it ends.
+    loop {
+        let ops = Ir::compile(&instructions).ops;
+        let mut unrolled = Vec::with_capacity(ops.len());
+        let loops = unroll_ops(&ops, &mut Values::start(), budget, &mut unrolled);
+        instructions = canon::canonicalize(&Ir::expand(&unrolled));
+        if loops == 0 {
+            return instructions;
+        }
+    }
+}
+
+/// Unrolls the loops of `ops` that it can into `unrolled`, from cells
+/// holding `values`, returning how many it unrolled.
+fn unroll_ops(ops: &[Op], values: &mut Values, budget: usize, unrolled: &mut Vec<Op>) -> usize {
+    let mut loops = 0;
+    let mut i = 0;
+    while i < ops.len() {
+        if ops[i] != Op::BeginLoop {
+            values.run(ops[i]);
+            unrolled.push(ops[i]);
+            i += 1;
+            continue;
+        }
+        let end = canon::end_of_loop(ops, i);
+        let body = &ops[i + 1..end];
+        match trips(body, values.get(values.pointer)) {
+            Some(trips) if trips * body.len() <= budget => {
+                tracing::trace!(at = i, trips, "unrolled a loop");
+                loops += 1;
+                for _ in 0..trips {
+                    for &op in body {
+                        values.run(op);
+                        unrolled.push(op);
+                    }
+                }
+            }
+            _ => {
+                // every time the body runs, its cells can hold anything.
+                unrolled.push(Op::BeginLoop);
+                loops += unroll_ops(body, &mut Values::unknown(), budget, unrolled);
+                unrolled.push(Op::EndLoop);
+                *values = Values::unknown();
+                values.set(0, Some(0));
+            }
+        }
+        i = end + 1;
+    }
+    loops
+}
+
+/// The times a loop with `body` runs, starting with its cell at `counter`.
+fn trips(body: &[Op], counter: Option<isize>) -> Option<usize> {
+    let counter = counter.filter(|counter| counter.abs() <= MAX_COUNTER)?;
+    if counter == 0 {
+        return Some(0);
+    }
+    let (mut offset, mut step) = (0, 0);
+    for &op in body {
+        match op {
+            Op::Add(amount) if offset == 0 => step += amount,
+            Op::Move(amount) => offset += amount,
+            Op::SetZero | Op::Read if offset == 0 => return None,
+            Op::BeginLoop | Op::EndLoop => return None,
+            _ => (),
+        }
+    }
+    if offset != 0 || step == 0 || counter % step != 0 || counter / step > 0 {
+        return None;
+    }
+    Some((-counter / step) as usize)
+}
+
+/// The values known for the cells, by where they are relative to
+/// the cell the pointer was at when they started being followed.
+struct Values {
+    cells: BTreeMap<isize, Option<isize>>,
+    /// whether the cells that aren't in `cells` are 0
+    zero: bool,
+    pointer: isize,
+}
+
+impl Values {
+    /// At the start of the program, where every cell is 0.
+    fn start() -> Self {
+        Self {
+            cells: BTreeMap::new(),
+            zero: true,
+            pointer: 0,
+        }
+    }
+
+    fn unknown() -> Self {
+        Self {
+            zero: false,
+            ..Self::start()
+        }
+    }
+
+    fn get(&self, cell: isize) -> Option<isize> {
+        match self.cells.get(&cell) {
+            Some(&value) => value,
+            None => self.zero.then_some(0),
+        }
+    }
+
+    fn set(&mut self, cell: isize, value: Option<isize>) {
+        self.cells.insert(cell, value);
+    }
+
+    /// Follows an operation other than a bracket.
+    fn run(&mut self, op: Op) {
+        let pointer = self.pointer;
+        match op {
+            Op::Add(amount) => {
+                let value = self
+                    .get(pointer)
+                    .and_then(|value| value.checked_add(amount));
+                self.set(pointer, value);
+            }
+            Op::Move(amount) => self.pointer += amount,
+            Op::SetZero => self.set(pointer, Some(0)),
+            Op::Read => self.set(pointer, None),
+            Op::Print | Op::BeginLoop | Op::EndLoop => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bfrs_common::parser::parse_str;
+
+    fn unrolled(src: &str, budget: usize) -> String {
+        let instructions = unroll(&parse_str(src).unwrap(), budget);
+        instructions.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn loops_with_a_known_counter() {
+        assert_eq!(unrolled("++++++++++[>+<-]", BUDGET), ">++++++++++<");
+        assert_eq!(unrolled("--[>+<++]", BUDGET), ">+<");
+        // a loop on a cell known to be 0 never runs
+        assert_eq!(unrolled("[>+<-]+", BUDGET), "+");
+        // and a loop leaves its cell at 0, whatever was read
+        assert_eq!(unrolled(",[-]++[>+<-]", BUDGET), ",[-]>++<");
+    }
+
+    #[test]
+    fn loops_unrolled_inside_others() {
+        // the inner loop's counter is cleared and set, so once it's
+        // unrolled the outer one only adds, and is unrolled too
+        assert_eq!(unrolled("++[>[-]+++[>+<-]<-]", BUDGET), ">>++++++<<");
+        // while the cells the body of a loop starts with aren't known
+        assert_eq!(unrolled("++[>++[>+<-]<-]", BUDGET), "++[>++[->+<]<-]");
+    }
+
+    #[test]
+    fn loops_left_as_they_are() {
+        // too many operations
+        assert_eq!(unrolled("++++++++++[>+<-]", 10), "++++++++++[->+<]");
+        // a counter that was read
+        assert_eq!(unrolled(",[>+<-]", BUDGET), ",[->+<]");
+        // a counter that never gets to 0 by its step
+        assert_eq!(unrolled("+++[--]", BUDGET), "+++[--]");
+        // a loop moving the pointer
+        assert_eq!(unrolled("++[->]", BUDGET), "++[->]");
+    }
+
+    #[test]
+    fn trips_of_a_loop() {
+        let body = Ir::compile(&parse_str(">+<---").unwrap()).ops;
+        assert_eq!(trips(&body, Some(12)), Some(4));
+        assert_eq!(trips(&body, Some(0)), Some(0));
+        assert_eq!(trips(&body, Some(-12)), None);
+        assert_eq!(trips(&body, Some(258)), None);
+        assert_eq!(trips(&body, None), None);
+        let body = Ir::compile(&parse_str("-,").unwrap()).ops;
+        assert_eq!(trips(&body, Some(2)), None);
+    }
+}